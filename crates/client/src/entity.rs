@@ -80,17 +80,22 @@ impl<'a, C: Scope, E: Scope> Entity<'a, C, E> {
     }
 
     pub(super) fn create_request(&self, hash: &ContentDigest, mime: &Mime) -> Result<Request> {
-        let token = self.client.token.as_ref().ok_or_else(|| {
-            anyhow!("endpoint requires authorization, but no token was configured")
-        })?;
+        ensure!(
+            self.client.token.is_some() || self.client.certificate,
+            "endpoint requires authorization, but neither a token nor a certificate was configured"
+        );
         let url = self.client.url(&self.path)?;
-        Ok(self
+        let req = self
             .client
             .inner
             .put(url.as_str())
-            .set("Authorization", &format!("Bearer {token}"))
             .set("Content-Digest", &hash.to_string())
-            .set(CONTENT_TYPE.as_str(), mime.as_ref()))
+            .set(CONTENT_TYPE.as_str(), mime.as_ref());
+        // Servers may grant write access to client certificates without a token.
+        Ok(match self.client.token {
+            Some(ref token) => req.set("Authorization", &format!("Bearer {token}")),
+            None => req,
+        })
     }
 
     pub(super) fn create_bytes(&self, mime: &Mime, data: impl AsRef<[u8]>) -> Result<bool> {
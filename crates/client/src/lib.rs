@@ -104,6 +104,9 @@ pub struct Client<S = scope::Root> {
     inner: ureq::Agent,
     root: Url,
     token: Option<String>,
+    /// Whether a client certificate is presented, which may authorize writes in place of a
+    /// token.
+    certificate: bool,
     scope: PhantomData<S>,
 }
 
@@ -135,18 +138,18 @@ impl Client<scope::Root> {
     pub fn repository<'a>(
         &'a self,
         RepositoryContext { owner, name }: &'a RepositoryContext,
-    ) -> Repository<'_, scope::Root> {
+    ) -> Repository<'a, scope::Root> {
         self.user(owner).repository(name)
     }
 
     pub fn tag<'a>(
         &'a self,
         TagContext { repository, name }: &'a TagContext,
-    ) -> Tag<'_, scope::Root> {
+    ) -> Tag<'a, scope::Root> {
         self.repository(repository).tag(name)
     }
 
-    pub fn tree<'a>(&'a self, TreeContext { tag, path }: &'a TreeContext) -> Node<'_, scope::Root> {
+    pub fn tree<'a>(&'a self, TreeContext { tag, path }: &'a TreeContext) -> Node<'a, scope::Root> {
         self.tag(tag).path(path)
     }
 }
@@ -219,6 +222,7 @@ impl<S: Scope> ClientBuilder<S> {
                 ));
                 root_store
             });
+        let certificate = self.credentials.is_some();
         let tls = if let Some((cert, key)) = self.credentials {
             tls.with_single_cert(cert, key)?
         } else {
@@ -236,6 +240,7 @@ impl<S: Scope> ClientBuilder<S> {
                 .build(),
             root: self.url,
             token: self.token,
+            certificate,
             scope: self.scope,
         })
     }
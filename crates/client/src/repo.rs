@@ -5,7 +5,7 @@ use super::{scope, Entity, Result, Scope, Tag};
 
 use std::ops::Deref;
 
-use drawbridge_type::{RepositoryConfig, RepositoryName, TagName};
+use drawbridge_type::{RepositoryChanges, RepositoryConfig, RepositoryName, TagName};
 
 use mime::APPLICATION_JSON;
 
@@ -41,6 +41,19 @@ impl<'a, S: Scope> Repository<'a, S> {
             .map(|(_, v)| v)
     }
 
+    /// Returns the changes to tags of the repository recorded after `since`, which is a cursor
+    /// returned by a previous call, or all recorded changes if `None`.
+    pub fn changes(&self, since: Option<&str>) -> Result<RepositoryChanges> {
+        let path = match since {
+            Some(since) => format!("_changes?since={since}"),
+            None => "_changes".into(),
+        };
+        self.0
+            .child::<scope::Unknown>(&path)
+            .get_json(u64::MAX)
+            .map(|(_, v)| v)
+    }
+
     pub fn tag(&self, name: &TagName) -> Tag<'a, S> {
         Tag::new(self.child("_tag"), name)
     }
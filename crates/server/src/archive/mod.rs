@@ -0,0 +1,310 @@
+// SPDX-FileCopyrightText: 2022 Profian Inc. <opensource@profian.com>
+// SPDX-License-Identifier: AGPL-3.0-only
+
+mod tar;
+
+use std::collections::HashMap;
+use std::io;
+use std::os::unix::fs::DirBuilderExt;
+
+use drawbridge_type::Meta;
+
+use anyhow::{anyhow, bail, Context};
+use async_std::fs::{create_dir_all, File};
+use async_std::path::Path;
+use camino::{Utf8Component, Utf8Path, Utf8PathBuf};
+use cap_async_std::fs_utf8::{Dir, DirBuilder};
+use futures::io::{copy, sink};
+use futures::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tracing::{debug, warn};
+
+/// Name of the file holding the metadata of a stored object.
+const META: &str = "meta.json";
+
+/// Name of the file holding the contents of a stored object.
+pub(crate) const CONTENT: &str = "content";
+
+/// Maximum size of object metadata accepted by [import_store].
+const MAX_META_SIZE: u64 = 64 * 1024;
+
+/// Summary of an [export_store] run.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ExportSummary {
+    /// Number of exported objects.
+    pub objects: u64,
+    /// Total size of the contents of exported objects.
+    pub bytes: u64,
+}
+
+/// Summary of an [import_store] run.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ImportSummary {
+    /// Number of objects created in the store.
+    pub created: u64,
+    /// Number of objects, which were already stored with identical contents.
+    pub existing: u64,
+}
+
+pub(crate) async fn open_store(path: &Path) -> anyhow::Result<Dir> {
+    File::open(path)
+        .await
+        .map(Dir::from_std_file)
+        .with_context(|| format!("failed to open store at `{}`", path.to_string_lossy()))
+}
+
+/// Returns `true` for transient files, e.g. leftovers of writability probes or interrupted
+/// imports, which are not part of the store contents.
+pub(crate) fn is_transient(name: &str) -> bool {
+    name.starts_with('.')
+}
+
+/// Writes the users, repositories, tags and trees contained in the store at `store` into `out`
+/// as a tar archive, which can be restored using [import_store].
+///
+/// The store must not be modified concurrently, i.e. the server should be stopped.
+pub async fn export_store(
+    store: impl AsRef<Path>,
+    out: impl Unpin + AsyncWrite,
+) -> anyhow::Result<ExportSummary> {
+    let root = open_store(store.as_ref()).await?;
+    let mut archive = tar::Writer::new(out);
+    let mut summary = ExportSummary::default();
+
+    let mut dirs = vec![Utf8PathBuf::new()];
+    while let Some(dir) = dirs.pop() {
+        if !dir.as_str().is_empty() {
+            archive
+                .append_dir(&dir)
+                .await
+                .with_context(|| format!("failed to archive directory `{dir}`"))?;
+        }
+        let entries = if dir.as_str().is_empty() {
+            root.entries().await
+        } else {
+            root.read_dir(&dir).await
+        }
+        .with_context(|| format!("failed to read directory `{dir}`"))?;
+
+        let (mut files, mut subdirs) = (vec![], vec![]);
+        for entry in entries {
+            let entry = entry.with_context(|| format!("failed to read directory `{dir}`"))?;
+            let name = entry
+                .file_name()
+                .with_context(|| format!("failed to read entry name in `{dir}`"))?;
+            if is_transient(&name) {
+                continue;
+            }
+            let ty = entry
+                .file_type()
+                .await
+                .with_context(|| format!("failed to query file type of `{dir}/{name}`"))?;
+            if ty.is_dir() {
+                subdirs.push(dir.join(name));
+            } else if ty.is_file() && (name == META || name == CONTENT) {
+                files.push(name);
+            } else {
+                warn!(target: "app::export_store", "skip unexpected store entry `{dir}/{name}`");
+            }
+        }
+        // Metadata precedes contents, such that the contents can be verified on import.
+        files.sort_by_key(|name| name != META);
+        for name in files {
+            let path = dir.join(&name);
+            let file = root
+                .open(&path)
+                .await
+                .with_context(|| format!("failed to open `{path}`"))?;
+            let size = file
+                .metadata()
+                .with_context(|| format!("failed to query metadata of `{path}`"))?
+                .len();
+            archive
+                .append_file(&path, size, file)
+                .await
+                .with_context(|| format!("failed to archive `{path}`"))?;
+            if name == CONTENT {
+                summary.objects += 1;
+                summary.bytes += size;
+            }
+        }
+        subdirs.sort();
+        dirs.extend(subdirs.into_iter().rev());
+    }
+    let mut out = archive.finish().await.context("failed to finish archive")?;
+    out.close().await.context("failed to close archive")?;
+    Ok(summary)
+}
+
+/// Validates that `path` is a relative path, which does not escape the store.
+fn validate_path(path: &Utf8Path) -> anyhow::Result<()> {
+    if path.as_str().is_empty() {
+        bail!("archive contains an entry with an empty path");
+    }
+    for component in path.components() {
+        match component {
+            Utf8Component::Normal(name) if !is_transient(name) => {}
+            _ => bail!("archive contains an entry with an invalid path `{path}`"),
+        }
+    }
+    Ok(())
+}
+
+fn create_dir_all_in(root: &Dir, path: &Utf8Path) -> anyhow::Result<()> {
+    root.create_dir_with(path, DirBuilder::new().recursive(true).mode(0o700))
+        .with_context(|| format!("failed to create directory `{path}`"))
+}
+
+/// Reads contents of an object described by `meta` from `rdr` into `dst`, verifying its digest
+/// and length.
+async fn copy_verified(
+    path: &Utf8Path,
+    meta: &Meta,
+    rdr: impl Unpin + AsyncRead,
+    dst: &mut (impl Unpin + AsyncWrite),
+) -> anyhow::Result<()> {
+    match copy(meta.hash.clone().verifier(rdr), dst).await {
+        Err(e) if e.kind() == io::ErrorKind::InvalidData => {
+            bail!("content digest of `{path}` does not match its metadata")
+        }
+        Err(e) => Err(anyhow!(e).context(format!("failed to import `{path}`"))),
+        Ok(n) if n != meta.size => bail!(
+            "content length of `{path}` does not match its metadata, expected {}, got {n}",
+            meta.size
+        ),
+        Ok(_) => Ok(()),
+    }
+}
+
+/// Imports the object at `dir` described by `meta` with contents read from `rdr`.
+///
+/// Returns `true` if the object was created and `false` if it was already stored.
+async fn import_object(
+    root: &Dir,
+    dir: &Utf8Path,
+    meta: &Meta,
+    rdr: impl Unpin + AsyncRead,
+) -> anyhow::Result<bool> {
+    let (meta_path, content_path) = (dir.join(META), dir.join(CONTENT));
+    match root.read(&meta_path).await {
+        Ok(stored) => {
+            let stored: Meta = serde_json::from_slice(&stored)
+                .with_context(|| format!("failed to decode stored `{meta_path}`"))?;
+            if stored != *meta {
+                bail!("object at `{dir}` is already stored with different contents");
+            }
+            copy_verified(&content_path, meta, rdr, &mut sink()).await?;
+            return Ok(false);
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e).with_context(|| format!("failed to read stored `{meta_path}`")),
+    }
+    if root.exists(&content_path).await {
+        bail!("object at `{dir}` is already stored without metadata");
+    }
+
+    // Contents are moved into place only once verified.
+    let tmp = dir.join(format!(".import-{}", uuid::Uuid::new_v4()));
+    let mut file = root
+        .create(&tmp)
+        .await
+        .with_context(|| format!("failed to create `{tmp}`"))?;
+    let res = async {
+        copy_verified(&content_path, meta, rdr, &mut file).await?;
+        file.sync_all()
+            .await
+            .with_context(|| format!("failed to sync `{tmp}`"))
+    }
+    .await;
+    drop(file);
+    if let Err(e) = res {
+        if let Err(e) = root.remove_file(&tmp).await {
+            warn!(target: "app::import_store", "failed to remove `{tmp}`: {e}");
+        }
+        return Err(e);
+    }
+    root.rename(&tmp, root, &content_path)
+        .await
+        .with_context(|| format!("failed to move `{tmp}` to `{content_path}`"))?;
+    let meta = serde_json::to_vec(meta).context("failed to encode metadata")?;
+    root.write(&meta_path, meta)
+        .await
+        .with_context(|| format!("failed to write `{meta_path}`"))?;
+    Ok(true)
+}
+
+/// Restores the contents of a tar archive produced by [export_store] read from `input` into
+/// the store at `store`, which is created if it does not exist.
+///
+/// Contents of each object are verified against the digests and length recorded in its
+/// metadata. Objects, which are already stored with identical metadata, are skipped, while
+/// objects stored with different metadata cause the import to fail.
+///
+/// Archives only contain objects, hence new stores created by an import have no layout version
+/// and need to be migrated on startup, which records the creation of the imported tags in the
+/// change log.
+///
+/// The store must not be modified concurrently, i.e. the server should be stopped.
+pub async fn import_store(
+    store: impl AsRef<Path>,
+    input: impl Unpin + AsyncRead,
+) -> anyhow::Result<ImportSummary> {
+    let store = store.as_ref();
+    create_dir_all(store)
+        .await
+        .with_context(|| format!("failed to create store at `{}`", store.to_string_lossy()))?;
+    let root = open_store(store).await?;
+    let mut archive = tar::Reader::new(input);
+    let mut summary = ImportSummary::default();
+
+    // Metadata of objects, whose contents were not read yet.
+    let mut pending = HashMap::<Utf8PathBuf, Meta>::new();
+    while let Some(tar::Header { path, kind, size }) =
+        archive.next().await.context("failed to read archive")?
+    {
+        validate_path(&path)?;
+        debug!(target: "app::import_store", "import `{path}`");
+        let (dir, name) = match (kind, path.parent(), path.file_name()) {
+            (tar::Kind::Directory, ..) => {
+                create_dir_all_in(&root, &path)?;
+                continue;
+            }
+            (tar::Kind::File, Some(dir), Some(name)) => (dir.to_path_buf(), name),
+            (tar::Kind::File, ..) => bail!("archive contains an invalid file path `{path}`"),
+        };
+        match name {
+            META => {
+                if size > MAX_META_SIZE {
+                    bail!("metadata at `{path}` is too large");
+                }
+                let mut buf = vec![];
+                _ = archive
+                    .read_to_end(&mut buf)
+                    .await
+                    .with_context(|| format!("failed to read `{path}`"))?;
+                let meta = serde_json::from_slice(&buf)
+                    .with_context(|| format!("failed to decode `{path}`"))?;
+                if pending.insert(dir, meta).is_some() {
+                    bail!("archive contains duplicate `{path}`");
+                }
+            }
+            CONTENT => {
+                let meta = pending
+                    .remove(&dir)
+                    .ok_or_else(|| anyhow!("archive does not contain metadata of `{dir}`"))?;
+                if !dir.as_str().is_empty() {
+                    create_dir_all_in(&root, &dir)?;
+                }
+                if import_object(&root, &dir, &meta, &mut archive).await? {
+                    summary.created += 1;
+                } else {
+                    summary.existing += 1;
+                }
+            }
+            _ => bail!("archive contains unexpected file `{path}`"),
+        }
+    }
+    if let Some(dir) = pending.keys().next() {
+        bail!("archive does not contain content of `{dir}`");
+    }
+    Ok(summary)
+}
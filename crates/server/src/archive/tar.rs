@@ -0,0 +1,385 @@
+// SPDX-FileCopyrightText: 2022 Profian Inc. <opensource@profian.com>
+// SPDX-License-Identifier: AGPL-3.0-only
+
+use std::io;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+
+use camino::{Utf8Path, Utf8PathBuf};
+use futures::io::{copy, sink};
+use futures::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+const BLOCK_SIZE: u64 = 512;
+
+/// Maximum size, which can be encoded in the 11 octal digits of a ustar header.
+const MAX_USTAR_SIZE: u64 = 0o777_7777_7777;
+
+/// Maximum length of a path, which can be encoded in the name field of a ustar header.
+const MAX_USTAR_PATH: usize = 100;
+
+/// Maximum size of extended headers accepted by [Reader].
+const MAX_EXTENDED_HEADER_SIZE: u64 = 64 * 1024;
+
+const TYPE_FILE: u8 = b'0';
+const TYPE_DIRECTORY: u8 = b'5';
+const TYPE_PAX: u8 = b'x';
+const TYPE_PAX_GLOBAL: u8 = b'g';
+const TYPE_GNU_LONG_NAME: u8 = b'L';
+
+fn invalid(msg: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.into())
+}
+
+fn padding(size: u64) -> u64 {
+    (BLOCK_SIZE - size % BLOCK_SIZE) % BLOCK_SIZE
+}
+
+/// Kind of a [Header].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(super) enum Kind {
+    Directory,
+    File,
+}
+
+/// Header of an archive entry.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(super) struct Header {
+    pub(super) path: Utf8PathBuf,
+    pub(super) kind: Kind,
+    pub(super) size: u64,
+}
+
+fn write_octal(field: &mut [u8], value: u64) {
+    let digits = format!("{value:0width$o}", width = field.len() - 1);
+    field[..digits.len()].copy_from_slice(digits.as_bytes());
+    field[digits.len()..].fill(0);
+}
+
+fn ustar_header(path: &str, kind: u8, size: u64, mode: u64) -> [u8; BLOCK_SIZE as usize] {
+    let mut header = [0; BLOCK_SIZE as usize];
+    // Paths exceeding the name field are truncated and carried in a PAX header instead.
+    let mut len = path.len().min(MAX_USTAR_PATH);
+    while !path.is_char_boundary(len) {
+        len -= 1;
+    }
+    header[..len].copy_from_slice(&path.as_bytes()[..len]);
+    write_octal(&mut header[100..108], mode);
+    write_octal(&mut header[108..116], 0);
+    write_octal(&mut header[116..124], 0);
+    write_octal(
+        &mut header[124..136],
+        if size > MAX_USTAR_SIZE { 0 } else { size },
+    );
+    write_octal(&mut header[136..148], 0);
+    header[156] = kind;
+    header[257..263].copy_from_slice(b"ustar\0");
+    header[263..265].copy_from_slice(b"00");
+
+    header[148..156].fill(b' ');
+    let checksum = header.iter().map(|&b| u64::from(b)).sum();
+    write_octal(&mut header[148..155], checksum);
+    header
+}
+
+/// Appends a PAX extended header record, whose length includes the length prefix itself.
+fn pax_record(records: &mut String, key: &str, value: &str) {
+    let rest = format!(" {key}={value}\n");
+    let mut len = rest.len() + 1;
+    while len.to_string().len() + rest.len() != len {
+        len = len.to_string().len() + rest.len();
+    }
+    records.push_str(&len.to_string());
+    records.push_str(&rest);
+}
+
+/// Streaming writer of POSIX tar archives.
+#[derive(Debug)]
+pub(super) struct Writer<W> {
+    inner: W,
+}
+
+impl<W: Unpin + AsyncWrite> Writer<W> {
+    pub(super) fn new(inner: W) -> Self {
+        Self { inner }
+    }
+
+    async fn write_padding(&mut self, size: u64) -> io::Result<()> {
+        let zeros = [0; BLOCK_SIZE as usize];
+        self.inner.write_all(&zeros[..padding(size) as usize]).await
+    }
+
+    async fn write_header(&mut self, path: &str, kind: u8, size: u64, mode: u64) -> io::Result<()> {
+        let mut records = String::new();
+        if path.len() > MAX_USTAR_PATH {
+            pax_record(&mut records, "path", path);
+        }
+        if size > MAX_USTAR_SIZE {
+            pax_record(&mut records, "size", &size.to_string());
+        }
+        if !records.is_empty() {
+            let len = records.len() as u64;
+            self.inner
+                .write_all(&ustar_header("././@PaxHeader", TYPE_PAX, len, 0o644))
+                .await?;
+            self.inner.write_all(records.as_bytes()).await?;
+            self.write_padding(len).await?;
+        }
+        self.inner
+            .write_all(&ustar_header(path, kind, size, mode))
+            .await
+    }
+
+    /// Appends a directory at `path`.
+    pub(super) async fn append_dir(&mut self, path: &Utf8Path) -> io::Result<()> {
+        self.write_header(&format!("{path}/"), TYPE_DIRECTORY, 0, 0o700)
+            .await
+    }
+
+    /// Appends a file at `path` containing `size` bytes read from `rdr`.
+    pub(super) async fn append_file(
+        &mut self,
+        path: &Utf8Path,
+        size: u64,
+        rdr: impl Unpin + AsyncRead,
+    ) -> io::Result<()> {
+        self.write_header(path.as_str(), TYPE_FILE, size, 0o600)
+            .await?;
+        let n = copy(rdr.take(size), &mut self.inner).await?;
+        if n != size {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!("`{path}` changed while being archived"),
+            ));
+        }
+        self.write_padding(size).await
+    }
+
+    /// Terminates the archive and returns the underlying writer.
+    pub(super) async fn finish(mut self) -> io::Result<W> {
+        self.inner.write_all(&[0; 2 * BLOCK_SIZE as usize]).await?;
+        self.inner.flush().await?;
+        Ok(self.inner)
+    }
+}
+
+fn parse_octal(field: &[u8]) -> io::Result<u64> {
+    let field = std::str::from_utf8(field)
+        .map_err(|_| invalid("invalid numeric header field"))?
+        .trim_matches(|c| c == ' ' || c == '\0');
+    if field.is_empty() {
+        return Ok(0);
+    }
+    u64::from_str_radix(field, 8).map_err(|_| invalid("invalid numeric header field"))
+}
+
+fn parse_path(bytes: &[u8]) -> io::Result<Utf8PathBuf> {
+    let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    std::str::from_utf8(&bytes[..end])
+        .map(|path| path.trim_end_matches('/').into())
+        .map_err(|_| invalid("entry path is not valid UTF-8"))
+}
+
+/// Streaming reader of POSIX tar archives, which reads the contents of the current entry
+/// via [AsyncRead].
+///
+/// Only directories and regular files are supported. Long paths and sizes may be given by
+/// PAX extended headers or GNU long name entries.
+#[derive(Debug)]
+pub(super) struct Reader<R> {
+    inner: R,
+    remaining: u64,
+    padding: u64,
+}
+
+impl<R: Unpin + AsyncRead> Reader<R> {
+    pub(super) fn new(inner: R) -> Self {
+        Self {
+            inner,
+            remaining: 0,
+            padding: 0,
+        }
+    }
+
+    async fn skip(&mut self, n: u64) -> io::Result<()> {
+        let skipped = copy((&mut self.inner).take(n), &mut sink()).await?;
+        if skipped == n {
+            Ok(())
+        } else {
+            Err(io::ErrorKind::UnexpectedEof.into())
+        }
+    }
+
+    async fn read_extended(&mut self, size: u64) -> io::Result<Vec<u8>> {
+        if size > MAX_EXTENDED_HEADER_SIZE {
+            return Err(invalid("extended header is too large"));
+        }
+        let mut buf = vec![0; size as usize];
+        self.inner.read_exact(&mut buf).await?;
+        self.skip(padding(size)).await?;
+        Ok(buf)
+    }
+
+    /// Advances to the next entry, skipping unread contents of the current one.
+    ///
+    /// Returns `None` at the end of the archive.
+    pub(super) async fn next(&mut self) -> io::Result<Option<Header>> {
+        let remaining = self.remaining + self.padding;
+        (self.remaining, self.padding) = (0, 0);
+        self.skip(remaining).await?;
+
+        let (mut path, mut size) = (None, None);
+        loop {
+            let mut block = [0; BLOCK_SIZE as usize];
+            self.inner.read_exact(&mut block).await?;
+            if block.iter().all(|&b| b == 0) {
+                return Ok(None);
+            }
+
+            let checksum = parse_octal(&block[148..156])?;
+            block[148..156].fill(b' ');
+            if block.iter().map(|&b| u64::from(b)).sum::<u64>() != checksum {
+                return Err(invalid("header checksum mismatch"));
+            }
+
+            let header_size = parse_octal(&block[124..136])?;
+            let kind = match block[156] {
+                TYPE_PAX => {
+                    let records = self.read_extended(header_size).await?;
+                    let records = std::str::from_utf8(&records)
+                        .map_err(|_| invalid("PAX header is not valid UTF-8"))?;
+                    for record in records.split_terminator('\n') {
+                        let (key, value) = record
+                            .split_once(' ')
+                            .and_then(|(_, record)| record.split_once('='))
+                            .ok_or_else(|| invalid("invalid PAX header record"))?;
+                        match key {
+                            "path" => path = Some(value.trim_end_matches('/').into()),
+                            "size" => {
+                                size = Some(
+                                    value
+                                        .parse()
+                                        .map_err(|_| invalid("invalid PAX size record"))?,
+                                )
+                            }
+                            _ => {}
+                        }
+                    }
+                    continue;
+                }
+                TYPE_PAX_GLOBAL => {
+                    _ = self.read_extended(header_size).await?;
+                    continue;
+                }
+                TYPE_GNU_LONG_NAME => {
+                    path = Some(parse_path(&self.read_extended(header_size).await?)?);
+                    continue;
+                }
+                TYPE_FILE | 0 => Kind::File,
+                TYPE_DIRECTORY => Kind::Directory,
+                kind => {
+                    return Err(invalid(format!(
+                        "unsupported entry type `{}`",
+                        char::from(kind)
+                    )))
+                }
+            };
+            let path = match path {
+                Some(path) => path,
+                None if &block[257..262] == b"ustar" && block[345] != 0 => {
+                    parse_path(&block[345..500])?.join(parse_path(&block[..100])?)
+                }
+                None => parse_path(&block[..100])?,
+            };
+            let size = size.unwrap_or(header_size);
+            (self.remaining, self.padding) = (size, padding(size));
+            return Ok(Some(Header { path, kind, size }));
+        }
+    }
+}
+
+impl<R: Unpin + AsyncRead> AsyncRead for Reader<R> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        if this.remaining == 0 || buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+        let max = buf
+            .len()
+            .min(usize::try_from(this.remaining).unwrap_or(usize::MAX));
+        let n = ready!(Pin::new(&mut this.inner).poll_read(cx, &mut buf[..max]))?;
+        if n == 0 {
+            return Poll::Ready(Err(io::ErrorKind::UnexpectedEof.into()));
+        }
+        this.remaining -= n as u64;
+        Poll::Ready(Ok(n))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use async_std::task::block_on;
+
+    #[test]
+    fn roundtrip() {
+        let long = Utf8PathBuf::from(format!("{}/content", "nested/".repeat(20)));
+        let archive = block_on(async {
+            let mut w = Writer::new(vec![]);
+            w.append_dir("users".into()).await.unwrap();
+            w.append_file("users/meta.json".into(), 3, b"foo".as_slice())
+                .await
+                .unwrap();
+            w.append_file(&long, 600, [42; 600].as_slice())
+                .await
+                .unwrap();
+            w.finish().await.unwrap()
+        });
+
+        block_on(async {
+            let mut r = Reader::new(archive.as_slice());
+            assert_eq!(
+                r.next().await.unwrap(),
+                Some(Header {
+                    path: "users".into(),
+                    kind: Kind::Directory,
+                    size: 0,
+                })
+            );
+            assert_eq!(
+                r.next().await.unwrap(),
+                Some(Header {
+                    path: "users/meta.json".into(),
+                    kind: Kind::File,
+                    size: 3,
+                })
+            );
+            let mut buf = vec![];
+            _ = r.read_to_end(&mut buf).await.unwrap();
+            assert_eq!(buf, b"foo");
+
+            // Unread contents are skipped.
+            assert_eq!(
+                r.next().await.unwrap(),
+                Some(Header {
+                    path: long,
+                    kind: Kind::File,
+                    size: 600,
+                })
+            );
+            assert_eq!(r.next().await.unwrap(), None);
+        });
+
+        // Files must not change while being archived.
+        let mut w = Writer::new(vec![]);
+        assert!(block_on(w.append_file("short".into(), 4, b"foo".as_slice())).is_err());
+
+        let mut corrupted = archive.clone();
+        corrupted[0] = b'x';
+        assert!(block_on(Reader::new(corrupted.as_slice()).next()).is_err());
+    }
+}
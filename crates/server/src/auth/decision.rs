@@ -0,0 +1,78 @@
+// SPDX-FileCopyrightText: 2022 Profian Inc. <opensource@profian.com>
+// SPDX-License-Identifier: AGPL-3.0-only
+
+use super::super::Metrics;
+
+use std::fmt;
+use std::sync::Arc;
+
+use axum::http::Extensions;
+
+/// Outcome of an authorization check.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum AuthDecision {
+    /// Access granted to a trusted client certificate.
+    GrantedViaCert,
+    /// Access granted to an OpenID Connect token.
+    GrantedViaOidc,
+    /// Access granted to an access token minted by a user.
+    GrantedViaToken,
+    /// Access to a public repository granted to anyone.
+    GrantedPublic,
+    /// Access to a single object granted to anyone presenting a valid signed URL.
+    GrantedViaSignedUrl,
+    /// Access denied, since no valid authentication was provided.
+    DeniedNoAuth,
+    /// Access denied, since the token is missing a required scope.
+    DeniedInsufficientScope,
+    /// Access denied, since the authenticated subject is not authorized for the target.
+    DeniedAcl,
+}
+
+impl AuthDecision {
+    /// All decisions.
+    pub const ALL: [Self; 8] = [
+        Self::GrantedViaCert,
+        Self::GrantedViaOidc,
+        Self::GrantedViaToken,
+        Self::GrantedPublic,
+        Self::GrantedViaSignedUrl,
+        Self::DeniedNoAuth,
+        Self::DeniedInsufficientScope,
+        Self::DeniedAcl,
+    ];
+
+    /// Returns `true` if access is granted.
+    pub fn is_granted(&self) -> bool {
+        matches!(
+            self,
+            Self::GrantedViaCert
+                | Self::GrantedViaOidc
+                | Self::GrantedViaToken
+                | Self::GrantedPublic
+                | Self::GrantedViaSignedUrl
+        )
+    }
+}
+
+impl fmt::Display for AuthDecision {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::GrantedViaCert => write!(f, "granted-via-cert"),
+            Self::GrantedViaOidc => write!(f, "granted-via-oidc"),
+            Self::GrantedViaToken => write!(f, "granted-via-token"),
+            Self::GrantedPublic => write!(f, "granted-public"),
+            Self::GrantedViaSignedUrl => write!(f, "granted-via-signed-url"),
+            Self::DeniedNoAuth => write!(f, "denied-no-auth"),
+            Self::DeniedInsufficientScope => write!(f, "denied-insufficient-scope"),
+            Self::DeniedAcl => write!(f, "denied-acl"),
+        }
+    }
+}
+
+/// Records `decision` in the [Metrics] contained in `extensions`, if any.
+pub(crate) fn record(extensions: &Extensions, decision: AuthDecision) {
+    if let Some(metrics) = extensions.get::<Arc<Metrics>>() {
+        metrics.record_authorization(decision);
+    }
+}
@@ -1,13 +1,33 @@
 // SPDX-FileCopyrightText: 2022 Profian Inc. <opensource@profian.com>
 // SPDX-License-Identifier: AGPL-3.0-only
 
+mod decision;
 mod oidc;
+mod signed_url;
 mod tls;
+mod webhook;
+mod writers;
 
-pub use oidc::{Claims as OidcClaims, ScopeContext, ScopeLevel, Verifier as OidcVerifier};
-pub use tls::{Config as TlsConfig, TrustedCertificate};
+pub(crate) use decision::record as record_decision;
+pub use decision::AuthDecision;
+pub use oidc::{
+    Claims as OidcClaims, ScopeContext, ScopeLevel, Verifier as OidcVerifier,
+    DEFAULT_OIDC_CLOCK_SKEW,
+};
+pub(crate) use signed_url::{encode_hex, hmac_sha256, sign as sign_url, UrlSigner};
+pub(crate) use tls::{read_certificates, read_key};
+pub use tls::{
+    CertificateAllowlist, Config as TlsConfig, Options as TlsOptions,
+    SessionConfig as TlsSessionConfig, TrustedCertificate, DEFAULT_MAX_CLIENT_CERT_CHAIN,
+    DEFAULT_TLS_SESSION_CACHE_SIZE, DEFAULT_TLS_TICKET_LIFETIME, MAX_TLS_TICKET_LIFETIME,
+    SERVED_ALPN_PROTOCOLS,
+};
+pub use webhook::DEFAULT_AUTHZ_CACHE_TTL;
+pub(crate) use webhook::{authorize as authorize_webhook, Subject, Webhook};
+pub(crate) use writers::CertificateWriter;
+pub use writers::CertificateWriters;
 
-use super::{Repository, Store, User};
+use super::{Repository, ServerTiming, Store, User};
 
 use drawbridge_type::RepositoryContext;
 
@@ -15,26 +35,41 @@ use axum::body::Body;
 use axum::extract::RequestParts;
 use axum::http::Request;
 use axum::response::IntoResponse;
+use std::sync::Arc;
+use std::time::SystemTime;
 
+#[allow(clippy::result_large_err)]
 pub async fn assert_repository_read<'a>(
     store: &'a Store,
     cx: &'a RepositoryContext,
     req: Request<Body>,
 ) -> Result<(Repository<'a>, Option<User<'a>>), impl IntoResponse> {
+    let timing = req.extensions().get::<ServerTiming>().cloned();
     let repo = store.repository(cx);
-    if repo
-        .is_public()
+    if let Some(signer) = req.extensions().get::<Arc<UrlSigner>>() {
+        if signer
+            .verify(req.uri(), SystemTime::now())
+            .map_err(IntoResponse::into_response)?
+        {
+            record_decision(req.extensions(), AuthDecision::GrantedViaSignedUrl);
+            return Ok((repo, None));
+        }
+    }
+    if ServerTiming::measure(timing.as_ref(), "auth", repo.is_public())
         .await
         .map_err(IntoResponse::into_response)?
     {
+        record_decision(req.extensions(), AuthDecision::GrantedPublic);
         Ok((repo, None))
     } else {
-        RequestParts::new(req)
-            .extract::<OidcClaims>()
-            .await?
-            .assert_user(store, &cx.owner, ScopeContext::Repository, ScopeLevel::Read)
-            .await
-            .map_err(IntoResponse::into_response)
-            .map(|user| (repo, Some(user)))
+        let claims = RequestParts::new(req).extract::<OidcClaims>().await?;
+        ServerTiming::measure(
+            timing.as_ref(),
+            "auth",
+            claims.assert_user(store, &cx.owner, ScopeContext::Repository, ScopeLevel::Read),
+        )
+        .await
+        .map_err(IntoResponse::into_response)
+        .map(|user| (repo, Some(user)))
     }
 }
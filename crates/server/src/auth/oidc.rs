@@ -1,21 +1,27 @@
 // SPDX-FileCopyrightText: 2022 Profian Inc. <opensource@profian.com>
 // SPDX-License-Identifier: AGPL-3.0-only
 
-use super::super::{GetError, OidcConfig, Store, User};
+use super::super::rate_limit::NamespaceLimiter;
+use super::super::tokens::{self, AccessToken, TokenAction, TokenError};
+use super::super::{GetError, Metrics, OidcConfig, ServerTiming, Store, TokenOutcome, User};
+use super::webhook::{Subject, Webhook};
+use super::{record_decision, AuthDecision, CertificateWriter};
 
-use drawbridge_type::{UserContext, UserRecord};
+use drawbridge_type::{RepositoryName, UserContext, UserName, UserRecord};
 
 use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
 
 use anyhow::{anyhow, bail, Context};
 use axum::extract::rejection::{TypedHeaderRejection, TypedHeaderRejectionReason};
 use axum::extract::{Extension, FromRequest, RequestParts};
 use axum::headers::authorization::Bearer;
 use axum::headers::Authorization;
-use axum::http::StatusCode;
+use axum::http::{Extensions, StatusCode};
 use axum::response::{IntoResponse, Response};
 use axum::{async_trait, TypedHeader};
+use jsonwebtoken::errors::ErrorKind;
 use jsonwebtoken::jwk::{AlgorithmParameters, JwkSet};
 use jsonwebtoken::{decode, decode_header, Algorithm, DecodingKey, Validation};
 use openidconnect::core::CoreProviderMetadata;
@@ -24,7 +30,12 @@ use openidconnect::IssuerUrl;
 use serde::{Deserialize, Deserializer};
 use tracing::{error, info, trace, warn};
 
+/// Default tolerance for differences between the clocks of the server and the OpenID Connect
+/// provider when validating the `exp` and `nbf` claims of tokens.
+pub const DEFAULT_OIDC_CLOCK_SKEW: Duration = Duration::from_secs(60);
+
 pub struct Verifier {
+    issuer: IssuerUrl,
     keyset: HashMap<String, DecodingKey>,
     validator: Validation,
 }
@@ -32,6 +43,7 @@ pub struct Verifier {
 impl std::fmt::Debug for Verifier {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("Verifier")
+            .field("issuer", &self.issuer)
             .field("validator", &self.validator)
             .finish()
     }
@@ -54,17 +66,103 @@ where
     Ok(HashSet::from_iter(s.split(' ').map(|s| s.to_owned())))
 }
 
+/// Returns the scopes checked by [Claims] for each context and level, any of which grants
+/// access of the level in the context.
+fn required_scopes() -> impl Iterator<Item = Vec<String>> {
+    [
+        ScopeContext::User,
+        ScopeContext::Repository,
+        ScopeContext::Tag,
+    ]
+    .into_iter()
+    .flat_map(|context| {
+        [ScopeLevel::Read, ScopeLevel::Write]
+            .into_iter()
+            .map(move |level| {
+                level
+                    .sufficient_levels()
+                    .iter()
+                    .map(|level| format!("{level}:{context}"))
+                    .collect()
+            })
+    })
+}
+
+/// Validates that the provider described by `metadata`, which published `keyset`, is usable
+/// with `audience`.
+fn validate_provider(
+    audience: &str,
+    metadata: &CoreProviderMetadata,
+    keyset: &HashMap<String, DecodingKey>,
+) -> anyhow::Result<()> {
+    let mut problems = vec![];
+    if audience.trim().is_empty() {
+        problems.push("the audience (client ID) must not be empty".into());
+    }
+    if keyset.is_empty() {
+        problems.push(format!(
+            "the JWK set at `{}` contains no RSA keys to verify tokens with",
+            metadata.jwks_uri().url()
+        ));
+    }
+    match metadata.scopes_supported() {
+        None => warn!(
+            target: "app::auth::oidc",
+            "provider metadata does not list supported scopes, skipping scope validation"
+        ),
+        Some(supported) => {
+            let supported: HashSet<_> = supported.iter().map(|scope| scope.as_str()).collect();
+            for scopes in required_scopes() {
+                if !scopes
+                    .iter()
+                    .any(|scope| supported.contains(scope.as_str()))
+                {
+                    problems.push(format!(
+                        "the provider supports none of the scopes `{}`",
+                        scopes.join("`, `")
+                    ));
+                }
+            }
+        }
+    }
+    if !problems.is_empty() {
+        bail!(
+            "OIDC provider `{}` is not configured correctly: {}",
+            metadata.issuer().as_str(),
+            problems.join("; ")
+        );
+    }
+    Ok(())
+}
+
 impl Verifier {
+    /// Constructs a new [Verifier] using the metadata discovered from the provider.
     pub fn new(config: OidcConfig) -> Result<Self, anyhow::Error> {
+        Self::discover(config, false)
+    }
+
+    /// Like [Verifier::new], but additionally validates that the provider is usable with the
+    /// configured audience, e.g. that it supports the scopes Drawbridge requires, such that
+    /// misconfigurations are reported on startup instead of on first use.
+    ///
+    /// Whether the provider issues tokens for the audience cannot be determined from its
+    /// metadata and is therefore not validated.
+    pub fn new_strict(config: OidcConfig) -> Result<Self, anyhow::Error> {
+        Self::discover(config, true)
+    }
+
+    fn discover(config: OidcConfig, strict: bool) -> Result<Self, anyhow::Error> {
         let mut validator = Validation::new(Algorithm::RS256);
-        validator.set_audience(&[config.audience]);
+        validator.set_audience(&[&config.audience]);
         validator.set_issuer(&[config.issuer.as_str()]);
         validator.set_required_spec_claims(&["exp", "iat", "scope", "aud"]);
         validator.validate_exp = true;
+        validator.validate_nbf = true;
+        validator.leeway = DEFAULT_OIDC_CLOCK_SKEW.as_secs();
 
-        let oidc_md =
-            CoreProviderMetadata::discover(&IssuerUrl::from_url(config.issuer), http_client)
-                .context("failed to discover provider metadata")?;
+        let issuer = IssuerUrl::from_url(config.issuer);
+        let oidc_md = CoreProviderMetadata::discover(&issuer, http_client)
+            .context("failed to discover provider metadata")?;
         let jwks = oidc_md.jwks();
         let jwks = serde_json::to_string(&jwks).context("failed to serialize jwks")?;
         let keyset: JwkSet = serde_json::from_str(&jwks).context("failed to parse jwks")?;
@@ -84,8 +182,32 @@ impl Verifier {
             })
             .collect::<Result<HashMap<String, DecodingKey>, anyhow::Error>>()
             .context("failed to parse jwks")?;
+        if strict {
+            validate_provider(&config.audience, &oidc_md, &keyset)?;
+        }
 
-        Ok(Self { keyset, validator })
+        Ok(Self {
+            issuer,
+            keyset,
+            validator,
+        })
+    }
+
+    /// Sets the tolerance for differences between the clocks of the server and the provider,
+    /// within which tokens are accepted past their `exp` or before their `nbf` claims.
+    /// Defaults to [DEFAULT_OIDC_CLOCK_SKEW].
+    pub fn with_clock_skew(mut self, skew: Duration) -> Self {
+        self.validator.leeway = skew.as_secs();
+        self
+    }
+
+    /// Probes whether the provider is reachable by discovering its metadata again.
+    ///
+    /// This performs blocking I/O.
+    pub(crate) fn probe(&self) -> anyhow::Result<()> {
+        _ = CoreProviderMetadata::discover(&self.issuer, http_client)
+            .context("failed to discover provider metadata")?;
+        Ok(())
     }
 
     fn verify_token(&self, token: &str) -> Result<VerifiedInfo, anyhow::Error> {
@@ -102,6 +224,12 @@ impl Verifier {
             decode::<VerifiedInfo>(token, key, &self.validator).context("Error decoding token")?;
         Ok(decoded_token.claims)
     }
+
+    /// Returns the subject of `token`, if it is valid, without recording an authorization
+    /// decision.
+    pub(crate) fn subject(&self, token: &str) -> Option<String> {
+        self.verify_token(token).ok().map(|info| info.subject)
+    }
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -109,6 +237,8 @@ pub enum ScopeContext {
     User,
     Repository,
     Tag,
+    /// Administration of all namespaces, which is optional for providers to support.
+    Admin,
 }
 
 impl std::fmt::Display for ScopeContext {
@@ -117,6 +247,7 @@ impl std::fmt::Display for ScopeContext {
             ScopeContext::User => write!(f, "drawbridge_users"),
             ScopeContext::Repository => write!(f, "drawbridge_repositories"),
             ScopeContext::Tag => write!(f, "drawbridge_tags"),
+            ScopeContext::Admin => write!(f, "drawbridge_admin"),
         }
     }
 }
@@ -145,13 +276,67 @@ impl ScopeLevel {
     }
 }
 
-#[repr(transparent)]
+/// Principal authenticated by [Claims].
+#[derive(Clone, Debug)]
+enum Principal {
+    /// Subject of a verified OpenID Connect token.
+    Oidc(VerifiedInfo),
+    /// Trusted client certificate contained in the
+    /// [CertificateWriters](super::CertificateWriters), which is identified by its hex-encoded
+    /// fingerprint.
+    Certificate {
+        fingerprint: String,
+        namespaces: HashSet<UserName>,
+    },
+    /// Access token minted by `owner`, which is used to access `repository` of `owner`, if
+    /// any.
+    Token {
+        token: AccessToken,
+        owner: UserContext,
+        repository: Option<RepositoryName>,
+    },
+}
+
 #[derive(Clone, Debug)]
-pub struct Claims(VerifiedInfo);
+pub struct Claims {
+    principal: Principal,
+    metrics: Option<Arc<Metrics>>,
+    webhook: Option<Arc<Webhook>>,
+    rate_limiter: Option<Arc<NamespaceLimiter>>,
+    resource: String,
+}
 
 impl Claims {
+    /// Returns the OpenID Connect subject, the hex-encoded fingerprint of the client
+    /// certificate or the ID of the access token, which authenticated the request.
     pub fn subject(&self) -> &str {
-        &self.0.subject
+        match self.principal {
+            Principal::Oidc(ref info) => &info.subject,
+            Principal::Certificate {
+                ref fingerprint, ..
+            } => fingerprint,
+            Principal::Token { ref token, .. } => token.id.as_str(),
+        }
+    }
+
+    /// Returns the decision recorded for granted access.
+    fn granted(&self) -> AuthDecision {
+        match self.principal {
+            Principal::Oidc(_) => AuthDecision::GrantedViaOidc,
+            Principal::Certificate { .. } => AuthDecision::GrantedViaCert,
+            Principal::Token { .. } => AuthDecision::GrantedViaToken,
+        }
+    }
+
+    /// Records the authorization `decision` for `target` and logs denials.
+    fn decide(&self, decision: AuthDecision, target: impl std::fmt::Display) -> AuthDecision {
+        if let Some(ref metrics) = self.metrics {
+            metrics.record_authorization(decision);
+        }
+        if !decision.is_granted() {
+            info!(target: "app::auth::oidc", subject = self.subject(), resource = %target, %decision, "access denied");
+        }
+        decision
     }
 
     fn check_scope(
@@ -159,31 +344,122 @@ impl Claims {
         context: ScopeContext,
         level: ScopeLevel,
     ) -> Result<(), (StatusCode, String)> {
+        let info = match self.principal {
+            Principal::Oidc(ref info) => info,
+            // Certificates may read repositories and write tags, but neither modify users nor
+            // repository configurations.
+            Principal::Certificate { .. } => {
+                if matches!(
+                    (context, level),
+                    (ScopeContext::Tag, _) | (ScopeContext::Repository, ScopeLevel::Read)
+                ) {
+                    return Ok(());
+                }
+                _ = self.decide(AuthDecision::DeniedAcl, format!("{level}:{context}"));
+                return Err((
+                    StatusCode::FORBIDDEN,
+                    format!(
+                        "Client certificate is not authorized for level {level}, context {context}"
+                    ),
+                ));
+            }
+            // Tokens may read the repositories they are restricted to and publish tags to them.
+            Principal::Token {
+                ref token,
+                ref repository,
+                ..
+            } => {
+                let action = match (context, level) {
+                    (ScopeContext::Repository | ScopeContext::Tag, ScopeLevel::Read) => {
+                        Some(TokenAction::Read)
+                    }
+                    (ScopeContext::Tag, ScopeLevel::Write) => Some(TokenAction::Publish),
+                    _ => None,
+                };
+                let Some(action) = action.filter(|action| action.is_granted(&token.actions)) else {
+                    _ = self.decide(
+                        AuthDecision::DeniedInsufficientScope,
+                        format!("{level}:{context}"),
+                    );
+                    return Err((
+                        StatusCode::FORBIDDEN,
+                        format!(
+                            "Access token is not authorized for level {level}, context {context}"
+                        ),
+                    ));
+                };
+                if !repository
+                    .as_ref()
+                    .is_some_and(|repository| token.repositories.contains(repository))
+                {
+                    _ = self.decide(AuthDecision::DeniedAcl, &self.resource);
+                    return Err((
+                        StatusCode::FORBIDDEN,
+                        format!("Access token is not authorized to {action} this repository"),
+                    ));
+                }
+                return Ok(());
+            }
+        };
         for level in level.sufficient_levels() {
             let scope = format!("{level}:{context}");
-            if self.0.scopes.contains(&scope) {
+            if info.scopes.contains(&scope) {
                 return Ok(());
             }
         }
+        _ = self.decide(
+            AuthDecision::DeniedInsufficientScope,
+            format!("{level}:{context}"),
+        );
         Err((
             StatusCode::UNAUTHORIZED,
             format!("Token is missing a scope for level {level}, context {context}"),
         ))
     }
 
+    /// Asserts that the authorization webhook, if configured, allows access of `level` to the
+    /// requested resource.
+    #[allow(clippy::result_large_err)]
+    async fn check_webhook(&self, level: ScopeLevel) -> Result<(), Response> {
+        let Some(ref webhook) = self.webhook else {
+            return Ok(());
+        };
+        let subject = match self.principal {
+            Principal::Oidc(ref info) => Subject::Oidc(info.subject.clone()),
+            Principal::Certificate {
+                ref fingerprint, ..
+            } => Subject::Certificate(fingerprint.clone()),
+            Principal::Token { ref token, .. } => Subject::Token(token.id.to_string()),
+        };
+        webhook
+            .authorize(subject, level, &self.resource)
+            .await
+            .inspect_err(|res| {
+                if res.status() == StatusCode::FORBIDDEN {
+                    _ = self.decide(AuthDecision::DeniedAcl, &self.resource);
+                }
+            })
+    }
+
     /// Asserts that the token has a scope that satisfies the given context and level.
     #[allow(clippy::result_large_err)]
-    pub fn assert_scope(
+    pub async fn assert_scope(
         &self,
         context: ScopeContext,
         level: ScopeLevel,
     ) -> Result<(), impl IntoResponse> {
         self.check_scope(context, level)
-            .map_err(|e| e.into_response())
+            .map_err(|e| e.into_response())?;
+        self.check_webhook(level).await?;
+        _ = self.decide(self.granted(), format!("{level}:{context}"));
+        Ok::<_, Response>(())
     }
 
     /// Assert that the client is the user identified by `cx`, and that the token has a scope that
     /// satisfies the given context and level.
+    ///
+    /// Client certificates are granted access to the namespaces they are mapped to instead.
+    #[allow(clippy::result_large_err)]
     pub async fn assert_user<'a>(
         &self,
         store: &'a Store,
@@ -191,6 +467,30 @@ impl Claims {
         scope_context: ScopeContext,
         scope_level: ScopeLevel,
     ) -> Result<User<'a>, impl IntoResponse> {
+        if let Principal::Certificate { ref namespaces, .. } = self.principal {
+            if !namespaces.contains(&cx.name) {
+                info!(target: "app::auth::oidc", user = ?cx, "client certificate is not authorized for namespace");
+                _ = self.decide(AuthDecision::DeniedAcl, cx);
+                return Err((
+                    StatusCode::FORBIDDEN,
+                    format!("Client certificate is not authorized for user `{cx}`"),
+                )
+                    .into_response());
+            }
+            return self.authorize(store, cx, scope_context, scope_level).await;
+        }
+        if let Principal::Token { ref owner, .. } = self.principal {
+            if owner != cx {
+                _ = self.decide(AuthDecision::DeniedAcl, cx);
+                return Err((
+                    StatusCode::FORBIDDEN,
+                    format!("Access token is not authorized for user `{cx}`"),
+                )
+                    .into_response());
+            }
+            return self.authorize(store, cx, scope_context, scope_level).await;
+        }
+
         let subj = self.subject();
         let oidc_record = UserRecord {
             subject: subj.to_string(),
@@ -199,7 +499,10 @@ impl Claims {
         let user = store.user(cx);
         let owner_record: UserRecord = user.get_content_json().await.map_err(|e|{
             match e {
-                GetError::NotFound => (StatusCode::UNAUTHORIZED, format!("User `{cx}` not found")).into_response(),
+                GetError::NotFound => {
+                    _ = self.decide(AuthDecision::DeniedAcl, cx);
+                    (StatusCode::UNAUTHORIZED, format!("User `{cx}` not found")).into_response()
+                },
                 _ => {
             warn!(target: "app::auth::oidc", ?oidc_record, error = ?e, "failed to get user by OpenID Connect subject");
 e.into_response()
@@ -208,17 +511,83 @@ e.into_response()
 
         if oidc_record != owner_record {
             warn!(target: "app::auth::oidc", ?oidc_record, user = ?cx, ?owner_record, "User access not authorized");
+            _ = self.decide(AuthDecision::DeniedAcl, cx);
             return Err((
                 StatusCode::UNAUTHORIZED,
                 format!("You are logged in as `{subj}`, and not authorized for user `{cx}`"),
             )
                 .into_response());
         }
+        self.authorize(store, cx, scope_context, scope_level).await
+    }
+
+    /// Asserts that the client may delete contents of the user identified by `cx`, i.e. that it
+    /// is the user and granted writes in `scope_context` as by [Claims::assert_user], or that
+    /// the token has a scope for writes in [ScopeContext::Admin], which applies to all users.
+    ///
+    /// Neither access tokens nor client certificates may delete anything.
+    #[allow(clippy::result_large_err)]
+    pub async fn assert_owner_or_admin<'a>(
+        &self,
+        store: &'a Store,
+        cx: &UserContext,
+        scope_context: ScopeContext,
+    ) -> Result<User<'a>, Response> {
+        match self.principal {
+            Principal::Token { .. } => {
+                _ = self.decide(AuthDecision::DeniedInsufficientScope, cx);
+                return Err((
+                    StatusCode::FORBIDDEN,
+                    "Access tokens may not delete contents",
+                )
+                    .into_response());
+            }
+            // Certificates may publish tags, but not delete them.
+            Principal::Certificate { .. } => {
+                _ = self.decide(AuthDecision::DeniedAcl, cx);
+                return Err((
+                    StatusCode::FORBIDDEN,
+                    "Client certificates may not delete contents",
+                )
+                    .into_response());
+            }
+            Principal::Oidc(_) => {}
+        }
+        if let Principal::Oidc(ref info) = self.principal {
+            if ScopeLevel::Write.sufficient_levels().iter().any(|level| {
+                info.scopes
+                    .contains(&format!("{level}:{}", ScopeContext::Admin))
+            }) {
+                self.check_webhook(ScopeLevel::Write).await?;
+                _ = self.decide(self.granted(), cx);
+                return Ok(store.user(cx));
+            }
+        }
+        self.assert_user(store, cx, scope_context, ScopeLevel::Write)
+            .await
+            .map_err(IntoResponse::into_response)
+    }
 
+    /// Asserts that the principal, which is known to act as the user identified by `cx`, is
+    /// granted access of `scope_level` in `scope_context`.
+    #[allow(clippy::result_large_err)]
+    async fn authorize<'a>(
+        &self,
+        store: &'a Store,
+        cx: &UserContext,
+        scope_context: ScopeContext,
+        scope_level: ScopeLevel,
+    ) -> Result<User<'a>, Response> {
         self.check_scope(scope_context, scope_level)
             .map_err(|e| e.into_response())?;
+        self.check_webhook(scope_level).await?;
+        // Only requests authenticated as the namespace count against its rate limit.
+        if let Some(ref limiter) = self.rate_limiter {
+            limiter.check(&cx.name)?;
+        }
 
-        Ok(user)
+        _ = self.decide(self.granted(), cx);
+        Ok(store.user(cx))
     }
 }
 
@@ -227,15 +596,47 @@ impl<B: Send> FromRequest<B> for Claims {
     type Rejection = Response;
 
     async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        let authenticated = |req: &RequestParts<B>, principal| Self {
+            principal,
+            metrics: req.extensions().get::<Arc<Metrics>>().cloned(),
+            webhook: req.extensions().get::<Arc<Webhook>>().cloned(),
+            rate_limiter: req.extensions().get::<Arc<NamespaceLimiter>>().cloned(),
+            resource: req.uri().path().into(),
+        };
+        let token = req.extract::<TypedHeader<Authorization<Bearer>>>().await;
+        // Tokens take precedence over client certificates, which may write to some namespaces.
+        if let Err(ref e) = token {
+            if let (TypedHeaderRejectionReason::Missing, Some(writer)) =
+                (e.reason(), req.extensions().get::<CertificateWriter>())
+            {
+                let principal = Principal::Certificate {
+                    fingerprint: writer.cert.hex_fingerprint(),
+                    namespaces: writer.namespaces.clone(),
+                };
+                trace!(target: "app::auth::oidc", "authenticated by client certificate");
+                return Ok(authenticated(req, principal));
+            }
+        }
         let TypedHeader(Authorization::<Bearer>(token)) =
-            req.extract()
-                .await
-                .map_err(|e: TypedHeaderRejection| match e.reason() {
-                    TypedHeaderRejectionReason::Missing => {
-                        (StatusCode::UNAUTHORIZED, "Bearer token header missing").into_response()
+            token
+                .map_err(|e: TypedHeaderRejection| {
+                    record_decision(req.extensions(), AuthDecision::DeniedNoAuth);
+                    info!(target: "app::auth::oidc", resource = req.uri().path(), decision = %AuthDecision::DeniedNoAuth, "access denied");
+                    match e.reason() {
+                        TypedHeaderRejectionReason::Missing => {
+                            (StatusCode::UNAUTHORIZED, "Bearer token header missing").into_response()
+                        }
+                        _ => e.into_response(),
                     }
-                    _ => e.into_response(),
                 })?;
+        // Access tokens are verified against the store instead of the provider. They are not
+        // logged, since they are long-lived secrets.
+        if tokens::is_access_token(token.token()) {
+            let principal =
+                verify_access_token(req.extensions(), req.uri().path(), token.token()).await?;
+            trace!(target: "app::auth::oidc", "authenticated by access token");
+            return Ok(authenticated(req, principal));
+        }
         warn!(target: "app::auth::oidc", ?token, "got token");
 
         let Extension(verifier) = req
@@ -248,14 +649,71 @@ impl<B: Send> FromRequest<B> for Claims {
 
         trace!(target: "app:auth::oidc", "verifying token");
 
-        let claims = verifier
-            .verify_token(token.token())
+        let start = Instant::now();
+        let claims = verifier.verify_token(token.token());
+        if let Some(timing) = req.extensions().get::<ServerTiming>() {
+            timing.record("auth", start.elapsed());
+        }
+        if let Some(metrics) = req.extensions().get::<Arc<Metrics>>() {
+            metrics.record_oidc_token(match claims {
+                Ok(_) => TokenOutcome::Valid,
+                Err(ref e)
+                    if e.downcast_ref::<jsonwebtoken::errors::Error>()
+                        .is_some_and(|e| matches!(e.kind(), ErrorKind::ExpiredSignature)) =>
+                {
+                    TokenOutcome::Expired
+                }
+                Err(_) => TokenOutcome::Invalid,
+            });
+        }
+        let claims = claims
             .map_err(|e| {
                 error!(target: "app::auth::oidc", error = ?e, "failed to verify token");
+                record_decision(req.extensions(), AuthDecision::DeniedNoAuth);
+                info!(target: "app::auth::oidc", resource = req.uri().path(), decision = %AuthDecision::DeniedNoAuth, "access denied");
                 (StatusCode::UNAUTHORIZED, "Invalid token provided").into_response()
             })
-            .map(Self);
+            .map(|info| authenticated(req, Principal::Oidc(info)));
         info!(target: "app::auth::oidc", ?claims, "verified token");
         claims
     }
 }
+
+/// Verifies the access `token` sent in a request to `path` with `extensions`, which is valid
+/// for the user the request is routed to.
+#[allow(clippy::result_large_err)]
+async fn verify_access_token(
+    extensions: &Extensions,
+    path: &str,
+    token: &str,
+) -> Result<Principal, Response> {
+    let denied = |msg: &'static str| {
+        record_decision(extensions, AuthDecision::DeniedNoAuth);
+        info!(target: "app::auth::oidc", resource = path, decision = %AuthDecision::DeniedNoAuth, "access denied");
+        (StatusCode::UNAUTHORIZED, msg).into_response()
+    };
+    let (Some(store), Some(name)) = (extensions.get::<Arc<Store>>(), extensions.get::<UserName>())
+    else {
+        return Err(denied("Invalid token provided"));
+    };
+    let owner = UserContext { name: name.clone() };
+
+    let start = Instant::now();
+    let res = tokens::verify(&store.user(&owner), token, SystemTime::now()).await;
+    if let Some(timing) = extensions.get::<ServerTiming>() {
+        timing.record("auth", start.elapsed());
+    }
+    match res {
+        Ok(token) => Ok(Principal::Token {
+            token,
+            owner,
+            repository: extensions.get::<RepositoryName>().cloned(),
+        }),
+        Err(TokenError::Invalid) => Err(denied("Invalid token provided")),
+        Err(TokenError::Expired) => Err(denied("Access token expired")),
+        Err(TokenError::Internal(e)) => {
+            error!(target: "app::auth::oidc", error = ?e, "failed to verify access token");
+            Err(StatusCode::INTERNAL_SERVER_ERROR.into_response())
+        }
+    }
+}
@@ -0,0 +1,292 @@
+// SPDX-FileCopyrightText: 2022 Profian Inc. <opensource@profian.com>
+// SPDX-License-Identifier: AGPL-3.0-only
+
+use super::super::{ClientInfo, Store};
+use super::{OidcClaims, ScopeContext, ScopeLevel};
+
+use std::fmt;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use drawbridge_type::RepositoryContext;
+
+use async_std::sync::Arc;
+use axum::http::header::HOST;
+use axum::http::{HeaderMap, StatusCode, Uri};
+use axum::response::{IntoResponse, Response};
+use axum::{Extension, Json};
+use openidconnect::url::Url;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use tracing::{debug, trace};
+
+/// Name of the query parameter carrying the expiry of a signed URL in seconds since the Unix
+/// epoch.
+const EXPIRES: &str = "expires";
+
+/// Name of the query parameter carrying the signature of a signed URL.
+const SIGNATURE: &str = "signature";
+
+/// Name of the query parameter requesting the lifetime of a signed URL in seconds.
+const EXPIRES_IN: &str = "expires-in";
+
+/// Lifetime of signed URLs, unless requested otherwise.
+pub(crate) const DEFAULT_SIGNED_URL_LIFETIME: Duration = Duration::from_secs(60 * 60);
+
+/// Maximum lifetime of signed URLs clients may request.
+pub(crate) const MAX_SIGNED_URL_LIFETIME: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// Block size of SHA-256 used by HMAC.
+const BLOCK_SIZE: usize = 64;
+
+/// Computes HMAC-SHA256 of `msg` using `key` padded to [BLOCK_SIZE] as specified by RFC 2104.
+fn hmac(key: &[u8; BLOCK_SIZE], msg: &[u8]) -> [u8; 32] {
+    let pad = |b: u8| key.map(|k| k ^ b);
+    let inner = Sha256::new()
+        .chain_update(pad(0x36))
+        .chain_update(msg)
+        .finalize();
+    Sha256::new()
+        .chain_update(pad(0x5c))
+        .chain_update(inner)
+        .finalize()
+        .into()
+}
+
+/// Returns `secret` padded to [BLOCK_SIZE] or its digest, if it is longer, as specified by
+/// RFC 2104.
+fn block_key(secret: &[u8]) -> [u8; BLOCK_SIZE] {
+    let mut key = [0; BLOCK_SIZE];
+    if secret.len() > BLOCK_SIZE {
+        key[..32].copy_from_slice(&Sha256::digest(secret));
+    } else {
+        key[..secret.len()].copy_from_slice(secret);
+    }
+    key
+}
+
+/// Computes HMAC-SHA256 of `msg` using `key` of any length.
+pub(crate) fn hmac_sha256(key: &[u8], msg: &[u8]) -> [u8; 32] {
+    hmac(&block_key(key), msg)
+}
+
+pub(crate) fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn decode_hex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) || !s.is_ascii() {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&s[i..i + 2], 16).ok())
+        .collect()
+}
+
+/// Returns the value of query parameter `name` of `uri`, if present.
+fn query_param<'a>(uri: &'a Uri, name: &str) -> Option<&'a str> {
+    uri.query()
+        .unwrap_or_default()
+        .split('&')
+        .find_map(|param| match param.split_once('=') {
+            Some((n, value)) if n == name => Some(value),
+            None if param == name => Some(""),
+            _ => None,
+        })
+}
+
+fn unix_time(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// Signs and verifies URLs, which grant time-limited anonymous read access to a single tag or
+/// tree node.
+///
+/// Signatures are computed using HMAC-SHA256 over the request path and the expiry, such that
+/// a signed URL cannot be used to access any other path.
+pub(crate) struct UrlSigner {
+    key: [u8; BLOCK_SIZE],
+    public_url: Option<Url>,
+}
+
+impl fmt::Debug for UrlSigner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("UrlSigner")
+            .field("public_url", &self.public_url)
+            .finish_non_exhaustive()
+    }
+}
+
+impl UrlSigner {
+    /// Constructs a new [UrlSigner] using `secret` as the HMAC key, which signs URLs relative
+    /// to `public_url`, if set, or the host the signing request was sent to otherwise.
+    pub(crate) fn new(secret: &[u8], public_url: Option<Url>) -> Self {
+        Self {
+            key: block_key(secret),
+            public_url,
+        }
+    }
+
+    fn signature(&self, path: &str, expires: u64) -> [u8; 32] {
+        hmac(&self.key, format!("{path}\n{expires}").as_bytes())
+    }
+
+    /// Returns the query string of a URL for `path`, which is valid until `expires`.
+    fn sign(&self, path: &str, expires: u64) -> String {
+        format!(
+            "{EXPIRES}={expires}&{SIGNATURE}={}",
+            encode_hex(&self.signature(path, expires))
+        )
+    }
+
+    /// Verifies the signature contained in `uri` at `now`.
+    ///
+    /// Returns `false` if `uri` is not signed and `403 Forbidden` if the signature is invalid
+    /// or expired.
+    #[allow(clippy::result_large_err)]
+    pub(crate) fn verify(&self, uri: &Uri, now: SystemTime) -> Result<bool, Response> {
+        let Some(signature) = query_param(uri, SIGNATURE) else {
+            return Ok(false);
+        };
+        let forbidden = |msg| Err((StatusCode::FORBIDDEN, msg).into_response());
+        let Some(expires) = query_param(uri, EXPIRES).and_then(|v| v.parse::<u64>().ok()) else {
+            return forbidden("Signed URL is missing a valid expiry");
+        };
+        let valid = decode_hex(signature).is_some_and(|signature| {
+            let expected = self.signature(uri.path(), expires);
+            // Compare in constant time to not leak the expected signature.
+            signature.len() == expected.len()
+                && signature
+                    .iter()
+                    .zip(expected)
+                    .fold(0, |acc, (a, b)| acc | (a ^ b))
+                    == 0
+        });
+        if !valid {
+            debug!(target: "app::auth::signed_url", "invalid signature for `{}`", uri.path());
+            return forbidden("Invalid URL signature");
+        }
+        if unix_time(now) >= expires {
+            debug!(target: "app::auth::signed_url", "signed URL for `{}` expired", uri.path());
+            return forbidden("Signed URL expired");
+        }
+        Ok(true)
+    }
+}
+
+/// Returns a signed URL for the tag or tree node the request is sent to, which can be used to
+/// download it without authentication until it expires.
+///
+/// The lifetime in seconds may be requested using the `expires-in` query parameter and defaults
+/// to [DEFAULT_SIGNED_URL_LIFETIME].
+pub(crate) async fn sign(
+    Extension(ref store): Extension<Arc<Store>>,
+    signer: Option<Extension<Arc<UrlSigner>>>,
+    client: Option<Extension<ClientInfo>>,
+    claims: OidcClaims,
+    cx: RepositoryContext,
+    uri: Uri,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    trace!(target: "app::auth::signed_url", "called for `{}`", uri.path());
+
+    let Some(Extension(signer)) = signer else {
+        return Err((StatusCode::NOT_IMPLEMENTED, "URL signing is not enabled").into_response());
+    };
+    _ = claims
+        .assert_user(store, &cx.owner, ScopeContext::Repository, ScopeLevel::Read)
+        .await
+        .map_err(IntoResponse::into_response)?;
+
+    let lifetime = match query_param(&uri, EXPIRES_IN) {
+        None => DEFAULT_SIGNED_URL_LIFETIME,
+        Some(secs) => secs
+            .parse()
+            .ok()
+            .map(Duration::from_secs)
+            .filter(|lifetime| !lifetime.is_zero() && *lifetime <= MAX_SIGNED_URL_LIFETIME)
+            .ok_or_else(|| {
+                (
+                    StatusCode::BAD_REQUEST,
+                    format!(
+                        "`{EXPIRES_IN}` must be between 1 and {} seconds",
+                        MAX_SIGNED_URL_LIFETIME.as_secs()
+                    ),
+                )
+                    .into_response()
+            })?,
+    };
+    let expires = unix_time(SystemTime::now() + lifetime);
+    let path_and_query = format!("{}?{}", uri.path(), signer.sign(uri.path(), expires));
+    let url = match signer.public_url {
+        Some(ref base) => format!("{}{path_and_query}", base.as_str().trim_end_matches('/')),
+        None => {
+            let client = client.map(|Extension(client)| client);
+            let host = client
+                .as_ref()
+                .and_then(|client| client.host.as_deref())
+                .or_else(|| headers.get(HOST).and_then(|host| host.to_str().ok()));
+            match host {
+                Some(host) => format!(
+                    "{}://{host}{path_and_query}",
+                    client.as_ref().map_or("https", |client| client.scheme)
+                ),
+                None => path_and_query,
+            }
+        }
+    };
+    Ok(Json(json!({ "url": url, EXPIRES: expires })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hmac_sha256() {
+        // RFC 4231, test case 2.
+        assert_eq!(
+            encode_hex(&super::hmac_sha256(
+                b"Jefe",
+                b"what do ya want for nothing?"
+            )),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+        // RFC 4231, test case 6, using a key longer than the block size.
+        assert_eq!(
+            encode_hex(&super::hmac_sha256(
+                &[0xaa; 131],
+                b"Test Using Larger Than Block-Size Key - Hash Key First"
+            )),
+            "60e431591ee0b67f0d8a26aacbf5b77f8e0bc6213728c5140546040f0ee37f54"
+        );
+    }
+
+    #[test]
+    fn verify() {
+        const PATH: &str = "/api/v0.1.0/user/repo/_tag/0.1.0";
+
+        let signer = UrlSigner::new(b"secret", None);
+        let now = SystemTime::now();
+        let expires = unix_time(now) + 60;
+        let verify = |uri: &str, now| signer.verify(&uri.parse().unwrap(), now).ok();
+
+        let signed = format!("{PATH}?{}", signer.sign(PATH, expires));
+        assert_eq!(verify(PATH, now), Some(false));
+        assert_eq!(verify(&signed, now), Some(true));
+        assert_eq!(verify(&signed, now + Duration::from_secs(60)), None);
+
+        let other = format!("{PATH}1?{}", signer.sign(PATH, expires));
+        assert_eq!(verify(&other, now), None);
+        let extended = signed.replace(&expires.to_string(), &(expires + 1).to_string());
+        assert_eq!(verify(&extended, now), None);
+        let resigned = format!(
+            "{PATH}?{}",
+            UrlSigner::new(b"other", None).sign(PATH, expires)
+        );
+        assert_eq!(verify(&resigned, now), None);
+        assert_eq!(verify(&format!("{PATH}?signature=00"), now), None);
+    }
+}
@@ -1,38 +1,401 @@
 // SPDX-FileCopyrightText: 2022 Profian Inc. <opensource@profian.com>
 // SPDX-License-Identifier: AGPL-3.0-only
 
+use std::collections::HashSet;
 use std::io::BufRead;
+use std::mem;
 use std::ops::Deref;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant, SystemTime};
 
-use anyhow::{anyhow, bail, Context};
-use rustls::server::AllowAnyAnonymousOrAuthenticatedClient;
-use rustls::{Certificate, PrivateKey, RootCertStore, ServerConfig};
+use anyhow::{anyhow, bail, ensure, Context};
+use rustls::client::HandshakeSignatureValid;
+use rustls::server::{
+    AllowAnyAnonymousOrAuthenticatedClient, ClientCertVerified, ClientCertVerifier, ClientHello,
+    NoServerSessionStorage, ProducesTickets, ResolvesServerCert, ServerSessionMemoryCache,
+};
+use rustls::sign::{any_supported_type, CertifiedKey};
+use rustls::{
+    Certificate, DigitallySignedStruct, DistinguishedNames, Error, PrivateKey, ProtocolVersion,
+    RootCertStore, ServerConfig, SignatureScheme, SupportedProtocolVersion,
+};
 use rustls_pemfile::Item::{ECKey, PKCS8Key, RSAKey, X509Certificate};
+use sha2::{Digest, Sha256};
+use tracing::warn;
+use webpki::{DnsNameRef, EndEntityCert};
 
+/// Marker of requests authenticated by a trusted client certificate, which is inserted into
+/// request extensions.
 #[derive(Clone, Copy, Debug)]
 #[repr(transparent)]
-pub struct TrustedCertificate;
+pub struct TrustedCertificate([u8; 32]);
 
+impl TrustedCertificate {
+    pub(crate) fn new(cert: &Certificate) -> Self {
+        Self(Sha256::digest(&cert.0).into())
+    }
+
+    /// Returns the SHA-256 fingerprint of the certificate.
+    pub fn fingerprint(&self) -> &[u8; 32] {
+        &self.0
+    }
+
+    /// Returns the hex-encoded SHA-256 fingerprint of the certificate.
+    pub(crate) fn hex_fingerprint(&self) -> String {
+        self.0.iter().map(|b| format!("{b:02x}")).collect()
+    }
+}
+
+/// Set of SHA-256 fingerprints of client certificates, which are allowed access.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
 #[repr(transparent)]
+pub struct CertificateAllowlist(HashSet<[u8; 32]>);
+
+impl CertificateAllowlist {
+    /// Reads an allowlist containing one hex-encoded SHA-256 certificate fingerprint per line.
+    ///
+    /// Bytes of a fingerprint may optionally be separated by `:`. Empty lines and lines
+    /// starting with `#` are ignored.
+    pub fn read(rd: impl BufRead) -> anyhow::Result<Self> {
+        rd.lines()
+            .enumerate()
+            .filter_map(|(i, line)| match line {
+                Ok(line) if line.trim().is_empty() || line.trim_start().starts_with('#') => None,
+                Ok(line) => Some(
+                    parse_fingerprint(line.trim())
+                        .with_context(|| format!("invalid fingerprint on line {}", i + 1)),
+                ),
+                Err(e) => Some(Err(e).context("failed to read allowlist")),
+            })
+            .collect()
+    }
+
+    /// Returns `true` if the fingerprint of `cert` is contained in the allowlist.
+    pub fn contains(&self, cert: &Certificate) -> bool {
+        let fingerprint: [u8; 32] = Sha256::digest(&cert.0).into();
+        self.0.contains(&fingerprint)
+    }
+}
+
+impl FromIterator<[u8; 32]> for CertificateAllowlist {
+    fn from_iter<T: IntoIterator<Item = [u8; 32]>>(iter: T) -> Self {
+        Self(iter.into_iter().collect())
+    }
+}
+
+pub(super) fn parse_fingerprint(s: &str) -> anyhow::Result<[u8; 32]> {
+    let s = s.replace(':', "");
+    ensure!(
+        s.len() == 64 && s.bytes().all(|b| b.is_ascii_hexdigit()),
+        "expected 64 hexadecimal characters"
+    );
+    let mut fingerprint = [0; 32];
+    for (b, i) in fingerprint.iter_mut().zip((0..s.len()).step_by(2)) {
+        *b = u8::from_str_radix(&s[i..i + 2], 16)?;
+    }
+    Ok(fingerprint)
+}
+
+/// Default number of TLS sessions cached for resumption.
+pub const DEFAULT_TLS_SESSION_CACHE_SIZE: usize = 256;
+
+/// Default lifetime of TLS session tickets.
+pub const DEFAULT_TLS_TICKET_LIFETIME: Duration = Duration::from_secs(12 * 60 * 60);
+
+/// Maximum lifetime of TLS session tickets.
+///
+/// Keys used to encrypt tickets are generated by [rustls::Ticketer], which erases them within
+/// at most this duration.
+pub const MAX_TLS_TICKET_LIFETIME: Duration = Duration::from_secs(12 * 60 * 60);
+
+/// Parameters of TLS session resumption.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SessionConfig {
+    /// Number of sessions cached in memory for resumption by session ID, `0` to disable the
+    /// cache.
+    pub cache_size: usize,
+    /// Lifetime of session tickets, `None` to not issue any tickets.
+    ///
+    /// Tickets are encrypted by keys, which are rotated every half of the lifetime, such that
+    /// forward secrecy of resumed sessions is bounded by the lifetime.
+    pub ticket_lifetime: Option<Duration>,
+}
+
+impl Default for SessionConfig {
+    fn default() -> Self {
+        Self {
+            cache_size: DEFAULT_TLS_SESSION_CACHE_SIZE,
+            ticket_lifetime: Some(DEFAULT_TLS_TICKET_LIFETIME),
+        }
+    }
+}
+
+/// Ticket encryption key.
+type TicketKey = Arc<dyn ProducesTickets>;
+
+/// Keys used by a [Ticketer].
+struct TicketKeys {
+    current: TicketKey,
+    previous: Option<TicketKey>,
+    /// Instant at which `current` was generated.
+    generated: Instant,
+}
+
+/// Session ticketer, which rotates its keys every half of the ticket lifetime and erases them
+/// once the lifetime elapsed.
+struct Ticketer {
+    lifetime: Duration,
+    keys: Mutex<TicketKeys>,
+}
+
+impl Ticketer {
+    fn new(lifetime: Duration) -> anyhow::Result<Self> {
+        ensure!(
+            lifetime >= Duration::from_secs(2) && lifetime <= MAX_TLS_TICKET_LIFETIME,
+            "TLS ticket lifetime must be between 2 and {} seconds",
+            MAX_TLS_TICKET_LIFETIME.as_secs()
+        );
+        Ok(Self {
+            lifetime,
+            keys: Mutex::new(TicketKeys {
+                current: generate_ticket_keys()?,
+                previous: None,
+                generated: Instant::now(),
+            }),
+        })
+    }
+
+    /// Returns the current and previous keys at `now`, rotating them if necessary.
+    fn keys(&self, now: Instant) -> anyhow::Result<(TicketKey, Option<TicketKey>)> {
+        let period = self.lifetime / 2;
+        let mut keys = self.keys.lock().unwrap_or_else(PoisonError::into_inner);
+        let elapsed = now.saturating_duration_since(keys.generated);
+        if elapsed >= 2 * period {
+            *keys = TicketKeys {
+                current: generate_ticket_keys()?,
+                previous: None,
+                generated: now,
+            };
+        } else if elapsed >= period {
+            let current = generate_ticket_keys()?;
+            keys.previous = Some(mem::replace(&mut keys.current, current));
+            keys.generated += period;
+        }
+        Ok((keys.current.clone(), keys.previous.clone()))
+    }
+}
+
+fn generate_ticket_keys() -> anyhow::Result<TicketKey> {
+    rustls::Ticketer::new().map_err(|_| anyhow!("failed to generate TLS ticket keys"))
+}
+
+impl ProducesTickets for Ticketer {
+    fn enabled(&self) -> bool {
+        true
+    }
+
+    fn lifetime(&self) -> u32 {
+        self.lifetime.as_secs() as _
+    }
+
+    fn encrypt(&self, plain: &[u8]) -> Option<Vec<u8>> {
+        let (current, _) = self.keys(Instant::now()).ok()?;
+        current.encrypt(plain)
+    }
+
+    fn decrypt(&self, cipher: &[u8]) -> Option<Vec<u8>> {
+        let (current, previous) = self.keys(Instant::now()).ok()?;
+        current
+            .decrypt(cipher)
+            .or_else(|| previous.and_then(|previous| previous.decrypt(cipher)))
+    }
+}
+
+/// Default maximum total size of client certificate chains in bytes.
+pub const DEFAULT_MAX_CLIENT_CERT_CHAIN: usize = 16 * 1024;
+
+/// Protocol IDs of the HTTP versions served, which may be negotiated using ALPN.
+pub const SERVED_ALPN_PROTOCOLS: &[&str] = &["http/1.1", "http/1.0"];
+
+/// Parameters of TLS connections.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Options {
+    /// Session resumption parameters.
+    pub sessions: SessionConfig,
+    /// Maximum total size of the DER-encoded certificates in a client certificate chain, above
+    /// which the handshake fails.
+    ///
+    /// Independently of this limit, a chain must fit into a single handshake message of at most
+    /// 64 KiB.
+    pub max_client_cert_chain: usize,
+    /// Protocol IDs advertised using ALPN in order of preference, which are used verbatim.
+    ///
+    /// If not empty, at least one of [SERVED_ALPN_PROTOCOLS] must be contained. If empty, no
+    /// protocol is negotiated.
+    pub alpn_protocols: Vec<String>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            sessions: Default::default(),
+            max_client_cert_chain: DEFAULT_MAX_CLIENT_CERT_CHAIN,
+            alpn_protocols: vec![],
+        }
+    }
+}
+
+/// Validates the ALPN protocol IDs `protocols` and returns their wire encodings.
+fn alpn_protocols(protocols: Vec<String>) -> anyhow::Result<Vec<Vec<u8>>> {
+    if protocols.is_empty() {
+        return Ok(vec![]);
+    }
+    let mut seen = HashSet::new();
+    for protocol in &protocols {
+        ensure!(
+            (1..=255).contains(&protocol.len()),
+            "ALPN protocol ID `{protocol}` must be between 1 and 255 bytes long"
+        );
+        ensure!(
+            seen.insert(protocol.as_str()),
+            "ALPN protocol ID `{protocol}` specified more than once"
+        );
+        if !SERVED_ALPN_PROTOCOLS.contains(&protocol.as_str()) {
+            warn!(
+                target: "app::auth::tls",
+                "advertised ALPN protocol `{protocol}` is not served, connections negotiating it fail"
+            );
+        }
+    }
+    ensure!(
+        SERVED_ALPN_PROTOCOLS
+            .iter()
+            .any(|served| seen.contains(served)),
+        "ALPN protocols must contain at least one of {SERVED_ALPN_PROTOCOLS:?}"
+    );
+    Ok(protocols.into_iter().map(String::into_bytes).collect())
+}
+
+/// Client certificate verifier, which rejects chains exceeding a total size before passing
+/// them on to `inner`.
+struct ChainLimit {
+    inner: Arc<dyn ClientCertVerifier>,
+    max_bytes: usize,
+}
+
+impl ClientCertVerifier for ChainLimit {
+    fn offer_client_auth(&self) -> bool {
+        self.inner.offer_client_auth()
+    }
+
+    fn client_auth_mandatory(&self) -> Option<bool> {
+        self.inner.client_auth_mandatory()
+    }
+
+    fn client_auth_root_subjects(&self) -> Option<DistinguishedNames> {
+        self.inner.client_auth_root_subjects()
+    }
+
+    fn verify_client_cert(
+        &self,
+        end_entity: &Certificate,
+        intermediates: &[Certificate],
+        now: SystemTime,
+    ) -> Result<ClientCertVerified, Error> {
+        let size = intermediates
+            .iter()
+            .fold(end_entity.0.len(), |size, cert| size + cert.0.len());
+        if size > self.max_bytes {
+            warn!(
+                target: "app::auth::tls",
+                "rejected client certificate chain of {} certificates and {size} bytes exceeding the limit of {} bytes",
+                intermediates.len() + 1,
+                self.max_bytes
+            );
+            return Err(Error::InvalidCertificateData(format!(
+                "client certificate chain exceeds {} bytes",
+                self.max_bytes
+            )));
+        }
+        self.inner
+            .verify_client_cert(end_entity, intermediates, now)
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &Certificate,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, Error> {
+        self.inner.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &Certificate,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, Error> {
+        self.inner.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.inner.supported_verify_schemes()
+    }
+}
+
+/// Server certificate resolver, which selects the first certificate valid for the server name
+/// requested by the client using SNI, falling back to the first certificate if none is or no
+/// name was requested.
+struct SniResolver(Vec<Arc<CertifiedKey>>);
+
+impl SniResolver {
+    /// Returns the certificate to present to clients requesting `server_name`.
+    fn select(&self, server_name: Option<&str>) -> Option<&Arc<CertifiedKey>> {
+        server_name
+            .and_then(|name| DnsNameRef::try_from_ascii_str(name).ok())
+            .and_then(|name| {
+                self.0.iter().find(|key| {
+                    key.end_entity_cert().ok().is_some_and(|cert| {
+                        EndEntityCert::try_from(cert.0.as_slice())
+                            .and_then(|cert| cert.verify_is_valid_for_dns_name(name))
+                            .is_ok()
+                    })
+                })
+            })
+            .or_else(|| self.0.first())
+    }
+}
+
+impl ResolvesServerCert for SniResolver {
+    fn resolve(&self, hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        self.select(hello.server_name()).cloned()
+    }
+}
+
 #[allow(missing_debug_implementations)]
 #[derive(Clone)]
-pub struct Config(ServerConfig);
+pub struct Config {
+    server: ServerConfig,
+    versions: &'static [&'static SupportedProtocolVersion],
+    client_auth_mandatory: bool,
+}
 
 impl Deref for Config {
     type Target = ServerConfig;
 
     fn deref(&self) -> &Self::Target {
-        &self.0
+        &self.server
     }
 }
 
 impl From<Config> for ServerConfig {
     fn from(conf: Config) -> Self {
-        conf.0
+        conf.server
     }
 }
 
-fn read_certificates(mut rd: impl BufRead) -> anyhow::Result<Vec<Certificate>> {
+pub(crate) fn read_certificates(mut rd: impl BufRead) -> anyhow::Result<Vec<Certificate>> {
     rustls_pemfile::read_all(&mut rd)?
         .into_iter()
         .map(|item| match item {
@@ -42,30 +405,101 @@ fn read_certificates(mut rd: impl BufRead) -> anyhow::Result<Vec<Certificate>> {
         .collect()
 }
 
+pub(crate) fn read_key(mut rd: impl BufRead) -> anyhow::Result<PrivateKey> {
+    let mut items = rustls_pemfile::read_all(&mut rd).context("failed to read certificate key")?;
+    let key = items
+        .pop()
+        .ok_or_else(|| anyhow!("certificate key missing"))
+        .and_then(|item| match item {
+            RSAKey(buf) | PKCS8Key(buf) | ECKey(buf) => Ok(PrivateKey(buf)),
+            _ => bail!("unsupported key type"),
+        })?;
+    if !items.is_empty() {
+        bail!("more than one certificate key specified")
+    }
+    Ok(key)
+}
+
+/// Reads a server certificate chain from `certs` and its key from `key`.
+fn read_identity(mut certs: impl BufRead, key: impl BufRead) -> anyhow::Result<CertifiedKey> {
+    let certs = read_certificates(&mut certs).context("failed to read server certificate chain")?;
+    let cert = certs
+        .first()
+        .ok_or_else(|| anyhow!("server certificate chain empty"))?;
+    _ = EndEntityCert::try_from(cert.0.as_slice())
+        .map_err(|e| anyhow!("invalid server certificate: {e}"))?;
+    let key = read_key(key)?;
+    let key = any_supported_type(&key).context("invalid server certificate key")?;
+    Ok(CertifiedKey::new(certs, key))
+}
+
 impl Config {
-    pub fn read(
-        mut certs: impl BufRead,
-        mut key: impl BufRead,
+    /// Reads the configuration using the [SessionConfig::default] session resumption
+    /// parameters.
+    pub fn read(certs: impl BufRead, key: impl BufRead, cas: impl BufRead) -> anyhow::Result<Self> {
+        Self::read_with_sessions(certs, key, cas, Default::default())
+    }
+
+    /// Reads the configuration using the session resumption parameters `sessions` and the
+    /// [Options::default] values of all other parameters.
+    pub fn read_with_sessions(
+        certs: impl BufRead,
+        key: impl BufRead,
+        cas: impl BufRead,
+        sessions: SessionConfig,
+    ) -> anyhow::Result<Self> {
+        Self::read_with_options(
+            certs,
+            key,
+            cas,
+            Options {
+                sessions,
+                ..Default::default()
+            },
+        )
+    }
+
+    /// Reads the configuration using the connection parameters `options`.
+    pub fn read_with_options(
+        certs: impl BufRead,
+        key: impl BufRead,
+        cas: impl BufRead,
+        options: Options,
+    ) -> anyhow::Result<Self> {
+        Self::read_with_identities([(certs, key)], cas, options)
+    }
+
+    /// Reads the configuration serving each of the certificate chains and keys of `identities`
+    /// using the connection parameters `options`.
+    ///
+    /// Clients are presented the first certificate valid for the server name they requested
+    /// using SNI, or the first certificate if none is or no server name was requested.
+    pub fn read_with_identities(
+        identities: impl IntoIterator<Item = (impl BufRead, impl BufRead)>,
         mut cas: impl BufRead,
+        Options {
+            sessions,
+            max_client_cert_chain,
+            alpn_protocols,
+        }: Options,
     ) -> anyhow::Result<Self> {
-        let certs =
-            read_certificates(&mut certs).context("failed to read server certificate chain")?;
-        let key = {
-            let mut items = rustls_pemfile::read_all(&mut key)
-                .context("failed to read server certificate key")?;
-            let key = items
-                .pop()
-                .ok_or_else(|| anyhow!("server certificate key missing"))
-                .and_then(|item| match item {
-                    RSAKey(buf) | PKCS8Key(buf) | ECKey(buf) => Ok(PrivateKey(buf)),
-                    _ => bail!("unsupported key type"),
-                })?;
-            if !items.is_empty() {
-                bail!("more than one server certificate key specified")
-            }
-            key
-        };
+        ensure!(
+            max_client_cert_chain > 0,
+            "maximum client certificate chain size must not be zero"
+        );
+        let alpn_protocols = self::alpn_protocols(alpn_protocols)?;
+        let keys = identities
+            .into_iter()
+            .enumerate()
+            .map(|(i, (certs, key))| {
+                read_identity(certs, key)
+                    .map(Arc::new)
+                    .with_context(|| format!("failed to read server certificate {}", i + 1))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        ensure!(!keys.is_empty(), "no server certificate specified");
 
+        let client_auth_mandatory = false;
         let client_verifier = {
             let mut roots = RootCertStore::empty();
             read_certificates(&mut cas)
@@ -74,14 +508,229 @@ impl Config {
                 .try_for_each(|ref cert| roots.add(cert))
                 .context("failed to construct root certificate store")?;
             // TODO: Allow client certificates signed by unknown CAs.
-            AllowAnyAnonymousOrAuthenticatedClient::new(roots)
+            Arc::new(ChainLimit {
+                inner: AllowAnyAnonymousOrAuthenticatedClient::new(roots),
+                max_bytes: max_client_cert_chain,
+            })
         };
 
-        ServerConfig::builder()
-            .with_safe_defaults()
+        let versions = rustls::DEFAULT_VERSIONS;
+        let mut server = ServerConfig::builder()
+            .with_safe_default_cipher_suites()
+            .with_safe_default_kx_groups()
+            .with_protocol_versions(versions)
+            .context("invalid TLS protocol versions")?
             .with_client_cert_verifier(client_verifier)
-            .with_single_cert(certs, key)
-            .context("invalid server certificate key")
-            .map(Self)
+            .with_cert_resolver(Arc::new(SniResolver(keys)));
+        server.session_storage = match sessions.cache_size {
+            0 => Arc::new(NoServerSessionStorage {}),
+            size => ServerSessionMemoryCache::new(size),
+        };
+        if let Some(lifetime) = sessions.ticket_lifetime {
+            server.ticketer = Arc::new(Ticketer::new(lifetime)?);
+        }
+        server.alpn_protocols = alpn_protocols;
+        Ok(Self {
+            server,
+            versions,
+            client_auth_mandatory,
+        })
+    }
+
+    /// Returns the enabled TLS protocol versions.
+    pub fn protocol_versions(&self) -> impl Iterator<Item = ProtocolVersion> {
+        self.versions.iter().map(|v| v.version)
+    }
+
+    /// Returns `true` if clients are required to present a certificate.
+    pub fn client_auth_mandatory(&self) -> bool {
+        self.client_auth_mandatory
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read_allowlist() {
+        const FINGERPRINT: [u8; 32] = [
+            0x01, 0x23, 0x45, 0x67, 0x89, 0xab, 0xcd, 0xef, 0x01, 0x23, 0x45, 0x67, 0x89, 0xab,
+            0xcd, 0xef, 0x01, 0x23, 0x45, 0x67, 0x89, 0xab, 0xcd, 0xef, 0x01, 0x23, 0x45, 0x67,
+            0x89, 0xab, 0xcd, 0xef,
+        ];
+
+        assert_eq!(
+            CertificateAllowlist::read(
+                "# comment\n\n0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef\n  01:23:45:67:89:AB:CD:EF:01:23:45:67:89:AB:CD:EF:01:23:45:67:89:AB:CD:EF:01:23:45:67:89:AB:CD:EF  \n"
+                    .as_bytes()
+            )
+            .unwrap(),
+            CertificateAllowlist::from_iter([FINGERPRINT])
+        );
+        assert!(CertificateAllowlist::read("0123".as_bytes()).is_err());
+        assert!(CertificateAllowlist::read(
+            "+123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef".as_bytes()
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn ticketer() {
+        let ticketer = Ticketer::new(Duration::from_secs(60)).unwrap();
+        let start = ticketer.keys.lock().unwrap().generated;
+        let at = |secs| {
+            let (current, previous) = ticketer.keys(start + Duration::from_secs(secs)).unwrap();
+            (current.encrypt(b"test").unwrap(), previous)
+        };
+        let decrypts = |secs, ticket: &[u8]| {
+            let (current, previous) = ticketer.keys(start + Duration::from_secs(secs)).unwrap();
+            current.decrypt(ticket).is_some()
+                || previous.is_some_and(|previous| previous.decrypt(ticket).is_some())
+        };
+
+        let (first, previous) = at(0);
+        assert!(previous.is_none());
+        assert!(decrypts(29, &first));
+        // Keys are rotated after half the lifetime and erased after the lifetime.
+        let (second, previous) = at(30);
+        assert!(previous.is_some());
+        assert!(decrypts(59, &first));
+        assert!(decrypts(59, &second));
+        assert!(!decrypts(60, &first));
+        assert!(decrypts(60, &second));
+        // All keys are erased if no ticket was processed for the lifetime.
+        assert!(!decrypts(150, &second));
+
+        assert!(Ticketer::new(Duration::from_secs(1)).is_err());
+        assert!(Ticketer::new(MAX_TLS_TICKET_LIFETIME + Duration::from_secs(1)).is_err());
+    }
+
+    #[test]
+    fn read_with_sessions() {
+        let read = |sessions| {
+            Config::read_with_sessions(
+                include_bytes!("../../../../testdata/server.crt").as_slice(),
+                include_bytes!("../../../../testdata/server.key").as_slice(),
+                include_bytes!("../../../../testdata/ca.crt").as_slice(),
+                sessions,
+            )
+        };
+
+        let conf = read(SessionConfig::default()).unwrap();
+        assert!(conf.session_storage.can_cache());
+        assert!(conf.ticketer.enabled());
+        assert_eq!(
+            conf.ticketer.lifetime() as u64,
+            DEFAULT_TLS_TICKET_LIFETIME.as_secs()
+        );
+
+        let conf = read(SessionConfig {
+            cache_size: 0,
+            ticket_lifetime: None,
+        })
+        .unwrap();
+        assert!(!conf.session_storage.can_cache());
+        assert!(!conf.ticketer.enabled());
+
+        assert!(read(SessionConfig {
+            cache_size: 0,
+            ticket_lifetime: Some(Duration::ZERO),
+        })
+        .is_err());
+    }
+
+    #[test]
+    fn chain_limit() {
+        let read = |pem: &[u8]| read_certificates(pem).unwrap().remove(0);
+        let client = read(include_bytes!("../../../../testdata/client.crt"));
+        let ca = read(include_bytes!("../../../../testdata/ca.crt"));
+        let mut roots = RootCertStore::empty();
+        roots.add(&ca).unwrap();
+
+        let limit = ChainLimit {
+            inner: AllowAnyAnonymousOrAuthenticatedClient::new(roots),
+            max_bytes: client.0.len() + 2 * ca.0.len(),
+        };
+        let verify = |n| limit.verify_client_cert(&client, &vec![ca.clone(); n], SystemTime::now());
+        assert!(verify(0).is_ok());
+        assert!(verify(2).is_ok());
+        assert!(matches!(verify(3), Err(Error::InvalidCertificateData(_))));
+
+        assert!(Config::read_with_options(
+            include_bytes!("../../../../testdata/server.crt").as_slice(),
+            include_bytes!("../../../../testdata/server.key").as_slice(),
+            include_bytes!("../../../../testdata/ca.crt").as_slice(),
+            Options {
+                max_client_cert_chain: 0,
+                ..Default::default()
+            },
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn sni() {
+        let identity = |certs: &[u8], key: &[u8]| Arc::new(read_identity(certs, key).unwrap());
+        let server = identity(
+            include_bytes!("../../../../testdata/server.crt"),
+            include_bytes!("../../../../testdata/server.key"),
+        );
+        let modules = identity(
+            include_bytes!("../../../../testdata/modules.crt"),
+            include_bytes!("../../../../testdata/modules.key"),
+        );
+        let resolver = SniResolver(vec![server.clone(), modules.clone()]);
+        let select = |name| resolver.select(name).unwrap();
+        assert!(Arc::ptr_eq(select(Some("localhost")), &server));
+        assert!(Arc::ptr_eq(select(Some("test.localhost")), &server));
+        assert!(Arc::ptr_eq(select(Some("modules.example.com")), &modules));
+        // Clients requesting other or no names are presented the first certificate.
+        assert!(Arc::ptr_eq(select(Some("example.com")), &server));
+        assert!(Arc::ptr_eq(select(None), &server));
+
+        assert!(read_identity(
+            include_bytes!("../../../../testdata/server.key").as_slice(),
+            include_bytes!("../../../../testdata/server.key").as_slice(),
+        )
+        .is_err());
+        assert!(Config::read_with_identities(
+            Vec::<(&[u8], &[u8])>::new(),
+            include_bytes!("../../../../testdata/ca.crt").as_slice(),
+            Options::default(),
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn alpn() {
+        let read = |alpn_protocols: &[&str]| {
+            Config::read_with_options(
+                include_bytes!("../../../../testdata/server.crt").as_slice(),
+                include_bytes!("../../../../testdata/server.key").as_slice(),
+                include_bytes!("../../../../testdata/ca.crt").as_slice(),
+                Options {
+                    alpn_protocols: alpn_protocols.iter().map(|p| p.to_string()).collect(),
+                    ..Default::default()
+                },
+            )
+        };
+
+        assert!(read(&[]).unwrap().alpn_protocols.is_empty());
+        // Protocols are advertised verbatim and in order, even if they are not served.
+        assert_eq!(
+            read(&["h2", "http/1.1", "http/1.0"])
+                .unwrap()
+                .alpn_protocols,
+            [b"h2".to_vec(), b"http/1.1".to_vec(), b"http/1.0".to_vec()]
+        );
+        assert_eq!(
+            read(&["http/1.0"]).unwrap().alpn_protocols,
+            [b"http/1.0".to_vec()]
+        );
+        assert!(read(&["h2"]).is_err());
+        assert!(read(&["http/1.1", "http/1.1"]).is_err());
+        assert!(read(&["http/1.1", ""]).is_err());
+        assert!(read(&["http/1.1", &"x".repeat(256)]).is_err());
     }
 }
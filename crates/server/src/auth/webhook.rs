@@ -0,0 +1,198 @@
+// SPDX-FileCopyrightText: 2022 Profian Inc. <opensource@profian.com>
+// SPDX-License-Identifier: AGPL-3.0-only
+
+use super::{ScopeLevel, TrustedCertificate};
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+use anyhow::Context;
+use async_std::task::spawn_blocking;
+use axum::http::{Extensions, StatusCode};
+use axum::response::{IntoResponse, Response};
+use openidconnect::url::Url;
+use serde::{Deserialize, Serialize};
+use tracing::{debug, error, info};
+
+/// Default duration for which decisions of an authorization webhook are cached.
+pub const DEFAULT_AUTHZ_CACHE_TTL: Duration = Duration::from_secs(10);
+
+/// Timeout of requests sent to the authorization webhook.
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Number of cached decisions, above which expired decisions are evicted.
+const MAX_CACHED_DECISIONS: usize = 4096;
+
+/// Authenticated subject of a request.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize)]
+#[serde(tag = "type", content = "id", rename_all = "kebab-case")]
+pub(crate) enum Subject {
+    /// OpenID Connect subject.
+    Oidc(String),
+    /// Hex-encoded SHA-256 fingerprint of a trusted client certificate.
+    Certificate(String),
+    /// ID of an access token.
+    Token(String),
+}
+
+impl Subject {
+    pub(crate) fn certificate(cert: &TrustedCertificate) -> Self {
+        Self::Certificate(cert.hex_fingerprint())
+    }
+}
+
+/// Access query sent to the authorization webhook.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize)]
+struct Query {
+    subject: Subject,
+    operation: &'static str,
+    resource: String,
+}
+
+/// Decision returned by the authorization webhook.
+#[derive(Clone, Copy, Debug, Deserialize)]
+struct Decision {
+    allow: bool,
+}
+
+/// External service consulted on each authorized request, which implements additional access
+/// policy.
+///
+/// Requests are only granted access if both the built-in checks and the webhook allow them.
+/// The webhook receives a `POST` request with a JSON body like
+/// `{"subject":{"type":"oidc","id":"..."},"operation":"read","resource":"/api/v0.1.0/..."}`
+/// and must respond with `{"allow":true}` or `{"allow":false}`.
+pub(crate) struct Webhook {
+    url: Url,
+    cache_ttl: Duration,
+    agent: ureq::Agent,
+    cache: Mutex<HashMap<Query, (bool, Instant)>>,
+}
+
+impl fmt::Debug for Webhook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Webhook")
+            .field("url", &self.url)
+            .field("cache_ttl", &self.cache_ttl)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Webhook {
+    /// Constructs a new [Webhook] sending queries to `url`, whose decisions are cached for
+    /// `cache_ttl`.
+    pub(crate) fn new(url: Url, cache_ttl: Duration) -> Self {
+        Self {
+            url,
+            cache_ttl,
+            agent: ureq::AgentBuilder::new().timeout(WEBHOOK_TIMEOUT).build(),
+            cache: Default::default(),
+        }
+    }
+
+    fn cached(&self, query: &Query) -> Option<bool> {
+        let cache = self.cache.lock().unwrap_or_else(PoisonError::into_inner);
+        cache
+            .get(query)
+            .filter(|(_, expires)| *expires > Instant::now())
+            .map(|(allow, _)| *allow)
+    }
+
+    fn cache(&self, query: Query, allow: bool) {
+        if self.cache_ttl.is_zero() {
+            return;
+        }
+        let now = Instant::now();
+        let mut cache = self.cache.lock().unwrap_or_else(PoisonError::into_inner);
+        if cache.len() >= MAX_CACHED_DECISIONS {
+            cache.retain(|_, (_, expires)| *expires > now);
+        }
+        _ = cache.insert(query, (allow, now + self.cache_ttl));
+    }
+
+    /// Evicts expired decisions from the cache and returns the number of evicted decisions.
+    pub(crate) fn evict(&self) -> usize {
+        let now = Instant::now();
+        let mut cache = self.cache.lock().unwrap_or_else(PoisonError::into_inner);
+        let cached = cache.len();
+        cache.retain(|_, (_, expires)| *expires > now);
+        cached - cache.len()
+    }
+
+    async fn query(&self, query: &Query) -> anyhow::Result<bool> {
+        if let Some(allow) = self.cached(query) {
+            return Ok(allow);
+        }
+        let req = self.agent.post(self.url.as_str());
+        let body = serde_json::to_value(query).context("failed to encode query")?;
+        let Decision { allow } = spawn_blocking(move || {
+            req.send_json(body)
+                .context("failed to send query")?
+                .into_json::<Decision>()
+                .context("failed to decode decision")
+        })
+        .await?;
+        self.cache(query.clone(), allow);
+        Ok(allow)
+    }
+
+    /// Asserts that the webhook allows `subject` to perform an operation of `level` on the
+    /// `resource` path.
+    ///
+    /// Fails closed with `503 Service Unavailable` if the webhook cannot be queried.
+    #[allow(clippy::result_large_err)]
+    pub(crate) async fn authorize(
+        &self,
+        subject: Subject,
+        level: ScopeLevel,
+        resource: &str,
+    ) -> Result<(), Response> {
+        let query = Query {
+            subject,
+            operation: match level {
+                ScopeLevel::Read => "read",
+                ScopeLevel::Write => "write",
+            },
+            resource: resource.into(),
+        };
+        match self.query(&query).await {
+            Ok(true) => {
+                debug!(target: "app::auth::webhook", ?query, "access allowed by webhook");
+                Ok(())
+            }
+            Ok(false) => {
+                info!(target: "app::auth::webhook", ?query, "access denied by webhook");
+                Err((
+                    StatusCode::FORBIDDEN,
+                    "Access denied by authorization policy",
+                )
+                    .into_response())
+            }
+            Err(e) => {
+                error!(target: "app::auth::webhook", ?query, "failed to query authorization webhook: {e:?}");
+                Err((
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    "Authorization service unavailable",
+                )
+                    .into_response())
+            }
+        }
+    }
+}
+
+/// Asserts that the [Webhook] contained in `extensions`, if any, allows `subject` to perform an
+/// operation of `level` on `resource`.
+#[allow(clippy::result_large_err)]
+pub(crate) async fn authorize(
+    extensions: &Extensions,
+    subject: Subject,
+    level: ScopeLevel,
+    resource: &str,
+) -> Result<(), Response> {
+    match extensions.get::<Arc<Webhook>>().cloned() {
+        Some(webhook) => webhook.authorize(subject, level, resource).await,
+        None => Ok(()),
+    }
+}
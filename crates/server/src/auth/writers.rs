@@ -0,0 +1,109 @@
+// SPDX-FileCopyrightText: 2022 Profian Inc. <opensource@profian.com>
+// SPDX-License-Identifier: AGPL-3.0-only
+
+use super::tls::parse_fingerprint;
+use super::TrustedCertificate;
+
+use std::collections::{HashMap, HashSet};
+use std::io::BufRead;
+
+use drawbridge_type::UserName;
+
+use anyhow::{ensure, Context};
+
+/// Mapping of SHA-256 fingerprints of client certificates to the namespaces, i.e. users,
+/// which they may publish tags to without an OpenID Connect token.
+///
+/// Certificates, which are not contained in the mapping, are only granted read access.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[repr(transparent)]
+pub struct CertificateWriters(HashMap<[u8; 32], HashSet<UserName>>);
+
+impl CertificateWriters {
+    /// Reads a mapping containing one hex-encoded SHA-256 certificate fingerprint per line,
+    /// followed by whitespace-separated names of the namespaces the certificate may write to,
+    /// e.g. `01:23:...:ef ci releases`.
+    ///
+    /// Bytes of a fingerprint may optionally be separated by `:`. Empty lines and lines
+    /// starting with `#` are ignored. Namespaces of fingerprints listed on multiple lines are
+    /// merged.
+    pub fn read(rd: impl BufRead) -> anyhow::Result<Self> {
+        let mut writers = Self::default();
+        for (i, line) in rd.lines().enumerate() {
+            let line = line.context("failed to read certificate writers")?;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut fields = line.split_whitespace();
+            let fingerprint = fields
+                .next()
+                .map(parse_fingerprint)
+                .expect("non-empty line has a field")
+                .with_context(|| format!("invalid fingerprint on line {}", i + 1))?;
+            let namespaces = fields
+                .map(str::parse)
+                .collect::<anyhow::Result<Vec<UserName>>>()
+                .with_context(|| format!("invalid namespace on line {}", i + 1))?;
+            ensure!(
+                !namespaces.is_empty(),
+                "missing namespaces on line {}",
+                i + 1
+            );
+            writers.0.entry(fingerprint).or_default().extend(namespaces);
+        }
+        Ok(writers)
+    }
+
+    /// Returns the namespaces `cert` may write to, if any.
+    pub fn namespaces(&self, cert: &TrustedCertificate) -> Option<&HashSet<UserName>> {
+        self.0.get(cert.fingerprint())
+    }
+}
+
+impl FromIterator<([u8; 32], HashSet<UserName>)> for CertificateWriters {
+    fn from_iter<T: IntoIterator<Item = ([u8; 32], HashSet<UserName>)>>(iter: T) -> Self {
+        Self(iter.into_iter().collect())
+    }
+}
+
+/// Namespaces the trusted client certificate of a request may write to, which are inserted
+/// into request extensions if the certificate is contained in [CertificateWriters].
+#[derive(Clone, Debug)]
+pub(crate) struct CertificateWriter {
+    pub(crate) cert: TrustedCertificate,
+    pub(crate) namespaces: HashSet<UserName>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read() {
+        const FINGERPRINT: [u8; 32] = [
+            0x01, 0x23, 0x45, 0x67, 0x89, 0xab, 0xcd, 0xef, 0x01, 0x23, 0x45, 0x67, 0x89, 0xab,
+            0xcd, 0xef, 0x01, 0x23, 0x45, 0x67, 0x89, 0xab, 0xcd, 0xef, 0x01, 0x23, 0x45, 0x67,
+            0x89, 0xab, 0xcd, 0xef,
+        ];
+        const HEX: &str = "0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef";
+
+        assert_eq!(
+            CertificateWriters::read(
+                format!(
+                    "# comment\n\n{HEX} ci release\n  {} ci\tother  \n",
+                    HEX.to_uppercase()
+                )
+                .as_bytes()
+            )
+            .unwrap(),
+            CertificateWriters::from_iter([(
+                FINGERPRINT,
+                HashSet::from_iter(["ci", "release", "other"].map(|name| name.parse().unwrap()))
+            )])
+        );
+        assert!(CertificateWriters::read(HEX.as_bytes()).is_err());
+        assert!(CertificateWriters::read(format!("{HEX} in/valid").as_bytes()).is_err());
+        assert!(CertificateWriters::read("0123 ci".as_bytes()).is_err());
+    }
+}
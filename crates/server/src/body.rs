@@ -0,0 +1,86 @@
+// SPDX-FileCopyrightText: 2022 Profian Inc. <opensource@profian.com>
+// SPDX-License-Identifier: AGPL-3.0-only
+
+use super::ServerTiming;
+
+use std::io;
+use std::num::NonZeroUsize;
+
+use axum::body::{boxed, BoxBody, Bytes, Full, StreamBody};
+use futures::stream::try_unfold;
+use futures::{AsyncRead, AsyncReadExt};
+
+/// Default size of the buffer used to read each response body from the store.
+pub const DEFAULT_RESPONSE_BUFFER_BYTES: usize = 64 * 1024;
+
+/// Size of the buffer used to read each response body from the store, which is inserted into
+/// request extensions.
+///
+/// Configured via [Builder::response_buffer_bytes](crate::Builder::response_buffer_bytes).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ResponseBuffer(pub(crate) NonZeroUsize);
+
+impl ResponseBuffer {
+    /// Returns a response body with contents of length `size` read from `rdr`.
+    ///
+    /// Contents fitting into the buffer are read at once, while larger contents are streamed
+    /// in chunks of at most the buffer size, such that memory used by a response does not
+    /// depend on the size of the object. Since the body of a streamed response is only read
+    /// once the response head is sent, the `body` phase is only recorded in `timing` for
+    /// contents read at once.
+    pub(crate) async fn read(
+        self,
+        timing: Option<&ServerTiming>,
+        size: u64,
+        mut rdr: impl 'static + Send + Unpin + AsyncRead,
+    ) -> io::Result<BoxBody> {
+        let cap = self.0.get();
+        if size <= cap as u64 {
+            let mut body = Vec::with_capacity(size as _);
+            _ = ServerTiming::measure(timing, "body", rdr.read_to_end(&mut body)).await?;
+            return Ok(boxed(Full::from(body)));
+        }
+        let chunks = try_unfold((rdr, vec![0; cap]), |(mut rdr, mut buf)| async move {
+            match rdr.read(&mut buf).await? {
+                0 => Ok::<_, io::Error>(None),
+                n => Ok(Some((Bytes::copy_from_slice(&buf[..n]), (rdr, buf)))),
+            }
+        });
+        Ok(boxed(StreamBody::new(chunks)))
+    }
+}
+
+impl Default for ResponseBuffer {
+    fn default() -> Self {
+        Self(NonZeroUsize::new(DEFAULT_RESPONSE_BUFFER_BYTES).expect("default is not zero"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use async_std::task::block_on;
+    use axum::body::HttpBody;
+
+    #[test]
+    fn read() {
+        block_on(async {
+            let buffer = ResponseBuffer(NonZeroUsize::new(4).unwrap());
+            let chunks = |content: &'static [u8]| async move {
+                let mut body = buffer
+                    .read(None, content.len() as _, content)
+                    .await
+                    .unwrap();
+                let mut chunks = vec![];
+                while let Some(chunk) = body.data().await {
+                    chunks.push(chunk.unwrap().to_vec());
+                }
+                chunks
+            };
+            assert_eq!(chunks(b"").await, Vec::<Vec<u8>>::new());
+            assert_eq!(chunks(b"test").await, [b"test"]);
+            assert_eq!(chunks(b"foobarbaz").await, [&b"foob"[..], b"arba", b"z"]);
+        })
+    }
+}
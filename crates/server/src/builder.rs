@@ -1,20 +1,45 @@
 // SPDX-FileCopyrightText: 2022 Profian Inc. <opensource@profian.com>
 // SPDX-License-Identifier: AGPL-3.0-only
 
-use super::{handle, App, Store, TlsConfig};
+use super::auth::{UrlSigner, Webhook, DEFAULT_AUTHZ_CACHE_TTL, DEFAULT_OIDC_CLOCK_SKEW};
+use super::tags::TagLimit;
+use super::{
+    cache_control, compression, content_type, deadline, expect, handle, hide_existence,
+    idempotency, inflight, ip_filter, maintenance::Maintenance, metrics, mirror, negative_cache,
+    paths, rate_limit, read_only, readiness, routes, slots, store_health, timing, App, Backend,
+    Breaker, Cached, CertificateAllowlist, CertificateWriters, Circuit, ClientInfo,
+    CompressionAlgorithm, Connections, Hsts, IpCidr, ManifestSchema, Metrics, MirrorConfig,
+    Precompression, ReadRepair, ResponseBuffer, RouteClass, Store, StoreUrl, TlsConfig, Uploads,
+    DEFAULT_CACHE_MAX_BYTES, DEFAULT_CONTENT_CACHE_CONTROL, DEFAULT_HANDSHAKE_TIMEOUT,
+    DEFAULT_IDEMPOTENCY_KEY_TTL, DEFAULT_LIMIT_WARNING_PERCENT, DEFAULT_ORPHAN_MAX_AGE,
+    DEFAULT_RESPONSE_BUFFER_BYTES, DEFAULT_SHUTDOWN_TIMEOUT, DEFAULT_STARTUP_SCAN_THREADS,
+    DEFAULT_STORE_CIRCUIT_COOLDOWN, DEFAULT_STORE_CIRCUIT_THRESHOLD, DEFAULT_TAG_CACHE_CONTROL,
+    DEFAULT_UPLOAD_SESSION_TTL, MAX_NEGATIVE_CACHE_TTL,
+};
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::num::{NonZeroU64, NonZeroUsize};
+use std::path::PathBuf;
+use std::sync::RwLock;
+use std::time::Duration;
+
+use drawbridge_type::UserName;
 
-use anyhow::{anyhow, Context};
-use async_std::fs::File;
-use async_std::path::Path;
+use anyhow::{anyhow, bail, Context};
 use async_std::sync::Arc;
+use async_std::task::spawn_blocking;
+use axum::body::Body;
 use axum::handler::Handler;
-use axum::routing::any;
+use axum::http::{HeaderValue, Method, Request};
+use axum::middleware::from_fn;
+use axum::routing::{any, get};
 use axum::{Extension, Router};
-use cap_async_std::fs_utf8::Dir;
 use futures::lock::Mutex;
-use futures::TryFutureExt;
 use futures_rustls::TlsAcceptor;
+use mime::Mime;
 use openidconnect::url::Url;
+use tower::steer::Steer;
 use tower_http::{
     trace::{
         DefaultOnBodyChunk, DefaultOnEos, DefaultOnFailure, DefaultOnRequest, DefaultOnResponse,
@@ -22,7 +47,7 @@ use tower_http::{
     },
     LatencyUnit,
 };
-use tracing::Level;
+use tracing::{debug, info, warn, Level};
 
 /// OpenID Connect client configuration.
 #[derive(Debug)]
@@ -31,15 +56,43 @@ pub struct OidcConfig {
     pub issuer: Url,
 }
 
+/// Class of failures of [Builder::build] and [App::watch_store], by which callers may decide
+/// how to react, e.g. retry on an unavailable store, but not on an invalid configuration.
+///
+/// The class is attached as context to the error and can be retrieved using
+/// [anyhow::Error::downcast_ref]. Failures without a class are caused by an invalid
+/// configuration.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum FailureClass {
+    /// The store could not be opened or prepared for serving or became unavailable.
+    Store,
+    /// The OpenID Connect provider could not be discovered.
+    Oidc,
+}
+
+impl fmt::Display for FailureClass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Store => write!(f, "store is unavailable"),
+            Self::Oidc => write!(f, "OIDC provider is unavailable"),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Default)]
 struct SpanMaker;
 
 impl<B> tower_http::trace::MakeSpan<B> for SpanMaker {
     fn make_span(&mut self, request: &axum::http::request::Request<B>) -> tracing::span::Span {
         let reqid = uuid::Uuid::new_v4();
+        let client = request
+            .extensions()
+            .get::<ClientInfo>()
+            .map(|info| info.addr);
         tracing::span!(
             Level::INFO,
             "request",
+            client = ?client,
             method = %request.method(),
             uri = %request.uri(),
             version = ?request.version(),
@@ -49,11 +102,86 @@ impl<B> tower_http::trace::MakeSpan<B> for SpanMaker {
     }
 }
 
+/// Default maximum request deadline clients may request.
+pub const DEFAULT_MAX_REQUEST_DEADLINE: Duration = Duration::from_secs(300);
+
+/// Default value of the `Server` header sent in responses.
+pub const DEFAULT_SERVER_HEADER: &str = concat!("drawbridge/", env!("CARGO_PKG_VERSION"));
+
+/// Minimum length of the secret used to sign URLs.
+pub const MIN_URL_SIGNING_SECRET_LEN: usize = 16;
+
+/// Clock skew tolerance, above which a warning is logged, since tokens remain usable for that
+/// long after they expired.
+const MAX_RECOMMENDED_OIDC_CLOCK_SKEW: Duration = Duration::from_secs(300);
+
 /// [App] builder.
 pub struct Builder<S> {
     store: S,
     tls: TlsConfig,
     oidc: OidcConfig,
+    max_request_deadline: Duration,
+    upload_timeout: Duration,
+    download_timeout: Duration,
+    metadata_timeout: Duration,
+    client_cert_allowlist: Option<CertificateAllowlist>,
+    client_cert_writers: Option<CertificateWriters>,
+    compression: Vec<CompressionAlgorithm>,
+    store_precompressed: Vec<CompressionAlgorithm>,
+    max_tags_per_repo: usize,
+    manifest_schema: Option<ManifestSchema>,
+    allowed_content_types: Vec<Mime>,
+    allowed_methods: Vec<Method>,
+    disabled_routes: Vec<RouteClass>,
+    read_only: bool,
+    hide_existence: bool,
+    require_writable_store: bool,
+    server_timing: bool,
+    read_slots: usize,
+    write_slots: usize,
+    max_inflight_bytes: u64,
+    limit_warning_percent: u8,
+    max_download_bps: u64,
+    response_buffer_bytes: usize,
+    public_url: Option<Url>,
+    allow_insecure_public_url: bool,
+    trusted_proxies: Vec<IpCidr>,
+    ip_filter: ip_filter::IpFilter,
+    log_exclude_paths: Vec<String>,
+    url_signing_secret: Option<Vec<u8>>,
+    authz_webhook: Option<Url>,
+    authz_cache_ttl: Duration,
+    upload_session_ttl: Duration,
+    max_upload_sessions_per_repo: usize,
+    max_upload_session_size: u64,
+    idempotency_key_ttl: Option<Duration>,
+    negative_cache_ttl: Duration,
+    mirror: Option<MirrorConfig>,
+    server_header: Option<String>,
+    hsts: Option<Hsts>,
+    content_cache_control: Option<String>,
+    tag_cache_control: Option<String>,
+    strict_paths: bool,
+    metrics_endpoint: bool,
+    keep_alive_timeout: Duration,
+    max_requests_per_connection: u64,
+    handshake_timeout: Duration,
+    max_connections: usize,
+    shutdown_timeout: Duration,
+    connection_log_sample_rate: f64,
+    oidc_strict_startup: bool,
+    oidc_clock_skew: Duration,
+    allow_store_migration: bool,
+    orphan_max_age: Option<Duration>,
+    startup_scan_threads: usize,
+    maintenance_gc: bool,
+    cache_dir: Option<PathBuf>,
+    cache_max_bytes: u64,
+    store_circuit_threshold: usize,
+    store_circuit_cooldown: Duration,
+    read_repair: Option<StoreUrl>,
+    namespace_rate_limit: u32,
+    namespace_rate_limit_overrides: HashMap<UserName, u32>,
 }
 
 impl<S: std::fmt::Debug> std::fmt::Debug for Builder<S> {
@@ -61,62 +189,1492 @@ impl<S: std::fmt::Debug> std::fmt::Debug for Builder<S> {
         f.debug_struct("Builder")
             .field("store", &self.store)
             .field("oidc", &self.oidc)
+            .field("max_request_deadline", &self.max_request_deadline)
+            .field("upload_timeout", &self.upload_timeout)
+            .field("download_timeout", &self.download_timeout)
+            .field("metadata_timeout", &self.metadata_timeout)
+            .field("client_cert_allowlist", &self.client_cert_allowlist)
+            .field("client_cert_writers", &self.client_cert_writers)
+            .field("compression", &self.compression)
+            .field("store_precompressed", &self.store_precompressed)
+            .field("max_tags_per_repo", &self.max_tags_per_repo)
+            .field("manifest_schema", &self.manifest_schema)
+            .field("allowed_content_types", &self.allowed_content_types)
+            .field("allowed_methods", &self.allowed_methods)
+            .field("disabled_routes", &self.disabled_routes)
+            .field("read_only", &self.read_only)
+            .field("hide_existence", &self.hide_existence)
+            .field("require_writable_store", &self.require_writable_store)
+            .field("server_timing", &self.server_timing)
+            .field("read_slots", &self.read_slots)
+            .field("write_slots", &self.write_slots)
+            .field("max_inflight_bytes", &self.max_inflight_bytes)
+            .field("limit_warning_percent", &self.limit_warning_percent)
+            .field("max_download_bps", &self.max_download_bps)
+            .field("response_buffer_bytes", &self.response_buffer_bytes)
+            .field("public_url", &self.public_url)
+            .field("allow_insecure_public_url", &self.allow_insecure_public_url)
+            .field("trusted_proxies", &self.trusted_proxies)
+            .field("ip_filter", &self.ip_filter)
+            .field("log_exclude_paths", &self.log_exclude_paths)
+            .field(
+                "url_signing_secret",
+                &self.url_signing_secret.as_ref().map(|_| "<redacted>"),
+            )
+            .field("authz_webhook", &self.authz_webhook)
+            .field("authz_cache_ttl", &self.authz_cache_ttl)
+            .field("upload_session_ttl", &self.upload_session_ttl)
+            .field(
+                "max_upload_sessions_per_repo",
+                &self.max_upload_sessions_per_repo,
+            )
+            .field("max_upload_session_size", &self.max_upload_session_size)
+            .field("idempotency_key_ttl", &self.idempotency_key_ttl)
+            .field("negative_cache_ttl", &self.negative_cache_ttl)
+            .field("mirror", &self.mirror)
+            .field("server_header", &self.server_header)
+            .field("hsts", &self.hsts)
+            .field("content_cache_control", &self.content_cache_control)
+            .field("tag_cache_control", &self.tag_cache_control)
+            .field("strict_paths", &self.strict_paths)
+            .field("metrics_endpoint", &self.metrics_endpoint)
+            .field("keep_alive_timeout", &self.keep_alive_timeout)
+            .field(
+                "max_requests_per_connection",
+                &self.max_requests_per_connection,
+            )
+            .field("handshake_timeout", &self.handshake_timeout)
+            .field("max_connections", &self.max_connections)
+            .field("shutdown_timeout", &self.shutdown_timeout)
+            .field(
+                "connection_log_sample_rate",
+                &self.connection_log_sample_rate,
+            )
+            .field("oidc_strict_startup", &self.oidc_strict_startup)
+            .field("oidc_clock_skew", &self.oidc_clock_skew)
+            .field("allow_store_migration", &self.allow_store_migration)
+            .field("orphan_max_age", &self.orphan_max_age)
+            .field("startup_scan_threads", &self.startup_scan_threads)
+            .field("maintenance_gc", &self.maintenance_gc)
+            .field("cache_dir", &self.cache_dir)
+            .field("cache_max_bytes", &self.cache_max_bytes)
+            .field("store_circuit_threshold", &self.store_circuit_threshold)
+            .field("store_circuit_cooldown", &self.store_circuit_cooldown)
+            .field("read_repair", &self.read_repair)
+            .field("namespace_rate_limit", &self.namespace_rate_limit)
+            .field(
+                "namespace_rate_limit_overrides",
+                &self.namespace_rate_limit_overrides,
+            )
             .finish()
     }
 }
 
-impl<S: AsRef<Path>> Builder<S> {
+impl<S: Into<StoreUrl>> Builder<S> {
     /// Constructs a new [Builder].
     pub fn new(store: S, tls: TlsConfig, oidc: OidcConfig) -> Self {
-        Self { store, tls, oidc }
+        Self {
+            store,
+            tls,
+            oidc,
+            max_request_deadline: DEFAULT_MAX_REQUEST_DEADLINE,
+            upload_timeout: Duration::ZERO,
+            download_timeout: Duration::ZERO,
+            metadata_timeout: Duration::ZERO,
+            client_cert_allowlist: None,
+            client_cert_writers: None,
+            compression: vec![],
+            store_precompressed: vec![],
+            max_tags_per_repo: 0,
+            manifest_schema: None,
+            allowed_content_types: vec![],
+            allowed_methods: vec![],
+            disabled_routes: vec![],
+            read_only: false,
+            hide_existence: false,
+            require_writable_store: false,
+            server_timing: false,
+            read_slots: 0,
+            write_slots: 0,
+            max_inflight_bytes: 0,
+            limit_warning_percent: DEFAULT_LIMIT_WARNING_PERCENT,
+            max_download_bps: 0,
+            response_buffer_bytes: DEFAULT_RESPONSE_BUFFER_BYTES,
+            public_url: None,
+            allow_insecure_public_url: false,
+            trusted_proxies: vec![],
+            ip_filter: Default::default(),
+            log_exclude_paths: vec![],
+            url_signing_secret: None,
+            authz_webhook: None,
+            authz_cache_ttl: DEFAULT_AUTHZ_CACHE_TTL,
+            upload_session_ttl: DEFAULT_UPLOAD_SESSION_TTL,
+            max_upload_sessions_per_repo: 0,
+            max_upload_session_size: 0,
+            idempotency_key_ttl: Some(DEFAULT_IDEMPOTENCY_KEY_TTL),
+            negative_cache_ttl: Duration::ZERO,
+            mirror: None,
+            server_header: Some(DEFAULT_SERVER_HEADER.into()),
+            hsts: None,
+            content_cache_control: Some(DEFAULT_CONTENT_CACHE_CONTROL.into()),
+            tag_cache_control: Some(DEFAULT_TAG_CACHE_CONTROL.into()),
+            strict_paths: false,
+            metrics_endpoint: false,
+            keep_alive_timeout: Duration::ZERO,
+            max_requests_per_connection: 0,
+            handshake_timeout: DEFAULT_HANDSHAKE_TIMEOUT,
+            max_connections: 0,
+            shutdown_timeout: DEFAULT_SHUTDOWN_TIMEOUT,
+            connection_log_sample_rate: 1.0,
+            oidc_strict_startup: false,
+            oidc_clock_skew: DEFAULT_OIDC_CLOCK_SKEW,
+            allow_store_migration: false,
+            orphan_max_age: Some(DEFAULT_ORPHAN_MAX_AGE),
+            startup_scan_threads: DEFAULT_STARTUP_SCAN_THREADS,
+            maintenance_gc: false,
+            cache_dir: None,
+            cache_max_bytes: DEFAULT_CACHE_MAX_BYTES,
+            store_circuit_threshold: DEFAULT_STORE_CIRCUIT_THRESHOLD,
+            store_circuit_cooldown: DEFAULT_STORE_CIRCUIT_COOLDOWN,
+            read_repair: None,
+            namespace_rate_limit: 0,
+            namespace_rate_limit_overrides: HashMap::new(),
+        }
+    }
+
+    /// Sets the maximum deadline clients may request for a single request using the
+    /// `grpc-timeout` header. Longer deadlines requested by clients are capped at this value.
+    pub fn max_request_deadline(self, max_request_deadline: Duration) -> Self {
+        Self {
+            max_request_deadline,
+            ..self
+        }
+    }
+
+    /// Sets the timeout of requests uploading file contents, i.e. `PUT` requests to tree nodes
+    /// and `PATCH` and `PUT` requests to upload sessions. Requests are aborted with
+    /// `408 Request Timeout` once the timeout is exceeded, unless the client requested a
+    /// shorter deadline. Disabled by default.
+    pub fn upload_timeout(self, upload_timeout: Duration) -> Self {
+        Self {
+            upload_timeout,
+            ..self
+        }
+    }
+
+    /// Sets the timeout of requests downloading file contents, i.e. `GET` requests to tree
+    /// nodes, which includes sending the response body. Disabled by default.
+    pub fn download_timeout(self, download_timeout: Duration) -> Self {
+        Self {
+            download_timeout,
+            ..self
+        }
+    }
+
+    /// Sets the timeout of all requests neither uploading nor downloading file contents.
+    /// Disabled by default.
+    pub fn metadata_timeout(self, metadata_timeout: Duration) -> Self {
+        Self {
+            metadata_timeout,
+            ..self
+        }
+    }
+
+    /// Restricts access granted to client certificates to the ones contained in the allowlist.
+    ///
+    /// Client certificates signed by a trusted CA, which are not contained in the allowlist,
+    /// are denied access.
+    pub fn client_cert_allowlist(self, client_cert_allowlist: CertificateAllowlist) -> Self {
+        Self {
+            client_cert_allowlist: Some(client_cert_allowlist),
+            ..self
+        }
+    }
+
+    /// Grants write access to tags of namespaces to the client certificates mapped to them,
+    /// such that publishers unable to obtain OpenID Connect tokens may authenticate using
+    /// certificates.
+    ///
+    /// Client certificates, which are not contained in the mapping, are only granted read
+    /// access.
+    pub fn client_cert_writers(self, client_cert_writers: CertificateWriters) -> Self {
+        Self {
+            client_cert_writers: Some(client_cert_writers),
+            ..self
+        }
+    }
+
+    /// Enables response compression using `algorithms`, which are negotiated with clients
+    /// via the `Accept-Encoding` request header.
+    pub fn compression(self, algorithms: impl IntoIterator<Item = CompressionAlgorithm>) -> Self {
+        Self {
+            compression: algorithms.into_iter().collect(),
+            ..self
+        }
+    }
+
+    /// Stores variants of uploaded file contents pre-compressed using `algorithms`, which must
+    /// all be enabled by [Builder::compression], alongside the contents.
+    ///
+    /// Clients accepting one of `algorithms` are served the stored variant instead of contents
+    /// compressed on every request, which trades storage for CPU time and latency. Variants
+    /// are verified against their digest before being served and removed if they fail
+    /// verification, in which case contents are compressed on the fly again. Small contents
+    /// and images are never pre-compressed.
+    pub fn store_precompressed(
+        self,
+        algorithms: impl IntoIterator<Item = CompressionAlgorithm>,
+    ) -> Self {
+        Self {
+            store_precompressed: algorithms.into_iter().collect(),
+            ..self
+        }
+    }
+
+    /// Sets the maximum number of tags per repository. `0` means unlimited, which is the default.
+    ///
+    /// Since tags are immutable, uploads of an already existing tag do not count against the limit.
+    pub fn max_tags_per_repo(self, max_tags_per_repo: usize) -> Self {
+        Self {
+            max_tags_per_repo,
+            ..self
+        }
+    }
+
+    /// Enables validation of uploaded tag manifests against `schema`, which is disabled by
+    /// default. Invalid manifests are rejected with `400 Bad Request`.
+    pub fn manifest_schema(self, schema: ManifestSchema) -> Self {
+        Self {
+            manifest_schema: Some(schema),
+            ..self
+        }
+    }
+
+    /// Restricts the media types, which file nodes may be uploaded as, to `types`, which may
+    /// include ranges of types like `text/*`. All types are allowed by default.
+    ///
+    /// Uploads declaring a disallowed type or with contents not matching the declared type,
+    /// e.g. `application/wasm` not starting with the WebAssembly magic number, are rejected with
+    /// `415 Unsupported Media Type`. Directories may always be uploaded.
+    pub fn allowed_content_types(self, types: impl IntoIterator<Item = Mime>) -> Self {
+        Self {
+            allowed_content_types: types.into_iter().collect(),
+            ..self
+        }
+    }
+
+    /// Restricts the methods, which requests may use, to `methods`. All methods are allowed by
+    /// default.
+    ///
+    /// Requests using any other method are rejected with `405 Method Not Allowed` before they
+    /// are routed, e.g. to disable deleting upload sessions by omitting `DELETE`.
+    pub fn allowed_methods(self, methods: impl IntoIterator<Item = Method>) -> Self {
+        Self {
+            allowed_methods: methods.into_iter().collect(),
+            ..self
+        }
+    }
+
+    /// Disables all routes of the classes `routes`, requests to which are rejected with
+    /// `404 Not Found` before they are routed, even if they are authorized. No routes are
+    /// disabled by default.
+    pub fn disabled_routes(self, routes: impl IntoIterator<Item = RouteClass>) -> Self {
+        Self {
+            disabled_routes: routes.into_iter().collect(),
+            ..self
+        }
+    }
+
+    /// Sets whether the server rejects all requests, which could modify the store.
+    ///
+    /// Read-only mode is enabled automatically if the store resides on a read-only filesystem,
+    /// unless [Builder::require_writable_store] is set.
+    pub fn read_only(self, read_only: bool) -> Self {
+        Self { read_only, ..self }
+    }
+
+    /// Sets whether responses to unauthorized requests are indistinguishable from responses to
+    /// requests for nonexistent resources, i.e. `404 Not Found`, such that clients cannot
+    /// enumerate users and repositories. Disabled by default.
+    pub fn hide_existence(self, hide_existence: bool) -> Self {
+        Self {
+            hide_existence,
+            ..self
+        }
+    }
+
+    /// Sets whether [Builder::build] fails if the store resides on a read-only filesystem,
+    /// instead of enabling read-only mode.
+    pub fn require_writable_store(self, require_writable_store: bool) -> Self {
+        Self {
+            require_writable_store,
+            ..self
+        }
+    }
+
+    /// Sets whether responses carry a `Server-Timing` header breaking down the time spent on
+    /// authentication, store lookups and body transfers, which is disabled by default.
+    pub fn server_timing(self, server_timing: bool) -> Self {
+        Self {
+            server_timing,
+            ..self
+        }
+    }
+
+    /// Sets the maximum number of reading requests, i.e. `GET`, `HEAD` and `OPTIONS`, handled
+    /// concurrently. `0` means unlimited, which is the default.
+    ///
+    /// Reading and writing requests have separate budgets, such that either kind is guaranteed
+    /// to make progress regardless of the load caused by the other one.
+    /// Requests exceeding the budget wait for a slot to become available.
+    pub fn read_slots(self, read_slots: usize) -> Self {
+        Self { read_slots, ..self }
+    }
+
+    /// Sets the maximum number of writing requests, i.e. all requests other than reading ones,
+    /// handled concurrently. `0` means unlimited, which is the default.
+    ///
+    /// See [Builder::read_slots].
+    pub fn write_slots(self, write_slots: usize) -> Self {
+        Self {
+            write_slots,
+            ..self
+        }
+    }
+
+    /// Sets the maximum number of bytes, which may be transferred in request and response
+    /// bodies across all connections at once. `0` means unlimited, which is the default.
+    ///
+    /// Bodies are accounted for by their declared length until they are completely
+    /// transferred. Requests, whose request body or, for requests not modifying the store,
+    /// response body would exceed the budget, are rejected with `503 Service Unavailable`
+    /// and a `Retry-After` header. The bytes in flight are exposed by
+    /// [Metrics::inflight_bytes].
+    pub fn max_inflight_bytes(self, max_inflight_bytes: u64) -> Self {
+        Self {
+            max_inflight_bytes,
+            ..self
+        }
+    }
+
+    /// Sets the percentage of [Builder::read_slots], [Builder::write_slots] and
+    /// [Builder::max_inflight_bytes], at and above which usage is considered near the limit,
+    /// which defaults to [DEFAULT_LIMIT_WARNING_PERCENT]. `0` disables the warnings.
+    ///
+    /// Requests admitted near a limit are counted by [Metrics::near_limit] and a warning is
+    /// logged at most once a minute per limit, such that operators can scale before requests
+    /// are delayed or rejected.
+    pub fn limit_warning_percent(self, limit_warning_percent: u8) -> Self {
+        Self {
+            limit_warning_percent,
+            ..self
+        }
+    }
+
+    /// Sets the maximum number of bytes per second sent on each connection. `0` means
+    /// unlimited, which is the default.
+    ///
+    /// Connections may send up to one second worth of bytes in a burst.
+    pub fn max_download_bps(self, max_download_bps: u64) -> Self {
+        Self {
+            max_download_bps,
+            ..self
+        }
+    }
+
+    /// Sets the size of the buffer used to read each response body from the store, which
+    /// defaults to [DEFAULT_RESPONSE_BUFFER_BYTES].
+    ///
+    /// Objects larger than the buffer are streamed in chunks of at most the buffer size, such
+    /// that memory used by responses is bounded by the number of concurrent responses times the
+    /// buffer size rather than by object sizes.
+    pub fn response_buffer_bytes(self, response_buffer_bytes: usize) -> Self {
+        Self {
+            response_buffer_bytes,
+            ..self
+        }
+    }
+
+    /// Sets the externally visible base URL of the server, which is used to construct absolute
+    /// URLs instead of inferring them from requests, e.g. when running behind a reverse proxy.
+    ///
+    /// The URL must use the `https` scheme, unless [Builder::allow_insecure_public_url] is set.
+    pub fn public_url(self, public_url: Url) -> Self {
+        Self {
+            public_url: Some(public_url),
+            ..self
+        }
+    }
+
+    /// Sets whether [Builder::public_url] may use the `http` scheme.
+    pub fn allow_insecure_public_url(self, allow_insecure_public_url: bool) -> Self {
+        Self {
+            allow_insecure_public_url,
+            ..self
+        }
+    }
+
+    /// Sets the address ranges of trusted reverse proxies, whose `X-Forwarded-For`,
+    /// `X-Forwarded-Proto` and `X-Forwarded-Host` headers determine the effective [ClientInfo]
+    /// of requests. These headers are removed from requests received from other peers.
+    pub fn trusted_proxies(self, trusted_proxies: impl IntoIterator<Item = IpCidr>) -> Self {
+        Self {
+            trusted_proxies: trusted_proxies.into_iter().collect(),
+            ..self
+        }
+    }
+
+    /// Sets the address ranges, which are allowed access. If set, connections and requests
+    /// from the effective [ClientInfo] address outside of these ranges are rejected.
+    ///
+    /// Connections from other addresses are dropped before the TLS handshake, unless they are
+    /// received from [Builder::trusted_proxies], whose requests are rejected with
+    /// `403 Forbidden` instead.
+    pub fn allow_cidrs(self, cidrs: impl IntoIterator<Item = IpCidr>) -> Self {
+        let mut ip_filter = self.ip_filter;
+        ip_filter.all.allow = cidrs.into_iter().collect();
+        Self { ip_filter, ..self }
+    }
+
+    /// Sets the address ranges, which are denied access, taking precedence over
+    /// [Builder::allow_cidrs].
+    pub fn deny_cidrs(self, cidrs: impl IntoIterator<Item = IpCidr>) -> Self {
+        let mut ip_filter = self.ip_filter;
+        ip_filter.all.deny = cidrs.into_iter().collect();
+        Self { ip_filter, ..self }
+    }
+
+    /// Like [Builder::allow_cidrs], but only applies to requests, which could modify the
+    /// store, i.e. ones not using `GET`, `HEAD` or `OPTIONS`.
+    pub fn write_allow_cidrs(self, cidrs: impl IntoIterator<Item = IpCidr>) -> Self {
+        let mut ip_filter = self.ip_filter;
+        ip_filter.write.allow = cidrs.into_iter().collect();
+        Self { ip_filter, ..self }
+    }
+
+    /// Like [Builder::deny_cidrs], but only applies to requests, which could modify the
+    /// store, i.e. ones not using `GET`, `HEAD` or `OPTIONS`.
+    pub fn write_deny_cidrs(self, cidrs: impl IntoIterator<Item = IpCidr>) -> Self {
+        let mut ip_filter = self.ip_filter;
+        ip_filter.write.deny = cidrs.into_iter().collect();
+        Self { ip_filter, ..self }
+    }
+
+    /// Sets the request paths, e.g. `/health`, which are excluded from access logging, such
+    /// that frequent probes do not flood the log. Paths must match exactly.
+    pub fn log_exclude_paths(
+        self,
+        log_exclude_paths: impl IntoIterator<Item = impl Into<String>>,
+    ) -> Self {
+        Self {
+            log_exclude_paths: log_exclude_paths.into_iter().map(Into::into).collect(),
+            ..self
+        }
+    }
+
+    /// Enables signed URLs, which grant time-limited anonymous read access to a single tag or
+    /// tree node, using `secret` as the HMAC-SHA256 key. Disabled by default.
+    ///
+    /// Authenticated users obtain a signed URL for an object they may read by sending a `POST`
+    /// request to it. Requests carrying an expired or tampered signature are rejected with
+    /// `403 Forbidden`.
+    pub fn url_signing_secret(self, secret: impl Into<Vec<u8>>) -> Self {
+        Self {
+            url_signing_secret: Some(secret.into()),
+            ..self
+        }
+    }
+
+    /// Consults the authorization webhook at `url` on each request authenticated by an OpenID
+    /// Connect token or a trusted client certificate, after the built-in checks passed.
+    ///
+    /// The webhook receives a `POST` request with a JSON body like
+    /// `{"subject":{"type":"oidc","id":"..."},"operation":"read","resource":"/api/v0.1.0/..."}`,
+    /// where the subject type is either `oidc` or `certificate` with the hex-encoded SHA-256
+    /// fingerprint of the certificate as the ID, and must respond with `{"allow":true}` or
+    /// `{"allow":false}`. Denied requests are rejected with `403 Forbidden`, while requests,
+    /// for which the webhook could not be queried, are rejected with `503 Service Unavailable`.
+    pub fn authz_webhook(self, url: Url) -> Self {
+        Self {
+            authz_webhook: Some(url),
+            ..self
+        }
+    }
+
+    /// Sets the duration for which decisions of [Builder::authz_webhook] are cached, which
+    /// defaults to [DEFAULT_AUTHZ_CACHE_TTL]. A zero duration disables caching.
+    pub fn authz_cache_ttl(self, authz_cache_ttl: Duration) -> Self {
+        Self {
+            authz_cache_ttl,
+            ..self
+        }
+    }
+
+    /// Sets the duration after which resumable upload sessions, which are not used, expire and
+    /// their data is removed, which defaults to [DEFAULT_UPLOAD_SESSION_TTL].
+    ///
+    /// Upload sessions are initiated by sending a `POST` request to `<repository>/_upload`,
+    /// which responds with the session URL. Contents are appended to a session in chunks using
+    /// `PATCH` requests and stored as a tree node once a `PUT` request naming the tag, path and
+    /// metadata of the node is sent to the session. Data of sessions is discarded on restart.
+    pub fn upload_session_ttl(self, upload_session_ttl: Duration) -> Self {
+        Self {
+            upload_session_ttl,
+            ..self
+        }
+    }
+
+    /// Sets the maximum number of open upload sessions per repository. `0` means unlimited,
+    /// which is the default.
+    ///
+    /// Requests initiating further sessions are rejected with `409 Conflict` until open sessions
+    /// are completed, cancelled or expire.
+    pub fn max_upload_sessions_per_repo(self, max_upload_sessions_per_repo: usize) -> Self {
+        Self {
+            max_upload_sessions_per_repo,
+            ..self
+        }
+    }
+
+    /// Sets the maximum number of bytes an upload session may receive. `0` means unlimited,
+    /// which is the default.
+    ///
+    /// Chunks, which would exceed the size, are discarded and rejected with
+    /// `413 Payload Too Large`.
+    pub fn max_upload_session_size(self, max_upload_session_size: u64) -> Self {
+        Self {
+            max_upload_session_size,
+            ..self
+        }
+    }
+
+    /// Sets the duration, for which the response to a mutating request carrying an
+    /// `Idempotency-Key` header is replayed to retries sent by the same subject with the same
+    /// key, which defaults to [DEFAULT_IDEMPOTENCY_KEY_TTL]. `None` disables the replay.
+    ///
+    /// Responses are recorded in the store, such that they are replayed across restarts, and
+    /// replayed responses carry an `Idempotent-Replayed: true` header. Keys may not be reused
+    /// for different requests within the duration.
+    pub fn idempotency_key_ttl(self, idempotency_key_ttl: Option<Duration>) -> Self {
+        Self {
+            idempotency_key_ttl,
+            ..self
+        }
+    }
+
+    /// Sets the duration for which `404 Not Found` responses to `GET` and `HEAD` requests are
+    /// cached per client credentials, which must not exceed [MAX_NEGATIVE_CACHE_TTL]. Disabled
+    /// by default.
+    ///
+    /// Cached responses of a namespace are invalidated once any request modifying it is
+    /// handled, such that clients never see a stale miss after a successful publish.
+    pub fn negative_cache_ttl(self, negative_cache_ttl: Duration) -> Self {
+        Self {
+            negative_cache_ttl,
+            ..self
+        }
+    }
+
+    /// Mirrors the Drawbridge server configured by `mirror`, from which tags requested by
+    /// clients, but missing in the store, are fetched along with their trees. Disabled by
+    /// default.
+    ///
+    /// Fetched trees are verified against the digests listed by their tag entry. Stored tags
+    /// are revalidated against the upstream once the TTL of `mirror` elapsed, such that tags
+    /// replaced or removed upstream are replaced or removed in the store as well. Requests,
+    /// which could modify the store, are rejected with `405 Method Not Allowed` instead of
+    /// letting the store diverge from the upstream, but the store must still be writable.
+    pub fn mirror(self, mirror: MirrorConfig) -> Self {
+        Self {
+            mirror: Some(mirror),
+            ..self
+        }
+    }
+
+    /// Sets the value of the `Server` header sent in all responses, including error responses,
+    /// which defaults to [DEFAULT_SERVER_HEADER]. `None` suppresses the header.
+    pub fn server_header(self, server_header: Option<String>) -> Self {
+        Self {
+            server_header,
+            ..self
+        }
+    }
+
+    /// Sets the HSTS policy sent in the `Strict-Transport-Security` header of all responses,
+    /// including error responses, instructing clients to never connect using plaintext HTTP.
+    /// Disabled by default.
+    pub fn hsts(self, hsts: Option<Hsts>) -> Self {
+        Self { hsts, ..self }
+    }
+
+    /// Sets the value of the `Cache-Control` header sent in successful responses containing tree
+    /// nodes, which defaults to [DEFAULT_CONTENT_CACHE_CONTROL]. `None` suppresses the header.
+    ///
+    /// Tree nodes cannot change once stored, such that clients and CDNs may cache them
+    /// indefinitely. Note that `public` allows shared caches to store responses to
+    /// authenticated requests, so deployments serving private repositories through shared
+    /// caches should use `private` instead.
+    pub fn content_cache_control(self, content_cache_control: Option<String>) -> Self {
+        Self {
+            content_cache_control,
+            ..self
+        }
+    }
+
+    /// Sets the value of the `Cache-Control` header sent in successful responses containing
+    /// tags or tag listings, which defaults to [DEFAULT_TAG_CACHE_CONTROL]. `None` suppresses
+    /// the header.
+    pub fn tag_cache_control(self, tag_cache_control: Option<String>) -> Self {
+        Self {
+            tag_cache_control,
+            ..self
+        }
+    }
+
+    /// Sets whether requests to non-canonical paths, i.e. ones containing duplicate or trailing
+    /// slashes, are redirected to their canonical form with `308 Permanent Redirect`. By default,
+    /// such requests are handled as if they were sent to the canonical path.
+    pub fn strict_paths(self, strict_paths: bool) -> Self {
+        Self {
+            strict_paths,
+            ..self
+        }
+    }
+
+    /// Sets whether the server [Metrics] are served unauthenticated at `/metrics`, in the
+    /// OpenMetrics text format if requested by the `Accept` header of requests and in the
+    /// classic Prometheus text format otherwise.
+    pub fn metrics_endpoint(self, metrics_endpoint: bool) -> Self {
+        Self {
+            metrics_endpoint,
+            ..self
+        }
+    }
+
+    /// Sets the duration after which connections, on which no bytes were transferred and no
+    /// requests were in flight, are closed. Disabled by default, i.e. idle connections are kept
+    /// open until the client closes them.
+    pub fn keep_alive_timeout(self, keep_alive_timeout: Duration) -> Self {
+        Self {
+            keep_alive_timeout,
+            ..self
+        }
+    }
+
+    /// Sets the maximum number of requests served on a single connection, such that clients
+    /// reconnect and load can be rebalanced. The response to the last request carries a
+    /// `Connection: close` header and the connection is closed once it is sent. Unlimited by
+    /// default.
+    pub fn max_requests_per_connection(self, max_requests_per_connection: u64) -> Self {
+        Self {
+            max_requests_per_connection,
+            ..self
+        }
+    }
+
+    /// Sets the duration a TLS handshake may take, after which the connection is dropped, such
+    /// that clients cannot hold on to connections without ever completing one. `0` disables
+    /// the timeout. Defaults to [DEFAULT_HANDSHAKE_TIMEOUT].
+    pub fn handshake_timeout(self, handshake_timeout: Duration) -> Self {
+        Self {
+            handshake_timeout,
+            ..self
+        }
+    }
+
+    /// Sets the maximum number of connections served concurrently by
+    /// [App::serve_connections] and [App::serve_redirects], where `0` means unlimited.
+    /// Further connections are only accepted once established ones are closed. Unlimited by
+    /// default.
+    pub fn max_connections(self, max_connections: usize) -> Self {
+        Self {
+            max_connections,
+            ..self
+        }
+    }
+
+    /// Sets the duration connections are drained for after [App::shutdown], after which the
+    /// ones still active are dropped. Defaults to [DEFAULT_SHUTDOWN_TIMEOUT].
+    pub fn shutdown_timeout(self, shutdown_timeout: Duration) -> Self {
+        Self {
+            shutdown_timeout,
+            ..self
+        }
+    }
+
+    /// Sets the fraction of connections, between 0 and 1, which are logged when accepted by
+    /// [App::serve_connections] and [App::serve_redirects]. Failures to handle connections
+    /// are logged regardless. Defaults to 1, i.e. all connections are logged.
+    pub fn connection_log_sample_rate(self, connection_log_sample_rate: f64) -> Self {
+        Self {
+            connection_log_sample_rate,
+            ..self
+        }
+    }
+
+    /// Validates the OpenID Connect provider configuration in depth on build, e.g. that the
+    /// provider supports the scopes Drawbridge requires, failing the build if it does not.
+    /// Disabled by default, in which case only provider discovery is required to succeed.
+    pub fn oidc_strict_startup(self, oidc_strict_startup: bool) -> Self {
+        Self {
+            oidc_strict_startup,
+            ..self
+        }
+    }
+
+    /// Sets the tolerance for differences between the clocks of the server and the OpenID
+    /// Connect provider, within which tokens are accepted past their `exp` or before their
+    /// `nbf` claims. Defaults to [DEFAULT_OIDC_CLOCK_SKEW].
+    ///
+    /// Tolerances above five minutes are accepted, but a warning is logged on build, since
+    /// expired tokens remain usable for that long.
+    pub fn oidc_clock_skew(self, oidc_clock_skew: Duration) -> Self {
+        Self {
+            oidc_clock_skew,
+            ..self
+        }
+    }
+
+    /// Sets whether stores using an older layout version than [STORE_VERSION] are migrated
+    /// in place on build. Disabled by default, in which case building fails for such stores.
+    /// Stores using a newer layout version are always rejected.
+    pub fn allow_store_migration(self, allow_store_migration: bool) -> Self {
+        Self {
+            allow_store_migration,
+            ..self
+        }
+    }
+
+    /// Sets the age, after which temporary files and partially written objects, e.g. ones left
+    /// over by a crash during an upload, are removed from the store on build, which defaults
+    /// to [DEFAULT_ORPHAN_MAX_AGE]. `None` disables the removal.
+    ///
+    /// Objects are partially written if their metadata is missing or does not match their
+    /// contents. Nothing is removed in read-only mode or from stores not held in a local
+    /// directory.
+    pub fn orphan_max_age(self, orphan_max_age: Option<Duration>) -> Self {
+        Self {
+            orphan_max_age,
+            ..self
+        }
+    }
+
+    /// Sets the number of directories scanned concurrently by the removal of orphaned files
+    /// on build, which defaults to [DEFAULT_STARTUP_SCAN_THREADS] and must not be zero.
+    ///
+    /// The scan completes before [Builder::build] returns, such that it does not race with
+    /// requests.
+    pub fn startup_scan_threads(self, startup_scan_threads: usize) -> Self {
+        Self {
+            startup_scan_threads,
+            ..self
+        }
+    }
+
+    /// Sets whether maintenance passes run by [App::maintain] collect garbage, i.e. remove
+    /// tree nodes not referenced by their parent directory, which defaults to `false`.
+    ///
+    /// Nothing is collected in read-only mode.
+    pub fn maintenance_gc(self, maintenance_gc: bool) -> Self {
+        Self {
+            maintenance_gc,
+            ..self
+        }
+    }
+
+    /// Sets the directory caching files of tags read from the store, which is created if
+    /// missing and emptied on build. Disabled by default.
+    ///
+    /// Since tags are immutable, cached files are served without reading them from the store,
+    /// which speeds up stores without a local directory, e.g. object stores, and is rejected
+    /// for stores with one. Tags removed by other servers sharing the store remain served until
+    /// evicted from the cache.
+    pub fn cache_dir(self, cache_dir: impl Into<PathBuf>) -> Self {
+        Self {
+            cache_dir: Some(cache_dir.into()),
+            ..self
+        }
+    }
+
+    /// Sets the maximum total size in bytes of the files held by the [Builder::cache_dir],
+    /// which defaults to [DEFAULT_CACHE_MAX_BYTES] and must not be zero. The least recently
+    /// used files are evicted once exceeded.
+    pub fn cache_max_bytes(self, cache_max_bytes: u64) -> Self {
+        Self {
+            cache_max_bytes,
+            ..self
+        }
+    }
+
+    /// Sets the number of consecutive failed or timed out operations on a store without a local
+    /// directory, e.g. an object store, after which all requests are rejected with
+    /// `503 Service Unavailable` for the [Builder::store_circuit_cooldown], which defaults to
+    /// [DEFAULT_STORE_CIRCUIT_THRESHOLD]. `0` disables the circuit breaker.
+    pub fn store_circuit_threshold(self, store_circuit_threshold: usize) -> Self {
+        Self {
+            store_circuit_threshold,
+            ..self
+        }
+    }
+
+    /// Sets the duration, for which requests are rejected once the
+    /// [Builder::store_circuit_threshold] is reached, before a single operation probes whether
+    /// the store recovered, which defaults to [DEFAULT_STORE_CIRCUIT_COOLDOWN].
+    pub fn store_circuit_cooldown(self, store_circuit_cooldown: Duration) -> Self {
+        Self {
+            store_circuit_cooldown,
+            ..self
+        }
+    }
+
+    /// Sets the replica of the store, from which contents read from the store, which do not
+    /// match their digest, are repaired before being served. Disabled by default.
+    ///
+    /// Contents are verified before being served, which costs reading them twice. Reads fail
+    /// with `500 Internal Server Error` if the replica stores no matching contents either.
+    pub fn read_repair(self, replica: impl Into<StoreUrl>) -> Self {
+        Self {
+            read_repair: Some(replica.into()),
+            ..self
+        }
+    }
+
+    /// Sets the number of requests per second each namespace, i.e. user, may send using an
+    /// OpenID Connect token, which defaults to `0`, i.e. unlimited.
+    ///
+    /// Namespaces may send bursts of up to this number of requests and are limited
+    /// independently of each other and of the address requests are received from. Requests
+    /// exceeding the limit are rejected with `429 Too Many Requests` and a `Retry-After`
+    /// header after authentication, such that unauthenticated requests are not counted.
+    pub fn namespace_rate_limit(self, namespace_rate_limit: u32) -> Self {
+        Self {
+            namespace_rate_limit,
+            ..self
+        }
+    }
+
+    /// Overrides the [Builder::namespace_rate_limit] of individual namespaces, where a limit
+    /// of `0` means unlimited.
+    pub fn namespace_rate_limit_overrides(
+        self,
+        overrides: impl IntoIterator<Item = (UserName, u32)>,
+    ) -> Self {
+        Self {
+            namespace_rate_limit_overrides: overrides.into_iter().collect(),
+            ..self
+        }
     }
 
     /// Builds the application and returns Drawbridge instance as a [tower::MakeService].
     pub async fn build(self) -> anyhow::Result<App> {
-        let Self { store, tls, oidc } = self;
-        let store_path = store.as_ref();
-        let store = File::open(store_path)
-            .and_then(|f| Store::new(Dir::from_std_file(f)))
+        let Self {
+            store,
+            tls,
+            oidc,
+            max_request_deadline,
+            upload_timeout,
+            download_timeout,
+            metadata_timeout,
+            client_cert_allowlist,
+            client_cert_writers,
+            compression,
+            store_precompressed,
+            max_tags_per_repo,
+            manifest_schema,
+            allowed_content_types,
+            allowed_methods,
+            disabled_routes,
+            read_only,
+            hide_existence,
+            require_writable_store,
+            server_timing,
+            read_slots,
+            write_slots,
+            max_inflight_bytes,
+            limit_warning_percent,
+            max_download_bps,
+            response_buffer_bytes,
+            public_url,
+            allow_insecure_public_url,
+            trusted_proxies,
+            ip_filter,
+            log_exclude_paths,
+            url_signing_secret,
+            authz_webhook,
+            authz_cache_ttl,
+            upload_session_ttl,
+            max_upload_sessions_per_repo,
+            max_upload_session_size,
+            idempotency_key_ttl,
+            negative_cache_ttl,
+            mirror,
+            server_header,
+            hsts,
+            content_cache_control,
+            tag_cache_control,
+            strict_paths,
+            metrics_endpoint,
+            keep_alive_timeout,
+            max_requests_per_connection,
+            handshake_timeout,
+            max_connections,
+            shutdown_timeout,
+            connection_log_sample_rate,
+            oidc_strict_startup,
+            oidc_clock_skew,
+            allow_store_migration,
+            orphan_max_age,
+            startup_scan_threads,
+            maintenance_gc,
+            cache_dir,
+            cache_max_bytes,
+            store_circuit_threshold,
+            store_circuit_cooldown,
+            read_repair,
+            namespace_rate_limit,
+            namespace_rate_limit_overrides,
+        } = self;
+        if let Some(ref url) = public_url {
+            match url.scheme() {
+                "https" => {}
+                "http" if allow_insecure_public_url => {}
+                scheme => bail!("public URL `{url}` must use the `https` scheme, got `{scheme}`"),
+            }
+            if url.cannot_be_a_base() || url.query().is_some() || url.fragment().is_some() {
+                bail!("public URL `{url}` must be a base URL without query or fragment");
+            }
+        }
+
+        if let Some(ref url) = authz_webhook {
+            if !matches!(url.scheme(), "http" | "https") {
+                bail!("authorization webhook URL `{url}` must use the `http` or `https` scheme");
+            }
+        }
+
+        let response_buffer = NonZeroUsize::new(response_buffer_bytes)
+            .map(ResponseBuffer)
+            .context("response buffer size must not be zero")?;
+        let startup_scan_threads = NonZeroUsize::new(startup_scan_threads)
+            .context("number of startup scan threads must not be zero")?;
+
+        if url_signing_secret
+            .as_ref()
+            .is_some_and(|secret| secret.len() < MIN_URL_SIGNING_SECRET_LEN)
+        {
+            bail!("URL signing secret must be at least {MIN_URL_SIGNING_SECRET_LEN} bytes long");
+        }
+
+        if !(0.0..=1.0).contains(&connection_log_sample_rate) {
+            bail!("connection log sample rate must be between 0 and 1");
+        }
+
+        if upload_session_ttl.is_zero() {
+            bail!("upload session TTL must not be zero");
+        }
+        if idempotency_key_ttl.is_some_and(|ttl| ttl.is_zero()) {
+            bail!("idempotency key TTL must not be zero");
+        }
+        if negative_cache_ttl > MAX_NEGATIVE_CACHE_TTL {
+            bail!(
+                "negative cache TTL of {}s exceeds {}s",
+                negative_cache_ttl.as_secs_f64(),
+                MAX_NEGATIVE_CACHE_TTL.as_secs()
+            );
+        }
+        let mirror = mirror
+            .map(mirror::Mirror::new)
+            .transpose()
+            .context("invalid mirror configuration")?
+            .map(Arc::new);
+        if let Some(algorithm) = store_precompressed
+            .iter()
+            .find(|algorithm| !compression.contains(algorithm))
+        {
+            bail!(
+                "pre-compressed `{algorithm}` variants require `{algorithm}` response compression"
+            );
+        }
+        if limit_warning_percent > 100 {
+            bail!("limit warning percentage of {limit_warning_percent} exceeds 100");
+        }
+
+        let server_header = server_header
+            .map(|value| {
+                HeaderValue::from_str(&value)
+                    .with_context(|| format!("invalid `Server` header value `{value}`"))
+            })
+            .transpose()?;
+        let hsts = hsts.as_ref().map(Hsts::header_value).transpose()?;
+
+        let cache_control = |value: Option<String>| {
+            value
+                .map(|value| {
+                    HeaderValue::from_str(&value)
+                        .with_context(|| format!("invalid `Cache-Control` header value `{value}`"))
+                })
+                .transpose()
+        };
+        let cache_control = cache_control::CacheControl {
+            content: cache_control(content_cache_control)?,
+            tags: cache_control(tag_cache_control)?,
+        };
+
+        if let Some(path) = log_exclude_paths.iter().find(|path| !path.starts_with('/')) {
+            bail!("path `{path}` excluded from access logging must start with `/`");
+        }
+
+        let store_url: StoreUrl = store.into();
+        if cache_dir.is_some() && cache_max_bytes == 0 {
+            bail!("maximum cache size must not be zero");
+        }
+        let backend = store_url
+            .open()
             .await
-            .context(anyhow!(
-                "failed to open store at `{}`",
-                store_path.to_string_lossy()
-            ))?;
+            .context(anyhow!("failed to open store at `{store_url}`"))
+            .context(FailureClass::Store)?;
+        let metrics = Arc::<Metrics>::default();
+        let circuit = (backend.dir().is_none() && store_circuit_threshold > 0).then(|| {
+            Arc::new(Circuit::new(
+                store_circuit_threshold,
+                store_circuit_cooldown,
+                Arc::clone(&metrics),
+            ))
+        });
+        let backend: Box<dyn Backend> = match circuit {
+            Some(ref circuit) => Box::new(Breaker::new(backend, Arc::clone(circuit))),
+            None => backend,
+        };
+        let backend: Box<dyn Backend> = match cache_dir {
+            Some(_) if backend.dir().is_some() => {
+                bail!("store at `{store_url}` resides in a local directory, which is not cached")
+            }
+            Some(dir) => Box::new(
+                Cached::open(backend, &dir, cache_max_bytes)
+                    .await
+                    .with_context(|| format!("failed to open cache at `{}`", dir.display()))?,
+            ),
+            None => backend,
+        };
+        let store = Store::with_backend(backend)
+            .await
+            .context(anyhow!("failed to open store at `{store_url}`"))
+            .context(FailureClass::Store)?;
+        let store = match read_repair {
+            Some(replica) if replica == store_url => {
+                bail!("replica store at `{replica}` must differ from the store")
+            }
+            Some(replica) => {
+                let backend = replica
+                    .open()
+                    .await
+                    .context(anyhow!("failed to open replica store at `{replica}`"))
+                    .context(FailureClass::Store)?;
+                store.with_read_repair(ReadRepair::new(backend, Arc::clone(&metrics)))
+            }
+            None => store,
+        };
+        let read_only = if store
+            .is_writable()
+            .await
+            .context("failed to probe whether store is writable")
+            .context(FailureClass::Store)?
+        {
+            read_only
+        } else if require_writable_store {
+            return Err(
+                anyhow!("store at `{store_url}` resides on a read-only filesystem")
+                    .context(FailureClass::Store),
+            );
+        } else {
+            warn!(
+                target: "app::Builder::build",
+                "store at `{store_url}` resides on a read-only filesystem, enabling read-only mode"
+            );
+            true
+        };
+        if mirror.is_some() && read_only {
+            bail!("mirroring requires a writable store, but the store is in read-only mode");
+        }
+
+        store
+            .upgrade(allow_store_migration)
+            .await
+            .with_context(|| format!("failed to upgrade store at `{store_url}`"))
+            .context(FailureClass::Store)?;
+
+        // Only stores held in a local directory can be scanned for orphans.
+        let orphan_max_age = orphan_max_age.filter(|_| store.dir().is_some());
+        match orphan_max_age {
+            Some(max_age) if !read_only => {
+                match store.remove_orphans(max_age, startup_scan_threads).await {
+                    Ok(summary) if summary.temporary > 0 || summary.partial > 0 => info!(
+                        target: "app::Builder::build",
+                        "removed {} temporary files and {} partially written objects from store, reclaiming {} bytes",
+                        summary.temporary,
+                        summary.partial,
+                        summary.bytes,
+                    ),
+                    Ok(_) => {}
+                    Err(e) => warn!(
+                        target: "app::Builder::build",
+                        "failed to remove orphaned files from store: {e:?}"
+                    ),
+                }
+            }
+            _ => {}
+        }
+
+        // Uploads are not accepted in read-only mode or by mirrors.
+        let uploads = if read_only || mirror.is_some() {
+            None
+        } else {
+            let dir = store
+                .open_uploads()
+                .await
+                .context("failed to open upload directory")
+                .context(FailureClass::Store)?;
+            Some(Arc::new(Uploads::new(
+                dir,
+                upload_session_ttl,
+                max_upload_sessions_per_repo,
+                max_upload_session_size,
+            )))
+        };
+
+        // Mutating requests are rejected in read-only mode and by mirrors anyway.
+        let idempotency = match idempotency_key_ttl {
+            Some(ttl) if !read_only && mirror.is_none() => {
+                let dir = store
+                    .open_idempotency()
+                    .await
+                    .context("failed to open idempotency record directory")
+                    .context(FailureClass::Store)?;
+                let idempotency = idempotency::Idempotency::new(dir, ttl);
+                match idempotency.expire().await {
+                    Ok(0) => {}
+                    Ok(n) => debug!(
+                        target: "app::Builder::build",
+                        "removed {n} expired idempotency records"
+                    ),
+                    Err(e) => warn!(
+                        target: "app::Builder::build",
+                        "failed to remove expired idempotency records: {e}"
+                    ),
+                }
+                Some(Arc::new(idempotency))
+            }
+            _ => None,
+        };
+
+        if oidc_clock_skew > MAX_RECOMMENDED_OIDC_CLOCK_SKEW {
+            warn!(
+                target: "app::Builder::build",
+                "OIDC clock skew tolerance of {}s exceeds {}s, expired tokens remain usable for that long",
+                oidc_clock_skew.as_secs(),
+                MAX_RECOMMENDED_OIDC_CLOCK_SKEW.as_secs()
+            );
+        }
+        // OIDC provider discovery performs blocking I/O.
+        let oidc_verifier = spawn_blocking(move || {
+            if oidc_strict_startup {
+                crate::auth::OidcVerifier::new_strict(oidc)
+            } else {
+                crate::auth::OidcVerifier::new(oidc)
+            }
+        })
+        .await
+        .context("failed to create OIDC verifier")
+        .context(FailureClass::Oidc)?
+        .with_clock_skew(oidc_clock_skew);
+        let oidc_verifier = Arc::new(oidc_verifier);
 
-        let oidc_verifier =
-            crate::auth::OidcVerifier::new(oidc).context("failed to create OIDC verifier")?;
+        let store = Arc::new(store);
+        let store_health = Arc::<store_health::StoreHealth>::default();
+        let readiness = Arc::new(readiness::Readiness::new(
+            Arc::clone(&store),
+            Arc::clone(&store_health),
+            Arc::clone(&oidc_verifier),
+            read_only,
+        ));
+        let ready = {
+            let readiness = Arc::clone(&readiness);
+            get(move || readiness::ready(Arc::clone(&readiness)))
+        };
+        let expose = {
+            let metrics = Arc::clone(&metrics);
+            get(move |headers| metrics::expose(Arc::clone(&metrics), headers))
+        };
+        // Served by `App::serve_metrics` without TLS.
+        let ops = Router::new()
+            .route("/healthz", any(|| async {}))
+            .route("/readyz", ready.clone())
+            .route("/metrics", expose.clone());
 
+        let app = Router::new()
+            .fallback(handle.into_service())
+            .route("/health", any(|| async {}))
+            .route("/healthz", any(|| async {}))
+            .route("/readyz", ready);
+        let app = if metrics_endpoint {
+            app.route("/metrics", expose)
+        } else {
+            app
+        };
+        // Requests are authenticated to scope idempotency keys, such that the extensions used
+        // for it must be inserted by outer layers.
+        let app = match idempotency {
+            Some(ref idempotency) => {
+                let idempotency = Arc::clone(idempotency);
+                app.layer(from_fn(move |req, next| {
+                    idempotency::replay(Arc::clone(&idempotency), req, next)
+                }))
+            }
+            None => app,
+        };
+        let app = app
+            .layer(Extension(Arc::clone(&store)))
+            .layer(Extension(Arc::clone(&metrics)))
+            .layer(Extension(oidc_verifier))
+            .layer(Extension(response_buffer));
+        // Tags are fetched before misses are cached, such that cached misses spare requests to
+        // the upstream as well.
+        let app = match mirror {
+            Some(ref mirror) => {
+                let mirror = Arc::clone(mirror);
+                let store = Arc::clone(&store);
+                app.layer(from_fn(move |req, next| {
+                    mirror::serve(Arc::clone(&mirror), Arc::clone(&store), req, next)
+                }))
+            }
+            None => app,
+        };
+        // Responses are cached before they are concealed, such that only actual misses are.
+        let app = if negative_cache_ttl.is_zero() {
+            app
+        } else {
+            let cache = Arc::new(negative_cache::NegativeCache::new(
+                negative_cache_ttl,
+                Arc::clone(&metrics),
+            ));
+            app.layer(from_fn(move |req, next| {
+                negative_cache::serve(Arc::clone(&cache), req, next)
+            }))
+        };
+        let app = if hide_existence {
+            app.layer(from_fn(hide_existence::conceal))
+        } else {
+            app
+        };
+        // Waiting for a slot counts against the request deadline.
+        let app = if read_slots == 0 && write_slots == 0 {
+            app
+        } else {
+            let slots = Arc::new(slots::Slots::new(
+                read_slots,
+                write_slots,
+                limit_warning_percent,
+                Arc::clone(&metrics),
+            ));
+            app.layer(from_fn(move |req, next| {
+                slots::acquire(Arc::clone(&slots), req, next)
+            }))
+        };
+        let app = if max_inflight_bytes == 0 {
+            app
+        } else {
+            let inflight = Arc::new(inflight::InflightBytes::new(
+                max_inflight_bytes,
+                limit_warning_percent,
+                Arc::clone(&metrics),
+            ));
+            app.layer(from_fn(move |req, next| {
+                inflight::limit(Arc::clone(&inflight), req, next)
+            }))
+        };
+        let timeouts = deadline::Timeouts {
+            upload: (!upload_timeout.is_zero()).then_some(upload_timeout),
+            download: (!download_timeout.is_zero()).then_some(download_timeout),
+            metadata: (!metadata_timeout.is_zero()).then_some(metadata_timeout),
+        };
+        let app = app
+            .layer(from_fn(expect::check))
+            .layer(from_fn(move |req, next| {
+                deadline::enforce(max_request_deadline, timeouts, req, next)
+            }));
+        let app = app.layer(from_fn({
+            let store_health = Arc::clone(&store_health);
+            move |req, next| store_health::reject_unavailable(Arc::clone(&store_health), req, next)
+        }));
+        let app = if let Some(circuit) = circuit {
+            app.layer(from_fn(move |req, next| {
+                store_health::reject_open_circuit(Arc::clone(&circuit), req, next)
+            }))
+        } else {
+            app
+        };
+        let app = if cache_control.content.is_none() && cache_control.tags.is_none() {
+            app
+        } else {
+            let cache_control = Arc::new(cache_control);
+            app.layer(from_fn(move |req, next| {
+                cache_control::set(Arc::clone(&cache_control), req, next)
+            }))
+        };
+        let app = if read_only {
+            app.layer(from_fn(read_only::reject_writes))
+        } else if let Some(mirror) = mirror {
+            app.layer(from_fn(move |req, next| {
+                mirror::reject_writes(Arc::clone(&mirror), req, next)
+            }))
+        } else {
+            app
+        };
+        let app = if allowed_methods.is_empty() && disabled_routes.is_empty() {
+            app
+        } else {
+            let surface = Arc::new(routes::Surface::new(allowed_methods, disabled_routes));
+            app.layer(from_fn(move |req, next| {
+                routes::restrict(Arc::clone(&surface), req, next)
+            }))
+        };
+        let app = if server_timing {
+            app.layer(from_fn(timing::report))
+        } else {
+            app
+        };
+        let app = if max_tags_per_repo == 0 {
+            app
+        } else {
+            app.layer(Extension(Arc::new(TagLimit::new(max_tags_per_repo))))
+        };
+        let webhook = authz_webhook.map(|url| Arc::new(Webhook::new(url, authz_cache_ttl)));
+        let app = match webhook {
+            Some(ref webhook) => app.layer(Extension(Arc::clone(webhook))),
+            None => app,
+        };
+        let app = if namespace_rate_limit == 0
+            && namespace_rate_limit_overrides.values().all(|n| *n == 0)
+        {
+            app
+        } else {
+            app.layer(Extension(Arc::new(rate_limit::NamespaceLimiter::new(
+                namespace_rate_limit,
+                namespace_rate_limit_overrides,
+            ))))
+        };
+        let app = match url_signing_secret {
+            Some(secret) => app.layer(Extension(Arc::new(UrlSigner::new(
+                &secret,
+                public_url.clone(),
+            )))),
+            None => app,
+        };
+        let app = match uploads {
+            Some(ref uploads) => app.layer(Extension(Arc::clone(uploads))),
+            None => app,
+        };
+        let app = match manifest_schema {
+            Some(schema) => app.layer(Extension(Arc::new(schema))),
+            None => app,
+        };
+        let app = if allowed_content_types.is_empty() {
+            app
+        } else {
+            app.layer(Extension(Arc::new(content_type::ContentTypes::new(
+                allowed_content_types,
+            ))))
+        };
+        let app = if store_precompressed.is_empty() {
+            app
+        } else {
+            app.layer(Extension(Arc::new(Precompression::new(
+                store_precompressed,
+                compression.clone(),
+            ))))
+        };
+        let app = if compression.is_empty() {
+            app
+        } else {
+            app.layer(compression::layer(&compression))
+                .layer(from_fn(compression::vary))
+        };
+        // Responses are observed as sent, i.e. after compression.
+        let app = app.layer(from_fn({
+            let metrics = Arc::clone(&metrics);
+            move |req, next| metrics::observe(Arc::clone(&metrics), req, next)
+        }));
+        // Paths are canonicalized before being routed and inspected by any layer.
+        let app = Router::new().fallback(app).layer(from_fn(move |req, next| {
+            paths::normalize(strict_paths, req, next)
+        }));
+        let traced = app.clone().layer(
+            TraceLayer::new_for_http()
+                .make_span_with(SpanMaker)
+                .on_request(DefaultOnRequest::new().level(Level::INFO))
+                .on_response(
+                    DefaultOnResponse::new()
+                        .level(Level::INFO)
+                        .latency_unit(LatencyUnit::Micros),
+                )
+                .on_body_chunk(DefaultOnBodyChunk::new())
+                .on_eos(
+                    DefaultOnEos::new()
+                        .level(Level::INFO)
+                        .latency_unit(LatencyUnit::Micros),
+                )
+                .on_failure(
+                    DefaultOnFailure::new()
+                        .level(Level::INFO)
+                        .latency_unit(LatencyUnit::Micros),
+                ),
+        );
+        let app = if log_exclude_paths.is_empty() {
+            traced
+        } else {
+            // Requests to excluded paths bypass the trace layer.
+            let excluded: Arc<HashSet<_>> = Arc::new(log_exclude_paths.into_iter().collect());
+            Router::new().fallback(Steer::new(
+                [traced, app],
+                move |req: &Request<Body>, _: &[_]| {
+                    usize::from(excluded.contains(req.uri().path()))
+                },
+            ))
+        };
+        // Neither orphans nor garbage must be removed from the store in read-only mode.
+        let maintenance = Maintenance {
+            store: Arc::clone(&store),
+            orphan_max_age: orphan_max_age.filter(|_| !read_only),
+            gc: maintenance_gc && !read_only,
+            uploads,
+            idempotency,
+            webhook,
+            metrics: Arc::clone(&metrics),
+        };
         Ok(App {
-            make_service: Mutex::new(
-                Router::new()
-                    .fallback(handle.into_service())
-                    .route("/health", any(|| async {}))
-                    .layer(Extension(Arc::new(store)))
-                    .layer(Extension(Arc::new(oidc_verifier)))
-                    .layer(
-                        TraceLayer::new_for_http()
-                            .make_span_with(SpanMaker::default())
-                            .on_request(DefaultOnRequest::new().level(Level::INFO))
-                            .on_response(
-                                DefaultOnResponse::new()
-                                    .level(Level::INFO)
-                                    .latency_unit(LatencyUnit::Micros),
-                            )
-                            .on_body_chunk(DefaultOnBodyChunk::new())
-                            .on_eos(
-                                DefaultOnEos::new()
-                                    .level(Level::INFO)
-                                    .latency_unit(LatencyUnit::Micros),
-                            )
-                            .on_failure(
-                                DefaultOnFailure::new()
-                                    .level(Level::INFO)
-                                    .latency_unit(LatencyUnit::Micros),
-                            ),
-                    )
-                    .into_make_service(),
+            make_service: Mutex::new(app.into_make_service()),
+            ops: Mutex::new(ops.into_make_service()),
+            tls: RwLock::new(TlsAcceptor::from(Arc::new(tls.into()))),
+            client_cert_allowlist: Arc::new(RwLock::new(client_cert_allowlist)),
+            client_cert_writers: Arc::new(RwLock::new(client_cert_writers)),
+            metrics,
+            read_only,
+            max_download_bps: NonZeroU64::new(max_download_bps),
+            public_url,
+            trusted_proxies: Arc::new(trusted_proxies),
+            ip_filter: Arc::new(ip_filter),
+            store,
+            store_health,
+            maintenance,
+            server_header,
+            hsts,
+            keep_alive_timeout: (!keep_alive_timeout.is_zero()).then_some(keep_alive_timeout),
+            max_requests_per_connection: NonZeroU64::new(max_requests_per_connection),
+            handshake_timeout: (!handshake_timeout.is_zero()).then_some(handshake_timeout),
+            connections: Connections::new(
+                max_connections,
+                shutdown_timeout,
+                connection_log_sample_rate,
             ),
-            tls: TlsAcceptor::from(Arc::new(tls.into())),
         })
     }
 }
@@ -0,0 +1,64 @@
+// SPDX-FileCopyrightText: 2022 Profian Inc. <opensource@profian.com>
+// SPDX-License-Identifier: AGPL-3.0-only
+
+use super::Endpoint;
+
+use std::sync::Arc;
+
+use axum::http::header::CACHE_CONTROL;
+use axum::http::{HeaderValue, Method, Request, StatusCode};
+use axum::middleware::Next;
+use axum::response::Response;
+
+/// Default `Cache-Control` header value of responses containing tree nodes, which are
+/// immutable.
+pub const DEFAULT_CONTENT_CACHE_CONTROL: &str = "public, max-age=31536000, immutable";
+
+/// Default `Cache-Control` header value of responses containing tags or tag listings, which
+/// may change.
+pub const DEFAULT_TAG_CACHE_CONTROL: &str = "no-cache";
+
+/// `Cache-Control` header values of successful responses to reading requests.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct CacheControl {
+    /// Value for tree nodes, which are addressed by the digests of their parents and cannot
+    /// change once stored.
+    pub(crate) content: Option<HeaderValue>,
+    /// Value for tags and tag listings.
+    pub(crate) tags: Option<HeaderValue>,
+}
+
+impl CacheControl {
+    /// Returns the value for responses of `endpoint`, if any.
+    fn value(&self, endpoint: Endpoint) -> Option<&HeaderValue> {
+        match endpoint {
+            Endpoint::Tree => self.content.as_ref(),
+            Endpoint::Tag | Endpoint::TagQuery => self.tags.as_ref(),
+            _ => None,
+        }
+    }
+}
+
+/// Sets the `Cache-Control` header of successful responses to `GET` and `HEAD` requests
+/// according to `cache_control`, unless the response already carries one.
+pub(crate) async fn set<B>(
+    cache_control: Arc<CacheControl>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let value = match *req.method() {
+        Method::GET | Method::HEAD => Endpoint::of(req.uri().path())
+            .and_then(|endpoint| cache_control.value(endpoint))
+            .cloned(),
+        _ => None,
+    };
+    let mut res = next.run(req).await;
+    if let Some(value) = value {
+        if (res.status().is_success() || res.status() == StatusCode::NOT_MODIFIED)
+            && !res.headers().contains_key(CACHE_CONTROL)
+        {
+            _ = res.headers_mut().insert(CACHE_CONTROL, value);
+        }
+    }
+    res
+}
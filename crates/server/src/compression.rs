@@ -0,0 +1,150 @@
+// SPDX-FileCopyrightText: 2022 Profian Inc. <opensource@profian.com>
+// SPDX-License-Identifier: AGPL-3.0-only
+
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::bail;
+use axum::http::header::{ACCEPT_ENCODING, CONTENT_ENCODING, ETAG, VARY};
+use axum::http::{HeaderMap, HeaderValue, Request};
+use axum::middleware::Next;
+use axum::response::Response;
+use tower_http::compression::CompressionLayer;
+
+/// Response compression algorithm offered to clients.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum CompressionAlgorithm {
+    Brotli,
+    Gzip,
+}
+
+impl fmt::Display for CompressionAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Brotli => write!(f, "br"),
+            Self::Gzip => write!(f, "gzip"),
+        }
+    }
+}
+
+impl FromStr for CompressionAlgorithm {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "br" => Ok(Self::Brotli),
+            "gzip" => Ok(Self::Gzip),
+            _ => bail!("unsupported compression algorithm `{s}`"),
+        }
+    }
+}
+
+/// Returns a [CompressionLayer] offering `algorithms`.
+///
+/// The encoding is negotiated using the quality values in the `Accept-Encoding` request header,
+/// falling back to identity if none of `algorithms` is acceptable to the client.
+pub(crate) fn layer(algorithms: &[CompressionAlgorithm]) -> CompressionLayer {
+    CompressionLayer::new()
+        .no_deflate()
+        .br(algorithms.contains(&CompressionAlgorithm::Brotli))
+        .gzip(algorithms.contains(&CompressionAlgorithm::Gzip))
+}
+
+/// Returns the algorithm out of `algorithms`, which is preferred by the client according to
+/// the `Accept-Encoding` header in `headers`, or `None` if identity is preferred.
+///
+/// The negotiation matches the one of [layer], i.e. the first encoding with the highest
+/// quality value wins and unknown encodings are ignored.
+pub(crate) fn negotiate(
+    headers: &HeaderMap,
+    algorithms: &[CompressionAlgorithm],
+) -> Option<CompressionAlgorithm> {
+    let mut preferred = None;
+    let mut max_quality = 0.0;
+    for encoding in headers
+        .get_all(ACCEPT_ENCODING)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+    {
+        let (name, quality) = encoding.split_once(';').unwrap_or((encoding, "q=1"));
+        let name = name.trim();
+        let algorithm = if name.eq_ignore_ascii_case("identity") {
+            None
+        } else {
+            match algorithms
+                .iter()
+                .find(|algorithm| name.eq_ignore_ascii_case(&algorithm.to_string()))
+            {
+                Some(algorithm) => Some(*algorithm),
+                None => continue,
+            }
+        };
+        let Some(quality) = quality
+            .trim()
+            .strip_prefix(['q', 'Q'])
+            .and_then(|quality| quality.strip_prefix('='))
+            .and_then(|quality| quality.parse::<f32>().ok())
+            .filter(|quality| (0.0..=1.0).contains(quality))
+        else {
+            continue;
+        };
+        if quality > max_quality {
+            preferred = algorithm;
+            max_quality = quality;
+        }
+    }
+    preferred
+}
+
+/// Marks responses as varying by `Accept-Encoding` and weakens strong entity tags of encoded
+/// responses, since those are not byte-for-byte identical to the identity representation.
+pub(crate) async fn vary<B>(req: Request<B>, next: Next<B>) -> Response {
+    let mut res = next.run(req).await;
+    let headers = res.headers_mut();
+    _ = headers.append(VARY, HeaderValue::from_static("accept-encoding"));
+    if headers.contains_key(CONTENT_ENCODING) {
+        if let Some(etag) = headers.get(ETAG).and_then(|etag| etag.to_str().ok()) {
+            if !etag.starts_with("W/") {
+                if let Ok(etag) = HeaderValue::from_str(&format!("W/{etag}")) {
+                    _ = headers.insert(ETAG, etag);
+                }
+            }
+        }
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn negotiate() {
+        let negotiate = |accept_encoding: Option<&str>, algorithms: &[CompressionAlgorithm]| {
+            let mut headers = HeaderMap::new();
+            if let Some(accept_encoding) = accept_encoding {
+                _ = headers.insert(ACCEPT_ENCODING, accept_encoding.parse().unwrap());
+            }
+            super::negotiate(&headers, algorithms)
+        };
+        let all = [CompressionAlgorithm::Brotli, CompressionAlgorithm::Gzip];
+        let gzip = Some(CompressionAlgorithm::Gzip);
+        let br = Some(CompressionAlgorithm::Brotli);
+
+        assert_eq!(negotiate(None, &all), None);
+        assert_eq!(negotiate(Some("identity"), &all), None);
+        assert_eq!(negotiate(Some("deflate"), &all), None);
+        assert_eq!(negotiate(Some("gzip"), &all), gzip);
+        assert_eq!(negotiate(Some("GZIP"), &all), gzip);
+        assert_eq!(negotiate(Some("br"), &all), br);
+        assert_eq!(negotiate(Some("br, gzip;q=0.5"), &all), br);
+        assert_eq!(negotiate(Some("br;q=0.5, gzip"), &all), gzip);
+        assert_eq!(negotiate(Some("gzip, br"), &all), gzip);
+        assert_eq!(negotiate(Some("deflate, gzip;q=0.5"), &all), gzip);
+        assert_eq!(negotiate(Some("identity, gzip;q=0.5"), &all), None);
+        assert_eq!(negotiate(Some("gzip;q=0"), &all), None);
+        assert_eq!(negotiate(Some("gzip;q=2, br;q=0.1"), &all), br);
+        assert_eq!(negotiate(Some("br"), &[CompressionAlgorithm::Gzip]), None);
+    }
+}
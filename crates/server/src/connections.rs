@@ -0,0 +1,245 @@
+// SPDX-FileCopyrightText: 2022 Profian Inc. <opensource@profian.com>
+// SPDX-License-Identifier: AGPL-3.0-only
+
+use std::future::Future;
+use std::net::SocketAddr;
+use std::pin::pin;
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_io::Timer;
+use async_lock::Semaphore;
+use async_std::channel::{bounded, Receiver, Sender};
+use async_std::net::{TcpListener, TcpStream};
+use async_std::task::spawn;
+use futures::future::{select, Either};
+use tracing::{debug, error, info, warn};
+
+/// Default duration a TLS handshake may take before the connection is dropped.
+pub const DEFAULT_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Default duration connections are drained for on shutdown.
+pub const DEFAULT_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Duration after which idle connections are dropped while shutting down, which hyper does
+/// not close by itself if no complete request was received on them yet.
+pub(crate) const DRAIN_IDLE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Duration to wait for after failing to accept a connection, e.g. because the process ran
+/// out of file descriptors, before accepting the next one.
+const ACCEPT_ERROR_BACKOFF: Duration = Duration::from_millis(100);
+
+/// Shutdown of an [App](crate::App), which is observed by all of its connections once
+/// triggered.
+#[derive(Debug)]
+pub(crate) struct Shutdown {
+    tx: Sender<()>,
+    rx: Receiver<()>,
+}
+
+impl Shutdown {
+    fn new() -> Self {
+        let (tx, rx) = bounded(1);
+        Self { tx, rx }
+    }
+
+    /// Triggers the shutdown and returns `true` unless it was triggered before.
+    fn trigger(&self) -> bool {
+        self.tx.close()
+    }
+
+    pub(crate) fn is_triggered(&self) -> bool {
+        self.tx.is_closed()
+    }
+
+    /// Waits for the shutdown to be triggered.
+    pub(crate) async fn triggered(&self) {
+        _ = self.rx.recv().await;
+    }
+}
+
+/// Manager of the connections accepted by an [App](crate::App), which serves each on its own
+/// task, limits how many are served concurrently and drains them on shutdown.
+#[derive(Debug)]
+pub(crate) struct Connections {
+    /// Permits to serve a connection, which are shared by all listeners, if limited.
+    limit: Option<(usize, Arc<Semaphore>)>,
+    shutdown_timeout: Duration,
+    log_sample_rate: f64,
+    shutdown: Shutdown,
+}
+
+impl Connections {
+    /// Constructs a new [Connections] serving at most `max_connections` connections
+    /// concurrently, where `0` means unlimited.
+    pub(crate) fn new(
+        max_connections: usize,
+        shutdown_timeout: Duration,
+        log_sample_rate: f64,
+    ) -> Self {
+        Self {
+            limit: (max_connections > 0)
+                .then(|| (max_connections, Arc::new(Semaphore::new(max_connections)))),
+            shutdown_timeout,
+            log_sample_rate,
+            shutdown: Shutdown::new(),
+        }
+    }
+
+    pub(crate) fn shutdown(&self) -> &Shutdown {
+        &self.shutdown
+    }
+
+    /// Triggers the shutdown, after which no more connections are accepted and established
+    /// ones are closed once their in-flight requests completed.
+    pub(crate) fn trigger_shutdown(&self) {
+        if self.shutdown.trigger() {
+            info!(target: "app::App::shutdown", "shutting down, draining connections");
+        }
+    }
+
+    /// Accepts connections from `listener` until shutdown and serves each on its own task
+    /// using `serve`, then waits for the served connections to complete for at most the
+    /// shutdown timeout, after which the remaining ones are dropped.
+    ///
+    /// Connections exceeding the limit are left in the listen backlog of `listener` until
+    /// another connection completes. Failures to serve a connection are logged prefixed by
+    /// `failure`.
+    pub(crate) async fn accept<F, Fut>(&self, listener: &TcpListener, failure: &str, serve: F)
+    where
+        F: Fn(TcpStream, Option<SocketAddr>) -> Fut,
+        Fut: Future<Output = anyhow::Result<()>> + Send + 'static,
+    {
+        self.accept_limited(listener, failure, serve, self.limit.as_ref())
+            .await
+    }
+
+    /// Like [Connections::accept], but the connections do not count towards the limit.
+    pub(crate) async fn accept_unlimited<F, Fut>(
+        &self,
+        listener: &TcpListener,
+        failure: &str,
+        serve: F,
+    ) where
+        F: Fn(TcpStream, Option<SocketAddr>) -> Fut,
+        Fut: Future<Output = anyhow::Result<()>> + Send + 'static,
+    {
+        self.accept_limited(listener, failure, serve, None).await
+    }
+
+    async fn accept_limited<F, Fut>(
+        &self,
+        listener: &TcpListener,
+        failure: &str,
+        serve: F,
+        limit: Option<&(usize, Arc<Semaphore>)>,
+    ) where
+        F: Fn(TcpStream, Option<SocketAddr>) -> Fut,
+        Fut: Future<Output = anyhow::Result<()>> + Send + 'static,
+    {
+        // Each task holds a sender, such that the receiver is closed once all completed.
+        let (active, drained) = bounded::<()>(1);
+        // Closed once the shutdown timeout elapsed, upon which the tasks drop their connection.
+        let (abort, aborted) = bounded::<()>(1);
+        loop {
+            let permit = match limit {
+                Some((max, permits)) => {
+                    let permit = match permits.try_acquire_arc() {
+                        Some(permit) => permit,
+                        None => {
+                            debug!(target: "app::App::accept", "reached limit of {max} connections, deferring new ones");
+                            match select(
+                                pin!(permits.acquire_arc()),
+                                pin!(self.shutdown.triggered()),
+                            )
+                            .await
+                            {
+                                Either::Left((permit, _)) => permit,
+                                Either::Right(_) => break,
+                            }
+                        }
+                    };
+                    Some(permit)
+                }
+                None => None,
+            };
+            let stream =
+                match select(pin!(listener.accept()), pin!(self.shutdown.triggered())).await {
+                    Either::Left((Ok((stream, _)), _)) => stream,
+                    Either::Left((Err(e), _)) => {
+                        error!(target: "app::App::accept", "failed to accept connection: {e}");
+                        _ = Timer::after(ACCEPT_ERROR_BACKOFF).await;
+                        continue;
+                    }
+                    Either::Right(_) => break,
+                };
+
+            // Failures are logged regardless of whether the connection is sampled.
+            let peer = stream.peer_addr().ok();
+            if is_log_sampled(self.log_sample_rate) {
+                match peer {
+                    Some(peer) => {
+                        debug!(target: "app::App::accept", "received TCP connection from {peer}")
+                    }
+                    None => {
+                        debug!(target: "app::App::accept", "received TCP connection from unknown address")
+                    }
+                }
+            }
+            let conn = serve(stream, peer);
+            let active = active.clone();
+            let aborted = aborted.clone();
+            let failure = failure.to_string();
+            _ = spawn(async move {
+                if let Either::Left((Err(e), _)) = select(pin!(conn), pin!(aborted.recv())).await {
+                    error!(target: "app::App::accept", "{failure}: {e}");
+                }
+                drop((permit, active));
+            });
+        }
+        drop(active);
+
+        match select(pin!(drained.recv()), Timer::after(self.shutdown_timeout)).await {
+            Either::Left(_) => debug!(target: "app::App::accept", "drained connections"),
+            Either::Right(_) => {
+                warn!(
+                    target: "app::App::accept",
+                    "dropping {} connections still active after shutdown timeout",
+                    drained.sender_count()
+                );
+                _ = abort.close();
+                _ = drained.recv().await;
+            }
+        }
+    }
+}
+
+/// Returns whether a connection is logged, such that `sample_rate` of all connections are.
+fn is_log_sampled(sample_rate: f64) -> bool {
+    sample_rate >= 1.0 || rand::random::<f64>() < sample_rate
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use async_std::task::block_on;
+
+    #[test]
+    fn log_sampling() {
+        assert!((0..1000).all(|_| is_log_sampled(1.0)));
+        assert!((0..1000).all(|_| !is_log_sampled(0.0)));
+        let sampled = (0..1000).filter(|_| is_log_sampled(0.5)).count();
+        assert!((1..1000).contains(&sampled), "{sampled}");
+    }
+
+    #[test]
+    fn shutdown() {
+        let shutdown = Shutdown::new();
+        assert!(!shutdown.is_triggered());
+        assert!(shutdown.trigger());
+        assert!(!shutdown.trigger());
+        assert!(shutdown.is_triggered());
+        block_on(shutdown.triggered());
+    }
+}
@@ -0,0 +1,174 @@
+// SPDX-FileCopyrightText: 2022 Profian Inc. <opensource@profian.com>
+// SPDX-License-Identifier: AGPL-3.0-only
+
+use std::io;
+
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use futures::io::Cursor;
+use futures::{AsyncRead, AsyncReadExt};
+use mime::Mime;
+use tracing::debug;
+
+/// Number of leading bytes of uploaded contents inspected to sniff their media type.
+const SNIFF_LEN: usize = 512;
+
+/// Leading bytes, by which contents of a media type are recognized.
+const SIGNATURES: &[(&str, &[u8])] = &[
+    ("application/wasm", b"\0asm"),
+    ("application/gzip", b"\x1f\x8b"),
+    ("application/zip", b"PK\x03\x04"),
+    ("application/pdf", b"%PDF-"),
+    ("application/x-executable", b"\x7fELF"),
+    ("image/png", b"\x89PNG\r\n\x1a\n"),
+];
+
+/// Returns the media type of the contents starting with `prefix` if they carry a known
+/// signature.
+fn sniff(prefix: &[u8]) -> Option<&'static str> {
+    SIGNATURES
+        .iter()
+        .find(|(_, signature)| prefix.starts_with(signature))
+        .map(|(mime, _)| *mime)
+}
+
+/// Returns whether contents starting with `prefix` plausibly are of media type `mime`.
+///
+/// Contents of media types with a known signature must start with it, JSON must start with a
+/// JSON value and contents of all other types must not carry the signature of a known type.
+fn plausible(mime: &Mime, prefix: &[u8]) -> bool {
+    let sniffed = sniff(prefix);
+    if SIGNATURES
+        .iter()
+        .any(|(known, _)| *known == mime.essence_str())
+    {
+        return sniffed == Some(mime.essence_str());
+    }
+    if sniffed.is_some() {
+        return false;
+    }
+    if mime.subtype() == mime::JSON || mime.suffix() == Some(mime::JSON) {
+        // A prefix consisting of whitespace only is inconclusive.
+        return prefix
+            .iter()
+            .find(|b| !b.is_ascii_whitespace())
+            .is_none_or(|b| b"{[\"-0123456789tfn".contains(b));
+    }
+    true
+}
+
+/// Media types, which file nodes may be uploaded as.
+///
+/// Each allowed type is either a full media type, e.g. `application/wasm`, or a range of
+/// types, e.g. `text/*`. Parameters are ignored when matching.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ContentTypes(Vec<Mime>);
+
+impl ContentTypes {
+    pub(crate) fn new(allowed: Vec<Mime>) -> Self {
+        Self(allowed)
+    }
+
+    /// Returns whether file nodes may be uploaded as `mime`.
+    fn allows(&self, mime: &Mime) -> bool {
+        self.0.iter().any(|allowed| {
+            if allowed.subtype() == mime::STAR {
+                allowed.type_() == mime.type_()
+            } else {
+                allowed.essence_str() == mime.essence_str()
+            }
+        })
+    }
+
+    /// Rejects `mime` with `415 Unsupported Media Type` unless it is allowed.
+    #[allow(clippy::result_large_err)]
+    pub(crate) fn check(&self, mime: &Mime) -> Result<(), Response> {
+        if self.allows(mime) {
+            Ok(())
+        } else {
+            debug!(target: "app::content_type", "reject disallowed content type `{mime}`");
+            Err(unsupported(format!("Content type `{mime}` is not allowed")))
+        }
+    }
+
+    /// Sniffs the media type of `body`, rejecting it with `415 Unsupported Media Type` unless
+    /// it matches the declared `mime`. Returns a reader yielding the complete `body`.
+    #[allow(clippy::result_large_err)]
+    pub(crate) async fn sniff<R>(
+        &self,
+        mime: &Mime,
+        mut body: R,
+    ) -> Result<impl AsyncRead + Unpin, Response>
+    where
+        R: AsyncRead + Unpin,
+    {
+        let mut prefix = Vec::with_capacity(SNIFF_LEN);
+        _ = (&mut body)
+            .take(SNIFF_LEN as u64)
+            .read_to_end(&mut prefix)
+            .await
+            .map_err(|e: io::Error| (StatusCode::BAD_REQUEST, e.to_string()).into_response())?;
+        if !plausible(mime, &prefix) {
+            debug!(
+                target: "app::content_type",
+                "reject contents not matching content type `{mime}`"
+            );
+            return Err(unsupported(format!(
+                "Contents do not match content type `{mime}`"
+            )));
+        }
+        Ok(Cursor::new(prefix).chain(body))
+    }
+}
+
+fn unsupported(msg: String) -> Response {
+    (StatusCode::UNSUPPORTED_MEDIA_TYPE, msg).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn allows() {
+        let types = ContentTypes::new(vec![
+            "application/wasm".parse().unwrap(),
+            "application/json".parse().unwrap(),
+            "text/*".parse().unwrap(),
+        ]);
+        for mime in [
+            "application/wasm",
+            "application/json",
+            "application/json; charset=utf-8",
+            "text/plain",
+            "text/markdown",
+        ] {
+            assert!(types.allows(&mime.parse().unwrap()), "{mime}");
+        }
+        for mime in [
+            "application/octet-stream",
+            "application/problem+json",
+            "image/png",
+            "application/wasm+json",
+        ] {
+            assert!(!types.allows(&mime.parse().unwrap()), "{mime}");
+        }
+    }
+
+    #[test]
+    fn plausible() {
+        let wasm = "application/wasm".parse().unwrap();
+        let json = "application/json".parse().unwrap();
+        let text = "text/plain".parse().unwrap();
+        assert!(super::plausible(&wasm, b"\0asm\x01\0\0\0"));
+        assert!(!super::plausible(&wasm, b"{}"));
+        assert!(!super::plausible(&wasm, b""));
+        assert!(super::plausible(&json, b"  {\"a\": 1}"));
+        assert!(super::plausible(&json, b"[]"));
+        assert!(super::plausible(&json, b""));
+        assert!(!super::plausible(&json, b"\0asm\x01\0\0\0"));
+        assert!(!super::plausible(&json, b"hello"));
+        assert!(super::plausible(&text, b"hello"));
+        assert!(!super::plausible(&text, b"\x7fELF\x02\x01"));
+    }
+}
@@ -0,0 +1,239 @@
+// SPDX-FileCopyrightText: 2022 Profian Inc. <opensource@profian.com>
+// SPDX-License-Identifier: AGPL-3.0-only
+
+use super::Endpoint;
+
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use async_std::future::timeout;
+use async_std::task::sleep;
+use axum::body::{boxed, BoxBody, Bytes};
+use axum::http::{HeaderMap, Method, Request, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use hyper::body::{HttpBody, SizeHint};
+use tracing::{debug, trace};
+
+/// Name of the header carrying the client-requested deadline.
+pub(crate) const GRPC_TIMEOUT: &str = "grpc-timeout";
+
+/// Parses a `grpc-timeout` header value, e.g. `100m` for 100 milliseconds.
+///
+/// The value consists of at most 8 ASCII digits followed by a single unit character,
+/// which is one of `H`, `M`, `S`, `m`, `u` or `n` for hours, minutes, seconds,
+/// milliseconds, microseconds and nanoseconds respectively.
+pub(crate) fn parse_grpc_timeout(s: &str) -> Option<Duration> {
+    if !s.is_ascii() || s.len() < 2 || s.len() > 9 {
+        return None;
+    }
+    let (value, unit) = s.split_at(s.len() - 1);
+    if !value.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let value = value.parse().ok()?;
+    match unit {
+        "H" => Some(Duration::from_secs(value * 60 * 60)),
+        "M" => Some(Duration::from_secs(value * 60)),
+        "S" => Some(Duration::from_secs(value)),
+        "m" => Some(Duration::from_millis(value)),
+        "u" => Some(Duration::from_micros(value)),
+        "n" => Some(Duration::from_nanos(value)),
+        _ => None,
+    }
+}
+
+/// Class of requests, whose handling is bounded by a dedicated timeout.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum TransferClass {
+    /// Requests transferring file contents to the server.
+    Upload,
+    /// Requests transferring file contents to the client.
+    Download,
+    /// All other requests, which only transfer small documents.
+    Metadata,
+}
+
+impl TransferClass {
+    /// Returns the class of requests using `method` to access `path`.
+    fn of(method: &Method, path: &str) -> Self {
+        match (Endpoint::of(path), method) {
+            (Some(Endpoint::Tree), &Method::PUT)
+            | (Some(Endpoint::Upload), &Method::PATCH | &Method::PUT) => Self::Upload,
+            (Some(Endpoint::Tree), &Method::GET) => Self::Download,
+            _ => Self::Metadata,
+        }
+    }
+}
+
+/// Timeouts configured by the server per [TransferClass], which bound the time from the
+/// start of handling a request until its response body is completely sent.
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct Timeouts {
+    pub(crate) upload: Option<Duration>,
+    pub(crate) download: Option<Duration>,
+    pub(crate) metadata: Option<Duration>,
+}
+
+impl Timeouts {
+    fn of(&self, class: TransferClass) -> Option<Duration> {
+        match class {
+            TransferClass::Upload => self.upload,
+            TransferClass::Download => self.download,
+            TransferClass::Metadata => self.metadata,
+        }
+    }
+}
+
+/// Response body, which fails once the timeout of the request is exceeded, such that the
+/// connection is aborted.
+struct Bounded {
+    inner: BoxBody,
+    expired: Pin<Box<dyn Future<Output = ()> + Send>>,
+}
+
+impl HttpBody for Bounded {
+    type Data = Bytes;
+    type Error = axum::Error;
+
+    fn poll_data(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        if let Poll::Ready(data) = Pin::new(&mut self.inner).poll_data(cx) {
+            return Poll::Ready(data);
+        }
+        match self.expired.as_mut().poll(cx) {
+            Poll::Ready(()) => {
+                debug!(target: "app::deadline", "request timeout exceeded sending response body");
+                Poll::Ready(Some(Err(axum::Error::new("request timeout exceeded"))))
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+
+    fn poll_trailers(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Option<HeaderMap>, Self::Error>> {
+        Pin::new(&mut self.inner).poll_trailers(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+/// Races handling of `req` against the deadline requested by the client, if any, and the
+/// server timeout of the [TransferClass] of `req` in `timeouts`, if set.
+///
+/// The requested deadline is capped at `max`. Requests exceeding the server timeout are
+/// aborted with `408 Request Timeout` and transfers of their response bodies are aborted
+/// once the timeout is exceeded.
+pub(crate) async fn enforce<B>(
+    max: Duration,
+    timeouts: Timeouts,
+    req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let start = Instant::now();
+    let deadline = match req.headers().get(GRPC_TIMEOUT) {
+        None => None,
+        Some(v) => match v.to_str().ok().and_then(parse_grpc_timeout) {
+            Some(deadline) => Some(deadline.min(max)),
+            None => {
+                return (
+                    StatusCode::BAD_REQUEST,
+                    format!("Invalid `{GRPC_TIMEOUT}` header value"),
+                )
+                    .into_response()
+            }
+        },
+    };
+    // The server timeout only applies if the client did not request a shorter deadline.
+    let class = TransferClass::of(req.method(), req.uri().path());
+    let limit = timeouts
+        .of(class)
+        .filter(|limit| deadline.is_none_or(|deadline| *limit <= deadline));
+    let Some(limit) = limit else {
+        let Some(deadline) = deadline else {
+            return next.run(req).await;
+        };
+        trace!(target: "app::deadline", "enforce request deadline of {deadline:?}");
+        return timeout(deadline, next.run(req)).await.unwrap_or_else(|_| {
+            debug!(target: "app::deadline", "request deadline of {deadline:?} exceeded");
+            (StatusCode::GATEWAY_TIMEOUT, "Request deadline exceeded").into_response()
+        });
+    };
+    trace!(target: "app::deadline", "enforce {class:?} request timeout of {limit:?}");
+    let Ok(res) = timeout(limit, next.run(req)).await else {
+        debug!(target: "app::deadline", "{class:?} request timeout of {limit:?} exceeded");
+        return (StatusCode::REQUEST_TIMEOUT, "Request timeout exceeded").into_response();
+    };
+    if res.body().is_end_stream() {
+        return res;
+    }
+    let remaining = limit.saturating_sub(start.elapsed());
+    res.map(|inner| {
+        boxed(Bounded {
+            inner,
+            expired: Box::pin(sleep(remaining)),
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn grpc_timeout() {
+        assert_eq!(parse_grpc_timeout("1H"), Some(Duration::from_secs(3600)));
+        assert_eq!(parse_grpc_timeout("2M"), Some(Duration::from_secs(120)));
+        assert_eq!(parse_grpc_timeout("30S"), Some(Duration::from_secs(30)));
+        assert_eq!(parse_grpc_timeout("100m"), Some(Duration::from_millis(100)));
+        assert_eq!(parse_grpc_timeout("5u"), Some(Duration::from_micros(5)));
+        assert_eq!(
+            parse_grpc_timeout("99999999n"),
+            Some(Duration::from_nanos(99999999))
+        );
+
+        assert_eq!(parse_grpc_timeout(""), None);
+        assert_eq!(parse_grpc_timeout("S"), None);
+        assert_eq!(parse_grpc_timeout("10"), None);
+        assert_eq!(parse_grpc_timeout("10s"), None);
+        assert_eq!(parse_grpc_timeout("-1S"), None);
+        assert_eq!(parse_grpc_timeout("+1S"), None);
+        assert_eq!(parse_grpc_timeout("123456789S"), None);
+    }
+
+    #[test]
+    fn transfer_class() {
+        const TREE: &str = "/api/v0.1.0/user/repo/_tag/0.1.0/tree/main.wasm";
+        const UPLOAD: &str = "/api/v0.1.0/user/repo/_upload/67e55044-10b1-426f-9247-bb680e5fe0c8";
+        for (method, path, class) in [
+            (Method::PUT, TREE, TransferClass::Upload),
+            (Method::PATCH, UPLOAD, TransferClass::Upload),
+            (Method::PUT, UPLOAD, TransferClass::Upload),
+            (Method::GET, TREE, TransferClass::Download),
+            (Method::HEAD, TREE, TransferClass::Metadata),
+            (Method::POST, TREE, TransferClass::Metadata),
+            (Method::GET, UPLOAD, TransferClass::Metadata),
+            (Method::PUT, "/api/v0.1.0/user", TransferClass::Metadata),
+            (
+                Method::PUT,
+                "/api/v0.1.0/user/repo/_tag/0.1.0",
+                TransferClass::Metadata,
+            ),
+            (Method::GET, "/health", TransferClass::Metadata),
+        ] {
+            assert_eq!(TransferClass::of(&method, path), class, "{method} {path}");
+        }
+    }
+}
@@ -0,0 +1,48 @@
+// SPDX-FileCopyrightText: 2022 Profian Inc. <opensource@profian.com>
+// SPDX-License-Identifier: AGPL-3.0-only
+
+use axum::http::{StatusCode, Uri};
+use axum::response::{IntoResponse, Response};
+
+/// Name of the query parameter requesting a dry run of an upload.
+const DRY_RUN: &str = "dry-run";
+
+/// Returns whether the `dry-run` query parameter of `uri` requests uploads to be validated
+/// without storing them.
+#[allow(clippy::result_large_err)]
+pub(crate) fn requested(uri: &Uri) -> Result<bool, Response> {
+    let value = uri
+        .query()
+        .unwrap_or_default()
+        .split('&')
+        .find_map(|param| match param.split_once('=') {
+            Some((name, value)) if name == DRY_RUN => Some(value),
+            None if param == DRY_RUN => Some("true"),
+            _ => None,
+        });
+    match value {
+        None | Some("false") => Ok(false),
+        Some("true") => Ok(true),
+        Some(_) => Err((
+            StatusCode::BAD_REQUEST,
+            format!("Invalid `{DRY_RUN}` query parameter value"),
+        )
+            .into_response()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dry_run_requested() {
+        let requested = |uri: &'static str| requested(&Uri::from_static(uri)).ok();
+        assert_eq!(requested("/api/v0.1.0/user"), Some(false));
+        assert_eq!(requested("/api/v0.1.0/user?foo=bar"), Some(false));
+        assert_eq!(requested("/api/v0.1.0/user?dry-run=false"), Some(false));
+        assert_eq!(requested("/api/v0.1.0/user?dry-run=true"), Some(true));
+        assert_eq!(requested("/api/v0.1.0/user?foo=bar&dry-run"), Some(true));
+        assert_eq!(requested("/api/v0.1.0/user?dry-run=yes"), None);
+    }
+}
@@ -0,0 +1,26 @@
+// SPDX-FileCopyrightText: 2022 Profian Inc. <opensource@profian.com>
+// SPDX-License-Identifier: AGPL-3.0-only
+
+use axum::http::header::EXPECT;
+use axum::http::{Request, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use tracing::debug;
+
+/// Rejects requests with expectations other than `100-continue` with `417 Expectation Failed`.
+///
+/// `100 Continue` is sent by the HTTP server once the body of the request is first read by the
+/// handler. Handlers therefore perform authorization and other checks before reading the body,
+/// such that rejected uploads are not transferred.
+pub(crate) async fn check<B>(req: Request<B>, next: Next<B>) -> Response {
+    if req
+        .headers()
+        .get_all(EXPECT)
+        .iter()
+        .any(|v| !v.as_bytes().eq_ignore_ascii_case(b"100-continue"))
+    {
+        debug!(target: "app::expect", "unsupported expectation");
+        return (StatusCode::EXPECTATION_FAILED, "Unsupported expectation").into_response();
+    }
+    next.run(req).await
+}
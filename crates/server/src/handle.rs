@@ -1,14 +1,19 @@
 // SPDX-FileCopyrightText: 2022 Profian Inc. <opensource@profian.com>
 // SPDX-License-Identifier: AGPL-3.0-only
 
-use super::{repos, tags, trees, users};
+use super::{auth, repos, tags, tokens, trees, uploads, users, TokenId, UploadId};
 
-use drawbridge_type::{RepositoryName, TagName, TreePath, UserName};
+use std::fmt::Display;
+
+use drawbridge_type::{
+    RepositoryContext, RepositoryName, TagContext, TagName, TreePath, UserContext, UserName,
+};
 
 use axum::body::Body;
 use axum::handler::Handler;
-use axum::http::{Method, Request, StatusCode};
-use axum::response::IntoResponse;
+use axum::http::header::ALLOW;
+use axum::http::{Extensions, HeaderValue, Method, Request, StatusCode};
+use axum::response::{IntoResponse, Response};
 use once_cell::sync::Lazy;
 use tower::Service;
 use tracing::trace;
@@ -23,140 +28,372 @@ pub(crate) static API_VERSION: Lazy<semver::Version> = Lazy::new(|| {
     })
 });
 
-/// Parses the URI of `req` and routes it to respective component.
-pub(crate) async fn handle(mut req: Request<Body>) -> impl IntoResponse {
-    #[inline]
-    fn not_found(path: &str) -> (StatusCode, String) {
-        (StatusCode::NOT_FOUND, format!("Route `/{path}` not found"))
+/// Request path parsed into the endpoint it is routed to and the names contained in it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Route {
+    pub(crate) endpoint: Endpoint,
+    pub(crate) user: UserName,
+    pub(crate) repository: Option<RepositoryName>,
+    pub(crate) tag: Option<TagName>,
+    pub(crate) path: Option<TreePath>,
+    pub(crate) upload: Option<UploadId>,
+    pub(crate) token: Option<TokenId>,
+}
+
+impl Route {
+    /// Parses the request `path`, returning the response to requests to it on failure.
+    pub(crate) fn parse(path: &str) -> Result<Self, (StatusCode, String)> {
+        #[inline]
+        fn not_found(path: &str) -> (StatusCode, String) {
+            (StatusCode::NOT_FOUND, format!("Route `/{path}` not found"))
+        }
+
+        #[inline]
+        fn bad_request<E: Display>(what: &str) -> impl FnOnce(E) -> (StatusCode, String) + '_ {
+            move |e| {
+                (
+                    StatusCode::BAD_REQUEST,
+                    format!("Failed to parse {what}: {e}"),
+                )
+            }
+        }
+
+        let path = path.trim_start_matches('/');
+        let (ver, path) = path
+            .strip_prefix("api")
+            .ok_or_else(|| not_found(path))?
+            .trim_start_matches('/')
+            .strip_prefix('v')
+            .ok_or_else(|| not_found(path))?
+            .split_once('/')
+            .ok_or_else(|| not_found(path))?;
+        let ver = ver.parse::<semver::Version>().map_err(|e| {
+            (
+                StatusCode::BAD_REQUEST,
+                format!("Failed to parse SemVer version from {path}: {e}"),
+            )
+        })?;
+        if ver > *API_VERSION
+            && (ver.major > API_VERSION.major
+                || API_VERSION.major == 0 && ver.minor > API_VERSION.minor)
+        {
+            return Err((
+                StatusCode::NOT_IMPLEMENTED,
+                format!("Unsupported API version `{ver}`"),
+            ));
+        }
+        let (head, tail) = path
+            .trim_start_matches('/')
+            .split_once("/_")
+            .map(|(left, right)| (left, format!("_{right}")))
+            .unwrap_or((path, "".into()));
+        if head.is_empty() {
+            return Err(not_found(path));
+        }
+
+        let (user, head) = head.split_once('/').unwrap_or((head, ""));
+        let mut route = Self {
+            endpoint: Endpoint::User,
+            user: user.parse().map_err(bad_request("user name"))?,
+            repository: None,
+            tag: None,
+            path: None,
+            upload: None,
+            token: None,
+        };
+        if head.is_empty() {
+            let mut tail = tail.splitn(2, '/');
+            route.endpoint = match (tail.next(), tail.next()) {
+                (None | Some(""), None) => Endpoint::User,
+                (Some("_tokens"), None | Some("")) => Endpoint::Tokens,
+                (Some("_tokens"), Some(id)) => {
+                    route.token = Some(id.parse().map_err(bad_request("token ID"))?);
+                    Endpoint::Token
+                }
+                _ => return Err((StatusCode::NOT_FOUND, "Route not found on user".into())),
+            };
+            return Ok(route);
+        }
+
+        route.repository = Some(head.parse().map_err(bad_request("repository name"))?);
+        let mut tail = tail.splitn(4, '/');
+        route.endpoint = match (tail.next(), tail.next(), tail.next()) {
+            (None | Some(""), None, None) => Endpoint::Repository,
+            (Some("_tag"), None, None) => Endpoint::TagQuery,
+            (Some("_changes"), None, None) => Endpoint::Changes,
+            (Some("_upload"), None | Some(""), None) => Endpoint::Uploads,
+            (Some("_upload"), Some(id), None) => {
+                let id = id.parse().map_err(bad_request("upload ID"))?;
+                route.upload = Some(UploadId(id));
+                Endpoint::Upload
+            }
+            (Some("_tag"), Some(tag), prop @ (None | Some("tree"))) => {
+                route.tag = Some(tag.parse().map_err(bad_request("tag name"))?);
+                if prop.is_none() {
+                    Endpoint::Tag
+                } else {
+                    let path = tail.next().unwrap_or("");
+                    route.path = Some(path.parse().map_err(bad_request("tree path"))?);
+                    Endpoint::Tree
+                }
+            }
+            _ => {
+                return Err((
+                    StatusCode::NOT_FOUND,
+                    "Route not found on repository".into(),
+                ))
+            }
+        };
+        Ok(route)
     }
 
-    trace!(target: "app::handle", "begin HTTP request handling {:?}", req);
-    let path = req.uri().path().trim_start_matches('/');
-    let (ver, path) = path
-        .strip_prefix("api")
-        .ok_or_else(|| not_found(path))?
-        .trim_start_matches('/')
-        .strip_prefix('v')
-        .ok_or_else(|| not_found(path))?
-        .split_once('/')
-        .ok_or_else(|| not_found(path))?;
-    let ver = ver.parse::<semver::Version>().map_err(|e| {
-        (
-            StatusCode::BAD_REQUEST,
-            format!("Failed to parse SemVer version from {path}: {e}"),
-        )
-    })?;
-    if ver > *API_VERSION
-        && (ver.major > API_VERSION.major
-            || API_VERSION.major == 0 && ver.minor > API_VERSION.minor)
-    {
-        return Err((
-            StatusCode::NOT_IMPLEMENTED,
-            format!("Unsupported API version `{ver}`"),
-        ));
+    /// Returns the context of the repository the route refers to, if any.
+    pub(crate) fn repository(&self) -> Option<RepositoryContext> {
+        Some(RepositoryContext {
+            owner: UserContext {
+                name: self.user.clone(),
+            },
+            name: self.repository.clone()?,
+        })
     }
-    let (head, tail) = path
-        .trim_start_matches('/')
-        .split_once("/_")
-        .map(|(left, right)| (left.to_string(), format!("_{right}")))
-        .unwrap_or((path.to_string(), "".into()));
-    if head.is_empty() {
-        return Err(not_found(path));
+
+    /// Returns the context of the tag the route refers to, if any.
+    pub(crate) fn tag(&self) -> Option<TagContext> {
+        Some(TagContext {
+            repository: self.repository()?,
+            name: self.tag.clone()?,
+        })
     }
 
-    let extensions = req.extensions_mut();
+    /// Inserts the names contained in the route into `extensions`, from which the handlers
+    /// extract them.
+    fn insert_into(self, extensions: &mut Extensions) {
+        trace!(target: "app::handle", "parsed user name: `{}`", self.user);
+        assert_eq!(extensions.insert(self.user), None, "duplicate user name");
+        if let Some(id) = self.token {
+            trace!(target: "app::handle", "parsed token ID: `{id}`");
+            assert_eq!(extensions.insert(id), None, "duplicate token ID");
+        }
+        if let Some(repo) = self.repository {
+            trace!(target: "app::handle", "parsed repository name: `{repo}`");
+            assert_eq!(extensions.insert(repo), None, "duplicate repository name");
+        }
+        if let Some(id) = self.upload {
+            trace!(target: "app::handle", "parsed upload ID: `{}`", id.0);
+            assert_eq!(extensions.insert(id), None, "duplicate upload ID");
+        }
+        if let Some(tag) = self.tag {
+            trace!(target: "app::handle", "parsed tag name: `{tag}`");
+            assert_eq!(extensions.insert(tag), None, "duplicate tag name");
+        }
+        if let Some(path) = self.path {
+            trace!(target: "app::handle", "parsed tree path: `{path}`");
+            assert_eq!(extensions.insert(path), None, "duplicate tree path");
+        }
+    }
+}
 
-    let (user, head) = head.split_once('/').unwrap_or((&head, ""));
-    let user = user.parse::<UserName>().map_err(|e| {
-        (
-            StatusCode::BAD_REQUEST,
-            format!("Failed to parse user name: {e}"),
-        )
-    })?;
-    trace!(target: "app::handle", "parsed user name: `{user}`");
-    assert_eq!(extensions.insert(user), None, "duplicate user name");
-    if head.is_empty() {
-        return match *req.method() {
-            Method::HEAD => Ok(users::head.into_service().call(req).await.into_response()),
-            Method::GET => Ok(users::get.into_service().call(req).await.into_response()),
-            Method::PUT => Ok(users::put.into_service().call(req).await.into_response()),
-            _ => Err((
-                StatusCode::METHOD_NOT_ALLOWED,
-                "Method not allowed for user endpoint".into(),
-            )),
-        };
+/// Kind of endpoint a request path is routed to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Endpoint {
+    User,
+    Tokens,
+    Token,
+    Repository,
+    TagQuery,
+    Changes,
+    Tag,
+    Tree,
+    Uploads,
+    Upload,
+}
+
+impl Endpoint {
+    /// Determines the endpoint `path` is routed to by [`handle`], if any.
+    pub(crate) fn of(path: &str) -> Option<Self> {
+        Route::parse(path).ok().map(|route| route.endpoint)
+    }
+
+    /// Returns the methods supported by the endpoint.
+    pub(crate) fn methods(self) -> &'static [Method] {
+        match self {
+            Self::User => &[Method::GET, Method::HEAD, Method::PUT],
+            Self::Tokens => &[Method::GET, Method::POST],
+            Self::Token => &[Method::DELETE],
+            Self::Repository => &[Method::DELETE, Method::GET, Method::HEAD, Method::PUT],
+            Self::TagQuery | Self::Changes => &[Method::GET],
+            Self::Tag => &[
+                Method::DELETE,
+                Method::GET,
+                Method::HEAD,
+                Method::POST,
+                Method::PUT,
+            ],
+            Self::Tree => &[Method::GET, Method::HEAD, Method::POST, Method::PUT],
+            Self::Uploads => &[Method::POST],
+            Self::Upload => &[
+                Method::DELETE,
+                Method::GET,
+                Method::HEAD,
+                Method::PATCH,
+                Method::PUT,
+            ],
+        }
     }
 
-    let repo = head.parse::<RepositoryName>().map_err(|e| {
+    /// Returns the response to a request using a method not supported by the endpoint.
+    fn method_not_allowed(self) -> Response {
+        let name = match self {
+            Self::User => "user",
+            Self::Tokens => "access token",
+            Self::Token => "access token revocation",
+            Self::Repository => "repository",
+            Self::TagQuery => "repository tag query",
+            Self::Changes => "repository change log",
+            Self::Tag => "tag",
+            Self::Tree => "tag tree",
+            Self::Uploads => "upload",
+            Self::Upload => "upload session",
+        };
         (
-            StatusCode::BAD_REQUEST,
-            format!("Failed to parse repository name: {e}"),
+            StatusCode::METHOD_NOT_ALLOWED,
+            [(ALLOW, allow_header(self.methods()))],
+            format!("Method not allowed for {name} endpoint"),
         )
-    })?;
-    trace!(target: "app::handle", "parsed repository name: `{repo}`");
-    assert_eq!(extensions.insert(repo), None, "duplicate repository name");
-
-    let mut tail = tail.splitn(4, '/');
-    match (tail.next(), tail.next(), tail.next()) {
-        (None | Some(""), None, None) => match *req.method() {
-            Method::HEAD => Ok(repos::head.into_service().call(req).await.into_response()),
-            Method::GET => Ok(repos::get.into_service().call(req).await.into_response()),
-            Method::PUT => Ok(repos::put.into_service().call(req).await.into_response()),
-            _ => Err((
-                StatusCode::METHOD_NOT_ALLOWED,
-                "Method not allowed for repository endpoint".into(),
-            )),
-        },
-        (Some("_tag"), None, None) => match *req.method() {
-            Method::GET => Ok(tags::query.into_service().call(req).await.into_response()),
-            _ => Err((
-                StatusCode::METHOD_NOT_ALLOWED,
-                "Method not allowed for repository tag query endpoint".into(),
-            )),
-        },
-        (Some("_tag"), Some(tag), prop @ (None | Some("tree"))) => {
-            let tag = tag.parse::<TagName>().map_err(|e| {
-                (
-                    StatusCode::BAD_REQUEST,
-                    format!("Failed to parse tag name: {e}"),
-                )
-            })?;
-            trace!(target: "app::handle", "parsed tag name: `{tag}`");
-            assert_eq!(extensions.insert(tag), None, "duplicate tag name");
+            .into_response()
+    }
+}
 
-            if prop.is_none() {
-                return match *req.method() {
-                    Method::HEAD => Ok(tags::head.into_service().call(req).await.into_response()),
-                    Method::GET => Ok(tags::get.into_service().call(req).await.into_response()),
-                    Method::PUT => Ok(tags::put.into_service().call(req).await.into_response()),
-                    _ => Err((
-                        StatusCode::METHOD_NOT_ALLOWED,
-                        "Method not allowed for tag endpoint".into(),
-                    )),
-                };
-            }
+/// Returns the value of an `Allow` header listing `methods`.
+pub(crate) fn allow_header<'a>(methods: impl IntoIterator<Item = &'a Method>) -> HeaderValue {
+    let methods: Vec<_> = methods.into_iter().map(Method::as_str).collect();
+    HeaderValue::from_str(&methods.join(", ")).expect("methods are valid header values")
+}
 
-            let path = tail.next().unwrap_or("").parse::<TreePath>().map_err(|e| {
-                (
-                    StatusCode::BAD_REQUEST,
-                    format!("Failed to parse tree path: {e}"),
-                )
-            })?;
-            trace!(target: "app::handle", "parsed tree path: `{path}`");
-            assert_eq!(extensions.insert(path), None, "duplicate tree path");
-            match *req.method() {
-                Method::HEAD => Ok(trees::head.into_service().call(req).await.into_response()),
-                Method::GET => Ok(trees::get.into_service().call(req).await.into_response()),
-                Method::PUT => Ok(trees::put.into_service().call(req).await.into_response()),
-                _ => Err((
-                    StatusCode::METHOD_NOT_ALLOWED,
-                    "Method not allowed for tag tree endpoint".into(),
-                )),
-            }
-        }
-        _ => Err((
-            StatusCode::NOT_FOUND,
-            "Route not found on repository".into(),
-        )),
+/// Calls `handler` with `req`.
+async fn call<H: Handler<T, Body>, T: 'static>(handler: H, req: Request<Body>) -> Response {
+    handler.into_service().call(req).await.into_response()
+}
+
+/// Parses the URI of `req` and routes it to respective component.
+pub(crate) async fn handle(mut req: Request<Body>) -> impl IntoResponse {
+    trace!(target: "app::handle", "begin HTTP request handling {:?}", req);
+    let route = Route::parse(req.uri().path())?;
+    let endpoint = route.endpoint;
+    route.insert_into(req.extensions_mut());
+    Ok::<_, (StatusCode, String)>(match (endpoint, req.method().clone()) {
+        (Endpoint::User, Method::HEAD) => call(users::head, req).await,
+        (Endpoint::User, Method::GET) => call(users::get, req).await,
+        (Endpoint::User, Method::PUT) => call(users::put, req).await,
+        (Endpoint::Tokens, Method::GET) => call(tokens::list, req).await,
+        (Endpoint::Tokens, Method::POST) => call(tokens::create, req).await,
+        (Endpoint::Token, Method::DELETE) => call(tokens::delete, req).await,
+        (Endpoint::Repository, Method::HEAD) => call(repos::head, req).await,
+        (Endpoint::Repository, Method::GET) => call(repos::get, req).await,
+        (Endpoint::Repository, Method::PUT) => call(repos::put, req).await,
+        (Endpoint::Repository, Method::DELETE) => call(repos::delete, req).await,
+        (Endpoint::TagQuery, Method::GET) => call(tags::query, req).await,
+        (Endpoint::Changes, Method::GET) => call(repos::changes, req).await,
+        (Endpoint::Uploads, Method::POST) => call(uploads::create, req).await,
+        (Endpoint::Upload, Method::HEAD | Method::GET) => call(uploads::status, req).await,
+        (Endpoint::Upload, Method::PATCH) => call(uploads::patch, req).await,
+        (Endpoint::Upload, Method::PUT) => call(uploads::put, req).await,
+        (Endpoint::Upload, Method::DELETE) => call(uploads::delete, req).await,
+        (Endpoint::Tag, Method::HEAD) => call(tags::head, req).await,
+        (Endpoint::Tag, Method::GET) => call(tags::get, req).await,
+        (Endpoint::Tag, Method::PUT) => call(tags::put, req).await,
+        (Endpoint::Tag, Method::DELETE) => call(tags::delete, req).await,
+        (Endpoint::Tree, Method::HEAD) => call(trees::head, req).await,
+        (Endpoint::Tree, Method::GET) => call(trees::get, req).await,
+        (Endpoint::Tree, Method::PUT) => call(trees::put, req).await,
+        (Endpoint::Tag | Endpoint::Tree, Method::POST) => call(auth::sign_url, req).await,
+        (endpoint, _) => endpoint.method_not_allowed(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn endpoint() {
+        assert_eq!(Endpoint::of("/api/v0.1.0/user"), Some(Endpoint::User));
+        assert_eq!(Endpoint::of("/api/v0.1.0/user/"), Some(Endpoint::User));
+        assert_eq!(
+            Endpoint::of("/api/v0.1.0/user/_tokens"),
+            Some(Endpoint::Tokens)
+        );
+        assert_eq!(
+            Endpoint::of("/api/v0.1.0/user/_tokens/0123456789abcdef0123456789abcdef"),
+            Some(Endpoint::Token)
+        );
+        assert_eq!(Endpoint::of("/api/v0.1.0/user/_foo"), None);
+        assert_eq!(
+            Endpoint::of("/api/v0.1.0/user/repo"),
+            Some(Endpoint::Repository)
+        );
+        assert_eq!(
+            Endpoint::of("/api/v0.1.0/user/repo/_tag"),
+            Some(Endpoint::TagQuery)
+        );
+        assert_eq!(
+            Endpoint::of("/api/v0.1.0/user/repo/_changes"),
+            Some(Endpoint::Changes)
+        );
+        assert_eq!(
+            Endpoint::of("/api/v0.1.0/user/repo/_tag/0.1.0"),
+            Some(Endpoint::Tag)
+        );
+        assert_eq!(
+            Endpoint::of("/api/v0.1.0/user/repo/_tag/0.1.0/tree"),
+            Some(Endpoint::Tree)
+        );
+        assert_eq!(
+            Endpoint::of("/api/v0.1.0/user/repo/_tag/0.1.0/tree/a/b"),
+            Some(Endpoint::Tree)
+        );
+        assert_eq!(
+            Endpoint::of("/api/v0.1.0/user/repo/_upload"),
+            Some(Endpoint::Uploads)
+        );
+        assert_eq!(
+            Endpoint::of("/api/v0.1.0/user/repo/_upload/67e55044-10b1-426f-9247-bb680e5fe0c8"),
+            Some(Endpoint::Upload)
+        );
+        assert_eq!(Endpoint::of("/api/v0.1.0/user/repo/_foo"), None);
+        assert_eq!(Endpoint::of("/api/v0.1.0/"), None);
+        assert_eq!(Endpoint::of("/health"), None);
+
+        assert_eq!(Endpoint::of("/api/v0.1.0/user/re\"po"), None);
+
+        let res = Endpoint::Tag.method_not_allowed();
+        assert_eq!(res.status(), StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(res.headers()[ALLOW], "DELETE, GET, HEAD, POST, PUT");
+        let res = Endpoint::Tree.method_not_allowed();
+        assert_eq!(res.headers()[ALLOW], "GET, HEAD, POST, PUT");
+    }
+
+    #[test]
+    fn route() {
+        let route = Route::parse("/api/v0.1.0/user/repo/_tag/0.1.0/tree/a/b").unwrap();
+        assert_eq!(route.endpoint, Endpoint::Tree);
+        assert_eq!(route.user, "user".parse().unwrap());
+        assert_eq!(
+            route.tag(),
+            Some(TagContext::try_from(("user", "repo", "0.1.0")).unwrap())
+        );
+        assert_eq!(route.path, Some("a/b".parse().unwrap()));
+        assert_eq!(route.upload, None);
+
+        let route = Route::parse("/api/v0.1.0/user/_tokens").unwrap();
+        assert_eq!(route.endpoint, Endpoint::Tokens);
+        assert_eq!(route.repository(), None);
+
+        let (status, _) = Route::parse("/api/v0.1.0/user/repo/_tag/invalid").unwrap_err();
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let (status, _) = Route::parse("/api/v0.1.0/user/repo/_foo").unwrap_err();
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let (status, _) = Route::parse("/api/v1000.0.0/user").unwrap_err();
+        assert_eq!(status, StatusCode::NOT_IMPLEMENTED);
     }
 }
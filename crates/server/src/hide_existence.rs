@@ -0,0 +1,23 @@
+// SPDX-FileCopyrightText: 2022 Profian Inc. <opensource@profian.com>
+// SPDX-License-Identifier: AGPL-3.0-only
+
+use super::GetError;
+
+use axum::http::{Request, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use tracing::debug;
+
+/// Replaces `401 Unauthorized` and `403 Forbidden` responses by the `404 Not Found` response
+/// returned for nonexistent resources, such that clients cannot tell whether a resource they
+/// are not authorized to access exists.
+pub(crate) async fn conceal<B>(req: Request<B>, next: Next<B>) -> Response {
+    let res = next.run(req).await;
+    match res.status() {
+        StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => {
+            debug!(target: "app::hide_existence", "conceal `{}` response", res.status());
+            GetError::<()>::NotFound.into_response()
+        }
+        _ => res,
+    }
+}
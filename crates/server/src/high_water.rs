@@ -0,0 +1,128 @@
+// SPDX-FileCopyrightText: 2022 Profian Inc. <opensource@profian.com>
+// SPDX-License-Identifier: AGPL-3.0-only
+
+use super::Metrics;
+
+use std::fmt;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+use tracing::warn;
+
+/// Default percentage of a limit, above which usage is considered to be near the limit.
+pub const DEFAULT_LIMIT_WARNING_PERCENT: u8 = 80;
+
+/// Minimum interval between warnings about usage near the same limit.
+const WARNING_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Limit of concurrently used resources, which usage may approach.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ResourceLimit {
+    /// [Builder::read_slots](crate::Builder::read_slots).
+    ReadSlots,
+    /// [Builder::write_slots](crate::Builder::write_slots).
+    WriteSlots,
+    /// [Builder::max_inflight_bytes](crate::Builder::max_inflight_bytes).
+    InflightBytes,
+}
+
+impl ResourceLimit {
+    /// All limits.
+    pub const ALL: [Self; 3] = [Self::ReadSlots, Self::WriteSlots, Self::InflightBytes];
+}
+
+impl fmt::Display for ResourceLimit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ReadSlots => write!(f, "read-slots"),
+            Self::WriteSlots => write!(f, "write-slots"),
+            Self::InflightBytes => write!(f, "inflight-bytes"),
+        }
+    }
+}
+
+/// High-water mark of a [ResourceLimit], above which usage is recorded in the [Metrics] and
+/// warned about at most once per [WARNING_INTERVAL].
+#[derive(Debug)]
+pub(crate) struct HighWater {
+    limit: ResourceLimit,
+    capacity: u64,
+    /// Usage, at and above which usage is near the limit, or `None` if never considered so.
+    mark: Option<u64>,
+    warned: Mutex<Option<Instant>>,
+    metrics: Arc<Metrics>,
+}
+
+impl HighWater {
+    /// Constructs a new [HighWater] mark at `percent` of the `capacity` of `limit`. A `percent`
+    /// of `0` disables the mark.
+    pub(crate) fn new(
+        limit: ResourceLimit,
+        capacity: u64,
+        percent: u8,
+        metrics: Arc<Metrics>,
+    ) -> Self {
+        // Rounded up, such that usage is never near a limit, which is far from being reached.
+        let mark = (percent > 0)
+            .then(|| (u128::from(capacity) * u128::from(percent)).div_ceil(100))
+            .map(|mark| u64::try_from(mark).unwrap_or(u64::MAX).max(1));
+        Self {
+            limit,
+            capacity,
+            mark,
+            warned: Mutex::new(None),
+            metrics,
+        }
+    }
+
+    /// Records `usage` of the limit and returns whether it is near the limit.
+    pub(crate) fn observe(&self, usage: u64) -> bool {
+        if self.mark.is_none_or(|mark| usage < mark) {
+            return false;
+        }
+        self.metrics.record_near_limit(self.limit);
+        let now = Instant::now();
+        let mut warned = self.warned.lock().unwrap_or_else(PoisonError::into_inner);
+        if warned.is_none_or(|warned| now.duration_since(warned) >= WARNING_INTERVAL) {
+            *warned = Some(now);
+            warn!(
+                target: "app::high_water",
+                limit = %self.limit,
+                usage,
+                capacity = self.capacity,
+                "usage is near the {} limit of {}",
+                self.limit,
+                self.capacity,
+            );
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn observe() {
+        let metrics = Arc::new(Metrics::default());
+        let mark = HighWater::new(ResourceLimit::ReadSlots, 10, 80, Arc::clone(&metrics));
+        assert!(!mark.observe(7));
+        assert!(mark.observe(8));
+        assert!(mark.observe(10));
+        assert_eq!(metrics.near_limit(ResourceLimit::ReadSlots), 2);
+        assert_eq!(metrics.near_limit(ResourceLimit::WriteSlots), 0);
+
+        // Marks are rounded up and never zero.
+        let mark = HighWater::new(ResourceLimit::WriteSlots, 3, 50, Arc::clone(&metrics));
+        assert!(!mark.observe(1));
+        assert!(mark.observe(2));
+        let mark = HighWater::new(ResourceLimit::WriteSlots, 1, 1, Arc::clone(&metrics));
+        assert!(!mark.observe(0));
+        assert!(mark.observe(1));
+
+        let mark = HighWater::new(ResourceLimit::InflightBytes, 100, 0, Arc::clone(&metrics));
+        assert!(!mark.observe(100));
+        assert_eq!(metrics.near_limit(ResourceLimit::InflightBytes), 0);
+    }
+}
@@ -0,0 +1,99 @@
+// SPDX-FileCopyrightText: 2022 Profian Inc. <opensource@profian.com>
+// SPDX-License-Identifier: AGPL-3.0-only
+
+use std::time::Duration;
+
+use anyhow::{bail, Context};
+use axum::http::HeaderValue;
+
+/// Default duration, for which clients are instructed to only connect using HTTPS.
+pub const DEFAULT_HSTS_MAX_AGE: Duration = Duration::from_secs(365 * 24 * 60 * 60);
+
+/// Minimum `max-age` required by browsers' HSTS preload lists.
+const PRELOAD_MIN_MAX_AGE: Duration = DEFAULT_HSTS_MAX_AGE;
+
+/// HTTP Strict Transport Security policy sent in the `Strict-Transport-Security` header of
+/// responses as defined by RFC 6797.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Hsts {
+    /// Duration, for which clients only connect to the host using HTTPS.
+    pub max_age: Duration,
+    /// Whether the policy also applies to all subdomains of the host.
+    pub include_subdomains: bool,
+    /// Whether the host consents to being included in browsers' HSTS preload lists, which
+    /// requires [Hsts::include_subdomains] and a [Hsts::max_age] of at least a year.
+    pub preload: bool,
+}
+
+impl Default for Hsts {
+    fn default() -> Self {
+        Self {
+            max_age: DEFAULT_HSTS_MAX_AGE,
+            include_subdomains: false,
+            preload: false,
+        }
+    }
+}
+
+impl Hsts {
+    /// Returns the value of the `Strict-Transport-Security` header expressing the policy.
+    pub(crate) fn header_value(&self) -> anyhow::Result<HeaderValue> {
+        if self.preload && !(self.include_subdomains && self.max_age >= PRELOAD_MIN_MAX_AGE) {
+            bail!(
+                "HSTS preloading requires including subdomains and a max-age of at least {} seconds",
+                PRELOAD_MIN_MAX_AGE.as_secs()
+            );
+        }
+        let mut value = format!("max-age={}", self.max_age.as_secs());
+        if self.include_subdomains {
+            value.push_str("; includeSubDomains");
+        }
+        if self.preload {
+            value.push_str("; preload");
+        }
+        HeaderValue::from_str(&value).context("invalid `Strict-Transport-Security` header value")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn header_value() {
+        assert_eq!(Hsts::default().header_value().unwrap(), "max-age=31536000");
+        assert_eq!(
+            Hsts {
+                max_age: Duration::ZERO,
+                include_subdomains: true,
+                preload: false,
+            }
+            .header_value()
+            .unwrap(),
+            "max-age=0; includeSubDomains"
+        );
+        assert_eq!(
+            Hsts {
+                max_age: Duration::from_secs(2 * 365 * 24 * 60 * 60),
+                include_subdomains: true,
+                preload: true,
+            }
+            .header_value()
+            .unwrap(),
+            "max-age=63072000; includeSubDomains; preload"
+        );
+        assert!(Hsts {
+            preload: true,
+            ..Default::default()
+        }
+        .header_value()
+        .is_err());
+        assert!(Hsts {
+            max_age: Duration::from_secs(60),
+            include_subdomains: true,
+            preload: true,
+        }
+        .header_value()
+        .is_err());
+    }
+}
@@ -0,0 +1,467 @@
+// SPDX-FileCopyrightText: 2022 Profian Inc. <opensource@profian.com>
+// SPDX-License-Identifier: AGPL-3.0-only
+
+use super::auth::{CertificateWriter, OidcVerifier, Subject};
+use super::{tokens, Route, Store};
+
+use std::collections::HashSet;
+use std::io;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use axum::body::{boxed, Full};
+use axum::http::header::{HeaderName, AUTHORIZATION};
+use axum::http::{Extensions, HeaderMap, HeaderValue, Method, Request, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use cap_async_std::fs_utf8::Dir;
+use drawbridge_type::UserContext;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tracing::{debug, warn};
+use uuid::Uuid;
+
+/// Default duration, for which the response to a request carrying an `Idempotency-Key` header
+/// is replayed to retries.
+pub const DEFAULT_IDEMPOTENCY_KEY_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Header identifying a mutating request, such that retries are not applied again.
+const IDEMPOTENCY_KEY: HeaderName = HeaderName::from_static("idempotency-key");
+
+/// Header set on responses replayed to retries.
+const IDEMPOTENT_REPLAYED: HeaderName = HeaderName::from_static("idempotent-replayed");
+
+/// Maximum length of an idempotency key.
+const MAX_KEY_LEN: usize = 255;
+
+/// Maximum size of a recorded response body. Responses with larger bodies are not recorded.
+const MAX_RECORDED_BODY: usize = 64 * 1024;
+
+/// Age, after which temporary files of interrupted writes of records are removed, such that
+/// records being written concurrently are left untouched.
+const TEMPORARY_MAX_AGE: Duration = Duration::from_secs(60);
+
+/// Header naming the content digest of a request, which identifies its body.
+const CONTENT_DIGEST: &str = "content-digest";
+
+/// Response to a request carrying an idempotency key, which is stored as JSON.
+#[derive(Debug, Deserialize, Serialize)]
+struct Record {
+    /// Seconds since the Unix epoch, at which the response was recorded.
+    created: u64,
+    /// Method, path and content digest of the request.
+    request: String,
+    status: u16,
+    headers: Vec<(String, String)>,
+    body: String,
+}
+
+impl Record {
+    fn is_expired(&self, ttl: Duration, now: SystemTime) -> bool {
+        UNIX_EPOCH + Duration::from_secs(self.created) + ttl <= now
+    }
+
+    fn into_response(self) -> Response {
+        let mut res = Response::new(boxed(Full::from(self.body)));
+        *res.status_mut() = StatusCode::from_u16(self.status).unwrap_or(StatusCode::OK);
+        let headers = res.headers_mut();
+        for (name, value) in self.headers {
+            if let (Ok(name), Ok(value)) =
+                (HeaderName::try_from(name), HeaderValue::try_from(value))
+            {
+                _ = headers.append(name, value);
+            }
+        }
+        _ = headers.insert(IDEMPOTENT_REPLAYED, HeaderValue::from_static("true"));
+        res
+    }
+}
+
+/// Registry of responses to mutating requests carrying an idempotency key, which are stored in
+/// a dedicated directory of the store, such that they are replayed across restarts.
+#[derive(Debug)]
+pub(crate) struct Idempotency {
+    dir: Dir,
+    ttl: Duration,
+    /// Identifiers of records of requests being handled.
+    in_flight: Mutex<HashSet<String>>,
+}
+
+/// Marker of a request being handled, which is removed from [Idempotency::in_flight] on drop.
+struct InFlight<'a> {
+    idempotency: &'a Idempotency,
+    id: String,
+}
+
+impl Drop for InFlight<'_> {
+    fn drop(&mut self) {
+        _ = self
+            .idempotency
+            .in_flight
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(&self.id);
+    }
+}
+
+impl Idempotency {
+    /// Constructs a new [Idempotency] storing records in `dir`, which are replayed for `ttl`.
+    pub(crate) fn new(dir: Dir, ttl: Duration) -> Self {
+        Self {
+            dir,
+            ttl,
+            in_flight: Default::default(),
+        }
+    }
+
+    /// Removes expired records and returns the number of removed records.
+    ///
+    /// Temporary files are only removed once they were not modified for a while, such that
+    /// this may be called while requests are handled.
+    pub(crate) async fn expire(&self) -> io::Result<usize> {
+        let now = SystemTime::now();
+        let mut expired = vec![];
+        for entry in self.dir.entries().await? {
+            let entry = entry?;
+            let name = entry.file_name()?;
+            // Temporary files are left over by interrupted writes.
+            if name.starts_with('.') {
+                let age = entry
+                    .metadata()
+                    .and_then(|meta| meta.modified())
+                    .ok()
+                    .and_then(|modified| now.duration_since(modified.into_std()).ok());
+                if age.is_some_and(|age| age >= TEMPORARY_MAX_AGE) {
+                    expired.push(name);
+                }
+                continue;
+            }
+            match self.load(&name).await {
+                Some(record) if !record.is_expired(self.ttl, now) => {}
+                _ => expired.push(name),
+            }
+        }
+        for name in &expired {
+            self.remove(name).await;
+        }
+        Ok(expired.len())
+    }
+
+    async fn load(&self, id: &str) -> Option<Record> {
+        let buf = match self.dir.read(id).await {
+            Ok(buf) => buf,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return None,
+            Err(e) => {
+                warn!(target: "app::idempotency", "failed to read idempotency record `{id}`: {e}");
+                return None;
+            }
+        };
+        serde_json::from_slice(&buf)
+            .map_err(|e| {
+                warn!(target: "app::idempotency", "failed to decode idempotency record `{id}`: {e}")
+            })
+            .ok()
+    }
+
+    /// Stores `record` as `id`, such that concurrent readers never observe partial records.
+    async fn save(&self, id: &str, record: &Record) {
+        let res = async {
+            let buf = serde_json::to_vec(record)?;
+            let tmp = format!(".record-{}", Uuid::new_v4());
+            self.dir.write(&tmp, buf).await?;
+            if let Err(e) = self.dir.rename(&tmp, &self.dir, id).await {
+                _ = self.dir.remove_file(&tmp).await;
+                return Err(e.into());
+            }
+            Ok::<_, anyhow::Error>(())
+        }
+        .await;
+        if let Err(e) = res {
+            warn!(target: "app::idempotency", "failed to store idempotency record `{id}`: {e}");
+        }
+    }
+
+    async fn remove(&self, id: &str) {
+        match self.dir.remove_file(id).await {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => {
+                warn!(target: "app::idempotency", "failed to remove idempotency record `{id}`: {e}")
+            }
+        }
+    }
+
+    /// Marks the request recorded as `id` as being handled, unless it already is.
+    fn begin(&self, id: String) -> Option<InFlight<'_>> {
+        let inserted = self
+            .in_flight
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(id.clone());
+        inserted.then(|| InFlight {
+            idempotency: self,
+            id,
+        })
+    }
+}
+
+/// Returns the identifier of the record of requests carrying `key` sent by `subject`, such
+/// that keys of distinct subjects never collide.
+fn record_id(subject: &Subject, key: &str) -> String {
+    let (kind, id) = match subject {
+        Subject::Oidc(id) => ("oidc", id),
+        Subject::Certificate(fingerprint) => ("certificate", fingerprint),
+        Subject::Token(id) => ("token", id),
+    };
+    let mut hasher = Sha256::new();
+    hasher.update(kind.as_bytes());
+    hasher.update([0]);
+    hasher.update(id.as_bytes());
+    hasher.update([0]);
+    hasher.update(key.as_bytes());
+    format!("{:x}", hasher.finalize())
+}
+
+/// Returns the authenticated subject of a request to `path` with `headers` and `extensions`.
+///
+/// Access tokens are verified, such that only the holder of a token can obtain responses
+/// recorded for it.
+async fn subject(headers: &HeaderMap, extensions: &Extensions, path: &str) -> Option<Subject> {
+    let Some(token) = headers.get(AUTHORIZATION) else {
+        // Client certificates only authenticate requests without tokens.
+        return extensions
+            .get::<CertificateWriter>()
+            .map(|writer| Subject::certificate(&writer.cert));
+    };
+    let token = token.to_str().ok()?.strip_prefix("Bearer ")?.trim();
+    if !tokens::is_access_token(token) {
+        return extensions
+            .get::<Arc<OidcVerifier>>()?
+            .subject(token)
+            .map(Subject::Oidc);
+    }
+    // Access tokens are only valid for the user the request is routed to.
+    let owner = UserContext {
+        name: Route::parse(path).ok()?.user,
+    };
+    let store = extensions.get::<Arc<Store>>()?;
+    let token = tokens::verify(&store.user(&owner), token, SystemTime::now())
+        .await
+        .ok()?;
+    Some(Subject::Token(token.id.to_string()))
+}
+
+/// Returns whether responses with `status` are final, i.e. retrying the request would not
+/// change the outcome.
+///
+/// Only successful responses and client errors caused by the request itself are final.
+/// Others, e.g. authentication failures, conflicts or exceeded quotas, may well be resolved
+/// by the time the request is retried.
+fn is_final(status: StatusCode) -> bool {
+    status.is_success()
+        || matches!(
+            status,
+            StatusCode::BAD_REQUEST
+                | StatusCode::NOT_FOUND
+                | StatusCode::METHOD_NOT_ALLOWED
+                | StatusCode::PAYLOAD_TOO_LARGE
+                | StatusCode::UNSUPPORTED_MEDIA_TYPE
+                | StatusCode::UNPROCESSABLE_ENTITY
+        )
+}
+
+/// Replays the recorded response to mutating requests carrying an `Idempotency-Key` header,
+/// which was already used by the authenticated subject, and records the response otherwise.
+///
+/// Responses, which are not final, e.g. server errors, are not recorded, such that requests
+/// failing due to them can be retried. Keys may not be reused for different requests.
+pub(crate) async fn replay<B: Send>(
+    idempotency: Arc<Idempotency>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    if matches!(*req.method(), Method::GET | Method::HEAD | Method::OPTIONS) {
+        return next.run(req).await;
+    }
+    let key = match req.headers().get(IDEMPOTENCY_KEY) {
+        None => return next.run(req).await,
+        Some(key) => match key.to_str() {
+            Ok(key) if !key.is_empty() && key.len() <= MAX_KEY_LEN => key.to_string(),
+            _ => {
+                return (
+                    StatusCode::BAD_REQUEST,
+                    format!(
+                    "`Idempotency-Key` must consist of 1 to {MAX_KEY_LEN} visible ASCII characters"
+                ),
+                )
+                    .into_response()
+            }
+        },
+    };
+
+    // Requests, which are not authenticated, are rejected by their handlers as usual.
+    let subject = subject(req.headers(), req.extensions(), req.uri().path()).await;
+    let Some(subject) = subject else {
+        return next.run(req).await;
+    };
+
+    let id = record_id(&subject, &key);
+    let request = format!(
+        "{} {} {}",
+        req.method(),
+        req.uri()
+            .path_and_query()
+            .map(|path| path.as_str())
+            .unwrap_or_default(),
+        req.headers()
+            .get(CONTENT_DIGEST)
+            .and_then(|digest| digest.to_str().ok())
+            .unwrap_or_default()
+    );
+    let Some(_in_flight) = idempotency.begin(id.clone()) else {
+        return (
+            StatusCode::CONFLICT,
+            "A request with the same `Idempotency-Key` is being handled",
+        )
+            .into_response();
+    };
+    match idempotency.load(&id).await {
+        Some(record) if record.is_expired(idempotency.ttl, SystemTime::now()) => {
+            idempotency.remove(&id).await
+        }
+        Some(record) if record.request != request => {
+            return (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "`Idempotency-Key` was already used for a different request",
+            )
+                .into_response()
+        }
+        Some(record) => {
+            debug!(target: "app::idempotency", "replay response to `{request}`");
+            return record.into_response();
+        }
+        None => {}
+    }
+
+    let res = next.run(req).await;
+    if !is_final(res.status()) {
+        return res;
+    }
+    let (parts, body) = res.into_parts();
+    let body = match hyper::body::to_bytes(body).await {
+        Ok(body) => body,
+        Err(e) => {
+            warn!(target: "app::idempotency", "failed to read response body: {e}");
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+    match std::str::from_utf8(&body) {
+        Ok(text) if body.len() <= MAX_RECORDED_BODY => {
+            let created = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs();
+            let headers = parts
+                .headers
+                .iter()
+                .filter_map(|(name, value)| {
+                    value
+                        .to_str()
+                        .ok()
+                        .map(|value| (name.to_string(), value.to_string()))
+                })
+                .collect();
+            let record = Record {
+                created,
+                request,
+                status: parts.status.as_u16(),
+                headers,
+                body: text.into(),
+            };
+            idempotency.save(&id, &record).await;
+        }
+        _ => debug!(target: "app::idempotency", "response to `{request}` is not recorded"),
+    }
+    Response::from_parts(parts, boxed(Full::from(body)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use async_std::task::block_on;
+    use cap_async_std::ambient_authority;
+
+    #[test]
+    fn persist() {
+        block_on(async {
+            let tmp = tempfile::tempdir().unwrap();
+            let open = || async {
+                Dir::open_ambient_dir(tmp.path().to_str().unwrap(), ambient_authority())
+                    .await
+                    .unwrap()
+            };
+            let record = |created| Record {
+                created,
+                request: "PUT /test".into(),
+                status: 201,
+                headers: vec![("location".into(), "/test".into())],
+                body: "created".into(),
+            };
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_secs();
+
+            let idempotency = Idempotency::new(open().await, DEFAULT_IDEMPOTENCY_KEY_TTL);
+            let (current, expired) = (
+                record_id(&Subject::Oidc("a".into()), "key"),
+                record_id(&Subject::Oidc("b".into()), "key"),
+            );
+            assert_ne!(current, expired);
+            assert_ne!(current, record_id(&Subject::Token("a".into()), "key"));
+            idempotency.save(&current, &record(now)).await;
+            idempotency.save(&expired, &record(0)).await;
+            let in_flight = idempotency.begin(current.clone());
+            assert!(in_flight.is_some());
+            assert!(idempotency.begin(current.clone()).is_none());
+            drop(in_flight);
+            assert!(idempotency.begin(current.clone()).is_some());
+
+            // Records are retained by other instances using the same directory.
+            let idempotency = Idempotency::new(open().await, DEFAULT_IDEMPOTENCY_KEY_TTL);
+            assert_eq!(idempotency.expire().await.unwrap(), 1);
+            assert!(idempotency.load(&expired).await.is_none());
+            let res = idempotency.load(&current).await.unwrap().into_response();
+            assert_eq!(res.status(), StatusCode::CREATED);
+            assert_eq!(res.headers()["location"], "/test");
+            assert_eq!(res.headers()[IDEMPOTENT_REPLAYED], "true");
+        })
+    }
+
+    #[test]
+    fn final_status() {
+        for status in [
+            StatusCode::OK,
+            StatusCode::CREATED,
+            StatusCode::NO_CONTENT,
+            StatusCode::BAD_REQUEST,
+            StatusCode::NOT_FOUND,
+            StatusCode::PAYLOAD_TOO_LARGE,
+            StatusCode::UNPROCESSABLE_ENTITY,
+        ] {
+            assert!(is_final(status), "{status}");
+        }
+        for status in [
+            StatusCode::UNAUTHORIZED,
+            StatusCode::FORBIDDEN,
+            StatusCode::CONFLICT,
+            StatusCode::PRECONDITION_FAILED,
+            StatusCode::TOO_MANY_REQUESTS,
+            StatusCode::INTERNAL_SERVER_ERROR,
+            StatusCode::SERVICE_UNAVAILABLE,
+        ] {
+            assert!(!is_final(status), "{status}");
+        }
+    }
+}
@@ -0,0 +1,227 @@
+// SPDX-FileCopyrightText: 2022 Profian Inc. <opensource@profian.com>
+// SPDX-License-Identifier: AGPL-3.0-only
+
+use super::high_water::{HighWater, ResourceLimit};
+use super::Metrics;
+
+use std::pin::Pin;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use axum::body::{boxed, BoxBody, Bytes};
+use axum::http::header::{CONTENT_LENGTH, RETRY_AFTER};
+use axum::http::{HeaderMap, Method, Request, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use hyper::body::{HttpBody, SizeHint};
+use tracing::info;
+
+/// Number of seconds, after which clients are asked to retry requests rejected for exceeding
+/// the budget.
+const RETRY_AFTER_SECS: u64 = 1;
+
+/// Budget of bytes, which may be transferred in request and response bodies at once.
+///
+/// Bodies are accounted for by their declared `Content-Length` from the start of a request
+/// until the body is completely transferred. The number of bytes in flight is exposed by
+/// [Metrics::inflight_bytes].
+#[derive(Debug)]
+pub(crate) struct InflightBytes {
+    budget: u64,
+    high_water: HighWater,
+    metrics: Arc<Metrics>,
+}
+
+impl InflightBytes {
+    /// Constructs a new [InflightBytes] budget, usage of at least `warning_percent` of which is
+    /// recorded in `metrics`.
+    pub(crate) fn new(budget: u64, warning_percent: u8, metrics: Arc<Metrics>) -> Self {
+        Self {
+            budget,
+            high_water: HighWater::new(
+                ResourceLimit::InflightBytes,
+                budget,
+                warning_percent,
+                Arc::clone(&metrics),
+            ),
+            metrics,
+        }
+    }
+
+    /// Reserves `bytes` of the budget until the returned guard is dropped, unless doing so
+    /// would exceed the budget.
+    fn reserve(self: &Arc<Self>, bytes: u64) -> Option<Reservation> {
+        let inflight_bytes = &self.metrics.inflight_bytes;
+        let mut used = inflight_bytes.load(Ordering::Relaxed);
+        loop {
+            let reserved = used.checked_add(bytes).filter(|n| *n <= self.budget)?;
+            match inflight_bytes.compare_exchange_weak(
+                used,
+                reserved,
+                Ordering::Relaxed,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => {
+                    if bytes > 0 {
+                        _ = self.high_water.observe(reserved);
+                    }
+                    break;
+                }
+                Err(current) => used = current,
+            }
+        }
+        Some(Reservation {
+            inflight: Arc::clone(self),
+            bytes,
+        })
+    }
+}
+
+/// Part of the [InflightBytes] budget held by a body.
+#[derive(Debug)]
+struct Reservation {
+    inflight: Arc<InflightBytes>,
+    bytes: u64,
+}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        _ = self
+            .inflight
+            .metrics
+            .inflight_bytes
+            .fetch_sub(self.bytes, Ordering::Relaxed);
+    }
+}
+
+/// Response body, which holds its [Reservation] until it is completely sent or dropped.
+struct Reserved {
+    inner: BoxBody,
+    reservation: Option<Reservation>,
+}
+
+impl HttpBody for Reserved {
+    type Data = Bytes;
+    type Error = axum::Error;
+
+    fn poll_data(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        let data = Pin::new(&mut self.inner).poll_data(cx);
+        if let Poll::Ready(None) = data {
+            self.reservation = None;
+        }
+        data
+    }
+
+    fn poll_trailers(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Option<HeaderMap>, Self::Error>> {
+        Pin::new(&mut self.inner).poll_trailers(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+fn exhausted() -> Response {
+    (
+        StatusCode::SERVICE_UNAVAILABLE,
+        [(RETRY_AFTER, RETRY_AFTER_SECS.to_string())],
+        "Server is busy transferring other requests",
+    )
+        .into_response()
+}
+
+fn content_length(headers: &HeaderMap) -> Option<u64> {
+    headers
+        .get(CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse().ok())
+}
+
+/// Responds with `503 Service Unavailable` if the request body or the response body of `req`
+/// would exceed the budget of `inflight`.
+///
+/// Since response bodies are only known once `req` is handled, only requests not modifying
+/// the store are rejected because of the size of their response.
+pub(crate) async fn limit<B>(
+    inflight: Arc<InflightBytes>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let request_bytes = content_length(req.headers()).unwrap_or(0);
+    let Some(request) = inflight.reserve(request_bytes) else {
+        info!(
+            target: "app::inflight",
+            "reject request with a {request_bytes} byte body exceeding the in-flight budget"
+        );
+        return exhausted();
+    };
+    let modifies = !req.method().is_safe();
+    let head = req.method() == Method::HEAD;
+    let res = next.run(req).await;
+    // Request bodies are consumed by the time the response is produced.
+    drop(request);
+
+    // Bodies of responses to `HEAD` requests are never sent.
+    let response_bytes = content_length(res.headers())
+        .or_else(|| res.body().size_hint().exact())
+        .unwrap_or(0);
+    if head || response_bytes == 0 {
+        return res;
+    }
+    let Some(reservation) = inflight.reserve(response_bytes) else {
+        if modifies {
+            return res;
+        }
+        info!(
+            target: "app::inflight",
+            "reject response with a {response_bytes} byte body exceeding the in-flight budget"
+        );
+        return exhausted();
+    };
+    res.map(|inner| {
+        boxed(Reserved {
+            inner,
+            reservation: Some(reservation),
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reserve() {
+        let metrics = Arc::new(Metrics::default());
+        let inflight = Arc::new(InflightBytes::new(100, 80, Arc::clone(&metrics)));
+        let a = inflight
+            .reserve(60)
+            .expect("failed to reserve within budget");
+        assert_eq!(metrics.inflight_bytes(), 60);
+        assert!(inflight.reserve(41).is_none());
+        assert_eq!(metrics.inflight_bytes(), 60);
+        let b = inflight
+            .reserve(40)
+            .expect("failed to reserve remaining budget");
+        assert_eq!(metrics.inflight_bytes(), 100);
+        assert_eq!(metrics.near_limit(ResourceLimit::InflightBytes), 1);
+        assert!(inflight.reserve(1).is_none());
+        drop(a);
+        assert_eq!(metrics.inflight_bytes(), 40);
+        assert!(inflight.reserve(u64::MAX).is_none());
+        drop(b);
+        assert_eq!(metrics.inflight_bytes(), 0);
+        assert!(inflight.reserve(101).is_none());
+    }
+}
@@ -0,0 +1,84 @@
+// SPDX-FileCopyrightText: 2022 Profian Inc. <opensource@profian.com>
+// SPDX-License-Identifier: AGPL-3.0-only
+
+use super::read_only::is_read;
+use super::IpCidr;
+
+use std::net::IpAddr;
+
+use axum::http::Method;
+
+/// Address ranges, which are allowed and denied access.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub(crate) struct IpRules {
+    pub(crate) allow: Vec<IpCidr>,
+    pub(crate) deny: Vec<IpCidr>,
+}
+
+impl IpRules {
+    /// Returns `true` if `addr` is not denied and, if any ranges are allowed, contained in one
+    /// of them.
+    fn allows(&self, addr: &IpAddr) -> bool {
+        !self.deny.iter().any(|cidr| cidr.contains(addr))
+            && (self.allow.is_empty() || self.allow.iter().any(|cidr| cidr.contains(addr)))
+    }
+}
+
+/// Address-based access control applied to the effective client of connections and requests.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub(crate) struct IpFilter {
+    /// Rules applying to all requests.
+    pub(crate) all: IpRules,
+    /// Rules additionally applying to requests, which could modify the store.
+    pub(crate) write: IpRules,
+}
+
+impl IpFilter {
+    /// Returns `true` if connections from `addr` may be accepted, i.e. if `addr` is allowed
+    /// to send any requests at all.
+    pub(crate) fn allows_connection(&self, addr: &IpAddr) -> bool {
+        self.all.allows(addr)
+    }
+
+    /// Returns `true` if `addr` may send requests using `method`.
+    pub(crate) fn allows(&self, addr: &IpAddr, method: &Method) -> bool {
+        self.all.allows(addr) && (is_read(method) || self.write.allows(addr))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn allows() {
+        let cidrs = |cidrs: &[&str]| cidrs.iter().map(|s| s.parse().unwrap()).collect();
+        let addr = |s: &str| s.parse::<IpAddr>().unwrap();
+
+        let filter = IpFilter::default();
+        assert!(filter.allows_connection(&addr("192.0.2.1")));
+        assert!(filter.allows(&addr("192.0.2.1"), &Method::PUT));
+
+        let filter = IpFilter {
+            all: IpRules {
+                allow: cidrs(&["10.0.0.0/8", "192.0.2.0/24"]),
+                deny: cidrs(&["10.1.0.0/16"]),
+            },
+            write: IpRules {
+                allow: cidrs(&["10.0.0.0/8"]),
+                deny: vec![],
+            },
+        };
+        assert!(filter.allows(&addr("10.2.0.1"), &Method::PUT));
+        // Denied ranges take precedence over allowed ones.
+        assert!(!filter.allows_connection(&addr("10.1.0.1")));
+        assert!(!filter.allows(&addr("10.1.0.1"), &Method::GET));
+        assert!(!filter.allows_connection(&addr("198.51.100.1")));
+        // Write rules only apply to requests, which could modify the store.
+        assert!(filter.allows_connection(&addr("192.0.2.1")));
+        assert!(filter.allows(&addr("192.0.2.1"), &Method::GET));
+        assert!(filter.allows(&addr("192.0.2.1"), &Method::HEAD));
+        assert!(!filter.allows(&addr("192.0.2.1"), &Method::PUT));
+        assert!(!filter.allows(&addr("192.0.2.1"), &Method::DELETE));
+    }
+}
@@ -0,0 +1,156 @@
+// SPDX-FileCopyrightText: 2022 Profian Inc. <opensource@profian.com>
+// SPDX-License-Identifier: AGPL-3.0-only
+
+use std::io;
+use std::num::NonZeroU64;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use axum::body::Body;
+use axum::http::header::CONNECTION;
+use axum::http::{HeaderValue, Request};
+use axum::middleware::Next;
+use axum::response::Response;
+use futures::{AsyncRead, AsyncWrite};
+use tracing::debug;
+
+/// Activity of a single connection, which is shared by its stream and the service serving it.
+#[derive(Debug)]
+pub(crate) struct Activity {
+    established: Instant,
+    /// Milliseconds since `established` at which bytes were last transferred.
+    transferred: AtomicU64,
+    /// Number of requests, whose response was not produced yet.
+    in_flight: AtomicUsize,
+    /// Number of requests received.
+    requests: AtomicU64,
+}
+
+impl Activity {
+    pub(crate) fn new() -> Self {
+        Self {
+            established: Instant::now(),
+            transferred: AtomicU64::new(0),
+            in_flight: AtomicUsize::new(0),
+            requests: AtomicU64::new(0),
+        }
+    }
+
+    fn touch(&self) {
+        let elapsed = self.established.elapsed().as_millis() as u64;
+        _ = self.transferred.fetch_max(elapsed, Ordering::Relaxed);
+    }
+
+    /// Returns the duration remaining until the connection has been idle for `timeout`, or
+    /// `None` if it already has.
+    ///
+    /// Connections are only idle if no bytes were transferred and no requests were in flight.
+    pub(crate) fn idle_remaining(&self, timeout: Duration) -> Option<Duration> {
+        if self.in_flight.load(Ordering::Relaxed) > 0 {
+            return Some(timeout);
+        }
+        let transferred =
+            self.established + Duration::from_millis(self.transferred.load(Ordering::Relaxed));
+        let remaining = timeout.saturating_sub(transferred.elapsed());
+        (!remaining.is_zero()).then_some(remaining)
+    }
+}
+
+/// Stream, which records transfers in its connection [Activity].
+#[derive(Debug)]
+pub(crate) struct Tracked<S> {
+    inner: S,
+    activity: Arc<Activity>,
+}
+
+impl<S> Tracked<S> {
+    pub(crate) fn new(inner: S, activity: Arc<Activity>) -> Self {
+        Self { inner, activity }
+    }
+
+    fn record<T>(&self, res: Poll<io::Result<T>>) -> Poll<io::Result<T>> {
+        if res.is_ready() {
+            self.activity.touch();
+        }
+        res
+    }
+}
+
+impl<S: Unpin + AsyncRead> AsyncRead for Tracked<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let res = Pin::new(&mut self.inner).poll_read(cx, buf);
+        self.record(res)
+    }
+}
+
+impl<S: Unpin + AsyncWrite> AsyncWrite for Tracked<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let res = Pin::new(&mut self.inner).poll_write(cx, buf);
+        self.record(res)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_close(cx)
+    }
+}
+
+/// Records the request in the connection `activity` and asks the client to close the
+/// connection using `Connection: close` once `max_requests` were received on it.
+pub(crate) async fn track(
+    activity: Arc<Activity>,
+    max_requests: Option<NonZeroU64>,
+    req: Request<Body>,
+    next: Next<Body>,
+) -> Response {
+    let n = activity.requests.fetch_add(1, Ordering::Relaxed) + 1;
+    _ = activity.in_flight.fetch_add(1, Ordering::Relaxed);
+    let mut res = next.run(req).await;
+    _ = activity.in_flight.fetch_sub(1, Ordering::Relaxed);
+    activity.touch();
+    if max_requests.is_some_and(|max| n >= max.get()) {
+        debug!(target: "app::keep_alive", "connection served {n} requests, closing");
+        _ = res
+            .headers_mut()
+            .insert(CONNECTION, HeaderValue::from_static("close"));
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn idle_remaining() {
+        let timeout = Duration::from_secs(60);
+        let activity = Activity::new();
+        assert!(activity
+            .idle_remaining(timeout)
+            .is_some_and(|remaining| remaining <= timeout));
+
+        let timeout = Duration::from_millis(1);
+        std::thread::sleep(Duration::from_millis(10));
+        assert_eq!(activity.idle_remaining(timeout), None);
+        // Connections are not idle while a request is in flight.
+        _ = activity.in_flight.fetch_add(1, Ordering::Relaxed);
+        assert_eq!(activity.idle_remaining(timeout), Some(timeout));
+        _ = activity.in_flight.fetch_sub(1, Ordering::Relaxed);
+        activity.touch();
+        assert!(activity.idle_remaining(Duration::from_secs(60)).is_some());
+    }
+}
@@ -28,8 +28,47 @@
     variant_size_differences
 )]
 
+mod archive;
+mod body;
 mod builder;
+mod cache_control;
+mod cidr;
+mod compression;
+mod connections;
+mod content_type;
+mod deadline;
+mod dry_run;
+mod expect;
 mod handle;
+mod hide_existence;
+mod high_water;
+mod hsts;
+mod idempotency;
+mod inflight;
+mod ip_filter;
+mod keep_alive;
+mod maintenance;
+mod manifest;
+mod metrics;
+mod mirror;
+mod negative_cache;
+mod paths;
+mod precompressed;
+mod problem;
+mod proxy;
+mod rate_limit;
+mod read_only;
+mod readiness;
+mod redirect;
+mod routes;
+mod slots;
+mod stats;
+mod store_health;
+mod throttle;
+mod timing;
+mod tokens;
+mod uploads;
+mod validators;
 
 pub mod auth;
 pub mod repos;
@@ -38,56 +77,398 @@ pub mod tags;
 pub mod trees;
 pub mod users;
 
-pub use auth::{OidcClaims, ScopeContext, ScopeLevel, TlsConfig, TrustedCertificate};
+pub use archive::{export_store, import_store, ExportSummary, ImportSummary};
+use auth::CertificateWriter;
+pub use auth::{
+    AuthDecision, CertificateAllowlist, CertificateWriters, OidcClaims, ScopeContext, ScopeLevel,
+    TlsConfig, TlsOptions, TlsSessionConfig, TrustedCertificate, DEFAULT_AUTHZ_CACHE_TTL,
+    DEFAULT_MAX_CLIENT_CERT_CHAIN, DEFAULT_OIDC_CLOCK_SKEW, DEFAULT_TLS_SESSION_CACHE_SIZE,
+    DEFAULT_TLS_TICKET_LIFETIME, MAX_TLS_TICKET_LIFETIME, SERVED_ALPN_PROTOCOLS,
+};
+pub use body::{ResponseBuffer, DEFAULT_RESPONSE_BUFFER_BYTES};
 pub use builder::*;
+pub use cache_control::{DEFAULT_CONTENT_CACHE_CONTROL, DEFAULT_TAG_CACHE_CONTROL};
+pub use cidr::IpCidr;
+pub use compression::CompressionAlgorithm;
+use connections::{Connections, DRAIN_IDLE_TIMEOUT};
+pub use connections::{DEFAULT_HANDSHAKE_TIMEOUT, DEFAULT_SHUTDOWN_TIMEOUT};
+pub use content_type::ContentTypes;
 pub(crate) use handle::*;
+pub use high_water::{ResourceLimit, DEFAULT_LIMIT_WARNING_PERCENT};
+pub use hsts::{Hsts, DEFAULT_HSTS_MAX_AGE};
+pub use idempotency::DEFAULT_IDEMPOTENCY_KEY_TTL;
+use ip_filter::IpFilter;
+use maintenance::Maintenance;
+pub use maintenance::MaintenanceSummary;
+pub use manifest::ManifestSchema;
+pub use metrics::{HandshakeOutcome, Metrics, TokenOutcome};
+pub use mirror::{MirrorConfig, DEFAULT_MIRROR_TTL};
+pub use negative_cache::MAX_NEGATIVE_CACHE_TTL;
+pub use precompressed::Precompression;
+pub use problem::{PROBLEM_DIGEST_MISMATCH, PROBLEM_INSUFFICIENT_STORAGE, PROBLEM_QUOTA_EXCEEDED};
+pub use proxy::ClientInfo;
+pub use routes::RouteClass;
+pub use stats::{store_stats, NamespaceStats, ObjectStats, StoreStats};
 pub(crate) use store::*;
+pub use store::{
+    CircuitState, RepairOutcome, S3Config, S3Credentials, StoreUrl, DEFAULT_CACHE_MAX_BYTES,
+    DEFAULT_ORPHAN_MAX_AGE, DEFAULT_S3_REGION, DEFAULT_STARTUP_SCAN_THREADS,
+    DEFAULT_STORE_CIRCUIT_COOLDOWN, DEFAULT_STORE_CIRCUIT_THRESHOLD,
+};
+pub use store_health::StoreFailurePolicy;
+use store_health::{StoreHealth, STORE_FAILURE_THRESHOLD};
+use throttle::Throttled;
+pub use timing::ServerTiming;
+use tokens::TokenId;
+pub use uploads::DEFAULT_UPLOAD_SESSION_TTL;
+use uploads::{UploadId, Uploads};
+use validators::Validators;
 
+pub use axum::http::Method;
+pub use mime;
 pub use openidconnect::url;
 
+use std::convert::Infallible;
+use std::error::Error as _;
+use std::io;
+use std::net::SocketAddr;
+use std::num::NonZeroU64;
+use std::pin::pin;
+use std::sync::{Arc, PoisonError, RwLock};
+use std::time::Duration;
+
 use anyhow::Context as _;
-use async_std::path::Path;
-use axum::extract::Extension;
+use async_io::Timer;
+use async_std::net::TcpListener;
+use async_std::task::sleep;
+use axum::body::Body;
+use axum::http::header::{SERVER, STRICT_TRANSPORT_SECURITY};
+use axum::http::{HeaderValue, Request, StatusCode};
+use axum::middleware::{from_fn, Next};
+use axum::response::IntoResponse;
 use axum::routing::IntoMakeService;
 use axum::Router;
+use futures::future::{select, Either};
 use futures::lock::Mutex;
-use futures::{AsyncRead, AsyncWrite};
+use futures::{pin_mut, AsyncRead, AsyncWrite};
 use futures_rustls::TlsAcceptor;
 use hyper::server::conn::Http;
+use hyper::service::service_fn;
 use tokio_util::compat::FuturesAsyncReadCompatExt;
 use tower::MakeService;
-use tracing::trace;
+use tracing::{debug, info, trace, warn};
 
 #[allow(missing_debug_implementations)] // TlsAcceptor does not implement Debug
 pub struct App {
     make_service: Mutex<IntoMakeService<Router>>,
-    tls: TlsAcceptor,
+    /// Routes served by [App::serve_metrics].
+    ops: Mutex<IntoMakeService<Router>>,
+    tls: RwLock<TlsAcceptor>,
+    client_cert_allowlist: Arc<RwLock<Option<CertificateAllowlist>>>,
+    client_cert_writers: Arc<RwLock<Option<CertificateWriters>>>,
+    metrics: Arc<Metrics>,
+    read_only: bool,
+    max_download_bps: Option<NonZeroU64>,
+    public_url: Option<url::Url>,
+    trusted_proxies: Arc<Vec<IpCidr>>,
+    ip_filter: Arc<IpFilter>,
+    store: Arc<Store>,
+    store_health: Arc<StoreHealth>,
+    maintenance: Maintenance,
+    server_header: Option<HeaderValue>,
+    hsts: Option<HeaderValue>,
+    keep_alive_timeout: Option<Duration>,
+    max_requests_per_connection: Option<NonZeroU64>,
+    handshake_timeout: Option<Duration>,
+    connections: Connections,
 }
 
 impl App {
-    pub fn builder<S: AsRef<Path>>(store: S, tls: TlsConfig, oidc: OidcConfig) -> Builder<S> {
+    pub fn builder<S: Into<StoreUrl>>(store: S, tls: TlsConfig, oidc: OidcConfig) -> Builder<S> {
         Builder::new(store, tls, oidc)
     }
 
     pub async fn new(
-        store: impl AsRef<Path>,
+        store: impl Into<StoreUrl>,
         tls: TlsConfig,
         oidc: OidcConfig,
     ) -> anyhow::Result<Self> {
         Self::builder(store, tls, oidc).build().await
     }
 
+    /// Returns `true` if the server rejects all requests, which could modify the store.
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    /// Returns the externally visible base URL of the server, if configured.
+    pub fn public_url(&self) -> Option<&url::Url> {
+        self.public_url.as_ref()
+    }
+
+    /// Returns `true` unless the store was found to be unavailable by [App::watch_store].
+    pub fn is_store_available(&self) -> bool {
+        self.store_health.is_available()
+    }
+
+    /// Probes the store every `interval` and applies `policy` once the store is found to be
+    /// unavailable by several consecutive probes.
+    ///
+    /// Using [StoreFailurePolicy::Serve503], all requests are rejected until a probe succeeds
+    /// again and this function never returns. Using [StoreFailurePolicy::Exit], this function
+    /// returns an error of [FailureClass::Store], after which the caller is expected to
+    /// terminate.
+    pub async fn watch_store(
+        &self,
+        interval: Duration,
+        policy: StoreFailurePolicy,
+    ) -> anyhow::Result<()> {
+        let mut failures = 0;
+        loop {
+            sleep(interval).await;
+            match self.store.probe().await {
+                Ok(()) => {
+                    if failures >= STORE_FAILURE_THRESHOLD {
+                        info!(target: "app::App::watch_store", "store is available again");
+                    }
+                    failures = 0;
+                    self.store_health.set_available(true);
+                }
+                Err(e) => {
+                    failures += 1;
+                    warn!(target: "app::App::watch_store", failures, "store probe failed: {e}");
+                    if failures < STORE_FAILURE_THRESHOLD {
+                        continue;
+                    }
+                    match policy {
+                        StoreFailurePolicy::Serve503 => self.store_health.set_available(false),
+                        StoreFailurePolicy::Exit => {
+                            return Err(e)
+                                .context("store probes failed")
+                                .context(FailureClass::Store);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Runs a maintenance pass every `interval` and never returns.
+    ///
+    /// Each pass removes stale temporary files and partially written objects from the store,
+    /// expired upload sessions and idempotency records and expired cached authorization
+    /// decisions, while requests are handled concurrently. Objects currently being written
+    /// are never removed. Results of the last pass are exposed by [App::metrics].
+    pub async fn maintain(&self, interval: Duration) {
+        loop {
+            sleep(interval).await;
+            _ = self.maintenance.run().await;
+        }
+    }
+
+    /// Returns the server metrics.
+    pub fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+
+    /// Replaces the TLS configuration used for new connections.
+    pub fn set_tls_config(&self, tls: TlsConfig) {
+        *self.tls.write().unwrap_or_else(PoisonError::into_inner) =
+            TlsAcceptor::from(Arc::new(tls.into()));
+    }
+
+    /// Replaces the client certificate allowlist.
+    ///
+    /// If `None`, all client certificates signed by a trusted CA are granted access.
+    pub fn set_client_cert_allowlist(&self, allowlist: Option<CertificateAllowlist>) {
+        *self
+            .client_cert_allowlist
+            .write()
+            .unwrap_or_else(PoisonError::into_inner) = allowlist;
+    }
+
+    /// Replaces the mapping of client certificates to the namespaces they may write to.
+    ///
+    /// If `None`, client certificates are only granted read access.
+    pub fn set_client_cert_writers(&self, writers: Option<CertificateWriters>) {
+        *self
+            .client_cert_writers
+            .write()
+            .unwrap_or_else(PoisonError::into_inner) = writers;
+    }
+
+    /// Accepts connections from `listener` and handles each on its own task, until the app is
+    /// [shut down](App::shutdown).
+    ///
+    /// Once shut down, this returns after all connections accepted from `listener` completed
+    /// or the shutdown timeout elapsed, whichever happens first.
+    pub async fn serve_connections(self: &Arc<Self>, listener: &TcpListener) {
+        self.connections
+            .accept(listener, "failed to handle request", |stream, peer| {
+                let app = Arc::clone(self);
+                async move {
+                    match peer {
+                        Some(peer) => app.handle_from(stream, peer).await,
+                        None => app.handle(stream).await,
+                    }
+                }
+            })
+            .await
+    }
+
+    /// Accepts plaintext connections from `listener` and serves only the metrics at `/metrics`
+    /// and the health probes at `/healthz` and `/readyz` on each, until the app is
+    /// [shut down](App::shutdown).
+    ///
+    /// Unlike connections served by [App::serve_connections] and [App::serve_redirects], these
+    /// do not count towards the connection limit, such that a saturated server can still be
+    /// monitored.
+    pub async fn serve_metrics(self: &Arc<Self>, listener: &TcpListener) {
+        self.connections
+            .accept_unlimited(listener, "failed to serve metrics", |stream, _| {
+                let app = Arc::clone(self);
+                async move {
+                    let svc = app
+                        .ops
+                        .lock()
+                        .await
+                        .make_service(())
+                        .await
+                        .context("failed to create metrics service")?;
+                    Http::new()
+                        .http1_only(true)
+                        .serve_connection(stream.compat(), svc)
+                        .await
+                        .context("failed to handle plaintext request")
+                }
+            })
+            .await
+    }
+
+    /// Accepts plaintext connections from `listener` and redirects requests received on each
+    /// to `https_port` like [App::redirect_to_https], until the app is
+    /// [shut down](App::shutdown).
+    ///
+    /// Redirected connections count towards the same connection limit as the ones served by
+    /// [App::serve_connections].
+    pub async fn serve_redirects(self: &Arc<Self>, listener: &TcpListener, https_port: u16) {
+        self.connections
+            .accept(listener, "failed to redirect request", |stream, _| {
+                let app = Arc::clone(self);
+                async move { app.redirect_to_https(stream, https_port).await }
+            })
+            .await
+    }
+
+    /// Shuts the app down gracefully.
+    ///
+    /// No more connections are accepted by [App::serve_connections], [App::serve_redirects] and
+    /// [App::serve_metrics] and established connections are closed once the requests in flight
+    /// on them completed.
+    pub fn shutdown(&self) {
+        self.connections.trigger_shutdown()
+    }
+
+    /// Returns `true` once [App::shutdown] was called.
+    pub fn is_shutting_down(&self) -> bool {
+        self.connections.shutdown().is_triggered()
+    }
+
     pub async fn handle(
         &self,
         stream: impl 'static + Unpin + AsyncRead + AsyncWrite,
     ) -> anyhow::Result<()> {
+        self.serve(stream, None).await
+    }
+
+    /// Handles a connection from `peer`.
+    ///
+    /// Unlike [App::handle], this inserts the effective [ClientInfo] into request extensions.
+    pub async fn handle_from(
+        &self,
+        stream: impl 'static + Unpin + AsyncRead + AsyncWrite,
+        peer: SocketAddr,
+    ) -> anyhow::Result<()> {
+        self.serve(stream, Some(peer)).await
+    }
+
+    async fn serve(
+        &self,
+        stream: impl 'static + Unpin + AsyncRead + AsyncWrite,
+        peer: Option<SocketAddr>,
+    ) -> anyhow::Result<()> {
+        let _conn = self.metrics.accept_connection();
+
+        // Connections from trusted proxies are accepted, since they may forward requests on
+        // behalf of allowed clients, which are filtered per request instead.
+        if let Some(peer) = peer {
+            let addr = peer.ip();
+            if !self.trusted_proxies.iter().any(|cidr| cidr.contains(&addr))
+                && !self.ip_filter.allows_connection(&addr)
+            {
+                info!(target: "app::App::handle", "reject connection from denied address {addr}");
+                return Ok(());
+            }
+        }
+
+        let activity = Arc::new(keep_alive::Activity::new());
+        let stream = Throttled::new(
+            keep_alive::Tracked::new(stream, Arc::clone(&activity)),
+            self.max_download_bps,
+        );
+
         trace!(target: "app::App::handle", "begin TLS handshake");
-        let stream = self
+        let tls = self
             .tls
-            .accept(stream)
-            .await
-            .context("failed to accept TLS connection")?;
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone();
+        let accept = tls.accept(stream);
+        let stream = match self.handshake_timeout {
+            Some(timeout) => async_std::future::timeout(timeout, accept)
+                .await
+                .unwrap_or_else(|_| {
+                    Err(io::Error::new(
+                        io::ErrorKind::TimedOut,
+                        "TLS handshake timed out",
+                    ))
+                }),
+            None => accept.await,
+        };
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                self.metrics
+                    .record_tls_handshake(if e.kind() == io::ErrorKind::TimedOut {
+                        HandshakeOutcome::TimedOut
+                    } else {
+                        HandshakeOutcome::Failed
+                    });
+                return Err(e).context("failed to accept TLS connection");
+            }
+        };
         trace!(target: "app::App::handle", "completed TLS handshake");
+        // Client certificates are only accepted if signed by a trusted CA.
+        self.metrics.record_tls_handshake(
+            if stream
+                .get_ref()
+                .1
+                .peer_certificates()
+                .is_some_and(|certs| !certs.is_empty())
+            {
+                HandshakeOutcome::ClientCertificate
+            } else {
+                HandshakeOutcome::NoClientCertificate
+            },
+        );
+        match stream.get_ref().1.alpn_protocol() {
+            Some(protocol) => debug!(
+                target: "app::App::handle",
+                "negotiated ALPN protocol `{}`",
+                String::from_utf8_lossy(protocol)
+            ),
+            None => debug!(target: "app::App::handle", "negotiated no ALPN protocol"),
+        }
 
         let mut svc = self
             .make_service
@@ -96,15 +477,184 @@ impl App {
             .make_service(())
             .await
             .context("failed to create app service")?;
+        if let Some(peer) = peer {
+            let trusted_proxies = Arc::clone(&self.trusted_proxies);
+            let ip_filter = Arc::clone(&self.ip_filter);
+            svc = svc.layer(from_fn(move |mut req: Request<Body>, next: Next<Body>| {
+                let info = ClientInfo::new(peer.ip(), req.headers_mut(), &trusted_proxies);
+                let allowed = ip_filter.allows(&info.addr, req.method());
+                if !allowed {
+                    info!(target: "app::App::handle", "reject `{}` request from denied address {}", req.method(), info.addr);
+                }
+                _ = req.extensions_mut().insert(info);
+                async move {
+                    if allowed {
+                        next.run(req).await
+                    } else {
+                        (StatusCode::FORBIDDEN, "Client address not allowed").into_response()
+                    }
+                }
+            }));
+        }
         let (_, conn) = stream.get_ref();
-        if conn.peer_certificates().is_some() {
-            svc = svc.layer(Extension(TrustedCertificate));
-            trace!(target: "app::App::handle", "add TrustedCertificate to extensions");
+        if let Some(cert) = conn.peer_certificates().and_then(|certs| certs.first()) {
+            let cert = cert.clone();
+            let trusted = TrustedCertificate::new(&cert);
+            let allowlist = Arc::clone(&self.client_cert_allowlist);
+            let writers = Arc::clone(&self.client_cert_writers);
+            // The allowlist and writers are consulted on each request, such that reloads apply
+            // to established connections as well.
+            svc = svc.layer(from_fn(move |mut req: Request<Body>, next: Next<Body>| {
+                let allowed = allowlist
+                    .read()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .as_ref()
+                    .is_none_or(|allowlist| allowlist.contains(&cert));
+                let namespaces = writers
+                    .read()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .as_ref()
+                    .and_then(|writers| writers.namespaces(&trusted))
+                    .cloned();
+                async move {
+                    if allowed {
+                        trace!(target: "app::App::handle", "add TrustedCertificate to extensions");
+                        _ = req.extensions_mut().insert(trusted);
+                        if let Some(namespaces) = namespaces {
+                            trace!(target: "app::App::handle", "add CertificateWriter to extensions");
+                            _ = req.extensions_mut().insert(CertificateWriter {
+                                cert: trusted,
+                                namespaces,
+                            });
+                        }
+                        next.run(req).await
+                    } else {
+                        warn!(target: "app::App::handle", "client certificate is not in the allowlist");
+                        (StatusCode::FORBIDDEN, "Client certificate not allowed").into_response()
+                    }
+                }
+            }));
+        }
+        {
+            let activity = Arc::clone(&activity);
+            let max_requests = self.max_requests_per_connection;
+            svc = svc.layer(from_fn(move |req, next| {
+                keep_alive::track(Arc::clone(&activity), max_requests, req, next)
+            }));
+        }
+        // Connections are always secured by TLS, such that HSTS policies are sent in all
+        // responses.
+        if let Some(value) = self.hsts.clone() {
+            svc = svc.layer(from_fn(move |req: Request<Body>, next: Next<Body>| {
+                let value = value.clone();
+                async move {
+                    let mut res = next.run(req).await;
+                    _ = res.headers_mut().insert(STRICT_TRANSPORT_SECURITY, value);
+                    res
+                }
+            }));
+        }
+        // Applied last, such that the header is also sent in responses produced by other layers.
+        if let Some(value) = self.server_header.clone() {
+            svc = svc.layer(from_fn(move |req: Request<Body>, next: Next<Body>| {
+                let value = value.clone();
+                async move {
+                    let mut res = next.run(req).await;
+                    _ = res.headers_mut().insert(SERVER, value);
+                    res
+                }
+            }));
         }
         trace!(target: "app::App::handle", "begin HTTP request serving");
+        let conn = Http::new().serve_connection(stream.compat(), svc);
+        pin_mut!(conn);
+        let shutdown = self.connections.shutdown();
+        let mut closing = false;
+        let res = loop {
+            if shutdown.is_triggered() && !closing {
+                debug!(target: "app::App::handle", "closing connection on shutdown");
+                // Responses, which are still being sent, are completed first.
+                conn.as_mut().graceful_shutdown();
+                closing = true;
+            }
+            // Once shutting down, connections are closed as soon as they are idle.
+            let timeout = if shutdown.is_triggered() {
+                Some(DRAIN_IDLE_TIMEOUT)
+            } else {
+                self.keep_alive_timeout
+            };
+            let remaining = match timeout.map(|timeout| (timeout, activity.idle_remaining(timeout)))
+            {
+                None => None,
+                Some((_, Some(remaining))) => Some(remaining),
+                // hyper does not complete the shutdown of connections, on which no complete
+                // request was received yet, e.g. because the client never sent one. Such
+                // clients are disconnected once idle for another timeout.
+                Some((_, None)) if closing => {
+                    debug!(target: "app::App::handle", "dropping idle connection");
+                    break Ok(());
+                }
+                Some((timeout, None)) => {
+                    debug!(target: "app::App::handle", "closing idle connection");
+                    conn.as_mut().graceful_shutdown();
+                    closing = true;
+                    Some(timeout)
+                }
+            };
+            let wake = async move {
+                match remaining {
+                    Some(remaining) if shutdown.is_triggered() => {
+                        _ = Timer::after(remaining).await;
+                    }
+                    Some(remaining) => {
+                        _ = select(Timer::after(remaining), pin!(shutdown.triggered())).await;
+                    }
+                    None => shutdown.triggered().await,
+                }
+            };
+            match select(conn.as_mut(), pin!(wake)).await {
+                Either::Left((res, _)) => break res,
+                Either::Right(_) => continue,
+            }
+        };
+        res.or_else(|e| match e.source().and_then(|e| e.downcast_ref::<io::Error>()) {
+                // Clients commonly drop idle connections without sending a TLS `close_notify`
+                // alert first, which is reported as an unexpected EOF or, if the connection is
+                // closed while it is being shut down, a broken pipe.
+                Some(e)
+                    if matches!(
+                        e.kind(),
+                        io::ErrorKind::UnexpectedEof | io::ErrorKind::BrokenPipe
+                    ) =>
+                {
+                    trace!(target: "app::App::handle", "peer closed connection without TLS close_notify");
+                    Ok(())
+                }
+                _ => Err(e),
+            })
+            .context("failed to handle request")
+    }
+
+    /// Handles a plaintext connection by redirecting all requests to their `https` equivalent
+    /// with `308 Permanent Redirect`, without ever serving any content.
+    ///
+    /// Redirects are based on [App::public_url], if set, or the `Host` of each request with
+    /// its port replaced by `https_port` otherwise. HSTS policies are never sent over
+    /// plaintext connections, as required by RFC 6797.
+    pub async fn redirect_to_https(
+        &self,
+        stream: impl 'static + Unpin + AsyncRead + AsyncWrite,
+        https_port: u16,
+    ) -> anyhow::Result<()> {
+        let public_url = self.public_url.clone();
+        let svc = service_fn(move |req: Request<Body>| {
+            let res = redirect::redirect(public_url.as_ref(), https_port, &req);
+            async move { Ok::<_, Infallible>(res) }
+        });
         Http::new()
+            .http1_only(true)
             .serve_connection(stream.compat(), svc)
             .await
-            .context("failed to handle request")
+            .context("failed to handle plaintext request")
     }
 }
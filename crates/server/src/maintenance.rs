@@ -0,0 +1,115 @@
+// SPDX-FileCopyrightText: 2022 Profian Inc. <opensource@profian.com>
+// SPDX-License-Identifier: AGPL-3.0-only
+
+use super::auth::Webhook;
+use super::idempotency::Idempotency;
+use super::{Metrics, Store, Uploads};
+
+use std::num::NonZeroUsize;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use tracing::{debug, info, warn};
+
+/// Summary of a maintenance pass run by [App::maintain](crate::App::maintain).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MaintenanceSummary {
+    /// Number of removed temporary files of the store.
+    pub temporary_files: u64,
+    /// Number of removed partially written objects of the store.
+    pub partial_objects: u64,
+    /// Total size of the removed temporary files and partially written objects.
+    pub reclaimed_bytes: u64,
+    /// Number of removed tree nodes of the store, which were not referenced by their parent.
+    pub unreferenced_nodes: u64,
+    /// Number of removed leftovers of interrupted deletions from the store.
+    pub deletion_remnants: u64,
+    /// Number of removed expired upload sessions.
+    pub upload_sessions: u64,
+    /// Number of removed expired idempotency records.
+    pub idempotency_records: u64,
+    /// Number of evicted expired authorization webhook decisions.
+    pub authz_decisions: u64,
+    /// Number of tasks of the pass, which failed.
+    pub failures: u64,
+}
+
+/// Background maintenance of the store and of state kept by the server.
+///
+/// Each pass runs its tasks one after another and scans the store using a single worker,
+/// such that maintenance competes with requests for as little I/O as possible.
+#[derive(Debug)]
+pub(crate) struct Maintenance {
+    pub(crate) store: Arc<Store>,
+    /// Age of temporary files and partially written objects, after which they are removed,
+    /// unless orphan cleanup is disabled.
+    pub(crate) orphan_max_age: Option<Duration>,
+    /// Whether unreferenced tree nodes are removed.
+    pub(crate) gc: bool,
+    pub(crate) uploads: Option<Arc<Uploads>>,
+    pub(crate) idempotency: Option<Arc<Idempotency>>,
+    pub(crate) webhook: Option<Arc<Webhook>>,
+    pub(crate) metrics: Arc<Metrics>,
+}
+
+impl Maintenance {
+    /// Runs a maintenance pass and records its summary in the metrics.
+    ///
+    /// Failing tasks are logged and do not prevent the remaining tasks from running.
+    pub(crate) async fn run(&self) -> MaintenanceSummary {
+        let mut summary = MaintenanceSummary::default();
+        if let Some(max_age) = self.orphan_max_age {
+            match self.store.remove_orphans(max_age, NonZeroUsize::MIN).await {
+                Ok(orphans) => {
+                    summary.temporary_files = orphans.temporary;
+                    summary.partial_objects = orphans.partial;
+                    summary.reclaimed_bytes = orphans.bytes;
+                }
+                Err(e) => {
+                    summary.failures += 1;
+                    warn!(target: "app::maintenance", "failed to remove orphaned files from store: {e:?}");
+                }
+            }
+        }
+        if self.gc {
+            match self.store.collect_garbage().await {
+                Ok(gc) => {
+                    summary.unreferenced_nodes = gc.nodes;
+                    summary.deletion_remnants = gc.remnants;
+                }
+                Err(e) => {
+                    summary.failures += 1;
+                    warn!(target: "app::maintenance", "failed to collect garbage of store: {e:?}");
+                }
+            }
+        }
+        if let Some(ref uploads) = self.uploads {
+            summary.upload_sessions = uploads.expire().await as u64;
+        }
+        if let Some(ref idempotency) = self.idempotency {
+            match idempotency.expire().await {
+                Ok(n) => summary.idempotency_records = n as u64,
+                Err(e) => {
+                    summary.failures += 1;
+                    warn!(target: "app::maintenance", "failed to remove expired idempotency records: {e}");
+                }
+            }
+        }
+        if let Some(ref webhook) = self.webhook {
+            summary.authz_decisions = webhook.evict() as u64;
+        }
+
+        if summary.temporary_files > 0 || summary.partial_objects > 0 {
+            info!(
+                target: "app::maintenance",
+                "removed {} temporary files and {} partially written objects from store, reclaiming {} bytes",
+                summary.temporary_files,
+                summary.partial_objects,
+                summary.reclaimed_bytes,
+            );
+        }
+        debug!(target: "app::maintenance", summary = ?summary, "completed maintenance pass");
+        self.metrics.record_maintenance(SystemTime::now(), summary);
+        summary
+    }
+}
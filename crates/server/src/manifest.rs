@@ -0,0 +1,358 @@
+// SPDX-FileCopyrightText: 2022 Profian Inc. <opensource@profian.com>
+// SPDX-License-Identifier: AGPL-3.0-only
+
+use super::problem::Problem;
+
+use std::str::FromStr;
+
+use drawbridge_jose::jws::{Flattened, General, Jws};
+use drawbridge_type::TagEntry;
+
+use anyhow::{bail, Context};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use serde_json::{json, Map, Value};
+
+/// Keywords, which are supported by [ManifestSchema].
+const KEYWORDS: &[&str] = &[
+    "additionalProperties",
+    "const",
+    "enum",
+    "items",
+    "maxItems",
+    "maxLength",
+    "maximum",
+    "minItems",
+    "minLength",
+    "minProperties",
+    "minimum",
+    "properties",
+    "required",
+    "type",
+];
+
+/// Keywords, which are accepted by [ManifestSchema], but do not affect validation.
+const ANNOTATIONS: &[&str] = &["$comment", "$id", "$schema", "description", "title"];
+
+/// Primitive types, which may be used as values of the `type` keyword.
+const TYPES: &[&str] = &[
+    "array", "boolean", "integer", "null", "number", "object", "string",
+];
+
+/// JSON schema, which uploaded tag manifests are validated against.
+///
+/// Signed manifests are validated using their JWS payload. Only a subset of JSON Schema is
+/// supported, namely the `type`, `enum`, `const`, `properties`, `required`,
+/// `additionalProperties`, `minProperties`, `items`, `minItems`, `maxItems`, `minLength`,
+/// `maxLength`, `minimum` and `maximum` keywords. Schemas using any other keyword are rejected.
+///
+/// The [Default] schema requires manifests to be tree entries with at least one digest.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ManifestSchema(Value);
+
+impl Default for ManifestSchema {
+    fn default() -> Self {
+        Self(json!({
+            "title": "Drawbridge tag manifest",
+            "type": "object",
+            "required": ["digest", "length", "type"],
+            "properties": {
+                "digest": {
+                    "type": "object",
+                    "minProperties": 1,
+                    "additionalProperties": { "type": "string", "minLength": 1 }
+                },
+                "length": { "type": "integer", "minimum": 0 },
+                "type": { "type": "string", "minLength": 1 }
+            }
+        }))
+    }
+}
+
+impl FromStr for ManifestSchema {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        serde_json::from_str(s)
+            .context("schema is not valid JSON")
+            .and_then(Self::new)
+    }
+}
+
+fn check(schema: &Value, path: &str) -> anyhow::Result<()> {
+    let schema = match schema {
+        Value::Bool(_) => return Ok(()),
+        Value::Object(schema) => schema,
+        _ => bail!("schema at `{path}` must be an object or a boolean"),
+    };
+    for (keyword, value) in schema {
+        let path = format!("{path}/{keyword}");
+        match (keyword.as_str(), value) {
+            ("type", Value::String(ty)) if TYPES.contains(&ty.as_str()) => {}
+            ("type", Value::Array(tys))
+                if tys
+                    .iter()
+                    .all(|ty| ty.as_str().is_some_and(|ty| TYPES.contains(&ty))) => {}
+            ("enum", Value::Array(_)) | ("const", _) => {}
+            ("required", Value::Array(names)) if names.iter().all(Value::is_string) => {}
+            ("properties", Value::Object(properties)) => {
+                for (name, schema) in properties {
+                    check(schema, &format!("{path}/{name}"))?;
+                }
+            }
+            ("additionalProperties" | "items", schema) => check(schema, &path)?,
+            (
+                "minProperties" | "minItems" | "maxItems" | "minLength" | "maxLength",
+                Value::Number(n),
+            ) if n.is_u64() => {}
+            ("minimum" | "maximum", Value::Number(_)) => {}
+            (keyword, _) if ANNOTATIONS.contains(&keyword) => {}
+            (keyword, _) if KEYWORDS.contains(&keyword) => bail!("invalid value at `{path}`"),
+            (keyword, _) => bail!("unsupported keyword `{keyword}` at `{path}`"),
+        }
+    }
+    Ok(())
+}
+
+fn type_of(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(n) if n.is_i64() || n.is_u64() => "integer",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+fn location(path: &str) -> &str {
+    if path.is_empty() {
+        "/"
+    } else {
+        path
+    }
+}
+
+fn validate(schema: &Value, value: &Value, path: &str, errors: &mut Vec<String>) {
+    let schema = match schema {
+        Value::Bool(true) => return,
+        Value::Bool(false) => {
+            errors.push(format!("`{}` is not allowed", location(path)));
+            return;
+        }
+        Value::Object(schema) => schema,
+        _ => return,
+    };
+    let at = location(path);
+    let get = |keyword| schema.get(keyword);
+    let limit = |keyword| get(keyword).and_then(Value::as_u64);
+
+    if let Some(ty) = get("type") {
+        let actual = type_of(value);
+        let matches = |ty: &Value| {
+            ty.as_str()
+                .is_some_and(|ty| ty == actual || ty == "number" && actual == "integer")
+        };
+        let matches = match ty {
+            Value::Array(tys) => tys.iter().any(matches),
+            ty => matches(ty),
+        };
+        if !matches {
+            errors.push(format!("`{at}` must be of type {ty}, got `{actual}`"));
+            // Further keywords do not apply to values of unexpected type.
+            return;
+        }
+    }
+    if let Some(Value::Array(values)) = get("enum") {
+        if !values.contains(value) {
+            errors.push(format!(
+                "`{at}` must be one of {}",
+                Value::Array(values.clone())
+            ));
+        }
+    }
+    if let Some(expected) = get("const") {
+        if value != expected {
+            errors.push(format!("`{at}` must be {expected}"));
+        }
+    }
+    match value {
+        Value::Object(object) => validate_object(schema, object, path, errors),
+        Value::Array(items) => {
+            if let Some(min) = limit("minItems").filter(|min| (items.len() as u64) < *min) {
+                errors.push(format!("`{at}` must have at least {min} items"));
+            }
+            if let Some(max) = limit("maxItems").filter(|max| (items.len() as u64) > *max) {
+                errors.push(format!("`{at}` must have at most {max} items"));
+            }
+            if let Some(schema) = get("items") {
+                for (i, item) in items.iter().enumerate() {
+                    validate(schema, item, &format!("{path}/{i}"), errors);
+                }
+            }
+        }
+        Value::String(s) => {
+            let len = s.chars().count() as u64;
+            if let Some(min) = limit("minLength").filter(|min| len < *min) {
+                errors.push(format!("`{at}` must be at least {min} characters long"));
+            }
+            if let Some(max) = limit("maxLength").filter(|max| len > *max) {
+                errors.push(format!("`{at}` must be at most {max} characters long"));
+            }
+        }
+        Value::Number(n) => {
+            let n = n.as_f64().unwrap_or(f64::NAN);
+            if let Some(min) = get("minimum").filter(|min| min.as_f64().is_some_and(|m| n < m)) {
+                errors.push(format!("`{at}` must be at least {min}"));
+            }
+            if let Some(max) = get("maximum").filter(|max| max.as_f64().is_some_and(|m| n > m)) {
+                errors.push(format!("`{at}` must be at most {max}"));
+            }
+        }
+        Value::Null | Value::Bool(_) => {}
+    }
+}
+
+fn validate_object(
+    schema: &Map<String, Value>,
+    object: &Map<String, Value>,
+    path: &str,
+    errors: &mut Vec<String>,
+) {
+    let at = location(path);
+    if let Some(Value::Array(required)) = schema.get("required") {
+        for name in required.iter().filter_map(Value::as_str) {
+            if !object.contains_key(name) {
+                errors.push(format!("`{at}` is missing required property `{name}`"));
+            }
+        }
+    }
+    if let Some(min) = schema
+        .get("minProperties")
+        .and_then(Value::as_u64)
+        .filter(|min| (object.len() as u64) < *min)
+    {
+        errors.push(format!("`{at}` must have at least {min} properties"));
+    }
+    let properties = schema.get("properties").and_then(Value::as_object);
+    for (name, value) in object {
+        let path = format!("{path}/{}", name.replace('~', "~0").replace('/', "~1"));
+        match properties.and_then(|properties| properties.get(name)) {
+            Some(schema) => validate(schema, value, &path, errors),
+            None => {
+                if let Some(schema) = schema.get("additionalProperties") {
+                    validate(schema, value, &path, errors)
+                }
+            }
+        }
+    }
+}
+
+impl ManifestSchema {
+    /// Constructs a new [ManifestSchema] from `schema`, failing if it uses unsupported keywords.
+    pub fn new(schema: Value) -> anyhow::Result<Self> {
+        check(&schema, "").map(|()| Self(schema))
+    }
+
+    /// Validates `manifest` and returns the list of validation errors, which is empty if
+    /// `manifest` is valid.
+    fn errors(&self, manifest: &Value) -> Vec<String> {
+        let mut errors = vec![];
+        validate(&self.0, manifest, "", &mut errors);
+        errors
+    }
+
+    /// Validates tag `entry` and returns a `400 Bad Request` problem response listing the
+    /// validation errors, if it is invalid.
+    #[allow(clippy::result_large_err)]
+    pub(crate) fn validate(&self, entry: &TagEntry) -> Result<(), Response> {
+        let manifest = match entry {
+            TagEntry::Unsigned(entry) => serde_json::to_value(entry).ok(),
+            TagEntry::Signed(
+                Jws::General(General { payload, .. }) | Jws::Flattened(Flattened { payload, .. }),
+            ) => payload
+                .as_ref()
+                .and_then(|payload| serde_json::from_slice(payload).ok()),
+        };
+        let errors = match manifest {
+            Some(manifest) => self.errors(&manifest),
+            None => vec!["signed manifest payload must be a JSON document".into()],
+        };
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(invalid(errors))
+        }
+    }
+}
+
+/// Returns an `application/problem+json` response listing manifest validation `errors`.
+fn invalid(errors: Vec<String>) -> Response {
+    Problem::new(StatusCode::BAD_REQUEST, "about:blank", "Invalid manifest")
+        .detail(errors.join("; "))
+        .member("errors", errors)
+        .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validate() {
+        let schema = ManifestSchema::default();
+        assert_eq!(
+            schema.errors(&json!({
+                "digest": { "sha-256": "LCa0a2j/xo/5m0U8HTBBNBNCLXBkg7+g+YpeiGJm564=" },
+                "length": 3,
+                "type": "application/octet-stream",
+                "custom": true,
+            })),
+            Vec::<String>::new()
+        );
+        assert_eq!(
+            schema.errors(&json!({ "digest": {}, "length": -1 })),
+            vec![
+                "`/` is missing required property `type`",
+                "`/digest` must have at least 1 properties",
+                "`/length` must be at least 0",
+            ]
+        );
+        assert_eq!(
+            schema.errors(&json!({ "digest": { "sha-256": 1 }, "length": "3", "type": "" })),
+            vec![
+                "`/digest/sha-256` must be of type \"string\", got `integer`",
+                "`/length` must be of type \"integer\", got `string`",
+                "`/type` must be at least 1 characters long",
+            ]
+        );
+        assert_eq!(
+            schema.errors(&json!([])),
+            vec!["`/` must be of type \"object\", got `array`"]
+        );
+
+        let schema: ManifestSchema = r#"{
+            "type": "object",
+            "properties": {
+                "tags": { "type": "array", "items": { "enum": ["a", "b"] }, "maxItems": 2 }
+            },
+            "additionalProperties": false
+        }"#
+        .parse()
+        .unwrap();
+        assert_eq!(
+            schema.errors(&json!({ "tags": ["a", "c", "b"], "other": null })),
+            vec![
+                "`/other` is not allowed",
+                "`/tags` must have at most 2 items",
+                "`/tags/1` must be one of [\"a\",\"b\"]",
+            ]
+        );
+
+        assert!("{".parse::<ManifestSchema>().is_err());
+        assert!(r#"{ "pattern": "^a" }"#.parse::<ManifestSchema>().is_err());
+        assert!(r#"{ "type": "float" }"#.parse::<ManifestSchema>().is_err());
+        assert!(r#"{ "properties": { "a": 1 } }"#.parse::<ManifestSchema>().is_err());
+    }
+}
@@ -0,0 +1,893 @@
+// SPDX-FileCopyrightText: 2022 Profian Inc. <opensource@profian.com>
+// SPDX-License-Identifier: AGPL-3.0-only
+
+use super::{
+    AuthDecision, CircuitState, Endpoint, MaintenanceSummary, RepairOutcome, ResourceLimit, Route,
+    RouteClass,
+};
+
+use std::collections::BTreeMap;
+use std::fmt::{self, Write};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, AtomicU8, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use axum::body::{boxed, Body, BoxBody, Bytes};
+use axum::http::header::{ACCEPT, CONTENT_TYPE};
+use axum::http::{HeaderMap, HeaderValue, Request};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use futures::TryStreamExt;
+use hyper::body::{HttpBody, SizeHint};
+
+/// Content type of metrics exposed in the classic Prometheus text format.
+const PROMETHEUS_TEXT: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+/// Content type of metrics exposed in the OpenMetrics text format.
+const OPENMETRICS_TEXT: &str = "application/openmetrics-text; version=1.0.0; charset=utf-8";
+
+/// Exposition format of metrics.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Format {
+    /// Classic Prometheus text format.
+    Prometheus,
+    /// OpenMetrics text format.
+    OpenMetrics,
+}
+
+impl Format {
+    /// Returns [Format::OpenMetrics] if `accept` contains an acceptable
+    /// `application/openmetrics-text` media range and [Format::Prometheus] otherwise.
+    pub(crate) fn negotiate(accept: Option<&HeaderValue>) -> Self {
+        let accepts_openmetrics = accept
+            .and_then(|accept| accept.to_str().ok())
+            .into_iter()
+            .flat_map(|accept| accept.split(','))
+            .any(|range| {
+                let mut params = range.split(';').map(str::trim);
+                params
+                    .next()
+                    .is_some_and(|mime| mime.eq_ignore_ascii_case("application/openmetrics-text"))
+                    && params
+                        .filter_map(|param| param.split_once('='))
+                        .filter(|(name, _)| name.trim().eq_ignore_ascii_case("q"))
+                        .all(|(_, q)| q.trim().parse::<f32>().is_ok_and(|q| q > 0.0))
+            });
+        if accepts_openmetrics {
+            Self::OpenMetrics
+        } else {
+            Self::Prometheus
+        }
+    }
+
+    fn content_type(self) -> &'static str {
+        match self {
+            Self::Prometheus => PROMETHEUS_TEXT,
+            Self::OpenMetrics => OPENMETRICS_TEXT,
+        }
+    }
+}
+
+/// Upper bounds of the buckets of the request duration histogram in seconds.
+const DURATION_BUCKETS: [f64; 12] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0,
+];
+
+/// Outcome of a TLS handshake.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HandshakeOutcome {
+    /// The handshake completed and the client presented a trusted certificate.
+    ClientCertificate,
+    /// The handshake completed without a client certificate.
+    NoClientCertificate,
+    /// The handshake failed, e.g. because the client presented an untrusted certificate.
+    Failed,
+    /// The handshake did not complete within the handshake timeout.
+    TimedOut,
+}
+
+impl HandshakeOutcome {
+    /// All outcomes.
+    pub const ALL: [Self; 4] = [
+        Self::ClientCertificate,
+        Self::NoClientCertificate,
+        Self::Failed,
+        Self::TimedOut,
+    ];
+}
+
+impl fmt::Display for HandshakeOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ClientCertificate => write!(f, "client-certificate"),
+            Self::NoClientCertificate => write!(f, "no-client-certificate"),
+            Self::Failed => write!(f, "failed"),
+            Self::TimedOut => write!(f, "timed-out"),
+        }
+    }
+}
+
+/// Outcome of the verification of an OpenID Connect token.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TokenOutcome {
+    /// The token is valid.
+    Valid,
+    /// The token is expired.
+    Expired,
+    /// The token is invalid for any other reason, e.g. its signature.
+    Invalid,
+}
+
+impl TokenOutcome {
+    /// All outcomes.
+    pub const ALL: [Self; 3] = [Self::Valid, Self::Expired, Self::Invalid];
+}
+
+impl fmt::Display for TokenOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Valid => write!(f, "valid"),
+            Self::Expired => write!(f, "expired"),
+            Self::Invalid => write!(f, "invalid"),
+        }
+    }
+}
+
+/// Histogram of the durations of requests.
+#[derive(Clone, Debug, Default)]
+struct Histogram {
+    /// Cumulative number of observations of at most each of [DURATION_BUCKETS].
+    buckets: [u64; DURATION_BUCKETS.len()],
+    count: u64,
+    sum: Duration,
+}
+
+impl Histogram {
+    fn observe(&mut self, duration: Duration) {
+        let secs = duration.as_secs_f64();
+        for (bucket, bound) in self.buckets.iter_mut().zip(DURATION_BUCKETS) {
+            if secs <= bound {
+                *bucket += 1;
+            }
+        }
+        self.count += 1;
+        self.sum += duration;
+    }
+}
+
+/// Bytes transferred in bodies of requests to a repository.
+#[derive(Debug, Default)]
+struct Transfers {
+    uploaded: AtomicU64,
+    downloaded: AtomicU64,
+}
+
+/// Server metrics.
+///
+/// Metrics are owned by the [App](crate::App) and are not affected by configuration reloads,
+/// i.e. counters are monotonic for the lifetime of the process.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    accepted_connections: AtomicU64,
+    active_connections: AtomicU64,
+    authorization_decisions: [AtomicU64; AuthDecision::ALL.len()],
+    /// Bytes reserved by bodies in flight, see [InflightBytes](crate::inflight::InflightBytes).
+    pub(crate) inflight_bytes: AtomicU64,
+    near_limit: [AtomicU64; ResourceLimit::ALL.len()],
+    negative_cache_hits: AtomicU64,
+    /// [CircuitState] of the store as its discriminant.
+    store_circuit: AtomicU8,
+    store_circuit_trips: AtomicU64,
+    read_repairs: [AtomicU64; RepairOutcome::ALL.len()],
+    maintenance_runs: AtomicU64,
+    /// Completion time of the last maintenance pass in seconds since the Unix epoch.
+    maintenance_last_run: AtomicU64,
+    maintenance_last_summary: Mutex<MaintenanceSummary>,
+    tls_handshakes: [AtomicU64; HandshakeOutcome::ALL.len()],
+    oidc_tokens: [AtomicU64; TokenOutcome::ALL.len()],
+    /// Durations of requests by their route class, if any, and status code class.
+    request_durations: Mutex<BTreeMap<(Option<String>, u16), Histogram>>,
+    /// Transfers by repository, which are only tracked once a request to it succeeded.
+    transfers: Mutex<BTreeMap<String, Arc<Transfers>>>,
+}
+
+impl Metrics {
+    /// Returns the total number of accepted connections.
+    pub fn accepted_connections(&self) -> u64 {
+        self.accepted_connections.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of currently active connections.
+    pub fn active_connections(&self) -> u64 {
+        self.active_connections.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of authorization checks, which resulted in `decision`.
+    pub fn authorization_decisions(&self, decision: AuthDecision) -> u64 {
+        self.authorization_decisions[decision as usize].load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of bytes currently transferred in request and response bodies.
+    ///
+    /// Bodies are only accounted for if [Builder::max_inflight_bytes](crate::Builder::max_inflight_bytes)
+    /// is set.
+    pub fn inflight_bytes(&self) -> u64 {
+        self.inflight_bytes.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of requests admitted while usage of `limit` was near the limit,
+    /// i.e. at or above [Builder::limit_warning_percent](crate::Builder::limit_warning_percent)
+    /// of it.
+    pub fn near_limit(&self, limit: ResourceLimit) -> u64 {
+        self.near_limit[limit as usize].load(Ordering::Relaxed)
+    }
+
+    /// Records usage of `limit` near the limit.
+    pub(crate) fn record_near_limit(&self, limit: ResourceLimit) {
+        _ = self.near_limit[limit as usize].fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Returns the number of requests served a cached `404 Not Found` response.
+    pub fn negative_cache_hits(&self) -> u64 {
+        self.negative_cache_hits.load(Ordering::Relaxed)
+    }
+
+    /// Records a request served a cached `404 Not Found` response.
+    pub(crate) fn record_negative_cache_hit(&self) {
+        _ = self.negative_cache_hits.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Returns the state of the circuit breaker of the store, which is always closed for stores
+    /// without one.
+    pub fn store_circuit(&self) -> CircuitState {
+        match self.store_circuit.load(Ordering::Relaxed) {
+            s if s == CircuitState::Open as u8 => CircuitState::Open,
+            s if s == CircuitState::HalfOpen as u8 => CircuitState::HalfOpen,
+            _ => CircuitState::Closed,
+        }
+    }
+
+    /// Records a transition of the circuit breaker of the store to `state`.
+    pub(crate) fn record_store_circuit(&self, state: CircuitState) {
+        self.store_circuit.store(state as u8, Ordering::Relaxed)
+    }
+
+    /// Returns the number of times the circuit breaker of the store opened after the store
+    /// failed persistently.
+    pub fn store_circuit_trips(&self) -> u64 {
+        self.store_circuit_trips.load(Ordering::Relaxed)
+    }
+
+    /// Records the circuit breaker of the store opening after the store failed persistently.
+    pub(crate) fn record_store_circuit_trip(&self) {
+        _ = self.store_circuit_trips.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Returns the number of reads of contents, which did not match their digest, repaired
+    /// from the replica of the store with `outcome`.
+    pub fn read_repairs(&self, outcome: RepairOutcome) -> u64 {
+        self.read_repairs[outcome as usize].load(Ordering::Relaxed)
+    }
+
+    /// Records a repair of contents from the replica of the store with `outcome`.
+    pub(crate) fn record_read_repair(&self, outcome: RepairOutcome) {
+        _ = self.read_repairs[outcome as usize].fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Returns the number of completed maintenance passes.
+    pub fn maintenance_runs(&self) -> u64 {
+        self.maintenance_runs.load(Ordering::Relaxed)
+    }
+
+    /// Returns the time, at which the last maintenance pass completed, if any did.
+    pub fn maintenance_last_run(&self) -> Option<SystemTime> {
+        match self.maintenance_last_run.load(Ordering::Relaxed) {
+            0 => None,
+            secs => Some(UNIX_EPOCH + Duration::from_secs(secs)),
+        }
+    }
+
+    /// Returns the summary of the last maintenance pass.
+    pub fn maintenance_last_summary(&self) -> MaintenanceSummary {
+        *self
+            .maintenance_last_summary
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Records a maintenance pass completed at `time`.
+    pub(crate) fn record_maintenance(&self, time: SystemTime, summary: MaintenanceSummary) {
+        *self
+            .maintenance_last_summary
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner) = summary;
+        let secs = time
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_secs());
+        self.maintenance_last_run.store(secs, Ordering::Relaxed);
+        _ = self.maintenance_runs.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Records the outcome of an authorization check.
+    pub(crate) fn record_authorization(&self, decision: AuthDecision) {
+        _ = self.authorization_decisions[decision as usize].fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Returns the number of TLS handshakes, which resulted in `outcome`.
+    pub fn tls_handshakes(&self, outcome: HandshakeOutcome) -> u64 {
+        self.tls_handshakes[outcome as usize].load(Ordering::Relaxed)
+    }
+
+    /// Records the outcome of a TLS handshake.
+    pub(crate) fn record_tls_handshake(&self, outcome: HandshakeOutcome) {
+        _ = self.tls_handshakes[outcome as usize].fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Returns the number of verified OpenID Connect tokens, which resulted in `outcome`.
+    pub fn oidc_tokens(&self, outcome: TokenOutcome) -> u64 {
+        self.oidc_tokens[outcome as usize].load(Ordering::Relaxed)
+    }
+
+    /// Records the outcome of the verification of an OpenID Connect token.
+    pub(crate) fn record_oidc_token(&self, outcome: TokenOutcome) {
+        _ = self.oidc_tokens[outcome as usize].fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Returns the number of handled requests, whose durations were recorded.
+    pub fn requests(&self) -> u64 {
+        self.request_durations
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .values()
+            .map(|histogram| histogram.count)
+            .sum()
+    }
+
+    /// Records a request of `class`, which was responded to with `status` after `duration`.
+    fn record_request(&self, class: Option<RouteClass>, status: u16, duration: Duration) {
+        self.request_durations
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .entry((class.map(|class| class.to_string()), status / 100))
+            .or_default()
+            .observe(duration);
+    }
+
+    /// Returns the number of bytes received in request bodies of successful requests to
+    /// `repository`, which is given as `user/repository`.
+    pub fn uploaded_bytes(&self, repository: &str) -> u64 {
+        self.transfers
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(repository)
+            .map_or(0, |transfers| transfers.uploaded.load(Ordering::Relaxed))
+    }
+
+    /// Returns the number of bytes sent in response bodies of successful requests to
+    /// `repository`, which is given as `user/repository`.
+    pub fn downloaded_bytes(&self, repository: &str) -> u64 {
+        self.transfers
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(repository)
+            .map_or(0, |transfers| transfers.downloaded.load(Ordering::Relaxed))
+    }
+
+    fn transfers(&self, repository: String) -> Arc<Transfers> {
+        Arc::clone(
+            self.transfers
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .entry(repository)
+                .or_default(),
+        )
+    }
+
+    /// Records an accepted connection, which is considered active until the returned guard is
+    /// dropped.
+    pub(crate) fn accept_connection(&self) -> ConnectionGuard<'_> {
+        _ = self.accepted_connections.fetch_add(1, Ordering::Relaxed);
+        _ = self.active_connections.fetch_add(1, Ordering::Relaxed);
+        ConnectionGuard(self)
+    }
+
+    /// Renders the metrics in `format`.
+    ///
+    /// Counters are named with a `_total` suffix in both formats, while only OpenMetrics omits
+    /// it from the metric family name and terminates the exposition with `# EOF`.
+    pub(crate) fn render(&self, format: Format) -> String {
+        let mut out = String::new();
+        let mut family = |name: &str, kind: &str, help: &str, samples: &[(String, u64)]| {
+            let family = match (format, kind) {
+                (Format::Prometheus, "counter") => format!("{name}_total"),
+                _ => name.to_string(),
+            };
+            let sample = match kind {
+                "counter" => format!("{name}_total"),
+                _ => name.to_string(),
+            };
+            // Writing to a `String` never fails.
+            _ = writeln!(out, "# HELP {family} {help}");
+            _ = writeln!(out, "# TYPE {family} {kind}");
+            for (labels, value) in samples {
+                _ = writeln!(out, "{sample}{labels} {value}");
+            }
+        };
+        family(
+            "drawbridge_connections_accepted",
+            "counter",
+            "Number of accepted connections.",
+            &[(String::new(), self.accepted_connections())],
+        );
+        family(
+            "drawbridge_connections_active",
+            "gauge",
+            "Number of currently active connections.",
+            &[(String::new(), self.active_connections())],
+        );
+        family(
+            "drawbridge_authorization_decisions",
+            "counter",
+            "Number of authorization checks by their outcome.",
+            &AuthDecision::ALL.map(|decision| {
+                (
+                    format!("{{decision=\"{decision}\"}}"),
+                    self.authorization_decisions(decision),
+                )
+            }),
+        );
+        family(
+            "drawbridge_inflight_bytes",
+            "gauge",
+            "Number of bytes currently transferred in request and response bodies.",
+            &[(String::new(), self.inflight_bytes())],
+        );
+        family(
+            "drawbridge_near_limit",
+            "counter",
+            "Number of requests admitted while usage was near a limit by the limit.",
+            &ResourceLimit::ALL
+                .map(|limit| (format!("{{limit=\"{limit}\"}}"), self.near_limit(limit))),
+        );
+        family(
+            "drawbridge_negative_cache_hits",
+            "counter",
+            "Number of requests served a cached `404 Not Found` response.",
+            &[(String::new(), self.negative_cache_hits())],
+        );
+        let store_circuit = self.store_circuit();
+        family(
+            "drawbridge_store_circuit_state",
+            "gauge",
+            "Whether the circuit breaker of the store is in a state by the state.",
+            &CircuitState::ALL.map(|state| {
+                (
+                    format!("{{state=\"{state}\"}}"),
+                    u64::from(state == store_circuit),
+                )
+            }),
+        );
+        family(
+            "drawbridge_store_circuit_trips",
+            "counter",
+            "Number of times the circuit breaker of the store opened after the store failed persistently.",
+            &[(String::new(), self.store_circuit_trips())],
+        );
+        family(
+            "drawbridge_read_repairs",
+            "counter",
+            "Number of reads of contents not matching their digest repaired from the replica by their outcome.",
+            &RepairOutcome::ALL.map(|outcome| {
+                (
+                    format!("{{outcome=\"{outcome}\"}}"),
+                    self.read_repairs(outcome),
+                )
+            }),
+        );
+        family(
+            "drawbridge_maintenance_runs",
+            "counter",
+            "Number of completed maintenance passes.",
+            &[(String::new(), self.maintenance_runs())],
+        );
+        family(
+            "drawbridge_maintenance_last_run_timestamp_seconds",
+            "gauge",
+            "Completion time of the last maintenance pass in seconds since the Unix epoch.",
+            &[(
+                String::new(),
+                self.maintenance_last_run.load(Ordering::Relaxed),
+            )],
+        );
+        let summary = self.maintenance_last_summary();
+        family(
+            "drawbridge_maintenance_last_removed",
+            "gauge",
+            "Number of items removed by the last maintenance pass by their kind.",
+            &[
+                ("temporary-files", summary.temporary_files),
+                ("partial-objects", summary.partial_objects),
+                ("unreferenced-nodes", summary.unreferenced_nodes),
+                ("deletion-remnants", summary.deletion_remnants),
+                ("upload-sessions", summary.upload_sessions),
+                ("idempotency-records", summary.idempotency_records),
+                ("authz-decisions", summary.authz_decisions),
+            ]
+            .map(|(kind, n)| (format!("{{kind=\"{kind}\"}}"), n)),
+        );
+        family(
+            "drawbridge_maintenance_last_reclaimed_bytes",
+            "gauge",
+            "Number of bytes of the store reclaimed by the last maintenance pass.",
+            &[(String::new(), summary.reclaimed_bytes)],
+        );
+        family(
+            "drawbridge_maintenance_last_failures",
+            "gauge",
+            "Number of tasks of the last maintenance pass, which failed.",
+            &[(String::new(), summary.failures)],
+        );
+        family(
+            "drawbridge_tls_handshakes",
+            "counter",
+            "Number of TLS handshakes by their outcome.",
+            &HandshakeOutcome::ALL.map(|outcome| {
+                (
+                    format!("{{outcome=\"{outcome}\"}}"),
+                    self.tls_handshakes(outcome),
+                )
+            }),
+        );
+        family(
+            "drawbridge_oidc_tokens",
+            "counter",
+            "Number of verified OpenID Connect tokens by their outcome.",
+            &TokenOutcome::ALL.map(|outcome| {
+                (
+                    format!("{{outcome=\"{outcome}\"}}"),
+                    self.oidc_tokens(outcome),
+                )
+            }),
+        );
+        let transfers: Vec<_> = self
+            .transfers
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .iter()
+            .map(|(repository, transfers)| {
+                (
+                    format!("{{repository=\"{repository}\"}}"),
+                    transfers.uploaded.load(Ordering::Relaxed),
+                    transfers.downloaded.load(Ordering::Relaxed),
+                )
+            })
+            .collect();
+        family(
+            "drawbridge_repository_uploaded_bytes",
+            "counter",
+            "Number of bytes received in request bodies of successful requests by repository.",
+            &transfers
+                .iter()
+                .map(|(labels, uploaded, _)| (labels.clone(), *uploaded))
+                .collect::<Vec<_>>(),
+        );
+        family(
+            "drawbridge_repository_downloaded_bytes",
+            "counter",
+            "Number of bytes sent in response bodies of successful requests by repository.",
+            &transfers
+                .iter()
+                .map(|(labels, _, downloaded)| (labels.clone(), *downloaded))
+                .collect::<Vec<_>>(),
+        );
+
+        // Histograms are named alike in both formats.
+        let name = "drawbridge_request_duration_seconds";
+        _ = writeln!(
+            out,
+            "# HELP {name} Duration of handling requests until the response head is ready by route class and status code class."
+        );
+        _ = writeln!(out, "# TYPE {name} histogram");
+        let durations = self
+            .request_durations
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone();
+        for ((class, status), histogram) in durations {
+            let labels = format!(
+                "class=\"{}\",code=\"{status}xx\"",
+                class.as_deref().unwrap_or("other")
+            );
+            for (bound, n) in DURATION_BUCKETS.iter().zip(histogram.buckets) {
+                _ = writeln!(out, "{name}_bucket{{{labels},le=\"{bound:?}\"}} {n}");
+            }
+            _ = writeln!(
+                out,
+                "{name}_bucket{{{labels},le=\"+Inf\"}} {}",
+                histogram.count
+            );
+            _ = writeln!(
+                out,
+                "{name}_sum{{{labels}}} {:?}",
+                histogram.sum.as_secs_f64()
+            );
+            _ = writeln!(out, "{name}_count{{{labels}}} {}", histogram.count);
+        }
+        if format == Format::OpenMetrics {
+            out.push_str("# EOF\n");
+        }
+        out
+    }
+}
+
+/// Guard of an active connection returned by [Metrics::accept_connection].
+#[derive(Debug)]
+pub(crate) struct ConnectionGuard<'a>(&'a Metrics);
+
+impl Drop for ConnectionGuard<'_> {
+    fn drop(&mut self) {
+        _ = self.0.active_connections.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Response body, which counts the bytes sent in it towards the downloads of a repository.
+struct Counted {
+    inner: BoxBody,
+    transfers: Arc<Transfers>,
+}
+
+impl HttpBody for Counted {
+    type Data = Bytes;
+    type Error = axum::Error;
+
+    fn poll_data(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        let data = Pin::new(&mut self.inner).poll_data(cx);
+        if let Poll::Ready(Some(Ok(ref data))) = data {
+            _ = self
+                .transfers
+                .downloaded
+                .fetch_add(data.len() as u64, Ordering::Relaxed);
+        }
+        data
+    }
+
+    fn poll_trailers(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Option<HeaderMap>, Self::Error>> {
+        Pin::new(&mut self.inner).poll_trailers(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+/// Returns the repository `path` is routed to as `user/repository`, if any.
+fn repository(path: &str) -> Option<String> {
+    // Only valid names are used as label values.
+    Route::parse(path)
+        .ok()?
+        .repository()
+        .map(|repo| repo.to_string())
+}
+
+/// Records the duration of handling `req` in `metrics` along with the bytes transferred in
+/// the request and response bodies, if `req` is a successful request to a repository.
+///
+/// Only bytes of request bodies received until the response is ready are accounted for.
+pub(crate) async fn observe(
+    metrics: Arc<Metrics>,
+    req: Request<Body>,
+    next: Next<Body>,
+) -> Response {
+    let start = Instant::now();
+    let class = Endpoint::of(req.uri().path()).map(RouteClass::of);
+    let repository = repository(req.uri().path());
+    let uploaded = Arc::new(AtomicU64::new(0));
+    let req = if repository.is_some() {
+        let uploaded = Arc::clone(&uploaded);
+        req.map(|body| {
+            Body::wrap_stream(body.inspect_ok(move |chunk| {
+                _ = uploaded.fetch_add(chunk.len() as u64, Ordering::Relaxed);
+            }))
+        })
+    } else {
+        req
+    };
+    let res = next.run(req).await;
+    metrics.record_request(class, res.status().as_u16(), start.elapsed());
+
+    // Requests are only attributed to repositories once they succeeded, such that requests to
+    // names, which do not exist, do not add to the tracked repositories.
+    let Some(repository) = repository.filter(|_| res.status().is_success()) else {
+        return res;
+    };
+    let transfers = metrics.transfers(repository);
+    _ = transfers
+        .uploaded
+        .fetch_add(uploaded.load(Ordering::Relaxed), Ordering::Relaxed);
+    res.map(|inner| boxed(Counted { inner, transfers }))
+}
+
+/// Serves `metrics` in the format negotiated using the `Accept` header of the request.
+pub(crate) async fn expose(metrics: Arc<Metrics>, headers: HeaderMap) -> Response {
+    let format = Format::negotiate(headers.get(ACCEPT));
+    (
+        [(CONTENT_TYPE, format.content_type())],
+        metrics.render(format),
+    )
+        .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render() {
+        let negotiate =
+            |accept: &'static str| Format::negotiate(Some(&HeaderValue::from_static(accept)));
+        assert_eq!(Format::negotiate(None), Format::Prometheus);
+        assert_eq!(negotiate("*/*"), Format::Prometheus);
+        assert_eq!(negotiate("text/plain"), Format::Prometheus);
+        assert_eq!(
+            negotiate("application/openmetrics-text"),
+            Format::OpenMetrics
+        );
+        assert_eq!(
+            negotiate("application/openmetrics-text; version=1.0.0; charset=utf-8; q=0.5, text/plain; q=0.1"),
+            Format::OpenMetrics
+        );
+        assert_eq!(
+            negotiate("application/openmetrics-text;q=0"),
+            Format::Prometheus
+        );
+
+        let metrics = Metrics::default();
+        let _conn = metrics.accept_connection();
+        metrics.record_authorization(AuthDecision::DeniedAcl);
+
+        let prometheus = metrics.render(Format::Prometheus);
+        assert!(prometheus.contains(
+            "# TYPE drawbridge_connections_accepted_total counter\ndrawbridge_connections_accepted_total 1\n"
+        ), "{prometheus}");
+        assert!(
+            prometheus.contains(
+                "# TYPE drawbridge_connections_active gauge\ndrawbridge_connections_active 1\n"
+            ),
+            "{prometheus}"
+        );
+        assert!(
+            prometheus
+                .contains("drawbridge_authorization_decisions_total{decision=\"denied-acl\"} 1\n"),
+            "{prometheus}"
+        );
+        assert!(
+            prometheus.contains(
+                "drawbridge_authorization_decisions_total{decision=\"granted-public\"} 0\n"
+            ),
+            "{prometheus}"
+        );
+        assert!(
+            prometheus
+                .contains("# TYPE drawbridge_inflight_bytes gauge\ndrawbridge_inflight_bytes 0\n"),
+            "{prometheus}"
+        );
+        assert!(
+            prometheus.contains("drawbridge_near_limit_total{limit=\"read-slots\"} 0\n"),
+            "{prometheus}"
+        );
+        assert!(
+            prometheus.contains("drawbridge_store_circuit_state{state=\"closed\"} 1\n"),
+            "{prometheus}"
+        );
+        assert!(
+            prometheus.contains("drawbridge_store_circuit_state{state=\"half-open\"} 0\n"),
+            "{prometheus}"
+        );
+        assert!(
+            prometheus.contains("drawbridge_maintenance_runs_total 0\n"),
+            "{prometheus}"
+        );
+        assert!(!prometheus.contains("# EOF"), "{prometheus}");
+
+        metrics.record_maintenance(
+            UNIX_EPOCH + Duration::from_secs(1_000),
+            MaintenanceSummary {
+                temporary_files: 2,
+                reclaimed_bytes: 42,
+                ..Default::default()
+            },
+        );
+        assert_eq!(
+            metrics.maintenance_last_run(),
+            Some(UNIX_EPOCH + Duration::from_secs(1_000))
+        );
+        let prometheus = metrics.render(Format::Prometheus);
+        for line in [
+            "drawbridge_maintenance_runs_total 1\n",
+            "drawbridge_maintenance_last_run_timestamp_seconds 1000\n",
+            "drawbridge_maintenance_last_removed{kind=\"temporary-files\"} 2\n",
+            "drawbridge_maintenance_last_removed{kind=\"partial-objects\"} 0\n",
+            "drawbridge_maintenance_last_removed{kind=\"unreferenced-nodes\"} 0\n",
+            "drawbridge_maintenance_last_reclaimed_bytes 42\n",
+        ] {
+            assert!(prometheus.contains(line), "{prometheus}");
+        }
+
+        let openmetrics = metrics.render(Format::OpenMetrics);
+        assert!(openmetrics.contains(
+            "# TYPE drawbridge_connections_accepted counter\ndrawbridge_connections_accepted_total 1\n"
+        ), "{openmetrics}");
+        assert!(
+            openmetrics
+                .contains("drawbridge_authorization_decisions_total{decision=\"denied-acl\"} 1\n"),
+            "{openmetrics}"
+        );
+        assert!(openmetrics.ends_with("\n# EOF\n"), "{openmetrics}");
+    }
+
+    #[test]
+    fn render_requests() {
+        let metrics = Metrics::default();
+        metrics.record_tls_handshake(HandshakeOutcome::TimedOut);
+        metrics.record_oidc_token(TokenOutcome::Expired);
+        metrics.record_request(Some(RouteClass::Trees), 200, Duration::from_millis(20));
+        metrics.record_request(None, 404, Duration::from_secs(60));
+        _ = metrics
+            .transfers("user/repo".into())
+            .uploaded
+            .fetch_add(42, Ordering::Relaxed);
+        assert_eq!(metrics.requests(), 2);
+        assert_eq!(metrics.uploaded_bytes("user/repo"), 42);
+        assert_eq!(metrics.downloaded_bytes("user/repo"), 0);
+        assert_eq!(metrics.uploaded_bytes("user/other"), 0);
+
+        for format in [Format::Prometheus, Format::OpenMetrics] {
+            let out = metrics.render(format);
+            for line in [
+                "drawbridge_tls_handshakes_total{outcome=\"timed-out\"} 1\n",
+                "drawbridge_tls_handshakes_total{outcome=\"failed\"} 0\n",
+                "drawbridge_oidc_tokens_total{outcome=\"expired\"} 1\n",
+                "drawbridge_repository_uploaded_bytes_total{repository=\"user/repo\"} 42\n",
+                "drawbridge_repository_downloaded_bytes_total{repository=\"user/repo\"} 0\n",
+                "# TYPE drawbridge_request_duration_seconds histogram\n",
+                "drawbridge_request_duration_seconds_bucket{class=\"trees\",code=\"2xx\",le=\"0.01\"} 0\n",
+                "drawbridge_request_duration_seconds_bucket{class=\"trees\",code=\"2xx\",le=\"0.025\"} 1\n",
+                "drawbridge_request_duration_seconds_bucket{class=\"trees\",code=\"2xx\",le=\"+Inf\"} 1\n",
+                "drawbridge_request_duration_seconds_sum{class=\"trees\",code=\"2xx\"} 0.02\n",
+                "drawbridge_request_duration_seconds_count{class=\"trees\",code=\"2xx\"} 1\n",
+                "drawbridge_request_duration_seconds_bucket{class=\"other\",code=\"4xx\",le=\"30.0\"} 0\n",
+                "drawbridge_request_duration_seconds_bucket{class=\"other\",code=\"4xx\",le=\"+Inf\"} 1\n",
+            ] {
+                assert!(out.contains(line), "{out}");
+            }
+        }
+    }
+
+    #[test]
+    fn repository() {
+        assert_eq!(
+            super::repository("/api/v0.1.0/user/repo/_tag/0.1.0/tree/a/b").as_deref(),
+            Some("user/repo")
+        );
+        assert_eq!(
+            super::repository("/api/v0.1.0/user/repo").as_deref(),
+            Some("user/repo")
+        );
+        assert_eq!(super::repository("/api/v0.1.0/user"), None);
+        assert_eq!(super::repository("/api/v0.1.0/user/_tokens"), None);
+        assert_eq!(super::repository("/api/v0.1.0/user/re\"po"), None);
+        assert_eq!(super::repository("/metrics"), None);
+    }
+}
@@ -0,0 +1,786 @@
+// SPDX-FileCopyrightText: 2022 Profian Inc. <opensource@profian.com>
+// SPDX-License-Identifier: AGPL-3.0-only
+
+use super::auth::{read_certificates, read_key};
+use super::{read_only, CreateError, Endpoint, GetError, RemoveError, Route, Store, API_VERSION};
+
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, BufRead, Read, Seek};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+use drawbridge_jose::jws::{Flattened, General, Jws};
+use drawbridge_jose::MediaTyped;
+use drawbridge_type::digest::{Algorithms, ContentDigest};
+use drawbridge_type::{
+    Meta, RepositoryChange, RepositoryChangeKind, RepositoryConfig, RepositoryContext, TagContext,
+    TagEntry, TreeDirectory, TreeEntry, TreePath, UserContext, UserRecord,
+};
+
+use anyhow::{anyhow, bail, ensure, Context};
+use async_std::task::spawn_blocking;
+use axum::body::Body;
+use axum::http::header::{ACCEPT_ENCODING, AUTHORIZATION, CONTENT_LENGTH, CONTENT_TYPE};
+use axum::http::{Method, Request, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use futures::lock::Mutex as AsyncMutex;
+use openidconnect::url::Url;
+use rustls::{Certificate, ClientConfig, OwnedTrustAnchor, PrivateKey, RootCertStore};
+use serde::de::DeserializeOwned;
+use tracing::{debug, info, warn};
+
+/// Default duration, after which tags fetched from the upstream are revalidated against it.
+pub const DEFAULT_MIRROR_TTL: Duration = Duration::from_secs(60);
+
+/// Duration, after which fetching a tag from the upstream is retried after it failed, unless
+/// the TTL is shorter.
+const RETRY_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Timeout of establishing connections to the upstream.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Timeout of each read from connections to the upstream.
+const READ_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Maximum size of user records, repository configurations and tag entries fetched from the
+/// upstream.
+const MAX_DOCUMENT_SIZE: u64 = 1 << 20;
+
+/// Number of tracked tags, above which tags not being revalidated are no longer tracked.
+const MAX_TRACKED_TAGS: usize = 4096;
+
+/// Configuration of the upstream Drawbridge server mirrored by the store.
+pub struct MirrorConfig {
+    url: Url,
+    token_file: Option<PathBuf>,
+    credentials: Option<(Vec<Certificate>, PrivateKey)>,
+    roots: Option<RootCertStore>,
+    ttl: Duration,
+}
+
+impl fmt::Debug for MirrorConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MirrorConfig")
+            .field("url", &self.url)
+            .field("token_file", &self.token_file)
+            .field("credentials", &self.credentials.is_some())
+            .field("roots", &self.roots.is_some())
+            .field("ttl", &self.ttl)
+            .finish()
+    }
+}
+
+impl MirrorConfig {
+    /// Constructs a new [MirrorConfig] of the Drawbridge server at `url`, e.g.
+    /// `https://store.example.com`, whose certificate is verified using the Mozilla root
+    /// certificates.
+    pub fn new(url: Url) -> Self {
+        Self {
+            url,
+            token_file: None,
+            credentials: None,
+            roots: None,
+            ttl: DEFAULT_MIRROR_TTL,
+        }
+    }
+
+    /// Sets the path of the file containing the OpenID Connect token sent to the upstream,
+    /// which is read again for each request, such that it may be refreshed externally.
+    pub fn token_file(self, token_file: impl Into<PathBuf>) -> Self {
+        Self {
+            token_file: Some(token_file.into()),
+            ..self
+        }
+    }
+
+    /// Reads the client certificate chain presented to the upstream from `certs` and its key
+    /// from `key`.
+    pub fn read_credentials(self, certs: impl BufRead, key: impl BufRead) -> anyhow::Result<Self> {
+        let certs = read_certificates(certs).context("failed to read client certificate chain")?;
+        ensure!(!certs.is_empty(), "client certificate chain empty");
+        let key = read_key(key).context("failed to read client certificate key")?;
+        Ok(Self {
+            credentials: Some((certs, key)),
+            ..self
+        })
+    }
+
+    /// Reads the CA certificates trusted to sign the certificate of the upstream from `cas`,
+    /// which are used instead of the Mozilla root certificates.
+    pub fn read_roots(self, cas: impl BufRead) -> anyhow::Result<Self> {
+        let mut roots = RootCertStore::empty();
+        read_certificates(cas)
+            .context("failed to read CA certificates")?
+            .into_iter()
+            .try_for_each(|ref cert| roots.add(cert))
+            .context("failed to construct root certificate store")?;
+        Ok(Self {
+            roots: Some(roots),
+            ..self
+        })
+    }
+
+    /// Sets the duration, after which tags fetched from the upstream are revalidated against
+    /// it, which defaults to [DEFAULT_MIRROR_TTL].
+    pub fn ttl(self, ttl: Duration) -> Self {
+        Self { ttl, ..self }
+    }
+
+    /// Returns the URL of the upstream.
+    pub fn url(&self) -> &Url {
+        &self.url
+    }
+}
+
+/// Response of the upstream to a request for an object.
+#[derive(Debug)]
+enum Fetched<T> {
+    Found(T),
+    NotFound,
+    /// The credentials of the mirror do not grant access to the object.
+    Denied,
+}
+
+/// Client of the API of the upstream, which blocks on requests.
+#[derive(Clone, Debug)]
+struct Upstream {
+    /// URL of the API without a trailing slash.
+    api: String,
+    token_file: Option<PathBuf>,
+    agent: ureq::Agent,
+}
+
+impl Upstream {
+    fn token(&self) -> anyhow::Result<Option<String>> {
+        self.token_file
+            .as_ref()
+            .map(|path| {
+                std::fs::read_to_string(path)
+                    .map(|token| token.trim().to_string())
+                    .with_context(|| format!("failed to read token file `{}`", path.display()))
+            })
+            .transpose()
+    }
+
+    /// Sends a `GET` request for the object at `path` relative to the API.
+    fn get(&self, path: &str) -> anyhow::Result<Fetched<ureq::Response>> {
+        let req = self
+            .agent
+            .get(&format!("{}/{path}", self.api))
+            .set(ACCEPT_ENCODING.as_str(), "identity");
+        let req = match self.token()? {
+            Some(token) => req.set(AUTHORIZATION.as_str(), &format!("Bearer {token}")),
+            None => req,
+        };
+        match req.call() {
+            Ok(res) => Ok(Fetched::Found(res)),
+            Err(ureq::Error::Status(404, _)) => Ok(Fetched::NotFound),
+            Err(ureq::Error::Status(401 | 403, _)) => Ok(Fetched::Denied),
+            Err(ureq::Error::Status(code, _)) => {
+                bail!("upstream responded to `{path}` with status code {code}")
+            }
+            Err(ureq::Error::Transport(e)) => {
+                Err(anyhow::Error::new(e).context(format!("failed to request `{path}`")))
+            }
+        }
+    }
+
+    /// Fetches the object at `path` of at most `limit` bytes into memory.
+    async fn fetch(&self, path: String, limit: u64) -> anyhow::Result<Fetched<(Meta, Vec<u8>)>> {
+        let upstream = self.clone();
+        spawn_blocking(move || {
+            let res = match upstream.get(&path)? {
+                Fetched::Found(res) => res,
+                Fetched::NotFound => return Ok(Fetched::NotFound),
+                Fetched::Denied => return Ok(Fetched::Denied),
+            };
+            let meta = meta(&res).with_context(|| format!("invalid response to `{path}`"))?;
+            ensure!(
+                meta.size <= limit,
+                "`{path}` of {} bytes exceeds the limit of {limit} bytes",
+                meta.size
+            );
+            let mut buf = vec![];
+            _ = res
+                .into_reader()
+                .take(limit + 1)
+                .read_to_end(&mut buf)
+                .with_context(|| format!("failed to read `{path}`"))?;
+            ensure!(
+                buf.len() as u64 == meta.size,
+                "`{path}` is {} bytes long instead of {} bytes",
+                buf.len(),
+                meta.size
+            );
+            Ok(Fetched::Found((meta, buf)))
+        })
+        .await
+    }
+
+    /// Fetches the JSON document at `path`.
+    async fn fetch_json<T: DeserializeOwned>(
+        &self,
+        path: String,
+    ) -> anyhow::Result<Fetched<(Meta, T)>> {
+        match self.fetch(path.clone(), MAX_DOCUMENT_SIZE).await? {
+            Fetched::Found((meta, buf)) => serde_json::from_slice(&buf)
+                .with_context(|| format!("failed to decode `{path}`"))
+                .map(|v| Fetched::Found((meta, v))),
+            Fetched::NotFound => Ok(Fetched::NotFound),
+            Fetched::Denied => Ok(Fetched::Denied),
+        }
+    }
+
+    /// Fetches the object at `path` of `size` bytes into a temporary file.
+    async fn fetch_file(&self, path: String, size: u64) -> anyhow::Result<Fetched<std::fs::File>> {
+        let upstream = self.clone();
+        spawn_blocking(move || {
+            let res = match upstream.get(&path)? {
+                Fetched::Found(res) => res,
+                Fetched::NotFound => return Ok(Fetched::NotFound),
+                Fetched::Denied => return Ok(Fetched::Denied),
+            };
+            let mut file = tempfile::tempfile().context("failed to create temporary file")?;
+            let n = io::copy(&mut res.into_reader().take(size + 1), &mut file)
+                .with_context(|| format!("failed to read `{path}`"))?;
+            ensure!(
+                n == size,
+                "`{path}` is {n} bytes long instead of {size} bytes"
+            );
+            file.rewind().context("failed to rewind temporary file")?;
+            Ok(Fetched::Found(file))
+        })
+        .await
+    }
+}
+
+/// Returns the metadata of the object contained in `res`.
+fn meta(res: &ureq::Response) -> anyhow::Result<Meta> {
+    let header = |name: &str| {
+        res.header(name)
+            .with_context(|| format!("missing `{name}` header"))
+    };
+    Ok(Meta {
+        hash: header("Content-Digest")?
+            .parse()
+            .context("invalid `Content-Digest` header")?,
+        size: header(CONTENT_LENGTH.as_str())?
+            .parse()
+            .context("invalid `Content-Length` header")?,
+        mime: header(CONTENT_TYPE.as_str())?
+            .parse()
+            .context("invalid `Content-Type` header")?,
+    })
+}
+
+fn get_error(e: GetError<anyhow::Error>) -> anyhow::Error {
+    match e {
+        GetError::NotFound => anyhow!("not found"),
+        GetError::Internal(e) => e,
+    }
+}
+
+fn create_error(e: CreateError<anyhow::Error>) -> anyhow::Error {
+    match e {
+        CreateError::Internal(e) => e,
+        e => anyhow!("{e:?}"),
+    }
+}
+
+fn remove_error(e: RemoveError<anyhow::Error>) -> anyhow::Error {
+    match e {
+        RemoveError::Internal(e) => e,
+        e => anyhow!("{e:?}"),
+    }
+}
+
+/// Revalidation state of a tag.
+#[derive(Debug, Default)]
+struct TagState {
+    checked: Option<Instant>,
+    failed: bool,
+    /// Digest of the tag, whose tree was found to be stored completely.
+    complete: Option<ContentDigest>,
+}
+
+/// Store mirroring an upstream Drawbridge server, into which tags requested by clients are
+/// fetched along with their trees.
+#[derive(Debug)]
+pub(crate) struct Mirror {
+    url: Url,
+    ttl: Duration,
+    upstream: Upstream,
+    tags: Mutex<HashMap<TagContext, Arc<AsyncMutex<TagState>>>>,
+}
+
+impl Mirror {
+    /// Constructs a new [Mirror] of the upstream configured by `config`.
+    pub(crate) fn new(config: MirrorConfig) -> anyhow::Result<Self> {
+        let MirrorConfig {
+            url,
+            token_file,
+            credentials,
+            roots,
+            ttl,
+        } = config;
+        if !matches!(url.scheme(), "http" | "https") {
+            bail!("mirror URL `{url}` must use the `http` or `https` scheme");
+        }
+        if url.cannot_be_a_base() || url.query().is_some() || url.fragment().is_some() {
+            bail!("mirror URL `{url}` must be a base URL without query or fragment");
+        }
+
+        let tls = ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(roots.unwrap_or_else(|| {
+                let mut roots = RootCertStore::empty();
+                roots.add_server_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.0.iter().map(|ta| {
+                    OwnedTrustAnchor::from_subject_spki_name_constraints(
+                        ta.subject,
+                        ta.spki,
+                        ta.name_constraints,
+                    )
+                }));
+                roots
+            }));
+        let tls = match credentials {
+            Some((certs, key)) => tls
+                .with_single_cert(certs, key)
+                .context("invalid mirror client certificate")?,
+            None => tls.with_no_client_auth(),
+        };
+        let agent = ureq::AgentBuilder::new()
+            .timeout_connect(CONNECT_TIMEOUT)
+            .timeout_read(READ_TIMEOUT)
+            .tls_config(Arc::new(tls))
+            .build();
+        let api = format!(
+            "{}/api/v{}",
+            url.as_str().trim_end_matches('/'),
+            *API_VERSION
+        );
+        Ok(Self {
+            url,
+            ttl,
+            upstream: Upstream {
+                api,
+                token_file,
+                agent,
+            },
+            tags: Default::default(),
+        })
+    }
+
+    /// Revalidates the copy of the tag `cx` in `store` against the upstream, unless it was
+    /// revalidated within the TTL, and returns whether the store is in sync with the upstream.
+    ///
+    /// Revalidations of a tag are serialized, such that concurrent requests for a tag missing
+    /// in the store fetch it only once.
+    async fn revalidate(&self, store: &Store, cx: &TagContext) -> bool {
+        let state = {
+            let mut tags = self.tags.lock().unwrap_or_else(PoisonError::into_inner);
+            if tags.len() >= MAX_TRACKED_TAGS && !tags.contains_key(cx) {
+                // Untracked tags are merely revalidated again once requested.
+                tags.retain(|_, state| Arc::strong_count(state) > 1);
+            }
+            Arc::clone(tags.entry(cx.clone()).or_default())
+        };
+        let mut state = state.lock().await;
+        if let Some(checked) = state.checked {
+            let interval = if state.failed {
+                RETRY_INTERVAL.min(self.ttl)
+            } else {
+                self.ttl
+            };
+            if checked.elapsed() < interval {
+                return !state.failed;
+            }
+        }
+        let res = self.sync(store, cx, &mut state.complete).await;
+        if let Err(ref e) = res {
+            warn!(target: "app::mirror", "failed to fetch `{cx}` from upstream: {e:?}");
+        }
+        state.checked = Some(Instant::now());
+        state.failed = res.is_err();
+        res.is_ok()
+    }
+
+    /// Fetches the tag `cx` from the upstream into `store`, unless the same tag is stored, and
+    /// the nodes of its tree missing in `store`, unless `complete` is the digest of the tag.
+    ///
+    /// Stored tags, which differ from the upstream ones, e.g. because they were deleted and
+    /// created again upstream, are replaced and ones missing upstream are removed.
+    async fn sync(
+        &self,
+        store: &Store,
+        cx: &TagContext,
+        complete: &mut Option<ContentDigest>,
+    ) -> anyhow::Result<()> {
+        let path = format!(
+            "{}/{}/_tag/{}",
+            cx.repository.owner, cx.repository.name, cx.name
+        );
+        let (meta, buf) = match self.upstream.fetch(path.clone(), MAX_DOCUMENT_SIZE).await? {
+            Fetched::Found(found) => found,
+            Fetched::NotFound => {
+                *complete = None;
+                if self.remove_tag(store, cx).await? {
+                    info!(target: "app::mirror", "removed `{cx}`, which was removed upstream");
+                }
+                return Ok(());
+            }
+            Fetched::Denied => bail!("upstream denied access to `{cx}`"),
+        };
+        let stored = match store.tag(cx).get_meta().await {
+            Ok(stored) => Some(stored.hash),
+            Err(GetError::NotFound) => None,
+            Err(e) => return Err(get_error(e).context(format!("failed to read `{cx}`"))),
+        };
+        if stored.as_ref() == Some(&meta.hash) && complete.as_ref() == Some(&meta.hash) {
+            return Ok(());
+        }
+        *complete = None;
+
+        let entry: TagEntry = match meta.mime.essence_str() {
+            TreeEntry::<()>::TYPE => serde_json::from_slice(&buf).map(TagEntry::Unsigned),
+            Jws::TYPE => serde_json::from_slice(&buf).map(TagEntry::Signed),
+            mime => bail!("upstream tag `{cx}` has unsupported type `{mime}`"),
+        }
+        .with_context(|| format!("failed to decode upstream tag `{cx}`"))?;
+        let root: TreeEntry = match entry {
+            TagEntry::Unsigned(ref root) => root.clone(),
+            TagEntry::Signed(
+                Jws::General(General { ref payload, .. })
+                | Jws::Flattened(Flattened { ref payload, .. }),
+            ) => payload
+                .as_ref()
+                .context("signed tag entry has no payload")
+                .and_then(|payload| {
+                    serde_json::from_slice(payload).context("failed to decode signed tag entry")
+                })
+                .with_context(|| format!("invalid upstream tag `{cx}`"))?,
+        };
+        if stored.as_ref() != Some(&meta.hash) {
+            if stored.is_some() && self.remove_tag(store, cx).await? {
+                info!(target: "app::mirror", "removed `{cx}`, which changed upstream");
+            }
+            self.create_tag(store, cx, meta.clone(), &entry).await?;
+            info!(target: "app::mirror", "fetched `{cx}` from upstream");
+        }
+        self.fetch_tree(store, cx, &path, root).await?;
+        *complete = Some(meta.hash);
+        Ok(())
+    }
+
+    /// Removes the copy of the tag `cx` from `store` and returns whether there was one.
+    async fn remove_tag(&self, store: &Store, cx: &TagContext) -> anyhow::Result<bool> {
+        match store.repository(&cx.repository).remove_tag(&cx.name).await {
+            Ok(()) => {}
+            Err(RemoveError::NotFound) => return Ok(false),
+            Err(e) => return Err(remove_error(e).context(format!("failed to remove `{cx}`"))),
+        }
+        store
+            .record_change(
+                &cx.repository,
+                RepositoryChange {
+                    kind: RepositoryChangeKind::Deleted,
+                    tag: cx.name.clone(),
+                    path: None,
+                },
+            )
+            .await;
+        Ok(true)
+    }
+
+    /// Stores the tag `cx` fetched from the upstream, along with its repository and owner, if
+    /// missing.
+    async fn create_tag(
+        &self,
+        store: &Store,
+        cx: &TagContext,
+        meta: Meta,
+        entry: &TagEntry,
+    ) -> anyhow::Result<()> {
+        self.create_repository(store, &cx.repository).await?;
+        let repo = store.repository(&cx.repository);
+        let res = match repo.create_tag(&cx.name, meta.clone(), entry).await {
+            // The tag was left partially written, e.g. by a crash.
+            Err(CreateError::Occupied) => {
+                debug!(target: "app::mirror", "replace partially written `{cx}`");
+                repo.remove_tag(&cx.name)
+                    .await
+                    .map_err(|e| remove_error(e).context(format!("failed to remove `{cx}`")))?;
+                repo.create_tag(&cx.name, meta, entry).await
+            }
+            res => res,
+        };
+        _ = res.map_err(|e| create_error(e).context(format!("failed to store `{cx}`")))?;
+        store
+            .record_change(
+                &cx.repository,
+                RepositoryChange {
+                    kind: RepositoryChangeKind::Added,
+                    tag: cx.name.clone(),
+                    path: None,
+                },
+            )
+            .await;
+        Ok(())
+    }
+
+    /// Stores the repository `cx` using its upstream configuration, along with its owner,
+    /// unless it is stored.
+    ///
+    /// Configurations of stored repositories are not revalidated.
+    async fn create_repository(&self, store: &Store, cx: &RepositoryContext) -> anyhow::Result<()> {
+        match store.repository(cx).get_meta().await {
+            Ok(_) => return Ok(()),
+            Err(GetError::NotFound) => {}
+            Err(e) => return Err(get_error(e).context(format!("failed to read `{cx}`"))),
+        }
+        let (meta, conf) = match self
+            .upstream
+            .fetch_json::<RepositoryConfig>(format!("{}/{}", cx.owner, cx.name))
+            .await?
+        {
+            Fetched::Found(found) => found,
+            Fetched::NotFound => bail!("upstream repository `{cx}` does not exist"),
+            Fetched::Denied => bail!("upstream denied access to repository `{cx}`"),
+        };
+        self.create_user(store, &cx.owner).await?;
+        match store
+            .user(&cx.owner)
+            .create_repository(&cx.name, meta, &conf)
+            .await
+        {
+            Ok(_) | Err(CreateError::Occupied) => Ok(()),
+            Err(e) => Err(create_error(e).context(format!("failed to store repository `{cx}`"))),
+        }
+    }
+
+    /// Stores the user `cx` using its upstream record, unless it is stored.
+    ///
+    /// Upstream records are only disclosed to the users themselves, such that users, whose
+    /// record the upstream denies access to, are stored with an empty subject, which matches no
+    /// OpenID Connect token. Their private repositories cannot be read using tokens.
+    async fn create_user(&self, store: &Store, cx: &UserContext) -> anyhow::Result<()> {
+        match store.user(cx).get_meta().await {
+            Ok(_) => return Ok(()),
+            Err(GetError::NotFound) => {}
+            Err(e) => return Err(get_error(e).context(format!("failed to read user `{cx}`"))),
+        }
+        let (meta, rec) = match self
+            .upstream
+            .fetch_json::<UserRecord>(cx.to_string())
+            .await?
+        {
+            Fetched::Found(found) => found,
+            Fetched::NotFound => bail!("upstream user `{cx}` does not exist"),
+            Fetched::Denied => {
+                debug!(target: "app::mirror", "upstream denied access to user `{cx}`, store it without subject");
+                let rec = UserRecord {
+                    subject: String::new(),
+                };
+                let buf = serde_json::to_vec(&rec).context("failed to encode user record")?;
+                let (size, hash) = Algorithms::default()
+                    .read_sync(buf.as_slice())
+                    .context("failed to compute user record digest")?;
+                let meta = Meta {
+                    hash,
+                    size,
+                    mime: mime::APPLICATION_JSON,
+                };
+                (meta, rec)
+            }
+        };
+        match store.create_user(cx, meta, &rec).await {
+            Ok(_) | Err(CreateError::Occupied) => Ok(()),
+            Err(e) => Err(create_error(e).context(format!("failed to store user `{cx}`"))),
+        }
+    }
+
+    /// Fetches the nodes of the tree rooted at `root` of the tag `cx`, which are missing in
+    /// `store`, from the upstream tag at `path`.
+    ///
+    /// Each node is verified against the digest listed by its parent, such that the whole tree
+    /// is verified against the tag entry.
+    async fn fetch_tree(
+        &self,
+        store: &Store,
+        cx: &TagContext,
+        path: &str,
+        root: TreeEntry,
+    ) -> anyhow::Result<()> {
+        let tag = store.tag(cx);
+        let mut nodes = vec![(TreePath::ROOT, root)];
+        while let Some((node_path, TreeEntry { meta, .. })) = nodes.pop() {
+            let node = tag.node(&node_path);
+            let is_stored = match node.get_meta().await {
+                Ok(stored) => stored.hash == meta.hash && stored.size == meta.size,
+                Err(GetError::NotFound) => false,
+                Err(e) => return Err(get_error(e).context(format!("failed to read `{node_path}`"))),
+            };
+            let is_directory = meta.mime.essence_str() == TreeDirectory::<()>::TYPE;
+            let dir = if is_stored && !is_directory {
+                None
+            } else if is_stored {
+                let dir = node
+                    .get_content_json::<TreeDirectory<TreeEntry>>()
+                    .await
+                    .map_err(|e| get_error(e).context(format!("failed to read `{node_path}`")))?;
+                Some(dir)
+            } else {
+                // Nodes left partially written, e.g. by an interrupted fetch, are fetched again.
+                match tag.remove_node(&node_path).await {
+                    Ok(()) | Err(RemoveError::NotFound) => {}
+                    Err(e) => {
+                        return Err(
+                            remove_error(e).context(format!("failed to remove `{node_path}`"))
+                        )
+                    }
+                }
+                let url_path = if node_path.is_empty() {
+                    format!("{path}/tree")
+                } else {
+                    format!("{path}/tree/{node_path}")
+                };
+                if is_directory {
+                    let buf = match self.upstream.fetch(url_path, meta.size).await? {
+                        Fetched::Found((_, buf)) => buf,
+                        Fetched::NotFound => {
+                            bail!("node `{node_path}` of `{cx}` is missing upstream")
+                        }
+                        Fetched::Denied => {
+                            bail!("upstream denied access to node `{node_path}` of `{cx}`")
+                        }
+                    };
+                    let dir = serde_json::from_slice(&buf)
+                        .with_context(|| format!("failed to decode directory `{node_path}`"))?;
+                    _ = tag
+                        .create_directory_node(&node_path, meta, &dir)
+                        .await
+                        .map_err(|e| {
+                            create_error(e).context(format!("failed to store `{node_path}`"))
+                        })?;
+                    Some(dir)
+                } else {
+                    let file = match self.upstream.fetch_file(url_path, meta.size).await? {
+                        Fetched::Found(file) => async_std::fs::File::from(file),
+                        Fetched::NotFound => {
+                            bail!("node `{node_path}` of `{cx}` is missing upstream")
+                        }
+                        Fetched::Denied => {
+                            bail!("upstream denied access to node `{node_path}` of `{cx}`")
+                        }
+                    };
+                    _ = tag
+                        .create_file_node(&node_path, meta, file)
+                        .await
+                        .map_err(|e| {
+                            create_error(e).context(format!("failed to store `{node_path}`"))
+                        })?;
+                    None
+                }
+            };
+            if let Some(dir) = dir {
+                nodes.extend(dir.into_iter().map(|(name, entry)| {
+                    let path = node_path.iter().cloned().chain([name]).collect();
+                    (path, entry)
+                }));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Returns the tag `path` is routed to, if it is the path of a tag or of a node of its tree.
+fn tag(path: &str) -> Option<TagContext> {
+    let route = Route::parse(path).ok()?;
+    if !matches!(route.endpoint, Endpoint::Tag | Endpoint::Tree) {
+        return None;
+    }
+    route.tag()
+}
+
+/// Revalidates tags requested using `GET` and `HEAD` requests against the upstream of
+/// `mirror`, fetching them into `store` if needed, before handling the requests from `store`.
+///
+/// Objects, which are missing in `store` after the revalidation failed, are responded to with
+/// `502 Bad Gateway` instead of `404 Not Found`, since they may well exist upstream. Objects in
+/// `store` are served regardless, e.g. while the upstream is unavailable.
+pub(crate) async fn serve(
+    mirror: Arc<Mirror>,
+    store: Arc<Store>,
+    req: Request<Body>,
+    next: Next<Body>,
+) -> Response {
+    if !matches!(*req.method(), Method::GET | Method::HEAD) {
+        return next.run(req).await;
+    }
+    let Some(cx) = tag(req.uri().path()) else {
+        return next.run(req).await;
+    };
+    let synced = mirror.revalidate(&store, &cx).await;
+    let res = next.run(req).await;
+    if synced || res.status() != StatusCode::NOT_FOUND {
+        return res;
+    }
+    (
+        StatusCode::BAD_GATEWAY,
+        format!("Failed to fetch `{cx}` from upstream"),
+    )
+        .into_response()
+}
+
+/// Rejects all requests, which could modify the store, with `405 Method Not Allowed`, such
+/// that the store never diverges from the upstream of `mirror`.
+pub(crate) async fn reject_writes<B>(
+    mirror: Arc<Mirror>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    if read_only::is_read(req.method()) {
+        return next.run(req).await;
+    }
+    debug!(target: "app::mirror", "reject `{}` request to mirror", req.method());
+    read_only::method_not_allowed(
+        req.uri().path(),
+        format!("Server mirrors `{}`, send writes there instead", mirror.url),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tag() {
+        assert_eq!(
+            super::tag("/api/v0.1.0/user/repo/_tag/0.1.0").map(|cx| cx.to_string()),
+            Some("user/repo:0.1.0".into())
+        );
+        assert_eq!(
+            super::tag("/api/v0.1.0/user/repo/_tag/0.1.0/tree/a/b").map(|cx| cx.to_string()),
+            Some("user/repo:0.1.0".into())
+        );
+        assert!(super::tag("/api/v0.1.0/user/repo/_tag").is_none());
+        assert!(super::tag("/api/v0.1.0/user/repo/_tag/invalid").is_none());
+        assert!(super::tag("/api/v0.1.0/user/repo").is_none());
+        assert!(super::tag("/health").is_none());
+    }
+
+    #[test]
+    fn new() {
+        let url = |url: &str| MirrorConfig::new(url.parse().unwrap());
+        let mirror = Mirror::new(url("https://store.example.com/prefix/")).unwrap();
+        assert_eq!(
+            mirror.upstream.api,
+            format!("https://store.example.com/prefix/api/v{}", *API_VERSION)
+        );
+        assert!(Mirror::new(url("ftp://store.example.com")).is_err());
+        assert!(Mirror::new(url("https://store.example.com/?query")).is_err());
+    }
+}
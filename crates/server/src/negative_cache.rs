@@ -0,0 +1,245 @@
+// SPDX-FileCopyrightText: 2022 Profian Inc. <opensource@profian.com>
+// SPDX-License-Identifier: AGPL-3.0-only
+
+use super::{Metrics, Route, TrustedCertificate};
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+use axum::body::{boxed, Bytes, Empty, Full};
+use axum::http::header::{AUTHORIZATION, CONTENT_TYPE};
+use axum::http::{HeaderValue, Method, Request, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use hyper::body::HttpBody;
+use tracing::{debug, trace};
+
+/// Maximum duration for which `404 Not Found` responses may be cached.
+///
+/// Misses are only cached briefly, since access granted to the principal, which observed
+/// them, may change in the meantime.
+pub const MAX_NEGATIVE_CACHE_TTL: Duration = Duration::from_secs(60);
+
+/// Number of cached responses, above which expired responses are evicted.
+const MAX_CACHED_RESPONSES: usize = 4096;
+
+/// Maximum length of bodies of cached responses.
+const MAX_BODY_LENGTH: u64 = 4096;
+
+/// Request, whose response is cached.
+///
+/// Responses are cached per principal, such that a miss observed by one principal is never
+/// served to another one, which may not be authorized to learn about it.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+struct Key {
+    /// Namespace, i.e. user, containing the requested object.
+    namespace: String,
+    method: Method,
+    uri: String,
+    authorization: Option<HeaderValue>,
+    cert: Option<[u8; 32]>,
+}
+
+/// Cached `404 Not Found` response.
+#[derive(Clone, Debug)]
+struct Miss {
+    content_type: Option<HeaderValue>,
+    body: Bytes,
+    expires: Instant,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    /// Number of invalidations, which is used to detect invalidations racing with lookups in
+    /// the store.
+    generation: u64,
+    misses: HashMap<Key, Miss>,
+}
+
+/// Cache of `404 Not Found` responses to reading requests, which spares repeated store lookups
+/// of missing objects, e.g. of tags clients poll for until they are published.
+///
+/// All responses of a namespace are invalidated once a request modifying it is handled, i.e.
+/// before the client is told about a successful publish.
+#[derive(Debug)]
+pub(crate) struct NegativeCache {
+    ttl: Duration,
+    metrics: Arc<Metrics>,
+    state: Mutex<State>,
+}
+
+impl NegativeCache {
+    /// Constructs a new [NegativeCache] caching responses for `ttl`, whose hits are recorded in
+    /// `metrics`.
+    pub(crate) fn new(ttl: Duration, metrics: Arc<Metrics>) -> Self {
+        Self {
+            ttl,
+            metrics,
+            state: Default::default(),
+        }
+    }
+
+    /// Returns the cached response to `key`, if any, along with the current generation.
+    fn get(&self, key: &Key) -> (Option<Miss>, u64) {
+        let state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        let miss = state
+            .misses
+            .get(key)
+            .filter(|miss| miss.expires > Instant::now())
+            .cloned();
+        (miss, state.generation)
+    }
+
+    /// Caches the response to `key`, unless the cache was invalidated since `generation`.
+    fn insert(
+        &self,
+        key: Key,
+        generation: u64,
+        content_type: Option<HeaderValue>,
+        body: Bytes,
+    ) -> bool {
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        if state.generation != generation {
+            return false;
+        }
+        if state.misses.len() >= MAX_CACHED_RESPONSES {
+            state.misses.retain(|_, miss| miss.expires > now);
+        }
+        _ = state.misses.insert(
+            key,
+            Miss {
+                content_type,
+                body,
+                expires: now + self.ttl,
+            },
+        );
+        true
+    }
+
+    /// Removes all cached responses of `namespace`.
+    fn invalidate(&self, namespace: &str) {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        state.generation += 1;
+        state.misses.retain(|key, _| key.namespace != namespace);
+    }
+}
+
+/// Returns the namespace of the API `path`, if any.
+fn namespace(path: &str) -> Option<String> {
+    Route::parse(path).ok().map(|route| route.user.to_string())
+}
+
+/// Serves `404 Not Found` responses to `GET` and `HEAD` requests from `cache` and invalidates
+/// them once any other request to the namespace is handled.
+pub(crate) async fn serve<B>(
+    cache: Arc<NegativeCache>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let Some(namespace) = namespace(req.uri().path()) else {
+        return next.run(req).await;
+    };
+    if !matches!(*req.method(), Method::GET | Method::HEAD) {
+        let res = next.run(req).await;
+        // Failed requests may have modified the namespace partially.
+        cache.invalidate(&namespace);
+        return res;
+    }
+
+    let key = Key {
+        namespace,
+        method: req.method().clone(),
+        uri: req.uri().to_string(),
+        authorization: req.headers().get(AUTHORIZATION).cloned(),
+        cert: req
+            .extensions()
+            .get::<TrustedCertificate>()
+            .map(|cert| *cert.fingerprint()),
+    };
+    let (miss, generation) = cache.get(&key);
+    if let Some(Miss {
+        content_type, body, ..
+    }) = miss
+    {
+        trace!(target: "app::negative_cache", "serve cached `404 Not Found` response");
+        cache.metrics.record_negative_cache_hit();
+        let mut res = (StatusCode::NOT_FOUND, body).into_response();
+        match content_type {
+            Some(content_type) => _ = res.headers_mut().insert(CONTENT_TYPE, content_type),
+            None => _ = res.headers_mut().remove(CONTENT_TYPE),
+        }
+        return res;
+    }
+
+    let res = next.run(req).await;
+    if res.status() != StatusCode::NOT_FOUND
+        || res
+            .body()
+            .size_hint()
+            .upper()
+            .is_none_or(|n| n > MAX_BODY_LENGTH)
+    {
+        return res;
+    }
+    let (parts, body) = res.into_parts();
+    let body = match hyper::body::to_bytes(body).await {
+        Ok(body) => body,
+        Err(e) => {
+            debug!(target: "app::negative_cache", "failed to buffer `404 Not Found` response: {e}");
+            return Response::from_parts(parts, boxed(Empty::new()));
+        }
+    };
+    if cache.insert(
+        key,
+        generation,
+        parts.headers.get(CONTENT_TYPE).cloned(),
+        body.clone(),
+    ) {
+        trace!(target: "app::negative_cache", "cache `404 Not Found` response");
+    }
+    Response::from_parts(parts, boxed(Full::new(body)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn invalidate() {
+        let cache = NegativeCache::new(Duration::from_secs(60), Default::default());
+        let key = |namespace: &str| Key {
+            namespace: namespace.into(),
+            method: Method::GET,
+            uri: format!("/api/v0.1.0/{namespace}/repo/_tag/0.1.0"),
+            authorization: None,
+            cert: None,
+        };
+
+        let (miss, generation) = cache.get(&key("user"));
+        assert!(miss.is_none());
+        assert!(cache.insert(key("user"), generation, None, "missing".into()));
+        assert!(cache.insert(key("other"), generation, None, "missing".into()));
+        let (miss, generation) = cache.get(&key("user"));
+        assert_eq!(miss.unwrap().body, "missing");
+
+        // Lookups racing with invalidations are not cached.
+        cache.invalidate("user");
+        assert!(cache.get(&key("user")).0.is_none());
+        assert!(cache.get(&key("other")).0.is_some());
+        assert!(!cache.insert(key("user"), generation, None, "missing".into()));
+        assert!(cache.get(&key("user")).0.is_none());
+    }
+
+    #[test]
+    fn namespace() {
+        assert_eq!(super::namespace("/api/v0.1.0/user"), Some("user".into()));
+        assert_eq!(
+            super::namespace("/api/v0.1.0/user/repo/_tag/0.1.0/tree/a/b"),
+            Some("user".into())
+        );
+        assert_eq!(super::namespace("/health"), None);
+        assert_eq!(super::namespace("/api/v0.1.0/"), None);
+    }
+}
@@ -0,0 +1,100 @@
+// SPDX-FileCopyrightText: 2022 Profian Inc. <opensource@profian.com>
+// SPDX-License-Identifier: AGPL-3.0-only
+
+use axum::http::header::LOCATION;
+use axum::http::uri::PathAndQuery;
+use axum::http::{HeaderValue, Request, StatusCode, Uri};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use tracing::debug;
+
+/// Returns the canonical form of `path`, in which runs of slashes are collapsed into one and
+/// trailing slashes are removed, or `None` if `path` is canonical already.
+///
+/// Percent-encoded slashes are not decoded, such that they never separate segments and are
+/// rejected as part of names instead.
+pub(crate) fn canonicalize(path: &str) -> Option<String> {
+    let canonical = path.split('/').filter(|s| !s.is_empty()).fold(
+        String::with_capacity(path.len()),
+        |mut canonical, segment| {
+            canonical.push('/');
+            canonical.push_str(segment);
+            canonical
+        },
+    );
+    let canonical = if canonical.is_empty() {
+        "/".into()
+    } else {
+        canonical
+    };
+    (canonical != path).then_some(canonical)
+}
+
+/// Routes requests to non-canonical paths as if they were sent to their canonical form or, if
+/// `strict` is set, redirects them there with `308 Permanent Redirect`, which preserves the
+/// method and body of requests.
+pub(crate) async fn normalize<B>(strict: bool, mut req: Request<B>, next: Next<B>) -> Response {
+    let Some(path) = canonicalize(req.uri().path()) else {
+        return next.run(req).await;
+    };
+    let path_and_query = match req.uri().query() {
+        Some(query) => format!("{path}?{query}"),
+        None => path,
+    };
+    if strict {
+        debug!(target: "app::paths", "redirect non-canonical path `{}`", req.uri().path());
+        return match HeaderValue::try_from(path_and_query) {
+            Ok(location) => {
+                (StatusCode::PERMANENT_REDIRECT, [(LOCATION, location)]).into_response()
+            }
+            Err(_) => (StatusCode::BAD_REQUEST, "Invalid request path").into_response(),
+        };
+    }
+    let mut parts = req.uri().clone().into_parts();
+    let uri = PathAndQuery::try_from(path_and_query)
+        .ok()
+        .and_then(|path_and_query| {
+            parts.path_and_query = Some(path_and_query);
+            Uri::from_parts(parts).ok()
+        });
+    match uri {
+        Some(uri) => *req.uri_mut() = uri,
+        None => return (StatusCode::BAD_REQUEST, "Invalid request path").into_response(),
+    }
+    next.run(req).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn canonical() {
+        for path in [
+            "/",
+            "/health",
+            "/api/v0.1.0/user/repo/_tag/0.1.0/tree/a/b",
+            "/api/v0.1.0/user%2Frepo",
+        ] {
+            assert_eq!(canonicalize(path), None, "{path}");
+        }
+        for (path, canonical) in [
+            ("", "/"),
+            ("//", "/"),
+            ("/health/", "/health"),
+            ("//api/v0.1.0/user", "/api/v0.1.0/user"),
+            ("/api/v0.1.0/user//repo", "/api/v0.1.0/user/repo"),
+            (
+                "/api/v0.1.0/user/repo/_tag/0.1.0/",
+                "/api/v0.1.0/user/repo/_tag/0.1.0",
+            ),
+            (
+                "/api/v0.1.0/user/repo/_tag/0.1.0/tree///a//b//",
+                "/api/v0.1.0/user/repo/_tag/0.1.0/tree/a/b",
+            ),
+            ("/api/v0.1.0/user%2F/", "/api/v0.1.0/user%2F"),
+        ] {
+            assert_eq!(canonicalize(path).as_deref(), Some(canonical), "{path}");
+        }
+    }
+}
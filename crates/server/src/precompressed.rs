@@ -0,0 +1,113 @@
+// SPDX-FileCopyrightText: 2022 Profian Inc. <opensource@profian.com>
+// SPDX-License-Identifier: AGPL-3.0-only
+
+use super::{compression, CompressionAlgorithm, Content, Node};
+
+use std::fmt;
+use std::pin::Pin;
+
+use drawbridge_type::Meta;
+
+use async_compression::futures::bufread::{BrotliEncoder, GzipEncoder};
+use axum::http::HeaderMap;
+use camino::Utf8Path;
+use futures::io::BufReader;
+use futures::AsyncRead;
+use tracing::{debug, warn};
+
+/// Minimum length of contents, which are stored along with pre-compressed variants, since
+/// compressing smaller contents barely saves any bandwidth.
+const MIN_LENGTH: u64 = 1024;
+
+/// Prefixes of media types of contents, which are not pre-compressed, since they are never
+/// compressed on the fly either.
+const INCOMPRESSIBLE_TYPES: &[&str] = &["image/", "application/grpc"];
+
+/// Compression algorithms, using which variants of file contents are stored on upload and
+/// served to clients accepting them, instead of compressing the contents on every request.
+#[derive(Clone, Debug)]
+pub struct Precompression {
+    algorithms: Vec<CompressionAlgorithm>,
+    /// Algorithms offered for response compression, which include `algorithms`.
+    compression: Vec<CompressionAlgorithm>,
+}
+
+impl Precompression {
+    pub(crate) fn new(
+        algorithms: Vec<CompressionAlgorithm>,
+        compression: Vec<CompressionAlgorithm>,
+    ) -> Self {
+        Self {
+            algorithms,
+            compression,
+        }
+    }
+
+    /// Stores variants of the contents described by `meta` of the file `node` at `path`.
+    ///
+    /// Variants are optional, such that failures are only logged, in which case the contents
+    /// are compressed on the fly when requested.
+    pub(crate) async fn store<P: AsRef<Utf8Path>>(
+        &self,
+        path: impl fmt::Display,
+        node: &Node<'_, P>,
+        meta: &Meta,
+    ) {
+        if meta.size < MIN_LENGTH
+            || INCOMPRESSIBLE_TYPES
+                .iter()
+                .any(|prefix| meta.mime.as_ref().starts_with(prefix))
+        {
+            return;
+        }
+        for algorithm in &self.algorithms {
+            let content = match node.get_content().await {
+                Ok(content) => BufReader::new(content),
+                Err(e) => {
+                    warn!(target: "app::precompressed", "failed to open contents of `{path}`: {e:?}");
+                    return;
+                }
+            };
+            let encoded: Pin<Box<dyn AsyncRead + Send>> = match algorithm {
+                CompressionAlgorithm::Brotli => Box::pin(BrotliEncoder::new(content)),
+                CompressionAlgorithm::Gzip => Box::pin(GzipEncoder::new(content)),
+            };
+            match node
+                .create_variant(&algorithm.to_string(), &meta.hash, encoded)
+                .await
+            {
+                Ok(length) => {
+                    debug!(target: "app::precompressed", "stored `{algorithm}` variant of `{path}` with {length} bytes")
+                }
+                Err(e) => {
+                    warn!(target: "app::precompressed", "failed to store `{algorithm}` variant of `{path}`: {e:?}")
+                }
+            }
+        }
+    }
+
+    /// Returns the stored variant of the contents described by `meta` of the file `node` at
+    /// `path`, which is encoded using the algorithm preferred by the client according to the
+    /// request `headers`, along with the algorithm and the length of the variant.
+    ///
+    /// Returns `None` if the client prefers no pre-compressed algorithm or no intact variant is
+    /// stored, in which case the contents are compressed on the fly if at all.
+    pub(crate) async fn get<P: AsRef<Utf8Path>>(
+        &self,
+        path: impl fmt::Display,
+        node: &Node<'_, P>,
+        meta: &Meta,
+        headers: &HeaderMap,
+    ) -> Option<(CompressionAlgorithm, u64, Content)> {
+        // Only algorithms, which would be chosen for compression on the fly, are served.
+        let algorithm = compression::negotiate(headers, &self.compression)
+            .filter(|algorithm| self.algorithms.contains(algorithm))?;
+        match node.get_variant(&algorithm.to_string(), &meta.hash).await {
+            Ok(variant) => variant.map(|(length, file)| (algorithm, length, file)),
+            Err(e) => {
+                warn!(target: "app::precompressed", "failed to read `{algorithm}` variant of `{path}`: {e:?}");
+                None
+            }
+        }
+    }
+}
@@ -0,0 +1,142 @@
+// SPDX-FileCopyrightText: 2022 Profian Inc. <opensource@profian.com>
+// SPDX-License-Identifier: AGPL-3.0-only
+
+use std::io;
+
+use axum::http::header::CONTENT_TYPE;
+use axum::http::{HeaderValue, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+/// `type` of problem details returned if a request would exceed a quota, e.g. the maximum
+/// number of tags per repository.
+///
+/// Problem details of this type carry the `limit` and the current `usage` of the quota.
+pub const PROBLEM_QUOTA_EXCEEDED: &str =
+    "https://github.com/profianinc/drawbridge/problems/quota-exceeded";
+
+/// `type` of problem details returned if the store has insufficient space to complete a
+/// request.
+pub const PROBLEM_INSUFFICIENT_STORAGE: &str =
+    "https://github.com/profianinc/drawbridge/problems/insufficient-storage";
+
+/// `type` of problem details returned if uploaded contents do not match their digest.
+///
+/// Problem details of this type carry the `expected` digest.
+pub const PROBLEM_DIGEST_MISMATCH: &str =
+    "https://github.com/profianinc/drawbridge/problems/digest-mismatch";
+
+/// Problem details as defined by RFC 9457, which are returned as
+/// `application/problem+json`.
+#[derive(Clone, Debug)]
+pub(crate) struct Problem {
+    status: StatusCode,
+    members: Map<String, Value>,
+}
+
+impl Problem {
+    /// Constructs a new [Problem] of type `r#type` summarized by `title`.
+    pub(crate) fn new(status: StatusCode, r#type: &str, title: &str) -> Self {
+        let mut members = Map::new();
+        _ = members.insert("type".into(), r#type.into());
+        _ = members.insert("title".into(), title.into());
+        _ = members.insert("status".into(), status.as_u16().into());
+        Self { status, members }
+    }
+
+    /// Sets the explanation specific to this occurrence of the problem.
+    pub(crate) fn detail(self, detail: impl Into<String>) -> Self {
+        self.member("detail", detail.into())
+    }
+
+    /// Sets the extension member `name` to `value`.
+    pub(crate) fn member(mut self, name: &str, value: impl Serialize) -> Self {
+        // Values are plain data, which always serialize.
+        _ = self.members.insert(
+            name.into(),
+            serde_json::to_value(value).unwrap_or(Value::Null),
+        );
+        self
+    }
+}
+
+impl IntoResponse for Problem {
+    fn into_response(self) -> Response {
+        let mut res = (self.status, Json(self.members)).into_response();
+        _ = res.headers_mut().insert(
+            CONTENT_TYPE,
+            HeaderValue::from_static("application/problem+json"),
+        );
+        res
+    }
+}
+
+/// Returns `true` if `e` signals that the store ran out of space or exceeded a filesystem
+/// quota.
+pub(crate) fn is_storage_full(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::StorageFull | io::ErrorKind::QuotaExceeded
+    )
+}
+
+/// Returns the `507 Insufficient Storage` response to requests failing due to [is_storage_full]
+/// errors.
+pub(crate) fn insufficient_storage() -> Response {
+    Problem::new(
+        StatusCode::INSUFFICIENT_STORAGE,
+        PROBLEM_INSUFFICIENT_STORAGE,
+        "Insufficient storage",
+    )
+    .detail("The store has insufficient space to complete the request")
+    .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use async_std::task::block_on;
+    use axum::body::HttpBody;
+
+    #[test]
+    fn into_response() {
+        let res = Problem::new(
+            StatusCode::CONFLICT,
+            PROBLEM_QUOTA_EXCEEDED,
+            "Quota exceeded",
+        )
+        .detail("Limit reached")
+        .member("limit", 2)
+        .member("usage", 2)
+        .into_response();
+        assert_eq!(res.status(), StatusCode::CONFLICT);
+        assert_eq!(
+            res.headers().get(CONTENT_TYPE).unwrap(),
+            "application/problem+json"
+        );
+        let body = block_on(res.into_body().data()).unwrap().unwrap();
+        assert_eq!(
+            serde_json::from_slice::<Value>(&body).unwrap(),
+            serde_json::json!({
+                "type": PROBLEM_QUOTA_EXCEEDED,
+                "title": "Quota exceeded",
+                "status": 409,
+                "detail": "Limit reached",
+                "limit": 2,
+                "usage": 2,
+            })
+        );
+
+        assert!(is_storage_full(&io::Error::from(
+            io::ErrorKind::StorageFull
+        )));
+        assert!(!is_storage_full(&io::Error::from(io::ErrorKind::NotFound)));
+        assert_eq!(
+            insufficient_storage().status(),
+            StatusCode::INSUFFICIENT_STORAGE
+        );
+    }
+}
@@ -0,0 +1,138 @@
+// SPDX-FileCopyrightText: 2022 Profian Inc. <opensource@profian.com>
+// SPDX-License-Identifier: AGPL-3.0-only
+
+use super::IpCidr;
+
+use std::net::IpAddr;
+
+use axum::http::header::HeaderName;
+use axum::http::HeaderMap;
+
+/// Header carrying the addresses of the client and the proxies a request passed through.
+pub(crate) const X_FORWARDED_FOR: HeaderName = HeaderName::from_static("x-forwarded-for");
+
+/// Header carrying the scheme used by the client to connect to the proxy.
+pub(crate) const X_FORWARDED_PROTO: HeaderName = HeaderName::from_static("x-forwarded-proto");
+
+/// Header carrying the host requested by the client from the proxy.
+pub(crate) const X_FORWARDED_HOST: HeaderName = HeaderName::from_static("x-forwarded-host");
+
+/// Effective client of a request, which is inserted into request extensions.
+///
+/// `X-Forwarded-*` headers are only taken into account if the immediate peer is a trusted proxy,
+/// otherwise they are removed from the request.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ClientInfo {
+    /// Address of the client.
+    pub addr: IpAddr,
+    /// Scheme used by the client, i.e. `http` or `https`.
+    pub scheme: &'static str,
+    /// Host requested by the client, if forwarded by a trusted proxy.
+    pub host: Option<String>,
+}
+
+impl ClientInfo {
+    /// Determines the effective client of a request received from `peer` with `headers`.
+    pub(crate) fn new(peer: IpAddr, headers: &mut HeaderMap, trusted: &[IpCidr]) -> Self {
+        let is_trusted = |addr: &IpAddr| trusted.iter().any(|cidr| cidr.contains(addr));
+        let mut info = Self {
+            addr: peer,
+            scheme: "https",
+            host: None,
+        };
+        if !is_trusted(&peer) {
+            _ = headers.remove(X_FORWARDED_FOR);
+            _ = headers.remove(X_FORWARDED_PROTO);
+            _ = headers.remove(X_FORWARDED_HOST);
+            return info;
+        }
+
+        // The client is the rightmost address, which is not a trusted proxy.
+        let hops = headers
+            .get_all(X_FORWARDED_FOR)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(','))
+            .map(|hop| hop.trim().parse::<IpAddr>())
+            .collect::<Result<Vec<_>, _>>();
+        if let Ok(hops) = hops {
+            for hop in hops.into_iter().rev() {
+                info.addr = hop;
+                if !is_trusted(&hop) {
+                    break;
+                }
+            }
+        }
+
+        let first = |name| {
+            headers
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.split(',').next())
+                .map(str::trim)
+                .filter(|v| !v.is_empty())
+        };
+        match first(X_FORWARDED_PROTO) {
+            Some(proto) if proto.eq_ignore_ascii_case("http") => info.scheme = "http",
+            Some(proto) if proto.eq_ignore_ascii_case("https") => info.scheme = "https",
+            _ => {}
+        }
+        info.host = first(X_FORWARDED_HOST).map(Into::into);
+        info
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use axum::http::HeaderValue;
+
+    #[test]
+    fn client_info() {
+        let trusted = ["10.0.0.0/8".parse().unwrap()];
+        let headers = || {
+            let mut headers = HeaderMap::new();
+            _ = headers.insert(
+                X_FORWARDED_FOR,
+                HeaderValue::from_static("192.0.2.1, 198.51.100.1, 10.0.0.2"),
+            );
+            _ = headers.insert(X_FORWARDED_PROTO, HeaderValue::from_static("http"));
+            _ = headers.insert(X_FORWARDED_HOST, HeaderValue::from_static("example.com"));
+            headers
+        };
+
+        let mut untrusted = headers();
+        assert_eq!(
+            ClientInfo::new("192.0.2.7".parse().unwrap(), &mut untrusted, &trusted),
+            ClientInfo {
+                addr: "192.0.2.7".parse().unwrap(),
+                scheme: "https",
+                host: None,
+            }
+        );
+        assert!(untrusted.is_empty());
+
+        let mut proxied = headers();
+        assert_eq!(
+            ClientInfo::new("10.0.0.1".parse().unwrap(), &mut proxied, &trusted),
+            ClientInfo {
+                addr: "198.51.100.1".parse().unwrap(),
+                scheme: "http",
+                host: Some("example.com".into()),
+            }
+        );
+
+        let mut invalid = HeaderMap::new();
+        _ = invalid.insert(X_FORWARDED_FOR, HeaderValue::from_static("foo, 192.0.2.1"));
+        _ = invalid.insert(X_FORWARDED_PROTO, HeaderValue::from_static("gopher"));
+        assert_eq!(
+            ClientInfo::new("10.0.0.1".parse().unwrap(), &mut invalid, &trusted),
+            ClientInfo {
+                addr: "10.0.0.1".parse().unwrap(),
+                scheme: "https",
+                host: None,
+            }
+        );
+    }
+}
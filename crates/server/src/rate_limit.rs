@@ -0,0 +1,147 @@
+// SPDX-FileCopyrightText: 2022 Profian Inc. <opensource@profian.com>
+// SPDX-License-Identifier: AGPL-3.0-only
+
+use std::collections::HashMap;
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+use drawbridge_type::UserName;
+
+use axum::http::header::RETRY_AFTER;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use tracing::info;
+
+/// Number of tracked namespaces, above which ones that did not send requests recently are
+/// evicted.
+const MAX_TRACKED_NAMESPACES: usize = 4096;
+
+/// Token bucket of a single namespace.
+#[derive(Clone, Copy, Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    /// Refills the bucket holding at most `rate` tokens at `rate` tokens per second.
+    fn refill(&mut self, rate: u32, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * f64::from(rate)).min(f64::from(rate));
+        self.updated = now;
+    }
+}
+
+/// Limits the rate of requests authenticated as each namespace, i.e. user, independent of the
+/// address they are received from.
+///
+/// Each namespace may send bursts of up to its rate and is limited to its rate on average.
+#[derive(Debug)]
+pub(crate) struct NamespaceLimiter {
+    /// Requests per second allowed for namespaces without an override, `0` means unlimited.
+    default: u32,
+    overrides: HashMap<UserName, u32>,
+    buckets: Mutex<HashMap<UserName, Bucket>>,
+}
+
+impl NamespaceLimiter {
+    /// Constructs a new [NamespaceLimiter] allowing `default` requests per second, unless
+    /// overridden for a namespace by `overrides`. A rate of `0` means unlimited.
+    pub(crate) fn new(default: u32, overrides: HashMap<UserName, u32>) -> Self {
+        Self {
+            default,
+            overrides,
+            buckets: Default::default(),
+        }
+    }
+
+    /// Takes a token from the bucket of `namespace` and returns the duration, after which a
+    /// token becomes available, if none is.
+    fn acquire(&self, namespace: &UserName, now: Instant) -> Result<(), Duration> {
+        let rate = self
+            .overrides
+            .get(namespace)
+            .copied()
+            .unwrap_or(self.default);
+        if rate == 0 {
+            return Ok(());
+        }
+        let mut buckets = self.buckets.lock().unwrap_or_else(PoisonError::into_inner);
+        if buckets.len() >= MAX_TRACKED_NAMESPACES && !buckets.contains_key(namespace) {
+            // Full buckets are equivalent to new ones.
+            let overrides = &self.overrides;
+            let default = self.default;
+            buckets.retain(|name, bucket| {
+                let rate = overrides.get(name).copied().unwrap_or(default);
+                bucket.refill(rate, now);
+                bucket.tokens < f64::from(rate)
+            });
+        }
+        let bucket = buckets.entry(namespace.clone()).or_insert(Bucket {
+            tokens: f64::from(rate),
+            updated: now,
+        });
+        bucket.refill(rate, now);
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64(
+                (1.0 - bucket.tokens) / f64::from(rate),
+            ))
+        }
+    }
+
+    /// Asserts that `namespace` did not exceed its rate, responding with
+    /// `429 Too Many Requests` otherwise.
+    #[allow(clippy::result_large_err)]
+    pub(crate) fn check(&self, namespace: &UserName) -> Result<(), Response> {
+        self.acquire(namespace, Instant::now()).map_err(|wait| {
+            info!(target: "app::rate_limit", "namespace `{namespace}` exceeded its rate limit");
+            // Clients should not retry before a token is available.
+            let retry_after = wait.as_secs() + u64::from(wait.subsec_nanos() > 0);
+            (
+                StatusCode::TOO_MANY_REQUESTS,
+                [(RETRY_AFTER, retry_after.max(1).to_string())],
+                "Rate limit exceeded",
+            )
+                .into_response()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn acquire() {
+        let user = |name: &str| name.parse::<UserName>().unwrap();
+        let limiter = NamespaceLimiter::new(
+            2,
+            [(user("fast"), 10), (user("unlimited"), 0)]
+                .into_iter()
+                .collect(),
+        );
+        let now = Instant::now();
+        assert_eq!(limiter.acquire(&user("slow"), now), Ok(()));
+        assert_eq!(limiter.acquire(&user("slow"), now), Ok(()));
+        assert_eq!(
+            limiter.acquire(&user("slow"), now),
+            Err(Duration::from_millis(500))
+        );
+        // Namespaces are limited independently.
+        assert_eq!(limiter.acquire(&user("other"), now), Ok(()));
+        for _ in 0..10 {
+            assert_eq!(limiter.acquire(&user("fast"), now), Ok(()));
+        }
+        assert!(limiter.acquire(&user("fast"), now).is_err());
+        for _ in 0..100 {
+            assert_eq!(limiter.acquire(&user("unlimited"), now), Ok(()));
+        }
+
+        let later = now + Duration::from_millis(500);
+        assert_eq!(limiter.acquire(&user("slow"), later), Ok(()));
+        assert!(limiter.acquire(&user("slow"), later).is_err());
+    }
+}
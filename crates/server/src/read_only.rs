@@ -0,0 +1,36 @@
+// SPDX-FileCopyrightText: 2022 Profian Inc. <opensource@profian.com>
+// SPDX-License-Identifier: AGPL-3.0-only
+
+use super::{allow_header, Endpoint};
+
+use axum::http::header::ALLOW;
+use axum::http::{Method, Request, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use tracing::debug;
+
+/// Returns `true` if requests using `method` cannot modify the store.
+pub(crate) fn is_read(method: &Method) -> bool {
+    matches!(*method, Method::GET | Method::HEAD | Method::OPTIONS)
+}
+
+/// Rejects all requests, which could modify the store, with `405 Method Not Allowed`.
+///
+/// The `Allow` header of the response lists the reading methods supported by the endpoint.
+pub(crate) async fn reject_writes<B>(req: Request<B>, next: Next<B>) -> Response {
+    if is_read(req.method()) {
+        return next.run(req).await;
+    }
+    debug!(target: "app::read_only", "reject `{}` request in read-only mode", req.method());
+    method_not_allowed(req.uri().path(), "Server is in read-only mode")
+}
+
+/// Returns a `405 Method Not Allowed` response to a request to `path`, which could modify the
+/// store, whose `Allow` header lists the reading methods supported by the endpoint.
+pub(crate) fn method_not_allowed(path: &str, body: impl IntoResponse) -> Response {
+    let allow = match Endpoint::of(path) {
+        Some(endpoint) => allow_header(endpoint.methods().iter().filter(|m| is_read(m))),
+        None => allow_header(&[Method::GET, Method::HEAD]),
+    };
+    (StatusCode::METHOD_NOT_ALLOWED, [(ALLOW, allow)], body).into_response()
+}
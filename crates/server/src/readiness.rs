@@ -0,0 +1,127 @@
+// SPDX-FileCopyrightText: 2022 Profian Inc. <opensource@profian.com>
+// SPDX-License-Identifier: AGPL-3.0-only
+
+use super::auth::OidcVerifier;
+use super::{Store, StoreHealth};
+
+use std::fmt::Write;
+use std::future::Future;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Context};
+use async_std::future::timeout;
+use async_std::task::spawn_blocking;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use futures::join;
+use tracing::warn;
+
+/// Duration each readiness check may take before it is considered failed.
+const CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Duration the result of the checks is reused for, such that frequent requests to the
+/// unauthenticated endpoint neither load the store nor the OpenID Connect provider.
+const RESULT_TTL: Duration = Duration::from_secs(1);
+
+/// Dependencies, which must be usable for the server to be ready to handle requests.
+#[derive(Debug)]
+pub(crate) struct Readiness {
+    store: Arc<Store>,
+    store_health: Arc<StoreHealth>,
+    verifier: Arc<OidcVerifier>,
+    read_only: bool,
+    /// Time of the last checks along with their result.
+    last: Mutex<Option<(Instant, StatusCode, String)>>,
+}
+
+impl Readiness {
+    pub(crate) fn new(
+        store: Arc<Store>,
+        store_health: Arc<StoreHealth>,
+        verifier: Arc<OidcVerifier>,
+        read_only: bool,
+    ) -> Self {
+        Self {
+            store,
+            store_health,
+            verifier,
+            read_only,
+            last: Mutex::new(None),
+        }
+    }
+
+    /// Checks whether the store is writable or, in read-only mode, accessible.
+    async fn check_store(&self) -> anyhow::Result<()> {
+        if !self.store_health.is_available() {
+            bail!("store was found to be unavailable by store probes");
+        }
+        if self.read_only {
+            return self.store.probe().await.context("failed to probe store");
+        }
+        match self.store.is_writable().await {
+            Ok(true) => Ok(()),
+            Ok(false) => bail!("store resides on a read-only filesystem"),
+            Err(e) => Err(e).context("failed to probe whether store is writable"),
+        }
+    }
+
+    /// Checks whether the OpenID Connect provider is reachable.
+    async fn check_oidc(&self) -> anyhow::Result<()> {
+        let verifier = Arc::clone(&self.verifier);
+        spawn_blocking(move || verifier.probe()).await
+    }
+}
+
+/// Runs `check`, which fails once it takes longer than [CHECK_TIMEOUT].
+async fn bounded(check: impl Future<Output = anyhow::Result<()>>) -> anyhow::Result<()> {
+    timeout(CHECK_TIMEOUT, check)
+        .await
+        .unwrap_or_else(|_| bail!("check did not complete within {CHECK_TIMEOUT:?}"))
+}
+
+/// Responds with `200 OK` if all checks of `readiness` pass and with
+/// `503 Service Unavailable` otherwise, listing the result of each check.
+///
+/// Reasons of failures are only logged, since the endpoint is not authenticated.
+pub(crate) async fn ready(readiness: Arc<Readiness>) -> Response {
+    let cached = readiness
+        .last
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .as_ref()
+        .filter(|(time, _, _)| time.elapsed() < RESULT_TTL)
+        .map(|(_, status, body)| (*status, body.clone()));
+    if let Some(res) = cached {
+        return res.into_response();
+    }
+
+    let (store, oidc) = join!(
+        bounded(readiness.check_store()),
+        bounded(readiness.check_oidc())
+    );
+    let mut ready = true;
+    let mut body = String::new();
+    for (name, res) in [("store", store), ("oidc", oidc)] {
+        let status = match res {
+            Ok(()) => "ok",
+            Err(e) => {
+                warn!(target: "app::readiness", "{name} readiness check failed: {e:?}");
+                ready = false;
+                "failed"
+            }
+        };
+        // Writing to a `String` never fails.
+        _ = writeln!(body, "{name}: {status}");
+    }
+    let status = if ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    *readiness
+        .last
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner) = Some((Instant::now(), status, body.clone()));
+    (status, body).into_response()
+}
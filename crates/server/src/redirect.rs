@@ -0,0 +1,104 @@
+// SPDX-FileCopyrightText: 2022 Profian Inc. <opensource@profian.com>
+// SPDX-License-Identifier: AGPL-3.0-only
+
+use axum::http::header::{HOST, LOCATION};
+use axum::http::uri::Authority;
+use axum::http::{HeaderValue, Request, StatusCode};
+use axum::response::{IntoResponse, Response};
+use openidconnect::url::Url;
+use tracing::debug;
+
+/// Returns the `https` URL equivalent to the URL `req` was sent to, which is based on
+/// `public_url`, if set, or the `Host` of `req` with its port replaced by `https_port`
+/// otherwise.
+pub(crate) fn location<B>(
+    public_url: Option<&Url>,
+    https_port: u16,
+    req: &Request<B>,
+) -> Option<String> {
+    let path_and_query = req
+        .uri()
+        .path_and_query()
+        .map_or("/", |path_and_query| path_and_query.as_str());
+    if let Some(base) = public_url {
+        return Some(format!(
+            "{}{path_and_query}",
+            base.as_str().trim_end_matches('/')
+        ));
+    }
+    let host = req
+        .headers()
+        .get(HOST)
+        .and_then(|host| host.to_str().ok())
+        .and_then(|host| host.parse::<Authority>().ok())?;
+    Some(match https_port {
+        443 => format!("https://{}{path_and_query}", host.host()),
+        port => format!("https://{}:{port}{path_and_query}", host.host()),
+    })
+}
+
+/// Redirects `req` to its `https` equivalent with `308 Permanent Redirect`, which preserves
+/// the method and body of requests.
+pub(crate) fn redirect<B>(public_url: Option<&Url>, https_port: u16, req: &Request<B>) -> Response {
+    let location =
+        location(public_url, https_port, req).and_then(|url| HeaderValue::try_from(url).ok());
+    match location {
+        Some(location) => {
+            debug!(target: "app::redirect", "redirect plaintext request to `{location:?}`");
+            (StatusCode::PERMANENT_REDIRECT, [(LOCATION, location)]).into_response()
+        }
+        None => (StatusCode::BAD_REQUEST, "Missing or invalid `Host` header").into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn location() {
+        let req = |uri: &str, host: Option<&str>| {
+            let mut req = Request::get(uri);
+            if let Some(host) = host {
+                req = req.header(HOST, host);
+            }
+            req.body(()).unwrap()
+        };
+        let location = |public_url: Option<&str>, https_port, req: &Request<()>| {
+            super::location(
+                public_url.map(|url| url.parse().unwrap()).as_ref(),
+                https_port,
+                req,
+            )
+        };
+
+        assert_eq!(
+            location(
+                None,
+                443,
+                &req("/api/v0.1.0/user?x=1", Some("store.example.com:80"))
+            )
+            .as_deref(),
+            Some("https://store.example.com/api/v0.1.0/user?x=1")
+        );
+        assert_eq!(
+            location(None, 8443, &req("/health", Some("localhost"))).as_deref(),
+            Some("https://localhost:8443/health")
+        );
+        assert_eq!(
+            location(None, 8443, &req("/", Some("[::1]:8080"))).as_deref(),
+            Some("https://[::1]:8443/")
+        );
+        assert_eq!(
+            location(
+                Some("https://store.example.com/drawbridge/"),
+                8443,
+                &req("/health", Some("localhost:8080"))
+            )
+            .as_deref(),
+            Some("https://store.example.com/drawbridge/health")
+        );
+        assert_eq!(location(None, 443, &req("/health", None)), None);
+        assert_eq!(location(None, 443, &req("/health", Some("a b"))), None);
+    }
+}
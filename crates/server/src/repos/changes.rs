@@ -0,0 +1,76 @@
+// SPDX-FileCopyrightText: 2022 Profian Inc. <opensource@profian.com>
+// SPDX-License-Identifier: AGPL-3.0-only
+
+use super::super::Store;
+use crate::auth::assert_repository_read;
+
+use drawbridge_type::digest::Algorithms;
+use drawbridge_type::{Meta, RepositoryChanges, RepositoryContext};
+
+use async_std::sync::Arc;
+use axum::body::Body;
+use axum::http::{Request, StatusCode};
+use axum::response::IntoResponse;
+use axum::Extension;
+use mime::APPLICATION_JSON;
+use tracing::{debug, trace};
+
+/// Name of the query parameter carrying the cursor returned by a previous request.
+const SINCE: &str = "since";
+
+/// Returns the changes to tags of the repository recorded after the cursor passed in the
+/// `since` query parameter, or all recorded changes if it is omitted, along with a new cursor.
+///
+/// Responses may contain a limited number of changes, hence clients should repeat requests
+/// using the returned cursor until it does not change anymore.
+pub async fn changes(
+    Extension(ref store): Extension<Arc<Store>>,
+    cx: RepositoryContext,
+    req: Request<Body>,
+) -> impl IntoResponse {
+    trace!(target: "app::repos::changes", "called for `{cx}`");
+
+    let since = req
+        .uri()
+        .query()
+        .unwrap_or_default()
+        .split('&')
+        .find_map(|param| param.strip_prefix(SINCE)?.strip_prefix('='))
+        .map_or(Ok(0), str::parse::<u64>)
+        .map_err(|_| {
+            (
+                StatusCode::BAD_REQUEST,
+                format!("Invalid `{SINCE}` query parameter value"),
+            )
+                .into_response()
+        })?;
+
+    _ = assert_repository_read(store, &cx, req)
+        .await
+        .map_err(IntoResponse::into_response)?;
+
+    let (changes, cursor) = store.changes(&cx, since).await.map_err(|e| {
+        debug!(target: "app::repos::changes", "failed for `{cx}`: {:?}", e);
+        e.into_response()
+    })?;
+    let buf = serde_json::to_vec(&RepositoryChanges {
+        changes,
+        cursor: cursor.to_string(),
+    })
+    .map_err(|e| {
+        debug!(target: "app::repos::changes", "failed to encode changes: {e}");
+        StatusCode::INTERNAL_SERVER_ERROR.into_response()
+    })?;
+    let (_, hash) = Algorithms::default().read_sync(&buf[..]).map_err(|e| {
+        debug!(target: "app::repos::changes", "failed to compute digest: {e}");
+        StatusCode::INTERNAL_SERVER_ERROR.into_response()
+    })?;
+    Ok::<_, axum::response::Response>((
+        Meta {
+            hash,
+            size: buf.len() as _,
+            mime: APPLICATION_JSON,
+        },
+        buf,
+    ))
+}
@@ -0,0 +1,56 @@
+// SPDX-FileCopyrightText: 2022 Profian Inc. <opensource@profian.com>
+// SPDX-License-Identifier: AGPL-3.0-only
+
+use super::super::tags::TagLimit;
+use super::super::{OidcClaims, ScopeContext, Store};
+
+use drawbridge_type::{RepositoryChange, RepositoryChangeKind, RepositoryContext};
+
+use async_std::sync::Arc;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::Extension;
+use tracing::{debug, info, trace};
+
+/// Deletes a repository along with all of its tags, which is permitted to the owner of the
+/// repository and to administrators.
+pub async fn delete(
+    Extension(store): Extension<Arc<Store>>,
+    limit: Option<Extension<Arc<TagLimit>>>,
+    claims: OidcClaims,
+    cx: RepositoryContext,
+) -> impl IntoResponse {
+    trace!(target: "app::repos::delete", "called for `{cx}`");
+
+    let user = claims
+        .assert_owner_or_admin(&store, &cx.owner, ScopeContext::Repository)
+        .await?;
+
+    // Tags are listed beforehand to record their deletion in the change log.
+    let tags = user.repository(&cx.name).tags().await.map_err(|e| {
+        debug!(target: "app::repos::delete", "failed for `{cx}`: {:?}", e);
+        e.into_response()
+    })?;
+    user.remove_repository(&cx.name).await.map_err(|e| {
+        debug!(target: "app::repos::delete", "failed for `{cx}`: {:?}", e);
+        e.into_response()
+    })?;
+    info!(target: "app::repos::delete", subject = claims.subject(), "deleted repository `{cx}` with {} tags", tags.len());
+
+    if let Some(Extension(ref limit)) = limit {
+        limit.forget(&cx);
+    }
+    for tag in tags {
+        store
+            .record_change(
+                &cx,
+                RepositoryChange {
+                    kind: RepositoryChangeKind::Deleted,
+                    tag,
+                    path: None,
+                },
+            )
+            .await;
+    }
+    Ok::<_, Response>(StatusCode::NO_CONTENT)
+}
@@ -6,7 +6,9 @@ use super::super::{OidcClaims, ScopeContext, ScopeLevel, Store};
 use drawbridge_type::{Meta, RepositoryConfig, RepositoryContext};
 
 use async_std::sync::Arc;
-use axum::http::StatusCode;
+use axum::body::Body;
+use axum::extract::RequestParts;
+use axum::http::{Request, StatusCode};
 use axum::response::IntoResponse;
 use axum::{Extension, Json};
 use tracing::{debug, trace};
@@ -16,11 +18,11 @@ pub async fn put(
     claims: OidcClaims,
     cx: RepositoryContext,
     meta: Meta,
-    Json(config): Json<RepositoryConfig>,
+    req: Request<Body>,
 ) -> impl IntoResponse {
     trace!(target: "app::trees::put", "called for `{cx}`");
 
-    claims
+    let user = claims
         .assert_user(
             store,
             &cx.owner,
@@ -28,8 +30,15 @@ pub async fn put(
             ScopeLevel::Write,
         )
         .await
-        .map_err(IntoResponse::into_response)?
-        .create_repository(&cx.name, meta, &config)
+        .map_err(IntoResponse::into_response)?;
+
+    // The body is only read after authorization, such that `Expect: 100-continue` requests
+    // are rejected before their body is sent.
+    let Json(config) = RequestParts::new(req)
+        .extract::<Json<RepositoryConfig>>()
+        .await
+        .map_err(IntoResponse::into_response)?;
+    user.create_repository(&cx.name, meta, &config)
         .await
         .map_err(|e| {
             debug!(target: "app::repos::put", "failed for `{cx}`: {:?}", e);
@@ -0,0 +1,167 @@
+// SPDX-FileCopyrightText: 2022 Profian Inc. <opensource@profian.com>
+// SPDX-License-Identifier: AGPL-3.0-only
+
+use super::{allow_header, Endpoint};
+
+use std::collections::HashSet;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use anyhow::bail;
+use axum::http::header::ALLOW;
+use axum::http::{Method, Request, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use tracing::debug;
+
+/// Class of API routes, which may be disabled as a whole.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum RouteClass {
+    /// User records.
+    Users,
+    /// Access tokens of users.
+    Tokens,
+    /// Repository configurations.
+    Repositories,
+    /// Listings of repository contents, i.e. tag queries and change logs.
+    Listings,
+    /// Tags, including signing of URLs to them.
+    Tags,
+    /// Tag trees, including signing of URLs to their nodes.
+    Trees,
+    /// Resumable upload sessions.
+    Uploads,
+}
+
+impl RouteClass {
+    /// Returns the class of `endpoint`.
+    pub(crate) fn of(endpoint: Endpoint) -> Self {
+        match endpoint {
+            Endpoint::User => Self::Users,
+            Endpoint::Tokens | Endpoint::Token => Self::Tokens,
+            Endpoint::Repository => Self::Repositories,
+            Endpoint::TagQuery | Endpoint::Changes => Self::Listings,
+            Endpoint::Tag => Self::Tags,
+            Endpoint::Tree => Self::Trees,
+            Endpoint::Uploads | Endpoint::Upload => Self::Uploads,
+        }
+    }
+}
+
+impl fmt::Display for RouteClass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Users => write!(f, "users"),
+            Self::Tokens => write!(f, "tokens"),
+            Self::Repositories => write!(f, "repositories"),
+            Self::Listings => write!(f, "listings"),
+            Self::Tags => write!(f, "tags"),
+            Self::Trees => write!(f, "trees"),
+            Self::Uploads => write!(f, "uploads"),
+        }
+    }
+}
+
+impl FromStr for RouteClass {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "users" => Ok(Self::Users),
+            "tokens" => Ok(Self::Tokens),
+            "repositories" => Ok(Self::Repositories),
+            "listings" => Ok(Self::Listings),
+            "tags" => Ok(Self::Tags),
+            "trees" => Ok(Self::Trees),
+            "uploads" => Ok(Self::Uploads),
+            _ => bail!("unknown route class `{s}`"),
+        }
+    }
+}
+
+/// Methods and routes, which are exposed by the server.
+#[derive(Debug)]
+pub(crate) struct Surface {
+    /// Allowed methods, all of which are if empty.
+    methods: Vec<Method>,
+    disabled: HashSet<RouteClass>,
+}
+
+impl Surface {
+    pub(crate) fn new(
+        methods: Vec<Method>,
+        disabled: impl IntoIterator<Item = RouteClass>,
+    ) -> Self {
+        Self {
+            methods,
+            disabled: disabled.into_iter().collect(),
+        }
+    }
+
+    fn allows(&self, method: &Method) -> bool {
+        self.methods.is_empty() || self.methods.contains(method)
+    }
+}
+
+/// Responds with `404 Not Found` to requests to disabled routes and with
+/// `405 Method Not Allowed` to requests using methods, which are not allowed.
+///
+/// The `Allow` header lists the allowed methods supported by the endpoint.
+pub(crate) async fn restrict<B>(surface: Arc<Surface>, req: Request<B>, next: Next<B>) -> Response {
+    let endpoint = Endpoint::of(req.uri().path());
+    if let Some(class) = endpoint
+        .map(RouteClass::of)
+        .filter(|class| surface.disabled.contains(class))
+    {
+        debug!(target: "app::routes", "reject request to disabled `{class}` route");
+        return (
+            StatusCode::NOT_FOUND,
+            format!("Route `{}` not found", req.uri().path()),
+        )
+            .into_response();
+    }
+    if surface.allows(req.method()) {
+        return next.run(req).await;
+    }
+    debug!(target: "app::routes", "reject `{}` request using a disallowed method", req.method());
+    let allow = match endpoint {
+        Some(endpoint) => allow_header(endpoint.methods().iter().filter(|m| surface.allows(m))),
+        None => allow_header(&surface.methods),
+    };
+    (
+        StatusCode::METHOD_NOT_ALLOWED,
+        [(ALLOW, allow)],
+        format!("Method `{}` is not allowed", req.method()),
+    )
+        .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn route_class() {
+        for class in [
+            RouteClass::Users,
+            RouteClass::Tokens,
+            RouteClass::Repositories,
+            RouteClass::Listings,
+            RouteClass::Tags,
+            RouteClass::Trees,
+            RouteClass::Uploads,
+        ] {
+            assert_eq!(class.to_string().parse::<RouteClass>().unwrap(), class);
+        }
+        assert!("deletes".parse::<RouteClass>().is_err());
+        assert_eq!(RouteClass::of(Endpoint::Changes), RouteClass::Listings);
+        assert_eq!(RouteClass::of(Endpoint::Upload), RouteClass::Uploads);
+        assert_eq!(RouteClass::of(Endpoint::Token), RouteClass::Tokens);
+
+        let surface = Surface::new(vec![Method::GET, Method::HEAD], []);
+        assert!(surface.allows(&Method::GET));
+        assert!(!surface.allows(&Method::DELETE));
+        assert!(Surface::new(vec![], []).allows(&Method::DELETE));
+    }
+}
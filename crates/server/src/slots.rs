@@ -0,0 +1,88 @@
+// SPDX-FileCopyrightText: 2022 Profian Inc. <opensource@profian.com>
+// SPDX-License-Identifier: AGPL-3.0-only
+
+use super::high_water::{HighWater, ResourceLimit};
+use super::Metrics;
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use async_lock::Semaphore;
+use axum::http::{Method, Request};
+use axum::middleware::Next;
+use axum::response::Response;
+use tracing::trace;
+
+/// Concurrency budget of requests of one kind.
+#[derive(Debug)]
+struct Budget {
+    semaphore: Semaphore,
+    in_use: AtomicU64,
+    high_water: HighWater,
+}
+
+/// Slot of a [Budget] counted as in use until dropped.
+struct InUse<'a>(&'a Budget);
+
+impl<'a> InUse<'a> {
+    fn new(budget: &'a Budget) -> Self {
+        let in_use = budget.in_use.fetch_add(1, Ordering::Relaxed) + 1;
+        _ = budget.high_water.observe(in_use);
+        Self(budget)
+    }
+}
+
+impl Drop for InUse<'_> {
+    fn drop(&mut self) {
+        _ = self.0.in_use.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Separate concurrency budgets of reading and writing requests, such that a burst of slow
+/// requests of one kind cannot starve requests of the other kind.
+#[derive(Debug)]
+pub(crate) struct Slots {
+    read: Option<Budget>,
+    write: Option<Budget>,
+}
+
+impl Slots {
+    /// Constructs new [Slots] allowing at most `read` reading and `write` writing requests to be
+    /// handled concurrently. `0` means unlimited.
+    ///
+    /// Usage of at least `warning_percent` of a budget is recorded in `metrics`.
+    pub(crate) fn new(
+        read: usize,
+        write: usize,
+        warning_percent: u8,
+        metrics: Arc<Metrics>,
+    ) -> Self {
+        let budget = |n, limit| {
+            (n > 0).then(|| Budget {
+                semaphore: Semaphore::new(n),
+                in_use: AtomicU64::new(0),
+                high_water: HighWater::new(limit, n as u64, warning_percent, Arc::clone(&metrics)),
+            })
+        };
+        Self {
+            read: budget(read, ResourceLimit::ReadSlots),
+            write: budget(write, ResourceLimit::WriteSlots),
+        }
+    }
+}
+
+/// Waits for a slot in the budget matching the method of `req` and holds it while `req` is
+/// handled.
+pub(crate) async fn acquire<B>(slots: Arc<Slots>, req: Request<B>, next: Next<B>) -> Response {
+    let budget = match *req.method() {
+        Method::GET | Method::HEAD | Method::OPTIONS => &slots.read,
+        _ => &slots.write,
+    };
+    let Some(budget) = budget else {
+        return next.run(req).await;
+    };
+    trace!(target: "app::slots", "wait for a slot to handle `{}` request", req.method());
+    let _slot = budget.semaphore.acquire().await;
+    let _in_use = InUse::new(budget);
+    next.run(req).await
+}
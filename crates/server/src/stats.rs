@@ -0,0 +1,208 @@
+// SPDX-FileCopyrightText: 2022 Profian Inc. <opensource@profian.com>
+// SPDX-License-Identifier: AGPL-3.0-only
+
+use super::archive::{is_transient, open_store, CONTENT};
+
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+
+use anyhow::Context;
+use async_std::path::Path;
+use camino::{Utf8Path, Utf8PathBuf};
+use serde::Serialize;
+use tracing::warn;
+
+/// Usage of a single namespace, i.e. a user and the repositories owned by it.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct NamespaceStats {
+    /// Name of the user owning the namespace.
+    pub name: String,
+    /// Number of repositories.
+    pub repositories: u64,
+    /// Number of tags.
+    pub tags: u64,
+    /// Number of stored objects, including the user and repository records.
+    pub objects: u64,
+    /// Total size of the contents of stored objects.
+    pub bytes: u64,
+}
+
+/// Size of a single stored object.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+pub struct ObjectStats {
+    /// Size of the object contents.
+    pub bytes: u64,
+    /// Path of the object relative to the store root.
+    pub path: String,
+}
+
+/// Statistics of a store computed by [store_stats].
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct StoreStats {
+    /// Number of users.
+    pub users: u64,
+    /// Number of repositories.
+    pub repositories: u64,
+    /// Number of tags.
+    pub tags: u64,
+    /// Number of stored objects, i.e. users, repositories, tags and tree nodes.
+    pub objects: u64,
+    /// Total size of the contents of stored objects.
+    pub bytes: u64,
+    /// Namespaces using the most bytes in descending order.
+    pub namespaces: Vec<NamespaceStats>,
+    /// Largest objects in descending order of size.
+    pub largest: Vec<ObjectStats>,
+}
+
+/// Kind of a stored object determined by its path, i.e.
+/// `users/<user>/repos/<repo>/tags/<tag>/tree/entries/...`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Kind {
+    User,
+    Repository,
+    Tag,
+    Node,
+}
+
+impl Kind {
+    /// Returns the kind of the object at `dir` along with the name of its namespace.
+    fn of(dir: &Utf8Path) -> Option<(Self, &str)> {
+        let components: Vec<_> = dir.iter().collect();
+        let kind = match components.as_slice() {
+            ["users", _] => Self::User,
+            ["users", _, "repos", _] => Self::Repository,
+            ["users", _, "repos", _, "tags", _] => Self::Tag,
+            ["users", _, "repos", _, "tags", _, "tree", ..] => Self::Node,
+            _ => return None,
+        };
+        Some((kind, components[1]))
+    }
+}
+
+/// Walks the store at `store` and returns its statistics, listing the `top` namespaces using
+/// the most bytes and the `top` largest objects.
+///
+/// Only directory entries and file metadata are read, i.e. object contents are not.
+/// The store should not be modified concurrently for the statistics to be consistent.
+pub async fn store_stats(store: impl AsRef<Path>, top: usize) -> anyhow::Result<StoreStats> {
+    let root = open_store(store.as_ref()).await?;
+    let mut stats = StoreStats::default();
+    let mut namespaces = HashMap::<String, NamespaceStats>::new();
+    // Min-heap of the largest objects seen so far.
+    let mut largest = BinaryHeap::<Reverse<ObjectStats>>::new();
+
+    let mut dirs = vec![Utf8PathBuf::new()];
+    while let Some(dir) = dirs.pop() {
+        let entries = if dir.as_str().is_empty() {
+            root.entries().await
+        } else {
+            root.read_dir(&dir).await
+        }
+        .with_context(|| format!("failed to read directory `{dir}`"))?;
+
+        let mut size = None;
+        for entry in entries {
+            let entry = entry.with_context(|| format!("failed to read directory `{dir}`"))?;
+            let name = entry
+                .file_name()
+                .with_context(|| format!("failed to read entry name in `{dir}`"))?;
+            if is_transient(&name) {
+                continue;
+            }
+            let ty = entry
+                .file_type()
+                .await
+                .with_context(|| format!("failed to query file type of `{dir}/{name}`"))?;
+            if ty.is_dir() {
+                dirs.push(dir.join(name));
+            } else if ty.is_file() && name == CONTENT {
+                size = Some(
+                    entry
+                        .metadata()
+                        .with_context(|| format!("failed to query metadata of `{dir}/{name}`"))?
+                        .len(),
+                );
+            }
+        }
+        let Some(size) = size else {
+            continue;
+        };
+        let Some((kind, namespace)) = Kind::of(&dir) else {
+            warn!(target: "app::store_stats", "skip unexpected object `{dir}`");
+            continue;
+        };
+        let ns = namespaces
+            .entry(namespace.into())
+            .or_insert_with(|| NamespaceStats {
+                name: namespace.into(),
+                ..Default::default()
+            });
+        match kind {
+            Kind::User => stats.users += 1,
+            Kind::Repository => {
+                stats.repositories += 1;
+                ns.repositories += 1;
+            }
+            Kind::Tag => {
+                stats.tags += 1;
+                ns.tags += 1;
+            }
+            Kind::Node => {}
+        }
+        stats.objects += 1;
+        stats.bytes += size;
+        ns.objects += 1;
+        ns.bytes += size;
+
+        if top > 0 {
+            largest.push(Reverse(ObjectStats {
+                bytes: size,
+                path: dir.into_string(),
+            }));
+            if largest.len() > top {
+                _ = largest.pop();
+            }
+        }
+    }
+
+    let mut namespaces: Vec<_> = namespaces.into_values().collect();
+    namespaces.sort_by(|a, b| b.bytes.cmp(&a.bytes).then_with(|| a.name.cmp(&b.name)));
+    namespaces.truncate(top);
+    stats.namespaces = namespaces;
+    stats.largest = largest
+        .into_sorted_vec()
+        .into_iter()
+        .map(|Reverse(object)| object)
+        .collect();
+    Ok(stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn kind() {
+        assert_eq!(Kind::of("users/user".into()), Some((Kind::User, "user")));
+        assert_eq!(
+            Kind::of("users/user/repos/repo".into()),
+            Some((Kind::Repository, "user"))
+        );
+        assert_eq!(
+            Kind::of("users/user/repos/repo/tags/0.1.0".into()),
+            Some((Kind::Tag, "user"))
+        );
+        assert_eq!(
+            Kind::of("users/user/repos/repo/tags/0.1.0/tree".into()),
+            Some((Kind::Node, "user"))
+        );
+        assert_eq!(
+            Kind::of("users/user/repos/repo/tags/0.1.0/tree/entries/foo".into()),
+            Some((Kind::Node, "user"))
+        );
+        assert_eq!(Kind::of("users".into()), None);
+        assert_eq!(Kind::of("users/user/repos".into()), None);
+        assert_eq!(Kind::of("other/user".into()), None);
+    }
+}
@@ -0,0 +1,407 @@
+// SPDX-FileCopyrightText: 2022 Profian Inc. <opensource@profian.com>
+// SPDX-License-Identifier: AGPL-3.0-only
+
+use super::{Backend, Content, Staged};
+use crate::Metrics;
+
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant, SystemTime};
+
+use async_std::io;
+use axum::async_trait;
+use camino::Utf8Path;
+use cap_async_std::fs_utf8::Dir;
+use futures::AsyncWrite;
+use tracing::{info, warn};
+
+/// Default number of consecutive failed operations on a remote store, after which the circuit
+/// of the store opens.
+pub const DEFAULT_STORE_CIRCUIT_THRESHOLD: usize = 5;
+
+/// Default duration, for which the circuit of a remote store stays open before an operation is
+/// attempted again.
+pub const DEFAULT_STORE_CIRCUIT_COOLDOWN: Duration = Duration::from_secs(30);
+
+/// State of the circuit breaker of a remote store.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CircuitState {
+    /// Operations are attempted.
+    Closed,
+    /// Operations fail immediately, since the store failed persistently.
+    Open,
+    /// A single operation is attempted to probe whether the store recovered.
+    HalfOpen,
+}
+
+impl CircuitState {
+    /// All states.
+    pub const ALL: [Self; 3] = [Self::Closed, Self::Open, Self::HalfOpen];
+}
+
+impl fmt::Display for CircuitState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Closed => write!(f, "closed"),
+            Self::Open => write!(f, "open"),
+            Self::HalfOpen => write!(f, "half-open"),
+        }
+    }
+}
+
+#[derive(Debug)]
+enum State {
+    Closed {
+        failures: usize,
+    },
+    Open {
+        until: Instant,
+    },
+    /// The probing operation is in flight.
+    HalfOpen,
+}
+
+/// Returns whether `e` indicates a failure of the store rather than of the operation, e.g. a
+/// missing file.
+fn is_failure(e: &io::Error) -> bool {
+    !matches!(
+        e.kind(),
+        io::ErrorKind::NotFound
+            | io::ErrorKind::AlreadyExists
+            | io::ErrorKind::PermissionDenied
+            | io::ErrorKind::InvalidInput
+    )
+}
+
+/// Circuit breaker of a remote store, which opens after `threshold` consecutive operations
+/// failed and stays open for `cooldown`.
+///
+/// Operations abandoned before completing, e.g. since their client disconnected, are not
+/// recorded, such that clients cannot open the circuit. Hanging operations fail by the timeouts
+/// of the [Backend] instead.
+///
+/// All operations fail immediately while the circuit is open. Once the cooldown elapsed, a
+/// single operation is attempted, which closes the circuit if it succeeds and opens it again
+/// otherwise.
+#[derive(Debug)]
+pub(crate) struct Circuit {
+    threshold: usize,
+    cooldown: Duration,
+    state: Mutex<State>,
+    metrics: Arc<Metrics>,
+}
+
+impl Circuit {
+    pub(crate) fn new(threshold: usize, cooldown: Duration, metrics: Arc<Metrics>) -> Self {
+        Self {
+            threshold,
+            cooldown,
+            state: Mutex::new(State::Closed { failures: 0 }),
+            metrics,
+        }
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Returns how long to wait until operations are attempted again, or `None` if they are
+    /// attempted.
+    pub(crate) fn retry_after(&self) -> Option<Duration> {
+        match *self.state() {
+            State::Closed { .. } => None,
+            State::Open { until } => until.checked_duration_since(Instant::now()),
+            State::HalfOpen => Some(Duration::ZERO),
+        }
+    }
+
+    fn transition(&self, state: &mut State, to: State) {
+        let recorded = match to {
+            State::Closed { .. } => CircuitState::Closed,
+            State::Open { .. } => CircuitState::Open,
+            State::HalfOpen => CircuitState::HalfOpen,
+        };
+        *state = to;
+        self.metrics.record_store_circuit(recorded);
+    }
+
+    /// Starts an operation, failing if the circuit is open.
+    fn attempt(&self) -> io::Result<Attempt<'_>> {
+        let mut state = self.state();
+        match *state {
+            State::Closed { .. } => {}
+            State::Open { until } if until <= Instant::now() => {
+                info!(target: "app::store::Breaker", "probing whether store recovered");
+                self.transition(&mut state, State::HalfOpen);
+            }
+            State::Open { .. } | State::HalfOpen => {
+                return Err(io::Error::other(
+                    "store is unavailable, operation was not attempted",
+                ))
+            }
+        }
+        Ok(Attempt {
+            circuit: self,
+            recorded: false,
+        })
+    }
+
+    /// Records the outcome of an operation.
+    fn record(&self, success: bool) {
+        let mut state = self.state();
+        match (&mut *state, success) {
+            (State::Closed { failures }, true) => *failures = 0,
+            (_, true) => {
+                info!(target: "app::store::Breaker", "store recovered, closing circuit");
+                self.transition(&mut state, State::Closed { failures: 0 });
+            }
+            (State::Closed { failures }, false) if *failures + 1 < self.threshold => *failures += 1,
+            (State::Closed { .. } | State::HalfOpen, false) => {
+                warn!(
+                    target: "app::store::Breaker",
+                    "store failed persistently, opening circuit for {}s",
+                    self.cooldown.as_secs()
+                );
+                let until = Instant::now() + self.cooldown;
+                self.transition(&mut state, State::Open { until });
+                self.metrics.record_store_circuit_trip();
+            }
+            // Operations attempted before the circuit opened do not extend the cooldown.
+            (State::Open { .. }, false) => {}
+        }
+    }
+
+    /// Records an operation abandoned before completing, which allows another operation to
+    /// probe the store if it was the probing one.
+    fn abandon(&self) {
+        let mut state = self.state();
+        if let State::HalfOpen = *state {
+            let until = Instant::now();
+            self.transition(&mut state, State::Open { until });
+        }
+    }
+}
+
+/// Operation attempted on a store, whose outcome is recorded unless it is abandoned.
+struct Attempt<'a> {
+    circuit: &'a Circuit,
+    recorded: bool,
+}
+
+impl Attempt<'_> {
+    fn record<T>(mut self, res: &io::Result<T>) {
+        self.recorded = true;
+        self.circuit
+            .record(res.as_ref().err().is_none_or(|e| !is_failure(e)));
+    }
+}
+
+impl Drop for Attempt<'_> {
+    fn drop(&mut self) {
+        if !self.recorded {
+            self.circuit.abandon()
+        }
+    }
+}
+
+/// [Backend] failing operations immediately while the [Circuit] of another, remote [Backend]
+/// is open, such that requests do not pile up waiting for an unavailable store.
+#[derive(Debug)]
+pub(crate) struct Breaker {
+    backend: Box<dyn Backend>,
+    circuit: Arc<Circuit>,
+}
+
+impl Breaker {
+    pub(crate) fn new(backend: Box<dyn Backend>, circuit: Arc<Circuit>) -> Self {
+        Self { backend, circuit }
+    }
+}
+
+/// Attempts the operation `op` unless the circuit is open.
+async fn call<T>(circuit: &Circuit, op: impl Future<Output = io::Result<T>>) -> io::Result<T> {
+    let attempt = circuit.attempt()?;
+    let res = op.await;
+    attempt.record(&res);
+    res
+}
+
+/// File being written to a [Breaker], whose commit is guarded by the circuit.
+struct StagedFile {
+    staged: Box<dyn Staged>,
+    circuit: Arc<Circuit>,
+}
+
+impl AsyncWrite for StagedFile {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.staged).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.staged).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.staged).poll_close(cx)
+    }
+}
+
+#[async_trait]
+impl Staged for StagedFile {
+    async fn commit(self: Box<Self>) -> io::Result<()> {
+        let Self { staged, circuit } = *self;
+        let attempt = match circuit.attempt() {
+            Ok(attempt) => attempt,
+            Err(e) => {
+                staged.discard().await;
+                return Err(e);
+            }
+        };
+        let res = staged.commit().await;
+        attempt.record(&res);
+        res
+    }
+
+    async fn discard(self: Box<Self>) {
+        self.staged.discard().await
+    }
+}
+
+#[async_trait]
+impl Backend for Breaker {
+    fn dir(&self) -> Option<&Dir> {
+        self.backend.dir()
+    }
+
+    // Probes determine the health of the store independently of the circuit.
+    async fn probe(&self) -> io::Result<()> {
+        self.backend.probe().await
+    }
+
+    async fn read(&self, path: &Utf8Path) -> io::Result<Vec<u8>> {
+        call(&self.circuit, self.backend.read(path)).await
+    }
+
+    async fn read_range(
+        &self,
+        path: &Utf8Path,
+        offset: u64,
+        limit: u64,
+    ) -> io::Result<(u64, Vec<u8>)> {
+        call(&self.circuit, self.backend.read_range(path, offset, limit)).await
+    }
+
+    async fn open(&self, path: &Utf8Path) -> io::Result<Content> {
+        call(&self.circuit, self.backend.open(path)).await
+    }
+
+    async fn modified(&self, path: &Utf8Path) -> io::Result<SystemTime> {
+        call(&self.circuit, self.backend.modified(path)).await
+    }
+
+    async fn stage(&self, path: &Utf8Path) -> io::Result<Box<dyn Staged>> {
+        let staged = call(&self.circuit, self.backend.stage(path)).await?;
+        Ok(Box::new(StagedFile {
+            staged,
+            circuit: Arc::clone(&self.circuit),
+        }))
+    }
+
+    async fn write(&self, path: &Utf8Path, buf: Vec<u8>) -> io::Result<()> {
+        call(&self.circuit, self.backend.write(path, buf)).await
+    }
+
+    async fn append(&self, path: &Utf8Path, buf: &[u8]) -> io::Result<()> {
+        call(&self.circuit, self.backend.append(path, buf)).await
+    }
+
+    async fn create_dir(&self, path: &Utf8Path) -> io::Result<()> {
+        call(&self.circuit, self.backend.create_dir(path)).await
+    }
+
+    async fn list(&self, path: &Utf8Path) -> io::Result<Vec<String>> {
+        call(&self.circuit, self.backend.list(path)).await
+    }
+
+    async fn remove(&self, path: &Utf8Path) -> io::Result<()> {
+        call(&self.circuit, self.backend.remove(path)).await
+    }
+
+    async fn remove_dir_all(&self, path: &Utf8Path) -> io::Result<()> {
+        call(&self.circuit, self.backend.remove_dir_all(path)).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn failed() -> io::Result<()> {
+        Err(io::Error::other("connection refused"))
+    }
+
+    #[test]
+    fn circuit() {
+        let metrics = Arc::<Metrics>::default();
+        let circuit = Circuit::new(2, Duration::from_secs(3600), Arc::clone(&metrics));
+
+        // Only consecutive failures of the store count.
+        circuit.attempt().unwrap().record(&failed());
+        circuit.attempt().unwrap().record(&Ok(()));
+        circuit.attempt().unwrap().record(&failed());
+        circuit
+            .attempt()
+            .unwrap()
+            .record::<()>(&Err(io::ErrorKind::NotFound.into()));
+        circuit.attempt().unwrap().record(&failed());
+        assert_eq!(circuit.retry_after(), None);
+
+        // Abandoned operations, e.g. by disconnected clients, are not recorded.
+        for _ in 0..3 {
+            drop(circuit.attempt().unwrap());
+        }
+        assert_eq!(circuit.retry_after(), None);
+        assert_eq!(metrics.store_circuit(), CircuitState::Closed);
+
+        circuit.attempt().unwrap().record(&failed());
+        assert!(circuit.retry_after().unwrap() > Duration::from_secs(3500));
+        assert!(circuit.attempt().is_err());
+        assert_eq!(metrics.store_circuit(), CircuitState::Open);
+        assert_eq!(metrics.store_circuit_trips(), 1);
+    }
+
+    #[test]
+    fn probe() {
+        let metrics = Arc::<Metrics>::default();
+        let circuit = Circuit::new(1, Duration::ZERO, Arc::clone(&metrics));
+        circuit.attempt().unwrap().record(&failed());
+        assert_eq!(metrics.store_circuit(), CircuitState::Open);
+
+        // A single operation probes the store once the cooldown elapsed.
+        let probe = circuit.attempt().unwrap();
+        assert_eq!(metrics.store_circuit(), CircuitState::HalfOpen);
+        assert_eq!(circuit.retry_after(), Some(Duration::ZERO));
+        assert!(circuit.attempt().is_err());
+        probe.record(&failed());
+        assert_eq!(metrics.store_circuit(), CircuitState::Open);
+        assert_eq!(metrics.store_circuit_trips(), 2);
+
+        // Abandoned probes allow another operation to probe the store.
+        drop(circuit.attempt().unwrap());
+        assert_eq!(metrics.store_circuit(), CircuitState::Open);
+        circuit.attempt().unwrap().record(&failed());
+        assert_eq!(metrics.store_circuit(), CircuitState::Open);
+        assert_eq!(metrics.store_circuit_trips(), 3);
+
+        circuit.attempt().unwrap().record(&Ok(()));
+        assert_eq!(metrics.store_circuit(), CircuitState::Closed);
+        assert_eq!(circuit.retry_after(), None);
+    }
+}
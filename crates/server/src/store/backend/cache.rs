@@ -0,0 +1,447 @@
+// SPDX-FileCopyrightText: 2022 Profian Inc. <opensource@profian.com>
+// SPDX-License-Identifier: AGPL-3.0-only
+
+use super::{Backend, Content, Filesystem, Staged};
+use crate::auth::encode_hex;
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::time::SystemTime;
+
+use async_std::io;
+use axum::async_trait;
+use camino::{Utf8Path, Utf8PathBuf};
+use cap_async_std::fs_utf8::Dir;
+use futures::io::copy;
+use futures::AsyncReadExt;
+use sha2::{Digest, Sha256};
+use tracing::{debug, trace, warn};
+
+/// Default maximum total size in bytes of the files held by a [Cached] backend.
+pub const DEFAULT_CACHE_MAX_BYTES: u64 = 1 << 30;
+
+/// Returns whether the file at `path` belongs to a tag, which is immutable once written, such
+/// that it may be cached.
+fn is_cacheable(path: &Utf8Path) -> bool {
+    let components: Vec<_> = path.iter().collect();
+    matches!(components[..], ["users", _, "repos", _, "tags", _, _, ..])
+}
+
+/// Returns the name of the file caching the file at `path`.
+fn cache_name(path: &Utf8Path) -> Utf8PathBuf {
+    encode_hex(&Sha256::digest(path.as_str())).into()
+}
+
+/// Returns whether `name` is the name of a cached file or of a temporary file written while
+/// caching one, such that no other files are removed from the cache directory.
+fn is_cache_file(name: &str) -> bool {
+    let name = name.strip_prefix('.').unwrap_or(name);
+    name.get(..64)
+        .is_some_and(|hash| hash.bytes().all(|b| b.is_ascii_hexdigit()))
+}
+
+/// Paths and sizes of the cached files in the order they were last used in.
+#[derive(Debug, Default)]
+struct Index {
+    /// Sizes and last uses of the cached files by path.
+    files: HashMap<Utf8PathBuf, (u64, u64)>,
+    /// Paths of the cached files by last use.
+    uses: BTreeMap<u64, Utf8PathBuf>,
+    /// Total size of the cached files.
+    size: u64,
+    /// Counter of uses, which orders them.
+    clock: u64,
+}
+
+impl Index {
+    /// Marks the file at `path` as used and returns whether it is cached.
+    fn touch(&mut self, path: &Utf8Path) -> bool {
+        let Some((_, used)) = self.files.get_mut(path) else {
+            return false;
+        };
+        self.clock += 1;
+        if let Some(path) = self.uses.remove(used) {
+            _ = self.uses.insert(self.clock, path);
+        }
+        *used = self.clock;
+        true
+    }
+
+    /// Records the file at `path` of `size` bytes and returns the paths of the least recently
+    /// used files evicted to keep the total size at most `max_bytes`.
+    fn insert(&mut self, path: &Utf8Path, size: u64, max_bytes: u64) -> Vec<Utf8PathBuf> {
+        _ = self.remove(path);
+        self.clock += 1;
+        self.size += size;
+        _ = self.files.insert(path.into(), (size, self.clock));
+        _ = self.uses.insert(self.clock, path.into());
+        let mut evicted = vec![];
+        while self.size > max_bytes {
+            let Some((_, path)) = self.uses.pop_first() else {
+                break;
+            };
+            if let Some((size, _)) = self.files.remove(&path) {
+                self.size -= size;
+            }
+            evicted.push(path);
+        }
+        evicted
+    }
+
+    /// Forgets the file at `path` and returns whether it was cached.
+    fn remove(&mut self, path: &Utf8Path) -> bool {
+        match self.files.remove(path) {
+            Some((size, used)) => {
+                _ = self.uses.remove(&used);
+                self.size -= size;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Forgets all files below the directory at `path` and returns their paths.
+    fn remove_all(&mut self, path: &Utf8Path) -> Vec<Utf8PathBuf> {
+        let paths: Vec<_> = self
+            .files
+            .keys()
+            .filter(|file| file.starts_with(path))
+            .cloned()
+            .collect();
+        for file in &paths {
+            _ = self.remove(file);
+        }
+        paths
+    }
+}
+
+/// [Backend] holding files of tags read from another, slow [Backend], e.g. an object store, in
+/// a local directory, which is bounded in size by evicting the least recently used files.
+///
+/// Since tags are immutable, cached files are served without consulting the other backend,
+/// which all writes go to. Files are only invalidated when overwritten or removed using this
+/// backend, such that tags removed by other servers sharing the store remain served until
+/// evicted. Files missing in the cache are read from the other backend completely before
+/// being served. The cache is emptied on startup.
+#[derive(Debug)]
+pub struct Cached {
+    backend: Box<dyn Backend>,
+    local: Filesystem,
+    max_bytes: u64,
+    index: Mutex<Index>,
+}
+
+impl Cached {
+    /// Opens the cache directory at `dir`, which is created if missing, holding at most
+    /// `max_bytes` of the files of `backend`.
+    pub async fn open(
+        backend: Box<dyn Backend>,
+        dir: impl AsRef<Path>,
+        max_bytes: u64,
+    ) -> io::Result<Self> {
+        async_std::fs::create_dir_all(dir.as_ref()).await?;
+        let local = Filesystem::open(dir).await?;
+        // Files are not indexed across restarts, such that files left over are stale.
+        for name in local.list(Utf8Path::new(".")).await? {
+            if is_cache_file(&name) {
+                local.remove(Utf8Path::new(&name)).await?;
+            }
+        }
+        Ok(Self {
+            backend,
+            local,
+            max_bytes,
+            index: Default::default(),
+        })
+    }
+
+    fn index(&self) -> MutexGuard<'_, Index> {
+        self.index.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Returns whether the file at `path` is cached and marks it as used.
+    fn is_cached(&self, path: &Utf8Path) -> bool {
+        is_cacheable(path) && self.index().touch(path)
+    }
+
+    /// Forgets the cached file at `path` after reading it failed with `e`.
+    fn forget(&self, path: &Utf8Path, e: io::Error) {
+        debug!(target: "app::store::Cached", "failed to read cached `{path}`, reading it from store: {e}");
+        _ = self.index().remove(path);
+    }
+
+    /// Removes the cached files at `paths`, which are no longer indexed.
+    async fn remove_files(&self, paths: impl IntoIterator<Item = Utf8PathBuf>) {
+        for path in paths {
+            trace!(target: "app::store::Cached", "remove `{path}` from cache");
+            match self.local.remove(&cache_name(&path)).await {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => {
+                    warn!(target: "app::store::Cached", "failed to remove cached `{path}`: {e}")
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Indexes the file at `path` of `size` bytes, once written to the cache directory.
+    async fn insert(&self, path: &Utf8Path, size: u64) {
+        trace!(target: "app::store::Cached", "cache `{path}`");
+        let evicted = self.index().insert(path, size, self.max_bytes);
+        self.remove_files(evicted).await
+    }
+
+    /// Invalidates the cached file at `path`, if any.
+    async fn invalidate(&self, path: &Utf8Path) {
+        if self.index().remove(path) {
+            self.remove_files([path.into()]).await
+        }
+    }
+
+    /// Caches `buf` as the file at `path`, if it fits into the cache.
+    async fn fill(&self, path: &Utf8Path, buf: Vec<u8>) {
+        let size = buf.len() as u64;
+        if size > self.max_bytes {
+            return;
+        }
+        match self.local.write(&cache_name(path), buf).await {
+            Ok(()) => self.insert(path, size).await,
+            Err(e) => warn!(target: "app::store::Cached", "failed to cache `{path}`: {e}"),
+        }
+    }
+
+    /// Caches the contents read from `rdr` as the file at `path` and returns whether they fit
+    /// into the cache.
+    async fn fill_from(&self, path: &Utf8Path, rdr: Content) -> io::Result<bool> {
+        let mut file = self.local.stage(&cache_name(path)).await?;
+        let size = match copy(rdr.take(self.max_bytes.saturating_add(1)), &mut file).await {
+            Ok(size) if size <= self.max_bytes => size,
+            res => {
+                file.discard().await;
+                return res.map(|_| false);
+            }
+        };
+        file.commit().await?;
+        self.insert(path, size).await;
+        Ok(true)
+    }
+}
+
+#[async_trait]
+impl Backend for Cached {
+    fn dir(&self) -> Option<&Dir> {
+        self.backend.dir()
+    }
+
+    async fn probe(&self) -> io::Result<()> {
+        self.backend.probe().await
+    }
+
+    async fn read(&self, path: &Utf8Path) -> io::Result<Vec<u8>> {
+        if self.is_cached(path) {
+            match self.local.read(&cache_name(path)).await {
+                Ok(buf) => return Ok(buf),
+                Err(e) => self.forget(path, e),
+            }
+        }
+        let buf = self.backend.read(path).await?;
+        if is_cacheable(path) {
+            self.fill(path, buf.clone()).await;
+        }
+        Ok(buf)
+    }
+
+    async fn read_range(
+        &self,
+        path: &Utf8Path,
+        offset: u64,
+        limit: u64,
+    ) -> io::Result<(u64, Vec<u8>)> {
+        if self.is_cached(path) {
+            match self
+                .local
+                .read_range(&cache_name(path), offset, limit)
+                .await
+            {
+                Ok(range) => return Ok(range),
+                Err(e) => self.forget(path, e),
+            }
+        }
+        self.backend.read_range(path, offset, limit).await
+    }
+
+    async fn open(&self, path: &Utf8Path) -> io::Result<Content> {
+        if !is_cacheable(path) {
+            return self.backend.open(path).await;
+        }
+        if self.index().touch(path) {
+            match self.local.open(&cache_name(path)).await {
+                Ok(rdr) => return Ok(rdr),
+                Err(e) => self.forget(path, e),
+            }
+        }
+        match self.fill_from(path, self.backend.open(path).await?).await {
+            Ok(true) => match self.local.open(&cache_name(path)).await {
+                Ok(rdr) => return Ok(rdr),
+                Err(e) => self.forget(path, e),
+            },
+            // Files larger than the cache are streamed from the other backend.
+            Ok(false) => {}
+            Err(e) => warn!(target: "app::store::Cached", "failed to cache `{path}`: {e}"),
+        }
+        self.backend.open(path).await
+    }
+
+    async fn modified(&self, path: &Utf8Path) -> io::Result<SystemTime> {
+        self.backend.modified(path).await
+    }
+
+    async fn stage(&self, path: &Utf8Path) -> io::Result<Box<dyn Staged>> {
+        self.invalidate(path).await;
+        self.backend.stage(path).await
+    }
+
+    async fn write(&self, path: &Utf8Path, buf: Vec<u8>) -> io::Result<()> {
+        self.invalidate(path).await;
+        if !is_cacheable(path) {
+            return self.backend.write(path, buf).await;
+        }
+        // Files written are likely read soon after, e.g. the metadata of tree nodes.
+        self.backend.write(path, buf.clone()).await?;
+        self.fill(path, buf).await;
+        Ok(())
+    }
+
+    async fn append(&self, path: &Utf8Path, buf: &[u8]) -> io::Result<()> {
+        self.invalidate(path).await;
+        self.backend.append(path, buf).await
+    }
+
+    async fn create_dir(&self, path: &Utf8Path) -> io::Result<()> {
+        self.backend.create_dir(path).await
+    }
+
+    async fn list(&self, path: &Utf8Path) -> io::Result<Vec<String>> {
+        self.backend.list(path).await
+    }
+
+    async fn remove(&self, path: &Utf8Path) -> io::Result<()> {
+        self.backend.remove(path).await?;
+        self.invalidate(path).await;
+        Ok(())
+    }
+
+    async fn remove_dir_all(&self, path: &Utf8Path) -> io::Result<()> {
+        self.backend.remove_dir_all(path).await?;
+        let removed = self.index().remove_all(path);
+        self.remove_files(removed).await;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use async_std::task::block_on;
+
+    const CONTENT: &str = "users/user/repos/repo/tags/0.1.0/tree/entries/a/content";
+    const OTHER: &str = "users/user/repos/repo/tags/0.1.0/tree/entries/b/content";
+
+    #[test]
+    fn cacheable() {
+        assert!(is_cacheable(Utf8Path::new(CONTENT)));
+        assert!(is_cacheable(Utf8Path::new(
+            "users/user/repos/repo/tags/0.1.0/meta.json"
+        )));
+        assert!(!is_cacheable(Utf8Path::new(
+            "users/user/repos/repo/tags/0.1.0"
+        )));
+        assert!(!is_cacheable(Utf8Path::new(
+            "users/user/repos/repo/meta.json"
+        )));
+        assert!(!is_cacheable(Utf8Path::new(".changes")));
+
+        let name = cache_name(Utf8Path::new(CONTENT));
+        assert!(is_cache_file(name.as_str()));
+        assert!(is_cache_file(&format!(".{name}-1b4db7eb")));
+        assert!(!is_cache_file("notes.txt"));
+    }
+
+    #[test]
+    fn index() {
+        let (a, b, c) = (Utf8Path::new("a"), Utf8Path::new("b"), Utf8Path::new("c"));
+        let mut index = Index::default();
+        assert!(index.insert(a, 4, 10).is_empty());
+        assert!(index.insert(b, 4, 10).is_empty());
+        assert!(index.touch(a));
+        assert_eq!(index.insert(c, 4, 10), [b]);
+        assert!(!index.touch(b));
+        assert_eq!(index.size, 8);
+        assert_eq!(index.insert(a, 8, 10), [c]);
+        assert_eq!(index.size, 8);
+        assert_eq!(index.remove_all(Utf8Path::new("")), [a]);
+        assert_eq!(index.size, 0);
+    }
+
+    #[test]
+    fn cached() {
+        block_on(async {
+            let store = tempfile::tempdir().unwrap();
+            let cache = tempfile::tempdir().unwrap();
+            std::fs::write(cache.path().join("notes.txt"), "kept").unwrap();
+
+            let backend = Filesystem::open(store.path()).await.unwrap();
+            for path in [CONTENT, OTHER] {
+                let dir = Utf8Path::new(path).parent().unwrap();
+                std::fs::create_dir_all(store.path().join(dir)).unwrap();
+                backend
+                    .write(path.as_ref(), b"test".to_vec())
+                    .await
+                    .unwrap();
+            }
+            let cached = Cached::open(Box::new(backend), cache.path(), 6)
+                .await
+                .unwrap();
+            assert!(cache.path().join("notes.txt").exists());
+
+            let mut buf = vec![];
+            _ = cached
+                .open(CONTENT.as_ref())
+                .await
+                .unwrap()
+                .read_to_end(&mut buf)
+                .await
+                .unwrap();
+            assert_eq!(buf, b"test");
+
+            // Cached files are served without reading the store.
+            std::fs::write(store.path().join(CONTENT), "todo").unwrap();
+            assert_eq!(cached.read(CONTENT.as_ref()).await.unwrap(), b"test");
+            assert_eq!(
+                cached.read_range(CONTENT.as_ref(), 1, 2).await.unwrap(),
+                (4, b"es".to_vec())
+            );
+
+            // Caching another file evicts the least recently used one.
+            assert_eq!(cached.read(OTHER.as_ref()).await.unwrap(), b"test");
+            assert_eq!(cached.read(CONTENT.as_ref()).await.unwrap(), b"todo");
+
+            // Writes and removals invalidate cached files.
+            cached
+                .write(CONTENT.as_ref(), b"done".to_vec())
+                .await
+                .unwrap();
+            assert_eq!(cached.read(CONTENT.as_ref()).await.unwrap(), b"done");
+            cached
+                .remove_dir_all("users/user/repos/repo/tags/0.1.0".as_ref())
+                .await
+                .unwrap();
+            assert_eq!(
+                cached.read(CONTENT.as_ref()).await.unwrap_err().kind(),
+                io::ErrorKind::NotFound
+            );
+            assert_eq!(cached.index().size, 0);
+        })
+    }
+}
@@ -0,0 +1,208 @@
+// SPDX-FileCopyrightText: 2022 Profian Inc. <opensource@profian.com>
+// SPDX-License-Identifier: AGPL-3.0-only
+
+use super::{Backend, Content, Staged};
+
+use std::os::unix::fs::DirBuilderExt;
+use std::path::Path;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::SystemTime;
+
+use async_std::fs::File as StdFile;
+use async_std::io;
+use axum::async_trait;
+use camino::{Utf8Path, Utf8PathBuf};
+use cap_async_std::fs::OpenOptions;
+use cap_async_std::fs_utf8::{Dir, DirBuilder, File};
+use futures::{AsyncReadExt, AsyncSeekExt, AsyncWrite, AsyncWriteExt};
+use tracing::warn;
+
+/// Prefix of the names of directories in the root, which removed directories are moved to
+/// before their contents are removed, followed by a UUID.
+pub(crate) const DELETED_PREFIX: &str = ".deleted-";
+
+/// [Backend] storing files in a local directory.
+///
+/// Files are written to temporary files named like `.<name>-<uuid>` next to them, which are
+/// moved into place once complete, such that readers never observe partially written files.
+#[derive(Debug)]
+pub struct Filesystem {
+    root: Dir,
+}
+
+impl Filesystem {
+    /// Constructs a new [Filesystem] storing files in `root`.
+    pub fn new(root: Dir) -> Self {
+        Self { root }
+    }
+
+    /// Opens the directory at `path` as a [Filesystem].
+    pub async fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        StdFile::open(path.as_ref())
+            .await
+            .map(|file| Self::new(Dir::from_std_file(file)))
+    }
+
+    /// Returns the path of a new temporary file to write `path` to.
+    fn temporary(path: &Utf8Path) -> Utf8PathBuf {
+        path.with_file_name(format!(
+            ".{}-{}",
+            path.file_name().unwrap_or_default(),
+            uuid::Uuid::new_v4()
+        ))
+    }
+}
+
+/// File of a [Filesystem], which is written to a temporary file.
+struct StagedFile {
+    root: Dir,
+    tmp: Utf8PathBuf,
+    path: Utf8PathBuf,
+    file: File,
+}
+
+impl AsyncWrite for StagedFile {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.file).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.file).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.file).poll_close(cx)
+    }
+}
+
+#[async_trait]
+impl Staged for StagedFile {
+    async fn commit(self: Box<Self>) -> io::Result<()> {
+        let res = async {
+            self.file.sync_all().await?;
+            self.root.rename(&self.tmp, &self.root, &self.path).await
+        }
+        .await;
+        if res.is_err() {
+            self.discard().await;
+        }
+        res
+    }
+
+    async fn discard(self: Box<Self>) {
+        let Self {
+            root, tmp, file, ..
+        } = *self;
+        drop(file);
+        if let Err(e) = root.remove_file(&tmp).await {
+            warn!(target: "app::store::Filesystem", "failed to remove `{tmp}`: {e}");
+        }
+    }
+}
+
+#[async_trait]
+impl Backend for Filesystem {
+    fn dir(&self) -> Option<&Dir> {
+        Some(&self.root)
+    }
+
+    async fn probe(&self) -> io::Result<()> {
+        self.root.metadata("users").await.map(|_| ())
+    }
+
+    async fn read(&self, path: &Utf8Path) -> io::Result<Vec<u8>> {
+        self.root.read(path).await
+    }
+
+    async fn read_range(
+        &self,
+        path: &Utf8Path,
+        offset: u64,
+        limit: u64,
+    ) -> io::Result<(u64, Vec<u8>)> {
+        let mut file = self.root.open(path).await?;
+        let len = file.metadata()?.len();
+        let mut buf = vec![];
+        if offset < len {
+            _ = file.seek(io::SeekFrom::Start(offset)).await?;
+            _ = file.take(limit).read_to_end(&mut buf).await?;
+        }
+        Ok((len, buf))
+    }
+
+    async fn open(&self, path: &Utf8Path) -> io::Result<Content> {
+        let file: Content = Box::pin(self.root.open(path).await?);
+        Ok(file)
+    }
+
+    async fn modified(&self, path: &Utf8Path) -> io::Result<SystemTime> {
+        self.root
+            .metadata(path)
+            .await
+            .and_then(|meta| meta.modified())
+            .map(|time| time.into_std())
+    }
+
+    async fn stage(&self, path: &Utf8Path) -> io::Result<Box<dyn Staged>> {
+        let tmp = Self::temporary(path);
+        let file = self.root.create(&tmp).await?;
+        Ok(Box::new(StagedFile {
+            root: self.root.clone(),
+            tmp,
+            path: path.into(),
+            file,
+        }))
+    }
+
+    async fn write(&self, path: &Utf8Path, buf: Vec<u8>) -> io::Result<()> {
+        let tmp = Self::temporary(path);
+        if let Err(e) = self.root.write(&tmp, buf).await {
+            _ = self.root.remove_file(&tmp).await;
+            return Err(e);
+        }
+        self.root.rename(&tmp, &self.root, path).await
+    }
+
+    async fn append(&self, path: &Utf8Path, buf: &[u8]) -> io::Result<()> {
+        // Each buffer is appended using a single write, such that concurrent appends are not
+        // interleaved.
+        self.root
+            .open_with(path, OpenOptions::new().append(true).create(true))
+            .await?
+            .write_all(buf)
+            .await
+    }
+
+    async fn create_dir(&self, path: &Utf8Path) -> io::Result<()> {
+        self.root
+            .create_dir_with(path, DirBuilder::new().mode(0o700))
+    }
+
+    async fn list(&self, path: &Utf8Path) -> io::Result<Vec<String>> {
+        let mut names = vec![];
+        for entry in self.root.read_dir(path).await? {
+            names.push(entry?.file_name()?);
+        }
+        Ok(names)
+    }
+
+    async fn remove(&self, path: &Utf8Path) -> io::Result<()> {
+        self.root.remove_file(path).await
+    }
+
+    async fn remove_dir_all(&self, path: &Utf8Path) -> io::Result<()> {
+        // The directory is moved into the root first, such that it disappears at once and its
+        // path may be reused while its contents are removed.
+        let removed = Utf8PathBuf::from(format!("{DELETED_PREFIX}{}", uuid::Uuid::new_v4()));
+        self.root.rename(path, &self.root, &removed).await?;
+        if let Err(e) = self.root.remove_dir_all(&removed).await {
+            warn!(target: "app::store::Filesystem", "failed to remove `{removed}`: {e}");
+        }
+        Ok(())
+    }
+}
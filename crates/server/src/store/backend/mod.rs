@@ -0,0 +1,227 @@
+// SPDX-FileCopyrightText: 2022 Profian Inc. <opensource@profian.com>
+// SPDX-License-Identifier: AGPL-3.0-only
+
+mod breaker;
+mod cache;
+mod fs;
+mod s3;
+
+pub use breaker::*;
+pub use cache::*;
+pub use fs::*;
+pub use s3::*;
+
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::str::FromStr;
+use std::time::SystemTime;
+
+use anyhow::{anyhow, bail, Context};
+use async_std::io;
+use axum::async_trait;
+use camino::Utf8Path;
+use cap_async_std::fs_utf8::Dir;
+use futures::{AsyncRead, AsyncWrite};
+use openidconnect::url::Url;
+
+/// Reader of the contents of a stored file, which does not borrow the [Backend], such that it
+/// may outlive the request handler, e.g. to stream a response body.
+pub type Content = Pin<Box<dyn AsyncRead + Send>>;
+
+/// File being written to a [Backend], which only becomes visible at its path once committed.
+// `async_trait` marks the methods `#[must_use]` in addition to their returned futures.
+#[allow(clippy::double_must_use)]
+#[async_trait]
+pub trait Staged: AsyncWrite + Send + Unpin {
+    /// Moves the written contents into place, replacing any file stored at the path.
+    async fn commit(self: Box<Self>) -> io::Result<()>;
+
+    /// Discards the written contents.
+    async fn discard(self: Box<Self>);
+}
+
+/// Storage of the files and directories making up a [Store](super::Store), which are
+/// addressed by `/`-separated paths relative to its root.
+///
+/// Backends need not store directories as such, but must reject creating directories, which
+/// already exist, with [io::ErrorKind::AlreadyExists], since the store relies on that to
+/// detect conflicting creations of users, repositories and tags. Missing files and
+/// directories are reported with [io::ErrorKind::NotFound].
+#[allow(clippy::double_must_use)]
+#[async_trait]
+pub trait Backend: fmt::Debug + Send + Sync {
+    /// Returns the local directory holding the store, if any.
+    ///
+    /// Features relying on filesystem semantics, like the removal of files left over by a
+    /// crash, are only available for backends returning one.
+    fn dir(&self) -> Option<&Dir> {
+        None
+    }
+
+    /// Probes whether the store is accessible.
+    async fn probe(&self) -> io::Result<()>;
+
+    /// Reads the file at `path`.
+    async fn read(&self, path: &Utf8Path) -> io::Result<Vec<u8>>;
+
+    /// Reads at most `limit` bytes of the file at `path` starting at `offset` and returns them
+    /// along with the length of the file.
+    ///
+    /// No bytes are returned if `offset` is not less than the length of the file.
+    async fn read_range(
+        &self,
+        path: &Utf8Path,
+        offset: u64,
+        limit: u64,
+    ) -> io::Result<(u64, Vec<u8>)>;
+
+    /// Opens the file at `path` for reading.
+    async fn open(&self, path: &Utf8Path) -> io::Result<Content>;
+
+    /// Returns the time the file at `path` was last modified.
+    async fn modified(&self, path: &Utf8Path) -> io::Result<SystemTime>;
+
+    /// Starts writing the file at `path`, which is replaced once the returned file is
+    /// committed.
+    async fn stage(&self, path: &Utf8Path) -> io::Result<Box<dyn Staged>>;
+
+    /// Replaces the file at `path` by `buf`, such that it is either replaced completely or not
+    /// at all.
+    async fn write(&self, path: &Utf8Path, buf: Vec<u8>) -> io::Result<()>;
+
+    /// Appends `buf` to the file at `path`, which is created if it does not exist.
+    ///
+    /// Concurrent appends must neither be interleaved nor lost.
+    async fn append(&self, path: &Utf8Path, buf: &[u8]) -> io::Result<()>;
+
+    /// Creates the directory at `path`.
+    async fn create_dir(&self, path: &Utf8Path) -> io::Result<()>;
+
+    /// Returns the names of the entries of the directory at `path`.
+    async fn list(&self, path: &Utf8Path) -> io::Result<Vec<String>>;
+
+    /// Removes the file at `path`.
+    async fn remove(&self, path: &Utf8Path) -> io::Result<()>;
+
+    /// Removes the directory at `path` along with all of its contents.
+    ///
+    /// Backends, which cannot remove a directory at once, must remove the directory itself
+    /// last, such that it cannot be created again while its contents are being removed.
+    async fn remove_dir_all(&self, path: &Utf8Path) -> io::Result<()>;
+}
+
+/// Location of a store, whose URL scheme selects the [Backend] used to access it.
+// Store URLs are only parsed once on startup, such that their size does not matter.
+#[allow(clippy::large_enum_variant)]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum StoreUrl {
+    /// Local directory, e.g. `file:///var/lib/drawbridge`.
+    File(PathBuf),
+    /// Bucket of an S3-compatible object store, e.g. `s3://bucket/prefix`.
+    S3(S3Config),
+}
+
+impl StoreUrl {
+    /// Opens the [Backend] of the store.
+    pub(crate) async fn open(&self) -> anyhow::Result<Box<dyn Backend>> {
+        match self {
+            Self::File(path) => Ok(Box::new(Filesystem::open(path).await?)),
+            Self::S3(config) => Ok(Box::new(S3::new(config.clone()))),
+        }
+    }
+}
+
+impl FromStr for StoreUrl {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let url = Url::parse(s).with_context(|| format!("invalid store URL `{s}`"))?;
+        match url.scheme() {
+            "file" => url
+                .to_file_path()
+                .map(Self::File)
+                .map_err(|()| anyhow!("store URL `{s}` does not name an absolute local path")),
+            "s3" => {
+                let bucket = url
+                    .host_str()
+                    .filter(|bucket| !bucket.is_empty())
+                    .with_context(|| format!("store URL `{s}` does not name a bucket"))?;
+                if url.query().is_some() || url.fragment().is_some() {
+                    bail!("store URL `{s}` must not contain a query or fragment");
+                }
+                Ok(Self::S3(S3Config::new(
+                    bucket,
+                    url.path().trim_matches('/'),
+                )))
+            }
+            scheme => bail!("unsupported store URL scheme `{scheme}`, expected `file` or `s3`"),
+        }
+    }
+}
+
+impl fmt::Display for StoreUrl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::File(path) => write!(f, "{}", path.display()),
+            Self::S3(config) if config.prefix().is_empty() => {
+                write!(f, "s3://{}", config.bucket())
+            }
+            Self::S3(config) => write!(f, "s3://{}/{}", config.bucket(), config.prefix()),
+        }
+    }
+}
+
+impl From<PathBuf> for StoreUrl {
+    fn from(path: PathBuf) -> Self {
+        Self::File(path)
+    }
+}
+
+impl From<&Path> for StoreUrl {
+    fn from(path: &Path) -> Self {
+        Self::File(path.into())
+    }
+}
+
+impl From<async_std::path::PathBuf> for StoreUrl {
+    fn from(path: async_std::path::PathBuf) -> Self {
+        Self::File(path.into())
+    }
+}
+
+impl From<&async_std::path::Path> for StoreUrl {
+    fn from(path: &async_std::path::Path) -> Self {
+        Self::File(path.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_str() {
+        assert_eq!(
+            "file:///var/lib/drawbridge".parse::<StoreUrl>().unwrap(),
+            StoreUrl::File("/var/lib/drawbridge".into())
+        );
+        assert_eq!(
+            "s3://bucket/some/prefix/".parse::<StoreUrl>().unwrap(),
+            StoreUrl::S3(S3Config::new("bucket", "some/prefix"))
+        );
+        assert_eq!(
+            "s3://bucket".parse::<StoreUrl>().unwrap(),
+            StoreUrl::S3(S3Config::new("bucket", ""))
+        );
+        assert!("file://host/var/lib/drawbridge"
+            .parse::<StoreUrl>()
+            .is_err());
+        assert!("s3:///prefix".parse::<StoreUrl>().is_err());
+        assert!("s3://bucket/prefix?versionId=1"
+            .parse::<StoreUrl>()
+            .is_err());
+        assert!("http://bucket/prefix".parse::<StoreUrl>().is_err());
+        assert!("/var/lib/drawbridge".parse::<StoreUrl>().is_err());
+    }
+}
@@ -0,0 +1,218 @@
+// SPDX-FileCopyrightText: 2022 Profian Inc. <opensource@profian.com>
+// SPDX-License-Identifier: AGPL-3.0-only
+
+use super::{GetError, Store};
+
+use std::collections::HashSet;
+
+use drawbridge_type::{RepositoryChange, RepositoryChangeKind, RepositoryContext, TagName};
+
+use anyhow::{anyhow, Context};
+use async_std::io;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use camino::Utf8Path;
+use serde::{Deserialize, Serialize};
+use tracing::{debug, warn};
+
+/// Name of the file holding the change log, which is not part of the store contents.
+const CHANGES: &str = ".changes";
+
+/// Maximum number of bytes of the change log read by a single [Store::changes] call.
+const MAX_CHANGES_READ: usize = 1024 * 1024;
+
+#[derive(Debug)]
+pub enum ChangesError<E> {
+    InvalidCursor,
+    Internal(E),
+}
+
+impl<E> IntoResponse for ChangesError<E> {
+    fn into_response(self) -> Response {
+        match self {
+            ChangesError::InvalidCursor => (StatusCode::BAD_REQUEST, "Invalid change log cursor"),
+            ChangesError::Internal(_) => {
+                (StatusCode::INTERNAL_SERVER_ERROR, "Storage backend failure")
+            }
+        }
+        .into_response()
+    }
+}
+
+/// Entry of the change log, which is stored as a single line of JSON.
+#[derive(Debug, Deserialize, Serialize)]
+struct Record {
+    repository: String,
+    #[serde(flatten)]
+    change: RepositoryChange,
+}
+
+impl Store {
+    /// Appends `change` of the repository `cx` to the change log.
+    ///
+    /// Failures are logged, but not returned, since the changed objects are already stored.
+    pub async fn record_change(&self, cx: &RepositoryContext, change: RepositoryChange) {
+        let res = async {
+            let mut line = serde_json::to_vec(&Record {
+                repository: cx.to_string(),
+                change,
+            })
+            .context("failed to encode change")?;
+            line.push(b'\n');
+            self.backend
+                .append(CHANGES.as_ref(), &line)
+                .await
+                .context("failed to append to change log")
+        }
+        .await;
+        if let Err(e) = res {
+            warn!(target: "app::store::changes", "failed to record change of `{cx}`: {e:?}");
+        }
+    }
+
+    /// Returns the changes of the repository `cx` recorded after the change log offset `since`
+    /// along with the offset to continue reading from.
+    ///
+    /// At most [MAX_CHANGES_READ] bytes of the change log are read, such that fewer changes may
+    /// be returned than were recorded, in which case the returned offset differs from `since`.
+    pub async fn changes(
+        &self,
+        cx: &RepositoryContext,
+        since: u64,
+    ) -> Result<(Vec<RepositoryChange>, u64), ChangesError<anyhow::Error>> {
+        // The byte preceding the offset is read as well, such that offsets, which do not
+        // point to the start of a record, are rejected.
+        let start = since.saturating_sub(1);
+        let (len, buf) = match self
+            .backend
+            .read_range(CHANGES.as_ref(), start, (MAX_CHANGES_READ + 1) as _)
+            .await
+        {
+            Ok(res) => res,
+            Err(e) if e.kind() == io::ErrorKind::NotFound && since == 0 => return Ok((vec![], 0)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                return Err(ChangesError::InvalidCursor)
+            }
+            Err(e) => {
+                return Err(ChangesError::Internal(
+                    anyhow::Error::new(e).context("failed to read change log"),
+                ))
+            }
+        };
+        if since > len {
+            return Err(ChangesError::InvalidCursor);
+        }
+        let buf = if since > 0 {
+            match buf.split_first() {
+                Some((b'\n', buf)) => buf,
+                _ => return Err(ChangesError::InvalidCursor),
+            }
+        } else {
+            &buf[..]
+        };
+        // Incomplete records, e.g. ones being appended concurrently, are read by the next call.
+        let complete = buf.iter().rposition(|b| *b == b'\n').map_or(0, |i| i + 1);
+
+        let repository = cx.to_string();
+        let changes = buf[..complete]
+            .split(|b| *b == b'\n')
+            .filter(|line| !line.is_empty())
+            .filter_map(|line| match serde_json::from_slice::<Record>(line) {
+                Ok(rec) => Some(rec),
+                Err(e) => {
+                    warn!(target: "app::store::changes", "skip invalid change log record: {e}");
+                    None
+                }
+            })
+            .filter(|rec| rec.repository == repository)
+            .map(|rec| rec.change)
+            .collect();
+        Ok((changes, since + complete as u64))
+    }
+
+    /// Appends a record of the creation of each stored tag, for which none is recorded in the
+    /// change log yet, and returns the number of appended records.
+    ///
+    /// Records are appended in the order the tags were created in using a single write, such
+    /// that an interrupted backfill can safely be repeated.
+    pub(super) async fn backfill_changes(&self) -> anyhow::Result<usize> {
+        let recorded: HashSet<_> = match self.backend.read(CHANGES.as_ref()).await {
+            Ok(buf) => buf
+                .split(|b| *b == b'\n')
+                .filter_map(|line| serde_json::from_slice::<Record>(line).ok())
+                .filter(|rec| rec.change.kind == RepositoryChangeKind::Added)
+                .map(|rec| (rec.repository, rec.change.tag))
+                .collect(),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => HashSet::new(),
+            Err(e) => return Err(e).context("failed to read change log"),
+        };
+        let get_error = |e| match e {
+            GetError::NotFound => anyhow!("not found"),
+            GetError::Internal(e) => e,
+        };
+
+        let mut tags = vec![];
+        for user in self.list("users").await.context("failed to list users")? {
+            let repos = self
+                .list(format!("users/{user}/repos"))
+                .await
+                .with_context(|| format!("failed to list repositories of `{user}`"))?;
+            for repo in repos {
+                let cx = RepositoryContext::try_from((user.as_str(), repo.as_str()))?;
+                let repository = self.repository(&cx);
+                let names: Vec<TagName> = repository
+                    .tags()
+                    .await
+                    .map_err(get_error)
+                    .with_context(|| format!("failed to list tags of `{cx}`"))?;
+                for name in names {
+                    let key = (cx.to_string(), name);
+                    if recorded.contains(&key) {
+                        continue;
+                    }
+                    let modified = repository
+                        .tag(&key.1)
+                        .modified()
+                        .await
+                        .map_err(get_error)
+                        .with_context(|| format!("failed to query tag `{cx}:{}`", key.1))?;
+                    tags.push((modified, key));
+                }
+            }
+        }
+        if tags.is_empty() {
+            return Ok(0);
+        }
+        tags.sort_by_key(|(modified, _)| *modified);
+
+        let mut buf = vec![];
+        for (_, (repository, tag)) in &tags {
+            debug!(target: "app::store::changes", "backfill creation of `{repository}:{tag}`");
+            serde_json::to_writer(
+                &mut buf,
+                &Record {
+                    repository: repository.clone(),
+                    change: RepositoryChange {
+                        kind: RepositoryChangeKind::Added,
+                        tag: tag.clone(),
+                        path: None,
+                    },
+                },
+            )
+            .context("failed to encode change")?;
+            buf.push(b'\n');
+        }
+        self.backend
+            .append(CHANGES.as_ref(), &buf)
+            .await
+            .context("failed to append to change log")?;
+        Ok(tags.len())
+    }
+
+    /// Returns the names of the entries of the directory at `path`, which are not transient.
+    pub(super) async fn list(&self, path: impl AsRef<Utf8Path>) -> io::Result<Vec<String>> {
+        let mut names = self.backend.list(path.as_ref()).await?;
+        names.retain(|name| !name.starts_with('.'));
+        Ok(names)
+    }
+}
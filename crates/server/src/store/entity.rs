@@ -1,23 +1,28 @@
 // SPDX-FileCopyrightText: 2022 Profian Inc. <opensource@profian.com>
 // SPDX-License-Identifier: AGPL-3.0-only
 
+use super::super::problem::{
+    insufficient_storage, is_storage_full, Problem, PROBLEM_DIGEST_MISMATCH,
+};
+use super::{Backend, Content, ReadRepair, RepairOutcome, Writes};
+
+use std::collections::BTreeSet;
 use std::io;
-use std::os::unix::fs::DirBuilderExt;
+use std::time::SystemTime;
 
 use drawbridge_type::Meta;
 
-use anyhow::Context;
+use anyhow::{anyhow, Context};
 use axum::http::StatusCode;
 use axum::response::{IntoResponse, Response};
 use camino::{Utf8Path, Utf8PathBuf};
-use cap_async_std::fs_utf8::{Dir, DirBuilder, ReadDir};
-use drawbridge_type::digest::ContentDigest;
+use drawbridge_type::digest::{Algorithm, Algorithms, ContentDigest};
 use futures::future::TryFutureExt;
-use futures::io::copy;
+use futures::io::{copy, sink};
 use futures::try_join;
 use futures::{AsyncRead, AsyncWrite};
 use serde::{Deserialize, Serialize};
-use tracing::{debug, trace};
+use tracing::{debug, error, info, trace, warn};
 
 const STORAGE_FAILURE_RESPONSE: (StatusCode, &str) =
     (StatusCode::INTERNAL_SERVER_ERROR, "Storage backend failure");
@@ -26,7 +31,8 @@ const STORAGE_FAILURE_RESPONSE: (StatusCode, &str) =
 pub enum CreateError<E> {
     Occupied,
     LengthMismatch { expected: u64, got: u64 },
-    DigestMismatch,
+    DigestMismatch { expected: ContentDigest },
+    InsufficientStorage,
     Internal(E),
 }
 
@@ -34,19 +40,34 @@ impl<E> IntoResponse for CreateError<E> {
     fn into_response(self) -> Response {
         match self {
             CreateError::Occupied => (StatusCode::CONFLICT, "Already exists").into_response(),
-            CreateError::DigestMismatch => {
-                (StatusCode::BAD_REQUEST, "Content digest mismatch").into_response()
-            }
+            CreateError::DigestMismatch { expected } => Problem::new(
+                StatusCode::BAD_REQUEST,
+                PROBLEM_DIGEST_MISMATCH,
+                "Content digest mismatch",
+            )
+            .detail(format!("Contents do not match the digest `{expected}`"))
+            .member("expected", expected)
+            .into_response(),
             CreateError::LengthMismatch { expected, got } => (
                 StatusCode::BAD_REQUEST,
                 format!("Content length mismatch, expected: {expected}, got {got}"),
             )
                 .into_response(),
+            CreateError::InsufficientStorage => insufficient_storage(),
             CreateError::Internal(_) => STORAGE_FAILURE_RESPONSE.into_response(),
         }
     }
 }
 
+/// Returns the [CreateError] of a failure to write to the store described by `msg`.
+fn write_error(e: io::Error, msg: &'static str) -> CreateError<anyhow::Error> {
+    if is_storage_full(&e) {
+        CreateError::InsufficientStorage
+    } else {
+        CreateError::Internal(anyhow::Error::new(e).context(msg))
+    }
+}
+
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub enum GetError<E> {
     NotFound,
@@ -63,6 +84,25 @@ impl<E> IntoResponse for GetError<E> {
     }
 }
 
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RemoveError<E> {
+    NotFound,
+    /// An object within the entity is being written.
+    Busy,
+    Internal(E),
+}
+
+impl<E> IntoResponse for RemoveError<E> {
+    fn into_response(self) -> Response {
+        match self {
+            RemoveError::NotFound => (StatusCode::NOT_FOUND, "Not found"),
+            RemoveError::Busy => (StatusCode::CONFLICT, "Being written, retry later"),
+            RemoveError::Internal(_) => STORAGE_FAILURE_RESPONSE,
+        }
+        .into_response()
+    }
+}
+
 #[derive(Debug)]
 pub enum GetToWriterError<E> {
     IO(io::Error),
@@ -84,28 +124,46 @@ impl<E> IntoResponse for GetToWriterError<E> {
     }
 }
 
+/// Metadata of a variant of the contents of an entity encoded using a content coding, which is
+/// written after the variant itself.
+#[derive(Debug, Serialize, Deserialize)]
+struct Variant {
+    /// Digest of the contents encoded by the variant.
+    source: ContentDigest,
+    /// Digest of the variant.
+    digest: ContentDigest,
+    /// Length of the variant.
+    length: u64,
+}
+
 #[derive(Copy, Clone, Debug)]
 pub struct Entity<'a, P> {
-    root: &'a Dir,
+    backend: &'a dyn Backend,
+    writes: &'a Writes,
+    repair: Option<&'a ReadRepair>,
     prefix: P,
 }
 
-async fn create_verified(
-    dir: &Dir,
-    path: impl AsRef<Utf8Path>,
+/// Returns whether the contents read from `rdr` match `meta`.
+async fn matches(meta: &Meta, rdr: impl Unpin + AsyncRead) -> io::Result<bool> {
+    match copy(meta.hash.clone().verifier(rdr), &mut sink()).await {
+        Ok(n) => Ok(n == meta.size),
+        Err(e) if e.kind() == io::ErrorKind::InvalidData => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+async fn copy_verified(
     hash: ContentDigest,
     size: u64,
     rdr: impl Unpin + AsyncRead,
+    dst: &mut (impl Unpin + AsyncWrite),
 ) -> Result<(), CreateError<anyhow::Error>> {
-    let mut file = dir.create(path).await.map_err(|e| match e.kind() {
-        io::ErrorKind::AlreadyExists => CreateError::Occupied,
-        _ => CreateError::Internal(anyhow::Error::new(e).context("failed to create file")),
-    })?;
-    match copy(hash.verifier(rdr), &mut file).await {
-        Err(e) if e.kind() == io::ErrorKind::InvalidData => Err(CreateError::DigestMismatch),
-        Err(e) => Err(CreateError::Internal(
-            anyhow::Error::new(e).context("failed to write file"),
-        )),
+    match copy(hash.clone().verifier(rdr), dst).await {
+        Err(e) if e.kind() == io::ErrorKind::InvalidData => {
+            Err(CreateError::DigestMismatch { expected: hash })
+        }
+        Err(e) => Err(write_error(e, "failed to write file")),
         Ok(n) if n != size => Err(CreateError::LengthMismatch {
             expected: size,
             got: n,
@@ -114,9 +172,39 @@ async fn create_verified(
     }
 }
 
+/// Verifies that the contents read from `rdr` match `meta` without storing them.
+pub async fn verify_content(
+    meta: Meta,
+    rdr: impl Unpin + AsyncRead,
+) -> Result<(), CreateError<anyhow::Error>> {
+    copy_verified(meta.hash, meta.size, rdr, &mut sink()).await
+}
+
+/// Verifies that the JSON encoding of `val` matches `meta` without storing it.
+pub async fn verify_json(
+    meta: Meta,
+    val: &impl Serialize,
+) -> Result<(), CreateError<anyhow::Error>> {
+    let buf = serde_json::to_vec(val)
+        .context("failed to encode value to JSON")
+        .map_err(CreateError::Internal)?;
+    verify_content(meta, buf.as_slice()).await
+}
+
 impl<'a> Entity<'a, &'static str> {
-    pub fn new(root: &'a Dir) -> Self {
-        Self { root, prefix: "" }
+    pub(crate) fn new(backend: &'a dyn Backend, writes: &'a Writes) -> Self {
+        Self {
+            backend,
+            writes,
+            repair: None,
+            prefix: "",
+        }
+    }
+
+    /// Repairs contents read from the entity and its children using `repair`, see
+    /// [Entity::get].
+    pub(crate) fn read_repair(self, repair: Option<&'a ReadRepair>) -> Self {
+        Self { repair, ..self }
     }
 }
 
@@ -124,7 +212,9 @@ impl<'a, P: AsRef<Utf8Path>> Entity<'a, P> {
     /// Returns a child [Entity] rooted at `path`.
     pub fn child(&self, path: impl AsRef<Utf8Path>) -> Entity<'a, Utf8PathBuf> {
         Entity {
-            root: self.root,
+            backend: self.backend,
+            writes: self.writes,
+            repair: self.repair,
             prefix: self.path(path),
         }
     }
@@ -141,6 +231,19 @@ impl<'a, P: AsRef<Utf8Path>> Entity<'a, P> {
         self.path("content")
     }
 
+    fn variant_path(&self, encoding: &str) -> Utf8PathBuf {
+        self.path(format!("content.{encoding}"))
+    }
+
+    fn variant_meta_path(&self, encoding: &str) -> Utf8PathBuf {
+        self.path(format!("content.{encoding}.json"))
+    }
+
+    /// Stores `meta` and the contents read from `rdr` in the entity.
+    ///
+    /// Contents are staged and only committed once verified, and metadata is written last, such
+    /// that entities with metadata are always complete. The write is registered for its whole
+    /// duration, such that the partially written entity is never removed as an orphan.
     pub(super) async fn create_from_reader(
         &self,
         meta: Meta,
@@ -150,25 +253,28 @@ impl<'a, P: AsRef<Utf8Path>> Entity<'a, P> {
         let meta_json = serde_json::to_vec(&meta)
             .context("failed to encode metadata")
             .map_err(CreateError::Internal)?;
-        try_join!(
-            self.root
-                .write(self.meta_path(), meta_json)
-                .map_err(|e| match e.kind() {
-                    io::ErrorKind::AlreadyExists => CreateError::Occupied,
-                    _ => CreateError::Internal(
-                        anyhow::Error::new(e).context("failed to write metadata"),
-                    ),
-                })
-                .map_err(|e| {
-                    debug!(target: "app::store::Entity::create_from_reader", "failed to create meta file `{:?}`", e);
-                    e
-                }),
-            create_verified(self.root, self.content_path(), meta.hash, meta.size, rdr).map_err(|e| {
-                debug!(target: "app::store::Entity::create_from_reader", "failed to create content file `{:?}`", e);
-                e
+
+        let _write = self.writes.begin(self.prefix.as_ref()).await;
+        let mut file = self
+            .backend
+            .stage(&self.content_path())
+            .await
+            .map_err(|e| write_error(e, "failed to create file"))?;
+        if let Err(e) = copy_verified(meta.hash, meta.size, rdr, &mut file).await {
+            debug!(target: "app::store::Entity::create_from_reader", "failed to create content file `{:?}`", e);
+            file.discard().await;
+            return Err(e);
+        }
+        file.commit()
+            .await
+            .map_err(|e| write_error(e, "failed to move content file"))?;
+        self.backend
+            .write(&self.meta_path(), meta_json)
+            .await
+            .map_err(|e| {
+                debug!(target: "app::store::Entity::create_from_reader", "failed to create meta file `{:?}`", e);
+                write_error(e, "failed to write metadata")
             })
-        )?;
-        Ok(())
     }
 
     pub(super) async fn create_json(
@@ -191,13 +297,12 @@ impl<'a, P: AsRef<Utf8Path>> Entity<'a, P> {
         debug_assert_ne!(path, self.content_path());
 
         trace!(target: "app::store::Entity::create_dir", "create directory at `{path}`");
-        self.root
-            .create_dir_with(path, DirBuilder::new().mode(0o700))
+        self.backend
+            .create_dir(&path)
+            .await
             .map_err(|e| match e.kind() {
                 io::ErrorKind::AlreadyExists => CreateError::Occupied,
-                _ => CreateError::Internal(
-                    anyhow::Error::new(e).context("failed to create directory"),
-                ),
+                _ => write_error(e, "failed to create directory"),
             })
             .map_err(|e| {
                 debug!(target: "app::store::Entity::create_dir", "failed to create directory: `{:?}`", e);
@@ -205,12 +310,35 @@ impl<'a, P: AsRef<Utf8Path>> Entity<'a, P> {
             })
     }
 
+    /// Removes the entity along with all entities stored within it.
+    ///
+    /// Writes are held off while the entity is removed, such that nothing is written into it
+    /// midway, and the removal is refused if an object within the entity is being written.
+    pub(super) async fn remove(&self) -> Result<(), RemoveError<anyhow::Error>> {
+        let path = self.prefix.as_ref();
+        trace!(target: "app::store::Entity::remove", "remove entity at `{path}`");
+        let _hold = self.writes.hold().await;
+        if self.writes.is_active_within(path) {
+            return Err(RemoveError::Busy);
+        }
+        self.backend
+            .remove_dir_all(path)
+            .await
+            .map_err(|e| match e.kind() {
+                io::ErrorKind::NotFound => RemoveError::NotFound,
+                _ => {
+                    RemoveError::Internal(anyhow::Error::new(e).context("failed to remove entity"))
+                }
+            })
+    }
+
+    /// Returns the names of the entries of the directory at `path`.
     pub(super) async fn read_dir(
         &self,
         path: impl AsRef<Utf8Path>,
-    ) -> Result<ReadDir, GetError<anyhow::Error>> {
-        self.root
-            .read_dir(self.path(path))
+    ) -> Result<Vec<String>, GetError<anyhow::Error>> {
+        self.backend
+            .list(&self.path(path))
             .await
             .map_err(|e| match e.kind() {
                 io::ErrorKind::NotFound => GetError::NotFound,
@@ -221,8 +349,8 @@ impl<'a, P: AsRef<Utf8Path>> Entity<'a, P> {
     /// Returns metadata of the entity.
     pub async fn get_meta(&self) -> Result<Meta, GetError<anyhow::Error>> {
         let buf = self
-            .root
-            .read(self.meta_path())
+            .backend
+            .read(&self.meta_path())
             .await
             .map_err(|e| match e.kind() {
                 io::ErrorKind::NotFound => GetError::NotFound,
@@ -233,10 +361,51 @@ impl<'a, P: AsRef<Utf8Path>> Entity<'a, P> {
             .map_err(GetError::Internal)
     }
 
+    /// Returns the time the contents of the entity were last modified, i.e. stored, since
+    /// entities are immutable.
+    pub async fn modified(&self) -> Result<SystemTime, GetError<anyhow::Error>> {
+        self.backend
+            .modified(&self.content_path())
+            .await
+            .map_err(|e| match e.kind() {
+                io::ErrorKind::NotFound => GetError::NotFound,
+                _ => GetError::Internal(
+                    anyhow::Error::new(e).context("failed to query content modification time"),
+                ),
+            })
+    }
+
+    /// Checks whether the entity is already stored with contents described by `meta`.
+    ///
+    /// Returns `false` if the entity does not exist and `true` if it exists and all digests
+    /// advertised in `meta` match the stored ones. An entity, which exists, but does not match
+    /// `meta` results in [CreateError::Occupied].
+    pub async fn is_stored(&self, meta: &Meta) -> Result<bool, CreateError<anyhow::Error>> {
+        let stored = match self.get_meta().await {
+            Ok(stored) => stored,
+            Err(GetError::NotFound) => return Ok(false),
+            Err(GetError::Internal(e)) => return Err(CreateError::Internal(e)),
+        };
+        if meta.hash.is_empty()
+            || meta.size != stored.size
+            || meta.mime != stored.mime
+            || meta
+                .hash
+                .iter()
+                .any(|(algo, hash)| stored.hash.get(algo) != Some(hash))
+        {
+            return Err(CreateError::Occupied);
+        }
+        Ok(true)
+    }
+
     /// Returns contents of the entity as [AsyncRead].
-    pub async fn get_content(&self) -> Result<impl '_ + AsyncRead, GetError<anyhow::Error>> {
-        self.root
-            .open(self.content_path())
+    ///
+    /// The returned reader does not borrow the entity, such that it may outlive the request
+    /// handler, e.g. to stream a response body.
+    pub async fn get_content(&self) -> Result<Content, GetError<anyhow::Error>> {
+        self.backend
+            .open(&self.content_path())
             .map_err(|e| match e.kind() {
                 io::ErrorKind::NotFound => GetError::NotFound,
                 _ => {
@@ -248,8 +417,8 @@ impl<'a, P: AsRef<Utf8Path>> Entity<'a, P> {
 
     /// Reads contents of the entity.
     pub async fn read_content(&self) -> Result<Vec<u8>, GetError<anyhow::Error>> {
-        self.root
-            .read(self.content_path())
+        self.backend
+            .read(&self.content_path())
             .map_err(|e| match e.kind() {
                 io::ErrorKind::NotFound => GetError::NotFound,
                 _ => {
@@ -271,9 +440,188 @@ impl<'a, P: AsRef<Utf8Path>> Entity<'a, P> {
             .map_err(GetError::Internal)
     }
 
+    /// Stores the contents read from `rdr` as the variant of the contents with digest `source`
+    /// encoded using `encoding` and returns its length.
+    ///
+    /// Like contents, the variant is staged and only committed once complete, and its metadata
+    /// is written last.
+    pub(crate) async fn create_variant(
+        &self,
+        encoding: &str,
+        source: &ContentDigest,
+        rdr: impl Unpin + AsyncRead,
+    ) -> Result<u64, CreateError<anyhow::Error>> {
+        trace!(target: "app::store::Entity::create_variant", "create `{encoding}` variant at `{}`", self.prefix.as_ref());
+        let _write = self.writes.begin(self.prefix.as_ref()).await;
+        let mut file = self
+            .backend
+            .stage(&self.variant_path(encoding))
+            .await
+            .map_err(|e| write_error(e, "failed to create file"))?;
+        let mut dst = Algorithms::from(BTreeSet::from([Algorithm::Sha256])).writer(&mut file);
+        let length = match copy(rdr, &mut dst).await {
+            Ok(length) => length,
+            Err(e) => {
+                file.discard().await;
+                return Err(write_error(e, "failed to write variant"));
+            }
+        };
+        let variant = Variant {
+            source: source.clone(),
+            digest: dst.digests(),
+            length,
+        };
+        let variant_json = serde_json::to_vec(&variant)
+            .context("failed to encode variant metadata")
+            .map_err(CreateError::Internal)?;
+        file.commit()
+            .await
+            .map_err(|e| write_error(e, "failed to move variant file"))?;
+        self.backend
+            .write(&self.variant_meta_path(encoding), variant_json)
+            .await
+            .map_err(|e| write_error(e, "failed to write variant metadata"))?;
+        Ok(variant.length)
+    }
+
+    /// Returns the length of the variant of the contents with digest `source` encoded using
+    /// `encoding` and a reader of it, or `None` if no intact variant is stored.
+    ///
+    /// The variant is verified against its digest before being returned, which costs reading
+    /// it twice, but never decoding or encoding it. Variants failing verification or encoding
+    /// other contents are removed.
+    pub(crate) async fn get_variant(
+        &self,
+        encoding: &str,
+        source: &ContentDigest,
+    ) -> Result<Option<(u64, Content)>, GetError<anyhow::Error>> {
+        let internal = |e, msg| GetError::Internal(anyhow::Error::new(e).context(msg));
+        let buf = match self.backend.read(&self.variant_meta_path(encoding)).await {
+            Ok(buf) => buf,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(internal(e, "failed to read variant metadata")),
+        };
+        let invalid = match serde_json::from_slice::<Variant>(&buf) {
+            Err(e) => format!("has invalid metadata: {e}"),
+            Ok(variant) if variant.source != *source => "encodes other contents".into(),
+            Ok(variant) if variant.digest.is_empty() => "has no digest".into(),
+            Ok(variant) => match self.backend.open(&self.variant_path(encoding)).await {
+                Err(e) if e.kind() == io::ErrorKind::NotFound => "is missing".into(),
+                Err(e) => return Err(internal(e, "failed to open variant file")),
+                Ok(file) => match copy(variant.digest.verifier(file), &mut sink()).await {
+                    Err(e) if e.kind() == io::ErrorKind::InvalidData => {
+                        "does not match its digest".into()
+                    }
+                    Err(e) => return Err(internal(e, "failed to verify variant")),
+                    Ok(n) if n != variant.length => {
+                        format!("has a length of {n} instead of {}", variant.length)
+                    }
+                    // Contents are not necessarily seekable, such that the verified variant is
+                    // opened again.
+                    Ok(n) => {
+                        let file = self
+                            .backend
+                            .open(&self.variant_path(encoding))
+                            .await
+                            .map_err(|e| internal(e, "failed to open variant file"))?;
+                        return Ok(Some((n, file)));
+                    }
+                },
+            },
+        };
+        warn!(target: "app::store::Entity::get_variant", "remove `{encoding}` variant at `{}`, which {invalid}", self.prefix.as_ref());
+        for path in [
+            self.variant_meta_path(encoding),
+            self.variant_path(encoding),
+        ] {
+            match self.backend.remove(&path).await {
+                Ok(()) => {}
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => {
+                    warn!(target: "app::store::Entity::get_variant", "failed to remove `{path}`: {e}")
+                }
+            }
+        }
+        Ok(None)
+    }
+
     /// Returns metadata of the entity and a reader of its contents.
-    pub async fn get(&self) -> Result<(Meta, impl '_ + AsyncRead), GetError<anyhow::Error>> {
-        try_join!(self.get_meta(), self.get_content())
+    ///
+    /// With read repair, contents are verified against their metadata before being returned,
+    /// which costs reading them twice. Contents, which do not match, are rewritten from the
+    /// replica if it stores matching ones and fail to be read otherwise.
+    pub async fn get(&self) -> Result<(Meta, Content), GetError<anyhow::Error>> {
+        let Some(repair) = self.repair else {
+            return try_join!(self.get_meta(), self.get_content());
+        };
+        let meta = self.get_meta().await?;
+        if !matches(&meta, self.get_content().await?)
+            .await
+            .context("failed to verify content file")
+            .map_err(GetError::Internal)?
+        {
+            self.repair(repair, &meta).await?;
+        }
+        // Contents are not necessarily seekable, such that the verified contents are opened
+        // again.
+        Ok((meta, self.get_content().await?))
+    }
+
+    /// Rewrites the contents of the entity from the replica of `repair`, which are only
+    /// committed once verified against `meta`.
+    async fn repair(
+        &self,
+        repair: &ReadRepair,
+        meta: &Meta,
+    ) -> Result<(), GetError<anyhow::Error>> {
+        let path = self.content_path();
+        warn!(target: "app::store::Entity::repair", "contents of `{path}` do not match their digest, repair them from replica");
+        let res = async {
+            let rdr = repair
+                .replica
+                .open(&path)
+                .await
+                .context("failed to open content file in replica")?;
+            let _write = self.writes.begin(self.prefix.as_ref()).await;
+            let mut file = self
+                .backend
+                .stage(&path)
+                .await
+                .context("failed to create file")?;
+            let verified = match copy(meta.hash.clone().verifier(rdr), &mut file).await {
+                Ok(n) if n == meta.size => Ok(()),
+                Ok(n) => Err(anyhow!(
+                    "contents in replica have a length of {n} instead of {}",
+                    meta.size
+                )),
+                Err(e) if e.kind() == io::ErrorKind::InvalidData => Err(anyhow!(
+                    "contents in replica do not match their digest either"
+                )),
+                Err(e) => {
+                    Err(anyhow::Error::new(e).context("failed to copy contents from replica"))
+                }
+            };
+            match verified {
+                Ok(()) => file.commit().await.context("failed to move content file"),
+                Err(e) => {
+                    file.discard().await;
+                    Err(e)
+                }
+            }
+        }
+        .await;
+        match res {
+            Ok(()) => {
+                info!(target: "app::store::Entity::repair", "repaired contents of `{path}` from replica");
+                repair.record(RepairOutcome::Repaired);
+                Ok(())
+            }
+            Err(e) => {
+                error!(target: "app::store::Entity::repair", "failed to repair contents of `{path}`: {e:#}");
+                repair.record(RepairOutcome::Failed);
+                Err(GetError::Internal(e))
+            }
+        }
     }
 
     /// Returns metadata of the entity and writes its contents into `dst`.
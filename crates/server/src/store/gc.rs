@@ -0,0 +1,146 @@
+// SPDX-FileCopyrightText: 2022 Profian Inc. <opensource@profian.com>
+// SPDX-License-Identifier: AGPL-3.0-only
+
+use super::{Entity, GetError, RemoveError, Store, DELETED_PREFIX};
+
+use std::collections::HashSet;
+
+use drawbridge_type::{TreeDirectory, TreeEntry};
+
+use anyhow::Context;
+use async_std::io;
+use camino::{Utf8Path, Utf8PathBuf};
+use tracing::{debug, info, warn};
+
+/// Summary of a [Store::collect_garbage] run.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct GcSummary {
+    /// Number of removed tree nodes, which were not referenced by their parent.
+    pub nodes: u64,
+    /// Number of removed leftovers of interrupted deletions of tags and repositories.
+    pub remnants: u64,
+}
+
+impl Store {
+    /// Removes tree nodes, which are not referenced by the directory node containing them,
+    /// e.g. ones uploaded under a path the directory does not list, along with everything
+    /// below them and leftovers of interrupted deletions.
+    ///
+    /// Only children of completely written nodes are considered, since a directory being
+    /// written may still come to reference them. Completely written directories are
+    /// immutable, such that the nodes they do not reference are unreachable for good and
+    /// removed regardless of their age, unless an object within them is being written. Nodes
+    /// referenced by tags are never removed, such that this may be called while requests are
+    /// handled, including uploads.
+    pub async fn collect_garbage(&self) -> anyhow::Result<GcSummary> {
+        let mut summary = GcSummary::default();
+
+        // Deletions from stores held in a local directory move the deleted directory into its
+        // root, from which it is removed afterwards.
+        if let Some(root) = self.dir() {
+            for entry in root.entries().await.context("failed to list store")? {
+                let entry = entry.context("failed to read store entry")?;
+                let name = entry
+                    .file_name()
+                    .context("failed to read store entry name")?;
+                if !name.starts_with(DELETED_PREFIX) || !entry.file_type().await?.is_dir() {
+                    continue;
+                }
+                debug!(target: "app::store::collect_garbage", "remove remnant of deletion `{name}`");
+                match root.remove_dir_all(&name).await {
+                    Ok(()) => summary.remnants += 1,
+                    // The deletion completed in the meantime.
+                    Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                    Err(e) => {
+                        warn!(target: "app::store::collect_garbage", "failed to remove `{name}`: {e}")
+                    }
+                }
+            }
+        }
+
+        for user in self.list_existing("users").await? {
+            let repos = format!("users/{user}/repos");
+            for repo in self.list_existing(&repos).await? {
+                let tags = format!("{repos}/{repo}/tags");
+                for tag in self.list_existing(&tags).await? {
+                    let tree = Utf8PathBuf::from(format!("{tags}/{tag}/tree"));
+                    summary.nodes += self.collect_tree(tree).await?;
+                }
+            }
+        }
+        if summary.nodes > 0 || summary.remnants > 0 {
+            info!(
+                target: "app::store::collect_garbage",
+                "removed {} unreferenced tree nodes and {} remnants of deletions from store",
+                summary.nodes,
+                summary.remnants,
+            );
+        }
+        Ok(summary)
+    }
+
+    /// Removes the unreferenced nodes of the tree rooted at `root` and returns their number.
+    async fn collect_tree(&self, root: Utf8PathBuf) -> anyhow::Result<u64> {
+        let mut removed = 0;
+        let mut nodes = vec![root];
+        while let Some(path) = nodes.pop() {
+            let node = Entity::new(self.backend.as_ref(), &self.writes).child(&path);
+            let meta = match node.get_meta().await {
+                Ok(meta) => meta,
+                // The node is partially written or was removed concurrently.
+                Err(GetError::NotFound) => continue,
+                Err(GetError::Internal(e)) => {
+                    return Err(e.context(format!("failed to read metadata of `{path}`")))
+                }
+            };
+            // File nodes reference no children.
+            let referenced: HashSet<String> = if meta.mime.essence_str()
+                == TreeDirectory::<()>::TYPE
+            {
+                match node.get_content_json::<TreeDirectory<TreeEntry>>().await {
+                    Ok(dir) => dir.keys().map(ToString::to_string).collect(),
+                    Err(GetError::NotFound) => continue,
+                    Err(GetError::Internal(e)) => {
+                        warn!(target: "app::store::collect_garbage", "skip directory `{path}`, which failed to be read: {e:?}");
+                        continue;
+                    }
+                }
+            } else {
+                HashSet::new()
+            };
+
+            let entries = path.join("entries");
+            for name in self.list_existing(&entries).await? {
+                let child = entries.join(&name);
+                if referenced.contains(&name) {
+                    nodes.push(child);
+                    continue;
+                }
+                debug!(target: "app::store::collect_garbage", "remove unreferenced tree node `{child}`");
+                match Entity::new(self.backend.as_ref(), &self.writes)
+                    .child(&child)
+                    .remove()
+                    .await
+                {
+                    Ok(()) => removed += 1,
+                    Err(RemoveError::NotFound | RemoveError::Busy) => {}
+                    Err(RemoveError::Internal(e)) => {
+                        warn!(target: "app::store::collect_garbage", "failed to remove `{child}`: {e:?}")
+                    }
+                }
+            }
+        }
+        Ok(removed)
+    }
+
+    /// Returns the names of the entries of the directory at `path`, which are not transient,
+    /// or none if it does not exist, e.g. because it was removed concurrently.
+    async fn list_existing(&self, path: impl AsRef<Utf8Path>) -> anyhow::Result<Vec<String>> {
+        let path = path.as_ref();
+        match self.list(path).await {
+            Ok(names) => Ok(names),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(vec![]),
+            Err(e) => Err(e).with_context(|| format!("failed to list `{path}`")),
+        }
+    }
+}
@@ -1,28 +1,55 @@
 // SPDX-FileCopyrightText: 2022 Profian Inc. <opensource@profian.com>
 // SPDX-License-Identifier: AGPL-3.0-only
 
+mod backend;
+mod changes;
 mod entity;
+mod gc;
+mod orphans;
+mod repair;
 mod repo;
 mod tag;
 mod tree;
 mod user;
+mod version;
 
+pub use backend::*;
+pub use changes::*;
 pub use entity::*;
+pub use gc::*;
+pub use orphans::*;
+pub use repair::*;
 pub use repo::*;
 pub use tag::*;
 pub use tree::*;
 pub use user::*;
+pub use version::*;
 
 use drawbridge_type::{Meta, RepositoryContext, TagContext, TreeContext, UserContext, UserRecord};
 
+use std::sync::Arc;
+
 use async_std::io;
 use camino::{Utf8Path, Utf8PathBuf};
 use cap_async_std::fs_utf8::Dir;
 use futures::try_join;
+use tempfile::TempDir;
+
+/// Name of the directory holding data of incomplete uploads, which is not part of the store
+/// contents.
+const UPLOADS: &str = ".uploads";
+
+/// Name of the directory holding idempotency records, which is not part of the store contents.
+const IDEMPOTENCY: &str = ".idempotency";
 
 #[derive(Debug)]
 pub struct Store {
-    root: Dir,
+    backend: Box<dyn Backend>,
+    writes: Arc<Writes>,
+    /// Local directory holding data of incomplete uploads and idempotency records of stores,
+    /// whose [Backend] has no local directory.
+    staging: Option<TempDir>,
+    repair: Option<ReadRepair>,
 }
 
 async fn upsert_dir(root: &Dir, path: impl AsRef<Utf8Path>) -> io::Result<()> {
@@ -37,12 +64,97 @@ async fn upsert_dir(root: &Dir, path: impl AsRef<Utf8Path>) -> io::Result<()> {
 impl Store {
     /// Initalizes a new [Store] at `root`
     pub async fn new(root: Dir) -> io::Result<Self> {
-        upsert_dir(&root, "users").await?;
-        Ok(Self { root })
+        Self::with_backend(Box::new(Filesystem::new(root))).await
+    }
+
+    /// Initalizes a new [Store] accessed using `backend`.
+    pub async fn with_backend(backend: Box<dyn Backend>) -> io::Result<Self> {
+        match backend.create_dir(Utf8Path::new("users")).await {
+            Err(e) if e.kind() != io::ErrorKind::AlreadyExists => return Err(e),
+            _ => {}
+        }
+        let staging = match backend.dir() {
+            Some(_) => None,
+            None => Some(tempfile::Builder::new().prefix("drawbridge-").tempdir()?),
+        };
+        Ok(Self {
+            backend,
+            writes: Default::default(),
+            staging,
+            repair: None,
+        })
+    }
+
+    /// Repairs contents read from the store, which do not match their digest, using `repair`.
+    pub(crate) fn with_read_repair(self, repair: ReadRepair) -> Self {
+        Self {
+            repair: Some(repair),
+            ..self
+        }
+    }
+
+    /// Opens the store at `url`.
+    pub async fn open(url: &StoreUrl) -> anyhow::Result<Self> {
+        let backend = url.open().await?;
+        Ok(Self::with_backend(backend).await?)
+    }
+
+    /// Returns the local directory holding the store, if its [Backend] has one.
+    pub(crate) fn dir(&self) -> Option<&Dir> {
+        self.backend.dir()
+    }
+
+    /// Probes whether the store is writable by creating and removing a file in its root.
+    ///
+    /// Returns `false` if the store resides on a read-only filesystem.
+    pub async fn is_writable(&self) -> io::Result<bool> {
+        let probe = Utf8PathBuf::from(format!(".probe-{}", uuid::Uuid::new_v4()));
+        match self.backend.write(&probe, vec![]).await {
+            Ok(()) => self.backend.remove(&probe).await.map(|()| true),
+            Err(e) if e.kind() == io::ErrorKind::ReadOnlyFilesystem => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Probes whether the store is accessible.
+    pub async fn probe(&self) -> io::Result<()> {
+        self.backend.probe().await
+    }
+
+    /// Returns the directory holding local state of the server, i.e. the store directory or
+    /// the staging directory of stores without one.
+    fn local(&self) -> io::Result<Dir> {
+        match (self.backend.dir(), &self.staging) {
+            (Some(root), _) => Ok(root.clone()),
+            (None, Some(staging)) => Ok(Dir::from_std_file(
+                std::fs::File::open(staging.path())?.into(),
+            )),
+            (None, None) => unreachable!("stores without a local directory are staged"),
+        }
+    }
+
+    /// Opens the directory holding data of incomplete uploads, removing any data left over by
+    /// a previous run.
+    pub async fn open_uploads(&self) -> io::Result<Dir> {
+        let root = self.local()?;
+        if root.is_dir(UPLOADS).await {
+            root.remove_dir_all(UPLOADS).await?;
+        }
+        root.create_dir(UPLOADS)?;
+        root.open_dir(UPLOADS).await
+    }
+
+    /// Opens the directory holding idempotency records, which are retained across runs of
+    /// stores with a local directory.
+    pub async fn open_idempotency(&self) -> io::Result<Dir> {
+        let root = self.local()?;
+        upsert_dir(&root, IDEMPOTENCY).await?;
+        root.open_dir(IDEMPOTENCY).await
     }
 
     pub fn user(&self, UserContext { name }: &UserContext) -> User<'_, Utf8PathBuf> {
-        Entity::new(&self.root)
+        Entity::new(self.backend.as_ref(), &self.writes)
+            .read_repair(self.repair.as_ref())
             .child(format!("users/{name}"))
             .into()
     }
@@ -62,15 +174,15 @@ impl Store {
     pub fn repository<'a>(
         &'a self,
         RepositoryContext { owner, name }: &'a RepositoryContext,
-    ) -> Repository<'_> {
+    ) -> Repository<'a> {
         self.user(owner).repository(name)
     }
 
-    pub fn tag<'a>(&'a self, TagContext { repository, name }: &'a TagContext) -> Tag<'_> {
+    pub fn tag<'a>(&'a self, TagContext { repository, name }: &'a TagContext) -> Tag<'a> {
         self.repository(repository).tag(name)
     }
 
-    pub fn tree<'a>(&'a self, TreeContext { tag, path }: &'a TreeContext) -> Node<'_> {
+    pub fn tree<'a>(&'a self, TreeContext { tag, path }: &'a TreeContext) -> Node<'a> {
         self.tag(tag).node(path)
     }
 }
@@ -0,0 +1,464 @@
+// SPDX-FileCopyrightText: 2022 Profian Inc. <opensource@profian.com>
+// SPDX-License-Identifier: AGPL-3.0-only
+
+use super::Store;
+
+use std::collections::HashMap;
+use std::num::NonZeroUsize;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant, SystemTime};
+
+use drawbridge_type::Meta;
+
+use anyhow::{bail, Context};
+use async_std::channel::{unbounded, Receiver, Sender};
+use async_std::io;
+use async_std::task::spawn;
+use camino::{Utf8Path, Utf8PathBuf};
+use cap_async_std::fs_utf8::Dir;
+use futures::lock::{Mutex as AsyncMutex, MutexGuard};
+use tracing::{debug, info, warn};
+use uuid::Uuid;
+
+/// Default age, after which temporary files and partially written objects are considered to
+/// be left over by a crash.
+pub const DEFAULT_ORPHAN_MAX_AGE: Duration = Duration::from_secs(60 * 60);
+
+/// Default number of directories of the store scanned concurrently on startup.
+pub const DEFAULT_STARTUP_SCAN_THREADS: usize = 8;
+
+/// Interval, at which the progress of a [Store::remove_orphans] run is reported.
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Name of the file holding the metadata of a stored object, which is written last.
+const META: &str = "meta.json";
+
+/// Name of the file holding the contents of a stored object.
+const CONTENT: &str = "content";
+
+/// Summary of a [Store::remove_orphans] run.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct OrphanSummary {
+    /// Number of removed temporary files.
+    pub temporary: u64,
+    /// Number of removed partially written objects.
+    pub partial: u64,
+    /// Total size of the removed files.
+    pub bytes: u64,
+}
+
+/// Returns `true` for names of temporary files created by Drawbridge, i.e. ones formatted like
+/// `.<purpose>-<uuid>`, as opposed to other transient files, like the change log.
+pub(crate) fn is_temporary(name: &str) -> bool {
+    name.strip_prefix('.')
+        .and_then(|name| name.len().checked_sub(36).map(|i| name.split_at(i)))
+        .is_some_and(|(purpose, id)| {
+            purpose.len() > 1 && purpose.ends_with('-') && Uuid::parse_str(id).is_ok()
+        })
+}
+
+/// Returns `true` if `modified` is at least `max_age` ago.
+fn is_stale(modified: io::Result<SystemTime>, max_age: Duration) -> bool {
+    modified
+        .ok()
+        .and_then(|modified| SystemTime::now().duration_since(modified).ok())
+        .is_some_and(|age| age >= max_age)
+}
+
+/// Registry of objects currently being written, which are never removed as orphans, even if
+/// they are stale, e.g. because their contents are uploaded for longer than the orphan age.
+#[derive(Debug, Default)]
+pub(crate) struct Writes {
+    /// Held while registering writes and while removing orphans, such that no write starts
+    /// on an object while it is removed.
+    gate: AsyncMutex<()>,
+    active: Mutex<HashMap<Utf8PathBuf, usize>>,
+}
+
+impl Writes {
+    /// Registers a write of the object at `dir` until the returned guard is dropped.
+    pub(crate) async fn begin(&self, dir: &Utf8Path) -> WriteGuard<'_> {
+        let _gate = self.gate.lock().await;
+        *self
+            .active
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .entry(dir.to_path_buf())
+            .or_default() += 1;
+        WriteGuard {
+            writes: self,
+            dir: dir.to_path_buf(),
+        }
+    }
+
+    fn is_active(&self, dir: &Utf8Path) -> bool {
+        self.active
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .contains_key(dir)
+    }
+
+    /// Returns whether an object at or below `dir` is being written.
+    pub(crate) fn is_active_within(&self, dir: &Utf8Path) -> bool {
+        self.active
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .keys()
+            .any(|path| path.starts_with(dir))
+    }
+
+    /// Holds off new writes until the returned guard is dropped, e.g. while removing objects.
+    pub(crate) async fn hold(&self) -> MutexGuard<'_, ()> {
+        self.gate.lock().await
+    }
+}
+
+/// Write of an object registered in [Writes].
+#[derive(Debug)]
+pub(crate) struct WriteGuard<'a> {
+    writes: &'a Writes,
+    dir: Utf8PathBuf,
+}
+
+impl Drop for WriteGuard<'_> {
+    fn drop(&mut self) {
+        let mut active = self
+            .writes
+            .active
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        if let Some(n) = active.get_mut(&self.dir) {
+            *n -= 1;
+            if *n == 0 {
+                _ = active.remove(&self.dir);
+            }
+        }
+    }
+}
+
+/// Directory queued to be scanned along with whether it holds an object.
+type Pending = (Utf8PathBuf, bool);
+
+/// State of a [Store::remove_orphans] run shared by its workers.
+#[derive(Debug)]
+struct Scan {
+    max_age: Duration,
+    /// Number of queued directories, which were not scanned yet, such that the scan completes
+    /// once it drops to zero.
+    pending: AtomicUsize,
+    scanned: AtomicU64,
+    reported: Mutex<Instant>,
+}
+
+impl Scan {
+    /// Records that a directory was scanned and reports the progress, if it was not reported
+    /// for [PROGRESS_INTERVAL].
+    fn scanned(&self) {
+        let scanned = self.scanned.fetch_add(1, Ordering::Relaxed) + 1;
+        let mut reported = self.reported.lock().unwrap_or_else(PoisonError::into_inner);
+        if reported.elapsed() >= PROGRESS_INTERVAL {
+            *reported = Instant::now();
+            info!(target: "app::store::remove_orphans", "scanned {scanned} directories of store, {} pending", self.pending.load(Ordering::Relaxed).saturating_sub(1));
+        }
+    }
+}
+
+/// Scanner of the local directory of a [Store] for orphans.
+#[derive(Clone, Debug)]
+struct Scanner {
+    root: Dir,
+    writes: Arc<Writes>,
+}
+
+impl Store {
+    /// Removes temporary files and partially written objects, which were last modified at
+    /// least `max_age` ago, e.g. ones left over by a crash during an upload.
+    ///
+    /// Objects are only considered partially written if their metadata is missing or does not
+    /// match their contents, such that objects referenced by tags can still be uploaded again.
+    /// Data of other transient files, like the change log, and of incomplete upload sessions is
+    /// left untouched, and so are objects currently being written, such that this may be
+    /// called while requests are handled.
+    ///
+    /// Up to `threads` directories are scanned concurrently and the progress is reported
+    /// periodically. Only stores held in a local directory can be scanned, since other backends
+    /// never leave temporary files behind.
+    pub async fn remove_orphans(
+        &self,
+        max_age: Duration,
+        threads: NonZeroUsize,
+    ) -> anyhow::Result<OrphanSummary> {
+        let Some(root) = self.dir() else {
+            bail!("store is not held in a local directory");
+        };
+        Scanner {
+            root: root.clone(),
+            writes: Arc::clone(&self.writes),
+        }
+        .remove_orphans(max_age, threads)
+        .await
+    }
+}
+
+impl Scanner {
+    /// Returns whether the object at `dir` is partially written, i.e. its metadata, which is
+    /// written after its contents were moved into place, is missing or does not match its
+    /// contents.
+    async fn is_partial(&self, dir: &Utf8Path) -> io::Result<bool> {
+        let meta = match self.root.read(dir.join(META)).await {
+            Ok(meta) => meta,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(true),
+            Err(e) => return Err(e),
+        };
+        let Ok(Meta { size, .. }) = serde_json::from_slice(&meta) else {
+            return Ok(true);
+        };
+        match self.root.metadata(dir.join(CONTENT)).await {
+            Ok(content) => Ok(content.len() != size),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(true),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Returns the total size of the files contained in `dir`.
+    async fn dir_size(&self, dir: &Utf8Path) -> io::Result<u64> {
+        let mut size = 0;
+        let mut dirs = vec![dir.to_path_buf()];
+        while let Some(dir) = dirs.pop() {
+            for entry in self.root.read_dir(&dir).await? {
+                let entry = entry?;
+                let meta = entry.metadata()?;
+                if meta.is_dir() {
+                    dirs.push(dir.join(entry.file_name()?));
+                } else {
+                    size += meta.len();
+                }
+            }
+        }
+        Ok(size)
+    }
+
+    async fn remove_orphans(
+        &self,
+        max_age: Duration,
+        threads: NonZeroUsize,
+    ) -> anyhow::Result<OrphanSummary> {
+        let mut summary = OrphanSummary::default();
+
+        // Temporary files of the store root, e.g. left over by writability probes.
+        for entry in self.root.entries().await.context("failed to list store")? {
+            let entry = entry.context("failed to read store entry")?;
+            let name = entry
+                .file_name()
+                .context("failed to read store entry name")?;
+            let meta = entry
+                .metadata()
+                .with_context(|| format!("failed to query `{name}`"))?;
+            if meta.is_file()
+                && is_temporary(&name)
+                && is_stale(meta.modified().map(|t| t.into_std()), max_age)
+            {
+                self.remove_orphan_file(Utf8Path::new(&name), meta.len(), &mut summary)
+                    .await;
+            }
+        }
+
+        // Objects are stored in directories named by their parent, except for the root
+        // directory of trees, which is stored in the directory of its tag. Directories are
+        // scanned concurrently by `threads` workers, which queue the subdirectories they find.
+        let scan = Arc::new(Scan {
+            max_age,
+            pending: AtomicUsize::new(1),
+            scanned: AtomicU64::new(0),
+            reported: Mutex::new(Instant::now()),
+        });
+        let (tx, rx) = unbounded();
+        tx.try_send((Utf8PathBuf::from("users"), false))
+            .expect("queue is neither full nor closed");
+        let workers: Vec<_> = (0..threads.get())
+            .map(|_| {
+                spawn(
+                    self.clone()
+                        .scan_worker(Arc::clone(&scan), tx.clone(), rx.clone()),
+                )
+            })
+            .collect();
+        drop((tx, rx));
+        let mut res = Ok(());
+        for worker in workers {
+            match worker.await {
+                Ok(found) => {
+                    summary.temporary += found.temporary;
+                    summary.partial += found.partial;
+                    summary.bytes += found.bytes;
+                }
+                Err(e) if res.is_ok() => res = Err(e),
+                Err(_) => {}
+            }
+        }
+        res.map(|()| summary)
+    }
+
+    /// Scans directories received from `rx` and queues their subdirectories to `tx` until the
+    /// scan completes or fails, in which case the queue is closed.
+    async fn scan_worker(
+        self,
+        scan: Arc<Scan>,
+        tx: Sender<Pending>,
+        rx: Receiver<Pending>,
+    ) -> anyhow::Result<OrphanSummary> {
+        let mut summary = OrphanSummary::default();
+        while let Ok((dir, is_object)) = rx.recv().await {
+            let dirs = match self
+                .scan_dir(&dir, is_object, scan.max_age, &mut summary)
+                .await
+            {
+                Ok(dirs) => dirs,
+                Err(e) => {
+                    _ = tx.close();
+                    return Err(e);
+                }
+            };
+            // Subdirectories are counted before the scanned directory is, such that pending
+            // directories are never miscounted as none.
+            _ = scan.pending.fetch_add(dirs.len(), Ordering::SeqCst);
+            for dir in dirs {
+                // Sends only fail once the queue is closed due to a failure.
+                _ = tx.try_send(dir);
+            }
+            scan.scanned();
+            if scan.pending.fetch_sub(1, Ordering::SeqCst) == 1 {
+                _ = tx.close();
+            }
+        }
+        Ok(summary)
+    }
+
+    /// Removes `dir`, if it is a stale and partially written object, or stale temporary files
+    /// within it otherwise, and returns its subdirectories to scan.
+    async fn scan_dir(
+        &self,
+        dir: &Utf8Path,
+        is_object: bool,
+        max_age: Duration,
+        summary: &mut OrphanSummary,
+    ) -> anyhow::Result<Vec<Pending>> {
+        if is_object {
+            let stale = is_stale(
+                self.root
+                    .metadata(dir)
+                    .await
+                    .and_then(|meta| meta.modified())
+                    .map(|t| t.into_std()),
+                max_age,
+            );
+            if stale
+                && self
+                    .is_partial(dir)
+                    .await
+                    .with_context(|| format!("failed to inspect `{dir}`"))?
+            {
+                self.remove_orphan_dir(dir, summary).await;
+                return Ok(vec![]);
+            }
+        }
+        let entries = self
+            .root
+            .read_dir(dir)
+            .await
+            .with_context(|| format!("failed to list `{dir}`"))?;
+        let mut dirs = vec![];
+        for entry in entries {
+            let entry = entry.with_context(|| format!("failed to read entry of `{dir}`"))?;
+            let name = entry
+                .file_name()
+                .with_context(|| format!("failed to read entry name of `{dir}`"))?;
+            let path = dir.join(&name);
+            let meta = entry
+                .metadata()
+                .with_context(|| format!("failed to query `{path}`"))?;
+            if meta.is_dir() && !name.starts_with('.') {
+                dirs.push((path, !is_object || name == "tree"));
+            } else if meta.is_file()
+                && is_temporary(&name)
+                && is_stale(meta.modified().map(|t| t.into_std()), max_age)
+            {
+                self.remove_orphan_file(&path, meta.len(), summary).await;
+            }
+        }
+        Ok(dirs)
+    }
+
+    async fn remove_orphan_file(&self, path: &Utf8Path, size: u64, summary: &mut OrphanSummary) {
+        let _gate = self.writes.gate.lock().await;
+        if path.parent().is_some_and(|dir| self.writes.is_active(dir)) {
+            return;
+        }
+        debug!(target: "app::store::remove_orphans", "remove temporary file `{path}`");
+        match self.root.remove_file(path).await {
+            Ok(()) => {
+                summary.temporary += 1;
+                summary.bytes += size;
+            }
+            // The file was moved into place by a write, which completed in the meantime.
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => warn!(target: "app::store::remove_orphans", "failed to remove `{path}`: {e}"),
+        }
+    }
+
+    async fn remove_orphan_dir(&self, path: &Utf8Path, summary: &mut OrphanSummary) {
+        let _gate = self.writes.gate.lock().await;
+        // The object may have been written completely since it was inspected.
+        if self.writes.is_active(path) || !self.is_partial(path).await.unwrap_or(false) {
+            return;
+        }
+        debug!(target: "app::store::remove_orphans", "remove partially written object `{path}`");
+        let size = self.dir_size(path).await.unwrap_or_default();
+        match self.root.remove_dir_all(path).await {
+            Ok(()) => {
+                summary.partial += 1;
+                summary.bytes += size;
+            }
+            Err(e) => warn!(target: "app::store::remove_orphans", "failed to remove `{path}`: {e}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use async_std::task::block_on;
+
+    #[test]
+    fn temporary() {
+        const ID: &str = "1b4db7eb-4057-4ddf-91e0-36dec72071f5";
+        assert!(is_temporary(&format!(".content-{ID}")));
+        assert!(is_temporary(&format!(".import-{ID}")));
+        assert!(is_temporary(&format!(".version-{ID}")));
+        assert!(!is_temporary(&format!(".-{ID}")));
+        assert!(!is_temporary(&format!("content-{ID}")));
+        assert!(!is_temporary(&format!(".content{ID}")));
+        assert!(!is_temporary(".content-1b4db7eb"));
+        assert!(!is_temporary(".changes"));
+        assert!(!is_temporary(".uploads"));
+        assert!(!is_temporary("meta.json"));
+    }
+
+    #[test]
+    fn writes() {
+        block_on(async {
+            let writes = Writes::default();
+            let dir = Utf8Path::new("users/test");
+            let a = writes.begin(dir).await;
+            let b = writes.begin(dir).await;
+            assert!(writes.is_active(dir));
+            assert!(!writes.is_active(Utf8Path::new("users/other")));
+            drop(a);
+            assert!(writes.is_active(dir));
+            drop(b);
+            assert!(!writes.is_active(dir));
+        })
+    }
+}
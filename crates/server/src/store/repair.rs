@@ -0,0 +1,49 @@
+// SPDX-FileCopyrightText: 2022 Profian Inc. <opensource@profian.com>
+// SPDX-License-Identifier: AGPL-3.0-only
+
+use super::Backend;
+use crate::Metrics;
+
+use std::fmt;
+use std::sync::Arc;
+
+/// Outcome of repairing contents, which do not match their digest, from a replica.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RepairOutcome {
+    /// The contents were rewritten from the replica and served.
+    Repaired,
+    /// The replica provided no matching contents, such that an error was served.
+    Failed,
+}
+
+impl RepairOutcome {
+    /// All outcomes.
+    pub const ALL: [Self; 2] = [Self::Repaired, Self::Failed];
+}
+
+impl fmt::Display for RepairOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Repaired => write!(f, "repaired"),
+            Self::Failed => write!(f, "failed"),
+        }
+    }
+}
+
+/// Replica of a store, from which contents read from the store, which do not match their
+/// digest, are repaired.
+#[derive(Debug)]
+pub(crate) struct ReadRepair {
+    pub(super) replica: Box<dyn Backend>,
+    metrics: Arc<Metrics>,
+}
+
+impl ReadRepair {
+    pub(crate) fn new(replica: Box<dyn Backend>, metrics: Arc<Metrics>) -> Self {
+        Self { replica, metrics }
+    }
+
+    pub(super) fn record(&self, outcome: RepairOutcome) {
+        self.metrics.record_read_repair(outcome)
+    }
+}
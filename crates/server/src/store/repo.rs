@@ -1,7 +1,7 @@
 // SPDX-FileCopyrightText: 2022 Profian Inc. <opensource@profian.com>
 // SPDX-License-Identifier: AGPL-3.0-only
 
-use super::{CreateError, Entity, GetError, Tag};
+use super::{CreateError, Entity, GetError, RemoveError, Tag};
 
 use std::ops::Deref;
 
@@ -42,15 +42,9 @@ impl<'a, P: AsRef<Utf8Path>> Repository<'a, P> {
     pub async fn tags(&self) -> Result<Vec<TagName>, GetError<anyhow::Error>> {
         self.read_dir("tags")
             .await?
-            .try_fold(vec![], |mut names, entry| {
-                let name = entry?
-                    .file_name()
-                    .context("failed to read tag name")?
-                    .parse()
-                    .context("failed to parse tag name")?;
-                names.push(name);
-                Ok(names)
-            })
+            .into_iter()
+            .map(|name| name.parse().context("failed to parse tag name"))
+            .collect::<Result<_, _>>()
             .map_err(GetError::Internal)
     }
 
@@ -88,4 +82,9 @@ impl<'a, P: AsRef<Utf8Path>> Repository<'a, P> {
         tag.create_json(meta, entry).await?;
         Ok(tag)
     }
+
+    /// Removes the tag `name` along with its tree.
+    pub async fn remove_tag(&self, name: &TagName) -> Result<(), RemoveError<anyhow::Error>> {
+        self.tag(name).remove().await
+    }
 }
@@ -1,7 +1,7 @@
 // SPDX-FileCopyrightText: 2022 Profian Inc. <opensource@profian.com>
 // SPDX-License-Identifier: AGPL-3.0-only
 
-use super::{CreateError, Entity, Node};
+use super::{CreateError, Entity, Node, RemoveError};
 
 use std::ops::Deref;
 
@@ -73,4 +73,10 @@ impl<'a, P: AsRef<Utf8Path>> Tag<'a, P> {
         try_join!(node.create_json(meta, dir), node.create_dir("entries"))?;
         Ok(node)
     }
+
+    /// Removes the node at `path` along with all nodes below it, e.g. one left partially
+    /// written.
+    pub async fn remove_node(&self, path: &TreePath) -> Result<(), RemoveError<anyhow::Error>> {
+        self.node(path).remove().await
+    }
 }
@@ -1,7 +1,7 @@
 // SPDX-FileCopyrightText: 2022 Profian Inc. <opensource@profian.com>
 // SPDX-License-Identifier: AGPL-3.0-only
 
-use super::{CreateError, Entity, Repository};
+use super::{CreateError, Entity, GetError, RemoveError, Repository};
 
 use std::ops::Deref;
 
@@ -9,6 +9,7 @@ use drawbridge_type::{Meta, RepositoryConfig, RepositoryName};
 
 use camino::{Utf8Path, Utf8PathBuf};
 use futures::try_join;
+use serde::Serialize;
 
 #[repr(transparent)]
 #[derive(Copy, Clone, Debug)]
@@ -44,4 +45,47 @@ impl<'a, P: AsRef<Utf8Path>> User<'a, P> {
         try_join!(repo.create_json(meta, conf), repo.create_dir("tags"))?;
         Ok(repo)
     }
+
+    /// Removes the repository `name` along with all of its tags.
+    pub async fn remove_repository(
+        &self,
+        name: &RepositoryName,
+    ) -> Result<(), RemoveError<anyhow::Error>> {
+        self.repository(name).remove().await
+    }
+
+    /// Returns the access token `id` of the user.
+    pub fn token(&self, id: &str) -> Entity<'a, Utf8PathBuf> {
+        self.0.child(format!("tokens/{id}"))
+    }
+
+    /// Returns the IDs of the access tokens of the user.
+    pub async fn tokens(&self) -> Result<Vec<String>, GetError<anyhow::Error>> {
+        // Users, which never minted a token, have no token directory.
+        match self.read_dir("tokens").await {
+            Err(GetError::NotFound) => Ok(vec![]),
+            res => res,
+        }
+    }
+
+    /// Stores the access token `id` described by `rec`.
+    pub async fn create_token(
+        &self,
+        id: &str,
+        meta: Meta,
+        rec: &impl Serialize,
+    ) -> Result<(), CreateError<anyhow::Error>> {
+        match self.create_dir("tokens").await {
+            Ok(()) | Err(CreateError::Occupied) => {}
+            Err(e) => return Err(e),
+        }
+        let token = self.token(id);
+        token.create_dir("").await?;
+        token.create_json(meta, rec).await
+    }
+
+    /// Removes the access token `id`.
+    pub async fn remove_token(&self, id: &str) -> Result<(), RemoveError<anyhow::Error>> {
+        self.token(id).remove().await
+    }
 }
@@ -0,0 +1,98 @@
+// SPDX-FileCopyrightText: 2022 Profian Inc. <opensource@profian.com>
+// SPDX-License-Identifier: AGPL-3.0-only
+
+use super::Store;
+
+use anyhow::{bail, Context};
+use async_std::io;
+use tracing::info;
+
+/// Name of the file holding the layout version of the store.
+const VERSION: &str = ".version";
+
+/// Layout version of stores created by this version of Drawbridge.
+///
+/// 1. Initial layout, i.e. the one of stores without a version marker.
+/// 2. The change log records the creation of all stored tags.
+pub const STORE_VERSION: u32 = 2;
+
+/// Layout version of stores, which contain objects, but no version marker.
+const UNVERSIONED: u32 = 1;
+
+impl Store {
+    /// Returns the layout version of the store, or `None` if the store is empty and has no
+    /// version.
+    async fn version(&self) -> anyhow::Result<Option<u32>> {
+        match self.backend.read(VERSION.as_ref()).await {
+            Ok(version) => {
+                let version = String::from_utf8_lossy(&version);
+                version
+                    .trim()
+                    .parse()
+                    .map(Some)
+                    .with_context(|| format!("invalid store version `{}`", version.trim()))
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                let users = self
+                    .backend
+                    .list("users".as_ref())
+                    .await
+                    .context("failed to list users")?;
+                Ok((!users.is_empty()).then_some(UNVERSIONED))
+            }
+            Err(e) => Err(e).context("failed to read store version"),
+        }
+    }
+
+    /// Replaces the version marker of the store, such that it is either updated completely or
+    /// not at all.
+    async fn set_version(&self, version: u32) -> anyhow::Result<()> {
+        self.backend
+            .write(VERSION.as_ref(), format!("{version}\n").into_bytes())
+            .await
+            .context("failed to replace store version")
+    }
+
+    /// Asserts that the store uses the layout of [STORE_VERSION], migrating stores using an
+    /// older layout in place if `allow_migration` is set.
+    ///
+    /// Each migration step is performed, such that it can be repeated if interrupted, and the
+    /// version is only updated once it completes. Stores using a newer layout are rejected.
+    pub async fn upgrade(&self, allow_migration: bool) -> anyhow::Result<()> {
+        let version = match self.version().await? {
+            Some(version) => version,
+            None => {
+                info!(target: "app::store::upgrade", "initialize empty store at version {STORE_VERSION}");
+                return self.set_version(STORE_VERSION).await;
+            }
+        };
+        if version == STORE_VERSION {
+            return Ok(());
+        }
+        if version > STORE_VERSION {
+            bail!("store version {version} is newer than the supported version {STORE_VERSION}");
+        }
+        if !allow_migration {
+            bail!("store version {version} must be migrated to version {STORE_VERSION}, but migration is not allowed");
+        }
+        for from in version..STORE_VERSION {
+            let to = from + 1;
+            info!(target: "app::store::upgrade", "migrate store from version {from} to {to}");
+            match to {
+                2 => {
+                    let n = self
+                        .backfill_changes()
+                        .await
+                        .context("failed to backfill change log")?;
+                    info!(target: "app::store::upgrade", "recorded creation of {n} existing tags in change log");
+                }
+                _ => unreachable!("no migration to store version {to}"),
+            }
+            self.set_version(to)
+                .await
+                .with_context(|| format!("failed to migrate store to version {to}"))?;
+            info!(target: "app::store::upgrade", "migrated store to version {to}");
+        }
+        Ok(())
+    }
+}
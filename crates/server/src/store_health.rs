@@ -0,0 +1,104 @@
+// SPDX-FileCopyrightText: 2022 Profian Inc. <opensource@profian.com>
+// SPDX-License-Identifier: AGPL-3.0-only
+
+use super::Circuit;
+
+use std::fmt;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use anyhow::bail;
+use axum::http::header::RETRY_AFTER;
+use axum::http::{Request, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use tracing::debug;
+
+/// Number of consecutive failed probes, after which the store is considered unavailable.
+pub(crate) const STORE_FAILURE_THRESHOLD: usize = 3;
+
+/// Handling of a persistently unavailable store.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StoreFailurePolicy {
+    /// Reject all requests with `503 Service Unavailable` until the store recovers.
+    Serve503,
+    /// Stop serving, such that the process can be restarted.
+    Exit,
+}
+
+impl fmt::Display for StoreFailurePolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Serve503 => write!(f, "serve-503"),
+            Self::Exit => write!(f, "exit"),
+        }
+    }
+}
+
+impl FromStr for StoreFailurePolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "serve-503" => Ok(Self::Serve503),
+            "exit" => Ok(Self::Exit),
+            _ => bail!("unsupported store failure policy `{s}`"),
+        }
+    }
+}
+
+/// Availability of the store as determined by the latest probes.
+#[derive(Debug)]
+pub(crate) struct StoreHealth(AtomicBool);
+
+impl Default for StoreHealth {
+    fn default() -> Self {
+        Self(AtomicBool::new(true))
+    }
+}
+
+impl StoreHealth {
+    pub(crate) fn is_available(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn set_available(&self, available: bool) {
+        self.0.store(available, Ordering::Relaxed)
+    }
+}
+
+/// Rejects all requests with `503 Service Unavailable` while the store is unavailable.
+pub(crate) async fn reject_unavailable<B>(
+    health: Arc<StoreHealth>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    if health.is_available() {
+        next.run(req).await
+    } else {
+        debug!(target: "app::store_health", "reject request, store is unavailable");
+        (StatusCode::SERVICE_UNAVAILABLE, "Store is unavailable").into_response()
+    }
+}
+
+/// Rejects all requests with `503 Service Unavailable` while the circuit of the store is open.
+pub(crate) async fn reject_open_circuit<B>(
+    circuit: Arc<Circuit>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    if let Some(retry_after) = circuit.retry_after() {
+        debug!(target: "app::store_health", "reject request, store circuit is open");
+        // Round up, such that clients retry once operations are attempted again.
+        let secs = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+        (
+            StatusCode::SERVICE_UNAVAILABLE,
+            [(RETRY_AFTER, secs.max(1).to_string())],
+            "Store is unavailable",
+        )
+            .into_response()
+    } else {
+        next.run(req).await
+    }
+}
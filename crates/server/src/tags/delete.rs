@@ -0,0 +1,67 @@
+// SPDX-FileCopyrightText: 2022 Profian Inc. <opensource@profian.com>
+// SPDX-License-Identifier: AGPL-3.0-only
+
+use super::super::{GetError, OidcClaims, ScopeContext, Store};
+use super::{Precondition, TagLimit};
+
+use drawbridge_type::{RepositoryChange, RepositoryChangeKind, TagContext};
+
+use async_std::sync::Arc;
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::IntoResponse;
+use axum::Extension;
+use tracing::{debug, info, trace};
+
+/// Deletes a tag along with its tree, which is permitted to the owner of the repository and to
+/// administrators.
+///
+/// An `If-Match` header makes the deletion conditional on the current digest of the tag.
+pub async fn delete(
+    Extension(store): Extension<Arc<Store>>,
+    limit: Option<Extension<Arc<TagLimit>>>,
+    claims: OidcClaims,
+    cx: TagContext,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    trace!(target: "app::tags::delete", "called for `{cx}`");
+
+    let precondition = Precondition::from_headers(&headers)?;
+    let user = claims
+        .assert_owner_or_admin(&store, &cx.repository.owner, ScopeContext::Tag)
+        .await?;
+
+    let repo = user.repository(&cx.repository.name);
+    if !precondition.is_empty() {
+        let current = match repo.tag(&cx.name).get_meta().await {
+            Ok(current) => Some(current),
+            Err(GetError::NotFound) => None,
+            Err(e) => {
+                debug!(target: "app::tags::delete", "failed for `{cx}`: {:?}", e);
+                return Err(e.into_response());
+            }
+        };
+        precondition.evaluate(current.as_ref()).inspect_err(
+            |_| debug!(target: "app::tags::delete", "failed for `{cx}`: precondition failed"),
+        )?;
+    }
+    repo.remove_tag(&cx.name).await.map_err(|e| {
+        debug!(target: "app::tags::delete", "failed for `{cx}`: {:?}", e);
+        e.into_response()
+    })?;
+    info!(target: "app::tags::delete", subject = claims.subject(), "deleted tag `{cx}`");
+
+    if let Some(Extension(ref limit)) = limit {
+        limit.release(&cx.repository);
+    }
+    store
+        .record_change(
+            &cx.repository,
+            RepositoryChange {
+                kind: RepositoryChangeKind::Deleted,
+                tag: cx.name.clone(),
+                path: None,
+            },
+        )
+        .await;
+    Ok(StatusCode::NO_CONTENT)
+}
@@ -1,7 +1,7 @@
 // SPDX-FileCopyrightText: 2022 Profian Inc. <opensource@profian.com>
 // SPDX-License-Identifier: AGPL-3.0-only
 
-use super::super::Store;
+use super::super::{GetToWriterError, ResponseBuffer, ServerTiming, Store, Validators};
 use crate::auth::assert_repository_read;
 
 use drawbridge_type::TagContext;
@@ -11,10 +11,13 @@ use axum::body::Body;
 use axum::http::Request;
 use axum::response::IntoResponse;
 use axum::Extension;
+use futures::try_join;
 use tracing::{debug, trace};
 
 pub async fn get(
     Extension(ref store): Extension<Arc<Store>>,
+    timing: Option<Extension<ServerTiming>>,
+    buffer: Option<Extension<ResponseBuffer>>,
     cx: TagContext,
     req: Request<Body>,
 ) -> impl IntoResponse {
@@ -24,15 +27,25 @@ pub async fn get(
         .await
         .map_err(IntoResponse::into_response)?;
 
-    // TODO: Stream body
-    // https://github.com/profianinc/drawbridge/issues/56
-    let mut body = vec![];
-    repo.tag(&cx.name)
-        .get_to_writer(&mut body)
-        .await
-        .map_err(|e| {
-            debug!(target: "app::tags::get", "failed for `{cx}`: {:?}", e);
-            e.into_response()
+    let timing = timing.as_deref();
+    let buffer = buffer.map(|Extension(buffer)| buffer).unwrap_or_default();
+    let tag = repo.tag(&cx.name);
+    async {
+        let ((meta, rdr), modified) = ServerTiming::measure(timing, "store", async {
+            try_join!(tag.get(), tag.modified())
         })
-        .map(|meta| (meta, body))
+        .await
+        .map_err(GetToWriterError::Get)?;
+        let validators = Validators::new(&meta, modified);
+        let body = buffer
+            .read(timing, meta.size, rdr)
+            .await
+            .map_err(GetToWriterError::IO)?;
+        Ok((meta, validators, body))
+    }
+    .await
+    .map_err(|e: GetToWriterError<_>| {
+        debug!(target: "app::tags::get", "failed for `{cx}`: {:?}", e);
+        e.into_response()
+    })
 }
@@ -1,7 +1,7 @@
 // SPDX-FileCopyrightText: 2022 Profian Inc. <opensource@profian.com>
 // SPDX-License-Identifier: AGPL-3.0-only
 
-use super::super::Store;
+use super::super::{Store, Validators};
 use crate::auth::assert_repository_read;
 
 use drawbridge_type::TagContext;
@@ -11,6 +11,7 @@ use axum::body::Body;
 use axum::http::Request;
 use axum::response::IntoResponse;
 use axum::Extension;
+use futures::try_join;
 use tracing::{debug, trace};
 
 pub async fn head(
@@ -20,16 +21,17 @@ pub async fn head(
 ) -> impl IntoResponse {
     trace!(target: "app::tags::head", "called for `{cx}`");
 
-    assert_repository_read(store, &cx.repository, req)
-        .await
-        .map_err(IntoResponse::into_response)
-        .map(|(repo, _)| repo)?
-        .tag(&cx.name)
-        .get_meta()
+    let (repo, _) = assert_repository_read(store, &cx.repository, req)
         .await
+        .map_err(IntoResponse::into_response)?;
+    let tag = repo.tag(&cx.name);
+    try_join!(tag.get_meta(), tag.modified())
         .map_err(|e| {
             debug!(target: "app::tags::head", "failed for `{cx}`: {:?}", e);
             e.into_response()
         })
-        .map(|meta| (meta, ()))
+        .map(|(meta, modified)| {
+            let validators = Validators::new(&meta, modified);
+            (meta, validators, ())
+        })
 }
@@ -0,0 +1,123 @@
+// SPDX-FileCopyrightText: 2022 Profian Inc. <opensource@profian.com>
+// SPDX-License-Identifier: AGPL-3.0-only
+
+use super::super::problem::{Problem, PROBLEM_QUOTA_EXCEEDED};
+use super::super::Repository;
+
+use std::collections::HashMap;
+use std::sync::{Mutex, PoisonError};
+
+use drawbridge_type::RepositoryContext;
+
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+
+/// Limit of the number of tags per repository.
+///
+/// Tag counts are loaded from the store once per repository and maintained incrementally
+/// afterwards.
+#[derive(Debug)]
+pub struct TagLimit {
+    max: usize,
+    counts: Mutex<HashMap<RepositoryContext, usize>>,
+}
+
+impl TagLimit {
+    /// Constructs a new [TagLimit] allowing at most `max` tags per repository.
+    pub fn new(max: usize) -> Self {
+        Self {
+            max,
+            counts: Default::default(),
+        }
+    }
+
+    /// Reserves a tag in repository `repo` identified by `cx`.
+    ///
+    /// The reservation is released on drop, unless [TagReservation::commit] is called.
+    #[allow(clippy::result_large_err)]
+    pub(crate) async fn reserve<'a>(
+        &'a self,
+        cx: &RepositoryContext,
+        repo: &Repository<'_>,
+    ) -> Result<TagReservation<'a>, Response> {
+        let known = self
+            .counts
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .contains_key(cx);
+        let stored = if known {
+            0
+        } else {
+            repo.tags()
+                .await
+                .map_err(IntoResponse::into_response)?
+                .len()
+        };
+
+        let mut counts = self.counts.lock().unwrap_or_else(PoisonError::into_inner);
+        let count = counts.entry(cx.clone()).or_insert(stored);
+        if *count >= self.max {
+            return Err(Problem::new(
+                StatusCode::CONFLICT,
+                PROBLEM_QUOTA_EXCEEDED,
+                "Repository tag limit reached",
+            )
+            .detail(format!(
+                "Repository tag limit of {} reached, delete old tags to create new ones",
+                self.max
+            ))
+            .member("quota", "tags")
+            .member("limit", self.max)
+            .member("usage", *count)
+            .into_response());
+        }
+        *count += 1;
+        Ok(TagReservation {
+            limit: self,
+            cx: Some(cx.clone()),
+        })
+    }
+
+    /// Records the deletion of a tag of repository `cx`.
+    pub(crate) fn release(&self, cx: &RepositoryContext) {
+        if let Some(count) = self
+            .counts
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get_mut(cx)
+        {
+            *count = count.saturating_sub(1);
+        }
+    }
+
+    /// Records the deletion of repository `cx`, which may be created again without tags.
+    pub(crate) fn forget(&self, cx: &RepositoryContext) {
+        _ = self
+            .counts
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(cx);
+    }
+}
+
+/// Tag reservation returned by [TagLimit::reserve].
+#[derive(Debug)]
+pub(crate) struct TagReservation<'a> {
+    limit: &'a TagLimit,
+    cx: Option<RepositoryContext>,
+}
+
+impl TagReservation<'_> {
+    /// Marks the reserved tag as created.
+    pub(crate) fn commit(mut self) {
+        self.cx = None;
+    }
+}
+
+impl Drop for TagReservation<'_> {
+    fn drop(&mut self) {
+        if let Some(cx) = self.cx.take() {
+            self.limit.release(&cx);
+        }
+    }
+}
@@ -1,12 +1,18 @@
 // SPDX-FileCopyrightText: 2022 Profian Inc. <opensource@profian.com>
 // SPDX-License-Identifier: AGPL-3.0-only
 
+mod delete;
 mod get;
 mod head;
+mod limit;
+mod precondition;
 mod put;
 mod query;
 
+pub use delete::*;
 pub use get::*;
 pub use head::*;
+pub use limit::*;
+pub(crate) use precondition::*;
 pub use put::*;
 pub use query::*;
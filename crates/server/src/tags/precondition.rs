@@ -0,0 +1,171 @@
+// SPDX-FileCopyrightText: 2022 Profian Inc. <opensource@profian.com>
+// SPDX-License-Identifier: AGPL-3.0-only
+
+use drawbridge_type::digest::ContentDigest;
+use drawbridge_type::Meta;
+
+use axum::http::header::{IF_MATCH, IF_NONE_MATCH};
+use axum::http::{HeaderMap, HeaderName, StatusCode};
+use axum::response::{IntoResponse, Response};
+
+/// Expected state of a tag.
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum State {
+    /// The tag exists with any digest, i.e. `*`.
+    Any,
+    /// The tag exists with a digest matching all the given hashes.
+    Digest(ContentDigest),
+}
+
+impl State {
+    fn matches(&self, current: &Meta) -> bool {
+        match self {
+            Self::Any => true,
+            Self::Digest(digest) => digest
+                .iter()
+                .all(|(algo, hash)| current.hash.get(algo) == Some(hash)),
+        }
+    }
+}
+
+/// Preconditions on the current state of a tag, which are expressed using `If-Match` and
+/// `If-None-Match` request headers.
+///
+/// Entity tags of tags are their quoted content digests, e.g. `"sha-256=:...:"`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub(crate) struct Precondition {
+    if_match: Option<State>,
+    if_none_match: Option<State>,
+}
+
+#[allow(clippy::result_large_err)]
+fn parse(headers: &HeaderMap, name: HeaderName) -> Result<Option<State>, Response> {
+    let Some(value) = headers.get(&name) else {
+        return Ok(None);
+    };
+    let invalid = || {
+        (
+            StatusCode::BAD_REQUEST,
+            format!("Invalid `{name}` header value"),
+        )
+            .into_response()
+    };
+    let value = value.to_str().map_err(|_| invalid())?.trim();
+    if value == "*" {
+        return Ok(Some(State::Any));
+    }
+    value
+        .strip_prefix('"')
+        .and_then(|v| v.strip_suffix('"'))
+        .unwrap_or(value)
+        .parse::<ContentDigest>()
+        .ok()
+        .filter(|digest| !digest.is_empty())
+        .map(|digest| Some(State::Digest(digest)))
+        .ok_or_else(invalid)
+}
+
+impl Precondition {
+    /// Parses the preconditions contained in `headers`.
+    #[allow(clippy::result_large_err)]
+    pub(crate) fn from_headers(headers: &HeaderMap) -> Result<Self, Response> {
+        Ok(Self {
+            if_match: parse(headers, IF_MATCH)?,
+            if_none_match: parse(headers, IF_NONE_MATCH)?,
+        })
+    }
+
+    /// Returns `true` if no preconditions are specified.
+    pub(crate) fn is_empty(&self) -> bool {
+        self.if_match.is_none() && self.if_none_match.is_none()
+    }
+
+    /// Returns `true` if the tag must not exist, in which case concurrent creation of the tag
+    /// fails the precondition.
+    pub(crate) fn requires_absent(&self) -> bool {
+        self.if_none_match == Some(State::Any)
+    }
+
+    /// Evaluates the preconditions against the `current` state of the tag.
+    #[allow(clippy::result_large_err)]
+    pub(crate) fn evaluate(&self, current: Option<&Meta>) -> Result<(), Response> {
+        let if_match = self
+            .if_match
+            .as_ref()
+            .is_none_or(|state| current.is_some_and(|current| state.matches(current)));
+        let if_none_match = self
+            .if_none_match
+            .as_ref()
+            .is_none_or(|state| !current.is_some_and(|current| state.matches(current)));
+        if if_match && if_none_match {
+            Ok(())
+        } else {
+            Err(Self::failed())
+        }
+    }
+
+    /// Returns the response to a request, whose preconditions failed.
+    pub(crate) fn failed() -> Response {
+        (
+            StatusCode::PRECONDITION_FAILED,
+            "Tag does not match the expected state",
+        )
+            .into_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use axum::http::HeaderValue;
+    use drawbridge_type::digest::Algorithms;
+
+    #[test]
+    fn precondition() {
+        let meta = |content: &[u8]| {
+            Algorithms::default()
+                .read_sync(content)
+                .map(|(size, hash)| Meta {
+                    hash,
+                    size,
+                    mime: mime::APPLICATION_JSON,
+                })
+                .unwrap()
+        };
+        let (foo, bar) = (meta(b"foo"), meta(b"bar"));
+        let precondition = |name, value: &str| {
+            let mut headers = HeaderMap::new();
+            _ = headers.insert(name, HeaderValue::from_str(value).unwrap());
+            Precondition::from_headers(&headers).ok()
+        };
+
+        let none = Precondition::from_headers(&HeaderMap::new()).unwrap();
+        assert!(none.is_empty());
+        assert!(none.evaluate(None).is_ok());
+        assert!(none.evaluate(Some(&foo)).is_ok());
+
+        let exists = precondition(IF_MATCH, "*").unwrap();
+        assert!(exists.evaluate(None).is_err());
+        assert!(exists.evaluate(Some(&foo)).is_ok());
+
+        let is_foo = precondition(IF_MATCH, &format!(r#""{}""#, foo.hash)).unwrap();
+        assert!(is_foo.evaluate(None).is_err());
+        assert!(is_foo.evaluate(Some(&foo)).is_ok());
+        assert!(is_foo.evaluate(Some(&bar)).is_err());
+
+        let absent = precondition(IF_NONE_MATCH, "*").unwrap();
+        assert!(absent.requires_absent());
+        assert!(absent.evaluate(None).is_ok());
+        assert!(absent.evaluate(Some(&foo)).is_err());
+
+        let not_foo = precondition(IF_NONE_MATCH, &foo.hash.to_string()).unwrap();
+        assert!(!not_foo.requires_absent());
+        assert!(not_foo.evaluate(None).is_ok());
+        assert!(not_foo.evaluate(Some(&foo)).is_err());
+        assert!(not_foo.evaluate(Some(&bar)).is_ok());
+
+        assert!(precondition(IF_MATCH, "\"foo\"").is_none());
+        assert!(precondition(IF_MATCH, "").is_none());
+    }
+}
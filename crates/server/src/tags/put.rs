@@ -1,22 +1,32 @@
 // SPDX-FileCopyrightText: 2022 Profian Inc. <opensource@profian.com>
 // SPDX-License-Identifier: AGPL-3.0-only
 
-use super::super::{OidcClaims, ScopeContext, ScopeLevel, Store};
+use super::super::{
+    dry_run, verify_json, CreateError, GetError, ManifestSchema, OidcClaims, ScopeContext,
+    ScopeLevel, ServerTiming, Store,
+};
+use super::{Precondition, TagLimit};
 
 use drawbridge_jose::jws::Jws;
 use drawbridge_jose::MediaTyped;
-use drawbridge_type::{Meta, TagContext, TagEntry, TreeEntry};
+use drawbridge_type::{
+    Meta, RepositoryChange, RepositoryChangeKind, TagContext, TagEntry, TreeEntry,
+};
 
 use async_std::sync::Arc;
 use axum::body::Body;
 use axum::extract::RequestParts;
 use axum::http::{Request, StatusCode};
 use axum::response::IntoResponse;
-use axum::{Extension, Json};
+use axum::{Extension, Json, TypedHeader};
 use tracing::{debug, trace};
 
+#[allow(clippy::too_many_arguments)]
 pub async fn put(
     Extension(store): Extension<Arc<Store>>,
+    limit: Option<Extension<Arc<TagLimit>>>,
+    schema: Option<Extension<Arc<ManifestSchema>>>,
+    timing: Option<Extension<ServerTiming>>,
     claims: OidcClaims,
     cx: TagContext,
     meta: Meta,
@@ -31,16 +41,57 @@ pub async fn put(
         )
             .into_response());
     }
+    let dry_run = dry_run::requested(req.uri())?;
+    // Dry runs report the verified digest.
+    let digest = dry_run.then(|| TypedHeader(meta.hash.clone()));
+    let precondition = Precondition::from_headers(req.headers())?;
 
-    let user = claims
-        .assert_user(
+    let timing = timing.as_deref();
+    let user = ServerTiming::measure(
+        timing,
+        "auth",
+        claims.assert_user(
             &store,
             &cx.repository.owner,
             ScopeContext::Tag,
             ScopeLevel::Write,
-        )
+        ),
+    )
+    .await
+    .map_err(IntoResponse::into_response)?;
+
+    let repo = user.repository(&cx.repository.name);
+    if !precondition.is_empty() {
+        let current = match repo.tag(&cx.name).get_meta().await {
+            Ok(current) => Some(current),
+            Err(GetError::NotFound) => None,
+            Err(e) => {
+                debug!(target: "app::tags::put", "failed for `{cx}`: {:?}", e);
+                return Err(e.into_response());
+            }
+        };
+        precondition.evaluate(current.as_ref()).inspect_err(
+            |_| debug!(target: "app::tags::put", "failed for `{cx}`: precondition failed"),
+        )?;
+    }
+    if ServerTiming::measure(timing, "store", repo.tag(&cx.name).is_stored(&meta))
         .await
-        .map_err(IntoResponse::into_response)?;
+        .map_err(|e| {
+            debug!(target: "app::tags::put", "failed for `{cx}`: {:?}", e);
+            e.into_response()
+        })?
+    {
+        debug!(target: "app::tags::put", "`{cx}` is already stored, skip upload");
+        return Ok((digest, StatusCode::OK));
+    }
+
+    let reservation = if let Some(Extension(ref limit)) = limit {
+        Some(limit.reserve(&cx.repository, &repo).await.inspect_err(
+            |_| debug!(target: "app::tags::put", "failed for `{cx}`: tag limit reached"),
+        )?)
+    } else {
+        None
+    };
 
     let mut req = RequestParts::new(req);
     let entry = match meta.mime.to_string().as_str() {
@@ -49,12 +100,43 @@ pub async fn put(
         _ => return Err((StatusCode::BAD_REQUEST, "Invalid content type").into_response()),
     }
     .map_err(|e| (StatusCode::BAD_REQUEST, e).into_response())?;
-    user.repository(&cx.repository.name)
-        .create_tag(&cx.name, meta, &entry)
+    if let Some(Extension(ref schema)) = schema {
+        schema.validate(&entry).inspect_err(
+            |_| debug!(target: "app::tags::put", "failed for `{cx}`: invalid manifest"),
+        )?;
+    }
+    if dry_run {
+        // The tag limit reservation, if any, is released on drop.
+        return verify_json(meta, &entry)
+            .await
+            .map_err(|e| {
+                debug!(target: "app::tags::put", "dry run failed for `{cx}`: {:?}", e);
+                e.into_response()
+            })
+            .map(|()| (digest, StatusCode::CREATED));
+    }
+    _ = ServerTiming::measure(timing, "store", repo.create_tag(&cx.name, meta, &entry))
         .await
         .map_err(|e| {
             debug!(target: "app::tags::put", "failed for `{cx}`: {:?}", e);
-            e.into_response()
-        })
-        .map(|_| StatusCode::CREATED)
+            match e {
+                // The tag was created concurrently.
+                CreateError::Occupied if precondition.requires_absent() => Precondition::failed(),
+                e => e.into_response(),
+            }
+        })?;
+    if let Some(reservation) = reservation {
+        reservation.commit();
+    }
+    store
+        .record_change(
+            &cx.repository,
+            RepositoryChange {
+                kind: RepositoryChangeKind::Added,
+                tag: cx.name.clone(),
+                path: None,
+            },
+        )
+        .await;
+    Ok((digest, StatusCode::CREATED))
 }
@@ -0,0 +1,141 @@
+// SPDX-FileCopyrightText: 2022 Profian Inc. <opensource@profian.com>
+// SPDX-License-Identifier: AGPL-3.0-only
+
+use std::future::Future;
+use std::io;
+use std::num::NonZeroU64;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use std::time::{Duration, Instant};
+
+use async_io::Timer;
+use futures::{AsyncRead, AsyncWrite};
+
+/// Token bucket holding up to one second worth of bytes.
+#[derive(Debug)]
+struct Bucket {
+    rate: f64,
+    tokens: f64,
+    refilled: Instant,
+    timer: Option<Timer>,
+}
+
+impl Bucket {
+    fn new(rate: NonZeroU64) -> Self {
+        let rate = rate.get() as f64;
+        Self {
+            rate,
+            tokens: rate,
+            refilled: Instant::now(),
+            timer: None,
+        }
+    }
+
+    /// Returns the number of bytes, which may be written now, out of `len` requested ones,
+    /// or schedules a wakeup once a reasonably sized write is possible.
+    fn poll_take(&mut self, cx: &mut Context<'_>, len: usize) -> Poll<usize> {
+        if let Some(timer) = self.timer.as_mut() {
+            _ = ready!(Pin::new(timer).poll(cx));
+            self.timer = None;
+        }
+
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.refilled).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.rate);
+        self.refilled = now;
+
+        // Avoid tiny writes by waiting for at least 50ms worth of bytes.
+        let want = (len as f64).min((self.rate / 20.0).max(1.0));
+        if self.tokens >= want {
+            return Poll::Ready((self.tokens as usize).min(len));
+        }
+        let wait = Duration::from_secs_f64((want - self.tokens) / self.rate);
+        let mut timer = Timer::after(wait);
+        // The timer is polled once to register the waker.
+        if Pin::new(&mut timer).poll(cx).is_ready() {
+            cx.waker().wake_by_ref();
+        } else {
+            self.timer = Some(timer);
+        }
+        Poll::Pending
+    }
+}
+
+/// Stream, writes to which are limited to a maximum number of bytes per second on average.
+///
+/// Reads are not limited.
+#[derive(Debug)]
+pub(crate) struct Throttled<S> {
+    inner: S,
+    bucket: Option<Bucket>,
+}
+
+impl<S> Throttled<S> {
+    /// Limits writes to `inner` to `rate` bytes per second, if set.
+    pub(crate) fn new(inner: S, rate: Option<NonZeroU64>) -> Self {
+        Self {
+            inner,
+            bucket: rate.map(Bucket::new),
+        }
+    }
+}
+
+impl<S: Unpin + AsyncRead> AsyncRead for Throttled<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+
+impl<S: Unpin + AsyncWrite> AsyncWrite for Throttled<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let Self { inner, bucket } = &mut *self;
+        let Some(bucket) = bucket else {
+            return Pin::new(inner).poll_write(cx, buf);
+        };
+        let n = ready!(bucket.poll_take(cx, buf.len()));
+        let n = ready!(Pin::new(inner).poll_write(cx, &buf[..n]))?;
+        bucket.tokens -= n as f64;
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_close(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use async_std::task::block_on;
+    use futures::AsyncWriteExt;
+
+    #[test]
+    fn throttled() {
+        let mut w = Throttled::new(vec![], NonZeroU64::new(1000));
+        let start = Instant::now();
+        // The first 1000 bytes are covered by the initial burst.
+        block_on(w.write_all(&[0; 2000])).unwrap();
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_millis(900), "{elapsed:?}");
+        assert!(elapsed < Duration::from_secs(3), "{elapsed:?}");
+        assert_eq!(w.inner.len(), 2000);
+
+        let mut w = Throttled::new(vec![], None);
+        let start = Instant::now();
+        block_on(w.write_all(&[0; 2000])).unwrap();
+        assert!(start.elapsed() < Duration::from_millis(100));
+    }
+}
@@ -0,0 +1,100 @@
+// SPDX-FileCopyrightText: 2022 Profian Inc. <opensource@profian.com>
+// SPDX-License-Identifier: AGPL-3.0-only
+
+use std::fmt::Write as _;
+use std::future::Future;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+use axum::http::header::HeaderName;
+use axum::http::{HeaderValue, Request};
+use axum::middleware::Next;
+use axum::response::Response;
+
+/// Name of the header carrying the request handling phase durations.
+pub(crate) const SERVER_TIMING: HeaderName = HeaderName::from_static("server-timing");
+
+/// Durations of request handling phases, which are reported to the client in the
+/// `Server-Timing` response header.
+///
+/// Present in request extensions only if enabled via
+/// [Builder::server_timing](crate::Builder::server_timing).
+#[derive(Clone, Debug, Default)]
+pub struct ServerTiming(Arc<Mutex<Vec<(&'static str, Duration)>>>);
+
+impl ServerTiming {
+    /// Adds `duration` to the total duration of phase `name`.
+    pub(crate) fn record(&self, name: &'static str, duration: Duration) {
+        let mut phases = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some((_, total)) = phases.iter_mut().find(|(n, _)| *n == name) {
+            *total += duration;
+        } else {
+            phases.push((name, duration));
+        }
+    }
+
+    /// Awaits `fut` and records the elapsed time as phase `name`, if `timing` is set.
+    pub(crate) async fn measure<F: Future>(
+        timing: Option<&Self>,
+        name: &'static str,
+        fut: F,
+    ) -> F::Output {
+        let Some(timing) = timing else {
+            return fut.await;
+        };
+        let start = Instant::now();
+        let out = fut.await;
+        timing.record(name, start.elapsed());
+        out
+    }
+
+    /// Formats the recorded phases as a `Server-Timing` header value, e.g.
+    /// `auth;dur=0.125, store;dur=1.500`. Durations are in milliseconds.
+    fn header_value(&self) -> String {
+        self.0
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .iter()
+            .fold(String::new(), |mut s, (name, duration)| {
+                if !s.is_empty() {
+                    s.push_str(", ");
+                }
+                _ = write!(s, "{name};dur={:.3}", duration.as_secs_f64() * 1000.0);
+                s
+            })
+    }
+}
+
+/// Records the durations of handling phases of `req` and reports them in the `Server-Timing`
+/// response header along with the total handling duration.
+pub(crate) async fn report<B>(mut req: Request<B>, next: Next<B>) -> Response {
+    let start = Instant::now();
+    let timing = ServerTiming::default();
+    _ = req.extensions_mut().insert(timing.clone());
+    let mut res = next.run(req).await;
+    timing.record("total", start.elapsed());
+    if let Ok(value) = HeaderValue::from_str(&timing.header_value()) {
+        _ = res.headers_mut().insert(SERVER_TIMING, value);
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn header_value() {
+        let timing = ServerTiming::default();
+        assert_eq!(timing.header_value(), "");
+
+        timing.record("auth", Duration::from_micros(125));
+        timing.record("store", Duration::from_millis(1));
+        timing.record("auth", Duration::from_micros(250));
+        timing.record("total", Duration::from_secs(2));
+        assert_eq!(
+            timing.header_value(),
+            "auth;dur=0.375, store;dur=1.000, total;dur=2000.000"
+        );
+    }
+}
@@ -0,0 +1,83 @@
+// SPDX-FileCopyrightText: 2022 Profian Inc. <opensource@profian.com>
+// SPDX-License-Identifier: AGPL-3.0-only
+
+use super::super::{CreateError, OidcClaims, ScopeContext, ScopeLevel, Store};
+use super::{hash_token, TokenInfo, TokenRecord, TokenRequest};
+
+use std::time::SystemTime;
+
+use drawbridge_type::digest::Algorithms;
+use drawbridge_type::{Meta, UserContext};
+
+use async_std::sync::Arc;
+use axum::body::Body;
+use axum::extract::RequestParts;
+use axum::http::{Request, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::{Extension, Json};
+use mime::APPLICATION_JSON;
+use serde::Serialize;
+use tracing::{debug, info, trace};
+
+/// Newly minted access token.
+#[derive(Serialize)]
+struct Minted {
+    token: String,
+    #[serde(flatten)]
+    info: TokenInfo,
+}
+
+/// Mints an access token of the user, which is only permitted to the user itself.
+pub(crate) async fn create(
+    Extension(ref store): Extension<Arc<Store>>,
+    claims: OidcClaims,
+    cx: UserContext,
+    req: Request<Body>,
+) -> impl IntoResponse {
+    trace!(target: "app::tokens::create", "called for `{cx}`");
+
+    // Tokens are denied access to users, such that they cannot mint further tokens.
+    let user = claims
+        .assert_user(store, &cx, ScopeContext::User, ScopeLevel::Write)
+        .await
+        .map_err(IntoResponse::into_response)?;
+
+    // The body is only read after authorization, such that `Expect: 100-continue` requests
+    // are rejected before their body is sent.
+    let Json(request) = RequestParts::new(req)
+        .extract::<Json<TokenRequest>>()
+        .await
+        .map_err(IntoResponse::into_response)?;
+    let (token, info) = request
+        .mint(SystemTime::now())
+        .map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()).into_response())?;
+
+    let rec = TokenRecord {
+        info: info.clone(),
+        hash: hash_token(&token),
+    };
+    let internal = |e: anyhow::Error| {
+        debug!(target: "app::tokens::create", "failed for `{cx}`: {:?}", e);
+        StatusCode::INTERNAL_SERVER_ERROR.into_response()
+    };
+    let buf = serde_json::to_vec(&rec).map_err(|e| internal(e.into()))?;
+    let (size, hash) = Algorithms::default()
+        .read_sync(buf.as_slice())
+        .map_err(|e| internal(e.into()))?;
+    let meta = Meta {
+        hash,
+        size,
+        mime: APPLICATION_JSON,
+    };
+    user.create_token(&info.id.0, meta, &rec)
+        .await
+        .map_err(|e| match e {
+            CreateError::Internal(e) => internal(e),
+            e => {
+                debug!(target: "app::tokens::create", "failed for `{cx}`: {:?}", e);
+                e.into_response()
+            }
+        })?;
+    info!(target: "app::tokens::create", subject = claims.subject(), "minted access token `{}` for `{cx}`", info.id);
+    Ok::<_, Response>((StatusCode::CREATED, Json(Minted { token, info })))
+}
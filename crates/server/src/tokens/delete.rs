@@ -0,0 +1,34 @@
+// SPDX-FileCopyrightText: 2022 Profian Inc. <opensource@profian.com>
+// SPDX-License-Identifier: AGPL-3.0-only
+
+use super::super::{OidcClaims, ScopeContext, ScopeLevel, Store};
+use super::TokenId;
+
+use drawbridge_type::UserContext;
+
+use async_std::sync::Arc;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::Extension;
+use tracing::{debug, info, trace};
+
+/// Revokes an access token of the user, which is only permitted to the user itself.
+pub(crate) async fn delete(
+    Extension(ref store): Extension<Arc<Store>>,
+    Extension(id): Extension<TokenId>,
+    claims: OidcClaims,
+    cx: UserContext,
+) -> impl IntoResponse {
+    trace!(target: "app::tokens::delete", "called for `{cx}` and `{id}`");
+
+    let user = claims
+        .assert_user(store, &cx, ScopeContext::User, ScopeLevel::Write)
+        .await
+        .map_err(IntoResponse::into_response)?;
+    user.remove_token(&id.0).await.map_err(|e| {
+        debug!(target: "app::tokens::delete", "failed for `{cx}` and `{id}`: {:?}", e);
+        e.into_response()
+    })?;
+    info!(target: "app::tokens::delete", subject = claims.subject(), "revoked access token `{id}` of `{cx}`");
+    Ok::<_, Response>(StatusCode::NO_CONTENT)
+}
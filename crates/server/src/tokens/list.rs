@@ -0,0 +1,35 @@
+// SPDX-FileCopyrightText: 2022 Profian Inc. <opensource@profian.com>
+// SPDX-License-Identifier: AGPL-3.0-only
+
+use super::super::{OidcClaims, ScopeContext, ScopeLevel, Store};
+use super::list_tokens;
+
+use std::time::SystemTime;
+
+use drawbridge_type::UserContext;
+
+use async_std::sync::Arc;
+use axum::response::IntoResponse;
+use axum::{Extension, Json};
+use tracing::{debug, trace};
+
+/// Lists the access tokens of the user, which are not expired, without the tokens themselves.
+pub(crate) async fn list(
+    Extension(ref store): Extension<Arc<Store>>,
+    claims: OidcClaims,
+    cx: UserContext,
+) -> impl IntoResponse {
+    trace!(target: "app::tokens::list", "called for `{cx}`");
+
+    let user = claims
+        .assert_user(store, &cx, ScopeContext::User, ScopeLevel::Read)
+        .await
+        .map_err(IntoResponse::into_response)?;
+    list_tokens(&user, SystemTime::now())
+        .await
+        .map_err(|e| {
+            debug!(target: "app::tokens::list", "failed for `{cx}`: {:?}", e);
+            e.into_response()
+        })
+        .map(Json)
+}
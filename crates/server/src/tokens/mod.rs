@@ -0,0 +1,395 @@
+// SPDX-FileCopyrightText: 2022 Profian Inc. <opensource@profian.com>
+// SPDX-License-Identifier: AGPL-3.0-only
+
+//! Access tokens, which grant clients like CI pipelines access to some repositories of a user
+//! without the OpenID Connect credentials of the user.
+//!
+//! 1. `POST /api/vX/<user>/_tokens` with a JSON body like
+//!    `{"repositories":["repo"],"actions":["publish"],"expires_in":86400,"description":"CI"}`
+//!    mints a token, which expires after `expires_in` seconds, and responds with
+//!    `201 Created` and the token in the `token` field of the JSON body. The token is only
+//!    ever returned in this response, since only its hash is stored.
+//! 2. `GET /api/vX/<user>/_tokens` lists the tokens of the user without the tokens themselves.
+//! 3. `DELETE /api/vX/<user>/_tokens/<id>` revokes a token.
+//!
+//! Tokens are sent in `Authorization: Bearer` headers like OpenID Connect tokens and are
+//! distinguished from them by their [TOKEN_PREFIX]. Tokens granted the `read` action may read
+//! their repositories and tags, tokens granted the `publish` action may additionally publish
+//! tags. Tokens neither modify users nor repository configurations, delete anything or manage
+//! tokens, which requires the OpenID Connect credentials of the user.
+
+mod create;
+mod delete;
+mod list;
+
+pub(crate) use create::*;
+pub(crate) use delete::*;
+pub(crate) use list::*;
+
+use super::auth::encode_hex;
+use super::{GetError, User};
+
+use std::collections::HashSet;
+use std::fmt;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use drawbridge_type::RepositoryName;
+
+use anyhow::bail;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tracing::debug;
+
+/// Prefix of access tokens, which distinguishes them from OpenID Connect tokens.
+pub(crate) const TOKEN_PREFIX: &str = "drawbridge_";
+
+/// Maximum lifetime of access tokens.
+pub(crate) const MAX_TOKEN_LIFETIME: Duration = Duration::from_secs(365 * 24 * 60 * 60);
+
+/// Maximum length of the description of an access token.
+const MAX_DESCRIPTION_LENGTH: usize = 256;
+
+/// Identifier of an access token, which is inserted into request extensions.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub(crate) struct TokenId(String);
+
+impl TokenId {
+    fn random() -> Self {
+        Self(encode_hex(&rand::random::<[u8; 16]>()))
+    }
+
+    pub(crate) fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl FromStr for TokenId {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.len() != 32 || !s.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f')) {
+            bail!("token ID must consist of 32 lowercase hexadecimal digits");
+        }
+        Ok(Self(s.into()))
+    }
+}
+
+impl TryFrom<String> for TokenId {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<TokenId> for String {
+    fn from(TokenId(id): TokenId) -> Self {
+        id
+    }
+}
+
+impl fmt::Display for TokenId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Action an access token may be granted.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum TokenAction {
+    /// Reading repositories, tags and trees.
+    Read,
+    /// Publishing tags and their trees, which includes reading.
+    Publish,
+}
+
+impl TokenAction {
+    /// Returns `true` if a token granted `actions` may perform the action.
+    pub(crate) fn is_granted(self, actions: &[TokenAction]) -> bool {
+        actions
+            .iter()
+            .any(|granted| *granted == self || *granted == Self::Publish)
+    }
+}
+
+impl fmt::Display for TokenAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Read => write!(f, "read"),
+            Self::Publish => write!(f, "publish"),
+        }
+    }
+}
+
+/// Request to mint an access token.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct TokenRequest {
+    repositories: Vec<RepositoryName>,
+    actions: Vec<TokenAction>,
+    /// Lifetime of the token in seconds.
+    expires_in: u64,
+    #[serde(default)]
+    description: Option<String>,
+}
+
+impl TokenRequest {
+    /// Validates the request and returns a new token along with its description, which is
+    /// created at `now`.
+    fn mint(self, now: SystemTime) -> anyhow::Result<(String, TokenInfo)> {
+        let Self {
+            mut repositories,
+            mut actions,
+            expires_in,
+            description,
+        } = self;
+        if repositories.is_empty() {
+            bail!("At least one repository must be specified");
+        }
+        if actions.is_empty() {
+            bail!("At least one action must be specified");
+        }
+        if expires_in == 0 || expires_in > MAX_TOKEN_LIFETIME.as_secs() {
+            bail!(
+                "Token lifetime must be between 1 and {} seconds",
+                MAX_TOKEN_LIFETIME.as_secs()
+            );
+        }
+        if description
+            .as_ref()
+            .is_some_and(|description| description.len() > MAX_DESCRIPTION_LENGTH)
+        {
+            bail!("Token description must not exceed {MAX_DESCRIPTION_LENGTH} bytes");
+        }
+        repositories.sort_by(|a, b| a.as_str().cmp(b.as_str()));
+        repositories.dedup();
+        actions.sort();
+        actions.dedup();
+
+        let id = TokenId::random();
+        let token = format!(
+            "{TOKEN_PREFIX}{id}_{}",
+            encode_hex(&rand::random::<[u8; 32]>())
+        );
+        let created = unix_time(now);
+        Ok((
+            token,
+            TokenInfo {
+                id,
+                repositories,
+                actions,
+                created,
+                expires: created + expires_in,
+                description,
+            },
+        ))
+    }
+}
+
+/// Description of an access token, which is listed.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub(crate) struct TokenInfo {
+    pub(crate) id: TokenId,
+    pub(crate) repositories: Vec<RepositoryName>,
+    pub(crate) actions: Vec<TokenAction>,
+    /// Creation time in seconds since the Unix epoch.
+    pub(crate) created: u64,
+    /// Expiry time in seconds since the Unix epoch.
+    pub(crate) expires: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) description: Option<String>,
+}
+
+impl TokenInfo {
+    fn is_expired(&self, now: SystemTime) -> bool {
+        unix_time(now) >= self.expires
+    }
+}
+
+/// Stored access token.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+struct TokenRecord {
+    #[serde(flatten)]
+    info: TokenInfo,
+    /// Hex-encoded SHA-256 digest of the token.
+    hash: String,
+}
+
+/// Access token authenticated by [verify].
+#[derive(Clone, Debug)]
+pub(crate) struct AccessToken {
+    pub(crate) id: TokenId,
+    pub(crate) repositories: HashSet<RepositoryName>,
+    pub(crate) actions: Vec<TokenAction>,
+}
+
+/// Failure to authenticate an access token.
+#[derive(Debug)]
+pub(crate) enum TokenError {
+    /// The token is unknown, e.g. because it was revoked, or malformed.
+    Invalid,
+    /// The token is expired.
+    Expired,
+    Internal(anyhow::Error),
+}
+
+/// Returns `true` if `token` sent in an `Authorization: Bearer` header is an access token
+/// instead of an OpenID Connect token.
+pub(crate) fn is_access_token(token: &str) -> bool {
+    token.starts_with(TOKEN_PREFIX)
+}
+
+/// Verifies the access `token` of `user` at `now`.
+pub(crate) async fn verify(
+    user: &User<'_>,
+    token: &str,
+    now: SystemTime,
+) -> Result<AccessToken, TokenError> {
+    let Some(id) = token
+        .strip_prefix(TOKEN_PREFIX)
+        .and_then(|token| token.split_once('_'))
+        .and_then(|(id, _)| id.parse::<TokenId>().ok())
+    else {
+        return Err(TokenError::Invalid);
+    };
+    let TokenRecord { info, hash } = match user.token(&id.0).get_content_json().await {
+        Ok(rec) => rec,
+        Err(GetError::NotFound) => return Err(TokenError::Invalid),
+        Err(GetError::Internal(e)) => {
+            return Err(TokenError::Internal(
+                e.context(format!("failed to read access token `{id}`")),
+            ))
+        }
+    };
+    let expected = hash_token(token);
+    // Compare in constant time to not leak the stored hash.
+    if hash.len() != expected.len()
+        || hash
+            .bytes()
+            .zip(expected.bytes())
+            .fold(0, |acc, (a, b)| acc | (a ^ b))
+            != 0
+    {
+        debug!(target: "app::tokens", "hash mismatch for access token `{id}`");
+        return Err(TokenError::Invalid);
+    }
+    if info.is_expired(now) {
+        debug!(target: "app::tokens", "access token `{id}` expired");
+        return Err(TokenError::Expired);
+    }
+    Ok(AccessToken {
+        id: info.id,
+        repositories: info.repositories.into_iter().collect(),
+        actions: info.actions,
+    })
+}
+
+/// Returns the descriptions of the access tokens of `user`, which are not expired at `now`,
+/// ordered by their creation time.
+async fn list_tokens(
+    user: &User<'_>,
+    now: SystemTime,
+) -> Result<Vec<TokenInfo>, GetError<anyhow::Error>> {
+    let mut tokens = vec![];
+    for id in user.tokens().await? {
+        match user.token(&id).get_content_json::<TokenRecord>().await {
+            Ok(TokenRecord { info, .. }) if !info.is_expired(now) => tokens.push(info),
+            Ok(_) => {}
+            // The token is being minted or revoked concurrently.
+            Err(GetError::NotFound) => {}
+            Err(GetError::Internal(e)) => {
+                return Err(GetError::Internal(
+                    e.context(format!("failed to read access token `{id}`")),
+                ))
+            }
+        }
+    }
+    tokens.sort_by(|a, b| (a.created, &a.id.0).cmp(&(b.created, &b.id.0)));
+    Ok(tokens)
+}
+
+fn hash_token(token: &str) -> String {
+    encode_hex(&Sha256::digest(token.as_bytes()))
+}
+
+fn unix_time(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(expires_in: u64) -> TokenRequest {
+        TokenRequest {
+            repositories: vec![
+                "b".parse().unwrap(),
+                "a".parse().unwrap(),
+                "b".parse().unwrap(),
+            ],
+            actions: vec![TokenAction::Publish, TokenAction::Read],
+            expires_in,
+            description: None,
+        }
+    }
+
+    #[test]
+    fn mint() {
+        let now = UNIX_EPOCH + Duration::from_secs(1000);
+        let (token, info) = request(60).mint(now).unwrap();
+        assert!(is_access_token(&token));
+        assert!(token.starts_with(&format!("{TOKEN_PREFIX}{}_", info.id)));
+        assert_eq!(token.len(), TOKEN_PREFIX.len() + 32 + 1 + 64);
+        assert_eq!(
+            info.repositories,
+            vec!["a".parse().unwrap(), "b".parse::<RepositoryName>().unwrap()]
+        );
+        assert_eq!(info.actions, [TokenAction::Read, TokenAction::Publish]);
+        assert_eq!((info.created, info.expires), (1000, 1060));
+        assert!(!info.is_expired(now + Duration::from_secs(59)));
+        assert!(info.is_expired(now + Duration::from_secs(60)));
+        assert_ne!(request(60).mint(now).unwrap().0, token);
+
+        assert!(request(0).mint(now).is_err());
+        assert!(request(MAX_TOKEN_LIFETIME.as_secs() + 1).mint(now).is_err());
+        assert!(TokenRequest {
+            repositories: vec![],
+            ..request(60)
+        }
+        .mint(now)
+        .is_err());
+        assert!(TokenRequest {
+            actions: vec![],
+            ..request(60)
+        }
+        .mint(now)
+        .is_err());
+    }
+
+    #[test]
+    fn token_id() {
+        assert!("0123456789abcdef0123456789abcdef"
+            .parse::<TokenId>()
+            .is_ok());
+        assert!("0123456789ABCDEF0123456789ABCDEF"
+            .parse::<TokenId>()
+            .is_err());
+        assert!("0123456789abcdef".parse::<TokenId>().is_err());
+        assert!("../0123456789abcdef0123456789abc"
+            .parse::<TokenId>()
+            .is_err());
+    }
+
+    #[test]
+    fn action() {
+        assert!(TokenAction::Read.is_granted(&[TokenAction::Read]));
+        assert!(TokenAction::Read.is_granted(&[TokenAction::Publish]));
+        assert!(!TokenAction::Publish.is_granted(&[TokenAction::Read]));
+        assert!(!TokenAction::Read.is_granted(&[]));
+    }
+}
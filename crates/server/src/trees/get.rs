@@ -1,45 +1,102 @@
 // SPDX-FileCopyrightText: 2022 Profian Inc. <opensource@profian.com>
 // SPDX-License-Identifier: AGPL-3.0-only
 
-use super::super::{Store, TrustedCertificate};
-use crate::auth::assert_repository_read;
+use super::super::{
+    GetToWriterError, Precompression, ResponseBuffer, ServerTiming, Store, TrustedCertificate,
+    Validators,
+};
+use crate::auth::{
+    assert_repository_read, authorize_webhook, record_decision, AuthDecision, ScopeLevel, Subject,
+};
 
 use drawbridge_type::TreeContext;
 
 use async_std::sync::Arc;
 use axum::body::Body;
-use axum::http::Request;
+use axum::http::header::{CONTENT_ENCODING, CONTENT_LENGTH};
+use axum::http::{HeaderValue, Request};
 use axum::response::IntoResponse;
 use axum::Extension;
+use futures::try_join;
 use tracing::{debug, trace};
 
 pub async fn get(
     Extension(ref store): Extension<Arc<Store>>,
     cert: Option<Extension<TrustedCertificate>>,
+    timing: Option<Extension<ServerTiming>>,
+    buffer: Option<Extension<ResponseBuffer>>,
+    precompression: Option<Extension<Arc<Precompression>>>,
     cx: TreeContext,
     req: Request<Body>,
 ) -> impl IntoResponse {
     trace!(target: "app::trees::get", "called for `{cx}`");
 
-    let repo = if cert.is_none() {
-        assert_repository_read(store, &cx.tag.repository, req)
+    // The request is consumed by authorization.
+    let headers = precompression.as_ref().map(|_| req.headers().clone());
+
+    let repo = match cert {
+        None => assert_repository_read(store, &cx.tag.repository, req)
             .await
             .map_err(IntoResponse::into_response)
-            .map(|(repo, _)| repo)?
-    } else {
-        store.repository(&cx.tag.repository)
+            .map(|(repo, _)| repo)?,
+        Some(Extension(ref cert)) => {
+            authorize_webhook(
+                req.extensions(),
+                Subject::certificate(cert),
+                ScopeLevel::Read,
+                req.uri().path(),
+            )
+            .await?;
+            record_decision(req.extensions(), AuthDecision::GrantedViaCert);
+            store.repository(&cx.tag.repository)
+        }
     };
 
-    // TODO: Stream body
-    // https://github.com/profianinc/drawbridge/issues/56
-    let mut body = vec![];
-    repo.tag(&cx.tag.name)
-        .node(&cx.path)
-        .get_to_writer(&mut body)
-        .await
-        .map_err(|e| {
-            debug!(target: "app::trees::get", "failed for `{cx}`: {:?}", e);
-            e.into_response()
+    let timing = timing.as_deref();
+    let buffer = buffer.map(|Extension(buffer)| buffer).unwrap_or_default();
+    let node = repo.tag(&cx.tag.name).node(&cx.path);
+    async {
+        let ((meta, rdr), modified) = ServerTiming::measure(timing, "store", async {
+            try_join!(node.get(), node.modified())
         })
-        .map(|meta| (meta, body))
+        .await
+        .map_err(GetToWriterError::Get)?;
+        let validators = Validators::new(&meta, modified);
+        let variant = match (&precompression, &headers) {
+            (Some(Extension(precompression)), Some(headers)) => {
+                ServerTiming::measure(
+                    timing,
+                    "store",
+                    precompression.get(&cx, &node, &meta, headers),
+                )
+                .await
+            }
+            _ => None,
+        };
+        let Some((algorithm, length, variant)) = variant else {
+            let body = buffer
+                .read(timing, meta.size, rdr)
+                .await
+                .map_err(GetToWriterError::IO)?;
+            return Ok((meta, validators, None, body));
+        };
+        // The variant is sent as is, bypassing compression on the fly.
+        let encoding = [
+            (
+                CONTENT_ENCODING,
+                HeaderValue::from_str(&algorithm.to_string()).expect("encoding is a valid header"),
+            ),
+            (CONTENT_LENGTH, HeaderValue::from(length)),
+        ];
+        let body = buffer
+            .read(timing, length, variant)
+            .await
+            .map_err(GetToWriterError::IO)?;
+        Ok((meta, validators, Some(encoding), body))
+    }
+    .await
+    .map_err(|e: GetToWriterError<_>| {
+        debug!(target: "app::trees::get", "failed for `{cx}`: {:?}", e);
+        e.into_response()
+    })
 }
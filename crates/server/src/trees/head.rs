@@ -1,8 +1,10 @@
 // SPDX-FileCopyrightText: 2022 Profian Inc. <opensource@profian.com>
 // SPDX-License-Identifier: AGPL-3.0-only
 
-use super::super::{Store, TrustedCertificate};
-use crate::auth::assert_repository_read;
+use super::super::{Store, TrustedCertificate, Validators};
+use crate::auth::{
+    assert_repository_read, authorize_webhook, record_decision, AuthDecision, ScopeLevel, Subject,
+};
 
 use drawbridge_type::TreeContext;
 
@@ -11,6 +13,7 @@ use axum::body::Body;
 use axum::http::Request;
 use axum::response::IntoResponse;
 use axum::Extension;
+use futures::try_join;
 use tracing::{debug, trace};
 
 pub async fn head(
@@ -21,21 +24,31 @@ pub async fn head(
 ) -> impl IntoResponse {
     trace!(target: "app::trees::head", "called for `{cx}`");
 
-    if cert.is_none() {
-        assert_repository_read(store, &cx.tag.repository, req)
+    let repo = match cert {
+        None => assert_repository_read(store, &cx.tag.repository, req)
             .await
             .map_err(IntoResponse::into_response)
-            .map(|(repo, _)| repo)?
-    } else {
-        store.repository(&cx.tag.repository)
-    }
-    .tag(&cx.tag.name)
-    .node(&cx.path)
-    .get_meta()
-    .await
-    .map_err(|e| {
-        debug!(target: "app::trees::head", "failed for `{cx}`: {:?}", e);
-        e.into_response()
-    })
-    .map(|meta| (meta, ()))
+            .map(|(repo, _)| repo)?,
+        Some(Extension(ref cert)) => {
+            authorize_webhook(
+                req.extensions(),
+                Subject::certificate(cert),
+                ScopeLevel::Read,
+                req.uri().path(),
+            )
+            .await?;
+            record_decision(req.extensions(), AuthDecision::GrantedViaCert);
+            store.repository(&cx.tag.repository)
+        }
+    };
+    let node = repo.tag(&cx.tag.name).node(&cx.path);
+    try_join!(node.get_meta(), node.modified())
+        .map_err(|e| {
+            debug!(target: "app::trees::head", "failed for `{cx}`: {:?}", e);
+            e.into_response()
+        })
+        .map(|(meta, modified)| {
+            let validators = Validators::new(&meta, modified);
+            (meta, validators, ())
+        })
 }
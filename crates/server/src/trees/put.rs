@@ -1,21 +1,30 @@
 // SPDX-FileCopyrightText: 2022 Profian Inc. <opensource@profian.com>
 // SPDX-License-Identifier: AGPL-3.0-only
 
-use super::super::{OidcClaims, ScopeContext, ScopeLevel, Store};
+use super::super::{
+    dry_run, verify_content, verify_json, ContentTypes, OidcClaims, Precompression, ScopeContext,
+    ScopeLevel, ServerTiming, Store,
+};
 
-use drawbridge_type::{Meta, TreeContext, TreeDirectory};
+use drawbridge_type::{Meta, RepositoryChange, RepositoryChangeKind, TreeContext, TreeDirectory};
+
+use std::pin::Pin;
 
 use async_std::sync::Arc;
 use axum::body::Body;
 use axum::extract::{BodyStream, RequestParts};
 use axum::http::{Request, StatusCode};
 use axum::response::IntoResponse;
-use axum::{Extension, Json};
-use futures::{io, TryStreamExt};
+use axum::{Extension, Json, TypedHeader};
+use futures::{io, AsyncRead, TryStreamExt};
 use tracing::{debug, trace};
 
+#[allow(clippy::too_many_arguments)]
 pub async fn put(
     Extension(ref store): Extension<Arc<Store>>,
+    timing: Option<Extension<ServerTiming>>,
+    content_types: Option<Extension<Arc<ContentTypes>>>,
+    precompression: Option<Extension<Arc<Precompression>>>,
     claims: OidcClaims,
     cx: TreeContext,
     meta: Meta,
@@ -30,41 +39,98 @@ pub async fn put(
         )
             .into_response());
     }
+    let dry_run = dry_run::requested(req.uri())?;
+    // Dry runs report the verified digest.
+    let digest = dry_run.then(|| TypedHeader(meta.hash.clone()));
 
-    let user = claims
-        .assert_user(
+    let timing = timing.as_deref();
+    let user = ServerTiming::measure(
+        timing,
+        "auth",
+        claims.assert_user(
             store,
             &cx.tag.repository.owner,
             ScopeContext::Tag,
             ScopeLevel::Write,
-        )
+        ),
+    )
+    .await
+    .map_err(IntoResponse::into_response)?;
+
+    let is_directory = meta.mime.essence_str() == TreeDirectory::<()>::TYPE;
+    let content_types = content_types.as_deref().filter(|_| !is_directory);
+    if let Some(content_types) = content_types {
+        content_types.check(&meta.mime)?;
+    }
+
+    let tag = user.repository(&cx.tag.repository.name).tag(&cx.tag.name);
+    if ServerTiming::measure(timing, "store", tag.node(&cx.path).is_stored(&meta))
         .await
-        .map_err(IntoResponse::into_response)?;
+        .map_err(|e| {
+            debug!(target: "app::trees::put", "failed for `{cx}`: {:?}", e);
+            e.into_response()
+        })?
+    {
+        debug!(target: "app::trees::put", "`{cx}` is already stored, skip upload");
+        return Ok((digest, StatusCode::OK));
+    }
 
     let mut req = RequestParts::new(req);
-    let tag = user.repository(&cx.tag.repository.name).tag(&cx.tag.name);
-    match meta.mime.to_string().as_str() {
-        TreeDirectory::<()>::TYPE => {
-            let dir = req
-                .extract()
-                .await
-                .map(|Json(v)| v)
-                .map_err(|e| (StatusCode::BAD_REQUEST, e).into_response())?;
-            tag.create_directory_node(&cx.path, meta, &dir).await
-        }
-        _ => {
-            let body = req
-                .extract::<BodyStream>()
-                .await
-                .map_err(|e| (StatusCode::BAD_REQUEST, e).into_response())?
-                .map_err(|e| io::Error::new(io::ErrorKind::Other, e));
-            tag.create_file_node(&cx.path, meta, body.into_async_read())
-                .await
+    ServerTiming::measure(timing, "body", async {
+        match meta.mime.to_string().as_str() {
+            TreeDirectory::<()>::TYPE => {
+                let dir = req
+                    .extract()
+                    .await
+                    .map(|Json(v)| v)
+                    .map_err(|e| (StatusCode::BAD_REQUEST, e).into_response())?;
+                if dry_run {
+                    verify_json(meta, &dir).await
+                } else {
+                    tag.create_directory_node(&cx.path, meta, &dir)
+                        .await
+                        .map(|_| ())
+                }
+            }
+            _ => {
+                let body = req
+                    .extract::<BodyStream>()
+                    .await
+                    .map_err(|e| (StatusCode::BAD_REQUEST, e).into_response())?
+                    .map_err(io::Error::other)
+                    .into_async_read();
+                let body: Pin<Box<dyn AsyncRead + Send>> = match content_types {
+                    Some(content_types) => Box::pin(content_types.sniff(&meta.mime, body).await?),
+                    None => Box::pin(body),
+                };
+                if dry_run {
+                    verify_content(meta, body).await
+                } else {
+                    let node = tag.create_file_node(&cx.path, meta.clone(), body).await;
+                    if let (Ok(node), Some(Extension(precompression))) = (&node, &precompression) {
+                        precompression.store(&cx, node, &meta).await;
+                    }
+                    node.map(|_| ())
+                }
+            }
         }
-    }
-    .map_err(|e| {
-        debug!(target: "app::trees::put", "failed for `{cx}`: {:?}", e);
-        e.into_response()
+        .map_err(|e| {
+            debug!(target: "app::trees::put", "failed for `{cx}`: {:?}", e);
+            e.into_response()
+        })
     })
-    .map(|_| StatusCode::CREATED)
+    .await?;
+    if !dry_run {
+        store
+            .record_change(
+                &cx.tag.repository,
+                RepositoryChange {
+                    kind: RepositoryChangeKind::Changed,
+                    tag: cx.tag.name.clone(),
+                    path: Some(cx.path.clone()),
+                },
+            )
+            .await;
+    }
+    Ok((digest, StatusCode::CREATED))
 }
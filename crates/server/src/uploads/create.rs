@@ -0,0 +1,66 @@
+// SPDX-FileCopyrightText: 2022 Profian Inc. <opensource@profian.com>
+// SPDX-License-Identifier: AGPL-3.0-only
+
+use super::super::problem::{Problem, PROBLEM_QUOTA_EXCEEDED};
+use super::super::{OidcClaims, ScopeContext, ScopeLevel, Store};
+use super::{CreateError, Progress, Uploads};
+
+use drawbridge_type::RepositoryContext;
+
+use async_std::sync::Arc;
+use axum::http::{StatusCode, Uri};
+use axum::response::IntoResponse;
+use axum::Extension;
+use tracing::{debug, trace, warn};
+
+/// Initiates an upload session for a tree node of the repository.
+pub(crate) async fn create(
+    Extension(ref store): Extension<Arc<Store>>,
+    uploads: Option<Extension<Arc<Uploads>>>,
+    claims: OidcClaims,
+    cx: RepositoryContext,
+    uri: Uri,
+) -> impl IntoResponse {
+    trace!(target: "app::uploads::create", "called for `{cx}`");
+
+    let user = claims
+        .assert_user(store, &cx.owner, ScopeContext::Tag, ScopeLevel::Write)
+        .await
+        .map_err(IntoResponse::into_response)?;
+    let Some(Extension(uploads)) = uploads else {
+        return Err((
+            StatusCode::NOT_IMPLEMENTED,
+            "Resumable uploads are not enabled",
+        )
+            .into_response());
+    };
+    _ = user.repository(&cx.name).get_meta().await.map_err(|e| {
+        debug!(target: "app::uploads::create", "failed for `{cx}`: {:?}", e);
+        e.into_response()
+    })?;
+
+    let session = uploads.create(cx.clone()).await.map_err(|e| match e {
+        CreateError::LimitReached(limit) => {
+            debug!(target: "app::uploads::create", "upload session limit of `{cx}` reached");
+            Problem::new(
+                StatusCode::CONFLICT,
+                PROBLEM_QUOTA_EXCEEDED,
+                "Repository upload session limit reached",
+            )
+            .detail(format!(
+                "Repository upload session limit of {limit} reached, complete or cancel open sessions to initiate new ones"
+            ))
+            .member("quota", "upload-sessions")
+            .member("limit", limit)
+            .member("usage", limit)
+            .into_response()
+        }
+        CreateError::Io(e) => {
+            warn!(target: "app::uploads::create", "failed to create upload for `{cx}`: {e}");
+            (StatusCode::INTERNAL_SERVER_ERROR, "Storage backend failure").into_response()
+        }
+    })?;
+    debug!(target: "app::uploads::create", "created upload `{}` for `{cx}`", session.id);
+    let location = format!("{}/{}", uri.path().trim_end_matches('/'), session.id);
+    Ok((StatusCode::ACCEPTED, Progress::new(location, 0), ()))
+}
@@ -0,0 +1,30 @@
+// SPDX-FileCopyrightText: 2022 Profian Inc. <opensource@profian.com>
+// SPDX-License-Identifier: AGPL-3.0-only
+
+use super::super::{OidcClaims, Store};
+use super::{session, UploadId, Uploads};
+
+use drawbridge_type::RepositoryContext;
+
+use async_std::sync::Arc;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::Extension;
+use tracing::{debug, trace};
+
+/// Cancels an upload session and removes its data.
+pub(crate) async fn delete(
+    Extension(ref store): Extension<Arc<Store>>,
+    uploads: Option<Extension<Arc<Uploads>>>,
+    Extension(id): Extension<UploadId>,
+    claims: OidcClaims,
+    cx: RepositoryContext,
+) -> impl IntoResponse {
+    trace!(target: "app::uploads::delete", "called for `{}`", id.0);
+
+    let (_, uploads, session) = session(store, uploads, &claims, &cx, id).await?;
+    let _offset = session.offset.lock().await;
+    uploads.remove(&session).await;
+    debug!(target: "app::uploads::delete", "cancelled upload `{}`", id.0);
+    Ok::<_, Response>(StatusCode::NO_CONTENT)
+}
@@ -0,0 +1,352 @@
+// SPDX-FileCopyrightText: 2022 Profian Inc. <opensource@profian.com>
+// SPDX-License-Identifier: AGPL-3.0-only
+
+//! Resumable uploads of tree file nodes, which mirror the OCI blob upload flow.
+//!
+//! 1. `POST /api/vX/<user>/<repo>/_upload` initiates an upload session and responds with
+//!    `202 Accepted` and the session URL in the `Location` header.
+//! 2. `PATCH <session>` appends a chunk of contents. The optional `Content-Range: <start>-<end>`
+//!    header must start at the number of bytes received so far, otherwise the chunk is rejected
+//!    with `416 Range Not Satisfiable`.
+//! 3. `GET <session>` or `HEAD <session>` reports the progress, i.e. the bytes received so far,
+//!    in the `Range: 0-<end>` header, such that interrupted uploads can be resumed.
+//! 4. `PUT <session>` with a JSON body containing the `tag` and `path` of a tree node and its
+//!    metadata, like
+//!    `{"tag":"0.1.0","path":"main.wasm","digest":{"sha-256":"..."},"length":42,"type":"application/wasm"}`,
+//!    verifies the received contents against the digests and length and stores them as the
+//!    node.
+//! 5. `DELETE <session>` cancels the upload.
+//!
+//! Sessions, which are not used for the session TTL, expire and their data is removed. The
+//! number of open sessions per repository and the size of each session may be limited, in which
+//! case further sessions are rejected with `409 Conflict` and chunks exceeding the size with
+//! `413 Payload Too Large`.
+
+mod create;
+mod delete;
+mod patch;
+mod put;
+mod status;
+
+pub(crate) use create::*;
+pub(crate) use delete::*;
+pub(crate) use patch::*;
+pub(crate) use put::*;
+pub(crate) use status::*;
+
+use super::problem::{insufficient_storage, is_storage_full};
+use super::{OidcClaims, ScopeContext, ScopeLevel, Store, User};
+
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::io;
+use std::mem;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+use drawbridge_type::RepositoryContext;
+
+use axum::http::header::{LOCATION, RANGE};
+use axum::http::{HeaderValue, StatusCode};
+use axum::response::{IntoResponse, IntoResponseParts, Response, ResponseParts};
+use axum::Extension;
+use cap_async_std::fs::OpenOptions;
+use cap_async_std::fs_utf8::{Dir, File};
+use futures::lock::Mutex as AsyncMutex;
+use tracing::{debug, warn};
+use uuid::Uuid;
+
+/// Default duration after which unused upload sessions expire.
+pub const DEFAULT_UPLOAD_SESSION_TTL: Duration = Duration::from_secs(60 * 60);
+
+/// Identifier of an upload session, which is inserted into request extensions.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub(crate) struct UploadId(pub(crate) Uuid);
+
+/// Upload session.
+#[derive(Debug)]
+pub(crate) struct Session {
+    id: Uuid,
+    repository: RepositoryContext,
+    /// Number of bytes received, which is locked for the duration of each operation on the
+    /// session data.
+    offset: AsyncMutex<u64>,
+    expires: Mutex<Instant>,
+}
+
+impl Session {
+    fn extend(&self, ttl: Duration) {
+        *self.expires.lock().unwrap_or_else(PoisonError::into_inner) = Instant::now() + ttl;
+    }
+
+    fn is_expired(&self, now: Instant) -> bool {
+        *self.expires.lock().unwrap_or_else(PoisonError::into_inner) <= now
+    }
+}
+
+/// Location and progress of an upload session, which are sent in responses to requests
+/// operating on it.
+#[derive(Clone, Debug)]
+pub(crate) struct Progress {
+    location: String,
+    offset: u64,
+}
+
+impl Progress {
+    /// Constructs the progress of the session at `location`, which received `offset` bytes.
+    fn new(location: impl Into<String>, offset: u64) -> Self {
+        Self {
+            location: location.into(),
+            offset,
+        }
+    }
+}
+
+impl IntoResponseParts for Progress {
+    type Error = Infallible;
+
+    fn into_response_parts(self, mut res: ResponseParts) -> Result<ResponseParts, Self::Error> {
+        let headers = res.headers_mut();
+        if let Ok(location) = HeaderValue::from_str(&self.location) {
+            _ = headers.insert(LOCATION, location);
+        }
+        if self.offset > 0 {
+            _ = headers.insert(RANGE, format!("0-{}", self.offset - 1).parse().unwrap());
+        }
+        Ok(res)
+    }
+}
+
+/// Failure to initiate an upload session.
+#[derive(Debug)]
+pub(crate) enum CreateError {
+    /// The repository already has the contained maximum number of open sessions.
+    LimitReached(usize),
+    Io(io::Error),
+}
+
+/// Registry of upload sessions, whose data is stored in a dedicated directory of the store.
+#[derive(Debug)]
+pub(crate) struct Uploads {
+    dir: Dir,
+    ttl: Duration,
+    /// Maximum number of open sessions per repository, `0` means unlimited.
+    max_sessions: usize,
+    /// Maximum number of bytes a session may receive, `0` means unlimited.
+    max_size: u64,
+    sessions: Mutex<HashMap<Uuid, Arc<Session>>>,
+}
+
+impl Uploads {
+    /// Constructs a new [Uploads] storing session data in `dir`, whose sessions expire after not
+    /// being used for `ttl`.
+    ///
+    /// At most `max_sessions` sessions may be open per repository and each of them may receive
+    /// at most `max_size` bytes, where `0` means unlimited.
+    pub(crate) fn new(dir: Dir, ttl: Duration, max_sessions: usize, max_size: u64) -> Self {
+        Self {
+            dir,
+            ttl,
+            max_sessions,
+            max_size,
+            sessions: Default::default(),
+        }
+    }
+
+    /// Removes expired sessions and their data and returns the number of removed sessions.
+    pub(crate) async fn expire(&self) -> usize {
+        let now = Instant::now();
+        let expired: Vec<_> = {
+            let mut sessions = self.sessions.lock().unwrap_or_else(PoisonError::into_inner);
+            let (expired, active) = mem::take(&mut *sessions)
+                .into_iter()
+                .partition(|(_, session)| session.is_expired(now));
+            *sessions = active;
+            expired.into_keys().collect()
+        };
+        for id in &expired {
+            debug!(target: "app::uploads", "upload session `{id}` expired");
+            self.remove_data(*id).await;
+        }
+        expired.len()
+    }
+
+    async fn remove_data(&self, id: Uuid) {
+        match self.dir.remove_file(id.to_string()).await {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => warn!(target: "app::uploads", "failed to remove data of upload `{id}`: {e}"),
+        }
+    }
+
+    /// Initiates a new upload session for `repository`, unless it reached the maximum number
+    /// of open sessions.
+    pub(crate) async fn create(
+        &self,
+        repository: RepositoryContext,
+    ) -> Result<Arc<Session>, CreateError> {
+        _ = self.expire().await;
+        let id = Uuid::new_v4();
+        drop(
+            self.dir
+                .create(id.to_string())
+                .await
+                .map_err(CreateError::Io)?,
+        );
+        let session = Arc::new(Session {
+            id,
+            repository,
+            offset: AsyncMutex::new(0),
+            expires: Mutex::new(Instant::now() + self.ttl),
+        });
+        {
+            // Sessions are counted and inserted at once, such that concurrent requests cannot
+            // exceed the limit.
+            let mut sessions = self.sessions.lock().unwrap_or_else(PoisonError::into_inner);
+            let open = sessions
+                .values()
+                .filter(|open| open.repository == session.repository)
+                .count();
+            if self.max_sessions == 0 || open < self.max_sessions {
+                _ = sessions.insert(id, Arc::clone(&session));
+                return Ok(session);
+            }
+        }
+        self.remove_data(id).await;
+        Err(CreateError::LimitReached(self.max_sessions))
+    }
+
+    /// Returns `true` if `size` exceeds the maximum number of bytes a session may receive.
+    fn exceeds_max_size(&self, size: u64) -> bool {
+        self.max_size > 0 && size > self.max_size
+    }
+
+    /// Returns the active session `id` of `repository`, extending its lifetime.
+    pub(crate) async fn get(
+        &self,
+        repository: &RepositoryContext,
+        UploadId(id): UploadId,
+    ) -> Option<Arc<Session>> {
+        _ = self.expire().await;
+        let session = self
+            .sessions
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(&id)
+            .filter(|session| session.repository == *repository)
+            .cloned()?;
+        session.extend(self.ttl);
+        Some(session)
+    }
+
+    /// Removes `session` and its data.
+    pub(crate) async fn remove(&self, session: &Session) {
+        _ = self
+            .sessions
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(&session.id);
+        self.remove_data(session.id).await;
+    }
+
+    /// Opens the data of `session` for appending.
+    async fn append(&self, session: &Session) -> io::Result<File> {
+        self.dir
+            .open_with(
+                session.id.to_string(),
+                OpenOptions::new().append(true).write(true),
+            )
+            .await
+    }
+
+    /// Opens the data of `session` for reading.
+    async fn open(&self, session: &Session) -> io::Result<File> {
+        self.dir.open(session.id.to_string()).await
+    }
+}
+
+/// Asserts that `claims` grant write access to tags of the repository `cx` and returns the
+/// user owning it along with the upload session `id` initiated for it.
+#[allow(clippy::result_large_err)]
+async fn session<'a>(
+    store: &'a Store,
+    uploads: Option<Extension<Arc<Uploads>>>,
+    claims: &OidcClaims,
+    cx: &RepositoryContext,
+    id: UploadId,
+) -> Result<(User<'a>, Arc<Uploads>, Arc<Session>), Response> {
+    let user = claims
+        .assert_user(store, &cx.owner, ScopeContext::Tag, ScopeLevel::Write)
+        .await
+        .map_err(IntoResponse::into_response)?;
+    let not_found = || (StatusCode::NOT_FOUND, "Upload session not found").into_response();
+    let Extension(uploads) = uploads.ok_or_else(not_found)?;
+    let session = uploads.get(cx, id).await.ok_or_else(not_found)?;
+    Ok((user, uploads, session))
+}
+
+/// Returns the response to a request failing due to an I/O error on the data of an upload.
+fn storage_failure(id: Uuid, e: io::Error) -> Response {
+    match e.kind() {
+        // Sessions may be removed concurrently.
+        io::ErrorKind::NotFound => {
+            (StatusCode::NOT_FOUND, "Upload session not found").into_response()
+        }
+        _ if is_storage_full(&e) => {
+            warn!(target: "app::uploads", "insufficient storage for data of upload `{id}`: {e}");
+            insufficient_storage()
+        }
+        _ => {
+            warn!(target: "app::uploads", "failed to access data of upload `{id}`: {e}");
+            (StatusCode::INTERNAL_SERVER_ERROR, "Storage backend failure").into_response()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use async_std::task::block_on;
+    use drawbridge_type::UserContext;
+
+    #[test]
+    fn expire() {
+        block_on(async {
+            let tmp = tempfile::tempdir().unwrap();
+            let dir = Dir::from_std_file(async_std::fs::File::open(tmp.path()).await.unwrap());
+            let uploads = Uploads::new(dir, Duration::from_secs(60), 2, 0);
+
+            let repo = RepositoryContext {
+                owner: UserContext {
+                    name: "user".parse().unwrap(),
+                },
+                name: "repo".parse().unwrap(),
+            };
+            let other = RepositoryContext {
+                name: "other".parse().unwrap(),
+                ..repo.clone()
+            };
+            let session = uploads.create(repo.clone()).await.unwrap();
+            let id = UploadId(session.id);
+            assert!(tmp.path().join(session.id.to_string()).exists());
+            assert!(uploads.get(&repo, id).await.is_some());
+            // Sessions are bound to the repository they were initiated for.
+            assert!(uploads.get(&other, id).await.is_none());
+
+            // Sessions are limited per repository.
+            let second = uploads.create(repo.clone()).await.unwrap();
+            assert!(matches!(
+                uploads.create(repo.clone()).await,
+                Err(CreateError::LimitReached(2))
+            ));
+            assert!(uploads.create(other.clone()).await.is_ok());
+            uploads.remove(&second).await;
+            assert!(uploads.create(repo.clone()).await.is_ok());
+
+            *session.expires.lock().unwrap() = Instant::now();
+            assert!(uploads.get(&repo, id).await.is_none());
+            assert!(!tmp.path().join(session.id.to_string()).exists());
+        })
+    }
+}
@@ -0,0 +1,162 @@
+// SPDX-FileCopyrightText: 2022 Profian Inc. <opensource@profian.com>
+// SPDX-License-Identifier: AGPL-3.0-only
+
+use super::super::problem::{Problem, PROBLEM_QUOTA_EXCEEDED};
+use super::super::{OidcClaims, Store};
+use super::{session, storage_failure, Progress, UploadId, Uploads};
+
+use drawbridge_type::RepositoryContext;
+
+use async_std::sync::Arc;
+use axum::body::Body;
+use axum::http::header::{CONTENT_LENGTH, CONTENT_RANGE};
+use axum::http::request::Parts;
+use axum::http::{Request, StatusCode};
+use axum::response::IntoResponse;
+use axum::Extension;
+use futures::{AsyncWriteExt, TryStreamExt};
+use tracing::{debug, trace};
+
+/// Parses a `Content-Range` header value of the form `<start>-<end>`, where both offsets are
+/// inclusive.
+fn parse_range(s: &str) -> Option<(u64, u64)> {
+    let (start, end) = s.split_once('-')?;
+    let (start, end) = (start.trim().parse().ok()?, end.trim().parse().ok()?);
+    (start <= end).then_some((start, end))
+}
+
+/// Appends the request body to the data of an upload session.
+pub(crate) async fn patch(
+    Extension(ref store): Extension<Arc<Store>>,
+    uploads: Option<Extension<Arc<Uploads>>>,
+    Extension(id): Extension<UploadId>,
+    claims: OidcClaims,
+    cx: RepositoryContext,
+    req: Request<Body>,
+) -> impl IntoResponse {
+    trace!(target: "app::uploads::patch", "called for `{}`", id.0);
+
+    let (_, uploads, session) = session(store, uploads, &claims, &cx, id).await?;
+    let (Parts { uri, headers, .. }, mut body) = req.into_parts();
+    let range = match headers.get(CONTENT_RANGE) {
+        None => None,
+        Some(range) => Some(range.to_str().ok().and_then(parse_range).ok_or_else(|| {
+            (
+                StatusCode::BAD_REQUEST,
+                "`Content-Range` must be of the form `<start>-<end>`",
+            )
+                .into_response()
+        })?),
+    };
+
+    let mut offset = session.offset.lock().await;
+    if let Some((start, _)) = range {
+        if start != *offset {
+            return Err((
+                StatusCode::RANGE_NOT_SATISFIABLE,
+                Progress::new(uri.path(), *offset),
+                format!("Chunk must start at offset {}", *offset),
+            )
+                .into_response());
+        }
+    }
+    let received = *offset;
+    let too_large = || {
+        (
+            Progress::new(uri.path(), received),
+            Problem::new(
+                StatusCode::PAYLOAD_TOO_LARGE,
+                PROBLEM_QUOTA_EXCEEDED,
+                "Upload session size limit reached",
+            )
+            .detail(format!(
+                "Upload sessions may receive at most {} bytes",
+                uploads.max_size
+            ))
+            .member("quota", "upload-size")
+            .member("limit", uploads.max_size)
+            .member("usage", received),
+        )
+            .into_response()
+    };
+    // Chunks of known length are rejected before they are received.
+    let length = headers
+        .get(CONTENT_LENGTH)
+        .and_then(|length| length.to_str().ok()?.parse::<u64>().ok())
+        .or(range.map(|(start, end)| end - start + 1));
+    if length.is_some_and(|length| uploads.exceeds_max_size(received.saturating_add(length))) {
+        return Err(too_large());
+    }
+
+    let mut file = uploads
+        .append(&session)
+        .await
+        .map_err(|e| storage_failure(session.id, e))?;
+    let mut written = 0;
+    let mut exceeded = false;
+    let res = async {
+        while let Some(chunk) = body.try_next().await.map_err(|e| {
+            debug!(target: "app::uploads::patch", "failed to read chunk of `{}`: {e}", id.0);
+            (StatusCode::BAD_REQUEST, "Failed to read request body").into_response()
+        })? {
+            if uploads.exceeds_max_size(received + written + chunk.len() as u64) {
+                exceeded = true;
+                break;
+            }
+            file.write_all(&chunk)
+                .await
+                .map_err(|e| storage_failure(session.id, e))?;
+            written += chunk.len() as u64;
+        }
+        file.flush()
+            .await
+            .map_err(|e| storage_failure(session.id, e))
+    }
+    .await;
+    let res = match (res, range) {
+        (Ok(()), _) if exceeded => {
+            // Chunks are applied completely or not at all.
+            file.set_len(received)
+                .await
+                .map_err(|e| storage_failure(session.id, e))?;
+            Err(too_large())
+        }
+        (Ok(()), Some((start, end))) if written != end - start + 1 => {
+            // Chunks are applied completely or not at all.
+            file.set_len(start)
+                .await
+                .map_err(|e| storage_failure(session.id, e))?;
+            Err((
+                StatusCode::BAD_REQUEST,
+                "Chunk length does not match `Content-Range`",
+            )
+                .into_response())
+        }
+        (res, _) => res,
+    };
+    // Contents received before a failure are retained, such that the upload can be resumed.
+    *offset = file
+        .metadata()
+        .map_err(|e| storage_failure(session.id, e))?
+        .len();
+    let progress = Progress::new(uri.path(), *offset);
+    match res {
+        Ok(()) => Ok((StatusCode::ACCEPTED, progress, ())),
+        Err(res) => Err((progress, res).into_response()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn content_range() {
+        assert_eq!(parse_range("0-0"), Some((0, 0)));
+        assert_eq!(parse_range("42-1023"), Some((42, 1023)));
+        assert_eq!(parse_range("2-1"), None);
+        assert_eq!(parse_range("bytes 0-1/2"), None);
+        assert_eq!(parse_range("0-"), None);
+        assert_eq!(parse_range(""), None);
+    }
+}
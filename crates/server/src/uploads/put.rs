@@ -0,0 +1,119 @@
+// SPDX-FileCopyrightText: 2022 Profian Inc. <opensource@profian.com>
+// SPDX-License-Identifier: AGPL-3.0-only
+
+use super::super::{verify_content, ContentTypes, CreateError, OidcClaims, Precompression, Store};
+use super::{session, storage_failure, UploadId, Uploads};
+
+use drawbridge_type::{
+    Meta, RepositoryChange, RepositoryChangeKind, RepositoryContext, TagName, TreeDirectory,
+    TreePath,
+};
+
+use async_std::sync::Arc;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::{Extension, Json};
+use serde::Deserialize;
+use tracing::{debug, trace};
+
+/// Tree node, which the contents of an upload session are stored as.
+#[derive(Debug, Deserialize)]
+pub(crate) struct Target {
+    tag: String,
+    path: String,
+    #[serde(flatten)]
+    meta: Meta,
+}
+
+/// Verifies the contents received by an upload session and stores them as a tree node.
+///
+/// The session is closed once the contents are stored.
+#[allow(clippy::too_many_arguments)]
+pub(crate) async fn put(
+    Extension(ref store): Extension<Arc<Store>>,
+    uploads: Option<Extension<Arc<Uploads>>>,
+    content_types: Option<Extension<Arc<ContentTypes>>>,
+    precompression: Option<Extension<Arc<Precompression>>>,
+    Extension(id): Extension<UploadId>,
+    claims: OidcClaims,
+    cx: RepositoryContext,
+    Json(Target { tag, path, meta }): Json<Target>,
+) -> impl IntoResponse {
+    trace!(target: "app::uploads::put", "called for `{}`", id.0);
+
+    let bad_request = |msg: String| (StatusCode::BAD_REQUEST, msg).into_response();
+    let tag = tag
+        .parse::<TagName>()
+        .map_err(|e| bad_request(format!("Failed to parse tag name: {e}")))?;
+    let path = path
+        .parse::<TreePath>()
+        .map_err(|e| bad_request(format!("Failed to parse tree path: {e}")))?;
+    if meta.hash.is_empty() {
+        return Err(bad_request(
+            "At least one content digest value must be specified".into(),
+        ));
+    }
+    if meta.mime.essence_str() == TreeDirectory::<()>::TYPE {
+        return Err(bad_request("Directories cannot be uploaded".into()));
+    }
+    if let Some(Extension(ref content_types)) = content_types {
+        content_types.check(&meta.mime)?;
+    }
+
+    let (user, uploads, session) = session(store, uploads, &claims, &cx, id).await?;
+    let offset = session.offset.lock().await;
+    if *offset != meta.size {
+        return Err(CreateError::<()>::LengthMismatch {
+            expected: meta.size,
+            got: *offset,
+        }
+        .into_response());
+    }
+
+    let tag_name = tag;
+    let tag = user.repository(&cx.name).tag(&tag_name);
+    let fail = |e: CreateError<anyhow::Error>| {
+        debug!(target: "app::uploads::put", "failed to store upload `{}`: {:?}", id.0, e);
+        e.into_response()
+    };
+    let status = if tag.node(&path).is_stored(&meta).await.map_err(fail)? {
+        debug!(target: "app::uploads::put", "`{path}` is already stored, discard upload `{}`", id.0);
+        StatusCode::OK
+    } else {
+        let open = || async {
+            uploads
+                .open(&session)
+                .await
+                .map_err(|e| storage_failure(session.id, e))
+        };
+        if let Some(Extension(ref content_types)) = content_types {
+            _ = content_types.sniff(&meta.mime, open().await?).await?;
+        }
+        // Contents are verified before creating the node, such that a mismatching upload does
+        // not leave a partially created node behind.
+        verify_content(meta.clone(), open().await?)
+            .await
+            .map_err(fail)?;
+        let node = tag
+            .create_file_node(&path, meta.clone(), open().await?)
+            .await
+            .map_err(fail)?;
+        if let Some(Extension(ref precompression)) = precompression {
+            precompression.store(&path, &node, &meta).await;
+        }
+        debug!(target: "app::uploads::put", "stored upload `{}` at `{path}`", id.0);
+        store
+            .record_change(
+                &cx,
+                RepositoryChange {
+                    kind: RepositoryChangeKind::Changed,
+                    tag: tag_name.clone(),
+                    path: Some(path.clone()),
+                },
+            )
+            .await;
+        StatusCode::CREATED
+    };
+    uploads.remove(&session).await;
+    Ok::<_, Response>(status)
+}
@@ -0,0 +1,33 @@
+// SPDX-FileCopyrightText: 2022 Profian Inc. <opensource@profian.com>
+// SPDX-License-Identifier: AGPL-3.0-only
+
+use super::super::{OidcClaims, Store};
+use super::{session, Progress, UploadId, Uploads};
+
+use drawbridge_type::RepositoryContext;
+
+use async_std::sync::Arc;
+use axum::http::{StatusCode, Uri};
+use axum::response::{IntoResponse, Response};
+use axum::Extension;
+use tracing::trace;
+
+/// Reports the number of bytes received by an upload session.
+pub(crate) async fn status(
+    Extension(ref store): Extension<Arc<Store>>,
+    uploads: Option<Extension<Arc<Uploads>>>,
+    Extension(id): Extension<UploadId>,
+    claims: OidcClaims,
+    cx: RepositoryContext,
+    uri: Uri,
+) -> impl IntoResponse {
+    trace!(target: "app::uploads::status", "called for `{}`", id.0);
+
+    let (_, _, session) = session(store, uploads, &claims, &cx, id).await?;
+    let offset = *session.offset.lock().await;
+    Ok::<_, Response>((
+        StatusCode::NO_CONTENT,
+        Progress::new(uri.path(), offset),
+        (),
+    ))
+}
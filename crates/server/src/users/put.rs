@@ -6,7 +6,9 @@ use super::super::{OidcClaims, ScopeContext, ScopeLevel, Store};
 use drawbridge_type::{Meta, UserContext, UserRecord};
 
 use async_std::sync::Arc;
-use axum::http::StatusCode;
+use axum::body::Body;
+use axum::extract::RequestParts;
+use axum::http::{Request, StatusCode};
 use axum::response::IntoResponse;
 use axum::{Extension, Json};
 use tracing::{debug, trace};
@@ -14,14 +16,22 @@ use tracing::{debug, trace};
 pub async fn put(
     Extension(ref store): Extension<Arc<Store>>,
     claims: OidcClaims,
-    ref cx: UserContext,
+    cx: UserContext,
     meta: Meta,
-    Json(ref record): Json<UserRecord>,
+    req: Request<Body>,
 ) -> impl IntoResponse {
     trace!(target: "app::users::put", "called for `{cx}`");
 
     claims
         .assert_scope(ScopeContext::User, ScopeLevel::Write)
+        .await
+        .map_err(IntoResponse::into_response)?;
+
+    // The body is only read after authorization, such that `Expect: 100-continue` requests
+    // are rejected before their body is sent.
+    let Json(ref record) = RequestParts::new(req)
+        .extract::<Json<UserRecord>>()
+        .await
         .map_err(IntoResponse::into_response)?;
 
     if record.subject != claims.subject() {
@@ -29,7 +39,7 @@ pub async fn put(
     }
 
     store
-        .create_user(cx, meta, record)
+        .create_user(&cx, meta, record)
         .await
         .map_err(|e| {
             debug!(target: "app::users::put", "failed for `{cx}`: {:?}", e);
@@ -0,0 +1,45 @@
+// SPDX-FileCopyrightText: 2022 Profian Inc. <opensource@profian.com>
+// SPDX-License-Identifier: AGPL-3.0-only
+
+use std::convert::Infallible;
+use std::time::SystemTime;
+
+use drawbridge_type::Meta;
+
+use axum::headers::LastModified;
+use axum::http::header::ETAG;
+use axum::http::HeaderValue;
+use axum::response::{IntoResponseParts, ResponseParts};
+use axum::TypedHeader;
+
+/// Validators of a stored object, i.e. its `ETag` and `Last-Modified` headers, which are sent
+/// in responses to both `GET` and `HEAD` requests.
+///
+/// Entity tags are quoted content digests, like the ones expected by tag preconditions.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Validators {
+    etag: Option<HeaderValue>,
+    last_modified: SystemTime,
+}
+
+impl Validators {
+    /// Constructs the validators of an object described by `meta`, which was last modified at
+    /// `last_modified`.
+    pub(crate) fn new(meta: &Meta, last_modified: SystemTime) -> Self {
+        Self {
+            etag: HeaderValue::from_str(&format!(r#""{}""#, meta.hash)).ok(),
+            last_modified,
+        }
+    }
+}
+
+impl IntoResponseParts for Validators {
+    type Error = Infallible;
+
+    fn into_response_parts(self, mut res: ResponseParts) -> Result<ResponseParts, Self::Error> {
+        if let Some(etag) = self.etag {
+            _ = res.headers_mut().insert(ETAG, etag);
+        }
+        TypedHeader(LastModified::from(self.last_modified)).into_response_parts(res)
+    }
+}
@@ -39,6 +39,7 @@ mod meta;
 
 pub use meta::*;
 pub use repository::{
+    Change as RepositoryChange, ChangeKind as RepositoryChangeKind, Changes as RepositoryChanges,
     Config as RepositoryConfig, Context as RepositoryContext, Name as RepositoryName,
 };
 pub use tag::{Context as TagContext, Entry as TagEntry, Name as TagName};
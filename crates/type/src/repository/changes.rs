@@ -0,0 +1,37 @@
+// SPDX-FileCopyrightText: 2022 Profian Inc. <opensource@profian.com>
+// SPDX-License-Identifier: AGPL-3.0-only
+
+use super::super::{TagName, TreePath};
+
+use serde::{Deserialize, Serialize};
+
+/// Kind of a change to a tag of a repository.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChangeKind {
+    /// The tag was created.
+    Added,
+    /// A node was added to the tree of the tag.
+    Changed,
+    /// The tag was deleted along with its tree.
+    Deleted,
+}
+
+/// A change to a tag of a repository
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Change {
+    pub kind: ChangeKind,
+    pub tag: TagName,
+    /// Path of the tree node, which was added to the tag, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<TreePath>,
+}
+
+/// Changes to the tags of a repository since a cursor
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Changes {
+    /// Changes in the order they were recorded.
+    pub changes: Vec<Change>,
+    /// Opaque cursor to pass as `since` to retrieve the changes recorded after these.
+    pub cursor: String,
+}
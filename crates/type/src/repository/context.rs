@@ -30,7 +30,7 @@ impl FromStr for Context {
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         let (owner, name) = s
-            .rsplit_once(&['/', ':'])
+            .rsplit_once(['/', ':'])
             .ok_or_else(|| anyhow!("`/` or ':' separator not found"))?;
         let owner = owner.parse().context("failed to parse user context")?;
         let name = name.parse().context("failed to parse repository name")?;
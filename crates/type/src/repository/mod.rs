@@ -1,10 +1,12 @@
 // SPDX-FileCopyrightText: 2022 Profian Inc. <opensource@profian.com>
 // SPDX-License-Identifier: AGPL-3.0-only
 
+mod changes;
 mod config;
 mod context;
 mod name;
 
+pub use changes::*;
 pub use config::*;
 pub use context::*;
 pub use name::*;
@@ -0,0 +1,379 @@
+// SPDX-FileCopyrightText: 2022 Profian Inc. <opensource@profian.com>
+// SPDX-License-Identifier: AGPL-3.0-only
+
+//! Synthetic load generation against a running Drawbridge server.
+
+use super::open_buffered;
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::num::NonZeroUsize;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use drawbridge_client::mime::APPLICATION_OCTET_STREAM;
+use drawbridge_client::types::TreeContent::{Directory, File};
+use drawbridge_client::types::{
+    RepositoryConfig, RepositoryName, TagEntry, TagName, Tree, TreePath, UserContext, UserName,
+    UserRecord,
+};
+use drawbridge_client::{scope, Client, Repository, Url};
+
+use anyhow::{bail, ensure, Context as _};
+use rustls::{Certificate, PrivateKey, RootCertStore};
+use rustls_pemfile::Item::{ECKey, PKCS8Key, RSAKey};
+use tracing::{debug, info};
+
+/// Name of the file contained in each tree written by the benchmark.
+const PAYLOAD: &str = "payload";
+
+/// Options of the `bench` command.
+#[derive(clap::Args, Debug)]
+pub(crate) struct BenchArgs {
+    /// URL of the Drawbridge server, e.g. `https://localhost:8080`.
+    #[arg(long)]
+    url: Url,
+
+    /// User owning the repository written to.
+    #[arg(long)]
+    user: UserName,
+
+    /// Repository written to, which should be dedicated to benchmarking.
+    #[arg(long)]
+    repo: RepositoryName,
+
+    /// OpenID Connect subject to create the user and repository for, if they do not exist
+    /// yet.
+    #[arg(long)]
+    subject: Option<String>,
+
+    /// Path to a file containing the OpenID Connect bearer token to authenticate with.
+    #[arg(long, value_name = "PATH")]
+    token_file: Option<PathBuf>,
+
+    /// Path to PEM-encoded CA certificate to trust instead of the Web PKI roots.
+    #[arg(long)]
+    ca: Option<PathBuf>,
+
+    /// Path to PEM-encoded client certificate to authenticate with.
+    #[arg(long, requires = "key")]
+    cert: Option<PathBuf>,
+
+    /// Path to PEM-encoded client certificate key.
+    #[arg(long, requires = "cert")]
+    key: Option<PathBuf>,
+
+    /// Number of concurrently issued requests.
+    #[arg(long, value_name = "N", default_value = "8")]
+    concurrency: NonZeroUsize,
+
+    /// Duration of the benchmark in seconds.
+    #[arg(long, value_name = "SECONDS", default_value_t = 10)]
+    duration: u64,
+
+    /// Fraction of operations reading a stored file, the remaining ones write a new tag along
+    /// with its tree.
+    #[arg(long, value_name = "RATIO", default_value_t = 0.9)]
+    read_ratio: f64,
+
+    /// Size of the files written and read in bytes.
+    #[arg(long, value_name = "BYTES", default_value_t = 64 * 1024)]
+    size: usize,
+}
+
+/// Kind of a benchmark operation.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum Operation {
+    /// Fetches the file of the seed tag and verifies its digest.
+    Read,
+    /// Creates a new tag and uploads its tree consisting of a directory and a file.
+    Write,
+}
+
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Read => write!(f, "read"),
+            Self::Write => write!(f, "write"),
+        }
+    }
+}
+
+impl Operation {
+    /// Returns the kind of the `n`th operation, such that reads make up `read_ratio` of any
+    /// sequence of operations as closely as possible.
+    fn nth(n: u64, read_ratio: f64) -> Self {
+        if ((n + 1) as f64 * read_ratio).floor() > (n as f64 * read_ratio).floor() {
+            Self::Read
+        } else {
+            Self::Write
+        }
+    }
+}
+
+/// Latencies and failures of operations of one kind.
+#[derive(Clone, Debug, Default)]
+struct Samples {
+    latencies: Vec<Duration>,
+    failed: u64,
+}
+
+impl Samples {
+    fn merge(&mut self, other: Samples) {
+        self.latencies.extend(other.latencies);
+        self.failed += other.failed;
+    }
+
+    /// Returns the `p`th percentile of the sorted `latencies` using the nearest-rank method.
+    fn percentile(latencies: &[Duration], p: f64) -> Duration {
+        let rank = (p / 100.0 * latencies.len() as f64).ceil() as usize;
+        latencies
+            .get(rank.saturating_sub(1))
+            .copied()
+            .unwrap_or_default()
+    }
+}
+
+/// Returns the contents of the file written by the `n`th operation, which are unique to it.
+fn payload(n: u64, size: usize) -> Vec<u8> {
+    let mut buf: Vec<_> = n.to_le_bytes().into_iter().cycle().take(size).collect();
+    buf.iter_mut()
+        .enumerate()
+        .for_each(|(i, b)| *b = b.wrapping_add(i as u8));
+    buf
+}
+
+/// Creates the tag `name` in `repo` and uploads a tree containing `data`.
+fn write_tag(
+    repo: &Repository<'_, scope::Root>,
+    name: &TagName,
+    data: &[u8],
+) -> anyhow::Result<()> {
+    let file = Tree::file_entry_sync(data, APPLICATION_OCTET_STREAM)
+        .context("failed to compute file digest")?;
+    let tree = Tree::try_from(BTreeMap::from([(
+        PAYLOAD.parse().context("failed to parse file name")?,
+        file,
+    )]))
+    .context("failed to construct tree")?;
+    let tag = repo.tag(name);
+    _ = tag.create(&TagEntry::Unsigned(tree.root()))?;
+    for (path, entry) in tree.iter() {
+        let node = tag.path(path);
+        _ = match entry.content {
+            File(_) => node.create_bytes(&entry.meta.mime, data)?,
+            Directory(ref buf) => node.create_from(&entry.meta, buf.as_slice())?,
+        };
+    }
+    Ok(())
+}
+
+fn read_certs(path: &PathBuf) -> anyhow::Result<Vec<Certificate>> {
+    rustls_pemfile::certs(&mut open_buffered(path)?)
+        .with_context(|| format!("Failed to read certificates from `{}`", path.display()))
+        .map(|certs| certs.into_iter().map(Certificate).collect())
+}
+
+fn read_key(path: &PathBuf) -> anyhow::Result<PrivateKey> {
+    match rustls_pemfile::read_one(&mut open_buffered(path)?)
+        .with_context(|| format!("Failed to read key from `{}`", path.display()))?
+    {
+        Some(RSAKey(buf) | PKCS8Key(buf) | ECKey(buf)) => Ok(PrivateKey(buf)),
+        _ => bail!("`{}` does not contain a supported key", path.display()),
+    }
+}
+
+fn client(args: &BenchArgs) -> anyhow::Result<Client> {
+    let client = Client::builder(args.url.clone())
+        .user_agent(concat!("drawbridge-bench/", env!("CARGO_PKG_VERSION")));
+    let client = match args.ca {
+        Some(ref ca) => {
+            let mut roots = RootCertStore::empty();
+            for cert in read_certs(ca)? {
+                roots.add(&cert).context("Failed to add CA certificate")?;
+            }
+            client.roots(roots)
+        }
+        None => client,
+    };
+    let client = match (&args.cert, &args.key) {
+        (Some(cert), Some(key)) => client.credentials(read_certs(cert)?, read_key(key)?),
+        _ => client,
+    };
+    let client = match args.token_file {
+        Some(ref path) => client.token(
+            std::fs::read_to_string(path)
+                .with_context(|| format!("Failed to read token from `{}`", path.display()))?
+                .trim(),
+        ),
+        None => client,
+    };
+    client.build().context("Failed to build client")
+}
+
+/// Drives load according to `args` against a running server and prints throughput and latency
+/// percentiles of reads and writes.
+///
+/// Tags written are named `0.0.0-bench.<run>.<n>`, where `<run>` identifies the invocation, and
+/// are not removed afterwards.
+pub(crate) fn bench(args: BenchArgs) -> anyhow::Result<()> {
+    ensure!(
+        (0.0..=1.0).contains(&args.read_ratio),
+        "read ratio must be between 0 and 1"
+    );
+    let client = client(&args)?;
+    let owner = UserContext {
+        name: args.user.clone(),
+    };
+    let user = client.user(&owner);
+    let repo = user.repository(&args.repo);
+    if let Some(ref subject) = args.subject {
+        if user.get().is_err() {
+            _ = user
+                .create(&UserRecord {
+                    subject: subject.clone(),
+                })
+                .context("Failed to create user")?;
+        }
+        if repo.get().is_err() {
+            _ = repo
+                .create(&RepositoryConfig { public: false })
+                .context("Failed to create repository")?;
+        }
+    }
+
+    let run = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let tag_name = |n: u64| -> anyhow::Result<TagName> {
+        format!("0.0.0-bench.{run}.{n}")
+            .parse()
+            .context("failed to parse tag name")
+    };
+    let seed = tag_name(0)?;
+    write_tag(&repo, &seed, &payload(0, args.size)).context("Failed to write seed tag")?;
+    let seed_path: TreePath = PAYLOAD.parse().context("failed to parse file path")?;
+
+    info!(target: "main::bench", concurrency = args.concurrency.get(), duration = args.duration, read_ratio = args.read_ratio, size = args.size, "benchmark started");
+    let next = AtomicU64::new(0);
+    let start = Instant::now();
+    let deadline = start + Duration::from_secs(args.duration);
+    let samples = thread::scope(|s| {
+        let workers: Vec<_> = (0..args.concurrency.get())
+            .map(|_| {
+                s.spawn(|| {
+                    let repo = client.user(&owner).repository(&args.repo);
+                    let seed = repo.tag(&seed).path(&seed_path);
+                    let mut samples = BTreeMap::<_, Samples>::new();
+                    while Instant::now() < deadline {
+                        let n = next.fetch_add(1, Ordering::Relaxed);
+                        let op = Operation::nth(n, args.read_ratio);
+                        let started = Instant::now();
+                        let res = match op {
+                            Operation::Read => {
+                                seed.get_bytes(args.size as u64).and_then(|(_, buf)| {
+                                    ensure!(buf.len() == args.size, "file size mismatch");
+                                    Ok(())
+                                })
+                            }
+                            Operation::Write => tag_name(n + 1).and_then(|name| {
+                                write_tag(&repo, &name, &payload(n + 1, args.size))
+                            }),
+                        };
+                        let samples = samples.entry(op).or_default();
+                        match res {
+                            Ok(()) => samples.latencies.push(started.elapsed()),
+                            Err(e) => {
+                                debug!(target: "main::bench", "{op} operation failed: {e:#}");
+                                samples.failed += 1;
+                            }
+                        }
+                    }
+                    samples
+                })
+            })
+            .collect();
+        workers
+            .into_iter()
+            .map(|worker| worker.join().expect("benchmark worker panicked"))
+            .fold(BTreeMap::<_, Samples>::new(), |mut all, samples| {
+                for (op, samples) in samples {
+                    all.entry(op).or_default().merge(samples);
+                }
+                all
+            })
+    });
+    let elapsed = start.elapsed();
+
+    let (mut total, mut failed) = (0, 0);
+    for samples in samples.values() {
+        total += samples.latencies.len() as u64 + samples.failed;
+        failed += samples.failed;
+    }
+    println!(
+        "{total} operations in {:.2}s, {:.1} operations/s, {failed} failed",
+        elapsed.as_secs_f64(),
+        (total - failed) as f64 / elapsed.as_secs_f64()
+    );
+    for (op, mut samples) in samples {
+        samples.latencies.sort_unstable();
+        let percentile = |p| Samples::percentile(&samples.latencies, p);
+        println!(
+            "{op:>5}: {} succeeded, {} failed, p50 {:?}, p90 {:?}, p99 {:?}, max {:?}",
+            samples.latencies.len(),
+            samples.failed,
+            percentile(50.0),
+            percentile(90.0),
+            percentile(99.0),
+            percentile(100.0),
+        );
+    }
+    ensure!(
+        total == 0 || failed < total,
+        "all operations failed, rerun with `RUST_LOG=main::bench=debug` for details"
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn operation_mix() {
+        let reads = |ratio| {
+            (0..100)
+                .filter(|&n| Operation::nth(n, ratio) == Operation::Read)
+                .count()
+        };
+        assert_eq!(reads(0.0), 0);
+        assert_eq!(reads(0.25), 25);
+        assert_eq!(reads(0.9), 90);
+        assert_eq!(reads(1.0), 100);
+        assert_eq!(Operation::nth(0, 0.5), Operation::Write);
+        assert_eq!(Operation::nth(1, 0.5), Operation::Read);
+    }
+
+    #[test]
+    fn percentile() {
+        let latencies: Vec<_> = (1..=10).map(Duration::from_millis).collect();
+        assert_eq!(
+            Samples::percentile(&latencies, 50.0),
+            Duration::from_millis(5)
+        );
+        assert_eq!(
+            Samples::percentile(&latencies, 99.0),
+            Duration::from_millis(10)
+        );
+        assert_eq!(
+            Samples::percentile(&latencies, 100.0),
+            Duration::from_millis(10)
+        );
+        assert_eq!(Samples::percentile(&[], 50.0), Duration::ZERO);
+        assert_ne!(payload(1, 64), payload(2, 64));
+        assert_eq!(payload(1, 3).len(), 3);
+    }
+}
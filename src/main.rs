@@ -1,7 +1,6 @@
 // SPDX-FileCopyrightText: 2022 Profian Inc. <opensource@profian.com>
 // SPDX-License-Identifier: AGPL-3.0-only
 
-#![forbid(unsafe_code)]
 #![deny(
     clippy::all,
     absolute_paths_not_starting_with_crate,
@@ -16,6 +15,7 @@
     trivial_bounds,
     trivial_casts,
     trivial_numeric_casts,
+    unsafe_code,
     unreachable_code,
     unreachable_patterns,
     unreachable_pub,
@@ -28,20 +28,53 @@
     variant_size_differences
 )]
 
+#[cfg(feature = "bench")]
+mod bench;
+
 use std::fs::File;
 use std::io::{self, BufRead, BufReader};
 use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+#[cfg(unix)]
+use std::os::unix::io::RawFd;
 use std::path::{Path, PathBuf};
+use std::pin::pin;
+use std::process::ExitCode;
+use std::sync::Arc;
+use std::time::Duration;
 
+use drawbridge_server::mime::Mime;
+use drawbridge_server::store::Store;
 use drawbridge_server::url::Url;
-use drawbridge_server::{App, OidcConfig, TlsConfig};
+use drawbridge_server::{
+    export_store, import_store, store_stats, App, Builder, CertificateAllowlist,
+    CertificateWriters, CompressionAlgorithm, FailureClass, Hsts, IpCidr, ManifestSchema, Method,
+    MirrorConfig, OidcConfig, RouteClass, S3Credentials, StoreFailurePolicy, StoreStats, StoreUrl,
+    TlsConfig, TlsOptions, TlsSessionConfig, DEFAULT_AUTHZ_CACHE_TTL, DEFAULT_CACHE_MAX_BYTES,
+    DEFAULT_CONTENT_CACHE_CONTROL, DEFAULT_HANDSHAKE_TIMEOUT, DEFAULT_HSTS_MAX_AGE,
+    DEFAULT_IDEMPOTENCY_KEY_TTL, DEFAULT_LIMIT_WARNING_PERCENT, DEFAULT_MAX_CLIENT_CERT_CHAIN,
+    DEFAULT_MAX_REQUEST_DEADLINE, DEFAULT_MIRROR_TTL, DEFAULT_OIDC_CLOCK_SKEW,
+    DEFAULT_ORPHAN_MAX_AGE, DEFAULT_RESPONSE_BUFFER_BYTES, DEFAULT_S3_REGION,
+    DEFAULT_SHUTDOWN_TIMEOUT, DEFAULT_STARTUP_SCAN_THREADS, DEFAULT_STORE_CIRCUIT_COOLDOWN,
+    DEFAULT_STORE_CIRCUIT_THRESHOLD, DEFAULT_TAG_CACHE_CONTROL, DEFAULT_TLS_SESSION_CACHE_SIZE,
+    DEFAULT_TLS_TICKET_LIFETIME, DEFAULT_UPLOAD_SESSION_TTL, MAX_NEGATIVE_CACHE_TTL,
+};
+use drawbridge_type::UserName;
 
-use anyhow::Context as _;
+use anyhow::{anyhow, Context as _};
 use async_std::net::TcpListener;
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use confargs::{args, prefix_char_filter, Toml};
-use futures::StreamExt;
-use tracing::{debug, error};
+use futures::future::{pending, select, Either};
+use futures::io::{BufReader as AsyncBufReader, BufWriter as AsyncBufWriter};
+use futures::{join, try_join, StreamExt};
+use signal_hook::consts::{SIGHUP, SIGINT, SIGTERM};
+use signal_hook_async_std::Signals;
+use tracing::{error, info};
+use tracing_subscriber::fmt::MakeWriter;
+use tracing_subscriber::EnvFilter;
+
+/// Value of the `Server` header sent by default.
+const SERVER_HEADER: &str = concat!("drawbridge/", env!("CARGO_PKG_VERSION"));
 
 /// Server for hosting WebAssembly modules for use in Enarx keeps.
 ///
@@ -50,24 +83,538 @@ use tracing::{debug, error};
 /// name of the file on the command-line with the syntax `@config.toml`.
 /// The configuration file must contain valid TOML table mapping argument
 /// names to their values.
+///
+/// If no command is given, the server is started using the options passed.
 #[derive(Parser, Debug)]
 #[command(author, version, about)]
-struct Args {
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Serve the store (default).
+    #[command(after_long_help = EXIT_CODES)]
+    Serve(Box<ServeArgs>),
+
+    #[command(flatten)]
+    Manage(ManageCommand),
+
+    /// Drive synthetic load against a running server and report throughput and latency
+    /// percentiles.
+    #[cfg(feature = "bench")]
+    Bench(Box<bench::BenchArgs>),
+}
+
+impl Command {
+    /// Name of the command run if none is given.
+    const DEFAULT: &'static str = "serve";
+}
+
+/// Options of the `serve` command.
+#[derive(clap::Args, Debug)]
+struct ServeArgs {
     /// Address to bind to.
     #[arg(long, default_value_t = SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 8080))]
     addr: SocketAddr,
 
+    /// Number of an inherited file descriptor of a listening TCP socket to accept connections
+    /// on instead of binding to `--addr`, e.g. as passed by inetd-style supervisors.
+    #[cfg(unix)]
+    #[arg(long, value_name = "N", conflicts_with = "addr")]
+    listen_fd: Option<RawFd>,
+
+    /// Address of an additional plaintext HTTP listener, which redirects all requests to their
+    /// `https` equivalent using `--public-url` or the `Host` header, and never serves content.
+    #[arg(long, value_name = "ADDR")]
+    http_redirect_addr: Option<SocketAddr>,
+
+    /// Address of an additional plaintext HTTP listener, which only serves the metrics at
+    /// `/metrics` and the health probes at `/healthz` and `/readyz`, e.g. to be scraped from a
+    /// private network. Connections to it do not count towards `--max-connections`.
+    #[arg(long, value_name = "ADDR")]
+    metrics_addr: Option<SocketAddr>,
+
+    /// Directory to resolve relative `--store`, `--cache-dir`, `--cert`, `--key`, `--ca`,
+    /// `--client-cert-allowlist`, `--cert-writers`, `--manifest-schema` and `--mirror-*` paths
+    /// against instead of the working directory. Absolute paths are used as-is.
+    ///
+    /// Relative paths given in `@config.toml` files are resolved the same way, i.e. relative to
+    /// this directory or the working directory and not to the configuration file.
+    #[arg(long, value_name = "PATH")]
+    base_dir: Option<PathBuf>,
+
+    /// Compress responses using an algorithm negotiated with the client via `Accept-Encoding`.
+    #[arg(long)]
+    compression: bool,
+
+    /// Comma-separated list of compression algorithms offered to clients if `--compression` is set.
+    ///
+    /// Supported algorithms are `br` and `gzip`.
+    #[arg(
+        long,
+        value_delimiter = ',',
+        default_value = "br,gzip",
+        value_parser = |s: &str| s.parse::<CompressionAlgorithm>().map_err(|e| e.to_string())
+    )]
+    compression_algorithms: Vec<CompressionAlgorithm>,
+
+    /// Store variants of uploaded file contents pre-compressed using each of
+    /// `--compression-algorithms` and serve those instead of compressing contents on every
+    /// request, which trades storage for CPU time and latency.
+    ///
+    /// Variants failing verification against their digest are removed and the contents
+    /// compressed on the fly instead.
+    #[arg(long, requires = "compression")]
+    store_precompressed: bool,
+
+    /// Validate uploaded tag manifests against a JSON schema.
+    ///
+    /// Invalid manifests are rejected with `400 Bad Request` listing the validation errors.
+    /// Unless `--manifest-schema` is set, manifests must be tree entries with at least one digest.
+    #[arg(long)]
+    validate_manifests: bool,
+
+    /// Path to the JSON schema used if `--validate-manifests` is set.
+    ///
+    /// Only the `type`, `enum`, `const`, `properties`, `required`, `additionalProperties`,
+    /// `minProperties`, `items`, `minItems`, `maxItems`, `minLength`, `maxLength`, `minimum`
+    /// and `maximum` keywords are supported.
+    #[arg(long, requires = "validate_manifests")]
+    manifest_schema: Option<PathBuf>,
+
+    /// Comma-separated list of media types, which files may be uploaded as, e.g.
+    /// `application/wasm,application/json` or `text/*`. All types are allowed by default.
+    ///
+    /// Uploads declaring any other type, or with contents not matching the declared one, are
+    /// rejected with `415 Unsupported Media Type`.
+    #[arg(
+        long,
+        value_delimiter = ',',
+        value_parser = |s: &str| s.parse::<Mime>().map_err(|e| e.to_string())
+    )]
+    allowed_content_types: Vec<Mime>,
+
+    /// Comma-separated methods, which requests may use, e.g. `GET,HEAD` to only serve
+    /// downloads. All methods are allowed by default.
+    ///
+    /// Requests using any other method are rejected with `405 Method Not Allowed`.
+    #[arg(
+        long,
+        value_name = "METHODS",
+        value_delimiter = ',',
+        value_parser = |s: &str| s.to_ascii_uppercase().parse::<Method>().map_err(|e| e.to_string())
+    )]
+    allowed_methods: Vec<Method>,
+
+    /// Comma-separated classes of API routes to disable, requests to which are rejected with
+    /// `404 Not Found` even if authorized.
+    ///
+    /// Supported classes are `users`, `tokens` (access tokens of users), `repositories`,
+    /// `listings` (tag queries and change logs), `tags`, `trees` and `uploads`.
+    #[arg(
+        long,
+        value_name = "ROUTES",
+        value_delimiter = ',',
+        value_parser = |s: &str| s.parse::<RouteClass>().map_err(|e| e.to_string())
+    )]
+    disable_routes: Vec<RouteClass>,
+
+    /// Respond to unauthorized requests with `404 Not Found`, like to requests for nonexistent
+    /// resources, such that clients cannot tell whether users and repositories exist.
+    #[arg(long)]
+    hide_existence: bool,
+
+    /// Duration in seconds, for which the response to a mutating request carrying an
+    /// `Idempotency-Key` header is replayed to retries by the same subject using the same key.
+    ///
+    /// Responses are recorded in the store, such that they are replayed across restarts.
+    #[arg(long, value_name = "SECONDS", default_value_t = DEFAULT_IDEMPOTENCY_KEY_TTL.as_secs())]
+    idempotency_key_ttl: u64,
+
+    /// Do not replay responses to requests carrying an `Idempotency-Key` header.
+    #[arg(long, conflicts_with = "idempotency_key_ttl")]
+    no_idempotency_keys: bool,
+
+    /// Duration in seconds, for which `404 Not Found` responses to reading requests are cached per
+    /// client credentials, `0` disables the cache.
+    ///
+    /// Cached responses of a namespace are invalidated once it is modified, such that clients
+    /// polling for a tag see it as soon as it is published.
+    #[arg(
+        long,
+        value_name = "SECONDS",
+        default_value_t = 0,
+        value_parser = clap::value_parser!(u64).range(0..=MAX_NEGATIVE_CACHE_TTL.as_secs())
+    )]
+    negative_cache_ttl: u64,
+
+    /// Externally visible base URL of the server used to construct absolute URLs,
+    /// e.g. when running behind a reverse proxy. Must use the `https` scheme.
+    #[arg(long)]
+    public_url: Option<Url>,
+
+    /// Allow `--public-url` to use the `http` scheme.
+    #[arg(long)]
+    allow_insecure_public_url: bool,
+
+    /// Secret key used to sign URLs, which grant time-limited anonymous read access to a
+    /// single tag or tree node. Must be at least 16 bytes long.
+    ///
+    /// Authenticated users obtain a signed URL by sending a `POST` request to the object,
+    /// optionally specifying the lifetime in seconds using the `expires-in` query parameter.
+    #[arg(long, value_name = "SECRET")]
+    url_signing_secret: Option<String>,
+
+    /// Comma-separated list of address ranges of trusted reverse proxies in CIDR notation,
+    /// e.g. `10.0.0.0/8,fd00::/8`.
+    ///
+    /// `X-Forwarded-For`, `X-Forwarded-Proto` and `X-Forwarded-Host` headers determine the
+    /// effective client of requests received from trusted proxies and are ignored otherwise.
+    #[arg(
+        long,
+        value_delimiter = ',',
+        value_parser = |s: &str| s.parse::<IpCidr>().map_err(|e| e.to_string())
+    )]
+    trusted_proxies: Vec<IpCidr>,
+
+    /// Address range in CIDR notation allowed access, may be repeated or comma-separated.
+    /// If set, connections and requests from the effective client address, i.e. taking
+    /// `--trusted-proxies` into account, outside of all allowed ranges are rejected.
+    #[arg(
+        long,
+        value_name = "CIDR",
+        value_delimiter = ',',
+        value_parser = |s: &str| s.parse::<IpCidr>().map_err(|e| e.to_string())
+    )]
+    allow_cidr: Vec<IpCidr>,
+
+    /// Address range in CIDR notation denied access, may be repeated or comma-separated.
+    /// Takes precedence over `--allow-cidr`.
+    #[arg(
+        long,
+        value_name = "CIDR",
+        value_delimiter = ',',
+        value_parser = |s: &str| s.parse::<IpCidr>().map_err(|e| e.to_string())
+    )]
+    deny_cidr: Vec<IpCidr>,
+
+    /// Like `--allow-cidr`, but only applies to requests, which could modify the store.
+    #[arg(
+        long,
+        value_name = "CIDR",
+        value_delimiter = ',',
+        value_parser = |s: &str| s.parse::<IpCidr>().map_err(|e| e.to_string())
+    )]
+    write_allow_cidr: Vec<IpCidr>,
+
+    /// Like `--deny-cidr`, but only applies to requests, which could modify the store.
+    #[arg(
+        long,
+        value_name = "CIDR",
+        value_delimiter = ',',
+        value_parser = |s: &str| s.parse::<IpCidr>().map_err(|e| e.to_string())
+    )]
+    write_deny_cidr: Vec<IpCidr>,
+
+    /// URL of an authorization webhook consulted on each authenticated request after the
+    /// built-in checks passed, which implements additional access policy.
+    ///
+    /// The webhook receives the subject, operation and resource as JSON and must respond with
+    /// `{"allow":true}` or `{"allow":false}`. Requests are rejected with
+    /// `503 Service Unavailable` if the webhook cannot be reached.
+    #[arg(long, value_name = "URL")]
+    authz_webhook: Option<Url>,
+
+    /// Duration in seconds for which decisions of `--authz-webhook` are cached, `0` disables
+    /// caching.
+    #[arg(long, value_name = "SECONDS", default_value_t = DEFAULT_AUTHZ_CACHE_TTL.as_secs(), requires = "authz_webhook")]
+    authz_cache_ttl: u64,
+
+    /// Exclude requests to the given path, e.g. `/health`, from access logging.
+    ///
+    /// May be specified multiple times. Paths must match exactly.
+    #[arg(long = "log-exclude-path", value_name = "PATH")]
+    log_exclude_paths: Vec<String>,
+
+    /// Fraction of connections, between 0 and 1, which are logged when received. Failures to
+    /// handle connections are logged regardless.
+    #[arg(
+        long,
+        value_name = "RATE",
+        default_value_t = 1.0,
+        value_parser = |s: &str| -> Result<f64, String> {
+            match s.parse::<f64>() {
+                Ok(rate) if (0.0..=1.0).contains(&rate) => Ok(rate),
+                Ok(_) => Err("must be between 0 and 1".into()),
+                Err(e) => Err(e.to_string()),
+            }
+        }
+    )]
+    log_sample_rate: f64,
+
+    /// Add a `Server-Timing` header to responses, which breaks down the time spent on
+    /// authentication, store lookups and body transfers.
+    #[arg(long)]
+    server_timing: bool,
+
+    /// Value of the `Server` header sent in all responses.
+    #[arg(long, value_name = "VALUE", default_value = SERVER_HEADER)]
+    server_header: String,
+
+    /// Do not send a `Server` header, e.g. to not disclose the server version.
+    #[arg(long, conflicts_with = "server_header")]
+    no_server_header: bool,
+
+    /// Send a `Strict-Transport-Security` header in all responses, instructing clients to only
+    /// connect using HTTPS.
+    #[arg(long)]
+    hsts: bool,
+
+    /// Duration in seconds, for which clients only connect using HTTPS if `--hsts` is set.
+    #[arg(long, value_name = "SECONDS", default_value_t = DEFAULT_HSTS_MAX_AGE.as_secs(), requires = "hsts")]
+    hsts_max_age: u64,
+
+    /// Apply the HSTS policy to all subdomains as well.
+    #[arg(long, requires = "hsts")]
+    hsts_include_subdomains: bool,
+
+    /// Consent to inclusion in browsers' HSTS preload lists, which requires
+    /// `--hsts-include-subdomains` and a `--hsts-max-age` of at least a year.
+    #[arg(long, requires = "hsts_include_subdomains")]
+    hsts_preload: bool,
+
+    /// Value of the `Cache-Control` header sent in responses containing tree nodes, which are
+    /// immutable, or an empty value to send none.
+    ///
+    /// `public` allows shared caches, e.g. CDNs, to store responses to authenticated requests,
+    /// so `private` should be used if private repositories are served through shared caches.
+    #[arg(long, value_name = "VALUE", default_value = DEFAULT_CONTENT_CACHE_CONTROL)]
+    content_cache_control: String,
+
+    /// Value of the `Cache-Control` header sent in responses containing tags or tag listings,
+    /// which may change, or an empty value to send none.
+    #[arg(long, value_name = "VALUE", default_value = DEFAULT_TAG_CACHE_CONTROL)]
+    tag_cache_control: String,
+
+    /// Redirect requests to paths containing duplicate or trailing slashes to their canonical
+    /// form with `308 Permanent Redirect` instead of handling them as if they were sent there.
+    #[arg(long)]
+    strict_paths: bool,
+
+    /// Serve the server metrics unauthenticated at `/metrics`, in the OpenMetrics text format
+    /// if requested by the `Accept` header and in the Prometheus text format otherwise.
+    #[arg(long)]
+    metrics_endpoint: bool,
+
+    /// Do not log the startup banner.
+    #[arg(long)]
+    quiet: bool,
+
+    #[command(flatten)]
+    storage: StoreArgs,
+
+    #[command(flatten)]
+    tls: TlsArgs,
+
+    #[command(flatten)]
+    oidc: OidcArgs,
+
+    #[command(flatten)]
+    limits: LimitArgs,
+
+    #[command(flatten)]
+    mirror: MirrorArgs,
+}
+
+/// Options selecting the store of the `serve` command and how it is maintained.
+#[derive(clap::Args, Debug)]
+#[command(next_help_heading = "Store options")]
+struct StoreArgs {
     /// Path to the Drawbridge store.
+    #[arg(
+        long,
+        required_unless_present = "store_url",
+        conflicts_with = "store_url"
+    )]
+    store: Option<PathBuf>,
+
+    /// URL of the Drawbridge store, which selects its storage backend, as an alternative to
+    /// `--store`.
+    ///
+    /// Supported are local directories, e.g. `file:///var/lib/drawbridge`, and buckets of
+    /// S3-compatible object stores, e.g. `s3://bucket/prefix`. Upload sessions and idempotency
+    /// records of object stores are kept in a local temporary directory, such that they are
+    /// neither shared between instances nor retained across restarts, and orphaned files are
+    /// only removed from local directories.
+    ///
+    /// Files written to object stores are spooled to the local temporary directory and
+    /// uploaded using a single request, which limits their size to 5 GiB on AWS S3.
+    #[arg(long, value_name = "URL")]
+    store_url: Option<StoreUrl>,
+
+    /// Endpoint of the S3-compatible object store, e.g. `http://localhost:9000`, which is
+    /// addressed using path-style requests. Defaults to the AWS endpoint of `--s3-region`.
+    #[arg(long, value_name = "URL", conflicts_with = "store")]
+    s3_endpoint: Option<Url>,
+
+    /// Region of the S3 bucket used to sign requests.
+    #[arg(long, value_name = "REGION", default_value = DEFAULT_S3_REGION)]
+    s3_region: String,
+
+    /// Access key ID used to sign requests to the S3 bucket. Defaults to the
+    /// `AWS_ACCESS_KEY_ID` environment variable.
+    ///
+    /// Requests are sent unsigned if no credentials are configured. A session token is read
+    /// from the `AWS_SESSION_TOKEN` environment variable, if set.
+    #[arg(long, value_name = "ID", conflicts_with = "store")]
+    s3_access_key_id: Option<String>,
+
+    /// Secret access key used to sign requests to the S3 bucket. Defaults to the
+    /// `AWS_SECRET_ACCESS_KEY` environment variable.
+    #[arg(long, value_name = "SECRET", conflicts_with = "store")]
+    s3_secret_access_key: Option<String>,
+
+    /// Directory caching files of tags read from `--store-url`, e.g. to speed up reads from an
+    /// object store. Files in the directory left over by a previous run are removed on startup.
+    ///
+    /// Since tags are immutable, cached files are served without reading them from the store.
+    /// Tags removed by other servers sharing the store remain served until evicted from the
+    /// cache.
+    #[arg(long, value_name = "PATH", conflicts_with = "store")]
+    cache_dir: Option<PathBuf>,
+
+    /// Maximum total size in bytes of the files held by `--cache-dir`, beyond which the least
+    /// recently used files are evicted.
+    #[arg(
+        long,
+        value_name = "BYTES",
+        default_value_t = DEFAULT_CACHE_MAX_BYTES,
+        value_parser = clap::value_parser!(u64).range(1..),
+        requires = "cache_dir"
+    )]
+    cache_max_bytes: u64,
+
+    /// Number of consecutive failed or timed out operations on an object store, after which all
+    /// requests are rejected with `503 Service Unavailable` for `--store-circuit-cooldown`.
+    ///
+    /// A single operation then probes whether the store recovered. `0` disables rejecting
+    /// requests. The state of the circuit breaker is exposed in the metrics.
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = DEFAULT_STORE_CIRCUIT_THRESHOLD,
+        conflicts_with = "store"
+    )]
+    store_circuit_threshold: usize,
+
+    /// Seconds, for which requests are rejected once `--store-circuit-threshold` is reached.
+    #[arg(
+        long,
+        value_name = "SECONDS",
+        default_value_t = DEFAULT_STORE_CIRCUIT_COOLDOWN.as_secs(),
+        value_parser = clap::value_parser!(u64).range(1..),
+        conflicts_with = "store"
+    )]
+    store_circuit_cooldown: u64,
+
+    /// Verify contents against their digest before serving them and repair ones, which do not
+    /// match, from `--replica-store`.
+    ///
+    /// Reads fail if the replica stores no matching contents either. Repairs are logged and
+    /// counted in the metrics.
+    #[arg(long, requires = "replica_store")]
+    read_repair: bool,
+
+    /// URL of a replica of the store used by `--read-repair`, e.g. `s3://bucket/prefix`.
+    ///
+    /// Object stores are accessed using the `--s3-*` options.
+    #[arg(long, value_name = "URL", requires = "read_repair")]
+    replica_store: Option<StoreUrl>,
+
+    /// Reject all requests, which could modify the store.
+    ///
+    /// Read-only mode is enabled automatically if the store resides on a read-only filesystem.
     #[arg(long)]
-    store: PathBuf,
+    read_only: bool,
 
-    /// Path to PEM-encoded server certificate.
+    /// Fail to start if the store resides on a read-only filesystem,
+    /// instead of enabling read-only mode.
     #[arg(long)]
-    cert: PathBuf,
+    require_writable_store: bool,
 
-    /// Path to PEM-encoded server certificate key.
+    /// Migrate the store in place on startup if it uses an older layout version, instead of
+    /// failing to start. Stores should be backed up using `export` before migrating.
     #[arg(long)]
-    key: PathBuf,
+    allow_store_migration: bool,
+
+    /// Age in seconds, after which temporary files and partially written objects, e.g. ones
+    /// left over by a crash during an upload, are removed from the store on startup and by
+    /// `--maintenance-interval`.
+    #[arg(long, value_name = "SECONDS", default_value_t = DEFAULT_ORPHAN_MAX_AGE.as_secs())]
+    orphan_max_age: u64,
+
+    /// Do not remove temporary files and partially written objects from the store on startup.
+    #[arg(long, conflicts_with = "orphan_max_age")]
+    no_orphan_cleanup: bool,
+
+    /// Number of store directories scanned concurrently for orphaned files on startup.
+    ///
+    /// The scan completes before the server starts accepting connections and its progress is
+    /// logged periodically.
+    #[arg(long, value_name = "N", default_value_t = DEFAULT_STARTUP_SCAN_THREADS)]
+    startup_scan_threads: usize,
+
+    /// Run maintenance in the background every given number of seconds while serving.
+    ///
+    /// Each pass removes temporary files and partially written objects older than
+    /// `--orphan-max-age`, unless `--no-orphan-cleanup` is set, as well as expired upload
+    /// sessions, idempotency records and cached authorization decisions. Objects being written
+    /// are never removed. Results of the last pass are exposed in the metrics.
+    #[arg(long, value_name = "SECONDS", value_parser = clap::value_parser!(u64).range(1..))]
+    maintenance_interval: Option<u64>,
+
+    /// Also collect garbage in each maintenance pass, i.e. remove tree nodes, which are not
+    /// referenced by their parent directory, as done by the `gc` command.
+    #[arg(long, requires = "maintenance_interval")]
+    maintenance_gc: bool,
+
+    /// Probe the store periodically and handle persistent failures using the given policy.
+    ///
+    /// Supported policies are `serve-503`, which rejects all requests with
+    /// `503 Service Unavailable` until the store recovers, and `exit`, which terminates the
+    /// server, such that it can be restarted.
+    #[arg(
+        long,
+        value_parser = |s: &str| s.parse::<StoreFailurePolicy>().map_err(|e| e.to_string())
+    )]
+    on_store_failure: Option<StoreFailurePolicy>,
+
+    /// Interval in seconds between store probes if `--on-store-failure` is set.
+    #[arg(long, default_value_t = 10)]
+    store_probe_interval: u64,
+}
+
+/// TLS options of the `serve` command.
+#[derive(clap::Args, Debug)]
+#[command(next_help_heading = "TLS options")]
+struct TlsArgs {
+    /// Path to PEM-encoded server certificate.
+    ///
+    /// May be specified multiple times along with `--key` to serve multiple certificates, which
+    /// are paired with keys in the order given. Clients are presented the first certificate
+    /// valid for the server name they request using SNI, or the first one if none is.
+    ///
+    /// The server certificates, keys and trusted CA certificate are reloaded on `SIGHUP`.
+    #[arg(long, required = true)]
+    cert: Vec<PathBuf>,
+
+    /// Path to PEM-encoded server certificate key.
+    ///
+    /// May be specified multiple times, once for each `--cert`.
+    #[arg(long, required = true)]
+    key: Vec<PathBuf>,
 
     /// Path to PEM-encoded trusted CA certificate.
     ///
@@ -76,6 +623,56 @@ struct Args {
     #[arg(long)]
     ca: PathBuf,
 
+    /// Number of TLS sessions cached in memory for resumption, 0 to disable the cache.
+    #[arg(long, default_value_t = DEFAULT_TLS_SESSION_CACHE_SIZE)]
+    tls_session_cache_size: usize,
+
+    /// Lifetime of TLS session tickets in seconds.
+    ///
+    /// Ticket encryption keys are rotated every half of the lifetime.
+    #[arg(long, value_name = "SECONDS", default_value_t = DEFAULT_TLS_TICKET_LIFETIME.as_secs())]
+    tls_ticket_lifetime: u64,
+
+    /// Do not issue TLS session tickets, e.g. to not weaken forward secrecy.
+    #[arg(long, conflicts_with = "tls_ticket_lifetime")]
+    no_tls_tickets: bool,
+
+    /// Maximum total size in bytes of client certificate chains.
+    ///
+    /// Handshakes of clients presenting larger chains fail.
+    #[arg(long, value_name = "BYTES", default_value_t = DEFAULT_MAX_CLIENT_CERT_CHAIN)]
+    max_client_cert_chain: usize,
+
+    /// Comma-separated ALPN protocol IDs to advertise in order of preference, e.g.
+    /// `http/1.1,http/1.0`, which are used verbatim.
+    ///
+    /// At least one protocol served by Drawbridge, i.e. `http/1.1` or `http/1.0`, must be
+    /// listed. By default, no protocol is negotiated.
+    #[arg(long, value_name = "PROTOCOLS", value_delimiter = ',')]
+    tls_alpn: Vec<String>,
+
+    /// Path to a list of SHA-256 fingerprints of client certificates granted access.
+    ///
+    /// If specified, clients presenting a certificate signed by the trusted CA,
+    /// which is not contained in this list, are denied access.
+    /// The list contains one hex-encoded fingerprint per line and is reloaded on `SIGHUP`.
+    #[arg(long)]
+    client_cert_allowlist: Option<PathBuf>,
+
+    /// Path to a mapping of SHA-256 fingerprints of client certificates to the namespaces they
+    /// may publish tags to without an OpenID Connect token.
+    ///
+    /// Each line contains a hex-encoded fingerprint followed by whitespace-separated user names,
+    /// e.g. `01:23:...:ef ci releases`. Certificates, which are not listed, are only granted read
+    /// access. The mapping is reloaded on `SIGHUP`.
+    #[arg(long, value_name = "PATH")]
+    cert_writers: Option<PathBuf>,
+}
+
+/// OpenID Connect options of the `serve` command.
+#[derive(clap::Args, Debug)]
+#[command(next_help_heading = "OpenID Connect options")]
+struct OidcArgs {
     /// OpenID Connect issuer URL.
     #[arg(long)]
     oidc_issuer: Url,
@@ -83,72 +680,1889 @@ struct Args {
     /// OpenID Connect audience.
     #[arg(long)]
     oidc_audience: String,
+
+    /// Fail to start if the OpenID Connect provider is not configured correctly, e.g. if it
+    /// does not support the scopes Drawbridge requires, instead of only discovering it.
+    #[arg(long)]
+    oidc_strict_startup: bool,
+
+    /// Tolerance in seconds for differences between the clocks of the server and the OpenID
+    /// Connect provider, within which tokens are accepted past their `exp` or before their
+    /// `nbf` claims.
+    ///
+    /// Tolerances above five minutes are accepted, but warned against.
+    #[arg(long, value_name = "SECONDS", default_value_t = DEFAULT_OIDC_CLOCK_SKEW.as_secs())]
+    oidc_clock_skew: u64,
 }
 
-fn open_buffered(p: impl AsRef<Path>) -> io::Result<impl BufRead> {
-    File::open(p).map(BufReader::new)
+/// Timeouts and resource limits of the `serve` command.
+#[derive(clap::Args, Debug)]
+#[command(next_help_heading = "Limit options")]
+struct LimitArgs {
+    /// Maximum request deadline in seconds clients may request using the `grpc-timeout` header.
+    ///
+    /// Requests exceeding their deadline are aborted with `504 Gateway Timeout`.
+    #[arg(long, default_value_t = DEFAULT_MAX_REQUEST_DEADLINE.as_secs())]
+    max_request_deadline: u64,
+
+    /// Timeout in seconds of requests uploading file contents, `0` means unlimited.
+    ///
+    /// Requests exceeding their timeout are aborted with `408 Request Timeout`, unless the client
+    /// requested a shorter deadline.
+    #[arg(long, value_name = "SECONDS", default_value_t = 0)]
+    upload_timeout: u64,
+
+    /// Timeout in seconds of requests downloading file contents including the transfer of the
+    /// response body, `0` means unlimited.
+    #[arg(long, value_name = "SECONDS", default_value_t = 0)]
+    download_timeout: u64,
+
+    /// Timeout in seconds of all other requests, e.g. to users, repositories and tags, `0` means
+    /// unlimited.
+    #[arg(long, value_name = "SECONDS", default_value_t = 0)]
+    metadata_timeout: u64,
+
+    /// Maximum number of tags per repository, `0` means unlimited.
+    ///
+    /// Creating a tag in a repository, which reached the limit, fails with `409 Conflict`.
+    /// Since tags are immutable, uploads of an already existing tag do not count against the limit.
+    #[arg(long, default_value_t = 0)]
+    max_tags_per_repo: usize,
+
+    /// Maximum number of reading requests handled concurrently, `0` means unlimited.
+    ///
+    /// Reading (`GET`, `HEAD` and `OPTIONS`) and writing requests have separate budgets,
+    /// such that slow writes cannot starve reads and vice versa.
+    #[arg(long, default_value_t = 0)]
+    read_slots: usize,
+
+    /// Maximum number of writing requests handled concurrently, `0` means unlimited.
+    #[arg(long, default_value_t = 0)]
+    write_slots: usize,
+
+    /// Maximum number of bytes transferred in request and response bodies across all
+    /// connections at once, `0` means unlimited.
+    ///
+    /// Requests exceeding the budget are rejected with `503 Service Unavailable`.
+    #[arg(long, value_name = "BYTES", default_value_t = 0)]
+    max_inflight_bytes: u64,
+
+    /// Percentage of `--read-slots`, `--write-slots` and `--max-inflight-bytes`, at and above
+    /// which a warning is logged and requests are counted as admitted near the limit, `0`
+    /// disables the warnings.
+    #[arg(
+        long,
+        value_name = "PERCENT",
+        default_value_t = DEFAULT_LIMIT_WARNING_PERCENT,
+        value_parser = clap::value_parser!(u8).range(0..=100)
+    )]
+    limit_warning_percent: u8,
+
+    /// Maximum number of bytes per second sent on each connection, `0` means unlimited.
+    #[arg(long, default_value_t = 0)]
+    max_download_bps: u64,
+
+    /// Duration in seconds after which idle connections are closed, `0` means they are kept
+    /// open until the client closes them.
+    #[arg(long, value_name = "SECONDS", default_value_t = 0)]
+    keep_alive_timeout: u64,
+
+    /// Maximum number of requests served on a single connection, `0` means unlimited.
+    ///
+    /// The response to the last request carries a `Connection: close` header, such that
+    /// clients reconnect, e.g. to a different instance behind a load balancer.
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    max_requests_per_connection: u64,
+
+    /// Maximum number of connections served concurrently, `0` means unlimited.
+    ///
+    /// Further connections wait in the listen backlog until an established one is closed.
+    /// Plaintext connections redirected by `--http-redirect-addr` count towards the limit.
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    max_connections: usize,
+
+    /// Duration in seconds a TLS handshake may take before the connection is dropped, `0`
+    /// disables the timeout.
+    #[arg(long, value_name = "SECONDS", default_value_t = DEFAULT_HANDSHAKE_TIMEOUT.as_secs())]
+    handshake_timeout: u64,
+
+    /// Duration in seconds connections are drained for on `SIGTERM` or `SIGINT`.
+    ///
+    /// No more connections are accepted once either signal is received and established ones
+    /// are closed once the requests in flight on them completed, such that uploads are not cut
+    /// off by restarts. Connections still active after the timeout are dropped. Receiving
+    /// another signal while draining stops the server immediately.
+    #[arg(long, value_name = "SECONDS", default_value_t = DEFAULT_SHUTDOWN_TIMEOUT.as_secs())]
+    shutdown_timeout: u64,
+
+    /// Size of the buffer used to read each response body from the store.
+    ///
+    /// Objects larger than the buffer are streamed in chunks of at most this size, such that
+    /// memory used by responses stays proportional to the number of concurrent responses.
+    #[arg(long, default_value_t = DEFAULT_RESPONSE_BUFFER_BYTES)]
+    response_buffer_bytes: usize,
+
+    /// Duration in seconds after which unused resumable upload sessions expire.
+    #[arg(long, value_name = "SECONDS", default_value_t = DEFAULT_UPLOAD_SESSION_TTL.as_secs())]
+    upload_session_ttl: u64,
+
+    /// Maximum number of open resumable upload sessions per repository, `0` means unlimited.
+    ///
+    /// Initiating a session in a repository, which reached the limit, fails with `409 Conflict`.
+    #[arg(long, default_value_t = 0)]
+    max_upload_sessions_per_repo: usize,
+
+    /// Maximum number of bytes a resumable upload session may receive, `0` means unlimited.
+    ///
+    /// Chunks, which would exceed the limit, are rejected with `413 Payload Too Large`.
+    #[arg(long, value_name = "BYTES", default_value_t = 0)]
+    max_upload_session_size: u64,
+
+    /// Maximum number of requests per second each namespace, i.e. user, may send using an
+    /// OpenID Connect token, `0` means unlimited.
+    ///
+    /// Namespaces may send bursts of up to this number of requests. Requests exceeding the
+    /// limit are rejected with `429 Too Many Requests` and a `Retry-After` header.
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    namespace_rate_limit: u32,
+
+    /// Overrides `--namespace-rate-limit` for a single namespace, e.g. `ci=100`, may be
+    /// repeated. A limit of `0` means unlimited.
+    #[arg(
+        long,
+        value_name = "NAMESPACE=N",
+        value_parser = |s: &str| -> Result<(UserName, u32), String> {
+            let (name, limit) = s.split_once('=').ok_or("expected `NAMESPACE=N`")?;
+            Ok((
+                name.parse().map_err(|e: anyhow::Error| e.to_string())?,
+                limit.parse().map_err(|e: std::num::ParseIntError| e.to_string())?,
+            ))
+        }
+    )]
+    namespace_rate_limit_override: Vec<(UserName, u32)>,
 }
 
-#[async_std::main]
-async fn main() -> anyhow::Result<()> {
+/// Options of the `serve` command mirroring an upstream server.
+#[derive(clap::Args, Debug)]
+#[command(next_help_heading = "Mirror options")]
+struct MirrorArgs {
+    /// Base URL of an upstream Drawbridge server to mirror, e.g. `https://store.example.com`.
+    ///
+    /// Tags requested by clients, but missing in the store, are fetched from the upstream
+    /// along with their trees, which are verified against the digests listed by the tag entry.
+    /// Requests, which could modify the store, are rejected with `405 Method Not Allowed`, such
+    /// that the store never diverges from the upstream.
+    #[arg(long, value_name = "URL", conflicts_with = "read_only")]
+    mirror_url: Option<Url>,
+
+    /// Path to a file containing the OpenID Connect token sent to `--mirror-url`, which is read
+    /// again for each request, such that it may be refreshed externally.
+    #[arg(long, value_name = "PATH", requires = "mirror_url")]
+    mirror_token_file: Option<PathBuf>,
+
+    /// Path to the PEM-encoded client certificate chain presented to `--mirror-url`.
+    #[arg(long, value_name = "PATH", requires_all = ["mirror_url", "mirror_key"])]
+    mirror_cert: Option<PathBuf>,
+
+    /// Path to the PEM-encoded key of `--mirror-cert`.
+    #[arg(long, value_name = "PATH", requires = "mirror_cert")]
+    mirror_key: Option<PathBuf>,
+
+    /// Path to PEM-encoded CA certificates trusted to sign the certificate of `--mirror-url`
+    /// instead of the Mozilla root certificates.
+    #[arg(long, value_name = "PATH", requires = "mirror_url")]
+    mirror_ca: Option<PathBuf>,
+
+    /// Duration in seconds, after which tags fetched from `--mirror-url` are revalidated
+    /// against it, such that tags replaced or removed upstream are replaced or removed locally.
+    #[arg(long, value_name = "SECONDS", default_value_t = DEFAULT_MIRROR_TTL.as_secs(), requires = "mirror_url")]
+    mirror_ttl: u64,
+}
+
+/// Management commands operating on the store of a stopped Drawbridge server.
+#[derive(Subcommand, Debug)]
+enum ManageCommand {
+    /// Export the store into a tar archive.
+    Export {
+        /// Path to the Drawbridge store.
+        #[arg(long)]
+        store: PathBuf,
+
+        /// Path to write the archive to, `-` for standard output.
+        #[arg(long)]
+        out: PathBuf,
+    },
+
+    /// Import a tar archive produced by `export` into the store.
+    ///
+    /// Contents of each object are verified against their digests. Objects, which are already
+    /// stored with different contents, are not overwritten, but cause the import to fail.
+    Import {
+        /// Path to the Drawbridge store, which is created if it does not exist.
+        #[arg(long)]
+        store: PathBuf,
+
+        /// Path to read the archive from, `-` for standard input.
+        #[arg(long = "in", value_name = "IN")]
+        input: PathBuf,
+    },
+
+    /// Report the number of users, repositories, tags and objects in the store, the total
+    /// bytes used and the largest namespaces and objects.
+    ///
+    /// Only directory entries and file metadata are read, such that large stores are walked
+    /// quickly.
+    Stats {
+        /// Path to the Drawbridge store.
+        #[arg(long)]
+        store: PathBuf,
+
+        /// Number of the largest namespaces and objects to list.
+        #[arg(long, value_name = "N", default_value_t = 10)]
+        top: usize,
+
+        /// Write the statistics as JSON.
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Remove tree nodes, which are not referenced by their parent directory, e.g. ones left
+    /// over by uploads of trees that changed midway, and leftovers of interrupted deletions.
+    ///
+    /// Nodes referenced by tags are never removed, such that this may run while a server
+    /// serves the store.
+    Gc {
+        /// Path to the Drawbridge store.
+        #[arg(long)]
+        store: PathBuf,
+    },
+}
+
+/// Returns `args` with the default command inserted, unless a command is given, such that
+/// invocations passing `serve` options only keep working.
+fn with_default_command(mut args: Vec<String>) -> Vec<String> {
+    let explicit = args.get(1).is_some_and(|arg| {
+        !arg.starts_with('-') || ["-h", "--help", "-V", "--version"].contains(&arg.as_str())
+    });
+    if !explicit {
+        args.insert(args.len().min(1), Command::DEFAULT.into());
+    }
+    args
+}
+
+fn init_tracing(writer: impl for<'w> MakeWriter<'w> + Send + Sync + 'static) {
+    let fmt = tracing_subscriber::fmt::fmt()
+        .with_env_filter(EnvFilter::from_default_env())
+        .with_writer(writer);
     if std::env::var("RUST_LOG_JSON").is_ok() {
-        tracing_subscriber::fmt::fmt()
-            .json()
-            .with_env_filter(tracing_subscriber::EnvFilter::from_default_env())
-            .init();
+        fmt.json().init();
     } else {
-        tracing_subscriber::fmt::init();
+        fmt.init();
     }
+}
 
-    let Args {
-        addr,
-        store,
-        cert,
-        key,
-        ca,
-        oidc_audience,
-        oidc_issuer,
-    } = args::<Toml>(prefix_char_filter::<'@'>)
-        .context("Failed to parse config")
-        .map(Args::parse_from)?;
-
-    let cert = open_buffered(cert).context("Failed to open server certificate file")?;
-    let key = open_buffered(key).context("Failed to open server key file")?;
+fn is_stdio(path: &Path) -> bool {
+    path == Path::new("-")
+}
+
+/// Exit code of the process, by which supervisors may tell failures apart, e.g. to retry on
+/// bind failures, but alert on configuration errors. The codes are listed in [EXIT_CODES].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Exit {
+    /// Graceful shutdown or successful completion of a command.
+    Success = 0,
+    /// Failure not covered by another code, e.g. of a `manage` command.
+    Failure = 1,
+    // Invalid command line arguments exit with `2`, as reported by the argument parser.
+    /// Invalid configuration, including unusable TLS certificates and keys.
+    Config = 3,
+    /// Failure to bind to a listening address.
+    Bind = 4,
+    /// Store failure on startup or, with `--on-store-failure exit`, while serving.
+    Store = 5,
+    /// OpenID Connect provider failure on startup.
+    Oidc = 6,
+}
+
+impl From<Exit> for ExitCode {
+    fn from(exit: Exit) -> Self {
+        Self::from(exit as u8)
+    }
+}
+
+/// Description of the [Exit] codes shown in the help of the `serve` command.
+const EXIT_CODES: &str = "\
+Exit codes:
+  0  Graceful shutdown
+  1  Unclassified failure
+  2  Invalid command line arguments
+  3  Invalid configuration, including unusable TLS certificates and keys
+  4  Failure to bind to a listening address
+  5  Store failure on startup or, with `--on-store-failure exit`, while serving
+  6  OIDC provider failure on startup";
+
+/// Failure terminating the process with an [Exit] code.
+#[derive(Debug)]
+struct Failed {
+    exit: Exit,
+    error: anyhow::Error,
+}
+
+impl Failed {
+    /// Classifies a failure to build or run an [App] by its [FailureClass], which is missing
+    /// for invalid configurations.
+    fn of_app(error: anyhow::Error) -> Self {
+        let exit = match error.downcast_ref::<FailureClass>() {
+            Some(FailureClass::Store) => Exit::Store,
+            Some(FailureClass::Oidc) => Exit::Oidc,
+            None => Exit::Config,
+        };
+        Self { exit, error }
+    }
+}
+
+/// Extension trait classifying failures by the [Exit] code they terminate the process with.
+trait ExitContext<T> {
+    fn exit(self, exit: Exit) -> Result<T, Failed>;
+}
+
+impl<T> ExitContext<T> for anyhow::Result<T> {
+    fn exit(self, exit: Exit) -> Result<T, Failed> {
+        self.map_err(|error| Failed { exit, error })
+    }
+}
+
+async fn manage(command: ManageCommand) -> anyhow::Result<()> {
+    match command {
+        ManageCommand::Export { store, out } => {
+            let summary = if is_stdio(&out) {
+                export_store(&store, AsyncBufWriter::new(async_std::io::stdout())).await
+            } else {
+                let out = async_std::fs::File::create(&out)
+                    .await
+                    .with_context(|| format!("Failed to create `{}`", out.display()))?;
+                export_store(&store, AsyncBufWriter::new(out)).await
+            }
+            .context("Failed to export store")?;
+            info!(
+                target: "main",
+                objects = summary.objects,
+                bytes = summary.bytes,
+                "exported store"
+            );
+        }
+        ManageCommand::Import { store, input } => {
+            let summary = if is_stdio(&input) {
+                import_store(&store, AsyncBufReader::new(async_std::io::stdin())).await
+            } else {
+                let input = async_std::fs::File::open(&input)
+                    .await
+                    .with_context(|| format!("Failed to open `{}`", input.display()))?;
+                import_store(&store, AsyncBufReader::new(input)).await
+            }
+            .context("Failed to import store")?;
+            info!(
+                target: "main",
+                created = summary.created,
+                existing = summary.existing,
+                "imported store"
+            );
+        }
+        ManageCommand::Stats { store, top, json } => {
+            let stats = store_stats(&store, top)
+                .await
+                .context("Failed to compute store statistics")?;
+            if json {
+                serde_json::to_writer_pretty(io::stdout(), &stats)
+                    .context("Failed to write store statistics")?;
+                println!();
+            } else {
+                print_stats(&stats);
+            }
+        }
+        ManageCommand::Gc { store } => {
+            let summary = Store::open(&store.into())
+                .await
+                .context("Failed to open store")?
+                .collect_garbage()
+                .await
+                .context("Failed to collect garbage")?;
+            info!(
+                target: "main",
+                nodes = summary.nodes,
+                remnants = summary.remnants,
+                "collected garbage"
+            );
+        }
+    }
+    Ok(())
+}
+
+fn print_stats(
+    StoreStats {
+        users,
+        repositories,
+        tags,
+        objects,
+        bytes,
+        namespaces,
+        largest,
+    }: &StoreStats,
+) {
+    println!("users:        {users}");
+    println!("repositories: {repositories}");
+    println!("tags:         {tags}");
+    println!("objects:      {objects}");
+    println!("bytes:        {bytes}");
+    if !namespaces.is_empty() {
+        println!();
+        println!("largest namespaces:");
+        for ns in namespaces {
+            println!(
+                "  {:>12} bytes  {:>8} objects  {:>6} tags  {:>4} repositories  {}",
+                ns.bytes, ns.objects, ns.tags, ns.repositories, ns.name
+            );
+        }
+    }
+    if !largest.is_empty() {
+        println!();
+        println!("largest objects:");
+        for object in largest {
+            println!("  {:>12} bytes  {}", object.bytes, object.path);
+        }
+    }
+}
+
+fn open_buffered(p: impl AsRef<Path>) -> io::Result<impl BufRead> {
+    File::open(p).map(BufReader::new)
+}
+
+fn read_tls_config(
+    certs: &[PathBuf],
+    keys: &[PathBuf],
+    ca: impl AsRef<Path>,
+    options: TlsOptions,
+) -> anyhow::Result<TlsConfig> {
+    anyhow::ensure!(
+        certs.len() == keys.len(),
+        "Number of server certificates ({}) does not match number of keys ({})",
+        certs.len(),
+        keys.len()
+    );
+    let identities = certs
+        .iter()
+        .zip(keys)
+        .map(|(cert, key)| {
+            let cert = open_buffered(cert).with_context(|| {
+                format!(
+                    "Failed to open server certificate file `{}`",
+                    cert.display()
+                )
+            })?;
+            let key = open_buffered(key)
+                .with_context(|| format!("Failed to open server key file `{}`", key.display()))?;
+            Ok((cert, key))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
     let ca = open_buffered(ca).context("Failed to open CA certificate file")?;
-    let tls = TlsConfig::read(cert, key, ca).context("Failed to construct server TLS config")?;
+    TlsConfig::read_with_identities(identities, ca, options)
+        .context("Failed to construct server TLS config")
+}
 
-    let app = App::new(
-        store,
-        tls,
+fn read_manifest_schema(p: impl AsRef<Path>) -> anyhow::Result<ManifestSchema> {
+    std::fs::read_to_string(p)
+        .context("Failed to read manifest schema file")?
+        .parse()
+        .context("Failed to parse manifest schema")
+}
+
+fn read_mirror_config(
+    url: Url,
+    token_file: Option<PathBuf>,
+    cert: Option<&Path>,
+    key: Option<&Path>,
+    ca: Option<&Path>,
+    ttl: Duration,
+) -> anyhow::Result<MirrorConfig> {
+    let config = MirrorConfig::new(url).ttl(ttl);
+    let config = match token_file {
+        Some(path) => config.token_file(path),
+        None => config,
+    };
+    let config = match (cert, key) {
+        (Some(cert), Some(key)) => {
+            let certs = open_buffered(cert).context("Failed to open mirror certificate file")?;
+            let key = open_buffered(key).context("Failed to open mirror key file")?;
+            config
+                .read_credentials(certs, key)
+                .context("Failed to read mirror credentials")?
+        }
+        _ => config,
+    };
+    match ca {
+        Some(ca) => {
+            let cas = open_buffered(ca).context("Failed to open mirror CA file")?;
+            config
+                .read_roots(cas)
+                .context("Failed to read mirror CA certificates")
+        }
+        None => Ok(config),
+    }
+}
+
+fn read_client_cert_allowlist(p: impl AsRef<Path>) -> anyhow::Result<CertificateAllowlist> {
+    let rd = open_buffered(p).context("Failed to open client certificate allowlist file")?;
+    CertificateAllowlist::read(rd).context("Failed to read client certificate allowlist")
+}
+
+fn read_cert_writers(p: impl AsRef<Path>) -> anyhow::Result<CertificateWriters> {
+    let rd = open_buffered(p).context("Failed to open certificate writers file")?;
+    CertificateWriters::read(rd).context("Failed to read certificate writers")
+}
+
+/// Returns the S3 credentials given on the command line or, if missing, in the standard AWS
+/// environment variables, or `None` if neither configures any.
+fn s3_credentials(
+    access_key_id: Option<String>,
+    secret_access_key: Option<String>,
+) -> anyhow::Result<Option<S3Credentials>> {
+    let env = |name| std::env::var(name).ok().filter(|value| !value.is_empty());
+    let access_key_id = access_key_id.or_else(|| env("AWS_ACCESS_KEY_ID"));
+    let secret_access_key = secret_access_key.or_else(|| env("AWS_SECRET_ACCESS_KEY"));
+    match (access_key_id, secret_access_key) {
+        (Some(id), Some(secret)) => {
+            let credentials = S3Credentials::new(id, secret);
+            Ok(Some(match env("AWS_SESSION_TOKEN") {
+                Some(token) => credentials.session_token(token),
+                None => credentials,
+            }))
+        }
+        (None, None) => Ok(None),
+        (Some(_), None) => anyhow::bail!("S3 access key ID is set, but no secret access key"),
+        (None, Some(_)) => anyhow::bail!("S3 secret access key is set, but no access key ID"),
+    }
+}
+
+/// Takes ownership of the inherited file descriptor `fd` and returns it as a [TcpListener],
+/// failing if it is not a listening TCP socket.
+#[cfg(unix)]
+#[allow(unsafe_code)]
+fn listener_from_fd(fd: RawFd) -> anyhow::Result<TcpListener> {
+    use std::os::unix::io::{FromRawFd, IntoRawFd};
+
+    use socket2::{Domain, Socket, Type};
+
+    if fd < 0 {
+        anyhow::bail!("Invalid file descriptor {fd}");
+    }
+    // SAFETY: `fd` was passed by the supervisor, which started the process, and nothing else in
+    // the process owns or closes it. Descriptors failing the checks below are released without
+    // being closed, such that ownership is only taken of adopted listeners.
+    let socket = unsafe { Socket::from_raw_fd(fd) };
+    let check = || -> anyhow::Result<()> {
+        let ty = socket
+            .r#type()
+            .with_context(|| format!("File descriptor {fd} is not a valid socket"))?;
+        let domain = socket
+            .domain()
+            .with_context(|| format!("Failed to query domain of socket {fd}"))?;
+        if ty != Type::STREAM || !(domain == Domain::IPV4 || domain == Domain::IPV6) {
+            anyhow::bail!("File descriptor {fd} is not a TCP socket");
+        }
+        // Other platforms do not support querying whether a socket is listening, in which
+        // case accepting connections fails instead.
+        #[cfg(any(
+            target_os = "android",
+            target_os = "freebsd",
+            target_os = "fuchsia",
+            target_os = "linux"
+        ))]
+        if !socket
+            .is_listener()
+            .with_context(|| format!("Failed to query whether socket {fd} is listening"))?
+        {
+            anyhow::bail!("Socket {fd} is not listening");
+        }
+        Ok(())
+    };
+    if let Err(e) = check() {
+        // Descriptors, which are not adopted, are left open.
+        _ = socket.into_raw_fd();
+        return Err(e);
+    }
+    Ok(std::net::TcpListener::from(socket).into())
+}
+
+impl ServeArgs {
+    /// Resolves relative paths against `--base-dir`, if set, and logs the resolved paths.
+    fn resolve_paths(&mut self) -> anyhow::Result<()> {
+        let Some(ref base_dir) = self.base_dir else {
+            return Ok(());
+        };
+        let base_dir = std::path::absolute(base_dir)
+            .with_context(|| format!("Failed to resolve `{}`", base_dir.display()))?;
+        let resolve = |path: &mut PathBuf| {
+            if path.is_relative() {
+                *path = base_dir.join(&*path);
+            }
+        };
+        self.storage.store.iter_mut().for_each(resolve);
+        self.storage.cache_dir.iter_mut().for_each(resolve);
+        self.tls.cert.iter_mut().for_each(resolve);
+        self.tls.key.iter_mut().for_each(resolve);
+        resolve(&mut self.tls.ca);
+        self.tls.client_cert_allowlist.iter_mut().for_each(resolve);
+        self.tls.cert_writers.iter_mut().for_each(resolve);
+        self.manifest_schema.iter_mut().for_each(resolve);
+        self.mirror.mirror_token_file.iter_mut().for_each(resolve);
+        self.mirror.mirror_cert.iter_mut().for_each(resolve);
+        self.mirror.mirror_key.iter_mut().for_each(resolve);
+        self.mirror.mirror_ca.iter_mut().for_each(resolve);
+        info!(
+            target: "main",
+            base_dir = %base_dir.display(),
+            store = ?self.storage.store,
+            cache_dir = ?self.storage.cache_dir,
+            cert = ?self.tls.cert,
+            key = ?self.tls.key,
+            ca = %self.tls.ca.display(),
+            client_cert_allowlist = ?self.tls.client_cert_allowlist,
+            cert_writers = ?self.tls.cert_writers,
+            manifest_schema = ?self.manifest_schema,
+            mirror_token_file = ?self.mirror.mirror_token_file,
+            mirror_cert = ?self.mirror.mirror_cert,
+            mirror_key = ?self.mirror.mirror_key,
+            mirror_ca = ?self.mirror.mirror_ca,
+            "resolved paths against base directory"
+        );
+        self.base_dir = Some(base_dir);
+        Ok(())
+    }
+}
+
+impl StoreArgs {
+    /// Returns the store selected by `--store` or `--store-url`, configuring object stores
+    /// using the `--s3-*` options.
+    fn url(&self) -> anyhow::Result<StoreUrl> {
+        match (&self.store, &self.store_url) {
+            (Some(path), _) => Ok(StoreUrl::File(path.clone())),
+            (None, Some(url)) => self.configure(url),
+            (None, None) => unreachable!("`--store` or `--store-url` is required"),
+        }
+    }
+
+    /// Configures `url` using the `--s3-*` options if it names an object store.
+    fn configure(&self, url: &StoreUrl) -> anyhow::Result<StoreUrl> {
+        let StoreUrl::S3(config) = url else {
+            return Ok(url.clone());
+        };
+        let config = config.clone().region(self.s3_region.clone());
+        let config = match self.s3_endpoint {
+            Some(ref endpoint) => config.endpoint(endpoint.clone()),
+            None => config,
+        };
+        match s3_credentials(
+            self.s3_access_key_id.clone(),
+            self.s3_secret_access_key.clone(),
+        )? {
+            Some(credentials) => Ok(StoreUrl::S3(config.credentials(credentials))),
+            None => Ok(StoreUrl::S3(config)),
+        }
+    }
+
+    fn apply(&self, app: Builder<StoreUrl>) -> anyhow::Result<Builder<StoreUrl>> {
+        let app = app
+            .read_only(self.read_only)
+            .require_writable_store(self.require_writable_store)
+            .allow_store_migration(self.allow_store_migration)
+            .orphan_max_age(
+                (!self.no_orphan_cleanup).then(|| Duration::from_secs(self.orphan_max_age)),
+            )
+            .startup_scan_threads(self.startup_scan_threads)
+            .maintenance_gc(self.maintenance_gc)
+            .store_circuit_threshold(self.store_circuit_threshold)
+            .store_circuit_cooldown(Duration::from_secs(self.store_circuit_cooldown));
+        let app = match self.cache_dir {
+            Some(ref dir) => app.cache_dir(dir).cache_max_bytes(self.cache_max_bytes),
+            None => app,
+        };
+        match self.replica_store {
+            Some(ref replica) if self.read_repair => Ok(app.read_repair(self.configure(replica)?)),
+            _ => Ok(app),
+        }
+    }
+}
+
+impl TlsArgs {
+    fn options(&self) -> TlsOptions {
+        TlsOptions {
+            sessions: TlsSessionConfig {
+                cache_size: self.tls_session_cache_size,
+                ticket_lifetime: (!self.no_tls_tickets)
+                    .then(|| Duration::from_secs(self.tls_ticket_lifetime)),
+            },
+            max_client_cert_chain: self.max_client_cert_chain,
+            alpn_protocols: self.tls_alpn.clone(),
+        }
+    }
+
+    fn read_config(&self) -> anyhow::Result<TlsConfig> {
+        read_tls_config(&self.cert, &self.key, &self.ca, self.options())
+    }
+
+    fn apply(&self, app: Builder<StoreUrl>) -> anyhow::Result<Builder<StoreUrl>> {
+        let app = match self.client_cert_allowlist {
+            Some(ref path) => app.client_cert_allowlist(read_client_cert_allowlist(path)?),
+            None => app,
+        };
+        match self.cert_writers {
+            Some(ref path) => Ok(app.client_cert_writers(read_cert_writers(path)?)),
+            None => Ok(app),
+        }
+    }
+
+    /// Reloads the TLS configuration, client certificate allowlist and certificate writers of
+    /// `app`, keeping the previous ones if reading them fails.
+    fn reload(&self, app: &App) {
+        match self.read_config() {
+            Ok(tls) => {
+                app.set_tls_config(tls);
+                info!(target: "main", "reloaded TLS configuration");
+            }
+            Err(e) => error!(target: "main", "failed to reload TLS configuration: {e:?}"),
+        }
+        if let Some(ref path) = self.client_cert_allowlist {
+            match read_client_cert_allowlist(path) {
+                Ok(allowlist) => {
+                    app.set_client_cert_allowlist(Some(allowlist));
+                    info!(target: "main", "reloaded client certificate allowlist");
+                }
+                Err(e) => {
+                    error!(target: "main", "failed to reload client certificate allowlist: {e:?}")
+                }
+            }
+        }
+        if let Some(ref path) = self.cert_writers {
+            match read_cert_writers(path) {
+                Ok(writers) => {
+                    app.set_client_cert_writers(Some(writers));
+                    info!(target: "main", "reloaded certificate writers");
+                }
+                Err(e) => error!(target: "main", "failed to reload certificate writers: {e:?}"),
+            }
+        }
+    }
+}
+
+impl OidcArgs {
+    fn config(&self) -> OidcConfig {
         OidcConfig {
-            audience: oidc_audience,
-            issuer: oidc_issuer,
-        },
-    )
-    .await
-    .context("Failed to build app")?;
-    TcpListener::bind(addr)
+            audience: self.oidc_audience.clone(),
+            issuer: self.oidc_issuer.clone(),
+        }
+    }
+
+    fn apply(&self, app: Builder<StoreUrl>) -> Builder<StoreUrl> {
+        app.oidc_strict_startup(self.oidc_strict_startup)
+            .oidc_clock_skew(Duration::from_secs(self.oidc_clock_skew))
+    }
+}
+
+impl LimitArgs {
+    fn apply(&self, app: Builder<StoreUrl>) -> Builder<StoreUrl> {
+        app.max_request_deadline(Duration::from_secs(self.max_request_deadline))
+            .upload_timeout(Duration::from_secs(self.upload_timeout))
+            .download_timeout(Duration::from_secs(self.download_timeout))
+            .metadata_timeout(Duration::from_secs(self.metadata_timeout))
+            .max_tags_per_repo(self.max_tags_per_repo)
+            .read_slots(self.read_slots)
+            .write_slots(self.write_slots)
+            .max_inflight_bytes(self.max_inflight_bytes)
+            .limit_warning_percent(self.limit_warning_percent)
+            .max_download_bps(self.max_download_bps)
+            .keep_alive_timeout(Duration::from_secs(self.keep_alive_timeout))
+            .max_requests_per_connection(self.max_requests_per_connection)
+            .max_connections(self.max_connections)
+            .handshake_timeout(Duration::from_secs(self.handshake_timeout))
+            .shutdown_timeout(Duration::from_secs(self.shutdown_timeout))
+            .response_buffer_bytes(self.response_buffer_bytes)
+            .upload_session_ttl(Duration::from_secs(self.upload_session_ttl))
+            .max_upload_sessions_per_repo(self.max_upload_sessions_per_repo)
+            .max_upload_session_size(self.max_upload_session_size)
+            .namespace_rate_limit(self.namespace_rate_limit)
+            .namespace_rate_limit_overrides(self.namespace_rate_limit_override.clone())
+    }
+
+    /// Returns whether any of the per-route timeouts is set.
+    fn route_timeouts(&self) -> bool {
+        self.upload_timeout > 0 || self.download_timeout > 0 || self.metadata_timeout > 0
+    }
+}
+
+impl MirrorArgs {
+    fn apply(&self, app: Builder<StoreUrl>) -> anyhow::Result<Builder<StoreUrl>> {
+        let Some(ref url) = self.mirror_url else {
+            return Ok(app);
+        };
+        let config = read_mirror_config(
+            url.clone(),
+            self.mirror_token_file.clone(),
+            self.mirror_cert.as_deref(),
+            self.mirror_key.as_deref(),
+            self.mirror_ca.as_deref(),
+            Duration::from_secs(self.mirror_ttl),
+        )?;
+        Ok(app.mirror(config))
+    }
+}
+
+async fn serve(mut args: ServeArgs) -> Result<(), Failed> {
+    args.resolve_paths().exit(Exit::Config)?;
+    let ServeArgs {
+        addr,
+        http_redirect_addr,
+        metrics_addr,
+        #[cfg(unix)]
+        listen_fd,
+        base_dir: _,
+        compression,
+        compression_algorithms,
+        store_precompressed,
+        validate_manifests,
+        manifest_schema,
+        allowed_content_types,
+        allowed_methods,
+        disable_routes,
+        hide_existence,
+        idempotency_key_ttl,
+        no_idempotency_keys,
+        negative_cache_ttl,
+        public_url,
+        allow_insecure_public_url,
+        url_signing_secret,
+        trusted_proxies,
+        allow_cidr,
+        deny_cidr,
+        write_allow_cidr,
+        write_deny_cidr,
+        authz_webhook,
+        authz_cache_ttl,
+        log_exclude_paths,
+        log_sample_rate,
+        server_timing,
+        server_header,
+        no_server_header,
+        hsts,
+        hsts_max_age,
+        hsts_include_subdomains,
+        hsts_preload,
+        content_cache_control,
+        tag_cache_control,
+        strict_paths,
+        metrics_endpoint,
+        quiet,
+        storage,
+        tls,
+        oidc,
+        limits,
+        mirror,
+    } = args;
+
+    // Inherited descriptors must be adopted before any are opened, such that they cannot
+    // refer to one used otherwise.
+    #[cfg(unix)]
+    let inherited = listen_fd
+        .map(listener_from_fd)
+        .transpose()
+        .exit(Exit::Config)?;
+
+    let tls_config = tls.read_config().exit(Exit::Config)?;
+    let tls_versions: Vec<_> = tls_config.protocol_versions().collect();
+    let client_cert = if tls_config.client_auth_mandatory() {
+        "required"
+    } else {
+        "optional"
+    };
+    let store = storage.url().exit(Exit::Config)?;
+    let (store_backend, store_path) = match store {
+        StoreUrl::File(ref path) => ("filesystem", path.display().to_string()),
+        StoreUrl::S3(_) => ("s3", store.to_string()),
+    };
+
+    let app = App::builder(store, tls_config, oidc.config())
+        .allowed_content_types(allowed_content_types.iter().cloned())
+        .allowed_methods(allowed_methods.iter().cloned())
+        .disabled_routes(disable_routes.iter().copied())
+        .hide_existence(hide_existence)
+        .connection_log_sample_rate(log_sample_rate)
+        .idempotency_key_ttl(
+            (!no_idempotency_keys).then(|| Duration::from_secs(idempotency_key_ttl)),
+        )
+        .negative_cache_ttl(Duration::from_secs(negative_cache_ttl))
+        .allow_insecure_public_url(allow_insecure_public_url)
+        .trusted_proxies(trusted_proxies)
+        .allow_cidrs(allow_cidr)
+        .deny_cidrs(deny_cidr)
+        .write_allow_cidrs(write_allow_cidr)
+        .write_deny_cidrs(write_deny_cidr)
+        .log_exclude_paths(log_exclude_paths)
+        .server_timing(server_timing)
+        .server_header((!no_server_header).then_some(server_header))
+        .hsts(hsts.then_some(Hsts {
+            max_age: Duration::from_secs(hsts_max_age),
+            include_subdomains: hsts_include_subdomains,
+            preload: hsts_preload,
+        }))
+        .content_cache_control((!content_cache_control.is_empty()).then_some(content_cache_control))
+        .tag_cache_control((!tag_cache_control.is_empty()).then_some(tag_cache_control))
+        .strict_paths(strict_paths)
+        .metrics_endpoint(metrics_endpoint);
+    let app = storage.apply(app).exit(Exit::Config)?;
+    let app = oidc.apply(app);
+    let app = limits.apply(app);
+    let app = tls.apply(app).exit(Exit::Config)?;
+    let app = mirror.apply(app).exit(Exit::Config)?;
+    let app = match (compression, store_precompressed) {
+        (true, true) => app
+            .compression(compression_algorithms.clone())
+            .store_precompressed(compression_algorithms),
+        (true, false) => app.compression(compression_algorithms),
+        (false, _) => app,
+    };
+    let app = match public_url {
+        Some(url) => app.public_url(url),
+        None => app,
+    };
+    let authz = authz_webhook.is_some();
+    let app = match authz_webhook {
+        Some(url) => app
+            .authz_webhook(url)
+            .authz_cache_ttl(Duration::from_secs(authz_cache_ttl)),
+        None => app,
+    };
+    let signed_urls = url_signing_secret.is_some();
+    let app = match url_signing_secret {
+        Some(secret) => app.url_signing_secret(secret),
+        None => app,
+    };
+    let app = match manifest_schema {
+        Some(ref path) => app.manifest_schema(read_manifest_schema(path).exit(Exit::Config)?),
+        None if validate_manifests => app.manifest_schema(Default::default()),
+        None => app,
+    };
+    let app = app
+        .build()
         .await
-        .with_context(|| format!("Failed to bind to {addr}"))?
-        .incoming()
-        .for_each_concurrent(None, |stream| async {
-            if let Err(e) = async {
-                let stream = stream.context("failed to initialize connection")?;
-                debug!(
-                    target: "main",
-                    "received TCP connection from {}",
-                    stream
-                        .peer_addr()
-                        .map(|peer| peer.to_string())
-                        .unwrap_or_else(|_| "unknown address".into())
-                );
-                app.handle(stream).await
-            }
+        .context("Failed to build app")
+        .map_err(Failed::of_app)?;
+    let app = Arc::new(app);
+
+    let features: Vec<_> = [
+        ("allowed-content-types", !allowed_content_types.is_empty()),
+        ("allowed-methods", !allowed_methods.is_empty()),
+        ("authz-webhook", authz),
+        ("cert-writers", tls.cert_writers.is_some()),
+        ("client-cert-allowlist", tls.client_cert_allowlist.is_some()),
+        ("compression", compression),
+        ("disabled-routes", !disable_routes.is_empty()),
+        ("hide-existence", hide_existence),
+        ("hsts", hsts),
+        ("store-precompressed", store_precompressed),
+        ("maintenance", storage.maintenance_interval.is_some()),
+        ("maintenance-gc", storage.maintenance_gc),
+        ("http-redirect", http_redirect_addr.is_some()),
+        ("max-connections", limits.max_connections > 0),
+        ("max-inflight-bytes", limits.max_inflight_bytes > 0),
+        ("metrics-addr", metrics_addr.is_some()),
+        ("metrics-endpoint", metrics_endpoint),
+        ("mirror", mirror.mirror_url.is_some()),
+        ("negative-cache", negative_cache_ttl > 0),
+        ("read-only", app.is_read_only()),
+        ("route-timeouts", limits.route_timeouts()),
+        ("server-timing", server_timing),
+        ("signed-urls", signed_urls),
+        ("strict-paths", strict_paths),
+        ("tls-tickets", !tls.no_tls_tickets),
+        ("validate-manifests", validate_manifests),
+        ("store-probe", storage.on_store_failure.is_some()),
+        ("s3-store", store_backend == "s3"),
+        ("store-cache", storage.cache_dir.is_some()),
+        ("read-repair", storage.read_repair),
+        (
+            "store-circuit-breaker",
+            storage.store.is_none() && storage.store_circuit_threshold > 0,
+        ),
+    ]
+    .into_iter()
+    .filter_map(|(name, enabled)| enabled.then_some(name))
+    .collect();
+
+    let mut signals = Signals::new([SIGHUP])
+        .context("Failed to register SIGHUP handler")
+        .exit(Exit::Failure)?;
+    let reload = async {
+        while signals.next().await.is_some() {
+            tls.reload(&app);
+        }
+    };
+
+    #[cfg(unix)]
+    let listener = match inherited {
+        Some(listener) => listener,
+        None => TcpListener::bind(addr)
             .await
-            {
-                error!(target: "main", "failed to handle request: {e}");
+            .with_context(|| format!("Failed to bind to {addr}"))
+            .exit(Exit::Bind)?,
+    };
+    #[cfg(not(unix))]
+    let listener = TcpListener::bind(addr)
+        .await
+        .with_context(|| format!("Failed to bind to {addr}"))
+        .exit(Exit::Bind)?;
+    let https_port = listener
+        .local_addr()
+        .context("Failed to query bound address")
+        .exit(Exit::Bind)?
+        .port();
+    let redirect_listener = match http_redirect_addr {
+        Some(addr) => Some(
+            TcpListener::bind(addr)
+                .await
+                .with_context(|| format!("Failed to bind to {addr}"))
+                .exit(Exit::Bind)?,
+        ),
+        None => None,
+    };
+    let metrics_listener = match metrics_addr {
+        Some(addr) => Some(
+            TcpListener::bind(addr)
+                .await
+                .with_context(|| format!("Failed to bind to {addr}"))
+                .exit(Exit::Bind)?,
+        ),
+        None => None,
+    };
+    if !quiet {
+        let addr = listener
+            .local_addr()
+            .context("Failed to query bound address")
+            .exit(Exit::Bind)?;
+        let http_redirect_addr = redirect_listener
+            .as_ref()
+            .map(TcpListener::local_addr)
+            .transpose()
+            .context("Failed to query bound address")
+            .exit(Exit::Bind)?;
+        let metrics_addr = metrics_listener
+            .as_ref()
+            .map(TcpListener::local_addr)
+            .transpose()
+            .context("Failed to query bound address")
+            .exit(Exit::Bind)?;
+        info!(
+            target: "main",
+            addr = %addr,
+            http_redirect_addr = http_redirect_addr.map(|addr| addr.to_string()),
+            metrics_addr = metrics_addr.map(|addr| addr.to_string()),
+            tls_versions = ?tls_versions,
+            tls_alpn = ?tls.tls_alpn,
+            client_cert,
+            oidc = %format_args!("{} ({})", oidc.oidc_issuer, oidc.oidc_audience),
+            store = store_backend,
+            store_path = %store_path,
+            public_url = app.public_url().map(Url::as_str),
+            mirror_url = mirror.mirror_url.as_ref().map(Url::as_str),
+            features = ?features,
+            "Drawbridge started"
+        );
+    }
+    let serve = app.serve_connections(&listener);
+    // Plaintext connections are only ever redirected, such that no store operations are
+    // exposed without TLS.
+    let redirect = async {
+        if let Some(ref listener) = redirect_listener {
+            app.serve_redirects(listener, https_port).await
+        }
+    };
+    let serve_metrics = async {
+        if let Some(ref listener) = metrics_listener {
+            app.serve_metrics(listener).await
+        }
+    };
+    let mut shutdown_signals = Signals::new([SIGTERM, SIGINT])
+        .context("Failed to register SIGTERM and SIGINT handlers")
+        .exit(Exit::Failure)?;
+    let shutdown = async {
+        if shutdown_signals.next().await.is_some() {
+            info!(target: "main", "received shutdown signal, draining connections");
+            app.shutdown();
+        }
+        match shutdown_signals.next().await {
+            Some(_) => Err(anyhow!(
+                "received another shutdown signal while draining connections"
+            )),
+            None => pending().await,
+        }
+    };
+    let watch_store = async {
+        match storage.on_store_failure {
+            Some(policy) => {
+                app.watch_store(Duration::from_secs(storage.store_probe_interval), policy)
+                    .await
             }
-        })
-        .await;
+            None => pending().await,
+        }
+    };
+    let maintain = async {
+        match storage.maintenance_interval {
+            Some(interval) => app.maintain(Duration::from_secs(interval)).await,
+            None => pending().await,
+        }
+    };
+    // Serving only completes once all connections are drained after shutdown, while the
+    // background tasks only complete on failure.
+    let serve = async {
+        let ((), (), ()) = join!(serve, redirect, serve_metrics);
+    };
+    let background = async {
+        let ((), (), ()) = try_join!(watch_store, shutdown, async {
+            let ((), ()) = join!(reload, maintain);
+            Ok(())
+        })?;
+        anyhow::Ok(())
+    };
+    match select(pin!(serve), pin!(background)).await {
+        Either::Left(((), _)) => info!(target: "main", "stopped serving"),
+        Either::Right((res, _)) => res.context("Stopped serving").map_err(Failed::of_app)?,
+    }
     Ok(())
 }
+
+#[async_std::main]
+async fn main() -> ExitCode {
+    let args = match args::<Toml>(prefix_char_filter::<'@'>) {
+        Ok(args) => args.into_iter().collect(),
+        Err(e) => {
+            // Tracing is only initialized once the command is known.
+            eprintln!("Failed to parse config: {e:#}");
+            return Exit::Config.into();
+        }
+    };
+    let res = match Cli::parse_from(with_default_command(args)).command {
+        Command::Serve(args) => {
+            init_tracing(io::stdout);
+            serve(*args).await
+        }
+        Command::Manage(command) => {
+            // Archives may be written to standard output.
+            init_tracing(io::stderr);
+            manage(command).await.exit(Exit::Failure)
+        }
+        #[cfg(feature = "bench")]
+        Command::Bench(args) => {
+            init_tracing(io::stderr);
+            bench::bench(*args).exit(Exit::Failure)
+        }
+    };
+    match res {
+        Ok(()) => Exit::Success.into(),
+        Err(Failed { exit, error }) => {
+            error!(target: "main", exit_code = exit as u8, "{error:#}");
+            exit.into()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use drawbridge_server::S3Config;
+
+    use clap::error::ErrorKind;
+    use clap::CommandFactory;
+
+    const SERVE_ARGS: [&str; 12] = [
+        "--store",
+        "/store",
+        "--cert",
+        "/server.crt",
+        "--key",
+        "/server.key",
+        "--ca",
+        "/ca.crt",
+        "--oidc-issuer",
+        "https://auth.example.com",
+        "--oidc-audience",
+        "https://store.example.com",
+    ];
+
+    fn parse(args: impl IntoIterator<Item = &'static str>) -> Result<Command, clap::Error> {
+        let args = ["drawbridge"].into_iter().chain(args).map(Into::into);
+        Cli::try_parse_from(with_default_command(args.collect())).map(|cli| cli.command)
+    }
+
+    #[test]
+    fn exit_codes() {
+        let of_app = |e: anyhow::Error| Failed::of_app(e.context("Failed to build app")).exit;
+        assert_eq!(
+            of_app(anyhow::anyhow!("failed to open store").context(FailureClass::Store)),
+            Exit::Store
+        );
+        assert_eq!(
+            of_app(anyhow::anyhow!("failed to discover provider").context(FailureClass::Oidc)),
+            Exit::Oidc
+        );
+        assert_eq!(
+            of_app(anyhow::anyhow!("upload session TTL must not be zero")),
+            Exit::Config
+        );
+
+        for exit in [
+            Exit::Success,
+            Exit::Failure,
+            Exit::Config,
+            Exit::Bind,
+            Exit::Store,
+            Exit::Oidc,
+        ] {
+            assert!(
+                EXIT_CODES.contains(&format!("\n  {}  ", exit as u8)),
+                "{exit:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn cli() {
+        Cli::command().debug_assert();
+
+        // Invocations passing `serve` options only are equivalent to explicit `serve` ones.
+        let serve = |args: Command| match args {
+            Command::Serve(args) => Some(format!("{args:?}")),
+            _ => None,
+        };
+        let implicit = serve(parse(SERVE_ARGS).unwrap()).expect("flags must parse as `serve`");
+        let explicit = serve(parse(["serve"].into_iter().chain(SERVE_ARGS)).unwrap());
+        assert_eq!(Some(implicit), explicit);
+        assert!(matches!(
+            parse(["--quiet"].into_iter().chain(SERVE_ARGS)),
+            Ok(Command::Serve(args)) if args.quiet
+        ));
+
+        assert!(matches!(
+            parse(
+                ["--upload-timeout", "600", "--metadata-timeout", "5"]
+                    .into_iter()
+                    .chain(SERVE_ARGS)
+            ),
+            Ok(Command::Serve(args))
+                if args.limits.upload_timeout == 600 && args.limits.download_timeout == 0 && args.limits.metadata_timeout == 5
+        ));
+
+        assert!(matches!(
+            parse(
+                ["--max-upload-sessions-per-repo", "4", "--max-upload-session-size", "1024"]
+                    .into_iter()
+                    .chain(SERVE_ARGS)
+            ),
+            Ok(Command::Serve(args))
+                if args.limits.max_upload_sessions_per_repo == 4 && args.limits.max_upload_session_size == 1024
+        ));
+
+        assert!(matches!(
+            parse(["--negative-cache-ttl", "5"].into_iter().chain(SERVE_ARGS)),
+            Ok(Command::Serve(args)) if args.negative_cache_ttl == 5
+        ));
+        assert!(parse(["--negative-cache-ttl", "61"].into_iter().chain(SERVE_ARGS)).is_err());
+
+        let mirror = [
+            "--mirror-url",
+            "https://upstream.example.com",
+            "--mirror-cert",
+            "mirror.crt",
+            "--mirror-key",
+            "mirror.key",
+        ];
+        assert!(matches!(
+            parse(mirror.into_iter().chain(SERVE_ARGS)),
+            Ok(Command::Serve(args))
+                if args.mirror.mirror_url.as_ref().map(Url::as_str) == Some("https://upstream.example.com/")
+                    && args.mirror.mirror_cert.as_deref() == Some(Path::new("mirror.crt"))
+                    && args.mirror.mirror_ttl == DEFAULT_MIRROR_TTL.as_secs()
+        ));
+        // Client certificates require a key and mirrors a writable store.
+        assert!(parse(mirror.into_iter().take(4).chain(SERVE_ARGS)).is_err());
+        assert!(parse(["--mirror-key", "mirror.key"].into_iter().chain(SERVE_ARGS)).is_err());
+        assert!(parse(
+            [
+                "--mirror-url",
+                "https://upstream.example.com",
+                "--read-only"
+            ]
+            .into_iter()
+            .chain(SERVE_ARGS)
+        )
+        .is_err());
+
+        let s3 = [
+            "--store-url",
+            "s3://bucket/prefix",
+            "--s3-endpoint",
+            "http://localhost:9000",
+        ];
+        assert!(matches!(
+            parse(s3.into_iter().chain(SERVE_ARGS.into_iter().skip(2))),
+            Ok(Command::Serve(args)) if args.storage.store.is_none()
+                && args.storage.store_url == Some(StoreUrl::S3(S3Config::new("bucket", "prefix")))
+                && args.storage.s3_endpoint.as_ref().map(Url::as_str) == Some("http://localhost:9000/")
+                && args.storage.s3_region == DEFAULT_S3_REGION
+        ));
+        // Exactly one of `--store` and `--store-url` is required.
+        assert!(parse(s3.into_iter().chain(SERVE_ARGS)).is_err());
+        assert!(parse(SERVE_ARGS.into_iter().skip(2)).is_err());
+        assert!(parse(
+            ["--s3-endpoint", "http://localhost:9000"]
+                .into_iter()
+                .chain(SERVE_ARGS)
+        )
+        .is_err());
+        assert!(parse(
+            ["--store-url", "ftp://bucket"]
+                .into_iter()
+                .chain(SERVE_ARGS.into_iter().skip(2))
+        )
+        .is_err());
+
+        let cache = [
+            "--cache-dir",
+            "/var/cache/drawbridge",
+            "--cache-max-bytes",
+            "1024",
+        ];
+        assert!(matches!(
+            parse(s3.into_iter().chain(cache).chain(SERVE_ARGS.into_iter().skip(2))),
+            Ok(Command::Serve(args))
+                if args.storage.cache_dir.as_deref() == Some(Path::new("/var/cache/drawbridge"))
+                    && args.storage.cache_max_bytes == 1024
+        ));
+        // Stores in local directories are not cached.
+        assert!(parse(cache.into_iter().chain(SERVE_ARGS)).is_err());
+        assert!(parse(
+            ["--cache-max-bytes", "1024"]
+                .into_iter()
+                .chain(s3)
+                .chain(SERVE_ARGS.into_iter().skip(2))
+        )
+        .is_err());
+
+        let circuit = [
+            "--store-circuit-threshold",
+            "0",
+            "--store-circuit-cooldown",
+            "5",
+        ];
+        assert!(matches!(
+            parse(s3.into_iter().chain(circuit).chain(SERVE_ARGS.into_iter().skip(2))),
+            Ok(Command::Serve(args))
+                if args.storage.store_circuit_threshold == 0
+                    && args.storage.store_circuit_cooldown == 5
+        ));
+        // Stores in local directories have no circuit breaker.
+        assert!(parse(circuit.into_iter().chain(SERVE_ARGS)).is_err());
+        assert!(parse(
+            ["--store-circuit-cooldown", "0"]
+                .into_iter()
+                .chain(s3)
+                .chain(SERVE_ARGS.into_iter().skip(2))
+        )
+        .is_err());
+
+        let repair = [
+            "--read-repair",
+            "--replica-store",
+            "file:///var/lib/replica",
+        ];
+        assert!(matches!(
+            parse(repair.into_iter().chain(SERVE_ARGS)),
+            Ok(Command::Serve(args))
+                if args.storage.read_repair
+                    && args.storage.replica_store
+                        == Some(StoreUrl::File("/var/lib/replica".into()))
+        ));
+        assert!(parse(["--read-repair"].into_iter().chain(SERVE_ARGS)).is_err());
+        assert!(parse(repair.into_iter().skip(1).chain(SERVE_ARGS)).is_err());
+
+        assert!(matches!(
+            parse(["--no-tls-tickets"].into_iter().chain(SERVE_ARGS)),
+            Ok(Command::Serve(args)) if args.tls.no_tls_tickets
+        ));
+        assert!(parse(
+            ["--no-tls-tickets", "--tls-ticket-lifetime", "60"]
+                .into_iter()
+                .chain(SERVE_ARGS)
+        )
+        .is_err());
+
+        assert!(matches!(
+            parse(SERVE_ARGS),
+            Ok(Command::Serve(args)) if args.server_header == SERVER_HEADER && !args.no_server_header
+        ));
+        assert!(matches!(
+            parse(["--no-server-header"].into_iter().chain(SERVE_ARGS)),
+            Ok(Command::Serve(args)) if args.no_server_header
+        ));
+
+        assert!(matches!(
+            parse(["--hsts", "--hsts-include-subdomains", "--hsts-preload"].into_iter().chain(SERVE_ARGS)),
+            Ok(Command::Serve(args)) if args.hsts
+                && args.hsts_max_age == DEFAULT_HSTS_MAX_AGE.as_secs()
+                && args.hsts_include_subdomains
+                && args.hsts_preload
+        ));
+        assert!(parse(["--hsts-max-age", "60"].into_iter().chain(SERVE_ARGS)).is_err());
+        assert!(parse(["--hsts", "--hsts-preload"].into_iter().chain(SERVE_ARGS)).is_err());
+        assert!(parse(
+            ["--no-server-header", "--server-header", "test"]
+                .into_iter()
+                .chain(SERVE_ARGS)
+        )
+        .is_err());
+
+        assert!(matches!(
+            parse(
+                ["--allow-cidr", "10.0.0.0/8,fd00::/8", "--allow-cidr", "192.0.2.1"]
+                    .into_iter()
+                    .chain(SERVE_ARGS)
+            ),
+            Ok(Command::Serve(args)) if args.allow_cidr.len() == 3 && args.deny_cidr.is_empty()
+        ));
+        assert!(parse(
+            ["--write-deny-cidr", "10.0.0.0/33"]
+                .into_iter()
+                .chain(SERVE_ARGS)
+        )
+        .is_err());
+
+        assert!(matches!(
+            parse(
+                ["--namespace-rate-limit", "5", "--namespace-rate-limit-override", "ci=0"]
+                    .into_iter()
+                    .chain(SERVE_ARGS)
+            ),
+            Ok(Command::Serve(args)) if args.limits.namespace_rate_limit == 5
+                && matches!(&args.limits.namespace_rate_limit_override[..], [(name, 0)] if name.to_string() == "ci")
+        ));
+        assert!(parse(
+            ["--namespace-rate-limit-override", "ci"]
+                .into_iter()
+                .chain(SERVE_ARGS)
+        )
+        .is_err());
+
+        assert!(matches!(
+            parse(
+                ["--allowed-content-types", "application/wasm,text/*"]
+                    .into_iter()
+                    .chain(SERVE_ARGS)
+            ),
+            Ok(Command::Serve(args)) if args.allowed_content_types.len() == 2
+        ));
+        assert!(matches!(
+            parse(
+                ["--allowed-methods", "get,HEAD", "--disable-routes", "listings,uploads"]
+                    .into_iter()
+                    .chain(SERVE_ARGS)
+            ),
+            Ok(Command::Serve(args)) if args.allowed_methods == [Method::GET, Method::HEAD]
+                && args.disable_routes == [RouteClass::Listings, RouteClass::Uploads]
+        ));
+        assert!(parse(
+            ["--disable-routes", "deletes"]
+                .into_iter()
+                .chain(SERVE_ARGS)
+        )
+        .is_err());
+        assert!(parse(
+            ["--allowed-content-types", "wasm"]
+                .into_iter()
+                .chain(SERVE_ARGS)
+        )
+        .is_err());
+
+        assert!(matches!(
+            parse(SERVE_ARGS),
+            Ok(Command::Serve(args)) if args.oidc.oidc_clock_skew == DEFAULT_OIDC_CLOCK_SKEW.as_secs()
+        ));
+        assert!(matches!(
+            parse(["--oidc-clock-skew", "0"].into_iter().chain(SERVE_ARGS)),
+            Ok(Command::Serve(args)) if args.oidc.oidc_clock_skew == 0
+        ));
+
+        assert!(matches!(
+            parse(["--http-redirect-addr", "[::]:8081"].into_iter().chain(SERVE_ARGS)),
+            Ok(Command::Serve(args)) if args.http_redirect_addr.is_some_and(|addr| addr.port() == 8081)
+        ));
+        assert!(parse(
+            ["--http-redirect-addr", "8081"]
+                .into_iter()
+                .chain(SERVE_ARGS)
+        )
+        .is_err());
+
+        assert!(matches!(
+            parse(["--metrics-addr", "127.0.0.1:9090"].into_iter().chain(SERVE_ARGS)),
+            Ok(Command::Serve(args)) if args.metrics_addr.is_some_and(|addr| addr.port() == 9090)
+        ));
+        assert!(parse(["--metrics-addr", "9090"].into_iter().chain(SERVE_ARGS)).is_err());
+
+        assert!(matches!(
+            parse(["--max-inflight-bytes", "1048576"].into_iter().chain(SERVE_ARGS)),
+            Ok(Command::Serve(args)) if args.limits.max_inflight_bytes == 1 << 20
+        ));
+        assert!(matches!(
+            parse(SERVE_ARGS),
+            Ok(Command::Serve(args)) if args.limits.limit_warning_percent == DEFAULT_LIMIT_WARNING_PERCENT
+        ));
+        assert!(matches!(
+            parse(["--limit-warning-percent", "0"].into_iter().chain(SERVE_ARGS)),
+            Ok(Command::Serve(args)) if args.limits.limit_warning_percent == 0
+        ));
+        assert!(parse(
+            ["--limit-warning-percent", "101"]
+                .into_iter()
+                .chain(SERVE_ARGS)
+        )
+        .is_err());
+
+        assert!(matches!(
+            parse(["--compression", "--store-precompressed"].into_iter().chain(SERVE_ARGS)),
+            Ok(Command::Serve(args)) if args.compression && args.store_precompressed
+        ));
+        assert!(parse(["--store-precompressed"].into_iter().chain(SERVE_ARGS)).is_err());
+
+        assert!(matches!(
+            parse(["--no-orphan-cleanup"].into_iter().chain(SERVE_ARGS)),
+            Ok(Command::Serve(args)) if args.storage.no_orphan_cleanup
+        ));
+        assert!(matches!(
+            parse(["--no-idempotency-keys"].into_iter().chain(SERVE_ARGS)),
+            Ok(Command::Serve(args)) if args.no_idempotency_keys
+        ));
+        assert!(parse(
+            ["--no-idempotency-keys", "--idempotency-key-ttl", "60"]
+                .into_iter()
+                .chain(SERVE_ARGS)
+        )
+        .is_err());
+
+        assert!(matches!(
+            parse(["--startup-scan-threads", "2"].into_iter().chain(SERVE_ARGS)),
+            Ok(Command::Serve(args)) if args.storage.startup_scan_threads == 2
+        ));
+        assert!(matches!(
+            parse(["--tls-alpn", "http/1.1,http/1.0"].into_iter().chain(SERVE_ARGS)),
+            Ok(Command::Serve(args)) if args.tls.tls_alpn == ["http/1.1", "http/1.0"]
+        ));
+        assert!(matches!(
+            parse(["--maintenance-interval", "300"].into_iter().chain(SERVE_ARGS)),
+            Ok(Command::Serve(args)) if args.storage.maintenance_interval == Some(300)
+        ));
+        assert!(parse(
+            ["--maintenance-interval", "0"]
+                .into_iter()
+                .chain(SERVE_ARGS)
+        )
+        .is_err());
+        assert!(matches!(
+            parse(
+                ["--maintenance-interval", "300", "--maintenance-gc"]
+                    .into_iter()
+                    .chain(SERVE_ARGS)
+            ),
+            Ok(Command::Serve(args)) if args.storage.maintenance_gc
+        ));
+        assert!(parse(["--maintenance-gc"].into_iter().chain(SERVE_ARGS)).is_err());
+        assert!(parse(
+            ["--no-orphan-cleanup", "--orphan-max-age", "60"]
+                .into_iter()
+                .chain(SERVE_ARGS)
+        )
+        .is_err());
+
+        #[cfg(unix)]
+        assert!(matches!(
+            parse(["--listen-fd", "3"].into_iter().chain(SERVE_ARGS)),
+            Ok(Command::Serve(args)) if args.listen_fd == Some(3)
+        ));
+        #[cfg(unix)]
+        assert!(parse(
+            ["--listen-fd", "3", "--addr", "127.0.0.1:8080"]
+                .into_iter()
+                .chain(SERVE_ARGS)
+        )
+        .is_err());
+
+        assert!(matches!(
+            parse(
+                ["--cert", "/other.crt", "--key", "/other.key"]
+                    .into_iter()
+                    .chain(SERVE_ARGS)
+            ),
+            Ok(Command::Serve(args)) if args.tls.cert.len() == 2 && args.tls.key.len() == 2
+        ));
+
+        assert!(matches!(
+            parse(["--tag-cache-control", ""].into_iter().chain(SERVE_ARGS)),
+            Ok(Command::Serve(args)) if args.tag_cache_control.is_empty()
+                && args.content_cache_control == DEFAULT_CONTENT_CACHE_CONTROL
+        ));
+
+        assert!(matches!(
+            parse(["--strict-paths"].into_iter().chain(SERVE_ARGS)),
+            Ok(Command::Serve(args)) if args.strict_paths
+        ));
+
+        assert!(matches!(
+            parse(["--metrics-endpoint"].into_iter().chain(SERVE_ARGS)),
+            Ok(Command::Serve(args)) if args.metrics_endpoint && !args.strict_paths
+        ));
+
+        assert!(matches!(
+            parse(SERVE_ARGS),
+            Ok(Command::Serve(args)) if args.log_sample_rate == 1.0
+        ));
+        assert!(matches!(
+            parse(["--log-sample-rate", "0.25"].into_iter().chain(SERVE_ARGS)),
+            Ok(Command::Serve(args)) if args.log_sample_rate == 0.25
+        ));
+        for rate in ["-0.1", "1.5", "NaN", "all"] {
+            assert!(
+                parse(["--log-sample-rate", rate].into_iter().chain(SERVE_ARGS)).is_err(),
+                "{rate}"
+            );
+        }
+
+        assert!(matches!(
+            parse(SERVE_ARGS),
+            Ok(Command::Serve(args)) if args.limits.max_connections == 0
+                && args.limits.handshake_timeout == DEFAULT_HANDSHAKE_TIMEOUT.as_secs()
+                && args.limits.shutdown_timeout == DEFAULT_SHUTDOWN_TIMEOUT.as_secs()
+        ));
+        assert!(matches!(
+            parse(
+                [
+                    "--max-connections",
+                    "100",
+                    "--handshake-timeout",
+                    "0",
+                    "--shutdown-timeout",
+                    "5",
+                ]
+                .into_iter()
+                .chain(SERVE_ARGS)
+            ),
+            Ok(Command::Serve(args)) if args.limits.max_connections == 100
+                && args.limits.handshake_timeout == 0
+                && args.limits.shutdown_timeout == 5
+        ));
+        assert!(parse(["--max-connections", "-1"].into_iter().chain(SERVE_ARGS)).is_err());
+
+        // Missing `serve` options are still reported.
+        assert!(parse([]).is_err());
+        assert!(parse(["--quiet"]).is_err());
+
+        assert!(matches!(
+            parse(["export", "--store", "/store", "--out", "-"]),
+            Ok(Command::Manage(ManageCommand::Export { .. }))
+        ));
+        assert!(matches!(
+            parse(["import", "--store", "/store", "--in", "-"]),
+            Ok(Command::Manage(ManageCommand::Import { .. }))
+        ));
+        assert!(matches!(
+            parse(["stats", "--store", "/store"]),
+            Ok(Command::Manage(ManageCommand::Stats {
+                top: 10,
+                json: false,
+                ..
+            }))
+        ));
+        assert!(matches!(
+            parse(["stats", "--store", "/store", "--top", "3", "--json"]),
+            Ok(Command::Manage(ManageCommand::Stats {
+                top: 3,
+                json: true,
+                ..
+            }))
+        ));
+        assert!(matches!(
+            parse(["gc", "--store", "/store"]),
+            Ok(Command::Manage(ManageCommand::Gc { store })) if store == Path::new("/store")
+        ));
+        assert!(parse(["gc"]).is_err());
+        // Management commands do not accept `serve` options.
+        assert!(parse(["export", "--store", "/store", "--out", "-", "--quiet"]).is_err());
+
+        #[cfg(feature = "bench")]
+        {
+            const BENCH_ARGS: [&str; 6] = [
+                "bench",
+                "--url",
+                "https://localhost:8080",
+                "--user",
+                "bench",
+                "--repo",
+            ];
+            assert!(matches!(
+                parse(BENCH_ARGS.into_iter().chain(["bench", "--concurrency", "4"])),
+                Ok(Command::Bench(args)) if format!("{args:?}").contains("concurrency: 4")
+            ));
+            assert!(parse(
+                BENCH_ARGS
+                    .into_iter()
+                    .chain(["bench", "--concurrency", "0"])
+            )
+            .is_err());
+            assert!(parse(
+                BENCH_ARGS
+                    .into_iter()
+                    .chain(["bench", "--cert", "/client.crt"])
+            )
+            .is_err());
+        }
+
+        for (arg, kind) in [
+            ("-h", ErrorKind::DisplayHelp),
+            ("--help", ErrorKind::DisplayHelp),
+            ("-V", ErrorKind::DisplayVersion),
+            ("--version", ErrorKind::DisplayVersion),
+        ] {
+            assert_eq!(parse([arg]).unwrap_err().kind(), kind);
+        }
+    }
+
+    #[test]
+    fn resolve_paths() {
+        let args = |base_dir: Option<&'static str>| {
+            let args = [
+                "--store",
+                "store",
+                "--cert",
+                "/etc/drawbridge/server.crt",
+                "--cert",
+                "tls/modules.crt",
+                "--key",
+                "tls/server.key",
+                "--key",
+                "tls/modules.key",
+                "--ca",
+                "../ca.crt",
+                "--validate-manifests",
+                "--manifest-schema",
+                "schema.json",
+                "--cert-writers",
+                "writers.txt",
+                "--mirror-url",
+                "https://upstream.example.com",
+                "--mirror-token-file",
+                "/run/secrets/token",
+                "--mirror-ca",
+                "upstream-ca.crt",
+                "--oidc-issuer",
+                "https://auth.example.com",
+                "--oidc-audience",
+                "https://store.example.com",
+            ];
+            let base_dir = base_dir.into_iter().flat_map(|dir| ["--base-dir", dir]);
+            match parse(base_dir.chain(args)) {
+                Ok(Command::Serve(mut args)) => {
+                    args.resolve_paths().unwrap();
+                    args
+                }
+                res => panic!("unexpected result {res:?}"),
+            }
+        };
+
+        let resolved = args(Some("/opt/drawbridge"));
+        assert_eq!(resolved.base_dir, Some("/opt/drawbridge".into()));
+        assert_eq!(
+            resolved.storage.store.as_deref(),
+            Some(Path::new("/opt/drawbridge/store"))
+        );
+        assert_eq!(
+            resolved.tls.cert,
+            [
+                Path::new("/etc/drawbridge/server.crt"),
+                Path::new("/opt/drawbridge/tls/modules.crt")
+            ]
+        );
+        assert_eq!(
+            resolved.tls.key,
+            [
+                Path::new("/opt/drawbridge/tls/server.key"),
+                Path::new("/opt/drawbridge/tls/modules.key")
+            ]
+        );
+        assert_eq!(resolved.tls.ca, Path::new("/opt/drawbridge/../ca.crt"));
+        assert_eq!(
+            resolved.manifest_schema.as_deref(),
+            Some(Path::new("/opt/drawbridge/schema.json"))
+        );
+        assert_eq!(resolved.tls.client_cert_allowlist, None);
+        assert_eq!(
+            resolved.tls.cert_writers.as_deref(),
+            Some(Path::new("/opt/drawbridge/writers.txt"))
+        );
+        assert_eq!(
+            resolved.mirror.mirror_token_file.as_deref(),
+            Some(Path::new("/run/secrets/token"))
+        );
+        assert_eq!(
+            resolved.mirror.mirror_ca.as_deref(),
+            Some(Path::new("/opt/drawbridge/upstream-ca.crt"))
+        );
+
+        // Relative base directories are resolved against the working directory.
+        let resolved = args(Some("opt"));
+        let cwd = std::env::current_dir().unwrap();
+        assert_eq!(resolved.storage.store, Some(cwd.join("opt/store")));
+
+        // Paths are used as-is without a base directory.
+        let resolved = args(None);
+        assert_eq!(resolved.storage.store.as_deref(), Some(Path::new("store")));
+        assert_eq!(resolved.tls.ca, Path::new("../ca.crt"));
+    }
+
+    #[cfg(unix)]
+    #[async_std::test]
+    async fn listen_fd() {
+        use std::os::unix::io::{AsRawFd, IntoRawFd};
+
+        let listener = std::net::TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let addr = listener.local_addr().unwrap();
+        let listener = listener_from_fd(listener.into_raw_fd()).expect("failed to adopt listener");
+        assert_eq!(listener.local_addr().unwrap(), addr);
+
+        let err = |res: anyhow::Result<_>| format!("{:#}", res.err().unwrap());
+        let file = File::open("Cargo.toml").unwrap();
+        assert!(err(listener_from_fd(file.as_raw_fd())).contains("is not a valid socket"));
+        // Descriptors, which are not adopted, are left open.
+        assert!(file.metadata().is_ok());
+
+        let udp = std::net::UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        assert!(err(listener_from_fd(udp.as_raw_fd())).contains("is not a TCP socket"));
+
+        #[cfg(target_os = "linux")]
+        {
+            use socket2::{Domain, Socket, Type};
+
+            let socket = Socket::new(Domain::IPV4, Type::STREAM, None).unwrap();
+            socket
+                .bind(&SocketAddr::from((Ipv4Addr::LOCALHOST, 0)).into())
+                .unwrap();
+            assert!(err(listener_from_fd(socket.as_raw_fd())).contains("is not listening"));
+        }
+        assert!(err(listener_from_fd(-1)).contains("Invalid file descriptor"));
+    }
+}
@@ -38,3 +38,18 @@ printf "\nGenerating Client Certificate\n"
 openssl x509 -req -days 9999 -CAcreateserial -CA ca.crt -CAkey ca.key -in client.csr -out client.crt -extfile client.conf -extensions client_crt
 printf "\nClient "
 openssl x509 -noout -text -in client.crt
+
+printf "\nGenerating Modules Server key\n"
+openssl ecparam -genkey -name prime256v1 | openssl pkcs8 -topk8 -nocrypt -out modules.key
+printf "\nModules Server "
+openssl pkey -noout -text -in modules.key
+
+printf "\nGenerating Modules Server Certificate Signing Request\n"
+openssl req -new -config modules.conf -key modules.key -out modules.csr
+printf "\nModules Server "
+openssl req -text -in modules.csr
+
+printf "\nGenerating Modules Server Certificate\n"
+openssl x509 -req -days 9999 -CAcreateserial -CA ca.crt -CAkey ca.key -in modules.csr -out modules.crt -extfile modules.conf -extensions server_crt
+printf "\nModules Server "
+openssl x509 -noout -text -in modules.crt
@@ -1,34 +1,52 @@
 // SPDX-FileCopyrightText: 2022 Profian Inc. <opensource@profian.com>
 // SPDX-License-Identifier: AGPL-3.0-only
 
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::time::{Duration, SystemTime};
 
 use drawbridge_client::mime::APPLICATION_OCTET_STREAM;
-use drawbridge_client::types::{RepositoryConfig, TreePath, UserRecord};
-use drawbridge_client::Client;
-use drawbridge_server::{App, OidcConfig, TlsConfig};
+use drawbridge_client::types::{
+    RepositoryChange, RepositoryChangeKind, RepositoryConfig, TreePath, UserRecord,
+};
+use drawbridge_client::{Client, ClientBuilder};
+use drawbridge_server::store::STORE_VERSION;
+use drawbridge_server::{
+    export_store, import_store, store_stats, App, AuthDecision, Builder, CertificateAllowlist,
+    CertificateWriters, CircuitState, CompressionAlgorithm, FailureClass, HandshakeOutcome, Hsts,
+    ManifestSchema, MirrorConfig, NamespaceStats, OidcConfig, RepairOutcome, ResourceLimit,
+    RouteClass, S3Config, S3Credentials, StoreFailurePolicy, StoreUrl, TlsConfig, TlsOptions,
+    TokenOutcome, DEFAULT_CONTENT_CACHE_CONTROL, DEFAULT_MAX_CLIENT_CERT_CHAIN,
+    DEFAULT_SERVER_HEADER, DEFAULT_TAG_CACHE_CONTROL, PROBLEM_DIGEST_MISMATCH,
+    PROBLEM_QUOTA_EXCEEDED,
+};
 
-use async_std::fs::{create_dir, write};
-use async_std::net::{Ipv4Addr, TcpListener};
-use async_std::task::{spawn, spawn_blocking};
+use async_std::fs::{create_dir, read_to_string, remove_file, write};
+use async_std::net::{Ipv4Addr, TcpListener, TcpStream};
+use async_std::sync::Arc;
+use async_std::task::{spawn, spawn_blocking, JoinHandle};
 use drawbridge_type::digest::Algorithms;
-use drawbridge_type::Meta;
-use futures::channel::oneshot::channel;
-use futures::{join, try_join, StreamExt};
+use drawbridge_type::{Meta, TreeEntry};
+use futures::channel::oneshot::{channel, Sender};
+use futures::{try_join, AsyncReadExt, AsyncWriteExt, StreamExt};
+use futures_rustls::client::TlsStream;
+use futures_rustls::TlsConnector;
 use http_types::convert::{json, Serialize};
-use http_types::{Body, Response, StatusCode};
+use http_types::{Body, Method, Request, Response, StatusCode};
 use jsonwebtoken::{encode, EncodingKey, Header};
 use openidconnect::core::{
     CoreJwsSigningAlgorithm, CoreProviderMetadata, CoreResponseType, CoreSubjectIdentifierType,
 };
 use openidconnect::{
-    AuthUrl, EmptyAdditionalProviderMetadata, IssuerUrl, JsonWebKeySetUrl, ResponseTypes,
+    AuthUrl, EmptyAdditionalProviderMetadata, IssuerUrl, JsonWebKeySetUrl, ResponseTypes, Scope,
 };
 use rsa::{pkcs1::EncodeRsaPrivateKey, PublicKeyParts};
 use rustls::{Certificate, PrivateKey, RootCertStore};
 use rustls_pemfile::Item::*;
-use tempfile::tempdir;
+use sha2::{Digest, Sha256};
+use tempfile::{tempdir, TempDir};
+
+const OIDC_AUDIENCE: &str = "testserver";
 
 #[derive(Debug, Clone, serde::Serialize)]
 struct TokenClaims {
@@ -42,65 +60,358 @@ struct TokenClaims {
     issued_at: u64,
     #[serde(rename = "exp")]
     expires_at: u64,
+    #[serde(rename = "nbf", skip_serializing_if = "Option::is_none")]
+    not_before: Option<u64>,
     scope: String,
 }
 
+/// Mock OpenID Connect provider.
+struct Oidc {
+    issuer: String,
+    key: EncodingKey,
+    tx: Sender<()>,
+    task: JoinHandle<()>,
+}
+
+impl Oidc {
+    async fn spawn() -> Self {
+        Self::spawn_with_scopes(None).await
+    }
+
+    /// Spawns a provider, which lists `scopes` as supported in its metadata, if set.
+    async fn spawn_with_scopes(scopes: Option<&'static [&'static str]>) -> Self {
+        let oidc_lis = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+            .await
+            .expect("failed to bind to address");
+        let oidc_addr = oidc_lis.local_addr().unwrap();
+
+        let mut rng = rand::thread_rng();
+        let oidc_key_raw =
+            rsa::RsaPrivateKey::new(&mut rng, 2048).expect("failed to generate a key");
+        let oidc_key_der = oidc_key_raw.to_pkcs1_der().expect("failed to encode key");
+        let oidc_key = EncodingKey::from_rsa_der(oidc_key_der.as_bytes());
+        let oidc_key_pub = oidc_key_raw.to_public_key();
+        let oidc_key_jwk = drawbridge_jose::jwk::Jwk {
+            key: drawbridge_jose::jwk::Key::Rsa {
+                prv: None,
+                n: oidc_key_pub.n().to_bytes_be().into(),
+                e: oidc_key_pub.e().to_bytes_be().into(),
+            },
+            prm: drawbridge_jose::jwk::Parameters {
+                kid: Some("signkey".into()),
+                ..Default::default()
+            },
+        };
+        let oidc_pubkeys = drawbridge_jose::jwk::JwkSet {
+            keys: vec![oidc_key_jwk.clone()],
+        };
+
+        let (oidc_tx, oidc_rx) = channel::<()>();
+        let oidc = spawn(async move {
+            oidc_lis
+                .incoming()
+                .take_until(oidc_rx)
+                .for_each_concurrent(None, |stream| async {
+                    let oidc_pubkeys = &oidc_pubkeys;
+
+                    async_h1::accept(
+                        stream.expect("failed to initialize stream"),
+                        |req| async move {
+                            fn json_response(
+                                body: &impl Serialize,
+                            ) -> Result<Response, http_types::Error> {
+                                let mut res = Response::new(StatusCode::Ok);
+                                res.insert_header("Content-Type", "application/json");
+                                let body = Body::from_json(&json!(body))?;
+                                res.set_body(body);
+                                Ok(res)
+                            }
+
+                            let oidc_url = format!("http://{oidc_addr}/");
+                            match req.url().path() {
+                                "/.well-known/openid-configuration" => {
+                                    json_response(
+                                        &CoreProviderMetadata::new(
+                                            // Parameters required by the OpenID Connect Discovery spec.
+                                            IssuerUrl::new(oidc_url.to_string()).unwrap(),
+                                            AuthUrl::new(format!("{oidc_url}authorize")).unwrap(),
+                                            // Use the JsonWebKeySet struct to serve the JWK Set at this URL.
+                                            JsonWebKeySetUrl::new(format!("{oidc_url}jwks"))
+                                                .unwrap(),
+                                            vec![ResponseTypes::new(vec![CoreResponseType::Code])],
+                                            vec![CoreSubjectIdentifierType::Pairwise],
+                                            vec![CoreJwsSigningAlgorithm::RsaSsaPssSha256],
+                                            EmptyAdditionalProviderMetadata {},
+                                        )
+                                        .set_scopes_supported(scopes.map(|scopes| {
+                                            scopes
+                                                .iter()
+                                                .map(|s| Scope::new(s.to_string()))
+                                                .collect()
+                                        })),
+                                    )
+                                }
+                                "/jwks" => json_response(&oidc_pubkeys),
+                                p => panic!("Unsupported path requested: `{p}`"),
+                            }
+                        },
+                    )
+                    .await
+                    .expect("failed to handle OIDC connection");
+                })
+                .await
+        });
+        Self {
+            issuer: format!("http://{oidc_addr}/"),
+            key: oidc_key,
+            tx: oidc_tx,
+            task: oidc,
+        }
+    }
+
+    /// Returns valid claims for `subject` with all Drawbridge management scopes.
+    fn claims(&self, subject: &str) -> TokenClaims {
+        TokenClaims {
+            issuer: self.issuer.clone(),
+            audience: vec![OIDC_AUDIENCE.to_owned()],
+            subject: subject.to_owned(),
+            issued_at: SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap()
+                .as_secs(),
+            expires_at: (SystemTime::now() + Duration::from_secs(3600))
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap()
+                .as_secs(),
+            not_before: None,
+            scope:
+                "openid manage:drawbridge_users manage:drawbridge_repositories manage:drawbridge_tags"
+                    .into(),
+        }
+    }
+
+    /// Signs `claims` using the provider key.
+    fn token(&self, claims: &TokenClaims) -> String {
+        let mut jwt_header = Header::new(jsonwebtoken::Algorithm::RS256);
+        jwt_header.kid = Some("signkey".to_owned());
+        encode(&jwt_header, claims, &self.key).expect("failed to sign token")
+    }
+
+    async fn stop(self) {
+        assert_eq!(self.tx.send(()), Ok(()));
+        self.task.await
+    }
+}
+
+/// Drawbridge server listening on a random local port.
+struct Server {
+    port: u16,
+    app: Arc<App>,
+    tx: Sender<()>,
+    task: JoinHandle<()>,
+    _store: TempDir,
+}
+
+impl Server {
+    /// Spawns a server using [OIDC_AUDIENCE] and the issuer of `oidc`, which is further
+    /// configured by `configure`.
+    async fn spawn(
+        oidc: &Oidc,
+        configure: impl FnOnce(Builder<StoreUrl>) -> Builder<StoreUrl>,
+    ) -> Self {
+        Self::spawn_with_store(oidc, None, configure).await
+    }
+
+    /// Like [Server::spawn], but uses the store at `url`, if set, instead of a temporary
+    /// directory.
+    async fn spawn_with_store(
+        oidc: &Oidc,
+        url: Option<StoreUrl>,
+        configure: impl FnOnce(Builder<StoreUrl>) -> Builder<StoreUrl>,
+    ) -> Self {
+        let srv_lis = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+            .await
+            .expect("failed to bind to address");
+        let srv_port = srv_lis.local_addr().unwrap().port();
+
+        let store = tempdir().expect("failed to create temporary store directory");
+
+        let app = configure(App::builder(
+            url.unwrap_or_else(|| store.path().into()),
+            tls_config(),
+            OidcConfig {
+                audience: OIDC_AUDIENCE.to_string(),
+                issuer: oidc.issuer.parse().unwrap(),
+            },
+        ))
+        .build()
+        .await
+        .map(Arc::new)
+        .unwrap();
+
+        let (srv_tx, srv_rx) = channel::<()>();
+        let srv = spawn({
+            let app = Arc::clone(&app);
+            async move {
+                srv_lis
+                    .incoming()
+                    .take_until(srv_rx)
+                    .for_each_concurrent(None, |stream| async {
+                        let stream = stream.expect("failed to initialize stream");
+                        let peer = stream.peer_addr().expect("failed to get peer address");
+                        app.handle_from(stream, peer)
+                            .await
+                            .expect("failed to handle stream")
+                    })
+                    .await
+            }
+        });
+        Self {
+            port: srv_port,
+            app,
+            tx: srv_tx,
+            task: srv,
+            _store: store,
+        }
+    }
+
+    /// Returns a [ClientBuilder] for the server, which trusts the test CA.
+    fn client(&self) -> ClientBuilder {
+        Client::builder(self.url("").parse().unwrap()).roots(roots())
+    }
+
+    /// Returns the URL of `path` on the server.
+    fn url(&self, path: &str) -> String {
+        format!("https://localhost:{}{path}", self.port)
+    }
+
+    /// Establishes a new TLS connection to the server.
+    async fn connect(&self) -> TlsStream<TcpStream> {
+        connect(self.port).await
+    }
+
+    /// Sends `req` to the server on a new connection and returns the response as-is.
+    ///
+    /// The response body is buffered, such that the connection is closed once this returns.
+    async fn send(&self, req: Request) -> Response {
+        let mut res = async_h1::connect(self.connect().await, req)
+            .await
+            .expect("failed to send request");
+        let body = res
+            .take_body()
+            .into_bytes()
+            .await
+            .expect("failed to read response body");
+        res.set_body(body);
+        res
+    }
+
+    async fn stop(self) {
+        assert_eq!(self.tx.send(()), Ok(()));
+        self.task.await
+    }
+}
+
+/// Request for the health of the server, which keeps the connection open.
+const HEALTH_REQUEST: &[u8] = b"GET /health HTTP/1.1\r\nHost: localhost\r\n\r\n";
+
+/// Establishes a new TLS connection to a server listening on `port`.
+async fn connect(port: u16) -> TlsStream<TcpStream> {
+    let stream = TcpStream::connect((Ipv4Addr::LOCALHOST, port))
+        .await
+        .expect("failed to connect to server");
+    TlsConnector::from(Arc::new(
+        rustls::ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(roots())
+            .with_no_client_auth(),
+    ))
+    .connect("localhost".try_into().unwrap(), stream)
+    .await
+    .expect("failed to establish TLS connection")
+}
+
+/// Reads an HTTP response head from `stream` and returns it as a string.
+async fn read_head(stream: &mut (impl Unpin + AsyncReadExt)) -> String {
+    let mut head = vec![];
+    while !head.ends_with(b"\r\n\r\n") {
+        let mut b = [0];
+        stream
+            .read_exact(&mut b)
+            .await
+            .expect("failed to read response head");
+        head.push(b[0]);
+    }
+    String::from_utf8(head).expect("response head is not valid UTF-8")
+}
+
+/// Returns `true` if the server closes the connection `stream` within `timeout`.
+async fn closed(stream: &mut TlsStream<TcpStream>, timeout: Duration) -> bool {
+    async_std::future::timeout(timeout, stream.read_to_end(&mut vec![]))
+        .await
+        .is_ok()
+}
+
+/// Returns the server TLS configuration using the test certificates.
+fn tls_config() -> TlsConfig {
+    TlsConfig::read(
+        include_bytes!("../testdata/server.crt").as_slice(),
+        include_bytes!("../testdata/server.key").as_slice(),
+        include_bytes!("../testdata/ca.crt").as_slice(),
+    )
+    .unwrap()
+}
+
+/// Returns a root certificate store containing the test CA.
+fn roots() -> RootCertStore {
+    let mut roots = RootCertStore::empty();
+    rustls_pemfile::certs(&mut std::io::BufReader::new(
+        include_bytes!("../testdata/ca.crt").as_slice(),
+    ))
+    .unwrap()
+    .into_iter()
+    .map(Certificate)
+    .try_for_each(|ref cert| roots.add(cert))
+    .unwrap();
+    roots
+}
+
+/// Returns the test client certificate chain and key signed by the test CA.
+fn client_credentials() -> (Vec<Certificate>, PrivateKey) {
+    let cert = rustls_pemfile::certs(&mut std::io::BufReader::new(
+        include_bytes!("../testdata/client.crt").as_slice(),
+    ))
+    .unwrap()
+    .into_iter()
+    .map(Certificate)
+    .collect::<Vec<Certificate>>();
+
+    let key = rustls_pemfile::read_one(&mut std::io::BufReader::new(
+        include_bytes!("../testdata/client.key").as_slice(),
+    ))
+    .unwrap()
+    .map(|item| match item {
+        RSAKey(buf) | PKCS8Key(buf) | ECKey(buf) => PrivateKey(buf),
+        _ => panic!("unsupported key type `{item:?}`"),
+    })
+    .unwrap();
+    (cert, key)
+}
+
 #[async_std::test]
 async fn app() {
-    tracing_subscriber::fmt::init();
+    let _ = tracing_subscriber::fmt::try_init();
 
-    let oidc_lis = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
-        .await
-        .expect("failed to bind to address");
-    let oidc_addr = oidc_lis.local_addr().unwrap();
-    let oidc_issuer = format!("http://{oidc_addr}/");
-    let oidc_audience = "testserver";
-
-    let mut rng = rand::thread_rng();
-    let oidc_key_raw = rsa::RsaPrivateKey::new(&mut rng, 2048).expect("failed to generate a key");
-    let oidc_key_der = oidc_key_raw.to_pkcs1_der().expect("failed to encode key");
-    let oidc_key = EncodingKey::from_rsa_der(oidc_key_der.as_bytes());
-    let oidc_key_pub = oidc_key_raw.to_public_key();
-    let oidc_key_jwk = drawbridge_jose::jwk::Jwk {
-        key: drawbridge_jose::jwk::Key::Rsa {
-            prv: None,
-            n: oidc_key_pub.n().to_bytes_be().into(),
-            e: oidc_key_pub.e().to_bytes_be().into(),
-        },
-        prm: drawbridge_jose::jwk::Parameters {
-            kid: Some("signkey".into()),
-            ..Default::default()
-        },
-    };
-    let oidc_pubkeys = drawbridge_jose::jwk::JwkSet {
-        keys: vec![oidc_key_jwk.clone()],
-    };
+    let oidc = Oidc::spawn().await;
 
     const SUBJECT: &str = "test|subject";
 
-    let mut jwt_header = Header::new(jsonwebtoken::Algorithm::RS256);
-    jwt_header.kid = Some("signkey".to_owned());
-    let jwt_payload = TokenClaims {
-        issuer: oidc_issuer.clone(),
-        audience: vec![oidc_audience.to_owned()],
-        subject: SUBJECT.to_owned(),
-        issued_at: SystemTime::now()
-            .duration_since(SystemTime::UNIX_EPOCH)
-            .unwrap()
-            .as_secs(),
-        expires_at: (SystemTime::now() + Duration::from_secs(3600))
-            .duration_since(SystemTime::UNIX_EPOCH)
-            .unwrap()
-            .as_secs(),
-        scope:
-            "openid manage:drawbridge_users manage:drawbridge_repositories manage:drawbridge_tags"
-                .into(),
-    };
+    let jwt_payload = oidc.claims(SUBJECT);
 
     let mut oidc_tokens = HashMap::from([
         ("valid", {
             let payload = jwt_payload.clone();
-            encode(&jwt_header, &payload, &oidc_key).expect("failed to sign token")
+            oidc.token(&payload)
         }),
         ("expired", {
             let mut payload = jwt_payload.clone();
@@ -116,154 +427,43 @@ async fn app() {
                 .duration_since(SystemTime::UNIX_EPOCH)
                 .unwrap()
                 .as_secs();
-            encode(&jwt_header, &payload, &oidc_key).expect("failed to sign token")
+            oidc.token(&payload)
         }),
         ("no_scopes", {
             let mut payload = jwt_payload.clone();
             payload.scope = "openid".into();
-            encode(&jwt_header, &payload, &oidc_key).expect("failed to sign token")
+            oidc.token(&payload)
         }),
         ("missing_manage_user_scope", {
             let mut payload = jwt_payload.clone();
             payload.scope = "openid manage:drawbridge_repositories manage:drawbridge_tags read:drawbridge_users".into();
-            encode(&jwt_header, &payload, &oidc_key).expect("failed to sign token")
+            oidc.token(&payload)
         }),
         ("invalid_aud", {
             let mut payload = jwt_payload.clone();
             payload.audience = vec!["invalid".into()];
-            encode(&jwt_header, &payload, &oidc_key).expect("failed to sign token")
+            oidc.token(&payload)
         }),
         ("invalid_iss", {
             let mut payload = jwt_payload.clone();
             payload.issuer = "invalid".into();
-            encode(&jwt_header, &payload, &oidc_key).expect("failed to sign token")
+            oidc.token(&payload)
         }),
         ("invalid_sig", {
             let payload = jwt_payload.clone();
-            let mut token = encode(&jwt_header, &payload, &oidc_key).expect("failed to sign token");
+            let mut token = oidc.token(&payload);
             token.pop();
             token
         }),
     ]);
     let oidc_token_valid = oidc_tokens.remove("valid").unwrap();
 
-    let (oidc_tx, oidc_rx) = channel::<()>();
-    let oidc = spawn(async move {
-        oidc_lis
-            .incoming()
-            .take_until(oidc_rx)
-            .for_each_concurrent(None, |stream| async {
-                let oidc_pubkeys = &oidc_pubkeys;
-
-                async_h1::accept(
-                    stream.expect("failed to initialize stream"),
-                    |req| async move {
-                        fn json_response(
-                            body: &impl Serialize,
-                        ) -> Result<Response, http_types::Error> {
-                            let mut res = Response::new(StatusCode::Ok);
-                            res.insert_header("Content-Type", "application/json");
-                            let body = Body::from_json(&json!(body))?;
-                            res.set_body(body);
-                            Ok(res)
-                        }
-
-                        let oidc_url = format!("http://{oidc_addr}/");
-                        match req.url().path() {
-                            "/.well-known/openid-configuration" => {
-                                json_response(&CoreProviderMetadata::new(
-                                    // Parameters required by the OpenID Connect Discovery spec.
-                                    IssuerUrl::new(oidc_url.to_string()).unwrap(),
-                                    AuthUrl::new(format!("{oidc_url}authorize")).unwrap(),
-                                    // Use the JsonWebKeySet struct to serve the JWK Set at this URL.
-                                    JsonWebKeySetUrl::new(format!("{oidc_url}jwks")).unwrap(),
-                                    vec![ResponseTypes::new(vec![CoreResponseType::Code])],
-                                    vec![CoreSubjectIdentifierType::Pairwise],
-                                    vec![CoreJwsSigningAlgorithm::RsaSsaPssSha256],
-                                    EmptyAdditionalProviderMetadata {},
-                                ))
-                            }
-                            "/jwks" => json_response(&oidc_pubkeys),
-                            p => panic!("Unsupported path requested: `{p}`"),
-                        }
-                    },
-                )
-                .await
-                .expect("failed to handle OIDC connection");
-            })
-            .await
-    });
-
-    let srv_lis = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
-        .await
-        .expect("failed to bind to address");
-    let srv_port = srv_lis.local_addr().unwrap().port();
-
-    let store = tempdir().expect("failed to create temporary store directory");
-
-    let (srv_tx, srv_rx) = channel::<()>();
-    let srv = spawn(async move {
-        let tls = TlsConfig::read(
-            include_bytes!("../testdata/server.crt").as_slice(),
-            include_bytes!("../testdata/server.key").as_slice(),
-            include_bytes!("../testdata/ca.crt").as_slice(),
-        )
-        .unwrap();
-        let app = App::new(
-            store.path(),
-            tls,
-            OidcConfig {
-                audience: oidc_audience.to_string(),
-                issuer: oidc_issuer.parse().unwrap(),
-            },
-        )
-        .await
-        .unwrap();
-        srv_lis
-            .incoming()
-            .take_until(srv_rx)
-            .for_each_concurrent(None, |stream| async {
-                app.handle(stream.expect("failed to initialize stream"))
-                    .await
-                    .expect("failed to handle stream")
-            })
-            .await
-    });
+    let srv = Server::spawn(&oidc, |builder| builder).await;
 
+    let cl = srv.client();
     let cl = spawn_blocking(move || async move {
         let (anon_cl, cert_cl, oidc_valid_cl, blank_cl) = {
-            let cl = Client::builder(format!("https://localhost:{srv_port}").parse().unwrap())
-                .roots({
-                    let mut roots = RootCertStore::empty();
-                    rustls_pemfile::certs(&mut std::io::BufReader::new(
-                        include_bytes!("../testdata/ca.crt").as_slice(),
-                    ))
-                    .unwrap()
-                    .into_iter()
-                    .map(Certificate)
-                    .try_for_each(|ref cert| roots.add(cert))
-                    .unwrap();
-                    roots
-                });
-
-            let cert = rustls_pemfile::certs(&mut std::io::BufReader::new(
-                include_bytes!("../testdata/client.crt").as_slice(),
-            ))
-            .unwrap()
-            .into_iter()
-            .map(Certificate)
-            .collect::<Vec<Certificate>>();
-
-            let key = rustls_pemfile::read_one(&mut std::io::BufReader::new(
-                include_bytes!("../testdata/client.key").as_slice(),
-            ))
-            .unwrap()
-            .map(|item| match item {
-                RSAKey(buf) | PKCS8Key(buf) | ECKey(buf) => PrivateKey(buf),
-                _ => panic!("unsupported key type `{item:?}`"),
-            })
-            .unwrap();
-
+            let (cert, key) = client_credentials();
             (
                 cl.clone().build().unwrap(),
                 cl.clone().credentials(cert, key).build().unwrap(),
@@ -451,9 +651,30 @@ async fn app() {
             oidc_pub_tag
                 .create_from_path_unsigned(pkg.path())
                 .expect("failed to create a tag and upload the tree"),
-            (prv_tag_created, prv_tree_created)
+            (prv_tag_created, prv_tree_created.clone())
+        );
+
+        assert_eq!(
+            oidc_prv_tag
+                .create_from_path_unsigned(pkg.path())
+                .expect("failed to re-upload an identical tag and tree"),
+            (
+                false,
+                prv_tree_created
+                    .into_keys()
+                    .map(|path| (path, false))
+                    .collect()
+            )
         );
 
+        let other_pkg = tempdir().expect("failed to create temporary package directory");
+        write(other_pkg.path().join("test-file"), "other")
+            .await
+            .unwrap();
+        assert!(oidc_prv_tag
+            .create_from_path_unsigned(other_pkg.path())
+            .is_err());
+
         assert!(anon_prv_repo.tags().is_err());
         assert!(cert_prv_repo.tags().is_err());
         assert_eq!(
@@ -518,10 +739,6009 @@ async fn app() {
     });
     assert!(matches!(cl.await.await, ()));
 
-    // Stop OpenID Connect provider
-    assert_eq!(oidc_tx.send(()), Ok(()));
+    srv.stop().await;
+    oidc.stop().await;
+}
+
+#[async_std::test]
+async fn client_cert_allowlist() {
+    let _ = tracing_subscriber::fmt::try_init();
+
+    let oidc = Oidc::spawn().await;
+
+    const SUBJECT: &str = "test|allowlist";
+    let oidc_token = oidc.token(&oidc.claims(SUBJECT));
+
+    let (cert, key) = client_credentials();
+    let cert_fingerprint: [u8; 32] = Sha256::digest(&cert[0].0).into();
+
+    let srv = Server::spawn(&oidc, |builder| {
+        builder.client_cert_allowlist(CertificateAllowlist::from_iter([[0xff; 32]]))
+    })
+    .await;
+
+    let cl = srv.client();
+    let app = Arc::clone(&srv.app);
+    let cl = spawn_blocking(move || async move {
+        let cert_cl = cl.clone().credentials(cert, key).build().unwrap();
+        let oidc_cl = cl.token(oidc_token).build().unwrap();
+
+        let user_name = "testuser".parse().unwrap();
+        let repo_name = "test-repo-private".parse().unwrap();
+        let tag_name = "0.1.0".parse().unwrap();
+
+        let oidc_user = oidc_cl.user(&user_name);
+        assert!(oidc_user
+            .create(&UserRecord {
+                subject: SUBJECT.into(),
+            })
+            .expect("failed to create user"));
+        let oidc_repo = oidc_user.repository(&repo_name);
+        assert!(oidc_repo
+            .create(&RepositoryConfig { public: false })
+            .expect("failed to create repository"));
+
+        let pkg = tempdir().expect("failed to create temporary package directory");
+        write(pkg.path().join("test-file.txt"), "text")
+            .await
+            .unwrap();
+        let (tag_created, _) = oidc_repo
+            .tag(&tag_name)
+            .create_from_path_unsigned(pkg.path())
+            .expect("failed to create a tag and upload the tree");
+        assert!(tag_created);
+
+        let file_name = "test-file.txt".parse().unwrap();
+        let cert_file = cert_cl
+            .user(&user_name)
+            .repository(&repo_name)
+            .tag(&tag_name)
+            .path(&file_name);
+
+        // The certificate is signed by the trusted CA, but is not in the allowlist.
+        let err = cert_file
+            .get_string(5)
+            .expect_err("certificate not in the allowlist granted access");
+        assert!(format!("{err:#}").contains("`403`"), "{err:#}");
+
+        // Certificate-less clients are unaffected.
+        assert_eq!(
+            oidc_user
+                .repository(&repo_name)
+                .tag(&tag_name)
+                .path(&file_name)
+                .get_string(5)
+                .expect("failed to get file")
+                .1,
+            "text"
+        );
+
+        app.set_client_cert_allowlist(Some(CertificateAllowlist::from_iter([cert_fingerprint])));
+        assert_eq!(
+            cert_file.get_string(5).expect("failed to get file").1,
+            "text"
+        );
+    });
+    assert!(matches!(cl.await.await, ()));
+
+    srv.stop().await;
+    oidc.stop().await;
+}
+
+#[async_std::test]
+async fn compression() {
+    let _ = tracing_subscriber::fmt::try_init();
+
+    let oidc = Oidc::spawn().await;
+
+    const SUBJECT: &str = "test|compression";
+    let oidc_token = oidc.token(&oidc.claims(SUBJECT));
+
+    let srv = Server::spawn(&oidc, |builder| {
+        builder.compression([CompressionAlgorithm::Brotli, CompressionAlgorithm::Gzip])
+    })
+    .await;
+
+    let cl = srv.client();
+    let cl = spawn_blocking(move || async move {
+        let oidc_cl = cl.token(oidc_token).build().unwrap();
+
+        let oidc_user = oidc_cl.user(&"testuser".parse().unwrap());
+        assert!(oidc_user
+            .create(&UserRecord {
+                subject: SUBJECT.into(),
+            })
+            .expect("failed to create user"));
+        let oidc_repo = oidc_user.repository(&"test-repo-public".parse().unwrap());
+        assert!(oidc_repo
+            .create(&RepositoryConfig { public: true })
+            .expect("failed to create repository"));
+
+        let pkg = tempdir().expect("failed to create temporary package directory");
+        try_join!(
+            write(pkg.path().join("test-file-1.txt"), "text"),
+            write(pkg.path().join("test-file-2.txt"), "text"),
+            write(pkg.path().join("test-file-3.txt"), "text"),
+        )
+        .unwrap();
+        let (tag_created, _) = oidc_repo
+            .tag(&"0.1.0".parse().unwrap())
+            .create_from_path_unsigned(pkg.path())
+            .expect("failed to create a tag and upload the tree");
+        assert!(tag_created);
+    });
+    assert!(matches!(cl.await.await, ()));
+
+    let url = srv.url("/api/v0.1.0/testuser/test-repo-public/_tag/0.1.0/tree");
+    for (accept_encoding, content_encoding) in [
+        (None, None),
+        (Some("identity"), None),
+        (Some("deflate"), None),
+        (Some("gzip"), Some("gzip")),
+        (Some("br"), Some("br")),
+        (Some("br, gzip;q=0.5"), Some("br")),
+        (Some("br;q=0.5, gzip"), Some("gzip")),
+        (Some("deflate, gzip;q=0.5"), Some("gzip")),
+    ] {
+        let mut req = Request::new(Method::Get, url.as_str());
+        if let Some(accept_encoding) = accept_encoding {
+            req.insert_header("Accept-Encoding", accept_encoding);
+        }
+        let res = srv.send(req).await;
+        assert_eq!(res.status(), StatusCode::Ok);
+        assert_eq!(
+            res.header("Vary").map(|v| v.as_str()),
+            Some("accept-encoding")
+        );
+        assert_eq!(
+            res.header("Content-Encoding").map(|v| v.as_str()),
+            content_encoding,
+            "unexpected encoding for `Accept-Encoding: {accept_encoding:?}`"
+        );
+    }
+
+    srv.stop().await;
+    oidc.stop().await;
+}
+
+#[async_std::test]
+async fn metrics_reload() {
+    let _ = tracing_subscriber::fmt::try_init();
+
+    let oidc = Oidc::spawn().await;
+    let srv = Server::spawn(&oidc, |builder| builder).await;
+
+    let health = || async {
+        let res = srv
+            .send(Request::new(Method::Get, srv.url("/health").as_str()))
+            .await;
+        assert_eq!(res.status(), StatusCode::Ok);
+    };
+    let active_connections = || async {
+        // Connections are closed asynchronously by the server.
+        for _ in 0..100 {
+            if srv.app.metrics().active_connections() == 0 {
+                break;
+            }
+            async_std::task::sleep(Duration::from_millis(10)).await;
+        }
+        srv.app.metrics().active_connections()
+    };
+
+    health().await;
+    health().await;
+    assert_eq!(srv.app.metrics().accepted_connections(), 2);
+    assert_eq!(active_connections().await, 0);
+
+    srv.app.set_tls_config(tls_config());
+
+    health().await;
+    assert_eq!(srv.app.metrics().accepted_connections(), 3);
+    assert_eq!(active_connections().await, 0);
+
+    let stream = srv.connect().await;
+    for _ in 0..100 {
+        if srv.app.metrics().active_connections() == 1 {
+            break;
+        }
+        async_std::task::sleep(Duration::from_millis(10)).await;
+    }
+    assert_eq!(srv.app.metrics().active_connections(), 1);
+    assert_eq!(srv.app.metrics().accepted_connections(), 4);
+    drop(stream);
+    assert_eq!(active_connections().await, 0);
+
+    srv.stop().await;
+    oidc.stop().await;
+}
+
+#[async_std::test]
+async fn max_tags_per_repo() {
+    let _ = tracing_subscriber::fmt::try_init();
+
+    let oidc = Oidc::spawn().await;
+
+    const SUBJECT: &str = "test|tag-limit";
+    let oidc_token = oidc.token(&oidc.claims(SUBJECT));
+
+    let srv = Server::spawn(&oidc, |builder| builder.max_tags_per_repo(2)).await;
+
+    let cl = srv.client();
+    let cl = spawn_blocking(move || async move {
+        let oidc_cl = cl.token(oidc_token).build().unwrap();
+
+        let oidc_user = oidc_cl.user(&"testuser".parse().unwrap());
+        assert!(oidc_user
+            .create(&UserRecord {
+                subject: SUBJECT.into(),
+            })
+            .expect("failed to create user"));
+
+        let pkg = tempdir().expect("failed to create temporary package directory");
+        write(pkg.path().join("test-file.txt"), "text")
+            .await
+            .unwrap();
+
+        let repo = oidc_user.repository(&"test-repo".parse().unwrap());
+        assert!(repo
+            .create(&RepositoryConfig { public: false })
+            .expect("failed to create repository"));
+        for tag in ["0.1.0", "0.2.0"] {
+            let (tag_created, _) = repo
+                .tag(&tag.parse().unwrap())
+                .create_from_path_unsigned(pkg.path())
+                .expect("failed to create a tag and upload the tree");
+            assert!(tag_created);
+        }
+
+        let err = repo
+            .tag(&"0.3.0".parse().unwrap())
+            .create_from_path_unsigned(pkg.path())
+            .expect_err("tag limit exceeded");
+        assert!(format!("{err:#}").contains("`409`"), "{err:#}");
+
+        // Re-uploads of existing tags do not count against the limit.
+        let (tag_created, _) = repo
+            .tag(&"0.2.0".parse().unwrap())
+            .create_from_path_unsigned(pkg.path())
+            .expect("failed to re-upload an identical tag and tree");
+        assert!(!tag_created);
+
+        // The limit applies per repository.
+        let other_repo = oidc_user.repository(&"test-repo-other".parse().unwrap());
+        assert!(other_repo
+            .create(&RepositoryConfig { public: false })
+            .expect("failed to create repository"));
+        let (tag_created, _) = other_repo
+            .tag(&"0.3.0".parse().unwrap())
+            .create_from_path_unsigned(pkg.path())
+            .expect("failed to create a tag and upload the tree");
+        assert!(tag_created);
+
+        let mut tags = repo.tags().expect("failed to get tags");
+        tags.sort_by_key(ToString::to_string);
+        assert_eq!(
+            tags,
+            vec!["0.1.0".parse().unwrap(), "0.2.0".parse().unwrap()]
+        );
+    });
+    assert!(matches!(cl.await.await, ()));
+
+    srv.stop().await;
+    oidc.stop().await;
+}
+
+#[async_std::test]
+async fn expect_continue() {
+    let _ = tracing_subscriber::fmt::try_init();
+
+    let oidc = Oidc::spawn().await;
+
+    const SUBJECT: &str = "test|expect";
+    let oidc_token = oidc.token(&oidc.claims(SUBJECT));
+
+    let srv = Server::spawn(&oidc, |builder| builder).await;
+
+    let body = format!(r#"{{"subject":"{SUBJECT}"}}"#);
+    let head = |authorization: &str, expect: &str| {
+        format!(
+            "PUT /api/v0.1.0/testuser HTTP/1.1\r\n\
+            Host: localhost\r\n\
+            Content-Type: application/json\r\n\
+            Content-Length: {}\r\n\
+            {authorization}\
+            Expect: {expect}\r\n\r\n",
+            body.len(),
+        )
+    };
+
+    // Unauthorized requests are rejected before the body is sent.
+    let mut stream = srv.connect().await;
+    stream
+        .write_all(head("", "100-continue").as_bytes())
+        .await
+        .unwrap();
+    let res = read_head(&mut stream).await;
+    assert!(res.starts_with("HTTP/1.1 401 "), "{res}");
+    drop(stream);
+
+    // Unsupported expectations are rejected.
+    let mut stream = srv.connect().await;
+    stream
+        .write_all(head(&format!("Authorization: Bearer {oidc_token}\r\n"), "foo").as_bytes())
+        .await
+        .unwrap();
+    let res = read_head(&mut stream).await;
+    assert!(res.starts_with("HTTP/1.1 417 "), "{res}");
+    drop(stream);
+
+    // Authorized requests receive `100 Continue` before the body is sent.
+    let mut stream = srv.connect().await;
+    stream
+        .write_all(
+            head(
+                &format!("Authorization: Bearer {oidc_token}\r\n"),
+                "100-continue",
+            )
+            .as_bytes(),
+        )
+        .await
+        .unwrap();
+    let res = read_head(&mut stream).await;
+    assert!(res.starts_with("HTTP/1.1 100 Continue"), "{res}");
+    stream.write_all(body.as_bytes()).await.unwrap();
+    let res = read_head(&mut stream).await;
+    assert!(res.starts_with("HTTP/1.1 201 "), "{res}");
+    drop(stream);
+
+    // Clients, which do not wait for `100 Continue`, still succeed.
+    let body = r#"{"public":false}"#;
+    let mut stream = srv.connect().await;
+    stream
+        .write_all(
+            format!(
+                "PUT /api/v0.1.0/testuser/test-repo HTTP/1.1\r\n\
+                Host: localhost\r\n\
+                Content-Type: application/json\r\n\
+                Content-Length: {}\r\n\
+                Authorization: Bearer {oidc_token}\r\n\
+                Expect: 100-continue\r\n\r\n\
+                {body}",
+                body.len()
+            )
+            .as_bytes(),
+        )
+        .await
+        .unwrap();
+    let mut res = read_head(&mut stream).await;
+    if res.starts_with("HTTP/1.1 100 ") {
+        res = read_head(&mut stream).await;
+    }
+    assert!(res.starts_with("HTTP/1.1 201 "), "{res}");
+    drop(stream);
+
+    srv.stop().await;
+    oidc.stop().await;
+}
+
+#[async_std::test]
+async fn read_only() {
+    let _ = tracing_subscriber::fmt::try_init();
+
+    let oidc = Oidc::spawn().await;
+
+    const SUBJECT: &str = "test|read-only";
+    let oidc_token = oidc.token(&oidc.claims(SUBJECT));
+
+    let srv = Server::spawn(&oidc, |builder| builder.read_only(true)).await;
+    assert!(srv.app.is_read_only());
+
+    let mut req = Request::new(Method::Put, srv.url("/api/v0.1.0/testuser").as_str());
+    req.insert_header("Authorization", format!("Bearer {oidc_token}"));
+    req.set_body(Body::from_json(&json!({ "subject": SUBJECT })).unwrap());
+    let res = srv.send(req).await;
+    assert_eq!(res.status(), StatusCode::MethodNotAllowed);
+    assert_eq!(res.header("Allow").map(|v| v.as_str()), Some("GET, HEAD"));
+
+    // The `Allow` header lists the reading methods supported by the endpoint.
+    for (path, allow) in [
+        ("/api/v0.1.0/testuser/test-repo/_tag", "GET"),
+        ("/api/v0.1.0/testuser/test-repo/_tag/0.1.0", "GET, HEAD"),
+        (
+            "/api/v0.1.0/testuser/test-repo/_tag/0.1.0/tree/foo",
+            "GET, HEAD",
+        ),
+    ] {
+        let res = srv
+            .send(Request::new(Method::Put, srv.url(path).as_str()))
+            .await;
+        assert_eq!(res.status(), StatusCode::MethodNotAllowed);
+        assert_eq!(res.header("Allow").map(|v| v.as_str()), Some(allow));
+    }
+
+    let res = srv
+        .send(Request::new(Method::Get, srv.url("/health").as_str()))
+        .await;
+    assert_eq!(res.status(), StatusCode::Ok);
+    srv.stop().await;
+
+    // Methods not supported by an endpoint are rejected regardless of read-only mode.
+    let srv = Server::spawn(&oidc, |builder| builder).await;
+    let res = srv
+        .send(Request::new(
+            Method::Delete,
+            srv.url("/api/v0.1.0/testuser").as_str(),
+        ))
+        .await;
+    assert_eq!(res.status(), StatusCode::MethodNotAllowed);
+    assert_eq!(
+        res.header("Allow").map(|v| v.as_str()),
+        Some("GET, HEAD, PUT")
+    );
+
+    srv.stop().await;
+    oidc.stop().await;
+}
+
+#[async_std::test]
+async fn route_restrictions() {
+    let _ = tracing_subscriber::fmt::try_init();
+
+    let oidc = Oidc::spawn().await;
+
+    const SUBJECT: &str = "test|route-restrictions";
+    let oidc_token = oidc.token(&oidc.claims(SUBJECT));
+
+    let srv = Server::spawn(&oidc, |builder| {
+        builder
+            .allowed_methods([
+                drawbridge_server::Method::GET,
+                drawbridge_server::Method::HEAD,
+                drawbridge_server::Method::PUT,
+            ])
+            .disabled_routes([RouteClass::Listings])
+    })
+    .await;
+    let cl = srv.client();
+    let token = oidc_token.clone();
+    let cl = spawn_blocking(move || async move {
+        let oidc_cl = cl.token(token).build().unwrap();
+
+        let oidc_user = oidc_cl.user(&"testuser".parse().unwrap());
+        assert!(oidc_user
+            .create(&UserRecord {
+                subject: SUBJECT.into(),
+            })
+            .expect("failed to create user"));
+        let oidc_repo = oidc_user.repository(&"test-repo".parse().unwrap());
+        assert!(oidc_repo
+            .create(&RepositoryConfig { public: false })
+            .expect("failed to create repository"));
+        let pkg = tempdir().expect("failed to create temporary package directory");
+        write(pkg.path().join("test-file.txt"), "text")
+            .await
+            .unwrap();
+        let (tag_created, _) = oidc_repo
+            .tag(&"0.1.0".parse().unwrap())
+            .create_from_path_unsigned(pkg.path())
+            .expect("failed to create tag");
+        assert!(tag_created);
+
+        // Routes of disabled classes are unreachable even for the owner of the repository.
+        assert!(oidc_repo.tags().is_err());
+    });
+    assert!(matches!(cl.await.await, ()));
+
+    let send = |method, path: &str| {
+        let mut req = Request::new(method, srv.url(path).as_str());
+        req.insert_header("Authorization", format!("Bearer {oidc_token}"));
+        srv.send(req)
+    };
+    for path in [
+        "/api/v0.1.0/testuser/test-repo/_tag",
+        "/api/v0.1.0/testuser/test-repo/_changes",
+    ] {
+        let res = send(Method::Get, path).await;
+        assert_eq!(res.status(), StatusCode::NotFound, "{path}");
+    }
+    let res = send(
+        Method::Get,
+        "/api/v0.1.0/testuser/test-repo/_tag/0.1.0/tree/test-file.txt",
+    )
+    .await;
+    assert_eq!(res.status(), StatusCode::Ok);
+
+    // The `Allow` header lists the allowed methods supported by the endpoint.
+    let res = send(
+        Method::Delete,
+        "/api/v0.1.0/testuser/test-repo/_upload/67e55044-10b1-426f-9247-bb680e5fe0c8",
+    )
+    .await;
+    assert_eq!(res.status(), StatusCode::MethodNotAllowed);
+    assert_eq!(
+        res.header("Allow").map(|v| v.as_str()),
+        Some("GET, HEAD, PUT")
+    );
+    let res = send(Method::Post, "/api/v0.1.0/testuser/test-repo/_upload").await;
+    assert_eq!(res.status(), StatusCode::MethodNotAllowed);
+    let res = send(Method::Post, "/api/v0.1.0/testuser/test-repo/_tag/0.1.0").await;
+    assert_eq!(res.status(), StatusCode::MethodNotAllowed);
+    assert_eq!(
+        res.header("Allow").map(|v| v.as_str()),
+        Some("GET, HEAD, PUT")
+    );
+
+    srv.stop().await;
+    oidc.stop().await;
+}
+
+#[async_std::test]
+async fn server_timing() {
+    let _ = tracing_subscriber::fmt::try_init();
+
+    let oidc = Oidc::spawn().await;
+
+    const SUBJECT: &str = "test|server-timing";
+    let oidc_token = oidc.token(&oidc.claims(SUBJECT));
+
+    let srv = Server::spawn(&oidc, |builder| builder).await;
+    let res = srv
+        .send(Request::new(Method::Get, srv.url("/health").as_str()))
+        .await;
+    assert_eq!(res.status(), StatusCode::Ok);
+    assert!(res.header("Server-Timing").is_none());
+    srv.stop().await;
+
+    let srv = Server::spawn(&oidc, |builder| builder.server_timing(true)).await;
+
+    let cl = srv.client();
+    let token = oidc_token.clone();
+    let cl = spawn_blocking(move || async move {
+        let oidc_cl = cl.token(token).build().unwrap();
+
+        let oidc_user = oidc_cl.user(&"testuser".parse().unwrap());
+        assert!(oidc_user
+            .create(&UserRecord {
+                subject: SUBJECT.into(),
+            })
+            .expect("failed to create user"));
+        let oidc_repo = oidc_user.repository(&"test-repo-private".parse().unwrap());
+        assert!(oidc_repo
+            .create(&RepositoryConfig { public: false })
+            .expect("failed to create repository"));
+
+        let pkg = tempdir().expect("failed to create temporary package directory");
+        write(pkg.path().join("test-file.txt"), "text")
+            .await
+            .unwrap();
+        let (tag_created, _) = oidc_repo
+            .tag(&"0.1.0".parse().unwrap())
+            .create_from_path_unsigned(pkg.path())
+            .expect("failed to create a tag and upload the tree");
+        assert!(tag_created);
+    });
+    assert!(matches!(cl.await.await, ()));
+
+    let mut req = Request::new(
+        Method::Get,
+        srv.url("/api/v0.1.0/testuser/test-repo-private/_tag/0.1.0/tree/test-file.txt")
+            .as_str(),
+    );
+    req.insert_header("Authorization", format!("Bearer {oidc_token}"));
+    let res = srv.send(req).await;
+    assert_eq!(res.status(), StatusCode::Ok);
+    let timing = res
+        .header("Server-Timing")
+        .expect("missing Server-Timing header")
+        .as_str();
+    let phases: Vec<_> = timing
+        .split(", ")
+        .map(|phase| {
+            let (name, duration) = phase
+                .split_once(";dur=")
+                .unwrap_or_else(|| panic!("malformed Server-Timing entry `{phase}`"));
+            assert!(
+                duration.parse::<f64>().is_ok(),
+                "invalid duration `{duration}`"
+            );
+            name
+        })
+        .collect();
+    assert_eq!(phases, ["auth", "store", "body", "total"]);
+
+    srv.stop().await;
+    oidc.stop().await;
+}
+
+#[async_std::test]
+async fn slots() {
+    let _ = tracing_subscriber::fmt::try_init();
+
+    let oidc = Oidc::spawn().await;
+
+    const SUBJECT: &str = "test|slots";
+    let oidc_token = oidc.token(&oidc.claims(SUBJECT));
+
+    let srv = Server::spawn(&oidc, |builder| builder.read_slots(1).write_slots(1)).await;
+
+    let body = format!(r#"{{"subject":"{SUBJECT}"}}"#);
+    let put_user = |name: &str| {
+        format!(
+            "PUT /api/v0.1.0/{name} HTTP/1.1\r\n\
+            Host: localhost\r\n\
+            Content-Type: application/json\r\n\
+            Content-Length: {}\r\n\
+            Authorization: Bearer {oidc_token}\r\n\
+            Expect: 100-continue\r\n\r\n",
+            body.len(),
+        )
+    };
+
+    // The write slot is held while the body of the first request is transferred.
+    let mut first = srv.connect().await;
+    first
+        .write_all(put_user("testuser").as_bytes())
+        .await
+        .unwrap();
+    let res = read_head(&mut first).await;
+    assert!(res.starts_with("HTTP/1.1 100 Continue"), "{res}");
+
+    // Reads are not starved by the pending write.
+    let res = srv
+        .send(Request::new(Method::Get, srv.url("/health").as_str()))
+        .await;
+    assert_eq!(res.status(), StatusCode::Ok);
+
+    // Further writes wait for the write slot to become available.
+    let mut second = srv.connect().await;
+    second
+        .write_all(put_user("testuser2").as_bytes())
+        .await
+        .unwrap();
+    assert!(
+        async_std::future::timeout(Duration::from_millis(500), read_head(&mut second))
+            .await
+            .is_err(),
+        "write was handled without a free write slot"
+    );
+
+    first.write_all(body.as_bytes()).await.unwrap();
+    let res = read_head(&mut first).await;
+    assert!(res.starts_with("HTTP/1.1 201 "), "{res}");
+    drop(first);
+
+    let res = read_head(&mut second).await;
+    assert!(res.starts_with("HTTP/1.1 100 Continue"), "{res}");
+    second.write_all(body.as_bytes()).await.unwrap();
+    let res = read_head(&mut second).await;
+    assert!(res.starts_with("HTTP/1.1 201 "), "{res}");
+    drop(second);
+
+    // With a single slot, every admitted request fully uses it.
+    assert!(srv.app.metrics().near_limit(ResourceLimit::ReadSlots) >= 1);
+    assert_eq!(srv.app.metrics().near_limit(ResourceLimit::WriteSlots), 2);
+    assert_eq!(
+        srv.app.metrics().near_limit(ResourceLimit::InflightBytes),
+        0
+    );
+
+    srv.stop().await;
+    oidc.stop().await;
+}
+
+#[async_std::test]
+async fn dry_run() {
+    let _ = tracing_subscriber::fmt::try_init();
+
+    let oidc = Oidc::spawn().await;
+
+    const SUBJECT: &str = "test|dry-run";
+    let oidc_token = oidc.token(&oidc.claims(SUBJECT));
+
+    let srv = Server::spawn(&oidc, |builder| builder).await;
+
+    let cl = srv.client();
+    let token = oidc_token.clone();
+    let cl = spawn_blocking(move || async move {
+        let oidc_cl = cl.token(token).build().unwrap();
+
+        let oidc_user = oidc_cl.user(&"testuser".parse().unwrap());
+        assert!(oidc_user
+            .create(&UserRecord {
+                subject: SUBJECT.into(),
+            })
+            .expect("failed to create user"));
+        assert!(oidc_user
+            .repository(&"test-repo".parse().unwrap())
+            .create(&RepositoryConfig { public: true })
+            .expect("failed to create repository"));
+    });
+    assert!(matches!(cl.await.await, ()));
+
+    let put = |path: &str, meta: &Meta, body: &[u8]| {
+        let mut req = Request::new(
+            Method::Put,
+            srv.url(&format!("/api/v0.1.0/testuser/test-repo/_tag/0.1.0{path}"))
+                .as_str(),
+        );
+        req.set_body(body);
+        req.insert_header("Authorization", format!("Bearer {oidc_token}"));
+        req.insert_header("Content-Type", meta.mime.to_string());
+        req.insert_header("Content-Digest", meta.hash.to_string());
+        req
+    };
+    let meta = |mime: &str, body: &[u8]| {
+        Algorithms::default()
+            .read_sync(body)
+            .map(|(size, hash)| Meta {
+                hash,
+                size,
+                mime: mime.parse().unwrap(),
+            })
+            .unwrap()
+    };
+
+    let file_meta = meta("application/octet-stream", b"text");
+    // Unsigned tag entries are encoded like the metadata of the root tree entry.
+    let tag_entry = serde_json::to_vec(&file_meta).unwrap();
+    let tag_meta = meta("application/vnd.drawbridge.entry.v1+json", &tag_entry);
+
+    for (path, meta, body) in [
+        ("", &tag_meta, tag_entry.as_slice()),
+        ("/tree/test-file.txt", &file_meta, b"text"),
+    ] {
+        let res = srv
+            .send(put(&format!("{path}?dry-run=true"), meta, body))
+            .await;
+        assert_eq!(
+            res.status(),
+            StatusCode::Created,
+            "dry run of `{path}` failed"
+        );
+        assert_eq!(
+            res.header("Content-Digest").map(|v| v.as_str().to_string()),
+            Some(meta.hash.to_string())
+        );
+    }
+
+    let res = srv
+        .send(put("/tree/test-file.txt?dry-run=true", &file_meta, b"txet"))
+        .await;
+    assert_eq!(res.status(), StatusCode::BadRequest);
+
+    let res = srv
+        .send(put("/tree/test-file.txt?dry-run=yes", &file_meta, b"text"))
+        .await;
+    assert_eq!(res.status(), StatusCode::BadRequest);
+
+    let res = srv
+        .send(Request::new(
+            Method::Get,
+            srv.url("/api/v0.1.0/testuser/test-repo/_tag/0.1.0")
+                .as_str(),
+        ))
+        .await;
+    assert_eq!(res.status(), StatusCode::NotFound);
+
+    srv.stop().await;
+    oidc.stop().await;
+}
+
+#[async_std::test]
+async fn public_url() {
+    let _ = tracing_subscriber::fmt::try_init();
+
+    let oidc = Oidc::spawn().await;
+
+    for (url, allow_insecure) in [
+        ("http://drawbridge.example.com", false),
+        ("ftp://drawbridge.example.com", true),
+        ("https://drawbridge.example.com/?foo=bar", false),
+        ("mailto:drawbridge@example.com", false),
+    ] {
+        let store = tempdir().expect("failed to create temporary store directory");
+        let res = App::builder(
+            store.path(),
+            tls_config(),
+            OidcConfig {
+                audience: OIDC_AUDIENCE.to_string(),
+                issuer: oidc.issuer.parse().unwrap(),
+            },
+        )
+        .public_url(url.parse().unwrap())
+        .allow_insecure_public_url(allow_insecure)
+        .build()
+        .await;
+        assert!(res.is_err(), "public URL `{url}` was accepted");
+    }
+
+    for (url, allow_insecure) in [
+        ("https://drawbridge.example.com/", false),
+        ("http://drawbridge.example.com/", true),
+    ] {
+        let srv = Server::spawn(&oidc, |builder| {
+            builder
+                .public_url(url.parse().unwrap())
+                .allow_insecure_public_url(allow_insecure)
+        })
+        .await;
+        assert_eq!(srv.app.public_url().map(|url| url.as_str()), Some(url));
+        srv.stop().await;
+    }
+
+    oidc.stop().await;
+}
+
+#[async_std::test]
+async fn store_failure() {
+    let _ = tracing_subscriber::fmt::try_init();
+
+    let oidc = Oidc::spawn().await;
+    let srv = Server::spawn(&oidc, |builder| builder).await;
+
+    let users = srv._store.path().join("users");
+    {
+        let health = || async {
+            srv.send(Request::new(Method::Get, srv.url("/health").as_str()))
+                .await
+                .status()
+        };
+
+        let watch = srv
+            .app
+            .watch_store(Duration::from_millis(20), StoreFailurePolicy::Serve503);
+        let check = async {
+            assert_eq!(health().await, StatusCode::Ok);
+
+            std::fs::remove_dir(&users).unwrap();
+            async_std::task::sleep(Duration::from_millis(500)).await;
+            assert!(!srv.app.is_store_available());
+            assert_eq!(health().await, StatusCode::ServiceUnavailable);
+
+            std::fs::create_dir(&users).unwrap();
+            async_std::task::sleep(Duration::from_millis(500)).await;
+            assert!(srv.app.is_store_available());
+            assert_eq!(health().await, StatusCode::Ok);
+        };
+        futures::pin_mut!(watch, check);
+        match futures::future::select(watch, check).await {
+            futures::future::Either::Left((res, _)) => panic!("store watch returned: {res:?}"),
+            futures::future::Either::Right(((), _)) => {}
+        }
+    }
+
+    std::fs::remove_dir(&users).unwrap();
+    assert!(srv
+        .app
+        .watch_store(Duration::from_millis(20), StoreFailurePolicy::Exit)
+        .await
+        .is_err());
+
+    srv.stop().await;
+    oidc.stop().await;
+}
+
+#[async_std::test]
+async fn tag_precondition() {
+    let _ = tracing_subscriber::fmt::try_init();
+
+    let oidc = Oidc::spawn().await;
+
+    const SUBJECT: &str = "test|tag-precondition";
+    let oidc_token = oidc.token(&oidc.claims(SUBJECT));
+
+    let srv = Server::spawn(&oidc, |builder| builder).await;
+
+    let cl = srv.client();
+    let token = oidc_token.clone();
+    let cl = spawn_blocking(move || async move {
+        let oidc_cl = cl.token(token).build().unwrap();
+
+        let oidc_user = oidc_cl.user(&"testuser".parse().unwrap());
+        assert!(oidc_user
+            .create(&UserRecord {
+                subject: SUBJECT.into(),
+            })
+            .expect("failed to create user"));
+        assert!(oidc_user
+            .repository(&"test-repo".parse().unwrap())
+            .create(&RepositoryConfig { public: true })
+            .expect("failed to create repository"));
+    });
+    assert!(matches!(cl.await.await, ()));
+
+    let meta = |mime: &str, body: &[u8]| {
+        Algorithms::default()
+            .read_sync(body)
+            .map(|(size, hash)| Meta {
+                hash,
+                size,
+                mime: mime.parse().unwrap(),
+            })
+            .unwrap()
+    };
+    let entry = |content: &[u8]| {
+        let entry = serde_json::to_vec(&meta("application/octet-stream", content)).unwrap();
+        let meta = meta("application/vnd.drawbridge.entry.v1+json", &entry);
+        (entry, meta)
+    };
+    let put = |name: &str, (entry, meta): &(Vec<u8>, Meta), precondition: (&str, String)| {
+        let mut req = Request::new(
+            Method::Put,
+            srv.url(&format!("/api/v0.1.0/testuser/test-repo/_tag/{name}"))
+                .as_str(),
+        );
+        req.set_body(entry.as_slice());
+        req.insert_header("Authorization", format!("Bearer {oidc_token}"));
+        req.insert_header("Content-Type", meta.mime.to_string());
+        req.insert_header("Content-Digest", meta.hash.to_string());
+        req.insert_header(precondition.0, precondition.1);
+        srv.send(req)
+    };
+
+    let (foo, bar) = (entry(b"foo"), entry(b"bar"));
+    let etag = |(_, meta): &(Vec<u8>, Meta)| format!(r#""{}""#, meta.hash);
+
+    // Tags, which do not exist, do not match.
+    let res = put("0.1.0", &foo, ("If-Match", "*".into())).await;
+    assert_eq!(res.status(), StatusCode::PreconditionFailed);
+    let res = put("0.1.0", &foo, ("If-Match", etag(&foo))).await;
+    assert_eq!(res.status(), StatusCode::PreconditionFailed);
+
+    let res = put("0.1.0", &foo, ("If-None-Match", "*".into())).await;
+    assert_eq!(res.status(), StatusCode::Created);
+    let res = put("0.1.0", &foo, ("If-None-Match", "*".into())).await;
+    assert_eq!(res.status(), StatusCode::PreconditionFailed);
+
+    let res = put("0.1.0", &foo, ("If-Match", etag(&bar))).await;
+    assert_eq!(res.status(), StatusCode::PreconditionFailed);
+    let res = put("0.1.0", &foo, ("If-Match", etag(&foo))).await;
+    assert_eq!(res.status(), StatusCode::Ok);
+
+    let res = put("0.1.0", &foo, ("If-Match", r#""invalid""#.into())).await;
+    assert_eq!(res.status(), StatusCode::BadRequest);
+
+    srv.stop().await;
+    oidc.stop().await;
+}
+
+#[async_std::test]
+async fn authorization_decisions() {
+    let _ = tracing_subscriber::fmt::try_init();
+
+    let oidc = Oidc::spawn().await;
+
+    const SUBJECT: &str = "test|authz";
+    let oidc_token = oidc.token(&oidc.claims(SUBJECT));
+    let other_token = oidc.token(&oidc.claims("test|authz-other"));
+    let unscoped_token = oidc.token(&TokenClaims {
+        scope: "openid".into(),
+        ..oidc.claims(SUBJECT)
+    });
+
+    let srv = Server::spawn(&oidc, |builder| builder).await;
+
+    let user_url = srv.url("/api/v0.1.0/testuser");
+    let request = |method, token: Option<&str>| {
+        let mut req = Request::new(method, user_url.as_str());
+        if let Some(token) = token {
+            req.insert_header("Authorization", format!("Bearer {token}"));
+        }
+        req
+    };
+
+    let mut req = request(Method::Put, Some(&oidc_token));
+    req.set_body(Body::from_json(&json!({ "subject": SUBJECT })).unwrap());
+    assert_eq!(srv.send(req).await.status(), StatusCode::Created);
+
+    for (token, status) in [
+        (Some(oidc_token.as_str()), StatusCode::Ok),
+        (None, StatusCode::Unauthorized),
+        (Some("invalid"), StatusCode::Unauthorized),
+        (Some(other_token.as_str()), StatusCode::Unauthorized),
+        (Some(unscoped_token.as_str()), StatusCode::Unauthorized),
+    ] {
+        assert_eq!(srv.send(request(Method::Get, token)).await.status(), status);
+    }
+
+    let metrics = srv.app.metrics();
+    for (decision, count) in [
+        (AuthDecision::GrantedViaCert, 0),
+        (AuthDecision::GrantedViaOidc, 2),
+        (AuthDecision::GrantedPublic, 0),
+        (AuthDecision::DeniedNoAuth, 2),
+        (AuthDecision::DeniedInsufficientScope, 1),
+        (AuthDecision::DeniedAcl, 1),
+    ] {
+        assert_eq!(
+            metrics.authorization_decisions(decision),
+            count,
+            "unexpected count of `{decision}` decisions"
+        );
+    }
+
+    srv.stop().await;
+    oidc.stop().await;
+}
+
+#[async_std::test]
+async fn manifest_validation() {
+    let _ = tracing_subscriber::fmt::try_init();
+
+    let oidc = Oidc::spawn().await;
+
+    const SUBJECT: &str = "test|manifest-validation";
+    let oidc_token = oidc.token(&oidc.claims(SUBJECT));
+
+    let schema: ManifestSchema = r#"{
+        "type": "object",
+        "required": ["license"],
+        "properties": { "license": { "type": "string", "minLength": 1 } }
+    }"#
+    .parse()
+    .unwrap();
+    let srv = Server::spawn(&oidc, |builder| builder.manifest_schema(schema)).await;
+
+    let cl = srv.client();
+    let token = oidc_token.clone();
+    let cl = spawn_blocking(move || async move {
+        let oidc_cl = cl.token(token).build().unwrap();
+
+        let oidc_user = oidc_cl.user(&"testuser".parse().unwrap());
+        assert!(oidc_user
+            .create(&UserRecord {
+                subject: SUBJECT.into(),
+            })
+            .expect("failed to create user"));
+        assert!(oidc_user
+            .repository(&"test-repo".parse().unwrap())
+            .create(&RepositoryConfig { public: false })
+            .expect("failed to create repository"));
+    });
+    assert!(matches!(cl.await.await, ()));
+
+    let meta = |mime: &str, body: &[u8]| {
+        Algorithms::default()
+            .read_sync(body)
+            .map(|(size, hash)| Meta {
+                hash,
+                size,
+                mime: mime.parse().unwrap(),
+            })
+            .unwrap()
+    };
+    let put = |name: &str, license: Option<&str>| {
+        let entry = serde_json::to_vec(&TreeEntry {
+            meta: meta("application/octet-stream", b"foo"),
+            custom: license
+                .map(|license| ("license".into(), license.into()))
+                .into_iter()
+                .collect(),
+            content: (),
+        })
+        .unwrap();
+        let meta = meta("application/vnd.drawbridge.entry.v1+json", &entry);
+
+        let mut req = Request::new(
+            Method::Put,
+            srv.url(&format!("/api/v0.1.0/testuser/test-repo/_tag/{name}"))
+                .as_str(),
+        );
+        req.set_body(entry);
+        req.insert_header("Authorization", format!("Bearer {oidc_token}"));
+        req.insert_header("Content-Type", meta.mime.to_string());
+        req.insert_header("Content-Digest", meta.hash.to_string());
+        srv.send(req)
+    };
+
+    {
+        let mut res = put("0.1.0", None).await;
+        assert_eq!(res.status(), StatusCode::BadRequest);
+        assert_eq!(
+            res.content_type().map(|mime| mime.essence().to_string()),
+            Some("application/problem+json".into())
+        );
+        let problem: serde_json::Value = res.body_json().await.unwrap();
+        assert_eq!(
+            problem["errors"],
+            json!(["`/` is missing required property `license`"])
+        );
+
+        let mut res = put("0.1.0", Some("")).await;
+        assert_eq!(res.status(), StatusCode::BadRequest);
+        let problem: serde_json::Value = res.body_json().await.unwrap();
+        assert_eq!(
+            problem["detail"],
+            "`/license` must be at least 1 characters long"
+        );
+
+        assert_eq!(
+            put("0.1.0", Some("MIT")).await.status(),
+            StatusCode::Created
+        );
+    }
+
+    srv.stop().await;
+    oidc.stop().await;
+}
+
+#[async_std::test]
+async fn store_archive() {
+    let _ = tracing_subscriber::fmt::try_init();
+
+    let oidc = Oidc::spawn().await;
+
+    const SUBJECT: &str = "test|store-archive";
+    let oidc_token = oidc.token(&oidc.claims(SUBJECT));
+
+    let srv = Server::spawn(&oidc, |builder| builder).await;
+
+    let pkg = tempdir().expect("failed to create temporary package directory");
+    try_join!(
+        write(pkg.path().join("test-file"), "no extension"),
+        create_dir(pkg.path().join("test-dir")),
+    )
+    .unwrap();
+    write(pkg.path().join("test-dir").join("test-file.txt"), "text")
+        .await
+        .unwrap();
+
+    let cl = srv.client();
+    let cl = spawn_blocking(move || async move {
+        let oidc_cl = cl.token(oidc_token).build().unwrap();
+
+        let oidc_user = oidc_cl.user(&"testuser".parse().unwrap());
+        assert!(oidc_user
+            .create(&UserRecord {
+                subject: SUBJECT.into(),
+            })
+            .expect("failed to create user"));
+        let oidc_repo = oidc_user.repository(&"test-repo".parse().unwrap());
+        assert!(oidc_repo
+            .create(&RepositoryConfig { public: true })
+            .expect("failed to create repository"));
+        let (tag_created, _) = oidc_repo
+            .tag(&"0.1.0".parse().unwrap())
+            .create_from_path_unsigned(pkg.path())
+            .expect("failed to create tag");
+        assert!(tag_created);
+    });
+    assert!(matches!(cl.await.await, ()));
+
+    let mut archive = vec![];
+    let exported = export_store(srv._store.path(), &mut archive)
+        .await
+        .expect("failed to export store");
+    let stats = store_stats(srv._store.path(), 2)
+        .await
+        .expect("failed to compute store statistics");
+    srv.stop().await;
+    oidc.stop().await;
+
+    // A user, a repository, a tag and two directories containing two files.
+    assert_eq!(exported.objects, 7);
+
+    let restored = tempdir().expect("failed to create temporary directory");
+    let store = restored.path().join("store");
+    let imported = import_store(&store, archive.as_slice())
+        .await
+        .expect("failed to import store");
+    assert_eq!((imported.created, imported.existing), (7, 0));
+
+    assert_eq!(
+        (stats.users, stats.repositories, stats.tags, stats.objects),
+        (1, 1, 1, 7)
+    );
+    assert_eq!(stats.bytes, exported.bytes);
+    assert_eq!(
+        stats.namespaces,
+        [NamespaceStats {
+            name: "testuser".into(),
+            repositories: 1,
+            tags: 1,
+            objects: 7,
+            bytes: exported.bytes,
+        }]
+    );
+    // Only the requested number of the largest objects is listed.
+    assert_eq!(stats.largest.len(), 2);
+    assert!(stats.largest[0].bytes >= stats.largest[1].bytes);
+    let sizes = stats.largest.iter().map(|object| object.bytes);
+    assert!(sizes.sum::<u64>() <= stats.bytes);
+    assert_eq!(store_stats(&store, 2).await.unwrap(), stats);
+
+    let mut reexported = vec![];
+    assert_eq!(
+        export_store(&store, &mut reexported).await.unwrap(),
+        exported
+    );
+    assert_eq!(reexported, archive);
+
+    // Importing an archive again skips identical objects.
+    let imported = import_store(&store, archive.as_slice())
+        .await
+        .expect("failed to import store");
+    assert_eq!((imported.created, imported.existing), (0, 7));
+
+    // Contents are verified against their digests.
+    let pos = archive
+        .windows(b"no extension".len())
+        .position(|w| w == b"no extension")
+        .unwrap();
+    let mut tampered = archive.clone();
+    tampered[pos] = b'N';
+    let err = import_store(restored.path().join("tampered"), tampered.as_slice())
+        .await
+        .unwrap_err();
+    assert!(
+        format!("{err:#}").contains("does not match its metadata"),
+        "unexpected error: {err:#}"
+    );
+
+    // Differing objects are not overwritten.
+    let meta_path = store.join("users").join("testuser").join("meta.json");
+    let mut meta: Meta = serde_json::from_slice(&async_std::fs::read(&meta_path).await.unwrap())
+        .expect("failed to decode user metadata");
+    meta.size += 1;
+    write(&meta_path, serde_json::to_vec(&meta).unwrap())
+        .await
+        .unwrap();
+    let err = import_store(&store, archive.as_slice()).await.unwrap_err();
+    assert!(
+        format!("{err:#}").contains("already stored with different contents"),
+        "unexpected error: {err:#}"
+    );
+}
+
+#[async_std::test]
+async fn log_exclude_paths() {
+    let _ = tracing_subscriber::fmt::try_init();
+
+    let oidc = Oidc::spawn().await;
+
+    let store = tempdir().expect("failed to create temporary store directory");
+    let res = App::builder(
+        store.path(),
+        tls_config(),
+        OidcConfig {
+            audience: OIDC_AUDIENCE.to_string(),
+            issuer: oidc.issuer.parse().unwrap(),
+        },
+    )
+    .log_exclude_paths(["health"])
+    .build()
+    .await;
+    let err = res.err().expect("relative excluded path was accepted");
+    // Invalid configurations are not classified.
+    assert_eq!(err.downcast_ref::<FailureClass>(), None);
+
+    let srv = Server::spawn(&oidc, |builder| builder.log_exclude_paths(["/health"])).await;
+
+    // Excluded requests are handled as usual.
+    let res = srv
+        .send(Request::new(Method::Get, srv.url("/health").as_str()))
+        .await;
+    assert_eq!(res.status(), StatusCode::Ok);
+    let res = srv
+        .send(Request::new(
+            Method::Get,
+            srv.url("/api/v0.1.0/testuser").as_str(),
+        ))
+        .await;
+    assert_eq!(res.status(), StatusCode::Unauthorized);
+
+    srv.stop().await;
+    oidc.stop().await;
+}
+
+#[async_std::test]
+async fn hide_existence() {
+    let _ = tracing_subscriber::fmt::try_init();
+
+    let oidc = Oidc::spawn().await;
+
+    const SUBJECT: &str = "test|hide-existence";
+    let oidc_token = oidc.token(&oidc.claims(SUBJECT));
+    let other_token = oidc.token(&oidc.claims("test|hide-existence-other"));
+
+    for hide in [false, true] {
+        let srv = Server::spawn(&oidc, |builder| builder.hide_existence(hide)).await;
+
+        let cl = srv.client();
+        let token = oidc_token.clone();
+        let cl = spawn_blocking(move || async move {
+            let oidc_cl = cl.token(token).build().unwrap();
+
+            let oidc_user = oidc_cl.user(&"testuser".parse().unwrap());
+            assert!(oidc_user
+                .create(&UserRecord {
+                    subject: SUBJECT.into(),
+                })
+                .expect("failed to create user"));
+            assert!(oidc_user
+                .repository(&"private-repo".parse().unwrap())
+                .create(&RepositoryConfig { public: false })
+                .expect("failed to create repository"));
+        });
+        assert!(matches!(cl.await.await, ()));
+
+        let get = |path: &str, token: Option<&str>| {
+            let mut req = Request::new(Method::Get, srv.url(path).as_str());
+            if let Some(token) = token {
+                req.insert_header("Authorization", format!("Bearer {token}"));
+            }
+            async {
+                let mut res = srv.send(req).await;
+                let content_type = res.content_type().map(|mime| mime.to_string());
+                (res.status(), content_type, res.body_string().await.unwrap())
+            }
+        };
+
+        {
+            for token in [None, Some(other_token.as_str())] {
+                let existing = get("/api/v0.1.0/testuser/private-repo/_tag", token).await;
+                let missing = get("/api/v0.1.0/testuser/missing-repo/_tag", token).await;
+                assert_eq!(missing.0, StatusCode::NotFound);
+                if hide {
+                    assert_eq!(existing, missing);
+                } else {
+                    assert_eq!(existing.0, StatusCode::Unauthorized);
+                }
+            }
+
+            // Authorized requests are not affected.
+            let res = get("/api/v0.1.0/testuser/private-repo/_tag", Some(&oidc_token)).await;
+            assert_eq!(res.0, StatusCode::Ok);
+        }
+
+        srv.stop().await;
+    }
+    oidc.stop().await;
+}
+
+#[async_std::test]
+async fn signed_urls() {
+    let _ = tracing_subscriber::fmt::try_init();
+
+    let oidc = Oidc::spawn().await;
+
+    const SUBJECT: &str = "test|signed-urls";
+    let oidc_token = oidc.token(&oidc.claims(SUBJECT));
+
+    let srv = Server::spawn(&oidc, |builder| {
+        builder.url_signing_secret("0123456789abcdef")
+    })
+    .await;
+
+    let cl = srv.client();
+    let token = oidc_token.clone();
+    let cl = spawn_blocking(move || async move {
+        let oidc_cl = cl.token(token).build().unwrap();
+
+        let oidc_user = oidc_cl.user(&"testuser".parse().unwrap());
+        assert!(oidc_user
+            .create(&UserRecord {
+                subject: SUBJECT.into(),
+            })
+            .expect("failed to create user"));
+        let oidc_repo = oidc_user.repository(&"private-repo".parse().unwrap());
+        assert!(oidc_repo
+            .create(&RepositoryConfig { public: false })
+            .expect("failed to create repository"));
+        let pkg = tempdir().expect("failed to create temporary package directory");
+        std::fs::write(pkg.path().join("test-file.txt"), "text").unwrap();
+        let (created, _) = oidc_repo
+            .tag(&"0.1.0".parse().unwrap())
+            .create_from_path_unsigned(pkg.path())
+            .expect("failed to create a tag and upload the tree");
+        assert!(created);
+    });
+    assert!(matches!(cl.await.await, ()));
+
+    const TAG: &str = "/api/v0.1.0/testuser/private-repo/_tag/0.1.0";
+    let request = |method, url: &str, token: Option<&str>| {
+        let mut req = Request::new(method, url);
+        if let Some(token) = token {
+            req.insert_header("Authorization", format!("Bearer {token}"));
+        }
+        srv.send(req)
+    };
+
+    {
+        let sign = |path: &str| {
+            let url = srv.url(path);
+            let token = oidc_token.clone();
+            async move {
+                let mut res = request(Method::Post, &url, Some(&token)).await;
+                assert_eq!(res.status(), StatusCode::Ok);
+                let res: serde_json::Value = res.body_json().await.unwrap();
+                res["url"].as_str().unwrap().to_string()
+            }
+        };
+
+        let url = sign(TAG).await;
+        assert!(url.starts_with(&srv.url(&format!("{TAG}?expires="))));
+
+        // Signed URLs grant anonymous access to the signed object only.
+        assert_eq!(
+            request(Method::Get, &url, None).await.status(),
+            StatusCode::Ok
+        );
+        assert_eq!(
+            request(Method::Get, &srv.url(TAG), None).await.status(),
+            StatusCode::Unauthorized
+        );
+        let (_, query) = url.split_once('?').unwrap();
+        let other = srv.url(&format!("{TAG}/tree?{query}"));
+        assert_eq!(
+            request(Method::Get, &other, None).await.status(),
+            StatusCode::Forbidden
+        );
+
+        let tree = sign(&format!("{TAG}/tree/test-file.txt")).await;
+        assert_eq!(
+            request(Method::Get, &tree, None).await.status(),
+            StatusCode::Ok
+        );
+
+        // Tampered signatures and expiries are rejected.
+        let tampered = format!("{}0", url.trim_end_matches(char::is_alphanumeric));
+        assert_eq!(
+            request(Method::Get, &tampered, None).await.status(),
+            StatusCode::Forbidden
+        );
+        let (head, signature) = query.split_once("&signature=").unwrap();
+        let expires: u64 = head.trim_start_matches("expires=").parse().unwrap();
+        let extended = srv.url(&format!(
+            "{TAG}?expires={}&signature={signature}",
+            expires + 1
+        ));
+        assert_eq!(
+            request(Method::Get, &extended, None).await.status(),
+            StatusCode::Forbidden
+        );
+        let expired = srv.url(&format!("{TAG}?expires=1&signature={signature}"));
+        assert_eq!(
+            request(Method::Get, &expired, None).await.status(),
+            StatusCode::Forbidden
+        );
+
+        // Signing requires authentication and a valid lifetime.
+        assert_eq!(
+            request(Method::Post, &srv.url(TAG), None).await.status(),
+            StatusCode::Unauthorized
+        );
+        for lifetime in ["0", "invalid", "604801"] {
+            let url = srv.url(&format!("{TAG}?expires-in={lifetime}"));
+            assert_eq!(
+                request(Method::Post, &url, Some(&oidc_token))
+                    .await
+                    .status(),
+                StatusCode::BadRequest
+            );
+        }
+    }
+
+    let metrics = srv.app.metrics();
+    assert_eq!(
+        metrics.authorization_decisions(AuthDecision::GrantedViaSignedUrl),
+        2
+    );
+    srv.stop().await;
+
+    let srv = Server::spawn(&oidc, |builder| builder).await;
+    let res = srv
+        .send({
+            let mut req = Request::new(Method::Post, srv.url(TAG).as_str());
+            req.insert_header("Authorization", format!("Bearer {oidc_token}"));
+            req
+        })
+        .await;
+    assert_eq!(res.status(), StatusCode::NotImplemented);
+    srv.stop().await;
+
+    let res = App::builder(
+        tempdir().unwrap().path().to_path_buf(),
+        tls_config(),
+        OidcConfig {
+            audience: OIDC_AUDIENCE.to_string(),
+            issuer: oidc.issuer.parse().unwrap(),
+        },
+    )
+    .url_signing_secret("short")
+    .build()
+    .await;
+    assert!(res.is_err());
+
+    oidc.stop().await;
+}
+
+#[async_std::test]
+async fn response_streaming() {
+    let _ = tracing_subscriber::fmt::try_init();
+
+    let oidc = Oidc::spawn().await;
+
+    const SUBJECT: &str = "test|response-streaming";
+    let oidc_token = oidc.token(&oidc.claims(SUBJECT));
+
+    let srv = Server::spawn(&oidc, |builder| builder.response_buffer_bytes(16)).await;
+
+    let content: Vec<u8> = (0..1000).map(|i| (i % 251) as u8).collect();
+    let cl = srv.client();
+    let token = oidc_token.clone();
+    let file = content.clone();
+    let cl = spawn_blocking(move || async move {
+        let oidc_cl = cl.token(token).build().unwrap();
+
+        let oidc_user = oidc_cl.user(&"testuser".parse().unwrap());
+        assert!(oidc_user
+            .create(&UserRecord {
+                subject: SUBJECT.into(),
+            })
+            .expect("failed to create user"));
+        let oidc_repo = oidc_user.repository(&"test-repo".parse().unwrap());
+        assert!(oidc_repo
+            .create(&RepositoryConfig { public: true })
+            .expect("failed to create repository"));
+
+        let pkg = tempdir().expect("failed to create temporary package directory");
+        write(pkg.path().join("large-file"), file).await.unwrap();
+        write(pkg.path().join("small-file"), "small").await.unwrap();
+        let (tag_created, _) = oidc_repo
+            .tag(&"0.1.0".parse().unwrap())
+            .create_from_path_unsigned(pkg.path())
+            .expect("failed to create a tag and upload the tree");
+        assert!(tag_created);
+    });
+    assert!(matches!(cl.await.await, ()));
+
+    // Streamed objects carry their length just like the ones read at once.
+    for (name, expected) in [("large-file", content.as_slice()), ("small-file", b"small")] {
+        let url = srv.url(&format!(
+            "/api/v0.1.0/testuser/test-repo/_tag/0.1.0/tree/{name}"
+        ));
+        let mut res = srv.send(Request::new(Method::Get, url.as_str())).await;
+        assert_eq!(res.status(), StatusCode::Ok);
+        assert_eq!(
+            res.header("Content-Length").map(|v| v.as_str()),
+            Some(expected.len().to_string().as_str())
+        );
+        assert!(res.header("Transfer-Encoding").is_none());
+        assert_eq!(res.body_bytes().await.unwrap(), expected);
+    }
+
+    srv.stop().await;
+
+    let res = App::builder(
+        tempdir().unwrap().path().to_path_buf(),
+        tls_config(),
+        OidcConfig {
+            audience: OIDC_AUDIENCE.to_string(),
+            issuer: oidc.issuer.parse().unwrap(),
+        },
+    )
+    .response_buffer_bytes(0)
+    .build()
+    .await;
+    assert!(res.is_err());
+
+    oidc.stop().await;
+}
+
+#[async_std::test]
+async fn resumable_uploads() {
+    let _ = tracing_subscriber::fmt::try_init();
+
+    let oidc = Oidc::spawn().await;
+
+    const SUBJECT: &str = "test|resumable-uploads";
+    let oidc_token = oidc.token(&oidc.claims(SUBJECT));
+
+    let srv = Server::spawn(&oidc, |builder| builder).await;
+
+    let cl = srv.client();
+    let token = oidc_token.clone();
+    let cl = spawn_blocking(move || async move {
+        let oidc_cl = cl.token(token).build().unwrap();
+
+        let oidc_user = oidc_cl.user(&"testuser".parse().unwrap());
+        assert!(oidc_user
+            .create(&UserRecord {
+                subject: SUBJECT.into(),
+            })
+            .expect("failed to create user"));
+        let oidc_repo = oidc_user.repository(&"test-repo".parse().unwrap());
+        assert!(oidc_repo
+            .create(&RepositoryConfig { public: true })
+            .expect("failed to create repository"));
+
+        let pkg = tempdir().expect("failed to create temporary package directory");
+        write(pkg.path().join("file.txt"), "text").await.unwrap();
+        let (tag_created, _) = oidc_repo
+            .tag(&"0.1.0".parse().unwrap())
+            .create_from_path_unsigned(pkg.path())
+            .expect("failed to create a tag and upload the tree");
+        assert!(tag_created);
+    });
+    assert!(matches!(cl.await.await, ()));
+
+    let request = |method, path: &str| {
+        let mut req = Request::new(method, srv.url(path).as_str());
+        req.insert_header("Authorization", format!("Bearer {oidc_token}"));
+        req
+    };
+    let range = |res: &Response| res.header("Range").map(|v| v.as_str().to_string());
+
+    // Sessions are only initiated by authorized users of existing repositories.
+    const UPLOADS: &str = "/api/v0.1.0/testuser/test-repo/_upload";
+    let res = srv
+        .send(Request::new(Method::Post, srv.url(UPLOADS).as_str()))
+        .await;
+    assert_eq!(res.status(), StatusCode::Unauthorized);
+    let res = srv
+        .send(request(
+            Method::Post,
+            "/api/v0.1.0/testuser/other-repo/_upload",
+        ))
+        .await;
+    assert_eq!(res.status(), StatusCode::NotFound);
+
+    let res = srv.send(request(Method::Post, UPLOADS)).await;
+    assert_eq!(res.status(), StatusCode::Accepted);
+    assert_eq!(range(&res), None);
+    let session = res.header("Location").unwrap().as_str().to_string();
+    assert!(session.starts_with(&format!("{UPLOADS}/")), "{session}");
+
+    let patch = |body: &'static str, content_range: Option<&str>| {
+        let mut req = request(Method::Patch, &session);
+        req.set_body(body);
+        if let Some(content_range) = content_range {
+            req.insert_header("Content-Range", content_range);
+        }
+        srv.send(req)
+    };
+    let res = patch("hello", Some("0-4")).await;
+    assert_eq!(res.status(), StatusCode::Accepted);
+    assert_eq!(res.header("Location").unwrap().as_str(), session);
+    assert_eq!(range(&res).as_deref(), Some("0-4"));
+
+    // Chunks must continue at the current offset and match their range.
+    let res = patch("hello", Some("0-4")).await;
+    assert_eq!(res.status(), StatusCode::RequestedRangeNotSatisfiable);
+    assert_eq!(range(&res).as_deref(), Some("0-4"));
+    let res = patch(", world", Some("5-7")).await;
+    assert_eq!(res.status(), StatusCode::BadRequest);
+    assert_eq!(range(&res).as_deref(), Some("0-4"));
+
+    // Progress is reported, such that interrupted uploads can be resumed.
+    let res = srv.send(request(Method::Get, &session)).await;
+    assert_eq!(res.status(), StatusCode::NoContent);
+    assert_eq!(range(&res).as_deref(), Some("0-4"));
+
+    let res = patch(", world", None).await;
+    assert_eq!(res.status(), StatusCode::Accepted);
+    assert_eq!(range(&res).as_deref(), Some("0-11"));
+
+    let finalize = |content: &str, tag: &str| {
+        let meta = Algorithms::default()
+            .read_sync(content.as_bytes())
+            .map(|(size, hash)| Meta {
+                hash,
+                size,
+                mime: "text/plain".parse().unwrap(),
+            })
+            .unwrap();
+        let mut req = request(Method::Put, &session);
+        req.set_body(json!({
+            "tag": tag,
+            "path": "upload.txt",
+            "digest": meta.hash,
+            "length": meta.size,
+            "type": "text/plain",
+        }));
+        srv.send(req)
+    };
+    // Contents are verified before they are stored.
+    let res = finalize("hello, world", "invalid").await;
+    assert_eq!(res.status(), StatusCode::BadRequest);
+    let res = finalize("hello", "0.1.0").await;
+    assert_eq!(res.status(), StatusCode::BadRequest);
+    let res = finalize("hello, there", "0.1.0").await;
+    assert_eq!(res.status(), StatusCode::BadRequest);
+    let res = finalize("hello, world", "0.1.0").await;
+    assert_eq!(res.status(), StatusCode::Created);
+
+    let mut res = srv
+        .send(Request::new(
+            Method::Get,
+            srv.url("/api/v0.1.0/testuser/test-repo/_tag/0.1.0/tree/upload.txt")
+                .as_str(),
+        ))
+        .await;
+    assert_eq!(res.status(), StatusCode::Ok);
+    assert_eq!(res.body_string().await.unwrap(), "hello, world");
+
+    // Sessions are closed once their contents are stored.
+    let res = srv.send(request(Method::Get, &session)).await;
+    assert_eq!(res.status(), StatusCode::NotFound);
+
+    // Sessions can be cancelled.
+    let res = srv.send(request(Method::Post, UPLOADS)).await;
+    assert_eq!(res.status(), StatusCode::Accepted);
+    let session = res.header("Location").unwrap().as_str().to_string();
+    let res = srv.send(request(Method::Delete, &session)).await;
+    assert_eq!(res.status(), StatusCode::NoContent);
+    let res = srv.send(request(Method::Get, &session)).await;
+    assert_eq!(res.status(), StatusCode::NotFound);
+
+    srv.stop().await;
+
+    let res = App::builder(
+        tempdir().unwrap().path().to_path_buf(),
+        tls_config(),
+        OidcConfig {
+            audience: OIDC_AUDIENCE.to_string(),
+            issuer: oidc.issuer.parse().unwrap(),
+        },
+    )
+    .upload_session_ttl(Duration::ZERO)
+    .build()
+    .await;
+    assert!(res.is_err());
+
+    oidc.stop().await;
+}
+
+#[async_std::test]
+async fn upload_limits() {
+    let _ = tracing_subscriber::fmt::try_init();
+
+    let oidc = Oidc::spawn().await;
+
+    const SUBJECT: &str = "test|upload-limits";
+    let oidc_token = oidc.token(&oidc.claims(SUBJECT));
+
+    let srv = Server::spawn(&oidc, |builder| {
+        builder
+            .max_upload_sessions_per_repo(1)
+            .max_upload_session_size(8)
+    })
+    .await;
+
+    let request = |method, path: &str| {
+        let mut req = Request::new(method, srv.url(path).as_str());
+        req.insert_header("Authorization", format!("Bearer {oidc_token}"));
+        req
+    };
+    let range = |res: &Response| res.header("Range").map(|v| v.as_str().to_string());
+
+    let mut req = request(Method::Put, "/api/v0.1.0/testuser");
+    req.set_body(json!({ "subject": SUBJECT }));
+    assert_eq!(srv.send(req).await.status(), StatusCode::Created);
+    for repo in ["test-repo", "other-repo"] {
+        let mut req = request(Method::Put, &format!("/api/v0.1.0/testuser/{repo}"));
+        req.set_body(json!({ "public": true }));
+        assert_eq!(srv.send(req).await.status(), StatusCode::Created);
+    }
+
+    // Open sessions are limited per repository.
+    const UPLOADS: &str = "/api/v0.1.0/testuser/test-repo/_upload";
+    let res = srv.send(request(Method::Post, UPLOADS)).await;
+    assert_eq!(res.status(), StatusCode::Accepted);
+    let session = res.header("Location").unwrap().as_str().to_string();
+    let mut res = srv.send(request(Method::Post, UPLOADS)).await;
+    assert_eq!(res.status(), StatusCode::Conflict);
+    let problem: serde_json::Value = res.body_json().await.unwrap();
+    assert_eq!(problem["quota"], "upload-sessions");
+    assert_eq!(problem["limit"], 1);
+    let res = srv
+        .send(request(
+            Method::Post,
+            "/api/v0.1.0/testuser/other-repo/_upload",
+        ))
+        .await;
+    assert_eq!(res.status(), StatusCode::Accepted);
+
+    // Chunks exceeding the maximum session size are discarded.
+    let patch = |body: Body| {
+        let mut req = request(Method::Patch, &session);
+        req.set_body(body);
+        srv.send(req)
+    };
+    let res = patch(Body::from_string("hello".into())).await;
+    assert_eq!(res.status(), StatusCode::Accepted);
+    let mut res = patch(Body::from_string(", world".into())).await;
+    assert_eq!(res.status(), StatusCode::PayloadTooLarge);
+    assert_eq!(range(&res).as_deref(), Some("0-4"));
+    let problem: serde_json::Value = res.body_json().await.unwrap();
+    assert_eq!(problem["quota"], "upload-size");
+    assert_eq!(problem["limit"], 8);
+    assert_eq!(problem["usage"], 5);
+    // Chunks of unknown length are discarded once they exceed the size.
+    let res = patch(Body::from_reader(
+        futures::io::BufReader::new(futures::io::Cursor::new(b", world".to_vec())),
+        None,
+    ))
+    .await;
+    assert_eq!(res.status(), StatusCode::PayloadTooLarge);
+    assert_eq!(range(&res).as_deref(), Some("0-4"));
+    let res = patch(Body::from_string(", w".into())).await;
+    assert_eq!(res.status(), StatusCode::Accepted);
+    assert_eq!(range(&res).as_deref(), Some("0-7"));
+
+    // Sessions can be initiated again once others are closed.
+    let res = srv.send(request(Method::Delete, &session)).await;
+    assert_eq!(res.status(), StatusCode::NoContent);
+    let res = srv.send(request(Method::Post, UPLOADS)).await;
+    assert_eq!(res.status(), StatusCode::Accepted);
+
+    srv.stop().await;
+    oidc.stop().await;
+}
+
+#[async_std::test]
+async fn head_requests() {
+    let _ = tracing_subscriber::fmt::try_init();
+
+    let oidc = Oidc::spawn().await;
+
+    const SUBJECT: &str = "test|head-requests";
+    let oidc_token = oidc.token(&oidc.claims(SUBJECT));
+
+    let srv = Server::spawn(&oidc, |builder| builder).await;
+
+    let cl = srv.client();
+    let cl = spawn_blocking(move || async move {
+        let oidc_cl = cl.token(oidc_token).build().unwrap();
+
+        let oidc_user = oidc_cl.user(&"testuser".parse().unwrap());
+        assert!(oidc_user
+            .create(&UserRecord {
+                subject: SUBJECT.into(),
+            })
+            .expect("failed to create user"));
+        let oidc_repo = oidc_user.repository(&"test-repo".parse().unwrap());
+        assert!(oidc_repo
+            .create(&RepositoryConfig { public: true })
+            .expect("failed to create repository"));
+        assert!(oidc_user
+            .repository(&"private-repo".parse().unwrap())
+            .create(&RepositoryConfig { public: false })
+            .expect("failed to create repository"));
+
+        let pkg = tempdir().expect("failed to create temporary package directory");
+        write(pkg.path().join("file.txt"), "text").await.unwrap();
+        let (tag_created, _) = oidc_repo
+            .tag(&"0.1.0".parse().unwrap())
+            .create_from_path_unsigned(pkg.path())
+            .expect("failed to create a tag and upload the tree");
+        assert!(tag_created);
+    });
+    assert!(matches!(cl.await.await, ()));
+
+    {
+        let srv = &srv;
+
+        // Returns the status line and headers, except `Date`, of the response to a request sent
+        // with `method` to `path` and the body following them.
+        let send = |method: &'static str, path: &'static str| async move {
+            let mut stream = srv.connect().await;
+            stream
+                .write_all(
+                    format!(
+                        "{method} {path} HTTP/1.1\r\n\
+                        Host: localhost\r\n\
+                        Connection: close\r\n\r\n"
+                    )
+                    .as_bytes(),
+                )
+                .await
+                .unwrap();
+            let head = read_head(&mut stream).await;
+            let mut lines = head.lines().filter(|line| !line.is_empty());
+            let status = lines.next().unwrap().to_string();
+            let headers: HashMap<String, String> = lines
+                .filter_map(|line| line.split_once(':'))
+                .map(|(name, value)| (name.to_ascii_lowercase(), value.trim().to_string()))
+                .filter(|(name, _)| name != "date")
+                .collect();
+            let mut body = vec![];
+            _ = stream.read_to_end(&mut body).await.unwrap();
+            (status, headers, body)
+        };
+
+        for (path, expected) in [
+            ("/api/v0.1.0/testuser/test-repo/_tag/0.1.0", None),
+            (
+                "/api/v0.1.0/testuser/test-repo/_tag/0.1.0/tree/file.txt",
+                Some(&b"text"[..]),
+            ),
+        ] {
+            let (get_status, get_headers, get_body) = send("GET", path).await;
+            let (head_status, head_headers, head_body) = send("HEAD", path).await;
+            assert!(get_status.starts_with("HTTP/1.1 200 "), "{get_status}");
+            assert_eq!(head_status, get_status);
+            assert_eq!(head_headers, get_headers);
+            for name in ["content-length", "content-type", "etag", "last-modified"] {
+                assert!(
+                    head_headers.contains_key(name),
+                    "`{name}` missing for `{path}`"
+                );
+            }
+            assert_eq!(
+                head_headers["content-length"],
+                get_body.len().to_string(),
+                "invalid length for `{path}`"
+            );
+            if let Some(expected) = expected {
+                assert_eq!(get_body, expected);
+            }
+            assert!(
+                head_body.is_empty(),
+                "HEAD response for `{path}` has a body"
+            );
+        }
+
+        // HEAD requests are authorized just like GET requests.
+        let (status, ..) = send("HEAD", "/api/v0.1.0/testuser/private-repo/_tag/0.1.0").await;
+        assert!(status.starts_with("HTTP/1.1 401 "), "{status}");
+    }
+
+    srv.stop().await;
+    oidc.stop().await;
+}
+
+#[async_std::test]
+async fn authz_webhook() {
+    let _ = tracing_subscriber::fmt::try_init();
+
+    let oidc = Oidc::spawn().await;
+
+    const SUBJECT: &str = "test|authz-webhook";
+    const DENIED_SUBJECT: &str = "test|authz-webhook-denied";
+    let oidc_token = oidc.token(&oidc.claims(SUBJECT));
+    let denied_token = oidc.token(&oidc.claims(DENIED_SUBJECT));
+
+    // Mock webhook allowing access to `SUBJECT` only, which records all queries.
+    let webhook_lis = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+        .await
+        .expect("failed to bind to address");
+    let webhook_addr = webhook_lis.local_addr().unwrap();
+    let queries = Arc::new(std::sync::Mutex::new(Vec::<serde_json::Value>::new()));
+    let (webhook_tx, webhook_rx) = channel::<()>();
+    let webhook = spawn({
+        let queries = Arc::clone(&queries);
+        async move {
+            webhook_lis
+                .incoming()
+                .take_until(webhook_rx)
+                .for_each_concurrent(None, |stream| {
+                    let queries = Arc::clone(&queries);
+                    async move {
+                        async_h1::accept(
+                            stream.expect("failed to initialize stream"),
+                            |mut req| {
+                                let queries = Arc::clone(&queries);
+                                async move {
+                                    let query: serde_json::Value = req.body_json().await?;
+                                    let allow = query["subject"]["id"] == SUBJECT;
+                                    queries.lock().unwrap().push(query);
+                                    let mut res = Response::new(StatusCode::Ok);
+                                    res.set_body(Body::from_json(&json!({ "allow": allow }))?);
+                                    Ok(res)
+                                }
+                            },
+                        )
+                        .await
+                        .expect("failed to handle webhook connection");
+                    }
+                })
+                .await
+        }
+    });
+
+    let srv = Server::spawn(&oidc, |builder| {
+        builder
+            .authz_webhook(format!("http://{webhook_addr}/authz").parse().unwrap())
+            .authz_cache_ttl(Duration::from_secs(60))
+    })
+    .await;
+
+    let request = |method, path: &str, token: &str, body: Option<serde_json::Value>| {
+        let mut req = Request::new(method, srv.url(path).as_str());
+        req.insert_header("Authorization", format!("Bearer {token}"));
+        if let Some(body) = body {
+            req.set_body(Body::from_json(&body).unwrap());
+        }
+        srv.send(req)
+    };
+
+    {
+        let res = request(
+            Method::Put,
+            "/api/v0.1.0/testuser",
+            &oidc_token,
+            Some(json!({ "subject": SUBJECT })),
+        )
+        .await;
+        assert_eq!(res.status(), StatusCode::Created);
+        for _ in 0..2 {
+            let res = request(Method::Get, "/api/v0.1.0/testuser", &oidc_token, None).await;
+            assert_eq!(res.status(), StatusCode::Ok);
+        }
+
+        // Requests passing the built-in checks are denied by the webhook.
+        let res = request(
+            Method::Put,
+            "/api/v0.1.0/denieduser",
+            &denied_token,
+            Some(json!({ "subject": DENIED_SUBJECT })),
+        )
+        .await;
+        assert_eq!(res.status(), StatusCode::Forbidden);
+
+        // Requests failing the built-in checks do not reach the webhook.
+        let res = request(Method::Get, "/api/v0.1.0/testuser", &denied_token, None).await;
+        assert_eq!(res.status(), StatusCode::Unauthorized);
+    }
+
+    // Repeated queries are answered from the cache.
+    assert_eq!(
+        *queries.lock().unwrap(),
+        [
+            json!({
+                "subject": { "type": "oidc", "id": SUBJECT },
+                "operation": "write",
+                "resource": "/api/v0.1.0/testuser",
+            }),
+            json!({
+                "subject": { "type": "oidc", "id": SUBJECT },
+                "operation": "read",
+                "resource": "/api/v0.1.0/testuser",
+            }),
+            json!({
+                "subject": { "type": "oidc", "id": DENIED_SUBJECT },
+                "operation": "write",
+                "resource": "/api/v0.1.0/denieduser",
+            }),
+        ]
+    );
+    srv.stop().await;
+    assert_eq!(webhook_tx.send(()), Ok(()));
+    webhook.await;
+
+    // Requests are denied if the webhook cannot be reached.
+    let srv = Server::spawn(&oidc, |builder| {
+        builder.authz_webhook(format!("http://{webhook_addr}/authz").parse().unwrap())
+    })
+    .await;
+    let mut req = Request::new(Method::Put, srv.url("/api/v0.1.0/testuser").as_str());
+    req.insert_header("Authorization", format!("Bearer {oidc_token}"));
+    req.set_body(Body::from_json(&json!({ "subject": SUBJECT })).unwrap());
+    assert_eq!(srv.send(req).await.status(), StatusCode::ServiceUnavailable);
+    srv.stop().await;
+
+    oidc.stop().await;
+}
+
+#[async_std::test]
+async fn server_header() {
+    let _ = tracing_subscriber::fmt::try_init();
+
+    let oidc = Oidc::spawn().await;
+
+    let srv = Server::spawn(&oidc, |builder| builder).await;
+    // The header is also sent in error responses, e.g. for unauthenticated requests and
+    // unsupported methods.
+    for (method, path, status) in [
+        (Method::Get, "/health", StatusCode::Ok),
+        (
+            Method::Get,
+            "/api/v0.1.0/testuser",
+            StatusCode::Unauthorized,
+        ),
+        (
+            Method::Delete,
+            "/api/v0.1.0/testuser",
+            StatusCode::MethodNotAllowed,
+        ),
+    ] {
+        let res = srv.send(Request::new(method, srv.url(path).as_str())).await;
+        assert_eq!(res.status(), status);
+        assert_eq!(
+            res.header("Server").map(|v| v.as_str()),
+            Some(DEFAULT_SERVER_HEADER)
+        );
+    }
+    assert!(DEFAULT_SERVER_HEADER.starts_with("drawbridge/"));
+    srv.stop().await;
+
+    let srv = Server::spawn(&oidc, |builder| {
+        builder.server_header(Some("test-server".into()))
+    })
+    .await;
+    let res = srv
+        .send(Request::new(
+            Method::Get,
+            srv.url("/api/v0.1.0/testuser").as_str(),
+        ))
+        .await;
+    assert_eq!(res.status(), StatusCode::Unauthorized);
+    assert_eq!(
+        res.header("Server").map(|v| v.as_str()),
+        Some("test-server")
+    );
+    srv.stop().await;
+
+    let srv = Server::spawn(&oidc, |builder| builder.server_header(None)).await;
+    let res = srv
+        .send(Request::new(Method::Get, srv.url("/health").as_str()))
+        .await;
+    assert_eq!(res.status(), StatusCode::Ok);
+    assert!(res.header("Server").is_none());
+    srv.stop().await;
+
+    let res = App::builder(
+        tempdir().unwrap().path().to_path_buf(),
+        tls_config(),
+        OidcConfig {
+            audience: OIDC_AUDIENCE.to_string(),
+            issuer: oidc.issuer.parse().unwrap(),
+        },
+    )
+    .server_header(Some("invalid\nvalue".into()))
+    .build()
+    .await;
+    assert!(res.is_err());
+
+    oidc.stop().await;
+}
+
+#[async_std::test]
+async fn client_cert_chain_limit() {
+    let _ = tracing_subscriber::fmt::try_init();
+
+    let oidc = Oidc::spawn().await;
+
+    let store = tempdir().expect("failed to create temporary store directory");
+    let app = App::builder(
+        store.path().to_path_buf(),
+        tls_config(),
+        OidcConfig {
+            audience: OIDC_AUDIENCE.to_string(),
+            issuer: oidc.issuer.parse().unwrap(),
+        },
+    )
+    .build()
+    .await
+    .unwrap();
+    let lis = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+        .await
+        .expect("failed to bind to address");
+    let port = lis.local_addr().unwrap().port();
+
+    let (cert, key) = client_credentials();
+    let ca = rustls_pemfile::certs(&mut std::io::BufReader::new(
+        include_bytes!("../testdata/ca.crt").as_slice(),
+    ))
+    .unwrap()
+    .into_iter()
+    .map(Certificate)
+    .next()
+    .unwrap();
+
+    // Serves a single connection, on which the client presenting `chain` requests `/health`,
+    // and returns the result of handling it along with the response head, if any.
+    let serve = |chain: Vec<Certificate>| {
+        let (app, lis, key) = (&app, &lis, key.clone());
+        async move {
+            let server = async {
+                let (stream, peer) = lis.accept().await.expect("failed to accept connection");
+                app.handle_from(stream, peer).await
+            };
+            let client = async {
+                let stream = TcpStream::connect((Ipv4Addr::LOCALHOST, port))
+                    .await
+                    .expect("failed to connect to server");
+                let mut stream = TlsConnector::from(Arc::new(
+                    rustls::ClientConfig::builder()
+                        .with_safe_defaults()
+                        .with_root_certificates(roots())
+                        .with_single_cert(chain, key)
+                        .unwrap(),
+                ))
+                .connect("localhost".try_into().unwrap(), stream)
+                .await
+                .ok()?;
+                stream
+                    .write_all(
+                        b"GET /health HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+                    )
+                    .await
+                    .ok()?;
+                let mut res = vec![];
+                _ = stream.read_to_end(&mut res).await;
+                Some(String::from_utf8_lossy(&res).into_owned())
+            };
+            futures::join!(server, client)
+        }
+    };
+
+    let (res, head) = serve(cert.iter().cloned().chain([ca.clone()]).collect()).await;
+    assert!(res.is_ok(), "{res:?}");
+    assert!(head.unwrap_or_default().starts_with("HTTP/1.1 200 OK\r\n"));
+
+    // Padding the chain with copies of the CA certificate exceeds the limit, while the chain
+    // still fits into a single handshake message.
+    let padding = DEFAULT_MAX_CLIENT_CERT_CHAIN / ca.0.len() + 1;
+    let chain: Vec<_> = cert
+        .iter()
+        .cloned()
+        .chain(std::iter::repeat_n(ca, padding))
+        .collect();
+    assert!(chain.iter().map(|cert| cert.0.len()).sum::<usize>() < 0xffff);
+    let (res, head) = serve(chain).await;
+    let err = res.expect_err("oversized certificate chain accepted");
+    assert!(
+        format!("{err:#}").contains("client certificate chain exceeds"),
+        "{err:#}"
+    );
+    assert!(!head.unwrap_or_default().starts_with("HTTP/1.1"));
+
+    oidc.stop().await;
+}
+
+#[async_std::test]
+async fn keep_alive() {
+    let _ = tracing_subscriber::fmt::try_init();
+
+    let oidc = Oidc::spawn().await;
+
+    // Connections are kept open by default.
+    let srv = Server::spawn(&oidc, |builder| builder).await;
+    let mut stream = srv.connect().await;
+    for _ in 0..3 {
+        stream.write_all(HEALTH_REQUEST).await.unwrap();
+        let res = read_head(&mut stream).await;
+        assert!(res.starts_with("HTTP/1.1 200 OK\r\n"), "{res}");
+        assert!(!res.contains("connection: close"), "{res}");
+    }
+    assert!(!closed(&mut stream, Duration::from_millis(1500)).await);
+    drop(stream);
+    srv.stop().await;
+
+    let srv = Server::spawn(&oidc, |builder| builder.max_requests_per_connection(2)).await;
+    let mut stream = srv.connect().await;
+    stream.write_all(HEALTH_REQUEST).await.unwrap();
+    let res = read_head(&mut stream).await;
+    assert!(!res.contains("connection: close"), "{res}");
+    stream.write_all(HEALTH_REQUEST).await.unwrap();
+    let res = read_head(&mut stream).await;
+    assert!(res.starts_with("HTTP/1.1 200 OK\r\n"), "{res}");
+    assert!(res.contains("connection: close"), "{res}");
+    assert!(closed(&mut stream, Duration::from_secs(10)).await);
+    srv.stop().await;
+
+    let srv = Server::spawn(&oidc, |builder| {
+        builder.keep_alive_timeout(Duration::from_secs(1))
+    })
+    .await;
+    let mut stream = srv.connect().await;
+    stream.write_all(HEALTH_REQUEST).await.unwrap();
+    let res = read_head(&mut stream).await;
+    assert!(res.starts_with("HTTP/1.1 200 OK\r\n"), "{res}");
+    let start = SystemTime::now();
+    assert!(closed(&mut stream, Duration::from_secs(10)).await);
+    assert!(start.elapsed().unwrap() >= Duration::from_millis(900));
+    // Connections, on which no request is sent, are closed as well.
+    let mut stream = srv.connect().await;
+    assert!(closed(&mut stream, Duration::from_secs(10)).await);
+    srv.stop().await;
+
+    oidc.stop().await;
+}
+
+#[async_std::test]
+async fn graceful_shutdown() {
+    let _ = tracing_subscriber::fmt::try_init();
+
+    let oidc = Oidc::spawn().await;
+
+    const SUBJECT: &str = "test|shutdown";
+    let oidc_token = oidc.token(&oidc.claims(SUBJECT));
+
+    // The app is served on another listener using its own connection management.
+    let srv = Server::spawn(&oidc, |builder| {
+        builder
+            .max_connections(2)
+            .shutdown_timeout(Duration::from_secs(10))
+    })
+    .await;
+    let lis = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+    let port = lis.local_addr().unwrap().port();
+    let serve = spawn({
+        let app = Arc::clone(&srv.app);
+        async move { app.serve_connections(&lis).await }
+    });
+
+    let mut idle = connect(port).await;
+    idle.write_all(HEALTH_REQUEST).await.unwrap();
+    let res = read_head(&mut idle).await;
+    assert!(res.starts_with("HTTP/1.1 200 OK\r\n"), "{res}");
+    let unused = connect(port).await;
+
+    // Connections exceeding the limit are only served once another one is closed.
+    let mut upload = spawn(connect(port));
+    assert!(
+        async_std::future::timeout(Duration::from_millis(500), &mut upload)
+            .await
+            .is_err()
+    );
+    drop(unused);
+    let mut upload = async_std::future::timeout(Duration::from_secs(10), upload)
+        .await
+        .expect("connection was not served after another one was closed");
+
+    // Requests in flight on shutdown are completed.
+    let body = format!(r#"{{"subject":"{SUBJECT}"}}"#);
+    let (start, end) = body.split_at(body.len() / 2);
+    upload
+        .write_all(
+            format!(
+                "PUT /api/v0.1.0/testuser HTTP/1.1\r\n\
+                Host: localhost\r\n\
+                Content-Type: application/json\r\n\
+                Content-Length: {}\r\n\
+                Authorization: Bearer {oidc_token}\r\n\r\n\
+                {start}",
+                body.len()
+            )
+            .as_bytes(),
+        )
+        .await
+        .unwrap();
+    async_std::task::sleep(Duration::from_millis(200)).await;
+    assert!(!srv.app.is_shutting_down());
+    srv.app.shutdown();
+    assert!(srv.app.is_shutting_down());
+
+    // Idle connections are closed right away.
+    assert!(closed(&mut idle, Duration::from_secs(5)).await);
+
+    upload.write_all(end.as_bytes()).await.unwrap();
+    let res = read_head(&mut upload).await;
+    assert!(res.starts_with("HTTP/1.1 201 "), "{res}");
+    assert!(closed(&mut upload, Duration::from_secs(5)).await);
+
+    // Serving completes once all connections are drained, after which none are accepted.
+    async_std::future::timeout(Duration::from_secs(5), serve)
+        .await
+        .expect("connections were not drained");
+    assert!(TcpStream::connect((Ipv4Addr::LOCALHOST, port))
+        .await
+        .is_err());
+
+    srv.stop().await;
+    oidc.stop().await;
+}
+
+#[async_std::test]
+async fn shutdown_timeout() {
+    let _ = tracing_subscriber::fmt::try_init();
+
+    let oidc = Oidc::spawn().await;
+
+    const SUBJECT: &str = "test|shutdown-timeout";
+    let oidc_token = oidc.token(&oidc.claims(SUBJECT));
+
+    let srv = Server::spawn(&oidc, |builder| {
+        builder.shutdown_timeout(Duration::from_secs(1))
+    })
+    .await;
+    let lis = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+    let port = lis.local_addr().unwrap().port();
+    let serve = spawn({
+        let app = Arc::clone(&srv.app);
+        async move { app.serve_connections(&lis).await }
+    });
+
+    // The body of the request is never completed.
+    let mut upload = connect(port).await;
+    upload
+        .write_all(
+            format!(
+                "PUT /api/v0.1.0/testuser HTTP/1.1\r\n\
+                Host: localhost\r\n\
+                Content-Type: application/json\r\n\
+                Content-Length: 64\r\n\
+                Authorization: Bearer {oidc_token}\r\n\r\n\
+                {{\"subject\""
+            )
+            .as_bytes(),
+        )
+        .await
+        .unwrap();
+    async_std::task::sleep(Duration::from_millis(200)).await;
+    srv.app.shutdown();
+
+    // Connections still active after the shutdown timeout are dropped.
+    async_std::future::timeout(Duration::from_secs(5), serve)
+        .await
+        .expect("serving did not complete after the shutdown timeout");
+    assert!(closed(&mut upload, Duration::from_millis(500)).await);
+
+    srv.stop().await;
+    oidc.stop().await;
+}
+
+#[async_std::test]
+async fn oidc_strict_startup() {
+    let _ = tracing_subscriber::fmt::try_init();
+
+    async fn build(oidc: &Oidc, audience: &str, strict: bool) -> anyhow::Result<App> {
+        App::builder(
+            tempdir().unwrap().path().to_path_buf(),
+            tls_config(),
+            OidcConfig {
+                audience: audience.into(),
+                issuer: oidc.issuer.parse().unwrap(),
+            },
+        )
+        .oidc_strict_startup(strict)
+        .build()
+        .await
+    }
+
+    // Scopes cannot be validated, if the provider does not list any.
+    let oidc = Oidc::spawn().await;
+    assert!(build(&oidc, OIDC_AUDIENCE, true).await.is_ok());
+    let err = build(&oidc, "", true)
+        .await
+        .err()
+        .expect("empty audience accepted");
+    assert!(format!("{err:#}").contains("audience"), "{err:#}");
+    oidc.stop().await;
+
+    let oidc = Oidc::spawn_with_scopes(Some(&[
+        "openid",
+        "manage:drawbridge_users",
+        "manage:drawbridge_repositories",
+        "manage:drawbridge_tags",
+    ]))
+    .await;
+    assert!(build(&oidc, OIDC_AUDIENCE, true).await.is_ok());
+    oidc.stop().await;
+
+    let oidc = Oidc::spawn_with_scopes(Some(&[
+        "openid",
+        "read:drawbridge_users",
+        "write:drawbridge_users",
+        "manage:drawbridge_repositories",
+        "read:drawbridge_tags",
+    ]))
+    .await;
+    // Misconfigurations are only reported in strict mode.
+    assert!(build(&oidc, OIDC_AUDIENCE, false).await.is_ok());
+    let err = build(&oidc, OIDC_AUDIENCE, true)
+        .await
+        .err()
+        .expect("provider missing scopes accepted");
+    assert_eq!(err.downcast_ref(), Some(&FailureClass::Oidc));
+    let err = format!("{err:#}");
+    assert!(
+        err.contains("`write:drawbridge_tags`, `manage:drawbridge_tags`"),
+        "{err}"
+    );
+    assert!(!err.contains("drawbridge_users"), "{err}");
+    assert!(!err.contains("drawbridge_repositories"), "{err}");
+    oidc.stop().await;
+}
+
+#[async_std::test]
+async fn ip_filter() {
+    let _ = tracing_subscriber::fmt::try_init();
+
+    let oidc = Oidc::spawn().await;
+    let oidc_token = oidc.token(&oidc.claims("test|ip-filter"));
+    let loopback = || ["127.0.0.0/8".parse().unwrap()];
+
+    let srv = Server::spawn(&oidc, |builder| builder.write_deny_cidrs(loopback())).await;
+    let url = srv.url("/api/v0.1.0/testuser");
+    // Reads are still authenticated as usual.
+    let res = srv.send(Request::new(Method::Get, url.as_str())).await;
+    assert_eq!(res.status(), StatusCode::Unauthorized);
+    let mut req = Request::new(Method::Put, url.as_str());
+    req.insert_header("Authorization", format!("Bearer {oidc_token}"));
+    req.set_body(Body::from_json(&json!({ "subject": "test|ip-filter" })).unwrap());
+    let mut res = srv.send(req).await;
+    assert_eq!(res.status(), StatusCode::Forbidden);
+    assert_eq!(
+        res.body_string().await.unwrap(),
+        "Client address not allowed"
+    );
+    srv.stop().await;
+
+    // Connections from addresses, which are not allowed, are dropped before the handshake.
+    let srv = Server::spawn(&oidc, |builder| {
+        builder.allow_cidrs(["10.0.0.0/8".parse().unwrap()])
+    })
+    .await;
+    let stream = TcpStream::connect((Ipv4Addr::LOCALHOST, srv.port))
+        .await
+        .unwrap();
+    assert!(TlsConnector::from(Arc::new(
+        rustls::ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(roots())
+            .with_no_client_auth(),
+    ))
+    .connect("localhost".try_into().unwrap(), stream)
+    .await
+    .is_err());
+    srv.stop().await;
+
+    // Requests received from trusted proxies are filtered by their effective client address.
+    let srv = Server::spawn(&oidc, |builder| {
+        builder
+            .trusted_proxies(loopback())
+            .allow_cidrs(["192.0.2.0/24".parse().unwrap()])
+    })
+    .await;
+    let url = srv.url("/api/v0.1.0/testuser");
+    for (forwarded_for, status) in [
+        (Some("192.0.2.1"), StatusCode::Unauthorized),
+        (Some("198.51.100.1"), StatusCode::Forbidden),
+        (None, StatusCode::Forbidden),
+    ] {
+        let mut req = Request::new(Method::Get, url.as_str());
+        if let Some(forwarded_for) = forwarded_for {
+            req.insert_header("X-Forwarded-For", forwarded_for);
+        }
+        assert_eq!(srv.send(req).await.status(), status, "{forwarded_for:?}");
+    }
+    srv.stop().await;
+    oidc.stop().await;
+}
+
+#[async_std::test]
+async fn namespace_rate_limit() {
+    let _ = tracing_subscriber::fmt::try_init();
+
+    let oidc = Oidc::spawn().await;
+    let srv = Server::spawn(&oidc, |builder| {
+        builder
+            .namespace_rate_limit(2)
+            .namespace_rate_limit_overrides([("unlimited".parse().unwrap(), 0)])
+    })
+    .await;
+
+    let get = |user: &str, token: &str| {
+        let mut req = Request::new(
+            Method::Get,
+            srv.url(&format!("/api/v0.1.0/{user}")).as_str(),
+        );
+        req.insert_header("Authorization", format!("Bearer {token}"));
+        srv.send(req)
+    };
+    let mut tokens = vec![];
+    for user in ["limited", "other", "unlimited"] {
+        let subject = format!("test|rate-limit-{user}");
+        let token = oidc.token(&oidc.claims(&subject));
+        let mut req = Request::new(
+            Method::Put,
+            srv.url(&format!("/api/v0.1.0/{user}")).as_str(),
+        );
+        req.insert_header("Authorization", format!("Bearer {token}"));
+        req.set_body(Body::from_json(&json!({ "subject": subject })).unwrap());
+        assert_eq!(srv.send(req).await.status(), StatusCode::Created);
+        tokens.push(token);
+    }
+
+    // Bursts of up to the limit are allowed.
+    let mut limited = None;
+    for _ in 0..10 {
+        let res = get("limited", &tokens[0]).await;
+        if res.status() != StatusCode::Ok {
+            limited = Some(res);
+            break;
+        }
+    }
+    let mut res = limited.expect("requests must be rate-limited");
+    assert_eq!(res.status(), StatusCode::TooManyRequests);
+    assert_eq!(res.header("Retry-After").unwrap().as_str(), "1");
+    assert_eq!(res.body_string().await.unwrap(), "Rate limit exceeded");
+
+    // Namespaces are limited independently of each other.
+    assert_eq!(get("other", &tokens[1]).await.status(), StatusCode::Ok);
+    for _ in 0..10 {
+        assert_eq!(get("unlimited", &tokens[2]).await.status(), StatusCode::Ok);
+    }
+    // Unauthenticated requests do not count against the limit.
+    for _ in 0..10 {
+        let res = srv
+            .send(Request::new(
+                Method::Get,
+                srv.url("/api/v0.1.0/other").as_str(),
+            ))
+            .await;
+        assert_eq!(res.status(), StatusCode::Unauthorized);
+    }
+    assert_eq!(get("other", &tokens[1]).await.status(), StatusCode::Ok);
+
+    srv.stop().await;
+    oidc.stop().await;
+}
+
+#[async_std::test]
+async fn repository_changes() {
+    let _ = tracing_subscriber::fmt::try_init();
+
+    let oidc = Oidc::spawn().await;
+
+    const SUBJECT: &str = "test|changes";
+    let oidc_token = oidc.token(&oidc.claims(SUBJECT));
+
+    let srv = Server::spawn(&oidc, |builder| builder).await;
+
+    let cl = srv.client();
+    let cl = spawn_blocking(move || async move {
+        let anon_cl = cl.clone().build().unwrap();
+        let oidc_cl = cl.token(oidc_token).build().unwrap();
+
+        let oidc_user = oidc_cl.user(&"testuser".parse().unwrap());
+        assert!(oidc_user
+            .create(&UserRecord {
+                subject: SUBJECT.into(),
+            })
+            .expect("failed to create user"));
+
+        let pkg = tempdir().expect("failed to create temporary package directory");
+        write(pkg.path().join("test-file.txt"), "text")
+            .await
+            .unwrap();
+
+        let repo = oidc_user.repository(&"test-repo".parse().unwrap());
+        assert!(repo
+            .create(&RepositoryConfig { public: false })
+            .expect("failed to create repository"));
+        let other_repo = oidc_user.repository(&"test-repo-other".parse().unwrap());
+        assert!(other_repo
+            .create(&RepositoryConfig { public: false })
+            .expect("failed to create repository"));
+
+        let empty = repo.changes(None).expect("failed to get changes");
+        assert_eq!(empty.changes, vec![]);
+
+        for (repo, tag) in [(&repo, "0.1.0"), (&other_repo, "0.2.0")] {
+            let (tag_created, _) = repo
+                .tag(&tag.parse().unwrap())
+                .create_from_path_unsigned(pkg.path())
+                .expect("failed to create a tag and upload the tree");
+            assert!(tag_created);
+        }
+
+        let added = |tag: &str| RepositoryChange {
+            kind: RepositoryChangeKind::Added,
+            tag: tag.parse().unwrap(),
+            path: None,
+        };
+        let changed = |tag: &str, path: &str| RepositoryChange {
+            kind: RepositoryChangeKind::Changed,
+            tag: tag.parse().unwrap(),
+            path: Some(path.parse().unwrap()),
+        };
+
+        // Changes of other repositories are not included.
+        let first = repo.changes(None).expect("failed to get changes");
+        assert_eq!(first.changes.first(), Some(&added("0.1.0")));
+        let mut nodes = first.changes[1..].to_vec();
+        nodes.sort_by_key(|change| change.path.clone());
+        assert_eq!(
+            nodes,
+            vec![changed("0.1.0", ""), changed("0.1.0", "test-file.txt")]
+        );
+        assert_eq!(
+            repo.changes(Some(&empty.cursor))
+                .expect("failed to get changes"),
+            first
+        );
+
+        // Only changes recorded after the cursor are returned.
+        let unchanged = repo
+            .changes(Some(&first.cursor))
+            .expect("failed to get changes");
+        assert_eq!(unchanged.changes, vec![]);
+        assert_eq!(unchanged.cursor, first.cursor);
+
+        let (tag_created, _) = repo
+            .tag(&"0.3.0".parse().unwrap())
+            .create_from_path_unsigned(pkg.path())
+            .expect("failed to create a tag and upload the tree");
+        assert!(tag_created);
+        let next = repo
+            .changes(Some(&first.cursor))
+            .expect("failed to get changes");
+        assert_eq!(next.changes.len(), 3);
+        assert_eq!(next.changes.first(), Some(&added("0.3.0")));
+        assert_ne!(next.cursor, first.cursor);
+
+        let other = other_repo.changes(None).expect("failed to get changes");
+        assert_eq!(other.changes.first(), Some(&added("0.2.0")));
+        assert_eq!(other.changes.len(), 3);
+
+        for cursor in ["1", "foo", "99999999"] {
+            let err = repo
+                .changes(Some(cursor))
+                .expect_err("invalid cursor accepted");
+            assert!(format!("{err:#}").contains("`400`"), "{err:#}");
+        }
+
+        // Changes are authorized like reads of the repository.
+        let err = anon_cl
+            .user(&"testuser".parse().unwrap())
+            .repository(&"test-repo".parse().unwrap())
+            .changes(None)
+            .expect_err("anonymous access to a private repository");
+        assert!(format!("{err:#}").contains("`401`"), "{err:#}");
+    });
+    assert!(matches!(cl.await.await, ()));
+
+    srv.stop().await;
+    oidc.stop().await;
+}
+
+#[async_std::test]
+async fn store_migration() {
+    let _ = tracing_subscriber::fmt::try_init();
+
+    let oidc = Oidc::spawn().await;
+
+    const SUBJECT: &str = "test|store-migration";
+    let oidc_token = oidc.token(&oidc.claims(SUBJECT));
+
+    let srv = Server::spawn(&oidc, |builder| builder).await;
+    let cl = srv.client();
+    let cl = spawn_blocking(move || async move {
+        let oidc_cl = cl.token(oidc_token).build().unwrap();
+
+        let oidc_user = oidc_cl.user(&"testuser".parse().unwrap());
+        assert!(oidc_user
+            .create(&UserRecord {
+                subject: SUBJECT.into(),
+            })
+            .expect("failed to create user"));
+        let oidc_repo = oidc_user.repository(&"test-repo".parse().unwrap());
+        assert!(oidc_repo
+            .create(&RepositoryConfig { public: false })
+            .expect("failed to create repository"));
+        let pkg = tempdir().expect("failed to create temporary package directory");
+        write(pkg.path().join("test-file.txt"), "text")
+            .await
+            .unwrap();
+        let (tag_created, _) = oidc_repo
+            .tag(&"0.1.0".parse().unwrap())
+            .create_from_path_unsigned(pkg.path())
+            .expect("failed to create tag");
+        assert!(tag_created);
+    });
+    assert!(matches!(cl.await.await, ()));
+
+    // New stores are initialized at the current version.
+    assert_eq!(
+        read_to_string(srv._store.path().join(".version"))
+            .await
+            .unwrap(),
+        format!("{STORE_VERSION}\n")
+    );
+    let mut archive = vec![];
+    _ = export_store(srv._store.path(), &mut archive)
+        .await
+        .expect("failed to export store");
+    srv.stop().await;
+
+    // Imported stores lack a version and a change log, like ones created before either was
+    // introduced.
+    let restored = tempdir().expect("failed to create temporary directory");
+    let store = restored.path().join("store");
+    _ = import_store(&store, archive.as_slice())
+        .await
+        .expect("failed to import store");
+    let build = |allow_store_migration| {
+        App::builder(
+            store.clone(),
+            tls_config(),
+            OidcConfig {
+                audience: OIDC_AUDIENCE.to_string(),
+                issuer: oidc.issuer.parse().unwrap(),
+            },
+        )
+        .allow_store_migration(allow_store_migration)
+        .build()
+    };
+
+    let err = build(false).await.err().expect("migration is not allowed");
+    assert!(format!("{err:#}").contains("must be migrated"), "{err:#}");
+    assert_eq!(err.downcast_ref(), Some(&FailureClass::Store));
+    assert!(!store.join(".version").exists());
+
+    let changes = || async { read_to_string(store.join(".changes")).await.unwrap() };
+    for _ in 0..2 {
+        drop(build(true).await.expect("failed to migrate store"));
+        assert_eq!(
+            read_to_string(store.join(".version")).await.unwrap(),
+            format!("{STORE_VERSION}\n")
+        );
+        // Tags, whose creation is already recorded, are skipped by repeated migrations.
+        let changes = changes().await;
+        assert_eq!(changes.lines().count(), 1, "{changes}");
+        assert!(changes.contains(r#""repository":"testuser/test-repo""#));
+        assert!(changes.contains(r#""kind":"added","tag":"0.1.0""#));
+        remove_file(store.join(".version")).await.unwrap();
+    }
+
+    write(store.join(".version"), format!("{}\n", STORE_VERSION + 1))
+        .await
+        .unwrap();
+    let err = build(true)
+        .await
+        .err()
+        .expect("newer store version accepted");
+    assert!(format!("{err:#}").contains("is newer"), "{err:#}");
+
+    oidc.stop().await;
+}
+
+#[async_std::test]
+async fn orphan_cleanup() {
+    let _ = tracing_subscriber::fmt::try_init();
+
+    let oidc = Oidc::spawn().await;
+
+    const SUBJECT: &str = "test|orphan-cleanup";
+    let oidc_token = oidc.token(&oidc.claims(SUBJECT));
+
+    let srv = Server::spawn(&oidc, |builder| builder).await;
+    let cl = srv.client();
+    let cl = spawn_blocking(move || async move {
+        let oidc_cl = cl.token(oidc_token).build().unwrap();
+
+        let oidc_user = oidc_cl.user(&"testuser".parse().unwrap());
+        assert!(oidc_user
+            .create(&UserRecord {
+                subject: SUBJECT.into(),
+            })
+            .expect("failed to create user"));
+        let oidc_repo = oidc_user.repository(&"test-repo".parse().unwrap());
+        assert!(oidc_repo
+            .create(&RepositoryConfig { public: false })
+            .expect("failed to create repository"));
+        let pkg = tempdir().expect("failed to create temporary package directory");
+        write(pkg.path().join("test-file.txt"), "text")
+            .await
+            .unwrap();
+        let (tag_created, _) = oidc_repo
+            .tag(&"0.1.0".parse().unwrap())
+            .create_from_path_unsigned(pkg.path())
+            .expect("failed to create tag");
+        assert!(tag_created);
+    });
+    assert!(matches!(cl.await.await, ()));
+
+    let store = srv._store.path().to_path_buf();
+    let tree = store.join("users/testuser/repos/test-repo/tags/0.1.0/tree");
+    // Uploaded contents are moved into place once complete.
+    assert!(tree.join("entries/test-file.txt/content").exists());
+    assert!(!tree
+        .join("entries/test-file.txt")
+        .read_dir()
+        .unwrap()
+        .any(|entry| entry
+            .unwrap()
+            .file_name()
+            .to_string_lossy()
+            .starts_with('.')));
+    let mut archive = vec![];
+    _ = export_store(&store, &mut archive)
+        .await
+        .expect("failed to export store");
+    srv.stop().await;
+
+    let restored = tempdir().expect("failed to create temporary directory");
+    let store = restored.path().join("store");
+    _ = import_store(&store, archive.as_slice())
+        .await
+        .expect("failed to import store");
+    let tree = store.join("users/testuser/repos/test-repo/tags/0.1.0/tree");
+
+    // Simulate leftovers of a crash during uploads.
+    const ID: &str = "1b4db7eb-4057-4ddf-91e0-36dec72071f5";
+    let probe = store.join(format!(".probe-{ID}"));
+    let temporary = tree.join(format!(".content-{ID}"));
+    let partial = tree.join("entries/partial.txt");
+    let other = tree.join(".other");
+    write(&probe, "").await.unwrap();
+    write(&temporary, "partial contents").await.unwrap();
+    create_dir(&partial).await.unwrap();
+    write(partial.join("content"), "partial").await.unwrap();
+    write(&other, "").await.unwrap();
+    let partial_repo = store.join("users/testuser/repos/partial-repo");
+    create_dir(&partial_repo).await.unwrap();
+    create_dir(partial_repo.join("tags")).await.unwrap();
+
+    let build = |orphan_max_age, startup_scan_threads| {
+        App::builder(
+            store.clone(),
+            tls_config(),
+            OidcConfig {
+                audience: OIDC_AUDIENCE.to_string(),
+                issuer: oidc.issuer.parse().unwrap(),
+            },
+        )
+        .allow_store_migration(true)
+        .orphan_max_age(orphan_max_age)
+        .startup_scan_threads(startup_scan_threads)
+        .build()
+    };
+
+    // Recently modified files may still be written, e.g. by another instance.
+    drop(build(Some(Duration::from_secs(60 * 60)), 1).await.unwrap());
+    drop(build(None, 1).await.unwrap());
+    assert!(probe.exists() && temporary.exists() && partial.exists());
+    assert!(build(Some(Duration::ZERO), 0).await.is_err());
+    assert!(probe.exists() && temporary.exists() && partial.exists());
+
+    // Directories are scanned concurrently, while all of them are scanned before the build
+    // completes.
+    drop(build(Some(Duration::ZERO), 4).await.unwrap());
+    assert!(!probe.exists());
+    assert!(!temporary.exists());
+    assert!(!partial.exists());
+    assert!(!partial_repo.exists());
+    // Complete objects and other transient files are left untouched.
+    assert!(other.exists());
+    assert!(store.join(".changes").exists());
+    assert!(tree.join("meta.json").exists());
+    assert!(tree.join("entries/test-file.txt/meta.json").exists());
+    assert_eq!(
+        read_to_string(tree.join("entries/test-file.txt/content"))
+            .await
+            .unwrap(),
+        "text"
+    );
+
+    oidc.stop().await;
+}
+
+#[async_std::test]
+async fn maintenance() {
+    let _ = tracing_subscriber::fmt::try_init();
+
+    let oidc = Oidc::spawn().await;
+
+    const SUBJECT: &str = "test|maintenance";
+    let oidc_token = oidc.token(&oidc.claims(SUBJECT));
+
+    let srv = Server::spawn(&oidc, |builder| {
+        builder
+            .orphan_max_age(Some(Duration::ZERO))
+            .metrics_endpoint(true)
+    })
+    .await;
+    let cl = srv.client();
+    let cl = spawn_blocking(move || async move {
+        let oidc_cl = cl.token(oidc_token).build().unwrap();
+
+        let oidc_user = oidc_cl.user(&"testuser".parse().unwrap());
+        assert!(oidc_user
+            .create(&UserRecord {
+                subject: SUBJECT.into(),
+            })
+            .expect("failed to create user"));
+        let oidc_repo = oidc_user.repository(&"test-repo".parse().unwrap());
+        assert!(oidc_repo
+            .create(&RepositoryConfig { public: false })
+            .expect("failed to create repository"));
+        let pkg = tempdir().expect("failed to create temporary package directory");
+        write(pkg.path().join("test-file.txt"), "text")
+            .await
+            .unwrap();
+        let (tag_created, _) = oidc_repo
+            .tag(&"0.1.0".parse().unwrap())
+            .create_from_path_unsigned(pkg.path())
+            .expect("failed to create tag");
+        assert!(tag_created);
+    });
+    assert!(matches!(cl.await.await, ()));
+    assert_eq!(srv.app.metrics().maintenance_runs(), 0);
+    assert_eq!(srv.app.metrics().maintenance_last_run(), None);
+
+    // Simulate leftovers of a crash during uploads while the server is running.
+    const ID: &str = "1b4db7eb-4057-4ddf-91e0-36dec72071f5";
+    let store = srv._store.path();
+    let tree = store.join("users/testuser/repos/test-repo/tags/0.1.0/tree");
+    let probe = store.join(format!(".probe-{ID}"));
+    let temporary = tree.join(format!(".content-{ID}"));
+    let partial = tree.join("entries/partial.txt");
+    write(&probe, "").await.unwrap();
+    write(&temporary, "partial contents").await.unwrap();
+    create_dir(&partial).await.unwrap();
+    write(partial.join("content"), "partial").await.unwrap();
+
+    let started = SystemTime::now();
+    let maintain = spawn({
+        let app = Arc::clone(&srv.app);
+        async move { app.maintain(Duration::from_millis(500)).await }
+    });
+    while srv.app.metrics().maintenance_runs() == 0 {
+        async_std::task::sleep(Duration::from_millis(10)).await;
+    }
+    assert_eq!(maintain.cancel().await, None);
+
+    assert!(!probe.exists());
+    assert!(!temporary.exists());
+    assert!(!partial.exists());
+    // Complete objects are left untouched.
+    assert_eq!(
+        read_to_string(tree.join("entries/test-file.txt/content"))
+            .await
+            .unwrap(),
+        "text"
+    );
+
+    let metrics = srv.app.metrics();
+    assert_eq!(metrics.maintenance_runs(), 1);
+    assert!(metrics
+        .maintenance_last_run()
+        .is_some_and(|last_run| last_run + Duration::from_secs(1) >= started));
+    let summary = metrics.maintenance_last_summary();
+    assert_eq!(summary.temporary_files, 2);
+    assert_eq!(summary.partial_objects, 1);
+    assert_eq!(summary.reclaimed_bytes, 23);
+    assert_eq!(summary.failures, 0);
+
+    let mut res = srv
+        .send(Request::new(Method::Get, srv.url("/metrics").as_str()))
+        .await;
+    assert_eq!(res.status(), StatusCode::Ok);
+    let body = res.body_string().await.unwrap();
+    assert!(
+        body.contains("drawbridge_maintenance_runs_total 1\n"),
+        "{body}"
+    );
+    assert!(
+        body.contains("drawbridge_maintenance_last_removed{kind=\"partial-objects\"} 1\n"),
+        "{body}"
+    );
+
+    srv.stop().await;
+    oidc.stop().await;
+}
+
+#[async_std::test]
+async fn cache_control() {
+    let _ = tracing_subscriber::fmt::try_init();
+
+    let oidc = Oidc::spawn().await;
+
+    const SUBJECT: &str = "test|cache-control";
+    let oidc_token = oidc.token(&oidc.claims(SUBJECT));
+
+    let srv = Server::spawn(&oidc, |builder| builder).await;
+
+    let cl = srv.client();
+    let cl = spawn_blocking(move || async move {
+        let oidc_cl = cl.token(oidc_token).build().unwrap();
+
+        let oidc_user = oidc_cl.user(&"testuser".parse().unwrap());
+        assert!(oidc_user
+            .create(&UserRecord {
+                subject: SUBJECT.into(),
+            })
+            .expect("failed to create user"));
+        let oidc_repo = oidc_user.repository(&"test-repo".parse().unwrap());
+        assert!(oidc_repo
+            .create(&RepositoryConfig { public: true })
+            .expect("failed to create repository"));
+
+        let pkg = tempdir().expect("failed to create temporary package directory");
+        write(pkg.path().join("file.txt"), "text").await.unwrap();
+        let (tag_created, _) = oidc_repo
+            .tag(&"0.1.0".parse().unwrap())
+            .create_from_path_unsigned(pkg.path())
+            .expect("failed to create a tag and upload the tree");
+        assert!(tag_created);
+    });
+    assert!(matches!(cl.await.await, ()));
+
+    const REPO: &str = "/api/v0.1.0/testuser/test-repo";
+    async fn cache_control(
+        srv: &Server,
+        method: Method,
+        path: String,
+    ) -> (StatusCode, Option<String>) {
+        let res = srv
+            .send(Request::new(method, srv.url(&path).as_str()))
+            .await;
+        (
+            res.status(),
+            res.header("Cache-Control").map(|v| v.as_str().to_string()),
+        )
+    }
+    let immutable = Some(DEFAULT_CONTENT_CACHE_CONTROL.to_string());
+    let mutable = Some(DEFAULT_TAG_CACHE_CONTROL.to_string());
+    for (method, path, expected) in [
+        (
+            Method::Get,
+            format!("{REPO}/_tag/0.1.0/tree/file.txt"),
+            (StatusCode::Ok, immutable.clone()),
+        ),
+        (
+            Method::Get,
+            format!("{REPO}/_tag/0.1.0/tree"),
+            (StatusCode::Ok, immutable.clone()),
+        ),
+        (
+            Method::Get,
+            format!("{REPO}/_tag/0.1.0"),
+            (StatusCode::Ok, mutable.clone()),
+        ),
+        (
+            Method::Get,
+            format!("{REPO}/_tag"),
+            (StatusCode::Ok, mutable.clone()),
+        ),
+        // Errors are not cached.
+        (
+            Method::Get,
+            REPO.to_string(),
+            (StatusCode::Unauthorized, None),
+        ),
+        (
+            Method::Get,
+            format!("{REPO}/_tag/0.1.0/tree/missing.txt"),
+            (StatusCode::NotFound, None),
+        ),
+    ] {
+        assert_eq!(
+            cache_control(&srv, method, path.clone()).await,
+            expected,
+            "{method} {path}"
+        );
+    }
+
+    let mut archive = vec![];
+    _ = export_store(srv._store.path(), &mut archive)
+        .await
+        .expect("failed to export store");
+    srv.stop().await;
+
+    let srv = Server::spawn(&oidc, |builder| {
+        builder
+            .content_cache_control(Some("private, max-age=60".into()))
+            .tag_cache_control(None)
+    })
+    .await;
+    _ = import_store(srv._store.path(), archive.as_slice())
+        .await
+        .expect("failed to import store");
+    assert_eq!(
+        cache_control(
+            &srv,
+            Method::Get,
+            format!("{REPO}/_tag/0.1.0/tree/file.txt")
+        )
+        .await,
+        (StatusCode::Ok, Some("private, max-age=60".into()))
+    );
+    assert_eq!(
+        cache_control(&srv, Method::Get, format!("{REPO}/_tag/0.1.0")).await,
+        (StatusCode::Ok, None)
+    );
+    srv.stop().await;
+
+    let store = tempdir().expect("failed to create temporary directory");
+    let err = App::builder(
+        store.path(),
+        tls_config(),
+        OidcConfig {
+            audience: OIDC_AUDIENCE.to_string(),
+            issuer: oidc.issuer.parse().unwrap(),
+        },
+    )
+    .tag_cache_control(Some("no-cache\n".into()))
+    .build()
+    .await
+    .err()
+    .expect("invalid header value accepted");
+    assert!(format!("{err:#}").contains("invalid `Cache-Control` header value"));
+
+    oidc.stop().await;
+}
+
+#[async_std::test]
+async fn sni() {
+    let _ = tracing_subscriber::fmt::try_init();
+
+    let oidc = Oidc::spawn().await;
+    let srv = Server::spawn(&oidc, |builder| builder).await;
+    srv.app.set_tls_config(
+        TlsConfig::read_with_identities(
+            [
+                (
+                    include_bytes!("../testdata/server.crt").as_slice(),
+                    include_bytes!("../testdata/server.key").as_slice(),
+                ),
+                (
+                    include_bytes!("../testdata/modules.crt").as_slice(),
+                    include_bytes!("../testdata/modules.key").as_slice(),
+                ),
+            ],
+            include_bytes!("../testdata/ca.crt").as_slice(),
+            Default::default(),
+        )
+        .unwrap(),
+    );
+    let certificate = |pem: &[u8]| {
+        rustls_pemfile::certs(&mut std::io::BufReader::new(pem))
+            .unwrap()
+            .remove(0)
+    };
+
+    // Clients are presented the certificate valid for the server name they request, which they
+    // verify as usual.
+    for (name, cert) in [
+        (
+            "localhost",
+            certificate(include_bytes!("../testdata/server.crt")),
+        ),
+        (
+            "modules.example.com",
+            certificate(include_bytes!("../testdata/modules.crt")),
+        ),
+    ] {
+        let stream = TcpStream::connect((Ipv4Addr::LOCALHOST, srv.port))
+            .await
+            .expect("failed to connect to server");
+        let mut stream = TlsConnector::from(Arc::new(
+            rustls::ClientConfig::builder()
+                .with_safe_defaults()
+                .with_root_certificates(roots())
+                .with_no_client_auth(),
+        ))
+        .connect(name.try_into().unwrap(), stream)
+        .await
+        .unwrap_or_else(|e| panic!("failed to establish TLS connection to `{name}`: {e}"));
+        assert_eq!(
+            stream.get_ref().1.peer_certificates(),
+            Some([Certificate(cert)].as_slice()),
+            "{name}"
+        );
+        stream
+            .write_all(
+                format!("GET /health HTTP/1.1\r\nHost: {name}\r\nConnection: close\r\n\r\n")
+                    .as_bytes(),
+            )
+            .await
+            .unwrap();
+        assert!(read_head(&mut stream)
+            .await
+            .starts_with("HTTP/1.1 200 OK\r\n"));
+    }
+
+    srv.stop().await;
+    oidc.stop().await;
+}
+
+#[async_std::test]
+async fn tls_alpn() {
+    let _ = tracing_subscriber::fmt::try_init();
+
+    let oidc = Oidc::spawn().await;
+    let srv = Server::spawn(&oidc, |builder| builder).await;
+    let negotiate = |offered: &'static [&'static [u8]]| {
+        let port = srv.port;
+        async move {
+            let stream = TcpStream::connect((Ipv4Addr::LOCALHOST, port))
+                .await
+                .expect("failed to connect to server");
+            let mut conf = rustls::ClientConfig::builder()
+                .with_safe_defaults()
+                .with_root_certificates(roots())
+                .with_no_client_auth();
+            conf.alpn_protocols = offered.iter().map(|p| p.to_vec()).collect();
+            let mut stream = TlsConnector::from(Arc::new(conf))
+                .connect("localhost".try_into().unwrap(), stream)
+                .await
+                .expect("failed to establish TLS connection");
+            let protocol = stream.get_ref().1.alpn_protocol().map(<[u8]>::to_vec);
+            stream
+                .write_all(b"GET /health HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+                .await
+                .unwrap();
+            assert!(read_head(&mut stream)
+                .await
+                .starts_with("HTTP/1.1 200 OK\r\n"));
+            protocol
+        }
+    };
+
+    // No protocol is negotiated by default.
+    assert_eq!(negotiate(&[b"http/1.1"]).await, None);
+
+    srv.app.set_tls_config(
+        TlsConfig::read_with_options(
+            include_bytes!("../testdata/server.crt").as_slice(),
+            include_bytes!("../testdata/server.key").as_slice(),
+            include_bytes!("../testdata/ca.crt").as_slice(),
+            TlsOptions {
+                alpn_protocols: vec!["http/1.0".into(), "http/1.1".into()],
+                ..Default::default()
+            },
+        )
+        .unwrap(),
+    );
+    // The advertised order is preferred over the order offered by clients.
+    assert_eq!(
+        negotiate(&[b"h2", b"http/1.1", b"http/1.0"])
+            .await
+            .as_deref(),
+        Some(b"http/1.0".as_slice())
+    );
+    assert_eq!(
+        negotiate(&[b"http/1.1"]).await.as_deref(),
+        Some(b"http/1.1".as_slice())
+    );
+    assert_eq!(negotiate(&[]).await, None);
+
+    srv.stop().await;
+    oidc.stop().await;
+}
+
+#[async_std::test]
+async fn problem_details() {
+    let _ = tracing_subscriber::fmt::try_init();
+
+    let oidc = Oidc::spawn().await;
+
+    const SUBJECT: &str = "test|problem-details";
+    let oidc_token = oidc.token(&oidc.claims(SUBJECT));
+
+    let srv = Server::spawn(&oidc, |builder| builder.max_tags_per_repo(1)).await;
+
+    let cl = srv.client();
+    let token = oidc_token.clone();
+    let cl = spawn_blocking(move || async move {
+        let oidc_cl = cl.token(token).build().unwrap();
+
+        let oidc_user = oidc_cl.user(&"testuser".parse().unwrap());
+        assert!(oidc_user
+            .create(&UserRecord {
+                subject: SUBJECT.into(),
+            })
+            .expect("failed to create user"));
+        assert!(oidc_user
+            .repository(&"test-repo".parse().unwrap())
+            .create(&RepositoryConfig { public: true })
+            .expect("failed to create repository"));
+    });
+    assert!(matches!(cl.await.await, ()));
+
+    let meta = |mime: &str, body: &[u8]| {
+        Algorithms::default()
+            .read_sync(body)
+            .map(|(size, hash)| Meta {
+                hash,
+                size,
+                mime: mime.parse().unwrap(),
+            })
+            .unwrap()
+    };
+    let entry = |content: &[u8]| {
+        let entry = serde_json::to_vec(&meta("application/octet-stream", content)).unwrap();
+        let meta = meta("application/vnd.drawbridge.entry.v1+json", &entry);
+        (entry, meta)
+    };
+    let put = |name: &str, entry: &[u8], meta: &Meta| {
+        let mut req = Request::new(
+            Method::Put,
+            srv.url(&format!("/api/v0.1.0/testuser/test-repo/_tag/{name}"))
+                .as_str(),
+        );
+        req.set_body(entry);
+        req.insert_header("Authorization", format!("Bearer {oidc_token}"));
+        req.insert_header("Content-Type", meta.mime.to_string());
+        req.insert_header("Content-Digest", meta.hash.to_string());
+        srv.send(req)
+    };
+    let problem = |mut res: Response| async move {
+        assert_eq!(
+            res.content_type().map(|mime| mime.to_string()),
+            Some("application/problem+json".into())
+        );
+        res.body_json::<serde_json::Value>().await.unwrap()
+    };
+
+    let (foo, foo_meta) = entry(b"foo");
+    let (bar, bar_meta) = entry(b"bar");
+
+    // Contents not matching their digest are rejected along with the expected digest.
+    let res = put("0.0.1", &bar, &foo_meta).await;
+    assert_eq!(res.status(), StatusCode::BadRequest);
+    let problem_json = problem(res).await;
+    assert_eq!(problem_json["type"], PROBLEM_DIGEST_MISMATCH);
+    assert_eq!(problem_json["status"], 400);
+    assert_eq!(
+        problem_json["expected"],
+        serde_json::to_value(&foo_meta.hash).unwrap()
+    );
+
+    // Tags exceeding the limit are rejected along with the limit and the current usage.
+    assert_eq!(
+        put("0.1.0", &foo, &foo_meta).await.status(),
+        StatusCode::Created
+    );
+    let res = put("0.2.0", &bar, &bar_meta).await;
+    assert_eq!(res.status(), StatusCode::Conflict);
+    let problem_json = problem(res).await;
+    assert_eq!(problem_json["type"], PROBLEM_QUOTA_EXCEEDED);
+    assert_eq!(problem_json["status"], 409);
+    assert_eq!(problem_json["quota"], "tags");
+    assert_eq!(problem_json["limit"], 1);
+    assert_eq!(problem_json["usage"], 1);
+
+    srv.stop().await;
+    oidc.stop().await;
+}
+
+#[async_std::test]
+async fn idempotency_keys() {
+    let _ = tracing_subscriber::fmt::try_init();
+
+    let oidc = Oidc::spawn().await;
+
+    const SUBJECT: &str = "test|idempotency-keys";
+    let oidc_token = oidc.token(&oidc.claims(SUBJECT));
+    let unscoped_token = oidc.token(&TokenClaims {
+        scope: "openid".into(),
+        ..oidc.claims(SUBJECT)
+    });
+    let other_token = oidc.token(&oidc.claims("test|idempotency-keys-other"));
+
+    let srv = Server::spawn(&oidc, |builder| builder).await;
+
+    let cl = srv.client();
+    let token = oidc_token.clone();
+    let cl = spawn_blocking(move || async move {
+        let oidc_cl = cl.token(token).build().unwrap();
+
+        let oidc_user = oidc_cl.user(&"testuser".parse().unwrap());
+        assert!(oidc_user
+            .create(&UserRecord {
+                subject: SUBJECT.into(),
+            })
+            .expect("failed to create user"));
+        assert!(oidc_user
+            .repository(&"test-repo".parse().unwrap())
+            .create(&RepositoryConfig { public: true })
+            .expect("failed to create repository"));
+    });
+    assert!(matches!(cl.await.await, ()));
+
+    let entry = |content: &[u8]| {
+        let meta = |mime: &str, body: &[u8]| {
+            Algorithms::default()
+                .read_sync(body)
+                .map(|(size, hash)| Meta {
+                    hash,
+                    size,
+                    mime: mime.parse().unwrap(),
+                })
+                .unwrap()
+        };
+        let entry = serde_json::to_vec(&meta("application/octet-stream", content)).unwrap();
+        let meta = meta("application/vnd.drawbridge.entry.v1+json", &entry);
+        (entry, meta)
+    };
+    let put = |name: &str, (entry, meta): &(Vec<u8>, Meta), token: &str, key: Option<&str>| {
+        let mut req = Request::new(
+            Method::Put,
+            srv.url(&format!("/api/v0.1.0/testuser/test-repo/_tag/{name}"))
+                .as_str(),
+        );
+        req.set_body(entry.as_slice());
+        req.insert_header("Authorization", format!("Bearer {token}"));
+        req.insert_header("Content-Type", meta.mime.to_string());
+        req.insert_header("Content-Digest", meta.hash.to_string());
+        if let Some(key) = key {
+            req.insert_header("Idempotency-Key", key);
+        }
+        srv.send(req)
+    };
+    let replayed = |res: &Response| {
+        res.header("Idempotent-Replayed")
+            .map(|v| v.as_str() == "true")
+    };
+
+    let (foo, bar) = (entry(b"foo"), entry(b"bar"));
+
+    // Retries using the same key receive the response to the original request.
+    let res = put("0.1.0", &foo, &oidc_token, Some("publish-1")).await;
+    assert_eq!(res.status(), StatusCode::Created);
+    assert_eq!(replayed(&res), None);
+    let res = put("0.1.0", &foo, &oidc_token, Some("publish-1")).await;
+    assert_eq!(res.status(), StatusCode::Created);
+    assert_eq!(replayed(&res), Some(true));
+    // Requests without a key are handled as usual.
+    let res = put("0.1.0", &foo, &oidc_token, None).await;
+    assert_eq!(res.status(), StatusCode::Ok);
+
+    // Keys may not be reused for different requests.
+    let res = put("0.1.0", &bar, &oidc_token, Some("publish-1")).await;
+    assert_eq!(res.status(), StatusCode::UnprocessableEntity);
+    let res = put("0.2.0", &foo, &oidc_token, Some("publish-1")).await;
+    assert_eq!(res.status(), StatusCode::UnprocessableEntity);
+
+    // Keys are scoped per subject.
+    let res = put("0.1.0", &foo, &other_token, Some("publish-1")).await;
+    assert_eq!(res.status(), StatusCode::Unauthorized);
+    assert_eq!(replayed(&res), None);
+
+    // Requests, which are not authenticated, are rejected as usual.
+    let res = put("0.1.0", &foo, "invalid", Some("publish-1")).await;
+    assert_eq!(res.status(), StatusCode::Unauthorized);
+
+    // Responses to requests failing authorization are not replayed to retries.
+    let res = put("0.2.0", &foo, &unscoped_token, Some("publish-2")).await;
+    assert_eq!(res.status(), StatusCode::Unauthorized);
+    let res = put("0.2.0", &foo, &oidc_token, Some("publish-2")).await;
+    assert_eq!(res.status(), StatusCode::Created);
+    assert_eq!(replayed(&res), None);
+
+    // Keys of access tokens are scoped per token.
+    let mint = || async {
+        let mut req = Request::new(
+            Method::Post,
+            srv.url("/api/v0.1.0/testuser/_tokens").as_str(),
+        );
+        req.insert_header("Authorization", format!("Bearer {oidc_token}"));
+        req.set_body(
+            Body::from_json(&json!({
+                "repositories": ["test-repo"],
+                "actions": ["publish"],
+                "expires_in": 3600,
+            }))
+            .unwrap(),
+        );
+        let mut res = srv.send(req).await;
+        assert_eq!(res.status(), StatusCode::Created);
+        let minted: serde_json::Value = res.body_json().await.unwrap();
+        minted["token"].as_str().unwrap().to_string()
+    };
+    let (access_token, other_access_token) = (mint().await, mint().await);
+    let res = put("0.4.0", &foo, &access_token, Some("publish-3")).await;
+    assert_eq!(res.status(), StatusCode::Created);
+    assert_eq!(replayed(&res), None);
+    let res = put("0.4.0", &foo, &access_token, Some("publish-3")).await;
+    assert_eq!(res.status(), StatusCode::Created);
+    assert_eq!(replayed(&res), Some(true));
+    let res = put("0.4.0", &foo, &other_access_token, Some("publish-3")).await;
+    assert_eq!(res.status(), StatusCode::Ok);
+    assert_eq!(replayed(&res), None);
+    let (secret, last) = access_token.split_at(access_token.len() - 1);
+    let forged = format!("{secret}{}", if last == "0" { 1 } else { 0 });
+    let res = put("0.4.0", &foo, &forged, Some("publish-3")).await;
+    assert_eq!(res.status(), StatusCode::Unauthorized);
+    assert_eq!(replayed(&res), None);
+
+    let res = put("0.3.0", &foo, &oidc_token, Some("")).await;
+    assert_eq!(res.status(), StatusCode::BadRequest);
+
+    srv.stop().await;
+    oidc.stop().await;
+}
+
+#[async_std::test]
+async fn strict_paths() {
+    let _ = tracing_subscriber::fmt::try_init();
+
+    let oidc = Oidc::spawn().await;
+
+    const SUBJECT: &str = "test|strict-paths";
+    let oidc_token = oidc.token(&oidc.claims(SUBJECT));
+
+    let srv = Server::spawn(&oidc, |builder| builder).await;
+
+    let cl = srv.client();
+    let cl = spawn_blocking(move || async move {
+        let oidc_cl = cl.token(oidc_token).build().unwrap();
+
+        let oidc_user = oidc_cl.user(&"testuser".parse().unwrap());
+        assert!(oidc_user
+            .create(&UserRecord {
+                subject: SUBJECT.into(),
+            })
+            .expect("failed to create user"));
+        let oidc_repo = oidc_user.repository(&"test-repo".parse().unwrap());
+        assert!(oidc_repo
+            .create(&RepositoryConfig { public: true })
+            .expect("failed to create repository"));
+
+        let pkg = tempdir().expect("failed to create temporary package directory");
+        write(pkg.path().join("file.txt"), "text").await.unwrap();
+        let (tag_created, _) = oidc_repo
+            .tag(&"0.1.0".parse().unwrap())
+            .create_from_path_unsigned(pkg.path())
+            .expect("failed to create a tag and upload the tree");
+        assert!(tag_created);
+    });
+    assert!(matches!(cl.await.await, ()));
+
+    const REPO: &str = "/api/v0.1.0/testuser/test-repo";
+
+    // Non-canonical paths are handled as if they were canonical by default.
+    for path in [
+        format!("{REPO}/_tag/0.1.0/tree/file.txt"),
+        format!("/{REPO}/_tag/0.1.0/tree/file.txt"),
+        format!("{REPO}//_tag//0.1.0/tree//file.txt"),
+        format!("{REPO}/_tag/0.1.0/tree/file.txt/"),
+        "//api//v0.1.0//testuser//test-repo//_tag//0.1.0//tree//file.txt//".into(),
+    ] {
+        let mut res = srv
+            .send(Request::new(Method::Get, srv.url(&path).as_str()))
+            .await;
+        assert_eq!(res.status(), StatusCode::Ok, "{path}");
+        assert_eq!(res.body_string().await.unwrap(), "text", "{path}");
+    }
+    for path in [
+        format!("{REPO}/_tag/0.1.0/"),
+        format!("{REPO}/_tag/0.1.0/tree/"),
+        format!("{REPO}//_tag/"),
+        "/health/".into(),
+    ] {
+        let res = srv
+            .send(Request::new(Method::Get, srv.url(&path).as_str()))
+            .await;
+        assert_eq!(res.status(), StatusCode::Ok, "{path}");
+    }
+    // Percent-encoded slashes do not separate segments.
+    for (path, status) in [
+        (
+            format!("{REPO}/_tag/0.1.0/tree%2Ffile.txt"),
+            StatusCode::NotFound,
+        ),
+        (
+            "/api/v0.1.0/testuser%2Ftest-repo/_tag/0.1.0".into(),
+            StatusCode::BadRequest,
+        ),
+        (
+            format!("{REPO}/_tag/0.1.0/tree/dir%2Ffile.txt"),
+            StatusCode::BadRequest,
+        ),
+    ] {
+        let res = srv
+            .send(Request::new(Method::Get, srv.url(&path).as_str()))
+            .await;
+        assert_eq!(res.status(), status, "{path}");
+    }
+    srv.stop().await;
+
+    // Strict servers redirect non-canonical paths to their canonical form, preserving queries.
+    let srv = Server::spawn(&oidc, |builder| builder.strict_paths(true)).await;
+    for (method, path, location) in [
+        (
+            Method::Get,
+            format!("{REPO}/_tag/0.1.0/"),
+            format!("{REPO}/_tag/0.1.0"),
+        ),
+        (
+            Method::Put,
+            format!("{REPO}//_tag/0.1.0"),
+            format!("{REPO}/_tag/0.1.0"),
+        ),
+        (
+            Method::Get,
+            format!("/{REPO}/_tag/0.1.0/tree//a//b/?x=1"),
+            format!("{REPO}/_tag/0.1.0/tree/a/b?x=1"),
+        ),
+        (Method::Get, "//".into(), "/".into()),
+        (
+            Method::Get,
+            "//example.com/path".into(),
+            "/example.com/path".into(),
+        ),
+    ] {
+        let res = srv
+            .send(Request::new(method, srv.url(&path).as_str()))
+            .await;
+        assert_eq!(
+            res.status(),
+            StatusCode::PermanentRedirect,
+            "{method} {path}"
+        );
+        assert_eq!(
+            res.header("Location").map(|v| v.as_str()),
+            Some(location.as_str()),
+            "{method} {path}"
+        );
+    }
+    for (path, status) in [
+        ("/health".into(), StatusCode::Ok),
+        (format!("{REPO}/_tag/0.1.0"), StatusCode::NotFound),
+        (
+            format!("{REPO}/_tag/0.1.0/tree%2Ffile.txt"),
+            StatusCode::NotFound,
+        ),
+    ] {
+        let res = srv
+            .send(Request::new(Method::Get, srv.url(&path).as_str()))
+            .await;
+        assert_eq!(res.status(), status, "{path}");
+    }
+    srv.stop().await;
+}
+
+#[async_std::test]
+async fn metrics_endpoint() {
+    let _ = tracing_subscriber::fmt::try_init();
+
+    let oidc = Oidc::spawn().await;
+
+    let srv = Server::spawn(&oidc, |builder| builder).await;
+    let res = srv
+        .send(Request::new(Method::Get, srv.url("/metrics").as_str()))
+        .await;
+    assert_eq!(res.status(), StatusCode::NotFound);
+    srv.stop().await;
+
+    let srv = Server::spawn(&oidc, |builder| builder.metrics_endpoint(true)).await;
+    let mut res = srv
+        .send(Request::new(Method::Get, srv.url("/metrics").as_str()))
+        .await;
+    assert_eq!(res.status(), StatusCode::Ok);
+    assert_eq!(
+        res.header("Content-Type").map(|v| v.as_str()),
+        Some("text/plain; version=0.0.4; charset=utf-8")
+    );
+    let body = res.body_string().await.unwrap();
+    assert!(
+        body.contains("# TYPE drawbridge_connections_accepted_total counter\n"),
+        "{body}"
+    );
+    assert!(
+        body.contains("\ndrawbridge_connections_accepted_total 1\n"),
+        "{body}"
+    );
+    assert!(!body.contains("# EOF"), "{body}");
+
+    let mut req = Request::new(Method::Get, srv.url("/metrics").as_str());
+    req.insert_header(
+        "Accept",
+        "application/openmetrics-text; version=1.0.0, text/plain; version=0.0.4; q=0.5",
+    );
+    let mut res = srv.send(req).await;
+    assert_eq!(res.status(), StatusCode::Ok);
+    assert_eq!(
+        res.header("Content-Type").map(|v| v.as_str()),
+        Some("application/openmetrics-text; version=1.0.0; charset=utf-8")
+    );
+    let body = res.body_string().await.unwrap();
+    assert!(
+        body.contains("# TYPE drawbridge_connections_accepted counter\n"),
+        "{body}"
+    );
+    assert!(
+        body.contains("\ndrawbridge_connections_accepted_total 2\n"),
+        "{body}"
+    );
+    assert!(
+        body.contains("\ndrawbridge_connections_active 1\n"),
+        "{body}"
+    );
+    assert!(body.ends_with("\n# EOF\n"), "{body}");
+
+    srv.stop().await;
+    oidc.stop().await;
+}
+
+#[async_std::test]
+async fn health_and_metrics() {
+    let _ = tracing_subscriber::fmt::try_init();
+
+    let oidc = Oidc::spawn().await;
+
+    const SUBJECT: &str = "test|health-and-metrics";
+    let oidc_token = oidc.token(&oidc.claims(SUBJECT));
+
+    let srv = Server::spawn(&oidc, |builder| builder).await;
+    let mut res = srv
+        .send(Request::new(Method::Get, srv.url("/healthz").as_str()))
+        .await;
+    assert_eq!(res.status(), StatusCode::Ok);
+    assert_eq!(res.body_string().await.unwrap(), "");
+    let mut res = srv
+        .send(Request::new(Method::Get, srv.url("/readyz").as_str()))
+        .await;
+    assert_eq!(res.status(), StatusCode::Ok);
+    assert_eq!(res.body_string().await.unwrap(), "store: ok\noidc: ok\n");
+
+    let metrics_lis = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+        .await
+        .expect("failed to bind to address");
+    let metrics_port = metrics_lis.local_addr().unwrap().port();
+    let serve_metrics = spawn({
+        let app = Arc::clone(&srv.app);
+        async move { app.serve_metrics(&metrics_lis).await }
+    });
+    let send_plain = |path: &str| {
+        let req = Request::new(
+            Method::Get,
+            format!("http://127.0.0.1:{metrics_port}{path}").as_str(),
+        );
+        async move {
+            let stream = TcpStream::connect((Ipv4Addr::LOCALHOST, metrics_port))
+                .await
+                .expect("failed to connect to metrics listener");
+            let mut res = async_h1::connect(stream, req)
+                .await
+                .expect("failed to send request");
+            let body = res.body_string().await.unwrap();
+            (res.status(), body)
+        }
+    };
+
+    let cl = srv.client();
+    let token = oidc_token.clone();
+    let cl = spawn_blocking(move || async move {
+        let oidc_cl = cl.token(token).build().unwrap();
+
+        let oidc_user = oidc_cl.user(&"testuser".parse().unwrap());
+        assert!(oidc_user
+            .create(&UserRecord {
+                subject: SUBJECT.into(),
+            })
+            .expect("failed to create user"));
+        let oidc_repo = oidc_user.repository(&"test-repo".parse().unwrap());
+        assert!(oidc_repo
+            .create(&RepositoryConfig { public: false })
+            .expect("failed to create repository"));
+        let pkg = tempdir().expect("failed to create temporary package directory");
+        write(pkg.path().join("test-file.txt"), "text")
+            .await
+            .unwrap();
+        let (tag_created, _) = oidc_repo
+            .tag(&"0.1.0".parse().unwrap())
+            .create_from_path_unsigned(pkg.path())
+            .expect("failed to create tag");
+        assert!(tag_created);
+    });
+    assert!(matches!(cl.await.await, ()));
+
+    let metrics = srv.app.metrics();
+    let uploaded = metrics.uploaded_bytes("testuser/test-repo");
+    assert!(uploaded >= 4, "{uploaded}");
+    assert_eq!(metrics.downloaded_bytes("testuser/test-repo"), 0);
+
+    let mut res = srv
+        .send({
+            let mut req = Request::new(
+                Method::Get,
+                srv.url("/api/v0.1.0/testuser/test-repo/_tag/0.1.0/tree/test-file.txt")
+                    .as_str(),
+            );
+            req.insert_header("Authorization", format!("Bearer {oidc_token}"));
+            req
+        })
+        .await;
+    assert_eq!(res.status(), StatusCode::Ok);
+    assert_eq!(res.body_string().await.unwrap(), "text");
+    // Requests to missing repositories are not attributed to them.
+    for (path, token, status) in [
+        (
+            "/api/v0.1.0/testuser/missing-repo",
+            oidc_token.as_str(),
+            StatusCode::NotFound,
+        ),
+        (
+            "/api/v0.1.0/testuser/test-repo",
+            "invalid",
+            StatusCode::Unauthorized,
+        ),
+    ] {
+        let res = srv
+            .send({
+                let mut req = Request::new(Method::Get, srv.url(path).as_str());
+                req.insert_header("Authorization", format!("Bearer {token}"));
+                req
+            })
+            .await;
+        assert_eq!(res.status(), status, "{path}");
+    }
+
+    let metrics = srv.app.metrics();
+    assert_eq!(metrics.uploaded_bytes("testuser/test-repo"), uploaded);
+    assert_eq!(metrics.downloaded_bytes("testuser/test-repo"), 4);
+    assert_eq!(metrics.downloaded_bytes("testuser/missing-repo"), 0);
+    assert!(metrics.oidc_tokens(TokenOutcome::Valid) > 0);
+    assert_eq!(metrics.oidc_tokens(TokenOutcome::Invalid), 1);
+    assert!(metrics.tls_handshakes(HandshakeOutcome::NoClientCertificate) > 0);
+    assert_eq!(metrics.tls_handshakes(HandshakeOutcome::Failed), 0);
+    assert!(metrics.requests() > 0);
+
+    // The metrics are served without TLS.
+    let (status, body) = send_plain("/metrics").await;
+    assert_eq!(status, StatusCode::Ok);
+    for line in [
+        "drawbridge_oidc_tokens_total{outcome=\"invalid\"} 1\n",
+        "drawbridge_repository_downloaded_bytes_total{repository=\"testuser/test-repo\"} 4\n",
+        "# TYPE drawbridge_request_duration_seconds histogram\n",
+        "drawbridge_request_duration_seconds_count{class=\"trees\",code=\"2xx\"} ",
+    ] {
+        assert!(body.contains(line), "{body}");
+    }
+    assert!(!body.contains("missing-repo"), "{body}");
+    assert_eq!(send_plain("/healthz").await.0, StatusCode::Ok);
+    assert_eq!(
+        send_plain("/readyz").await,
+        (StatusCode::Ok, "store: ok\noidc: ok\n".into())
+    );
+    // Nothing but the metrics and the health probes is served.
+    assert_eq!(
+        send_plain("/api/v0.1.0/testuser").await.0,
+        StatusCode::NotFound
+    );
+
+    // Servers are not ready once the OpenID Connect provider is unreachable.
+    oidc.stop().await;
+    async_std::task::sleep(Duration::from_millis(1100)).await;
+    let (status, body) = send_plain("/readyz").await;
+    assert_eq!(status, StatusCode::ServiceUnavailable);
+    assert_eq!(body, "store: ok\noidc: failed\n");
+    let mut res = srv
+        .send(Request::new(Method::Get, srv.url("/readyz").as_str()))
+        .await;
+    assert_eq!(res.status(), StatusCode::ServiceUnavailable);
+    assert_eq!(
+        res.body_string().await.unwrap(),
+        "store: ok\noidc: failed\n"
+    );
+    let res = srv
+        .send(Request::new(Method::Get, srv.url("/healthz").as_str()))
+        .await;
+    assert_eq!(res.status(), StatusCode::Ok);
+
+    srv.app.shutdown();
+    serve_metrics.await;
+    srv.stop().await;
+}
+
+#[async_std::test]
+async fn hsts() {
+    let _ = tracing_subscriber::fmt::try_init();
+
+    let oidc = Oidc::spawn().await;
+
+    let srv = Server::spawn(&oidc, |builder| builder).await;
+    let res = srv
+        .send(Request::new(Method::Get, srv.url("/health").as_str()))
+        .await;
+    assert_eq!(res.status(), StatusCode::Ok);
+    assert!(res.header("Strict-Transport-Security").is_none());
+    srv.stop().await;
+
+    let srv = Server::spawn(&oidc, |builder| builder.hsts(Some(Hsts::default()))).await;
+    // The header is also sent in error responses.
+    for (method, path, status) in [
+        (Method::Get, "/health", StatusCode::Ok),
+        (
+            Method::Get,
+            "/api/v0.1.0/testuser",
+            StatusCode::Unauthorized,
+        ),
+        (
+            Method::Delete,
+            "/api/v0.1.0/testuser",
+            StatusCode::MethodNotAllowed,
+        ),
+    ] {
+        let res = srv.send(Request::new(method, srv.url(path).as_str())).await;
+        assert_eq!(res.status(), status);
+        assert_eq!(
+            res.header("Strict-Transport-Security").map(|v| v.as_str()),
+            Some("max-age=31536000"),
+            "{method} {path}"
+        );
+    }
+    srv.stop().await;
+
+    let srv = Server::spawn(&oidc, |builder| {
+        builder.hsts(Some(Hsts {
+            max_age: Duration::from_secs(63072000),
+            include_subdomains: true,
+            preload: true,
+        }))
+    })
+    .await;
+    let res = srv
+        .send(Request::new(Method::Get, srv.url("/health").as_str()))
+        .await;
+    assert_eq!(
+        res.header("Strict-Transport-Security").map(|v| v.as_str()),
+        Some("max-age=63072000; includeSubDomains; preload")
+    );
+    srv.stop().await;
+
+    // Preloading requires including subdomains.
+    let res = App::builder(
+        tempdir().unwrap().path().to_path_buf(),
+        tls_config(),
+        OidcConfig {
+            audience: OIDC_AUDIENCE.to_string(),
+            issuer: oidc.issuer.parse().unwrap(),
+        },
+    )
+    .hsts(Some(Hsts {
+        preload: true,
+        ..Default::default()
+    }))
+    .build()
+    .await;
+    assert!(res.is_err());
+
+    oidc.stop().await;
+}
+
+#[async_std::test]
+async fn allowed_content_types() {
+    let _ = tracing_subscriber::fmt::try_init();
+
+    let oidc = Oidc::spawn().await;
+
+    const SUBJECT: &str = "test|allowed-content-types";
+    let oidc_token = oidc.token(&oidc.claims(SUBJECT));
+
+    let srv = Server::spawn(&oidc, |builder| {
+        builder.allowed_content_types([
+            "application/wasm".parse().unwrap(),
+            "application/json".parse().unwrap(),
+        ])
+    })
+    .await;
+
+    let cl = srv.client();
+    let token = oidc_token.clone();
+    let cl = spawn_blocking(move || async move {
+        let oidc_cl = cl.token(token).build().unwrap();
+
+        let oidc_user = oidc_cl.user(&"testuser".parse().unwrap());
+        assert!(oidc_user
+            .create(&UserRecord {
+                subject: SUBJECT.into(),
+            })
+            .expect("failed to create user"));
+        assert!(oidc_user
+            .repository(&"test-repo".parse().unwrap())
+            .create(&RepositoryConfig { public: true })
+            .expect("failed to create repository"));
+    });
+    assert!(matches!(cl.await.await, ()));
+
+    let put = |mime: &str, body: &[u8]| {
+        let (_, hash) = Algorithms::default().read_sync(body).unwrap();
+        let mut req = Request::new(
+            Method::Put,
+            srv.url("/api/v0.1.0/testuser/test-repo/_tag/0.1.0/tree/file?dry-run=true")
+                .as_str(),
+        );
+        req.set_body(body);
+        req.insert_header("Authorization", format!("Bearer {oidc_token}"));
+        req.insert_header("Content-Type", mime);
+        req.insert_header("Content-Digest", hash.to_string());
+        req
+    };
+
+    for (mime, body, status) in [
+        (
+            "application/wasm",
+            &b"\0asm\x01\0\0\0"[..],
+            StatusCode::Created,
+        ),
+        (
+            "application/json",
+            b"{\"key\":\"value\"}",
+            StatusCode::Created,
+        ),
+        (
+            "application/json; charset=utf-8",
+            b"[]",
+            StatusCode::Created,
+        ),
+        (
+            "application/octet-stream",
+            b"\0asm\x01\0\0\0",
+            StatusCode::UnsupportedMediaType,
+        ),
+        ("text/plain", b"text", StatusCode::UnsupportedMediaType),
+        (
+            "application/wasm",
+            b"text",
+            StatusCode::UnsupportedMediaType,
+        ),
+        (
+            "application/json",
+            b"\x7fELF\x02\x01\x01",
+            StatusCode::UnsupportedMediaType,
+        ),
+    ] {
+        assert_eq!(
+            srv.send(put(mime, body)).await.status(),
+            status,
+            "unexpected status for `{mime}`"
+        );
+    }
+
+    srv.stop().await;
+    oidc.stop().await;
+}
+
+#[async_std::test]
+async fn max_inflight_bytes() {
+    let _ = tracing_subscriber::fmt::try_init();
+
+    let oidc = Oidc::spawn().await;
+
+    let srv = Server::spawn(&oidc, |builder| {
+        builder.max_inflight_bytes(4096).metrics_endpoint(true)
+    })
+    .await;
+
+    let put = |body: Vec<u8>| {
+        let mut req = Request::new(
+            Method::Put,
+            srv.url("/api/v0.1.0/testuser/test-repo/_tag/0.1.0/tree/file?dry-run=true")
+                .as_str(),
+        );
+        req.set_body(body);
+        req.insert_header("Content-Type", "application/octet-stream");
+        req
+    };
+    let res = srv.send(put(vec![0; 8192])).await;
+    assert_eq!(res.status(), StatusCode::ServiceUnavailable);
+    assert_eq!(res.header("Retry-After").map(|v| v.as_str()), Some("1"));
+    // Requests within the budget are handled as usual.
+    let res = srv.send(put(vec![0; 16])).await;
+    assert_ne!(res.status(), StatusCode::ServiceUnavailable);
+
+    assert_eq!(srv.app.metrics().inflight_bytes(), 0);
+    srv.stop().await;
+
+    // Responses exceeding the budget are rejected as well.
+    let srv = Server::spawn(&oidc, |builder| {
+        builder.max_inflight_bytes(16).metrics_endpoint(true)
+    })
+    .await;
+    let res = srv
+        .send(Request::new(Method::Get, srv.url("/metrics").as_str()))
+        .await;
+    assert_eq!(res.status(), StatusCode::ServiceUnavailable);
+    srv.stop().await;
+
+    oidc.stop().await;
+}
+
+#[async_std::test]
+async fn http_redirect() {
+    let _ = tracing_subscriber::fmt::try_init();
+
+    let oidc = Oidc::spawn().await;
+
+    let srv = Server::spawn(&oidc, |builder| builder.hsts(Some(Hsts::default()))).await;
+
+    let lis = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+        .await
+        .expect("failed to bind to address");
+    let port = lis.local_addr().unwrap().port();
+    let redirect = spawn({
+        let app = Arc::clone(&srv.app);
+        let https_port = srv.port;
+        async move {
+            lis.incoming()
+                .take(2)
+                .for_each(|stream| async {
+                    let stream = stream.expect("failed to initialize stream");
+                    app.redirect_to_https(stream, https_port)
+                        .await
+                        .expect("failed to redirect request")
+                })
+                .await
+        }
+    });
+
+    let send = |request: String| async move {
+        let mut stream = TcpStream::connect((Ipv4Addr::LOCALHOST, port))
+            .await
+            .expect("failed to connect");
+        stream.write_all(request.as_bytes()).await.unwrap();
+        read_head(&mut stream).await
+    };
+
+    let head = send(format!(
+        "PUT /api/v0.1.0/testuser?x=1 HTTP/1.1\r\n\
+        Host: localhost:{port}\r\n\
+        Content-Length: 0\r\n\
+        Connection: close\r\n\r\n"
+    ))
+    .await;
+    assert!(
+        head.starts_with("HTTP/1.1 308 Permanent Redirect\r\n"),
+        "{head}"
+    );
+    assert!(
+        head.to_lowercase().contains(&format!(
+            "\r\nlocation: https://localhost:{}/api/v0.1.0/testuser?x=1\r\n",
+            srv.port
+        )),
+        "{head}"
+    );
+    // HSTS policies must not be sent over plaintext connections.
+    assert!(
+        !head.to_lowercase().contains("strict-transport-security"),
+        "{head}"
+    );
+
+    let head = send("GET /health HTTP/1.1\r\nConnection: close\r\n\r\n".into()).await;
+    assert!(head.starts_with("HTTP/1.1 400 Bad Request\r\n"), "{head}");
+
+    redirect.await;
+    srv.stop().await;
+    oidc.stop().await;
+}
+
+#[async_std::test]
+async fn oidc_clock_skew() {
+    let _ = tracing_subscriber::fmt::try_init();
+
+    let oidc = Oidc::spawn().await;
+
+    let srv = Server::spawn(&oidc, |builder| {
+        builder.oidc_clock_skew(Duration::from_secs(30))
+    })
+    .await;
+
+    let now = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    for (i, (expires_at, not_before, valid)) in [
+        (now - 10, None, true),
+        (now - 90, None, false),
+        (now + 3600, Some(now + 10), true),
+        (now + 3600, Some(now + 90), false),
+    ]
+    .into_iter()
+    .enumerate()
+    {
+        let subject = format!("test|clock-skew-{i}");
+        let token = oidc.token(&TokenClaims {
+            issued_at: now - 3600,
+            expires_at,
+            not_before,
+            ..oidc.claims(&subject)
+        });
+        let mut req = Request::new(
+            Method::Put,
+            srv.url(&format!("/api/v0.1.0/clockskew{i}")).as_str(),
+        );
+        req.insert_header("Authorization", format!("Bearer {token}"));
+        req.insert_header("Content-Type", "application/json");
+        req.set_body(Body::from_json(&json!({ "subject": subject })).unwrap());
+        let status = srv.send(req).await.status();
+        if valid {
+            assert_eq!(status, StatusCode::Created, "token {i} must be accepted");
+        } else {
+            assert_eq!(
+                status,
+                StatusCode::Unauthorized,
+                "token {i} must be rejected"
+            );
+        }
+    }
+
+    srv.stop().await;
+    oidc.stop().await;
+}
+
+#[async_std::test]
+async fn store_precompressed() {
+    let _ = tracing_subscriber::fmt::try_init();
+
+    let oidc = Oidc::spawn().await;
+
+    const SUBJECT: &str = "test|store-precompressed";
+    let oidc_token = oidc.token(&oidc.claims(SUBJECT));
+
+    let srv = Server::spawn(&oidc, |builder| {
+        builder
+            .compression([CompressionAlgorithm::Brotli, CompressionAlgorithm::Gzip])
+            .store_precompressed([CompressionAlgorithm::Gzip])
+    })
+    .await;
+
+    let cl = srv.client();
+    let cl = spawn_blocking(move || async move {
+        let oidc_cl = cl.token(oidc_token).build().unwrap();
+
+        let oidc_user = oidc_cl.user(&"testuser".parse().unwrap());
+        assert!(oidc_user
+            .create(&UserRecord {
+                subject: SUBJECT.into(),
+            })
+            .expect("failed to create user"));
+        let oidc_repo = oidc_user.repository(&"test-repo".parse().unwrap());
+        assert!(oidc_repo
+            .create(&RepositoryConfig { public: true })
+            .expect("failed to create repository"));
+
+        let pkg = tempdir().expect("failed to create temporary package directory");
+        try_join!(
+            write(pkg.path().join("large.txt"), "text ".repeat(1024)),
+            write(pkg.path().join("small.txt"), "text"),
+        )
+        .unwrap();
+        let (tag_created, _) = oidc_repo
+            .tag(&"0.1.0".parse().unwrap())
+            .create_from_path_unsigned(pkg.path())
+            .expect("failed to create a tag and upload the tree");
+        assert!(tag_created);
+    });
+    assert!(matches!(cl.await.await, ()));
+
+    let entries = srv
+        ._store
+        .path()
+        .join("users/testuser/repos/test-repo/tags/0.1.0/tree/entries");
+    let variant = entries.join("large.txt/content.gzip");
+    let variant_meta = entries.join("large.txt/content.gzip.json");
+    let stored = async_std::fs::read(&variant)
+        .await
+        .expect("failed to read pre-compressed variant");
+    assert!(stored.starts_with(b"\x1f\x8b"));
+    assert!(stored.len() < 5 * 1024);
+    assert!(variant_meta.exists());
+    // Small contents and algorithms not configured for pre-compression are not stored.
+    assert!(!entries.join("small.txt/content.gzip").exists());
+    assert!(!entries.join("large.txt/content.br").exists());
+
+    let get = |accept_encoding: &str| {
+        let mut req = Request::new(
+            Method::Get,
+            srv.url("/api/v0.1.0/testuser/test-repo/_tag/0.1.0/tree/large.txt")
+                .as_str(),
+        );
+        req.insert_header("Accept-Encoding", accept_encoding);
+        srv.send(req)
+    };
+
+    // The stored variant is served as is, including its length.
+    let mut res = get("gzip").await;
+    assert_eq!(res.status(), StatusCode::Ok);
+    assert_eq!(
+        res.header("Content-Encoding").map(|v| v.as_str()),
+        Some("gzip")
+    );
+    let content_length = stored.len().to_string();
+    assert_eq!(
+        res.header("Content-Length").map(|v| v.as_str()),
+        Some(content_length.as_str())
+    );
+    assert_eq!(res.body_bytes().await.unwrap(), stored);
+
+    // Other encodings are still compressed on the fly.
+    let res = get("br").await;
+    assert_eq!(res.status(), StatusCode::Ok);
+    assert_eq!(
+        res.header("Content-Encoding").map(|v| v.as_str()),
+        Some("br")
+    );
+    assert!(res.header("Content-Length").is_none());
+
+    // Corrupted variants are removed and contents compressed on the fly instead.
+    let mut corrupted = stored.clone();
+    *corrupted.last_mut().unwrap() ^= 0xff;
+    write(&variant, corrupted).await.unwrap();
+    let mut res = get("gzip").await;
+    assert_eq!(res.status(), StatusCode::Ok);
+    assert_eq!(
+        res.header("Content-Encoding").map(|v| v.as_str()),
+        Some("gzip")
+    );
+    assert!(res.header("Content-Length").is_none());
+    // Both are compressed using the same level.
+    assert_eq!(res.body_bytes().await.unwrap(), stored);
+    assert!(!variant.exists());
+    assert!(!variant_meta.exists());
+
+    srv.stop().await;
+    oidc.stop().await;
+}
+
+#[async_std::test]
+async fn route_timeouts() {
+    let _ = tracing_subscriber::fmt::try_init();
+
+    let oidc = Oidc::spawn().await;
+
+    const SUBJECT: &str = "test|route-timeouts";
+    let oidc_token = oidc.token(&oidc.claims(SUBJECT));
+
+    let srv = Server::spawn(&oidc, |builder| {
+        builder
+            .metadata_timeout(Duration::from_secs(1))
+            .upload_timeout(Duration::from_secs(10))
+    })
+    .await;
+
+    // Sends the head of a request and its `body` after `delay` and returns the response head.
+    let slow = |method: &str, path: &str, body: &'static str, delay| {
+        let head = format!(
+            "{method} {path} HTTP/1.1\r\n\
+            Host: localhost\r\n\
+            Content-Type: application/json\r\n\
+            Content-Length: {}\r\n\
+            Authorization: Bearer {oidc_token}\r\n\r\n",
+            body.len(),
+        );
+        let srv = &srv;
+        async move {
+            let mut stream = srv.connect().await;
+            stream.write_all(head.as_bytes()).await.unwrap();
+            async_std::task::sleep(delay).await;
+            // The server may have already responded and closed the connection.
+            _ = stream.write_all(body.as_bytes()).await;
+            read_head(&mut stream).await
+        }
+    };
+    const USER: &str = r#"{"subject":"test|route-timeouts"}"#;
+
+    // Metadata requests are tightly bounded.
+    let res = slow(
+        "PUT",
+        "/api/v0.1.0/testuser",
+        USER,
+        Duration::from_millis(1500),
+    )
+    .await;
+    assert!(res.starts_with("HTTP/1.1 408 "), "{res}");
+    let res = slow("PUT", "/api/v0.1.0/testuser", USER, Duration::ZERO).await;
+    assert!(res.starts_with("HTTP/1.1 201 "), "{res}");
+    let res = slow(
+        "PUT",
+        "/api/v0.1.0/testuser/test-repo",
+        r#"{"public":true}"#,
+        Duration::ZERO,
+    )
+    .await;
+    assert!(res.starts_with("HTTP/1.1 201 "), "{res}");
+
+    let mut req = Request::new(
+        Method::Post,
+        srv.url("/api/v0.1.0/testuser/test-repo/_upload").as_str(),
+    );
+    req.insert_header("Authorization", format!("Bearer {oidc_token}"));
+    let res = srv.send(req).await;
+    assert_eq!(res.status(), StatusCode::Accepted);
+    let session = res.header("Location").unwrap().as_str().to_string();
+
+    // Equally slow uploads are allowed under their larger budget.
+    let res = slow("PATCH", &session, "hello", Duration::from_millis(1500)).await;
+    assert!(res.starts_with("HTTP/1.1 202 "), "{res}");
+
+    // Deadlines requested by clients still apply if they are shorter.
+    let mut req = Request::new(Method::Get, srv.url(&session).as_str());
+    req.insert_header("Authorization", format!("Bearer {oidc_token}"));
+    req.insert_header("grpc-timeout", "10S");
+    let res = srv.send(req).await;
+    assert_eq!(res.status(), StatusCode::NoContent);
+    assert_eq!(res.header("Range").map(|v| v.as_str()), Some("0-4"));
+
+    srv.stop().await;
+    oidc.stop().await;
+}
+
+#[async_std::test]
+async fn cert_writers() {
+    let _ = tracing_subscriber::fmt::try_init();
+
+    let oidc = Oidc::spawn().await;
+
+    const SUBJECT: &str = "test|cert-writers";
+    let oidc_token = oidc.token(&oidc.claims(SUBJECT));
+    const OTHER_SUBJECT: &str = "test|cert-writers-other";
+    let other_token = oidc.token(&oidc.claims(OTHER_SUBJECT));
+
+    let (cert, key) = client_credentials();
+    let cert_fingerprint: [u8; 32] = Sha256::digest(&cert[0].0).into();
+
+    let srv = Server::spawn(&oidc, |builder| {
+        builder.client_cert_writers(CertificateWriters::from_iter([(
+            cert_fingerprint,
+            HashSet::from(["testuser".parse().unwrap()]),
+        )]))
+    })
+    .await;
+
+    let cl = srv.client();
+    let app = Arc::clone(&srv.app);
+    let credentials = (cert.clone(), key.clone());
+    let cl = spawn_blocking(move || async move {
+        let cert_cl = cl.clone().credentials(cert, key).build().unwrap();
+        let repo_name = "test-repo".parse().unwrap();
+
+        for (token, user, subject) in [
+            (oidc_token, "testuser", SUBJECT),
+            (other_token, "otheruser", OTHER_SUBJECT),
+        ] {
+            let oidc_user = cl.clone().token(token).build().unwrap();
+            let oidc_user = oidc_user.user(&user.parse().unwrap());
+            assert!(oidc_user
+                .create(&UserRecord {
+                    subject: subject.into(),
+                })
+                .expect("failed to create user"));
+            assert!(oidc_user
+                .repository(&repo_name)
+                .create(&RepositoryConfig { public: false })
+                .expect("failed to create repository"));
+        }
+
+        let pkg = tempdir().expect("failed to create temporary package directory");
+        write(pkg.path().join("test-file.txt"), "text")
+            .await
+            .unwrap();
+        let cert_repo = |user: &str| cert_cl.user(&user.parse().unwrap()).repository(&repo_name);
+
+        // The certificate publishes to the namespace it is mapped to.
+        let (tag_created, _) = cert_repo("testuser")
+            .tag(&"0.1.0".parse().unwrap())
+            .create_from_path_unsigned(pkg.path())
+            .expect("failed to create a tag using a certificate");
+        assert!(tag_created);
+        assert_eq!(
+            cert_repo("testuser")
+                .tag(&"0.1.0".parse().unwrap())
+                .path(&"test-file.txt".parse().unwrap())
+                .get_string(5)
+                .expect("failed to get file")
+                .1,
+            "text"
+        );
+
+        // Other namespaces, users and repository configurations are not writable.
+        let err = cert_repo("otheruser")
+            .tag(&"0.1.0".parse().unwrap())
+            .create_from_path_unsigned(pkg.path())
+            .expect_err("certificate granted write access to unmapped namespace");
+        assert!(format!("{err:#}").contains("`403`"), "{err:#}");
+        let err = cert_cl
+            .user(&"certuser".parse().unwrap())
+            .create(&UserRecord {
+                subject: SUBJECT.into(),
+            })
+            .expect_err("certificate granted write access to users");
+        assert!(format!("{err:#}").contains("`403`"), "{err:#}");
+        let err = cert_repo("testuser")
+            .create(&RepositoryConfig { public: true })
+            .expect_err("certificate granted write access to repository configuration");
+        assert!(format!("{err:#}").contains("`403`"), "{err:#}");
+
+        // Reloaded mappings apply to established clients.
+        app.set_client_cert_writers(None);
+        let err = cert_repo("testuser")
+            .tag(&"0.2.0".parse().unwrap())
+            .create_from_path_unsigned(pkg.path())
+            .expect_err("unmapped certificate granted write access");
+        assert!(format!("{err:#}").contains("`401`"), "{err:#}");
+    });
+    assert!(matches!(cl.await.await, ()));
+
+    assert!(
+        srv.app
+            .metrics()
+            .authorization_decisions(AuthDecision::GrantedViaCert)
+            > 0
+    );
+
+    // Idempotency keys of requests authenticated by a certificate are scoped per certificate.
+    srv.app
+        .set_client_cert_writers(Some(CertificateWriters::from_iter([(
+            cert_fingerprint,
+            HashSet::from(["testuser".parse().unwrap()]),
+        )])));
+    let send = |req: Request, cert: bool| {
+        let (chain, key) = credentials.clone();
+        let srv = &srv;
+        async move {
+            if !cert {
+                return srv.send(req).await;
+            }
+            let stream = TcpStream::connect((Ipv4Addr::LOCALHOST, srv.port))
+                .await
+                .expect("failed to connect to server");
+            let stream = TlsConnector::from(Arc::new(
+                rustls::ClientConfig::builder()
+                    .with_safe_defaults()
+                    .with_root_certificates(roots())
+                    .with_single_cert(chain, key)
+                    .unwrap(),
+            ))
+            .connect("localhost".try_into().unwrap(), stream)
+            .await
+            .expect("failed to establish TLS connection");
+            let mut res = async_h1::connect(stream, req)
+                .await
+                .expect("failed to send request");
+            let body = res
+                .take_body()
+                .into_bytes()
+                .await
+                .expect("failed to read response body");
+            res.set_body(body);
+            res
+        }
+    };
+    let create_upload = |cert: bool| {
+        let mut req = Request::new(
+            Method::Post,
+            srv.url("/api/v0.1.0/testuser/test-repo/_upload").as_str(),
+        );
+        req.insert_header("Idempotency-Key", "upload-1");
+        send(req, cert)
+    };
+    let res = create_upload(true).await;
+    assert_eq!(res.status(), StatusCode::Accepted);
+    assert!(res.header("Idempotent-Replayed").is_none());
+    let session = res.header("Location").unwrap().as_str().to_string();
+    let res = create_upload(true).await;
+    assert_eq!(res.status(), StatusCode::Accepted);
+    assert_eq!(
+        res.header("Idempotent-Replayed").map(|v| v.as_str()),
+        Some("true")
+    );
+    assert_eq!(
+        res.header("Location").map(|v| v.as_str()),
+        Some(session.as_str())
+    );
+    let res = create_upload(false).await;
+    assert_eq!(res.status(), StatusCode::Unauthorized);
+
+    // Certificates publish tags, but may not delete them.
+    let res = send(
+        Request::new(
+            Method::Delete,
+            srv.url("/api/v0.1.0/testuser/test-repo/_tag/0.1.0")
+                .as_str(),
+        ),
+        true,
+    )
+    .await;
+    assert_eq!(res.status(), StatusCode::Forbidden);
+    assert_eq!(
+        send(
+            Request::new(
+                Method::Get,
+                srv.url("/api/v0.1.0/testuser/test-repo/_tag/0.1.0/tree/test-file.txt")
+                    .as_str(),
+            ),
+            true,
+        )
+        .await
+        .status(),
+        StatusCode::Ok
+    );
+
+    srv.stop().await;
+    oidc.stop().await;
+}
+
+#[async_std::test]
+async fn negative_cache() {
+    let _ = tracing_subscriber::fmt::try_init();
+
+    let oidc = Oidc::spawn().await;
+
+    const SUBJECT: &str = "test|negative-cache";
+    let oidc_token = oidc.token(&oidc.claims(SUBJECT));
+
+    let srv = Server::spawn(&oidc, |builder| {
+        builder.negative_cache_ttl(Duration::from_secs(60))
+    })
+    .await;
+
+    let cl = srv.client();
+    let token = oidc_token.clone();
+    let cl = spawn_blocking(move || async move {
+        let oidc_user = cl.token(token).build().unwrap();
+        let oidc_user = oidc_user.user(&"testuser".parse().unwrap());
+        assert!(oidc_user
+            .create(&UserRecord {
+                subject: SUBJECT.into(),
+            })
+            .expect("failed to create user"));
+        assert!(oidc_user
+            .repository(&"test-repo".parse().unwrap())
+            .create(&RepositoryConfig { public: true })
+            .expect("failed to create repository"));
+    });
+    assert!(matches!(cl.await.await, ()));
+
+    let get = |token: Option<&str>| {
+        let mut req = Request::new(
+            Method::Get,
+            srv.url("/api/v0.1.0/testuser/test-repo/_tag/0.1.0")
+                .as_str(),
+        );
+        if let Some(token) = token {
+            req.insert_header("Authorization", format!("Bearer {token}"));
+        }
+        async {
+            let mut res = srv.send(req).await;
+            let content_type = res.content_type().map(|mime| mime.to_string());
+            (res.status(), content_type, res.body_string().await.unwrap())
+        }
+    };
+
+    let miss = get(Some(&oidc_token)).await;
+    assert_eq!(miss.0, StatusCode::NotFound);
+    assert_eq!(srv.app.metrics().negative_cache_hits(), 0);
+    assert_eq!(get(Some(&oidc_token)).await, miss);
+    assert_eq!(srv.app.metrics().negative_cache_hits(), 1);
+
+    // Misses are not shared between principals.
+    assert_eq!(get(None).await.0, StatusCode::NotFound);
+    assert_eq!(srv.app.metrics().negative_cache_hits(), 1);
+    assert_eq!(get(None).await.0, StatusCode::NotFound);
+    assert_eq!(srv.app.metrics().negative_cache_hits(), 2);
+
+    // Published tags are visible immediately.
+    let cl = srv.client();
+    let token = oidc_token.clone();
+    let cl = spawn_blocking(move || async move {
+        let oidc_cl = cl.token(token).build().unwrap();
+        let pkg = tempdir().expect("failed to create temporary package directory");
+        write(pkg.path().join("test-file.txt"), "text")
+            .await
+            .unwrap();
+        let (tag_created, _) = oidc_cl
+            .user(&"testuser".parse().unwrap())
+            .repository(&"test-repo".parse().unwrap())
+            .tag(&"0.1.0".parse().unwrap())
+            .create_from_path_unsigned(pkg.path())
+            .expect("failed to create tag");
+        assert!(tag_created);
+    });
+    assert!(matches!(cl.await.await, ()));
+    assert_eq!(get(Some(&oidc_token)).await.0, StatusCode::Ok);
+    assert_eq!(get(None).await.0, StatusCode::Ok);
+    assert_eq!(srv.app.metrics().negative_cache_hits(), 2);
+
+    srv.stop().await;
+    oidc.stop().await;
+}
+
+#[async_std::test]
+async fn deletion() {
+    let _ = tracing_subscriber::fmt::try_init();
+
+    let oidc = Oidc::spawn().await;
+
+    const SUBJECT: &str = "test|deletion";
+    let oidc_token = oidc.token(&oidc.claims(SUBJECT));
+    let other_token = oidc.token(&oidc.claims("test|deletion-other"));
+    let admin_token = oidc.token(&TokenClaims {
+        scope: "openid write:drawbridge_admin".into(),
+        ..oidc.claims("test|deletion-admin")
+    });
+
+    let srv = Server::spawn(&oidc, |builder| builder.max_tags_per_repo(2)).await;
+    let pkg = tempdir().expect("failed to create temporary package directory");
+    write(pkg.path().join("test-file.txt"), "text")
+        .await
+        .unwrap();
+
+    let cl = srv.client();
+    let token = oidc_token.clone();
+    let pkg_path = pkg.path().to_owned();
+    let cl = spawn_blocking(move || async move {
+        let oidc_cl = cl.token(token).build().unwrap();
+
+        let oidc_user = oidc_cl.user(&"testuser".parse().unwrap());
+        assert!(oidc_user
+            .create(&UserRecord {
+                subject: SUBJECT.into(),
+            })
+            .expect("failed to create user"));
+        let oidc_repo = oidc_user.repository(&"test-repo".parse().unwrap());
+        assert!(oidc_repo
+            .create(&RepositoryConfig { public: false })
+            .expect("failed to create repository"));
+        for tag in ["0.1.0", "0.2.0"] {
+            let (tag_created, _) = oidc_repo
+                .tag(&tag.parse().unwrap())
+                .create_from_path_unsigned(&pkg_path)
+                .expect("failed to create tag");
+            assert!(tag_created);
+        }
+        assert!(oidc_repo
+            .tag(&"0.3.0".parse().unwrap())
+            .create_from_path_unsigned(&pkg_path)
+            .is_err());
+    });
+    assert!(matches!(cl.await.await, ()));
+
+    let send = |method, path: &str, token: &str| {
+        let mut req = Request::new(method, srv.url(path).as_str());
+        req.insert_header("Authorization", format!("Bearer {token}"));
+        srv.send(req)
+    };
+    const REPO: &str = "/api/v0.1.0/testuser/test-repo";
+    const TAG: &str = "/api/v0.1.0/testuser/test-repo/_tag/0.1.0";
+
+    // Only the owner of the repository and administrators may delete.
+    for path in [TAG, REPO] {
+        let res = send(Method::Delete, path, &other_token).await;
+        assert_eq!(res.status(), StatusCode::Unauthorized, "{path}");
+    }
+    let res = send(Method::Delete, REPO, "invalid").await;
+    assert_eq!(res.status(), StatusCode::Unauthorized);
+
+    let res = srv
+        .send({
+            let mut req = Request::new(
+                Method::Delete,
+                srv.url("/api/v0.1.0/testuser/test-repo/_tag/0.4.0")
+                    .as_str(),
+            );
+            req.insert_header("Authorization", format!("Bearer {oidc_token}"));
+            req.insert_header("If-Match", "*");
+            req
+        })
+        .await;
+    assert_eq!(res.status(), StatusCode::PreconditionFailed);
+
+    let res = send(Method::Delete, TAG, &oidc_token).await;
+    assert_eq!(res.status(), StatusCode::NoContent);
+    let res = send(Method::Delete, TAG, &oidc_token).await;
+    assert_eq!(res.status(), StatusCode::NotFound);
+    for path in [TAG, &format!("{TAG}/tree/test-file.txt")] {
+        let res = send(Method::Get, path, &oidc_token).await;
+        assert_eq!(res.status(), StatusCode::NotFound, "{path}");
+    }
+    let store = srv._store.path();
+    assert!(!store
+        .join("users/testuser/repos/test-repo/tags/0.1.0")
+        .exists());
+    // Deleted directories are removed completely.
+    let mut entries = async_std::fs::read_dir(store).await.unwrap();
+    while let Some(entry) = entries.next().await {
+        let name = entry.unwrap().file_name();
+        assert!(!name.to_string_lossy().starts_with(".deleted"), "{name:?}");
+    }
+
+    let cl = srv.client();
+    let token = oidc_token.clone();
+    let pkg_path = pkg.path().to_owned();
+    let cl = spawn_blocking(move || async move {
+        let oidc_cl = cl.token(token).build().unwrap();
+        let oidc_repo = oidc_cl
+            .user(&"testuser".parse().unwrap())
+            .repository(&"test-repo".parse().unwrap());
+
+        // Deleted tags no longer count against the limit.
+        let (tag_created, _) = oidc_repo
+            .tag(&"0.3.0".parse().unwrap())
+            .create_from_path_unsigned(&pkg_path)
+            .expect("failed to create tag");
+        assert!(tag_created);
+        let mut tags = oidc_repo.tags().expect("failed to list tags");
+        tags.sort_by_key(ToString::to_string);
+        assert_eq!(tags, ["0.2.0", "0.3.0"].map(|tag| tag.parse().unwrap()));
+
+        let changes = oidc_repo.changes(None).expect("failed to get changes");
+        assert!(changes
+            .changes
+            .iter()
+            .any(|change| change.kind == RepositoryChangeKind::Deleted
+                && change.tag == "0.1.0".parse().unwrap()));
+    });
+    assert!(matches!(cl.await.await, ()));
+
+    // Administrators may delete repositories of other users.
+    let res = send(Method::Delete, REPO, &admin_token).await;
+    assert_eq!(res.status(), StatusCode::NoContent);
+    let res = send(Method::Get, REPO, &oidc_token).await;
+    assert_eq!(res.status(), StatusCode::NotFound);
+    let res = send(Method::Delete, REPO, &admin_token).await;
+    assert_eq!(res.status(), StatusCode::NotFound);
+
+    // Deleted repositories can be created again without any tags.
+    let cl = srv.client();
+    let pkg_path = pkg.path().to_owned();
+    let cl = spawn_blocking(move || async move {
+        let oidc_cl = cl.token(oidc_token).build().unwrap();
+        let oidc_repo = oidc_cl
+            .user(&"testuser".parse().unwrap())
+            .repository(&"test-repo".parse().unwrap());
+        assert!(oidc_repo
+            .create(&RepositoryConfig { public: false })
+            .expect("failed to create repository"));
+        assert!(oidc_repo.tags().expect("failed to list tags").is_empty());
+        for tag in ["0.2.0", "0.3.0"] {
+            let (tag_created, _) = oidc_repo
+                .tag(&tag.parse().unwrap())
+                .create_from_path_unsigned(&pkg_path)
+                .expect("failed to create tag");
+            assert!(tag_created);
+        }
+    });
+    assert!(matches!(cl.await.await, ()));
+
+    srv.stop().await;
+    oidc.stop().await;
+}
+
+#[async_std::test]
+async fn garbage_collection() {
+    let _ = tracing_subscriber::fmt::try_init();
+
+    let oidc = Oidc::spawn().await;
+
+    const SUBJECT: &str = "test|garbage-collection";
+    let oidc_token = oidc.token(&oidc.claims(SUBJECT));
+
+    let srv = Server::spawn(&oidc, |builder| builder.maintenance_gc(true)).await;
+    let cl = srv.client();
+    let token = oidc_token.clone();
+    let cl = spawn_blocking(move || async move {
+        let oidc_cl = cl.token(token).build().unwrap();
+
+        let oidc_user = oidc_cl.user(&"testuser".parse().unwrap());
+        assert!(oidc_user
+            .create(&UserRecord {
+                subject: SUBJECT.into(),
+            })
+            .expect("failed to create user"));
+        let oidc_repo = oidc_user.repository(&"test-repo".parse().unwrap());
+        assert!(oidc_repo
+            .create(&RepositoryConfig { public: false })
+            .expect("failed to create repository"));
+        let pkg = tempdir().expect("failed to create temporary package directory");
+        create_dir(pkg.path().join("dir")).await.unwrap();
+        write(pkg.path().join("dir/test-file.txt"), "text")
+            .await
+            .unwrap();
+        let (tag_created, _) = oidc_repo
+            .tag(&"0.1.0".parse().unwrap())
+            .create_from_path_unsigned(pkg.path())
+            .expect("failed to create tag");
+        assert!(tag_created);
+    });
+    assert!(matches!(cl.await.await, ()));
+
+    // Nodes can be uploaded below directories, which do not reference them.
+    let body = b"unreferenced";
+    let (size, hash) = Algorithms::default().read_sync(&body[..]).unwrap();
+    let put = |path: &str| {
+        let mut req = Request::new(
+            Method::Put,
+            srv.url(&format!(
+                "/api/v0.1.0/testuser/test-repo/_tag/0.1.0/tree/{path}"
+            ))
+            .as_str(),
+        );
+        req.insert_header("Authorization", format!("Bearer {oidc_token}"));
+        req.insert_header("Content-Digest", hash.to_string());
+        req.insert_header("Content-Type", "text/plain");
+        req.insert_header("Content-Length", size.to_string());
+        req.set_body(&body[..]);
+        srv.send(req)
+    };
+    for path in ["unreferenced.txt", "dir/unreferenced.txt"] {
+        let res = put(path).await;
+        assert_eq!(res.status(), StatusCode::Created, "{path}");
+    }
+
+    // Simulate leftovers of a crash during a deletion and nodes being written.
+    const ID: &str = "1b4db7eb-4057-4ddf-91e0-36dec72071f5";
+    let store = srv._store.path();
+    let tree = store.join("users/testuser/repos/test-repo/tags/0.1.0/tree");
+    let remnant = store.join(format!(".deleted-{ID}"));
+    create_dir(&remnant).await.unwrap();
+    write(remnant.join("content"), "deleted").await.unwrap();
+    let partial = store.join("users/testuser/repos/test-repo/tags/0.2.0/tree");
+    async_std::fs::create_dir_all(partial.join("entries/file.txt"))
+        .await
+        .unwrap();
+
+    let maintain = spawn({
+        let app = Arc::clone(&srv.app);
+        async move { app.maintain(Duration::from_millis(500)).await }
+    });
+    while srv.app.metrics().maintenance_runs() == 0 {
+        async_std::task::sleep(Duration::from_millis(10)).await;
+    }
+    assert_eq!(maintain.cancel().await, None);
+
+    assert!(!tree.join("entries/unreferenced.txt").exists());
+    assert!(!tree.join("entries/dir/entries/unreferenced.txt").exists());
+    assert!(!remnant.exists());
+    // Nodes below partially written trees may still be referenced once complete.
+    assert!(partial.join("entries/file.txt").exists());
+    let summary = srv.app.metrics().maintenance_last_summary();
+    assert_eq!(summary.unreferenced_nodes, 2);
+    assert_eq!(summary.deletion_remnants, 1);
+    assert_eq!(summary.failures, 0);
+
+    let mut res = srv
+        .send({
+            let mut req = Request::new(
+                Method::Get,
+                srv.url("/api/v0.1.0/testuser/test-repo/_tag/0.1.0/tree/dir/test-file.txt")
+                    .as_str(),
+            );
+            req.insert_header("Authorization", format!("Bearer {oidc_token}"));
+            req
+        })
+        .await;
+    assert_eq!(res.status(), StatusCode::Ok);
+    assert_eq!(res.body_string().await.unwrap(), "text");
+
+    srv.stop().await;
+    oidc.stop().await;
+}
+
+/// Objects of an [ObjectStore] along with their entity tags.
+type Objects = Arc<std::sync::Mutex<BTreeMap<String, (String, Vec<u8>)>>>;
+
+/// Mock S3-compatible object store serving the bucket `bucket` using path-style requests,
+/// which requires all requests to be signed.
+struct ObjectStore {
+    url: String,
+    objects: Objects,
+    /// Whether all requests fail with `500 Internal Server Error`.
+    failing: Arc<AtomicBool>,
+    tx: Sender<()>,
+    task: JoinHandle<()>,
+}
+
+impl ObjectStore {
+    /// Number of entries listed per page, which is small to exercise pagination.
+    const PAGE_SIZE: usize = 2;
+
+    async fn spawn() -> Self {
+        let lis = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+            .await
+            .expect("failed to bind to address");
+        let addr = lis.local_addr().unwrap();
+        let objects = Objects::default();
+        let failing = Arc::<AtomicBool>::default();
+        let (tx, rx) = channel::<()>();
+        let task = spawn({
+            let objects = Arc::clone(&objects);
+            let failing = Arc::clone(&failing);
+            async move {
+                lis.incoming()
+                    .take_until(rx)
+                    .for_each_concurrent(None, |stream| {
+                        let objects = Arc::clone(&objects);
+                        let failing = Arc::clone(&failing);
+                        async move {
+                            async_h1::accept(stream.expect("failed to initialize stream"), |req| {
+                                let objects = Arc::clone(&objects);
+                                let failing = failing.load(Ordering::Relaxed);
+                                async move {
+                                    if failing {
+                                        Ok(Response::new(StatusCode::InternalServerError))
+                                    } else {
+                                        Self::handle(objects, req).await
+                                    }
+                                }
+                            })
+                            .await
+                            .expect("failed to handle object store connection");
+                        }
+                    })
+                    .await
+            }
+        });
+        Self {
+            url: format!("http://{addr}"),
+            objects,
+            failing,
+            tx,
+            task,
+        }
+    }
+
+    async fn handle(objects: Objects, mut req: Request) -> http_types::Result<Response> {
+        assert!(
+            req.header("Authorization").is_some_and(|auth| auth
+                .as_str()
+                .starts_with("AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/")),
+            "unsigned request to `{}`",
+            req.url()
+        );
+        let key = req
+            .url()
+            .path()
+            .strip_prefix("/bucket/")
+            .expect("request to unknown bucket")
+            .to_owned();
+        let query: HashMap<_, _> = req.url().query_pairs().into_owned().collect();
+        let header = |name| req.header(name).map(|value| value.as_str().to_owned());
+        let (range, if_match, if_none_match, sha256_header) = (
+            header("Range"),
+            header("If-Match"),
+            header("If-None-Match"),
+            header("x-amz-content-sha256"),
+        );
+        let body = req.body_bytes().await?;
+        let sha256: String = Sha256::digest(&body)
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect();
+        assert_eq!(
+            sha256_header.as_deref(),
+            Some(sha256.as_str()),
+            "payload digest of request to `{}` does not match",
+            req.url()
+        );
+
+        let mut objects = objects.lock().unwrap();
+        let res = match (req.method(), objects.get(&key)) {
+            (Method::Get, _) if query.get("list-type").map(String::as_str) == Some("2") => {
+                let delimited = match query.get("delimiter").map(String::as_str) {
+                    Some("/") => true,
+                    None => false,
+                    Some(delimiter) => panic!("unexpected delimiter `{delimiter}`"),
+                };
+                let prefix = query.get("prefix").cloned().unwrap_or_default();
+                let mut entries = BTreeSet::new();
+                for key in objects.keys().filter(|key| key.starts_with(&prefix)) {
+                    match key[prefix.len()..].find('/').filter(|_| delimited) {
+                        Some(i) => entries.insert(format!(
+                            "<CommonPrefixes><Prefix>{}</Prefix></CommonPrefixes>",
+                            &key[..prefix.len() + i + 1]
+                        )),
+                        None => entries.insert(format!("<Contents><Key>{key}</Key></Contents>")),
+                    };
+                }
+                let entries: Vec<_> = entries.into_iter().collect();
+                let start = query
+                    .get("continuation-token")
+                    .map_or(0, |token| token.parse().unwrap());
+                let end = entries.len().min(start + Self::PAGE_SIZE);
+                let mut res = Response::new(StatusCode::Ok);
+                res.set_body(if end < entries.len() {
+                    format!(
+                        "<ListBucketResult><IsTruncated>true</IsTruncated><NextContinuationToken>{end}</NextContinuationToken>{}</ListBucketResult>",
+                        entries[start..end].concat()
+                    )
+                } else {
+                    format!(
+                        "<ListBucketResult><IsTruncated>false</IsTruncated>{}</ListBucketResult>",
+                        entries[start.min(end)..end].concat()
+                    )
+                });
+                res
+            }
+            (Method::Get | Method::Head, None) => Response::new(StatusCode::NotFound),
+            (Method::Get | Method::Head, Some((etag, buf))) => {
+                let mut res = match range.and_then(|range| {
+                    let (start, end) = range.strip_prefix("bytes=")?.split_once('-')?;
+                    Some((start.parse::<usize>().ok()?, end.parse::<usize>().ok()?))
+                }) {
+                    Some((start, end)) => {
+                        let mut res = Response::new(StatusCode::PartialContent);
+                        res.set_body(&buf[start.min(buf.len())..buf.len().min(end + 1)]);
+                        res
+                    }
+                    None => {
+                        let mut res = Response::new(StatusCode::Ok);
+                        res.set_body(buf.as_slice());
+                        res
+                    }
+                };
+                res.insert_header("ETag", etag);
+                res.insert_header("Last-Modified", "Wed, 21 Oct 2015 07:28:00 GMT");
+                res
+            }
+            (Method::Put, current) => {
+                let current = current.map(|(etag, _)| etag);
+                if if_none_match.is_some_and(|_| current.is_some())
+                    || if_match.is_some_and(|etag| current != Some(&etag))
+                {
+                    Response::new(StatusCode::PreconditionFailed)
+                } else {
+                    static VERSION: AtomicU64 = AtomicU64::new(0);
+                    let etag = format!("\"{}\"", VERSION.fetch_add(1, Ordering::Relaxed));
+                    _ = objects.insert(key, (etag.clone(), body));
+                    let mut res = Response::new(StatusCode::Ok);
+                    res.insert_header("ETag", etag);
+                    res
+                }
+            }
+            (Method::Delete, _) => {
+                _ = objects.remove(&key);
+                Response::new(StatusCode::NoContent)
+            }
+            (method, _) => panic!("unsupported method `{method}`"),
+        };
+        Ok(res)
+    }
+
+    async fn stop(self) {
+        assert_eq!(self.tx.send(()), Ok(()));
+        self.task.await
+    }
+}
+
+#[async_std::test]
+async fn s3_store() {
+    let _ = tracing_subscriber::fmt::try_init();
+
+    let oidc = Oidc::spawn().await;
+    let s3 = ObjectStore::spawn().await;
+
+    const SUBJECT: &str = "test|s3-store";
+    let oidc_token = oidc.token(&oidc.claims(SUBJECT));
+
+    let url = StoreUrl::S3(
+        S3Config::new("bucket", "prefix")
+            .endpoint(s3.url.parse().unwrap())
+            .credentials(S3Credentials::new("AKIDEXAMPLE", "secret")),
+    );
+    let srv = Server::spawn_with_store(&oidc, Some(url.clone()), |builder| builder).await;
+    let cl = srv.client();
+    let token = oidc_token.clone();
+    let cl = spawn_blocking(move || async move {
+        let oidc_cl = cl.token(token).build().unwrap();
+
+        let oidc_user = oidc_cl.user(&"testuser".parse().unwrap());
+        assert!(oidc_user
+            .create(&UserRecord {
+                subject: SUBJECT.into(),
+            })
+            .expect("failed to create user"));
+        // Conflicting creations are detected without a local directory.
+        assert!(oidc_user
+            .create(&UserRecord {
+                subject: SUBJECT.into(),
+            })
+            .is_err());
+        let oidc_repo = oidc_user.repository(&"test-repo".parse().unwrap());
+        assert!(oidc_repo
+            .create(&RepositoryConfig { public: false })
+            .expect("failed to create repository"));
+        let pkg = tempdir().expect("failed to create temporary package directory");
+        write(pkg.path().join("test-file.txt"), "text")
+            .await
+            .unwrap();
+        for tag in ["0.1.0", "0.2.0", "0.3.0"] {
+            let (tag_created, _) = oidc_repo
+                .tag(&tag.parse().unwrap())
+                .create_from_path_unsigned(pkg.path())
+                .expect("failed to create tag");
+            assert!(tag_created);
+        }
+        let changes = oidc_repo.changes(None).expect("failed to get changes");
+        assert_eq!(
+            changes
+                .changes
+                .iter()
+                .filter(|change| change.kind == RepositoryChangeKind::Added)
+                .count(),
+            3
+        );
+    });
+    assert!(matches!(cl.await.await, ()));
+
+    let res = srv
+        .send({
+            let mut req = Request::new(
+                Method::Get,
+                srv.url("/api/v0.1.0/testuser/test-repo/_tag/0.2.0/tree/test-file.txt")
+                    .as_str(),
+            );
+            req.insert_header("Authorization", format!("Bearer {oidc_token}"));
+            req
+        })
+        .await
+        .take_body()
+        .into_string()
+        .await
+        .unwrap();
+    assert_eq!(res, "text");
+    srv.stop().await;
+
+    // All files are stored below the key prefix, but nothing is staged in the bucket.
+    {
+        let objects = s3.objects.lock().unwrap();
+        assert!(objects.keys().all(|key| key.starts_with("prefix/")));
+        assert!(objects.contains_key("prefix/.version"));
+        assert!(objects.contains_key("prefix/.changes"));
+        assert!(!objects.keys().any(|key| key.contains("/.uploads")));
+    }
+
+    // The store outlives the server, e.g. to be served by another instance.
+    let srv = Server::spawn_with_store(&oidc, Some(url), |builder| builder).await;
+    let cl = srv.client();
+    let token = oidc_token.clone();
+    let cl = spawn_blocking(move || {
+        let oidc_cl = cl.token(token).build().unwrap();
+        let mut tags = oidc_cl
+            .user(&"testuser".parse().unwrap())
+            .repository(&"test-repo".parse().unwrap())
+            .tags()
+            .expect("failed to list tags");
+        tags.sort_by_key(ToString::to_string);
+        assert_eq!(
+            tags,
+            ["0.1.0", "0.2.0", "0.3.0"].map(|tag| tag.parse().unwrap())
+        );
+    });
+    cl.await;
+
+    // Deletions remove all objects of a tag.
+    let res = srv
+        .send({
+            let mut req = Request::new(
+                Method::Delete,
+                srv.url("/api/v0.1.0/testuser/test-repo/_tag/0.3.0")
+                    .as_str(),
+            );
+            req.insert_header("Authorization", format!("Bearer {oidc_token}"));
+            req
+        })
+        .await;
+    assert_eq!(res.status(), StatusCode::NoContent);
+    srv.stop().await;
+    {
+        let objects = s3.objects.lock().unwrap();
+        assert!(!objects.keys().any(|key| key.contains("/tags/0.3.0")));
+        assert!(objects
+            .keys()
+            .any(|key| key.starts_with("prefix/users/testuser/repos/test-repo/tags/0.2.0/")));
+    }
+
+    s3.stop().await;
+    oidc.stop().await;
+}
+
+#[async_std::test]
+async fn store_cache() {
+    let _ = tracing_subscriber::fmt::try_init();
+
+    let oidc = Oidc::spawn().await;
+    let s3 = ObjectStore::spawn().await;
+    let cache = tempdir().expect("failed to create temporary cache directory");
+
+    const SUBJECT: &str = "test|store-cache";
+    let oidc_token = oidc.token(&oidc.claims(SUBJECT));
+
+    let url = StoreUrl::S3(
+        S3Config::new("bucket", "prefix")
+            .endpoint(s3.url.parse().unwrap())
+            .credentials(S3Credentials::new("AKIDEXAMPLE", "secret")),
+    );
+    let srv = Server::spawn_with_store(&oidc, Some(url), |builder| {
+        builder.cache_dir(cache.path()).cache_max_bytes(1 << 20)
+    })
+    .await;
+    let cl = srv.client();
+    let token = oidc_token.clone();
+    let cl = spawn_blocking(move || async move {
+        let oidc_cl = cl.token(token).build().unwrap();
+        let oidc_user = oidc_cl.user(&"testuser".parse().unwrap());
+        assert!(oidc_user
+            .create(&UserRecord {
+                subject: SUBJECT.into(),
+            })
+            .expect("failed to create user"));
+        let oidc_repo = oidc_user.repository(&"test-repo".parse().unwrap());
+        assert!(oidc_repo
+            .create(&RepositoryConfig { public: false })
+            .expect("failed to create repository"));
+        let pkg = tempdir().expect("failed to create temporary package directory");
+        write(pkg.path().join("test-file.txt"), "text")
+            .await
+            .unwrap();
+        let (tag_created, _) = oidc_repo
+            .tag(&"0.1.0".parse().unwrap())
+            .create_from_path_unsigned(pkg.path())
+            .expect("failed to create tag");
+        assert!(tag_created);
+    });
+    assert!(matches!(cl.await.await, ()));
+
+    let get = || async {
+        let mut req = Request::new(
+            Method::Get,
+            srv.url("/api/v0.1.0/testuser/test-repo/_tag/0.1.0/tree/test-file.txt")
+                .as_str(),
+        );
+        req.insert_header("Authorization", format!("Bearer {oidc_token}"));
+        srv.send(req).await.take_body().into_string().await.unwrap()
+    };
+    assert_eq!(get().await, "text");
+    assert!(std::fs::read_dir(cache.path()).unwrap().next().is_some());
+
+    // Cached contents are served without reading them from the object store.
+    {
+        let mut objects = s3.objects.lock().unwrap();
+        let (_, content) = objects
+            .get_mut("prefix/users/testuser/repos/test-repo/tags/0.1.0/tree/entries/test-file.txt/content")
+            .expect("content is missing in the object store");
+        *content = b"edit".to_vec();
+    }
+    assert_eq!(get().await, "text");
+    srv.stop().await;
+
+    s3.stop().await;
+    oidc.stop().await;
+}
+
+#[async_std::test]
+async fn store_circuit() {
+    let _ = tracing_subscriber::fmt::try_init();
+
+    let oidc = Oidc::spawn().await;
+    let s3 = ObjectStore::spawn().await;
+
+    const SUBJECT: &str = "test|store-circuit";
+    let oidc_token = oidc.token(&oidc.claims(SUBJECT));
+
+    let url = StoreUrl::S3(
+        S3Config::new("bucket", "prefix")
+            .endpoint(s3.url.parse().unwrap())
+            .credentials(S3Credentials::new("AKIDEXAMPLE", "secret")),
+    );
+    let srv = Server::spawn_with_store(&oidc, Some(url), |builder| {
+        builder
+            .store_circuit_threshold(2)
+            .store_circuit_cooldown(Duration::from_secs(1))
+    })
+    .await;
+    let cl = srv.client();
+    let token = oidc_token.clone();
+    let cl = spawn_blocking(move || async move {
+        assert!(cl
+            .token(token)
+            .build()
+            .unwrap()
+            .user(&"testuser".parse().unwrap())
+            .create(&UserRecord {
+                subject: SUBJECT.into(),
+            })
+            .expect("failed to create user"));
+    });
+    assert!(matches!(cl.await.await, ()));
+
+    let get = || async {
+        let mut req = Request::new(Method::Get, srv.url("/api/v0.1.0/testuser").as_str());
+        req.insert_header("Authorization", format!("Bearer {oidc_token}"));
+        srv.send(req).await
+    };
+    assert_eq!(get().await.status(), StatusCode::Ok);
+    assert_eq!(srv.app.metrics().store_circuit(), CircuitState::Closed);
+
+    // Requests are rejected without reaching the store once it failed persistently.
+    s3.failing.store(true, Ordering::Relaxed);
+    assert_eq!(get().await.status(), StatusCode::InternalServerError);
+    assert_eq!(get().await.status(), StatusCode::InternalServerError);
+    let res = get().await;
+    assert_eq!(res.status(), StatusCode::ServiceUnavailable);
+    assert_eq!(res.header("Retry-After").map(|v| v.as_str()), Some("1"));
+    assert_eq!(srv.app.metrics().store_circuit(), CircuitState::Open);
+    assert_eq!(srv.app.metrics().store_circuit_trips(), 1);
+
+    // A request probes the store once the cooldown elapsed, which recovered.
+    s3.failing.store(false, Ordering::Relaxed);
+    async_std::task::sleep(Duration::from_millis(1100)).await;
+    assert_eq!(get().await.status(), StatusCode::Ok);
+    assert_eq!(srv.app.metrics().store_circuit(), CircuitState::Closed);
+    srv.stop().await;
+
+    s3.stop().await;
+    oidc.stop().await;
+}
+
+#[async_std::test]
+async fn read_repair() {
+    let _ = tracing_subscriber::fmt::try_init();
+
+    let oidc = Oidc::spawn().await;
+    let s3 = ObjectStore::spawn().await;
+    let replica = ObjectStore::spawn().await;
+
+    const SUBJECT: &str = "test|read-repair";
+    let oidc_token = oidc.token(&oidc.claims(SUBJECT));
+
+    let url = |s3: &ObjectStore| {
+        StoreUrl::S3(
+            S3Config::new("bucket", "prefix")
+                .endpoint(s3.url.parse().unwrap())
+                .credentials(S3Credentials::new("AKIDEXAMPLE", "secret")),
+        )
+    };
+    let srv = Server::spawn_with_store(&oidc, Some(url(&s3)), |builder| {
+        builder.read_repair(url(&replica))
+    })
+    .await;
+    let cl = srv.client();
+    let token = oidc_token.clone();
+    let cl = spawn_blocking(move || async move {
+        let oidc_cl = cl.token(token).build().unwrap();
+        let oidc_user = oidc_cl.user(&"testuser".parse().unwrap());
+        assert!(oidc_user
+            .create(&UserRecord {
+                subject: SUBJECT.into(),
+            })
+            .expect("failed to create user"));
+        let oidc_repo = oidc_user.repository(&"test-repo".parse().unwrap());
+        assert!(oidc_repo
+            .create(&RepositoryConfig { public: false })
+            .expect("failed to create repository"));
+        let pkg = tempdir().expect("failed to create temporary package directory");
+        write(pkg.path().join("test-file.txt"), "text")
+            .await
+            .unwrap();
+        let (tag_created, _) = oidc_repo
+            .tag(&"0.1.0".parse().unwrap())
+            .create_from_path_unsigned(pkg.path())
+            .expect("failed to create tag");
+        assert!(tag_created);
+    });
+    assert!(matches!(cl.await.await, ()));
+    *replica.objects.lock().unwrap() = s3.objects.lock().unwrap().clone();
+
+    const CONTENT: &str =
+        "prefix/users/testuser/repos/test-repo/tags/0.1.0/tree/entries/test-file.txt/content";
+    let corrupt = |s3: &ObjectStore| {
+        let mut objects = s3.objects.lock().unwrap();
+        let (_, content) = objects
+            .get_mut(CONTENT)
+            .expect("content is missing in the object store");
+        *content = b"edit".to_vec();
+    };
+    let get = || async {
+        let mut req = Request::new(
+            Method::Get,
+            srv.url("/api/v0.1.0/testuser/test-repo/_tag/0.1.0/tree/test-file.txt")
+                .as_str(),
+        );
+        req.insert_header("Authorization", format!("Bearer {oidc_token}"));
+        srv.send(req).await
+    };
+    assert_eq!(get().await.body_string().await.unwrap(), "text");
+
+    // Corrupt contents are repaired from the replica before being served.
+    corrupt(&s3);
+    assert_eq!(get().await.body_string().await.unwrap(), "text");
+    assert_eq!(s3.objects.lock().unwrap()[CONTENT].1, b"text");
+    assert_eq!(srv.app.metrics().read_repairs(RepairOutcome::Repaired), 1);
+
+    // Contents are never served if the replica is corrupt as well.
+    corrupt(&s3);
+    corrupt(&replica);
+    assert_eq!(get().await.status(), StatusCode::InternalServerError);
+    assert_eq!(s3.objects.lock().unwrap()[CONTENT].1, b"edit");
+    assert_eq!(srv.app.metrics().read_repairs(RepairOutcome::Failed), 1);
+    srv.stop().await;
+
+    replica.stop().await;
+    s3.stop().await;
+    oidc.stop().await;
+}
+
+#[async_std::test]
+async fn mirror() {
+    let _ = tracing_subscriber::fmt::try_init();
+
+    let oidc = Oidc::spawn().await;
+
+    const SUBJECT: &str = "test|mirror";
+    let oidc_token = oidc.token(&oidc.claims(SUBJECT));
+
+    // Connections pooled by the mirror are closed, such that the upstream can be stopped.
+    let upstream = Server::spawn(&oidc, |builder| {
+        builder.keep_alive_timeout(Duration::from_secs(1))
+    })
+    .await;
+    let pkg = tempdir().expect("failed to create temporary package directory");
+    write(pkg.path().join("test-file.txt"), "text")
+        .await
+        .unwrap();
+    create_dir(pkg.path().join("vendor")).await.unwrap();
+    write(pkg.path().join("vendor/nested.txt"), "nested")
+        .await
+        .unwrap();
+
+    let cl = upstream.client();
+    let token = oidc_token.clone();
+    let pkg_path = pkg.path().to_owned();
+    let cl = spawn_blocking(move || async move {
+        let oidc_cl = cl.token(token).build().unwrap();
+        let oidc_user = oidc_cl.user(&"testuser".parse().unwrap());
+        assert!(oidc_user
+            .create(&UserRecord {
+                subject: SUBJECT.into(),
+            })
+            .expect("failed to create user"));
+        for (repo, public) in [("test-repo", true), ("private-repo", false)] {
+            let oidc_repo = oidc_user.repository(&repo.parse().unwrap());
+            assert!(oidc_repo
+                .create(&RepositoryConfig { public })
+                .expect("failed to create repository"));
+            let (tag_created, _) = oidc_repo
+                .tag(&"0.1.0".parse().unwrap())
+                .create_from_path_unsigned(&pkg_path)
+                .expect("failed to create tag");
+            assert!(tag_created);
+        }
+    });
+    assert!(matches!(cl.await.await, ()));
+
+    let token_dir = tempdir().expect("failed to create temporary token directory");
+    let token_file = token_dir.path().join("token");
+    write(&token_file, &oidc_token).await.unwrap();
+    let mirror = MirrorConfig::new(upstream.url("/").parse().unwrap())
+        .read_roots(include_bytes!("../testdata/ca.crt").as_slice())
+        .unwrap()
+        .token_file(&token_file)
+        .ttl(Duration::ZERO);
+    let srv = Server::spawn(&oidc, |builder| builder.mirror(mirror)).await;
+
+    const TAG: &str = "/api/v0.1.0/testuser/test-repo/_tag/0.1.0";
+    async fn digest(srv: &Server, path: &str) -> String {
+        let res = srv
+            .send(Request::new(Method::Get, srv.url(path).as_str()))
+            .await;
+        assert_eq!(res.status(), StatusCode::Ok, "{path}");
+        res.header("Content-Digest").unwrap().as_str().to_string()
+    }
+    // Tags are fetched once requested and served along with their trees.
+    assert_eq!(digest(&srv, TAG).await, digest(&upstream, TAG).await);
+    for path in [
+        format!("{TAG}/tree"),
+        format!("{TAG}/tree/vendor"),
+        format!("{TAG}/tree/vendor/nested.txt"),
+        format!("{TAG}/tree/test-file.txt"),
+    ] {
+        assert_eq!(digest(&srv, &path).await, digest(&upstream, &path).await);
+    }
+
+    let cl = srv.client();
+    let token = oidc_token.clone();
+    let cl = spawn_blocking(move || async move {
+        let anon_cl = cl.clone().build().unwrap();
+        let oidc_cl = cl.token(token).build().unwrap();
+        let file_name = "test-file.txt".parse().unwrap();
+        let tag_name = "0.1.0".parse().unwrap();
+
+        let file = anon_cl
+            .user(&"testuser".parse().unwrap())
+            .repository(&"test-repo".parse().unwrap())
+            .tag(&tag_name)
+            .path(&file_name);
+        assert_eq!(file.get_string(5).expect("failed to get file").1, "text");
+
+        // Private repositories are subject to the same authorization as upstream.
+        let anon_prv_file = anon_cl
+            .user(&"testuser".parse().unwrap())
+            .repository(&"private-repo".parse().unwrap())
+            .tag(&tag_name)
+            .path(&file_name);
+        assert!(anon_prv_file.get_string(5).is_err());
+        let oidc_prv_file = oidc_cl
+            .user(&"testuser".parse().unwrap())
+            .repository(&"private-repo".parse().unwrap())
+            .tag(&tag_name)
+            .path(&file_name);
+        assert_eq!(
+            oidc_prv_file.get_string(5).expect("failed to get file").1,
+            "text"
+        );
+    });
+    assert!(matches!(cl.await.await, ()));
+
+    // Writes are rejected instead of diverging from the upstream.
+    let mut req = Request::new(
+        Method::Put,
+        srv.url("/api/v0.1.0/testuser/test-repo/_tag/0.2.0")
+            .as_str(),
+    );
+    req.insert_header("Authorization", format!("Bearer {oidc_token}"));
+    let mut res = srv.send(req).await;
+    assert_eq!(res.status(), StatusCode::MethodNotAllowed);
+    assert_eq!(res.header("Allow").map(|v| v.as_str()), Some("GET, HEAD"));
+    assert!(res.body_string().await.unwrap().contains("mirrors"));
+
+    // Tags replaced upstream are replaced once revalidated.
+    let mut req = Request::new(Method::Delete, upstream.url(TAG).as_str());
+    req.insert_header("Authorization", format!("Bearer {oidc_token}"));
+    assert_eq!(upstream.send(req).await.status(), StatusCode::NoContent);
+    write(pkg.path().join("test-file.txt"), "other")
+        .await
+        .unwrap();
+    let cl = upstream.client();
+    let token = oidc_token.clone();
+    let pkg_path = pkg.path().to_owned();
+    let cl = spawn_blocking(move || async move {
+        let (tag_created, _) = cl
+            .token(token)
+            .build()
+            .unwrap()
+            .user(&"testuser".parse().unwrap())
+            .repository(&"test-repo".parse().unwrap())
+            .tag(&"0.1.0".parse().unwrap())
+            .create_from_path_unsigned(&pkg_path)
+            .expect("failed to create tag");
+        assert!(tag_created);
+    });
+    assert!(matches!(cl.await.await, ()));
+    let file = format!("{TAG}/tree/test-file.txt");
+    let mut res = srv
+        .send(Request::new(Method::Get, srv.url(&file).as_str()))
+        .await;
+    assert_eq!(res.status(), StatusCode::Ok);
+    assert_eq!(res.body_string().await.unwrap(), "other");
+    assert_eq!(digest(&srv, TAG).await, digest(&upstream, TAG).await);
+
+    // Fetched tags are served while the upstream is unavailable, unknown ones are not.
+    upstream.stop().await;
+    let mut res = srv
+        .send(Request::new(Method::Get, srv.url(&file).as_str()))
+        .await;
+    assert_eq!(res.status(), StatusCode::Ok);
+    assert_eq!(res.body_string().await.unwrap(), "other");
+    let res = srv
+        .send(Request::new(
+            Method::Get,
+            srv.url("/api/v0.1.0/testuser/test-repo/_tag/0.2.0")
+                .as_str(),
+        ))
+        .await;
+    assert_eq!(res.status(), StatusCode::BadGateway);
+
+    srv.stop().await;
+    oidc.stop().await;
+}
+
+#[async_std::test]
+async fn access_tokens() {
+    let _ = tracing_subscriber::fmt::try_init();
+
+    let oidc = Oidc::spawn().await;
+
+    const SUBJECT: &str = "test|access-tokens";
+    let oidc_token = oidc.token(&oidc.claims(SUBJECT));
+
+    let srv = Server::spawn(&oidc, |builder| builder).await;
+    let pkg = tempdir().expect("failed to create temporary package directory");
+    write(pkg.path().join("test-file.txt"), "text")
+        .await
+        .unwrap();
+
+    let cl = srv.client();
+    let token = oidc_token.clone();
+    let cl = spawn_blocking(move || async move {
+        let oidc_cl = cl.token(token).build().unwrap();
+        let oidc_user = oidc_cl.user(&"testuser".parse().unwrap());
+        assert!(oidc_user
+            .create(&UserRecord {
+                subject: SUBJECT.into(),
+            })
+            .expect("failed to create user"));
+        for repo in ["ci-repo", "other-repo"] {
+            assert!(oidc_user
+                .repository(&repo.parse().unwrap())
+                .create(&RepositoryConfig { public: false })
+                .expect("failed to create repository"));
+        }
+    });
+    assert!(matches!(cl.await.await, ()));
+
+    let send = |method, path: &str, token: &str, body: Option<serde_json::Value>| {
+        let mut req = Request::new(method, srv.url(path).as_str());
+        req.insert_header("Authorization", format!("Bearer {token}"));
+        if let Some(body) = body {
+            req.set_body(Body::from_json(&body).unwrap());
+        }
+        srv.send(req)
+    };
+    const TOKENS: &str = "/api/v0.1.0/testuser/_tokens";
+    const TAG: &str = "/api/v0.1.0/testuser/ci-repo/_tag/0.1.0";
+    let mint = |actions: &[&str], expires_in: u64| {
+        let body = json!({
+            "repositories": ["ci-repo"],
+            "actions": actions,
+            "expires_in": expires_in,
+            "description": "CI",
+        });
+        let oidc_token = oidc_token.clone();
+        async move {
+            let mut res = send(Method::Post, TOKENS, &oidc_token, Some(body)).await;
+            assert_eq!(res.status(), StatusCode::Created);
+            let minted: serde_json::Value = res.body_json().await.unwrap();
+            assert_eq!(minted["repositories"], json!(["ci-repo"]));
+            assert_eq!(minted["description"], "CI");
+            (
+                minted["id"].as_str().unwrap().to_string(),
+                minted["token"].as_str().unwrap().to_string(),
+            )
+        }
+    };
+    let (publish_id, publish_token) = mint(&["publish"], 3600).await;
+    let (read_id, read_token) = mint(&["read"], 3600).await;
+
+    // Tokens are only stored hashed.
+    let rec = read_to_string(
+        srv._store
+            .path()
+            .join(format!("users/testuser/tokens/{publish_id}/content")),
+    )
+    .await
+    .unwrap();
+    assert!(!rec.contains(&publish_token), "{rec}");
+
+    let res = send(
+        Method::Post,
+        TOKENS,
+        &oidc_token,
+        Some(json!({ "repositories": ["ci-repo"], "actions": ["read"], "expires_in": 0 })),
+    )
+    .await;
+    assert_eq!(res.status(), StatusCode::BadRequest);
+    // Tokens cannot manage tokens.
+    let res = send(
+        Method::Post,
+        TOKENS,
+        &publish_token,
+        Some(json!({ "repositories": ["ci-repo"], "actions": ["read"], "expires_in": 60 })),
+    )
+    .await;
+    assert_eq!(res.status(), StatusCode::Forbidden);
+    let res = send(Method::Get, TOKENS, &publish_token, None).await;
+    assert_eq!(res.status(), StatusCode::Forbidden);
+
+    let cl = srv.client();
+    let token = publish_token.clone();
+    let pkg_path = pkg.path().to_owned();
+    let cl = spawn_blocking(move || async move {
+        let token_cl = cl.token(token).build().unwrap();
+        let token_user = token_cl.user(&"testuser".parse().unwrap());
+        let (tag_created, _) = token_user
+            .repository(&"ci-repo".parse().unwrap())
+            .tag(&"0.1.0".parse().unwrap())
+            .create_from_path_unsigned(&pkg_path)
+            .expect("failed to create tag");
+        assert!(tag_created);
+        assert_eq!(
+            token_user
+                .repository(&"ci-repo".parse().unwrap())
+                .tags()
+                .expect("failed to list tags"),
+            vec!["0.1.0".parse().unwrap()]
+        );
+
+        // Tokens are restricted to their repositories and may not modify users or
+        // repository configurations.
+        assert!(token_user
+            .repository(&"other-repo".parse().unwrap())
+            .tag(&"0.1.0".parse().unwrap())
+            .create_from_path_unsigned(&pkg_path)
+            .is_err());
+        assert!(token_user.get().is_err());
+        assert!(token_user
+            .repository(&"new-repo".parse().unwrap())
+            .create(&RepositoryConfig { public: true })
+            .is_err());
+    });
+    assert!(matches!(cl.await.await, ()));
+
+    let res = send(Method::Get, TAG, &read_token, None).await;
+    assert_eq!(res.status(), StatusCode::Ok);
+    let res = send(
+        Method::Get,
+        &format!("{TAG}/tree/test-file.txt"),
+        &read_token,
+        None,
+    )
+    .await;
+    assert_eq!(res.status(), StatusCode::Ok);
+    let res = send(
+        Method::Get,
+        "/api/v0.1.0/testuser/other-repo/_tag",
+        &read_token,
+        None,
+    )
+    .await;
+    assert_eq!(res.status(), StatusCode::Forbidden);
+
+    let cl = srv.client();
+    let token = read_token.clone();
+    let pkg_path = pkg.path().to_owned();
+    let cl = spawn_blocking(move || async move {
+        let token_cl = cl.token(token).build().unwrap();
+        assert!(token_cl
+            .user(&"testuser".parse().unwrap())
+            .repository(&"ci-repo".parse().unwrap())
+            .tag(&"0.2.0".parse().unwrap())
+            .create_from_path_unsigned(&pkg_path)
+            .is_err());
+    });
+    assert!(matches!(cl.await.await, ()));
+
+    // Tokens may neither delete nor be used in other namespaces.
+    let res = send(Method::Delete, TAG, &publish_token, None).await;
+    assert_eq!(res.status(), StatusCode::Forbidden);
+    let res = send(
+        Method::Get,
+        "/api/v0.1.0/otheruser/ci-repo",
+        &publish_token,
+        None,
+    )
+    .await;
+    assert_eq!(res.status(), StatusCode::Unauthorized);
+    let res = send(Method::Get, TAG, "drawbridge_invalid", None).await;
+    assert_eq!(res.status(), StatusCode::Unauthorized);
+
+    let mut res = send(Method::Get, TOKENS, &oidc_token, None).await;
+    assert_eq!(res.status(), StatusCode::Ok);
+    let tokens: serde_json::Value = res.body_json().await.unwrap();
+    let tokens = tokens.as_array().unwrap();
+    assert_eq!(
+        tokens
+            .iter()
+            .map(|token| token["id"].as_str().unwrap())
+            .collect::<HashSet<_>>(),
+        HashSet::from([publish_id.as_str(), read_id.as_str()])
+    );
+    for token in tokens {
+        assert!(token.get("token").is_none(), "{token}");
+        assert!(token.get("hash").is_none(), "{token}");
+    }
+
+    let res = send(
+        Method::Delete,
+        &format!("{TOKENS}/{publish_id}"),
+        &oidc_token,
+        None,
+    )
+    .await;
+    assert_eq!(res.status(), StatusCode::NoContent);
+    let res = send(
+        Method::Delete,
+        &format!("{TOKENS}/{publish_id}"),
+        &oidc_token,
+        None,
+    )
+    .await;
+    assert_eq!(res.status(), StatusCode::NotFound);
+    let res = send(Method::Get, TAG, &publish_token, None).await;
+    assert_eq!(res.status(), StatusCode::Unauthorized);
+    let res = send(Method::Get, TAG, &read_token, None).await;
+    assert_eq!(res.status(), StatusCode::Ok);
+
+    // Expired tokens are rejected and no longer listed.
+    let (_, expiring_token) = mint(&["read"], 1).await;
+    async_std::task::sleep(Duration::from_secs(2)).await;
+    let res = send(Method::Get, TAG, &expiring_token, None).await;
+    assert_eq!(res.status(), StatusCode::Unauthorized);
+    let mut res = send(Method::Get, TOKENS, &oidc_token, None).await;
+    let tokens: serde_json::Value = res.body_json().await.unwrap();
+    assert_eq!(tokens.as_array().unwrap().len(), 1, "{tokens}");
 
-    // Stop server
-    assert_eq!(srv_tx.send(()), Ok(()));
-    assert!(matches!(join!(oidc, srv), ((), ())));
+    srv.stop().await;
+    oidc.stop().await;
 }